@@ -0,0 +1,70 @@
+// 1BRC - my take on the 1 Billion Row Challenge
+// Copyright (C) 2024  Charles German <5donuts@pm.me>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Time the newline searches every reader & runner splits the input with, on blocks of typical
+//! lines & on blocks with no newline at all (the worst case, searching the whole block).
+//!
+//! Run with `cargo bench --bench boundaries`.
+
+use std::hint::black_box;
+use std::io::Cursor;
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+
+use onebrc::boundaries::{
+    first_newline, last_newline, nearest_newline_after, split_at_last_newline,
+};
+
+/// Block sizes from a probe window up to a typical `--buffer-size`
+const SIZES: [usize; 3] = [256, 64 * 1024, 1024 * 1024];
+
+/// `len` bytes of lines about as long as the official ones, ending part-way through a line
+fn lines(len: usize) -> Vec<u8> {
+    b"Petropavlovsk-Kamchatsky;-12.3\n"
+        .iter()
+        .copied()
+        .cycle()
+        .take(len)
+        .collect()
+}
+
+fn boundaries(c: &mut Criterion) {
+    let mut group = c.benchmark_group("boundaries");
+    for size in SIZES {
+        let (lines, flat) = (lines(size), vec![b'x'; size]);
+        group.throughput(Throughput::Bytes(size as u64));
+        for (shape, buf) in [("lines", &lines), ("no-newline", &flat)] {
+            group.bench_function(BenchmarkId::new(format!("first/{shape}"), size), |b| {
+                b.iter(|| first_newline(black_box(buf)))
+            });
+            group.bench_function(BenchmarkId::new(format!("last/{shape}"), size), |b| {
+                b.iter(|| last_newline(black_box(buf)))
+            });
+            group.bench_function(BenchmarkId::new(format!("split/{shape}"), size), |b| {
+                b.iter(|| split_at_last_newline(black_box(buf)))
+            });
+        }
+
+        // Probing from the middle of a line, as chunk boundaries usually are
+        let mut input = Cursor::new(&lines);
+        group.bench_function(BenchmarkId::new("nearest-after", size), |b| {
+            b.iter(|| nearest_newline_after(&mut input, black_box(size as u64 / 2), size).unwrap())
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, boundaries);
+criterion_main!(benches);
@@ -0,0 +1,80 @@
+// 1BRC - my take on the 1 Billion Row Challenge
+// Copyright (C) 2024  Charles German <5donuts@pm.me>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Compare runners on input sorted by station (long runs of the same station, as in sorted
+//! shards) against the same lines shuffled.
+//!
+//! Run with `cargo bench --bench bursts`. Sorted input should be quicker, since a line for the
+//! same station as the line before it doesn't need a lookup.
+
+use std::io::Cursor;
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+
+use onebrc::config::{Config, Source};
+use onebrc::runners;
+use onebrc::Runner;
+
+/// About as many stations as the official input
+const STATIONS: usize = 400;
+const LINES: usize = 1_000_000;
+
+/// Generate `LINES` measurements spread randomly (but reproducibly) over `STATIONS` stations,
+/// shuffled or sorted by station
+fn generate_input(sorted: bool) -> Vec<u8> {
+    // xorshift64, as in the prefetch benchmark
+    let mut state: u64 = 0x9e37_79b9_7f4a_7c15;
+    let mut next = move || {
+        state ^= state << 13;
+        state ^= state >> 7;
+        state ^= state << 17;
+        state
+    };
+
+    let mut lines: Vec<(usize, f32)> = (0..LINES)
+        .map(|_| {
+            let station = next() as usize % STATIONS;
+            (station, (next() % 1999) as f32 / 10.0 - 99.9)
+        })
+        .collect();
+    if sorted {
+        lines.sort_by_key(|&(station, _)| station);
+    }
+
+    let mut input = Vec::with_capacity(LINES * 24);
+    for (station, measurement) in lines {
+        input.extend_from_slice(format!("Station {station};{measurement:.1}\n").as_bytes());
+    }
+    input
+}
+
+fn bursts(c: &mut Criterion) {
+    for sorted in [false, true] {
+        let input = generate_input(sorted);
+        let mut group = c.benchmark_group(if sorted { "sorted" } else { "shuffled" });
+        group.sample_size(10);
+        group.throughput(Throughput::Bytes(input.len() as u64));
+        for runner in [Runner::AHash, Runner::Table] {
+            let config = Config::default().with_runner(runner, Source::Cli);
+            group.bench_function(BenchmarkId::from_parameter(runner), |b| {
+                b.iter(|| runners::run_with(Cursor::new(&input), &config).unwrap())
+            });
+        }
+        group.finish();
+    }
+}
+
+criterion_group!(benches, bursts);
+criterion_main!(benches);
@@ -0,0 +1,81 @@
+// 1BRC - my take on the 1 Billion Row Challenge
+// Copyright (C) 2024  Charles German <5donuts@pm.me>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Compare the table runner with & without a cache in front of the table, on input shaped like
+//! the official one (a few hundred stations, picked uniformly at random) & on one with far more
+//! stations than the cache has entries.
+//!
+//! Run with `cargo bench --bench front_cache`.
+
+use std::io::Cursor;
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+
+use onebrc::config::Config;
+use onebrc::helpers::ChallengeRunner;
+use onebrc::runners::{CachedTable, Table};
+
+const LINES: usize = 2_000_000;
+
+/// Generate `LINES` measurements spread randomly (but reproducibly) over `stations` stations,
+/// with names of varied lengths like the official ones
+fn generate_input(stations: usize) -> Vec<u8> {
+    // xorshift64, as in the prefetch benchmark
+    let mut state: u64 = 0x9e37_79b9_7f4a_7c15;
+    let mut next = move || {
+        state ^= state << 13;
+        state ^= state >> 7;
+        state ^= state << 17;
+        state
+    };
+
+    let names: Vec<String> = (0..stations)
+        .map(|i| {
+            format!(
+                "{}{i}",
+                &"Ouagadougou Petropavlovsk-Kamchatsky"[..(i * 7) % 30]
+            )
+        })
+        .collect();
+    let mut input = Vec::with_capacity(LINES * 24);
+    for _ in 0..LINES {
+        let station = &names[next() as usize % stations];
+        let measurement = (next() % 1999) as f32 / 10.0 - 99.9;
+        input.extend_from_slice(format!("{station};{measurement:.1}\n").as_bytes());
+    }
+    input
+}
+
+fn front_cache(c: &mut Criterion) {
+    let config = Config::default();
+
+    let mut group = c.benchmark_group("front-cache");
+    group.sample_size(10);
+    // As many stations as the official input, & far more than the cache has entries
+    for stations in [413, 20_000] {
+        let input = generate_input(stations);
+        group.throughput(Throughput::Bytes(input.len() as u64));
+        group.bench_function(BenchmarkId::new("table", stations), |b| {
+            b.iter(|| Table::run(Cursor::new(&input), &config).unwrap())
+        });
+        group.bench_function(BenchmarkId::new("cached-table", stations), |b| {
+            b.iter(|| CachedTable::run(Cursor::new(&input), &config).unwrap())
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, front_cache);
+criterion_main!(benches);
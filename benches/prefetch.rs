@@ -0,0 +1,70 @@
+// 1BRC - my take on the 1 Billion Row Challenge
+// Copyright (C) 2024  Charles German <5donuts@pm.me>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Compare the table runner with & without prefetching on a large generated input.
+//!
+//! Run with `cargo bench --bench prefetch`. Prefetching only pays off once the table no longer
+//! fits in cache, so the input uses far more stations than the official one.
+
+use std::io::Cursor;
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+
+use onebrc::config::Config;
+use onebrc::helpers::ChallengeRunner;
+use onebrc::runners::{Table, TablePrefetch};
+
+/// Enough stations that the table is far larger than L2
+const STATIONS: usize = 200_000;
+const LINES: usize = 2_000_000;
+
+/// Generate `LINES` measurements spread randomly (but reproducibly) over `STATIONS` stations
+fn generate_input() -> Vec<u8> {
+    // xorshift64; good enough to scatter lookups across the table
+    let mut state: u64 = 0x9e37_79b9_7f4a_7c15;
+    let mut next = move || {
+        state ^= state << 13;
+        state ^= state >> 7;
+        state ^= state << 17;
+        state
+    };
+
+    let mut input = Vec::with_capacity(LINES * 24);
+    for _ in 0..LINES {
+        let station = next() as usize % STATIONS;
+        let measurement = (next() % 1999) as f32 / 10.0 - 99.9;
+        input.extend_from_slice(format!("Station {station};{measurement:.1}\n").as_bytes());
+    }
+    input
+}
+
+fn prefetch(c: &mut Criterion) {
+    let input = generate_input();
+    let config = Config::default();
+
+    let mut group = c.benchmark_group("table");
+    group.sample_size(10);
+    group.throughput(Throughput::Bytes(input.len() as u64));
+    group.bench_function(BenchmarkId::new("scalar", STATIONS), |b| {
+        b.iter(|| Table::run(Cursor::new(&input), &config).unwrap())
+    });
+    group.bench_function(BenchmarkId::new("prefetch", STATIONS), |b| {
+        b.iter(|| TablePrefetch::run(Cursor::new(&input), &config).unwrap())
+    });
+    group.finish();
+}
+
+criterion_group!(benches, prefetch);
+criterion_main!(benches);
@@ -0,0 +1,75 @@
+// 1BRC - my take on the 1 Billion Row Challenge
+// Copyright (C) 2024  Charles German <5donuts@pm.me>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//! Compare the `memchr` runner, which checks every line is valid UTF-8, with the `unchecked`
+//! runner, which only turns each new name into a `&str` (unchecked), on names which are all ASCII
+//! & on names which are mostly multi-byte characters, where checking costs the most.
+//!
+//! Run with `cargo bench --bench utf8_validation`. Benchmarks are built without debug
+//! assertions, so the `unchecked` runner really doesn't check anything.
+
+use std::io::Cursor;
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+
+use onebrc::config::{Config, Source};
+use onebrc::runners;
+use onebrc::Runner;
+
+/// About as many stations as the official input
+const STATIONS: usize = 400;
+const LINES: usize = 1_000_000;
+
+/// Generate `LINES` measurements spread randomly (but reproducibly) over `STATIONS` stations,
+/// each named after `stem`
+fn generate_input(stem: &str) -> Vec<u8> {
+    // xorshift64, as in the prefetch benchmark
+    let mut state: u64 = 0x9e37_79b9_7f4a_7c15;
+    let mut next = move || {
+        state ^= state << 13;
+        state ^= state >> 7;
+        state ^= state << 17;
+        state
+    };
+
+    let mut input = Vec::with_capacity(LINES * 32);
+    for _ in 0..LINES {
+        let station = next() as usize % STATIONS;
+        let measurement = (next() % 1999) as f32 / 10.0 - 99.9;
+        input.extend_from_slice(format!("{stem} {station};{measurement:.1}\n").as_bytes());
+    }
+    input
+}
+
+fn utf8_validation(c: &mut Criterion) {
+    let mut group = c.benchmark_group("utf8-validation");
+    group.sample_size(10);
+    for (names, stem) in [
+        ("ascii", "Petropavlovsk"),
+        ("multi-byte", "Ярославль-Главный"),
+    ] {
+        let input = generate_input(stem);
+        group.throughput(Throughput::Bytes(input.len() as u64));
+        for runner in [Runner::Memchr, Runner::Unchecked] {
+            let config = Config::default().with_runner(runner, Source::Cli);
+            group.bench_function(BenchmarkId::new(runner.to_string(), names), |b| {
+                b.iter(|| runners::run_with(Cursor::new(&input), &config).unwrap())
+            });
+        }
+    }
+    group.finish();
+}
+
+criterion_group!(benches, utf8_validation);
+criterion_main!(benches);
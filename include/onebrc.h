@@ -0,0 +1,148 @@
+#ifndef ONEBRC_H
+#define ONEBRC_H
+
+/* Generated by cbindgen from src/ffi.rs; do not edit by hand. */
+
+#include <stddef.h>
+#include <stdint.h>
+
+// The call succeeded
+#define ONEBRC_OK 0
+
+// A required pointer argument was null
+#define ONEBRC_ERR_NULL_ARGUMENT 1
+
+// The runner kind isn't one of the `ONEBRC_RUNNER_*` constants
+#define ONEBRC_ERR_INVALID_RUNNER 2
+
+// The input couldn't be read
+#define ONEBRC_ERR_IO 3
+
+// The input was read, but isn't valid challenge input
+#define ONEBRC_ERR_INVALID_INPUT 4
+
+// An index was past the end of the result
+#define ONEBRC_ERR_OUT_OF_BOUNDS 5
+
+// Anything else, including a panic inside the library
+#define ONEBRC_ERR_OTHER 6
+
+#define ONEBRC_RUNNER_BASELINE 0
+
+#define ONEBRC_RUNNER_RUSTC_HASH 1
+
+#define ONEBRC_RUNNER_A_HASH 2
+
+#define ONEBRC_RUNNER_TABLE 3
+
+#define ONEBRC_RUNNER_TABLE_PREFETCH 4
+
+#define ONEBRC_RUNNER_SAMPLED_DENSE 5
+
+#define ONEBRC_RUNNER_CACHED_TABLE 6
+
+#define ONEBRC_RUNNER_MMAP 7
+
+#define ONEBRC_RUNNER_PAR_MMAP 8
+
+#define ONEBRC_RUNNER_SCOPED_THREADS 9
+
+#define ONEBRC_RUNNER_MEMCHR 10
+
+#define ONEBRC_RUNNER_FIXED_POINT 11
+
+#define ONEBRC_RUNNER_BYTE_KEYS 12
+
+#define ONEBRC_RUNNER_INLINE_TABLE 13
+
+#define ONEBRC_RUNNER_PERFECT_HASH 14
+
+// Only available in builds with the `io-uring` feature
+#define ONEBRC_RUNNER_IO_URING 15
+
+// Only available in builds with the `tokio` feature
+#define ONEBRC_RUNNER_TOKIO 16
+
+#define ONEBRC_RUNNER_PIPELINED 17
+
+#define ONEBRC_RUNNER_CROSSBEAM 18
+
+#define ONEBRC_RUNNER_BRANCHLESS 19
+
+#define ONEBRC_RUNNER_SIMD 20
+
+#define ONEBRC_RUNNER_SWAR 21
+
+#define ONEBRC_RUNNER_UNCHECKED 22
+
+#define ONEBRC_RUNNER_PRESIZED 23
+
+#define ONEBRC_RUNNER_ENTRY_REF 24
+
+#define ONEBRC_RUNNER_FADVISE 25
+
+// Only available in builds with the `direct-io` feature
+#define ONEBRC_RUNNER_DIRECT_IO 26
+
+#define ONEBRC_RUNNER_WORK_QUEUE 27
+
+#define ONEBRC_RUNNER_COMPACT_KEYS 28
+
+#define ONEBRC_RUNNER_HASH_KEYS 29
+
+#define ONEBRC_RUNNER_DOUBLE_BUFFERED 30
+
+// Only available in builds with the `affinity` feature
+#define ONEBRC_RUNNER_THREAD_PER_CORE 31
+
+// The (opaque) result of a successful [`onebrc_run`]
+typedef struct OneBrcResult OneBrcResult;
+
+// A single station from a [`OneBrcResult`].
+//
+// `name` is *not* NUL-terminated; it's `name_len` bytes of UTF-8 which remain valid until the
+// result is freed.
+typedef struct OneBrcStation {
+  const char *name;
+  size_t name_len;
+  float min;
+  float mean;
+  float max;
+  uint32_t count;
+} OneBrcStation;
+
+// Solve the challenge for the input at `path` (a NUL-terminated path) with the given runner.
+//
+// On success, returns [`ONEBRC_OK`] and stores a result in `*out` which must be released with
+// [`onebrc_free_result`]. Otherwise, returns one of the `ONEBRC_ERR_*` codes and leaves `*out`
+// untouched; see [`onebrc_last_error_message`] for the details.
+//
+// # Safety
+// `path` must be a valid NUL-terminated string and `out` must be valid for writes.
+int onebrc_run(const char *path, int runner_kind, struct OneBrcResult **out);
+
+// The number of stations in `result`, or 0 if it is null
+//
+// # Safety
+// `result` must be null or a result returned by [`onebrc_run`] which hasn't been freed.
+size_t onebrc_result_len(const struct OneBrcResult *result);
+
+// Store the `idx`-th station (in alphabetical order) of `result` in `*out`
+//
+// # Safety
+// `result` must be a result returned by [`onebrc_run`] which hasn't been freed, and `out` must
+// be valid for writes.
+int onebrc_result_station(const struct OneBrcResult *result, size_t idx, struct OneBrcStation *out);
+
+// Release a result returned by [`onebrc_run`]; does nothing if it is null
+//
+// # Safety
+// `result` must be null or a result returned by [`onebrc_run`] which hasn't been freed.
+void onebrc_free_result(struct OneBrcResult *result);
+
+// A description of the last error on the calling thread, or null if there hasn't been one.
+//
+// The string remains valid until the next failing call on the same thread.
+const char *onebrc_last_error_message(void);
+
+#endif  /* ONEBRC_H */
@@ -0,0 +1,796 @@
+// 1BRC - my take on the 1 Billion Row Challenge
+// Copyright (C) 2024  Charles German <5donuts@pm.me>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! The core aggregation of measurements, independent of how the input is read.
+//!
+//! The parsing & the per-station accumulation don't need `std`, so they're in
+//! [`core_aggregate`](crate::core_aggregate) & re-exported here; this adds the maps of stations,
+//! case folding & errors which do.
+
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+use std::hash::{BuildHasher, RandomState};
+use std::sync::Arc;
+
+pub(crate) use crate::core_aggregate::is_month;
+use crate::core_aggregate::{key_end, parse_value};
+pub use crate::core_aggregate::{
+    parse_fixed, parse_line, parse_measurement, parse_tenths, Delimiter, Dialect, Encoding,
+    Extents, KeyFormat, StationData, Temperature, AMBIGUOUS_COMMAS, INVALID_MEASUREMENT,
+    MAX_MEASUREMENT, MAX_STATIONS, MISSING_DELIMITER, MISSING_SEPARATOR, NON_CANONICAL_MEASUREMENT,
+    OUT_OF_RANGE,
+};
+use crate::error::{ChallengeError, SkippedLines};
+use crate::helpers::{ResultsBuffer, StationInfo};
+
+/// Fold the case of a station name, so names which only differ in case (e.g. `istanbul` &
+/// `Istanbul`, or `İZMİR` & `İzmir`) fold to the same key.
+///
+/// Each character is lower-cased, apart from a few which Unicode full case folding treats
+/// differently: `ß` (& its capital, `ẞ`) folds to `ss`, & a final `ς` to `σ`. `İ` is the other
+/// exception, which full folding turns into `i` with a combining dot: a name written with `İ` in
+/// capitals is usually written with a plain `i` in lower case, so it's folded to a plain `i`
+/// instead, as it would be in Turkish. That means `Izmir` folds to the same key as well. A dotless
+/// `ı` is only ever lower case, so it stays as it is, & `Diyarbakır` isn't `Diyarbakir`.
+pub fn fold_case(name: &str) -> String {
+    if name.is_ascii() {
+        return name.to_ascii_lowercase();
+    }
+    let mut folded = String::with_capacity(name.len());
+    for c in name.chars() {
+        match c {
+            'İ' => folded.push('i'),
+            'ß' | 'ẞ' => folded.push_str("ss"),
+            'ς' => folded.push('σ'),
+            c => folded.extend(c.to_lowercase()),
+        }
+    }
+    folded
+}
+
+/// Merge the stations whose names only differ in case (see [`fold_case`]) under whichever
+/// spelling has the lowest `first_seen`, e.g. the row or byte offset it was first seen at.
+///
+/// This is for aggregating by exact name & folding once at the end, rather than folding as each
+/// new name turns up like [`Aggregator::case_insensitive`]. The spellings of each station are
+/// merged in order of `first_seen`, so the sums don't depend on the order they're given in.
+/// Stations without any measurements are left out.
+pub fn merge_case_variants<K: Ord>(
+    stations: impl IntoIterator<Item = (String, StationData, K)>,
+) -> Vec<(String, StationData)> {
+    let mut stations: Vec<_> = stations
+        .into_iter()
+        .filter(|(_, data, _)| data.cnt > 0)
+        .collect();
+    stations.sort_by(|(_, _, a), (_, _, b)| a.cmp(b));
+
+    let mut ids: HashMap<String, usize> = HashMap::new();
+    let mut merged: Vec<(String, StationData)> = Vec::new();
+    for (name, data, _) in stations {
+        match ids.entry(fold_case(&name)) {
+            Entry::Occupied(id) => merged[*id.get()].1.merge(&data),
+            Entry::Vacant(id) => {
+                id.insert(merged.len());
+                merged.push((name, data));
+            }
+        }
+    }
+    merged
+}
+
+/// [Merge the stations](merge_case_variants) whose names only differ in case under the spelling
+/// on the earliest row, for runners which count rows for this even when not tracking extents.
+///
+/// The rows are forgotten again unless `keep_extents`.
+pub fn merge_case_variants_by_row(
+    stations: impl IntoIterator<Item = (String, StationData)>,
+    keep_extents: bool,
+) -> Vec<(String, StationData)> {
+    let mut merged = merge_case_variants(stations.into_iter().map(|(name, data)| {
+        let first_row = data.first_row;
+        (name, data, first_row)
+    }));
+    if !keep_extents {
+        for (_, data) in &mut merged {
+            data.first_row = u64::MAX;
+            data.last_row = 0;
+        }
+    }
+    merged
+}
+
+/// Parse the given (1-based) line of input, reporting problems as a [`ChallengeError`].
+///
+/// Returns `Ok(None)` for a blank line which should be skipped. In strict mode, blank lines are
+/// an error instead.
+pub fn parse_record(
+    line: &str,
+    line_number: u64,
+    strict: bool,
+    dialect: Dialect,
+) -> Result<Option<(&str, f32)>, ChallengeError> {
+    if line.is_empty() {
+        if strict {
+            return Err(ChallengeError::MalformedLine {
+                line: line_number,
+                reason: "blank line",
+            });
+        }
+        return Ok(None);
+    }
+
+    parse_line(line, strict, dialect)
+        .map(Some)
+        .map_err(|reason| ChallengeError::MalformedLine {
+            line: line_number,
+            reason,
+        })
+}
+
+/// Parse a line of input like [`parse_record`], without checking the station name is valid
+/// UTF-8.
+///
+/// A station name can only be split from its measurement at a `;` byte, which never occurs
+/// inside a multi-byte character, so checking each distinct name once can be left until the
+/// results are built. A measurement which isn't valid UTF-8 is malformed.
+pub fn parse_record_bytes(
+    line: &[u8],
+    line_number: u64,
+    strict: bool,
+    dialect: Dialect,
+) -> Result<Option<(&[u8], f32)>, ChallengeError> {
+    if line.is_empty() {
+        return parse_record("", line_number, strict, dialect).map(|_| None);
+    }
+
+    let malformed = |reason| ChallengeError::MalformedLine {
+        line: line_number,
+        reason,
+    };
+    let idx = key_end(line, dialect, strict).map_err(malformed)?;
+    let key = &line[..idx];
+    let measurement = std::str::from_utf8(&line[idx + 1..])
+        .map_err(|_| malformed(INVALID_MEASUREMENT))
+        .and_then(|m| parse_value(key, m, strict, dialect).map_err(malformed))?;
+    Ok(Some((key, measurement)))
+}
+
+/// Aggregate measurements into per-station statistics.
+///
+/// The hashing algorithm used for the station map is configurable with `S`. Lines often come in
+/// runs for the same station (e.g. in sorted shards), so a line for the same station as the one
+/// before it skips the map altogether.
+///
+/// ```
+/// use onebrc::aggregate::Aggregator;
+///
+/// let mut aggregator: Aggregator = Aggregator::new();
+/// aggregator.ingest_line("Hamburg;12.0")?;
+/// aggregator.ingest_line("Bulawayo;8.9")?;
+/// aggregator.ingest_line("Hamburg;34.2")?;
+///
+/// let stations = aggregator.into_sorted();
+/// assert_eq!(stations[0].name(), "Bulawayo");
+/// assert_eq!(stations[1].to_string(), "Hamburg=12.0/23.1/34.2");
+/// assert_eq!(stations[1].count(), 2);
+/// # Ok::<(), onebrc::error::ChallengeError>(())
+/// ```
+pub struct Aggregator<S = RandomState> {
+    /// Where each station is in `stations`
+    ids: HashMap<String, usize, S>,
+
+    /// Each station's name & data, in the order they were first seen. Unlike the entries of the
+    /// map, these never move, so the last one used can be remembered by its index.
+    stations: Vec<(String, StationData)>,
+
+    /// The station the last measurement was for
+    last: Option<usize>,
+
+    /// Where each station is in `stations` by its [case-folded](fold_case) name, if
+    /// [case-insensitive](Aggregator::case_insensitive)
+    folded: Option<HashMap<String, usize, S>>,
+    known_stations: Option<Arc<[String]>>,
+    lines: u64,
+    strict: bool,
+    dialect: Dialect,
+    track_extents: bool,
+    max_skipped: u64,
+    skipped: SkippedLines,
+
+    /// The storage of earlier results to build these in, if [reusing](Aggregator::reuse_results)
+    results: ResultsBuffer,
+}
+
+impl<S: BuildHasher + Default> Aggregator<S> {
+    pub fn new() -> Self {
+        Self::with_known_stations(None)
+    }
+
+    /// Create an aggregator primed with the (sorted) stations known to be in the input.
+    ///
+    /// This sizes the map for every station up front, means the first sighting of each station
+    /// is an update rather than an insert, and lets [`Aggregator::into_sorted`] skip sorting.
+    pub fn with_known_stations(known_stations: Option<Arc<[String]>>) -> Self {
+        let mut aggregator = Self {
+            ids: HashMap::default(),
+            stations: Vec::new(),
+            last: None,
+            folded: None,
+            known_stations: None,
+            lines: 0,
+            strict: false,
+            dialect: Dialect::default(),
+            track_extents: false,
+            max_skipped: 0,
+            skipped: SkippedLines::default(),
+            results: ResultsBuffer::default(),
+        };
+        if let Some(names) = &known_stations {
+            aggregator.ids.reserve(names.len());
+            aggregator.stations.reserve(names.len());
+            for name in names.iter() {
+                aggregator.id(name);
+            }
+        }
+        aggregator.known_stations = known_stations;
+        aggregator
+    }
+
+    /// Reject blank lines rather than skipping them.
+    ///
+    /// The other builder methods can be chained with this, e.g. to skip a malformed line
+    /// instead of failing on it:
+    ///
+    /// ```
+    /// use onebrc::aggregate::{Aggregator, KeyFormat};
+    ///
+    /// let mut aggregator: Aggregator = Aggregator::new()
+    ///     .strict(true)
+    ///     .key_format(KeyFormat::MonthStation)
+    ///     .max_skipped(1);
+    /// aggregator.ingest_str("2023-07;Hamburg;12.0\nHamburg;12.0\n2023-08;Hamburg;34.2\n")?;
+    ///
+    /// assert_eq!(aggregator.skipped().total(), 1);
+    /// let stations = aggregator.into_sorted();
+    /// assert_eq!(stations[0].to_string(), "Hamburg (2023-07)=12.0/12.0/12.0");
+    /// assert_eq!(stations[1].month(), Some("2023-08"));
+    /// # Ok::<(), onebrc::error::ChallengeError>(())
+    /// ```
+    pub fn strict(mut self, strict: bool) -> Self {
+        self.strict = strict;
+        self
+    }
+
+    /// Group measurements by the given key rather than just by station
+    pub fn key_format(mut self, key_format: KeyFormat) -> Self {
+        self.dialect.key_format = key_format;
+        self
+    }
+
+    /// Parse lines laid out as described by `dialect`, which includes the
+    /// [key format](Aggregator::key_format)
+    pub fn dialect(mut self, dialect: Dialect) -> Self {
+        self.dialect = dialect;
+        self
+    }
+
+    /// Record the first & last row each station is on (see [`StationData::extents`])
+    pub fn track_extents(mut self, track_extents: bool) -> Self {
+        self.track_extents = track_extents;
+        self
+    }
+
+    /// Merge stations whose names only differ in case (see [`fold_case`]), shown with the
+    /// spelling seen first.
+    ///
+    /// A name is only folded the first time it's seen; every spelling is remembered, so later
+    /// lines with it are looked up as usual.
+    ///
+    /// ```
+    /// use onebrc::aggregate::Aggregator;
+    ///
+    /// let mut aggregator: Aggregator = Aggregator::new().case_insensitive(true);
+    /// aggregator.ingest_str("istanbul;12.0\nIstanbul;8.9\nISTANBUL;34.2\n")?;
+    ///
+    /// let stations = aggregator.into_sorted();
+    /// assert_eq!(stations.len(), 1);
+    /// assert_eq!(stations[0].to_string(), "istanbul=8.9/18.4/34.2");
+    /// # Ok::<(), onebrc::error::ChallengeError>(())
+    /// ```
+    pub fn case_insensitive(mut self, case_insensitive: bool) -> Self {
+        if !case_insensitive {
+            self.folded = None;
+            return self;
+        }
+        let mut folded = HashMap::with_capacity_and_hasher(self.stations.capacity(), S::default());
+        for (name, &id) in &self.ids {
+            // The known stations were added in order, so the first spelling has the lowest id
+            folded
+                .entry(fold_case(name))
+                .and_modify(|first: &mut usize| *first = (*first).min(id))
+                .or_insert(id);
+        }
+        self.folded = Some(folded);
+        self
+    }
+
+    /// Skip up to `max_skipped` malformed lines rather than failing on the first one (see
+    /// [`SkippedLines::skip`])
+    pub fn max_skipped(mut self, max_skipped: u64) -> Self {
+        self.max_skipped = max_skipped;
+        self
+    }
+
+    /// Make room for at least `stations` stations up front, so the map isn't resized until
+    /// there are more of them than that (e.g. [`MAX_STATIONS`]).
+    pub fn with_capacity(mut self, stations: usize) -> Self {
+        let more = stations.saturating_sub(self.stations.len());
+        self.ids.reserve(more);
+        self.stations.reserve(more);
+        if let Some(folded) = &mut self.folded {
+            folded.reserve(stations.saturating_sub(folded.len()));
+        }
+        self
+    }
+
+    /// Build the results in the storage of earlier ones rather than allocating new ones.
+    ///
+    /// Only the names of stations added from here on are copied into recycled `String`s, so not
+    /// those of any known stations.
+    pub fn reuse_results(mut self, results: ResultsBuffer) -> Self {
+        self.results = results;
+        self
+    }
+}
+
+impl<S: BuildHasher + Default> Default for Aggregator<S> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<S: BuildHasher> Aggregator<S> {
+    /// Parse a single line of input (without its newline) & record its measurement.
+    ///
+    /// Blank lines (e.g. leading or trailing newlines) are skipped, unless the aggregator is
+    /// [strict](Aggregator::strict). Either way they still count towards line numbers.
+    pub fn ingest_line(&mut self, line: &str) -> Result<(), ChallengeError> {
+        self.lines += 1;
+        let record = match parse_record(line, self.lines, self.strict, self.dialect) {
+            Ok(record) => record,
+            Err(e) => return self.skipped.skip(e, 1, self.max_skipped),
+        };
+        if let Some((station, measurement)) = record {
+            self.record(station, measurement);
+        }
+        Ok(())
+    }
+
+    /// Record a line of input which has already been split into its station & measurement, e.g.
+    /// by a runner with a faster way of splitting the common case.
+    ///
+    /// Like [`ingest_line`](Aggregator::ingest_line), this counts towards line numbers; any
+    /// line the caller can't split itself should be passed to `ingest_line` instead, so it's
+    /// parsed (or reported as malformed) the usual way.
+    #[inline]
+    pub fn ingest_record(&mut self, station: &str, measurement: f32) {
+        self.lines += 1;
+        self.record(station, measurement);
+    }
+
+    #[inline]
+    fn record(&mut self, station: &str, measurement: f32) {
+        let id = self.id(station);
+        let data = &mut self.stations[id].1;
+        data.push(measurement, self.strict);
+        if self.track_extents {
+            data.record_row(self.lines);
+        }
+    }
+
+    /// Parse every line of a whole input (e.g. the contents of a file) & record their
+    /// measurements.
+    ///
+    /// Lines may end with `\n` or `\r\n`. A UTF-8 byte-order mark at the start is skipped,
+    /// unless the aggregator is [strict](Aggregator::strict).
+    ///
+    /// ```
+    /// use onebrc::aggregate::Aggregator;
+    ///
+    /// let mut aggregator: Aggregator = Aggregator::new();
+    /// aggregator.ingest_str("Hamburg;12.0\r\nBulawayo;8.9\r\n")?;
+    /// aggregator.ingest_str("Hamburg;34.2")?;
+    ///
+    /// let stations = aggregator.into_sorted();
+    /// assert_eq!(stations.len(), 2);
+    /// assert_eq!(stations[1].to_string(), "Hamburg=12.0/23.1/34.2");
+    /// # Ok::<(), onebrc::error::ChallengeError>(())
+    /// ```
+    pub fn ingest_str(&mut self, input: &str) -> Result<(), ChallengeError> {
+        let input = match input.strip_prefix('\u{FEFF}') {
+            Some(_) if self.strict && self.lines == 0 => return Err(ChallengeError::ByteOrderMark),
+            Some(rest) if self.lines == 0 => rest,
+            _ => input,
+        };
+        for line in input.lines() {
+            self.ingest_line(line)?;
+        }
+        Ok(())
+    }
+
+    /// Skip a line the reader couldn't read, e.g. because it was too long, if allowed to.
+    ///
+    /// The line still counts towards line numbers.
+    pub fn skip_unreadable(&mut self, error: ChallengeError) -> Result<(), ChallengeError> {
+        self.lines += 1;
+        self.skipped.skip(error, 1, self.max_skipped)
+    }
+
+    /// Record a measurement for a station
+    pub fn push(&mut self, station: &str, measurement: f32) {
+        let id = self.id(station);
+        self.stations[id].1.push(measurement, self.strict);
+    }
+
+    /// Where `station` is in the list of stations, adding it if it's new
+    #[inline]
+    fn id(&mut self, station: &str) -> usize {
+        if let Some(last) = self.last {
+            if self.stations[last].0 == station {
+                return last;
+            }
+        }
+
+        let id = match self.ids.get(station) {
+            Some(&id) => id,
+            None => self.add(station),
+        };
+        self.last = Some(id);
+        id
+    }
+
+    /// Add a spelling of a station which hasn't been seen before, merging it with any station
+    /// whose name only differs in case if case-insensitive
+    #[cold]
+    fn add(&mut self, station: &str) -> usize {
+        let id = match &mut self.folded {
+            Some(folded) => *folded
+                .entry(fold_case(station))
+                .or_insert(self.stations.len()),
+            None => self.stations.len(),
+        };
+        if id == self.stations.len() {
+            let name = self.results.name(station);
+            self.stations.push((name, StationData::empty()));
+        }
+        self.ids.insert(station.to_owned(), id);
+        id
+    }
+
+    /// How many stations the map can hold before it has to be resized
+    pub fn capacity(&self) -> usize {
+        self.ids.capacity()
+    }
+
+    /// How many non-finite measurements have been left out so far
+    pub fn ignored_non_finite(&self) -> u64 {
+        self.stations
+            .iter()
+            .map(|(_, data)| data.skipped as u64)
+            .sum()
+    }
+
+    /// The malformed lines skipped so far
+    pub fn skipped(&self) -> SkippedLines {
+        self.skipped
+    }
+
+    /// The stations aggregated so far, for watching the run's progress
+    #[cfg(feature = "native")]
+    pub fn snapshot(&self) -> crate::progress::Snapshot {
+        crate::progress::Snapshot {
+            stations: self
+                .stations
+                .iter()
+                .filter(|(_, data)| data.cnt > 0)
+                .map(|(name, data)| {
+                    StationInfo::new(name.clone(), data.min, data.max, data.avg(), data.cnt)
+                        .keyed_by(self.dialect.key_format)
+                })
+                .collect(),
+            skipped: self.skipped,
+            ignored_non_finite: self.ignored_non_finite(),
+        }
+    }
+
+    /// Every station's name & data, in the order they were first seen, for merging with other
+    /// aggregates rather than building the results straight away
+    pub fn into_stations(self) -> Vec<(String, StationData)> {
+        self.stations
+    }
+
+    /// Build the alphabetically-sorted list of stations.
+    ///
+    /// If the aggregator was primed with a sorted list of known stations, that ordering is
+    /// reused rather than sorting again; only stations missing from the list (if any) need to be
+    /// sorted in.
+    pub fn into_sorted(mut self) -> Vec<StationInfo> {
+        // The known stations come first, in order, and the rest only if any were missing from
+        // the list
+        let known = self.known_stations.map_or(0, |names| names.len());
+        let sorted = self.stations.len() == known && known > 0;
+
+        // Stations whose every measurement was skipped (or known stations which never showed
+        // up) don't belong in the output
+        let key_format = self.dialect.key_format;
+        let mut stations = self.results.take_stations();
+        stations.extend(
+            self.stations
+                .into_iter()
+                .filter(|(_, data)| data.cnt > 0)
+                .map(|(name, data)| {
+                    StationInfo::new(name, data.min, data.max, data.avg(), data.cnt)
+                        .with_extents(data.extents())
+                        .keyed_by(key_format)
+                }),
+        );
+        if !sorted {
+            stations.sort_unstable();
+        }
+        stations
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ingest_str_byte_order_mark() {
+        let input = "\u{FEFF}Hamburg;12.0\n";
+        let mut aggregator: Aggregator = Aggregator::new();
+        aggregator.ingest_str(input).unwrap();
+        assert_eq!(aggregator.into_sorted()[0].name(), "Hamburg");
+
+        let mut strict: Aggregator = Aggregator::new().strict(true);
+        assert!(matches!(
+            strict.ingest_str(input),
+            Err(ChallengeError::ByteOrderMark)
+        ));
+
+        // Only at the very start of the input
+        let mut aggregator: Aggregator = Aggregator::new();
+        aggregator.ingest_str("Hamburg;12.0\n").unwrap();
+        aggregator.ingest_str(input).unwrap();
+        assert_eq!(aggregator.into_sorted()[1].name(), "\u{FEFF}Hamburg");
+    }
+
+    #[test]
+    fn case_insensitive_ascii() {
+        let input = "Istanbul;10.0\nistanbul;20.0\nHamburg;5.0\nISTANBUL;30.0\nhamburg;7.0\n";
+
+        let mut aggregator: Aggregator = Aggregator::new().case_insensitive(true);
+        aggregator.ingest_str(input).unwrap();
+        let stations = aggregator.into_sorted();
+        let names: Vec<_> = stations.iter().map(|s| (s.name(), s.count())).collect();
+        assert_eq!(names, [("Hamburg", 2), ("Istanbul", 3)]);
+        assert_eq!(stations[1].avg(), 20.0);
+
+        // Off by default
+        let mut aggregator: Aggregator = Aggregator::new();
+        aggregator.ingest_str(input).unwrap();
+        assert_eq!(aggregator.into_sorted().len(), 5);
+    }
+
+    #[test]
+    fn case_insensitive_dotted_i() {
+        // `İ` folds to a plain `i`, like it does in Turkish, rather than one with a combining dot
+        assert_eq!(fold_case("İzmir"), "izmir");
+        assert_eq!(fold_case("İZMİR"), fold_case("izmir"));
+        assert_eq!(fold_case("Izmir"), fold_case("izmir"));
+        assert_eq!(fold_case("Straße"), fold_case("STRASSE"));
+        assert_ne!(fold_case("Izmir"), fold_case("Ismir"));
+        // Upper-casing a dotless `ı` would make it an `I`, & so a plain `i`
+        assert_eq!(fold_case("DİYARBAKIR"), fold_case("diyarbakir"));
+        assert_ne!(fold_case("Diyarbakır"), fold_case("Diyarbakir"));
+        assert_eq!(fold_case("ΟΔΟΣ"), fold_case("οδος"));
+
+        let input = "İZMİR;1.0\nİzmir;2.0\nizmir;3.0\nIZMIR;4.0\nİzmit;5.0\n\
+                     Diyarbakır;6.0\nDiyarbakir;7.0\n";
+        let mut aggregator: Aggregator = Aggregator::new().case_insensitive(true);
+        aggregator.ingest_str(input).unwrap();
+        let stations = aggregator.into_sorted();
+        let names: Vec<_> = stations.iter().map(|s| (s.name(), s.count())).collect();
+        assert_eq!(
+            names,
+            [
+                ("Diyarbakir", 1),
+                ("Diyarbakır", 1),
+                ("İZMİR", 4),
+                ("İzmit", 1)
+            ]
+        );
+    }
+
+    #[test]
+    fn case_insensitive_first_spelling() {
+        // Known stations keep their spelling, & a new station is shown as it was first written,
+        // however many times another spelling comes up afterwards
+        let known: Arc<[String]> = vec![String::from("Bulawayo")].into();
+        let mut aggregator: Aggregator =
+            Aggregator::with_known_stations(Some(known)).case_insensitive(true);
+        aggregator
+            .ingest_str("BULAWAYO;1.0\nhAmBuRg;2.0\nHamburg;3.0\nHamburg;4.0\nHAMBURG;5.0\n")
+            .unwrap();
+        let names: Vec<_> = aggregator
+            .into_sorted()
+            .iter()
+            .map(|s| (s.name().to_owned(), s.count()))
+            .collect();
+        assert_eq!(
+            names,
+            [(String::from("Bulawayo"), 1), (String::from("hAmBuRg"), 4)]
+        );
+    }
+
+    #[test]
+    fn merge_case_variants_in_order() {
+        let data = |m| StationData::new(m, false);
+        let merged = merge_case_variants([
+            (String::from("HAMBURG"), data(3.0), 20),
+            (String::from("Hamburg"), data(1.0), 10),
+            (String::from("Nowhere"), StationData::empty(), 0),
+            (String::from("hamburg"), data(2.0), 15),
+        ]);
+        assert_eq!(merged.len(), 1);
+        assert_eq!(merged[0].0, "Hamburg");
+        assert_eq!((merged[0].1.cnt, merged[0].1.sum), (3, 6.0));
+    }
+
+    #[test]
+    fn boundary_measurements() {
+        // The extremes of the canonical form, either side of where the integer part gains a
+        // digit, & both zeroes
+        let cases = [
+            ("99.9", 999),
+            ("-99.9", -999),
+            ("0.0", 0),
+            ("-0.0", 0),
+            ("9.9", 99),
+            ("-9.9", -99),
+            ("10.0", 100),
+            ("-10.0", -100),
+        ];
+        let exact = |parsed: f32, tenths: i32, what: &str| {
+            assert_eq!(parsed, tenths as f32 / 10.0, "{what}");
+            assert_eq!((parsed * 10.0).round() as i32, tenths, "{what}");
+        };
+
+        for (measurement, tenths) in cases {
+            exact(parse_fixed(measurement).unwrap(), tenths, measurement);
+            for strict in [false, true] {
+                exact(
+                    parse_measurement(measurement, strict).unwrap(),
+                    tenths,
+                    measurement,
+                );
+            }
+
+            for dialect in dialects() {
+                let delimiter = dialect.delimiter.byte() as char;
+                let key = match dialect.key_format {
+                    KeyFormat::Station => String::from("Hamburg"),
+                    KeyFormat::MonthStation => format!("2023-07{delimiter}Hamburg"),
+                };
+                let value = if dialect.decimal_comma {
+                    measurement.replace('.', ",")
+                } else {
+                    measurement.to_owned()
+                };
+                let line = format!("{key}{delimiter}{value}");
+                for strict in [false, true] {
+                    let what = format!("{line:?} {dialect:?}");
+                    let (_, parsed) = parse_line(&line, strict, dialect).unwrap();
+                    exact(parsed, tenths, &what);
+                    let (_, parsed) = parse_record_bytes(line.as_bytes(), 1, strict, dialect)
+                        .unwrap()
+                        .unwrap();
+                    exact(parsed, tenths, &what);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn malformed_line_number() {
+        let mut aggregator: Aggregator = Aggregator::new();
+        aggregator.ingest_line("Hamburg;12.0").unwrap();
+        match aggregator.ingest_line("Hamburg") {
+            Err(ChallengeError::MalformedLine { line, .. }) => assert_eq!(line, 2),
+            other => panic!("expected MalformedLine, got {other:?}"),
+        }
+    }
+
+    /// Every combination of key format, delimiter & decimal mark worth testing
+    fn dialects() -> Vec<Dialect> {
+        let mut dialects = Vec::new();
+        for key_format in [KeyFormat::Station, KeyFormat::MonthStation] {
+            for (delimiter, decimal_comma) in [(b';', false), (b';', true), (b'\t', false)]
+                .into_iter()
+                .chain([(b',', false), (b',', true)])
+            {
+                dialects.push(Dialect {
+                    key_format,
+                    delimiter: Delimiter::new(delimiter).unwrap(),
+                    decimal_comma,
+                    encoding: Encoding::Utf8,
+                });
+            }
+        }
+        dialects
+    }
+
+    #[test]
+    fn parse_record_bytes_matches_str() {
+        for line in [
+            "Hamburg;12.0",
+            "Aïn el Mediour;-5.7",
+            "",
+            "Hamburg",
+            "Hamburg;x",
+            "2023-07;Hamburg;12.0",
+            "2023-13;Hamburg;12.0",
+            "Hamburg\t12.0",
+            "Hamburg,12.0",
+            "Hamburg,12,3",
+            "Washington, D.C.,1,2",
+            "2023-07,Hamburg,-1,5",
+        ] {
+            for dialect in dialects() {
+                for strict in [false, true] {
+                    let expected = parse_record(line, 1, strict, dialect)
+                        .map(|r| r.map(|(s, m)| (s.as_bytes(), m)));
+                    let actual = parse_record_bytes(line.as_bytes(), 1, strict, dialect);
+                    assert_eq!(
+                        format!("{actual:?}"),
+                        format!("{expected:?}"),
+                        "{line:?} {dialect:?}"
+                    );
+                }
+            }
+        }
+
+        // Names are left for the caller to check, but measurements must be readable
+        assert!(matches!(
+            parse_record_bytes(b"Ham\xFFburg;12.0", 1, true, Dialect::default()),
+            Ok(Some((b"Ham\xFFburg", 12.0)))
+        ));
+        assert!(parse_record_bytes(b"Hamburg;1\xFF.0", 1, false, Dialect::default()).is_err());
+    }
+
+    #[test]
+    fn non_finite_left_out() {
+        // A station with nothing but non-finite measurements is left out entirely
+        let mut aggregator: Aggregator = Aggregator::new();
+        aggregator.push("Hamburg", 12.0);
+        aggregator.push("Hamburg", f32::NAN);
+        aggregator.push("Bulawayo", f32::INFINITY);
+        assert_eq!(aggregator.ignored_non_finite(), 2);
+        let stations = aggregator.into_sorted();
+        assert_eq!(stations.len(), 1);
+        assert_eq!(stations[0].name(), "Hamburg");
+        assert!(stations[0].avg().is_finite());
+    }
+}
@@ -0,0 +1,410 @@
+// 1BRC - my take on the 1 Billion Row Challenge
+// Copyright (C) 2024  Charles German <5donuts@pm.me>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Sizing the blocks the pipelined runner reads as it goes, for `--block-sizing adaptive`.
+//!
+//! No one block size suits every disk: on fast local storage small blocks spend their time in
+//! syscalls, while on network storage big ones stall for long enough that reading stops
+//! overlapping with parsing. The [`Controller`] starts at a moderate size & nudges it between
+//! blocks, from how long the last read took & how many blocks were waiting for the parsers.
+
+use std::collections::BTreeSet;
+use std::fmt::{self, Display};
+use std::str::FromStr;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+use crate::helpers::{fmt_bytes, parse_bytes};
+
+/// The smallest block adaptive sizing picks, unless given other bounds
+pub const DEFAULT_MIN: usize = 64 << 10;
+
+/// The largest block adaptive sizing picks, unless given other bounds
+pub const DEFAULT_MAX: usize = 16 << 20;
+
+/// The size adaptive sizing starts at, if it's within the bounds
+pub const START: usize = 1 << 20;
+
+/// A read which takes longer than this is a latency spike, and the blocks shrink
+pub const LATENCY_TARGET: Duration = Duration::from_millis(20);
+
+/// How many observations in a row must agree before the size changes, so one slow read or one
+/// busy moment doesn't send it back & forth
+const PATIENCE: i8 = 2;
+
+/// How the blocks a runner reads are sized
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum BlockSizing {
+    /// Every block is the runner's own fixed size
+    #[default]
+    Fixed,
+
+    /// Blocks are resized between reads by a [`Controller`], within these bounds (in bytes)
+    Adaptive { min: usize, max: usize },
+}
+
+impl BlockSizing {
+    /// A controller for these bounds, if sizing is adaptive
+    pub fn controller(self) -> Option<Controller> {
+        match self {
+            BlockSizing::Fixed => None,
+            BlockSizing::Adaptive { min, max } => Some(Controller::new(min, max)),
+        }
+    }
+}
+
+impl FromStr for BlockSizing {
+    type Err = String;
+
+    /// Parse `fixed`, `adaptive`, or `adaptive:MIN-MAX` with the bounds in bytes (with an
+    /// optional unit, e.g. `adaptive:64KiB-4MiB`)
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let bounds = match s.split_once(':') {
+            None if s == "fixed" => return Ok(BlockSizing::Fixed),
+            None if s == "adaptive" => {
+                return Ok(BlockSizing::Adaptive {
+                    min: DEFAULT_MIN,
+                    max: DEFAULT_MAX,
+                })
+            }
+            Some(("adaptive", bounds)) => bounds,
+            _ => {
+                return Err(format!(
+                    "expected fixed, adaptive or adaptive:MIN-MAX, not '{s}'"
+                ))
+            }
+        };
+        let (min, max) = bounds
+            .split_once('-')
+            .ok_or_else(|| format!("expected the bounds as MIN-MAX, not '{bounds}'"))?;
+        let parse = |s: &str| -> Result<usize, String> {
+            usize::try_from(parse_bytes(s)?).map_err(|_| format!("'{s}' is too big"))
+        };
+        let (min, max) = (parse(min)?, parse(max)?);
+        if min == 0 || min > max {
+            return Err(format!(
+                "the smallest block must be at least 1 byte & no bigger than the largest, not {min}-{max}"
+            ));
+        }
+        Ok(BlockSizing::Adaptive { min, max })
+    }
+}
+
+impl Display for BlockSizing {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BlockSizing::Fixed => write!(f, "fixed"),
+            BlockSizing::Adaptive { min, max } => write!(f, "adaptive:{min}-{max}"),
+        }
+    }
+}
+
+impl Serialize for BlockSizing {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for BlockSizing {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+/// What the reader saw around the block it just read
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Observation {
+    /// How long reading the block took
+    pub read: Duration,
+
+    /// How many blocks were queued for the parsers, waiting to be parsed
+    pub queued: usize,
+
+    /// How many blocks can be queued before the reader has to wait
+    pub capacity: usize,
+}
+
+/// Which way an observation argues the size should go
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Decision {
+    Grow,
+    Shrink,
+    Hold,
+}
+
+impl Observation {
+    /// A slow read shrinks the blocks whatever else is going on, since waiting on it is what
+    /// stops reading overlapping with parsing. Otherwise a full queue means the parsers are
+    /// behind, so bigger blocks would only take up more memory, & an empty one means they're
+    /// waiting on the reader, which bigger blocks (& so fewer reads) would help.
+    pub fn decision(&self) -> Decision {
+        if self.read > LATENCY_TARGET || self.queued >= self.capacity {
+            Decision::Shrink
+        } else if self.queued == 0 {
+            Decision::Grow
+        } else {
+            Decision::Hold
+        }
+    }
+}
+
+/// Picks the size of each block from what was observed reading the ones before it.
+///
+/// Sizes are doubled or halved, within the bounds, once [`PATIENCE`] observations in a row
+/// agree which way to go.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Controller {
+    min: usize,
+    max: usize,
+    size: usize,
+
+    /// How many observations in a row have argued to grow (positive) or shrink (negative)
+    streak: i8,
+}
+
+impl Controller {
+    pub fn new(min: usize, max: usize) -> Self {
+        let min = min.max(1);
+        let max = max.max(min);
+        Self {
+            min,
+            max,
+            size: START.clamp(min, max),
+            streak: 0,
+        }
+    }
+
+    /// The size of the next block
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    /// Take in what was seen around the last block, returning the size of the next one
+    pub fn observe(&mut self, observation: Observation) -> usize {
+        self.streak = match observation.decision() {
+            Decision::Grow => self.streak.max(0) + 1,
+            Decision::Shrink => self.streak.min(0) - 1,
+            Decision::Hold => 0,
+        };
+        if self.streak >= PATIENCE {
+            self.size = self.size.saturating_mul(2).min(self.max);
+            self.streak = 0;
+        } else if self.streak <= -PATIENCE {
+            self.size = (self.size / 2).max(self.min);
+            self.streak = 0;
+        }
+        self.size
+    }
+
+    /// Every size this controller can go on to pick, smallest first.
+    ///
+    /// Halving rounds down & both directions stop at the bounds, so with bounds which aren't
+    /// powers of two apart, shrinking & growing again can land on sizes which weren't seen on the
+    /// way down; this follows every path until no new sizes turn up.
+    pub fn choices(&self) -> Vec<usize> {
+        let mut choices = BTreeSet::from([self.size]);
+        let mut pending = vec![self.size];
+        while let Some(size) = pending.pop() {
+            for next in [
+                (size / 2).max(self.min),
+                size.saturating_mul(2).min(self.max),
+            ] {
+                if choices.insert(next) {
+                    pending.push(next);
+                }
+            }
+        }
+        choices.into_iter().collect()
+    }
+}
+
+/// The size of every block a run read, in order, for `--timings`
+#[derive(Debug, Default)]
+pub struct BlockSizeLog {
+    sizes: Mutex<Vec<usize>>,
+}
+
+impl BlockSizeLog {
+    /// Forget the sizes of any previous run
+    pub fn start(&self) {
+        self.lock().clear();
+    }
+
+    pub fn record(&self, size: usize) {
+        self.lock().push(size);
+    }
+
+    /// Every size recorded since the run started
+    pub fn sizes(&self) -> Vec<usize> {
+        self.lock().clone()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.lock().is_empty()
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Vec<usize>> {
+        // The reader is the only thread which records, & a panic fails the whole run anyway
+        self.sizes.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl Display for BlockSizeLog {
+    /// Each size in turn, with how many blocks in a row were read at it, e.g.
+    /// `1.0 MiB ×4 → 2.0 MiB ×12 → 1.0 MiB ×3`
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let sizes = self.sizes();
+        let mut runs: Vec<(usize, usize)> = Vec::new();
+        for size in sizes {
+            match runs.last_mut() {
+                Some((last, count)) if *last == size => *count += 1,
+                _ => runs.push((size, 1)),
+            }
+        }
+        for (i, (size, count)) in runs.into_iter().enumerate() {
+            if i > 0 {
+                write!(f, " → ")?;
+            }
+            write!(f, "{} ×{count}", fmt_bytes(size as u64))?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FAST: Duration = Duration::from_millis(1);
+    const SLOW: Duration = Duration::from_millis(100);
+
+    fn seen(read: Duration, queued: usize) -> Observation {
+        Observation {
+            read,
+            queued,
+            capacity: 8,
+        }
+    }
+
+    #[test]
+    fn parse() {
+        assert_eq!("fixed".parse(), Ok(BlockSizing::Fixed));
+        assert_eq!(
+            "adaptive".parse(),
+            Ok(BlockSizing::Adaptive {
+                min: DEFAULT_MIN,
+                max: DEFAULT_MAX
+            })
+        );
+        let sizing: BlockSizing = "adaptive:64KiB-4MiB".parse().unwrap();
+        assert_eq!(
+            sizing,
+            BlockSizing::Adaptive {
+                min: 64 << 10,
+                max: 4 << 20
+            }
+        );
+        assert_eq!(sizing.to_string().parse(), Ok(sizing));
+
+        assert!("adaptive:4MiB-64KiB".parse::<BlockSizing>().is_err());
+        assert!("adaptive:0-1".parse::<BlockSizing>().is_err());
+        assert!("adaptive:1MiB".parse::<BlockSizing>().is_err());
+        assert!("dynamic".parse::<BlockSizing>().is_err());
+    }
+
+    #[test]
+    fn decisions() {
+        assert_eq!(seen(FAST, 0).decision(), Decision::Grow);
+        assert_eq!(seen(FAST, 3).decision(), Decision::Hold);
+        assert_eq!(seen(FAST, 8).decision(), Decision::Shrink);
+        // A latency spike outweighs starved parsers
+        assert_eq!(seen(SLOW, 0).decision(), Decision::Shrink);
+    }
+
+    /// Starved parsers on fast storage grow the blocks up to the largest, and no further
+    #[test]
+    fn grows_when_starved() {
+        let mut controller = Controller::new(DEFAULT_MIN, 4 << 20);
+        assert_eq!(controller.size(), START);
+
+        // One observation isn't enough on its own
+        assert_eq!(controller.observe(seen(FAST, 0)), START);
+        assert_eq!(controller.observe(seen(FAST, 0)), 2 << 20);
+        let sizes: Vec<usize> = (0..6).map(|_| controller.observe(seen(FAST, 0))).collect();
+        assert_eq!(
+            sizes,
+            [2 << 20, 4 << 20, 4 << 20, 4 << 20, 4 << 20, 4 << 20]
+        );
+    }
+
+    /// A full queue, or reads with latency spikes, shrink the blocks down to the smallest
+    #[test]
+    fn shrinks_when_full_or_slow() {
+        let mut controller = Controller::new(256 << 10, DEFAULT_MAX);
+        controller.observe(seen(FAST, 8));
+        assert_eq!(controller.observe(seen(FAST, 8)), 512 << 10);
+        controller.observe(seen(SLOW, 0));
+        assert_eq!(controller.observe(seen(SLOW, 2)), 256 << 10);
+        controller.observe(seen(SLOW, 2));
+        assert_eq!(controller.observe(seen(SLOW, 2)), 256 << 10);
+    }
+
+    /// Signals which disagree, or a queue which is neither empty nor full, leave the size be
+    #[test]
+    fn holds_when_mixed() {
+        let mut controller = Controller::new(DEFAULT_MIN, DEFAULT_MAX);
+        for _ in 0..10 {
+            assert_eq!(controller.observe(seen(FAST, 0)), START);
+            assert_eq!(controller.observe(seen(FAST, 8)), START);
+        }
+        for _ in 0..10 {
+            assert_eq!(controller.observe(seen(FAST, 4)), START);
+        }
+        // A hold breaks a streak
+        controller.observe(seen(FAST, 0));
+        controller.observe(seen(FAST, 4));
+        assert_eq!(controller.observe(seen(FAST, 0)), START);
+    }
+
+    /// The start is kept within bounds which don't include it, & the sizes reached by shrinking
+    /// then growing again (like 12 → 24) are choices too
+    #[test]
+    fn bounds() {
+        assert_eq!(Controller::new(4 << 20, 8 << 20).size(), 4 << 20);
+        assert_eq!(Controller::new(1, 100).size(), 100);
+        assert_eq!(
+            Controller::new(10, 100).choices(),
+            [10, 12, 20, 24, 25, 40, 48, 50, 80, 96, 100]
+        );
+        assert_eq!(
+            Controller::new(256 << 10, 4 << 20).choices(),
+            [256 << 10, 512 << 10, 1 << 20, 2 << 20, 4 << 20]
+        );
+    }
+
+    #[test]
+    fn log() {
+        let log = BlockSizeLog::default();
+        for size in [1 << 20, 1 << 20, 2 << 20, 1 << 20] {
+            log.record(size);
+        }
+        assert_eq!(log.to_string(), "1.0 MiB ×2 → 2.0 MiB ×1 → 1.0 MiB ×1");
+        log.start();
+        assert_eq!(log.to_string(), "");
+    }
+}
@@ -0,0 +1,453 @@
+// 1BRC - my take on the 1 Billion Row Challenge
+// Copyright (C) 2024  Charles German <5donuts@pm.me>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Read the input in blocks of whole lines which can be handed off to other threads.
+
+use std::io::{self, Read, Seek, SeekFrom};
+use std::sync::Arc;
+
+use crate::aggregate::{parse_record, Dialect};
+use crate::boundaries::{first_newline, last_newline};
+use crate::config::Config;
+use crate::error::{ChallengeError, SkippedLines};
+use crate::progress::Progress;
+use crate::reader::leading_bom;
+
+/// A run of complete lines from the input, either read into a buffer of its own or borrowed
+/// from the whole input in memory (e.g. a `&[u8]` of a memory map)
+#[derive(Debug)]
+pub struct Block<D = Vec<u8>> {
+    /// Byte offset of the start of the block in the input
+    pub offset: u64,
+
+    /// The lines, each ending with a newline except (possibly) the last line of the input
+    pub data: D,
+
+    /// The (1-based) line number of the first line in the block, if the reader was asked to
+    /// [count lines](BlockReader::count_lines)
+    pub first_line: Option<u64>,
+}
+
+impl<D: AsRef<[u8]>> Block<D> {
+    /// Parse each line in the block, passing each key & measurement to `record` along with the
+    /// (1-based) number of the line within the block.
+    ///
+    /// This applies the same rules as [`LineReader`](crate::reader::LineReader) &
+    /// [`parse_record`]. Malformed lines are added to `skipped` as long as there are no more
+    /// than `max_skipped` in all (see [`SkippedLines::skip`]). Line numbers in the errors are
+    /// relative to the start of the block; see [`BlockFailure`].
+    pub fn for_each_record<F>(
+        &self,
+        max_line_length: usize,
+        strict: bool,
+        dialect: Dialect,
+        max_skipped: u64,
+        skipped: &mut SkippedLines,
+        mut record: F,
+    ) -> Result<(), BlockFailure>
+    where
+        F: FnMut(&str, f32, u64),
+    {
+        let data = self.data.as_ref();
+        let data = data.strip_suffix(b"\n").unwrap_or(data);
+        let mut offset = self.offset;
+        let mut decoded = String::new();
+        for (idx, line) in data.split(|&b| b == b'\n').enumerate() {
+            let line_number = idx as u64 + 1;
+            let parsed = if line.len() > max_line_length {
+                Err(ChallengeError::LineTooLong {
+                    offset,
+                    limit: max_line_length,
+                })
+            } else {
+                let text = line.strip_suffix(b"\r").unwrap_or(line);
+                dialect
+                    .encoding
+                    .decode(text, &mut decoded)
+                    .map_err(|e| ChallengeError::invalid_utf8(offset, text, e))
+                    .and_then(|text| parse_record(text, line_number, strict, dialect))
+            };
+
+            match parsed {
+                Ok(Some((station, measurement))) => record(station, measurement, line_number),
+                Ok(None) => {}
+                Err(error) => {
+                    skipped
+                        .skip(error, 1, max_skipped)
+                        .map_err(|error| BlockFailure {
+                            block_offset: self.offset,
+                            offset,
+                            error,
+                        })?
+                }
+            }
+
+            offset += line.len() as u64 + 1;
+        }
+        Ok(())
+    }
+}
+
+/// An error from a line in a [`Block`]
+#[derive(Debug)]
+pub struct BlockFailure {
+    /// Byte offset of the start of the block
+    pub block_offset: u64,
+
+    /// Byte offset of the start of the offending line
+    pub offset: u64,
+
+    /// The error, with line numbers relative to the start of the block
+    pub error: ChallengeError,
+}
+
+impl BlockFailure {
+    /// Convert to an error with line numbers relative to the start of the input.
+    ///
+    /// Line numbers aren't tracked while reading in parallel, so this counts the lines before
+    /// the block by re-reading the input up to it.
+    pub fn locate<R: Read + Seek>(self, input: &mut R) -> ChallengeError {
+        let ChallengeError::MalformedLine { line, reason } = self.error else {
+            return self.error;
+        };
+        match count_lines(input, self.block_offset) {
+            Ok(before) => ChallengeError::MalformedLine {
+                line: before + line,
+                reason,
+            },
+            Err(e) => e.into(),
+        }
+    }
+}
+
+/// Count the newlines in the first `len` bytes of the input
+fn count_lines<R: Read + Seek>(input: &mut R, len: u64) -> io::Result<u64> {
+    input.seek(SeekFrom::Start(0))?;
+    let mut buf = vec![0; 64 * 1024];
+    let mut limited = input.take(len);
+    let mut lines = 0;
+    loop {
+        let n = limited.read(&mut buf)?;
+        if n == 0 {
+            return Ok(lines);
+        }
+        lines += count_newlines(&buf[..n]);
+    }
+}
+
+pub(crate) fn count_newlines(data: &[u8]) -> u64 {
+    data.iter().filter(|&&b| b == b'\n').count() as u64
+}
+
+/// Read the input in [`Block`]s of roughly `buffer-size` bytes.
+///
+/// Each block is cut after the last newline read, with the rest of the last line carried over
+/// to the next block. As with [`LineReader`](crate::reader::LineReader), a line longer than
+/// the maximum line length is an error, and a leading byte-order mark is skipped (or an error,
+/// in strict mode). The rest of a line which was too long is skipped if reading carries on
+/// afterwards.
+pub struct BlockReader<R> {
+    inner: R,
+    block_size: usize,
+    max_line_length: usize,
+    strict: bool,
+    verbose: u8,
+
+    /// Bytes read past the end of the last block
+    carry: Vec<u8>,
+
+    /// Offset of the start of `carry` in the input
+    offset: u64,
+
+    /// The line number of the start of `carry`, if counting lines
+    line: Option<u64>,
+
+    /// Whether the line at `offset` was too long, & the rest of it still needs skipping
+    skip_rest: bool,
+
+    /// Where to count what's been read, & the offset it's been counted up to
+    progress: Option<Arc<Progress>>,
+    reported: u64,
+}
+
+impl<R: Read> BlockReader<R> {
+    pub fn new(input: R, config: &Config) -> Self {
+        Self {
+            inner: input,
+            block_size: config.buffer_size.value.max(1),
+            max_line_length: config.max_line_length.value,
+            strict: config.strict.value,
+            verbose: config.verbose,
+            carry: Vec::new(),
+            offset: 0,
+            line: None,
+            skip_rest: false,
+            progress: config.progress.clone(),
+            reported: 0,
+        }
+    }
+
+    /// Read blocks of roughly `block_size` bytes rather than `--buffer-size`
+    pub fn block_size(mut self, block_size: usize) -> Self {
+        self.block_size = block_size.max(1);
+        self
+    }
+
+    /// Read the blocks after this one in roughly `block_size` bytes, for sizing them as the input
+    /// is read
+    pub fn set_block_size(&mut self, block_size: usize) {
+        self.block_size = block_size.max(1);
+    }
+
+    /// Count the lines in each block as it's read, so each [`Block`] knows its first line's
+    /// number
+    pub fn count_lines(mut self, count: bool) -> Self {
+        self.line = count.then_some(1);
+        self
+    }
+
+    /// Treat the input as starting `offset` bytes into the whole input (e.g. one chunk of a
+    /// file), so offsets in blocks & errors are relative to the whole input. Only the very start
+    /// of the whole input can have a byte-order mark.
+    pub fn starting_at(mut self, offset: u64) -> Self {
+        self.offset = offset;
+        self.reported = offset;
+        self
+    }
+
+    /// The (1-based) number of the next line to be read, if counting lines
+    pub fn line(&self) -> Option<u64> {
+        self.line
+    }
+
+    /// Read the next block, or `Ok(None)` once the input is exhausted
+    pub fn next_block(&mut self) -> Result<Option<Block>, ChallengeError> {
+        self.next_block_into(Vec::new())
+    }
+
+    /// Read the next block into `data` (e.g. the buffer of a block which has been parsed
+    /// already) in place of what's in it, so once the buffers handed back are big enough,
+    /// reading a block doesn't allocate
+    pub fn next_block_into(&mut self, mut data: Vec<u8>) -> Result<Option<Block>, ChallengeError> {
+        if self.skip_rest {
+            self.skip_line()?;
+        }
+        data.clear();
+        data.extend_from_slice(&self.carry);
+        self.carry.clear();
+        let mut offset = self.offset;
+
+        let end = loop {
+            let filled = data.len();
+            data.resize(filled + self.block_size, 0);
+            let n = self.inner.read(&mut data[filled..])?;
+            data.truncate(filled + n);
+
+            if n == 0 {
+                if data.is_empty() {
+                    return Ok(None);
+                }
+                break data.len();
+            }
+            if let Some(idx) = last_newline(&data[filled..]) {
+                break filled + idx + 1;
+            }
+
+            // Everything read so far is part of a single line
+            if data.len() > self.max_line_length {
+                self.offset = offset + data.len() as u64;
+                self.skip_rest = true;
+                return Err(ChallengeError::LineTooLong {
+                    offset,
+                    limit: self.max_line_length,
+                });
+            }
+        };
+
+        self.carry.extend_from_slice(&data[end..]);
+        data.truncate(end);
+        self.offset = offset + end as u64;
+        if let Some(progress) = &self.progress {
+            progress.advance(self.offset - self.reported);
+            self.reported = self.offset;
+        }
+        let first_line = self.line;
+        self.line = first_line.map(|line| line + count_newlines(&data));
+
+        if offset == 0 {
+            let skip = leading_bom(&data, self.strict, self.verbose)?;
+            data.drain(..skip);
+            offset += skip as u64;
+        }
+
+        Ok(Some(Block {
+            offset,
+            data,
+            first_line,
+        }))
+    }
+
+    /// Skip past the next newline, to get to the end of a line which was too long
+    fn skip_line(&mut self) -> io::Result<()> {
+        let mut buf = vec![0; self.block_size];
+        loop {
+            let n = self.inner.read(&mut buf)?;
+            if n == 0 {
+                break;
+            }
+            if let Some(idx) = first_newline(&buf[..n]) {
+                self.carry = buf[idx + 1..n].to_vec();
+                self.offset += idx as u64 + 1;
+                self.line = self.line.map(|line| line + 1);
+                break;
+            }
+            self.offset += n as u64;
+        }
+        self.skip_rest = false;
+        Ok(())
+    }
+
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{Setting, Source};
+
+    fn config(buffer_size: usize) -> Config {
+        Config {
+            buffer_size: Setting::new(buffer_size, Source::Cli),
+            ..Default::default()
+        }
+    }
+
+    fn read_all(input: &str, config: &Config) -> Result<Vec<Block>, ChallengeError> {
+        let mut reader = BlockReader::new(input.as_bytes(), config);
+        let mut blocks = Vec::new();
+        while let Some(block) = reader.next_block()? {
+            blocks.push(block);
+        }
+        Ok(blocks)
+    }
+
+    #[test]
+    fn whole_lines() -> Result<(), ChallengeError> {
+        let input = "Hamburg;12.0\nBulawayo;8.9\nPalembang;38.8\nSt. John's;15.2";
+
+        for buffer_size in [1, 5, 16, 1024] {
+            let blocks = read_all(input, &config(buffer_size))?;
+            let mut joined = Vec::new();
+            for block in &blocks {
+                assert_eq!(block.offset, joined.len() as u64);
+                joined.extend_from_slice(&block.data);
+
+                let last = blocks.last().unwrap();
+                if !std::ptr::eq(block, last) {
+                    assert_eq!(block.data.last(), Some(&b'\n'), "{buffer_size}");
+                }
+            }
+            assert_eq!(joined, input.as_bytes());
+        }
+
+        Ok(())
+    }
+
+    /// Reading each block into the last one's buffer gives the same blocks, without the buffer
+    /// moving once it's big enough
+    #[test]
+    fn reused_buffer() -> Result<(), ChallengeError> {
+        let input = "Hamburg;12.0\nBulawayo;8.9\nPalembang;38.8\nSt. John's;15.2";
+
+        for buffer_size in [1, 5, 16, 1024] {
+            let config = config(buffer_size);
+            let expected = read_all(input, &config)?;
+            let mut reader = BlockReader::new(input.as_bytes(), &config);
+            let mut buf = Vec::with_capacity(buffer_size + 64);
+            let ptr = buf.as_ptr();
+            for expected in &expected {
+                let block = reader.next_block_into(buf)?.expect("as many blocks");
+                assert_eq!(block.offset, expected.offset);
+                assert_eq!(block.data, expected.data);
+                assert_eq!(block.data.as_ptr(), ptr, "{buffer_size}");
+                buf = block.data;
+            }
+            assert!(reader.next_block_into(buf)?.is_none());
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn after_line_too_long() {
+        let input = format!("Hamburg;12.0\n{};1.0\nBulawayo;8.9\n", "x".repeat(100));
+        let config = Config {
+            max_line_length: Setting::new(64, Source::Cli),
+            ..config(16)
+        };
+        let mut reader = BlockReader::new(input.as_bytes(), &config).count_lines(true);
+
+        let mut lines = Vec::new();
+        let mut too_long = 0;
+        loop {
+            match reader.next_block() {
+                Ok(Some(block)) => lines.push((block.first_line, block.data)),
+                Ok(None) => break,
+                Err(ChallengeError::LineTooLong { offset: 13, .. }) => too_long += 1,
+                Err(e) => panic!("{e}"),
+            }
+        }
+        assert_eq!(too_long, 1);
+        assert_eq!(
+            lines,
+            vec![
+                (Some(1), b"Hamburg;12.0\n".to_vec()),
+                (Some(3), b"Bulawayo;8.9\n".to_vec()),
+            ]
+        );
+    }
+
+    #[test]
+    fn relative_line_numbers() {
+        let input = "Hamburg;12.0\nBulawayo;8.9\nPalembang;38.8\nnope\n";
+        let mut reader = BlockReader::new(io::Cursor::new(input), &config(20));
+
+        let mut failure = None;
+        while let Some(block) = reader.next_block().unwrap() {
+            let mut skipped = SkippedLines::default();
+            let result = block.for_each_record(
+                1024,
+                false,
+                Dialect::default(),
+                0,
+                &mut skipped,
+                |_, _, _| {},
+            );
+            if let Err(e) = result {
+                failure = Some(e);
+                break;
+            }
+        }
+
+        let failure = failure.expect("the last line is malformed");
+        let mut input = reader.into_inner();
+        match failure.locate(&mut input) {
+            ChallengeError::MalformedLine { line, .. } => assert_eq!(line, 4),
+            other => panic!("expected MalformedLine, got {other:?}"),
+        }
+    }
+}
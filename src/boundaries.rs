@@ -0,0 +1,153 @@
+// 1BRC - my take on the 1 Billion Row Challenge
+// Copyright (C) 2024  Charles German <5donuts@pm.me>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Find the newlines which lines, blocks & chunks of the input are split at.
+//!
+//! Every reader & runner searches for newlines through these, so a faster search only needs
+//! writing once. None of them panic: a buffer without a newline is `None` (or an empty split).
+
+use std::io::{Read, Seek, SeekFrom};
+
+use crate::error::ChallengeError;
+
+/// How many bytes to read at first while looking for the newline after an offset.
+///
+/// This covers almost every line in a challenge input; the window doubles (up to the maximum) for
+/// any longer ones.
+const PROBE_WINDOW: usize = 256;
+
+/// The index of the first newline in `buf`
+pub fn first_newline(buf: &[u8]) -> Option<usize> {
+    buf.iter().position(|&b| b == b'\n')
+}
+
+/// The index of the last newline in `buf`
+pub fn last_newline(buf: &[u8]) -> Option<usize> {
+    buf.iter().rposition(|&b| b == b'\n')
+}
+
+/// Split `buf` just after its last newline, into the complete lines & the start of a partial
+/// line. Without a newline, all of `buf` is a partial line.
+pub fn split_at_last_newline(buf: &[u8]) -> (&[u8], &[u8]) {
+    let end = last_newline(buf).map_or(0, |idx| idx + 1);
+    buf.split_at(end)
+}
+
+/// Find the offset of the first line starting at or after `offset`, or the end of the input if
+/// there isn't one.
+///
+/// Starts by probing [`PROBE_WINDOW`] bytes, doubling the window until either a newline is found
+/// or `max_window` bytes have been searched. Returns [`ChallengeError::NoLineBoundary`] if
+/// there's no newline within `max_window` bytes (plus the newline itself) of `offset`, since the
+/// line it falls in must be too long.
+pub fn nearest_newline_after<R>(
+    input: &mut R,
+    offset: u64,
+    max_window: usize,
+) -> Result<u64, ChallengeError>
+where
+    R: Read + Seek,
+{
+    if offset == 0 {
+        return Ok(0);
+    }
+
+    // A line starts at `offset` if the byte before it is a newline
+    let start = offset - 1;
+    let limit = max_window.saturating_add(1);
+    input.seek(SeekFrom::Start(start))?;
+
+    let mut pos = start;
+    let mut window = vec![0; PROBE_WINDOW.min(limit)];
+    loop {
+        let searched = (pos - start) as usize;
+        let want = window.len().min(limit - searched);
+        let n = input.read(&mut window[..want])?;
+        if n == 0 {
+            return Ok(pos.max(offset));
+        }
+        if let Some(idx) = first_newline(&window[..n]) {
+            return Ok(pos + idx as u64 + 1);
+        }
+        pos += n as u64;
+
+        if pos - start >= limit as u64 {
+            return Err(ChallengeError::NoLineBoundary { start, end: pos });
+        }
+        if n == window.len() {
+            window.resize((window.len() * 2).min(limit), 0);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io;
+
+    #[test]
+    fn empty() {
+        assert_eq!(first_newline(b""), None);
+        assert_eq!(last_newline(b""), None);
+        assert_eq!(split_at_last_newline(b""), (&b""[..], &b""[..]));
+        assert_eq!(
+            nearest_newline_after(&mut io::Cursor::new(b""), 0, 64).unwrap(),
+            0
+        );
+    }
+
+    #[test]
+    fn no_newline() {
+        let buf = b"Hamburg;12.0";
+        assert_eq!(first_newline(buf), None);
+        assert_eq!(last_newline(buf), None);
+        assert_eq!(split_at_last_newline(buf), (&b""[..], &buf[..]));
+
+        // The end of the input, as long as it's close enough
+        let mut input = io::Cursor::new(buf);
+        assert_eq!(nearest_newline_after(&mut input, 3, 64).unwrap(), 12);
+        assert!(matches!(
+            nearest_newline_after(&mut input, 3, 4),
+            Err(ChallengeError::NoLineBoundary { start: 2, end: 7 })
+        ));
+    }
+
+    #[test]
+    fn newline_at_end() {
+        let buf = b"Hamburg;12.0\nBulawayo;8.9\n";
+        assert_eq!(first_newline(buf), Some(12));
+        assert_eq!(last_newline(buf), Some(25));
+        assert_eq!(split_at_last_newline(buf), (&buf[..], &b""[..]));
+
+        let mut input = io::Cursor::new(buf);
+        assert_eq!(nearest_newline_after(&mut input, 5, 64).unwrap(), 13);
+        assert_eq!(nearest_newline_after(&mut input, 13, 64).unwrap(), 13);
+        assert_eq!(nearest_newline_after(&mut input, 14, 64).unwrap(), 26);
+        assert_eq!(nearest_newline_after(&mut input, 26, 64).unwrap(), 26);
+    }
+
+    #[test]
+    fn newline_at_start() {
+        let buf = b"\nHamburg;12.0\nBul";
+        assert_eq!(first_newline(buf), Some(0));
+        assert_eq!(last_newline(buf), Some(13));
+        assert_eq!(split_at_last_newline(buf), (&buf[..14], &b"Bul"[..]));
+        assert_eq!(split_at_last_newline(b"\n"), (&b"\n"[..], &b""[..]));
+
+        let mut input = io::Cursor::new(buf);
+        assert_eq!(nearest_newline_after(&mut input, 1, 64).unwrap(), 1);
+        assert_eq!(nearest_newline_after(&mut input, 2, 64).unwrap(), 14);
+    }
+}
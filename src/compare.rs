@@ -0,0 +1,630 @@
+// 1BRC - my take on the 1 Billion Row Challenge
+// Copyright (C) 2024  Charles German <5donuts@pm.me>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Benchmark several runners against the same input & compare the results.
+//!
+//! Comparing every runner on the full input takes a long time, so progress is saved after each
+//! runner completes. If a later runner fails, the comparison can be resumed from where it left
+//! off rather than starting over.
+//!
+//! Where the OS allows it (Linux), the peak memory use of each runner is measured too, so the
+//! results can be [weighed](crate::report::tradeoffs) against a memory budget.
+
+use std::path::Path;
+use std::time::Duration;
+
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+
+use crate::config::{Config, Setting, Source};
+use crate::fingerprint::{fnv1a, path_key, Fingerprint};
+use crate::helpers::{fmt_bytes, fmt_duration, write_atomically, ChallengeResult};
+use crate::outln;
+use crate::output::Format;
+use crate::report::schema;
+use crate::report::tradeoffs::{self, Cost};
+use crate::runners;
+use crate::stats::BenchStats;
+use crate::style::{Cell, Style, Styler, Table};
+use crate::{Determinism, Runner, ALLOCATOR};
+
+/// A runner to include in a comparison
+pub struct Candidate<'a> {
+    pub name: String,
+    pub determinism: Determinism,
+    pub run: Box<dyn Fn(&Config) -> ChallengeResult + 'a>,
+}
+
+impl Candidate<'_> {
+    /// Every available runner, in the order they are declared.
+    ///
+    /// `sampled-dense` is also run without its sampling pass (so every station goes through
+    /// the overflow maps) to show whether the sampling pays for itself. `unchecked` is left out:
+    /// it's undefined behaviour on input which isn't valid UTF-8, so is only run when asked for
+    /// by name. How reproducible each one is depends on the settings in `config` as well as its
+    /// runner.
+    pub fn all(config: &Config) -> Vec<Self> {
+        let mut candidates: Vec<Self> = Runner::value_variants()
+            .iter()
+            .filter(|&&runner| runner != Runner::Unchecked)
+            .map(|&runner| Candidate {
+                name: runner.to_string(),
+                determinism: config.with_runner(runner, Source::Auto).determinism(),
+                run: Box::new(move |config: &Config| {
+                    runners::run(&config.with_runner(runner, Source::Auto))
+                }),
+            })
+            .collect();
+
+        candidates.push(Candidate {
+            name: format!("{} (no sample)", Runner::SampledDense),
+            determinism: config
+                .with_runner(Runner::SampledDense, Source::Auto)
+                .determinism(),
+            run: Box::new(|config: &Config| {
+                let mut config = config.with_runner(Runner::SampledDense, Source::Auto);
+                config.sample_fraction = Setting::new(0.0, Source::Auto);
+                config.known_stations = None;
+                runners::run(&config)
+            }),
+        });
+
+        candidates
+    }
+}
+
+/// The benchmark results for a single runner
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RunnerStats {
+    pub runner: String,
+    pub runs: Vec<Duration>,
+    pub stats: BenchStats,
+
+    /// Hash of the runner's output, used to check all runners agree
+    pub output_hash: u64,
+
+    /// How reproducible the runner's output is, which progress saved before it was recorded
+    /// doesn't say
+    #[serde(default)]
+    pub determinism: Determinism,
+
+    /// The peak resident memory of the process while the runner ran, in bytes, if it could be
+    /// measured
+    #[serde(default)]
+    pub peak_memory: Option<u64>,
+
+    /// Whether these results were loaded from a previous invocation
+    #[serde(skip)]
+    pub resumed: bool,
+}
+
+/// Results saved after each runner completes
+#[derive(Debug, Serialize, Deserialize)]
+struct Progress {
+    /// Which file the progress is for; see [`path_key`]
+    input_key: u64,
+    fingerprint: Fingerprint,
+
+    /// The global allocator the results were measured with; see [`ALLOCATOR`]
+    #[serde(default = "system_allocator")]
+    allocator: String,
+    completed: Vec<RunnerStats>,
+}
+
+/// The allocator of progress saved before it was recorded
+fn system_allocator() -> String {
+    String::from("system")
+}
+
+impl Progress {
+    fn save(&self, path: &Path) -> Result<(), Box<dyn std::error::Error>> {
+        write_atomically(path, &serde_json::to_vec_pretty(self)?)?;
+        Ok(())
+    }
+}
+
+/// Benchmark each candidate against the configured input.
+///
+/// Each candidate is invoked `iterations` times and progress is saved to `progress_path` after
+/// each one completes. If `resume = true`, candidates with results already saved there are
+/// skipped, as long as the input hasn't changed since they were saved.
+pub fn compare(
+    config: &Config,
+    candidates: &[Candidate],
+    iterations: usize,
+    progress_path: &Path,
+    resume: bool,
+) -> Result<Vec<RunnerStats>, Box<dyn std::error::Error>> {
+    let input = &config.canonical_input.value;
+    let fingerprint = Fingerprint::of(input)?;
+    let input_key = path_key(input);
+
+    let mut progress = Progress {
+        input_key,
+        fingerprint,
+        allocator: String::from(ALLOCATOR),
+        completed: Vec::new(),
+    };
+    if resume {
+        match std::fs::read(progress_path) {
+            Ok(contents) => {
+                let saved: Progress = serde_json::from_slice(&contents).map_err(|e| {
+                    format!("Invalid progress file {}: {e}", progress_path.display())
+                })?;
+                if saved.input_key != input_key {
+                    return Err(format!(
+                        "{} was saved for a different input than {}; re-run without --resume",
+                        progress_path.display(),
+                        config.input.value.display()
+                    )
+                    .into());
+                }
+                if saved.fingerprint != fingerprint {
+                    return Err(format!(
+                        "{} has changed since {} was saved; re-run without --resume",
+                        config.input.value.display(),
+                        progress_path.display()
+                    )
+                    .into());
+                }
+                if saved.allocator != ALLOCATOR {
+                    return Err(format!(
+                        "{} was saved by a build using the {} allocator, but this one uses {ALLOCATOR}; \
+                         re-run without --resume",
+                        progress_path.display(),
+                        saved.allocator
+                    )
+                    .into());
+                }
+                progress.completed = saved.completed;
+                for stats in progress.completed.iter_mut() {
+                    stats.resumed = true;
+                }
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                eprintln!(
+                    "No progress saved at {}; starting from scratch",
+                    progress_path.display()
+                );
+            }
+            Err(e) => return Err(e.into()),
+        }
+    }
+
+    for candidate in candidates {
+        if progress
+            .completed
+            .iter()
+            .any(|s| s.runner == candidate.name)
+        {
+            outln!("{}: resumed from previous invocation", candidate.name);
+            continue;
+        }
+
+        let measured = reset_peak_memory();
+        let mut runs = Vec::with_capacity(iterations);
+        let mut output_hash = 0;
+        for i in 1..=iterations {
+            let (stations, stats) = (candidate.run)(config)
+                .map_err(|e| format!("Runner {} failed: {e}", candidate.name))?;
+            let duration = stats.timings.total;
+            outln!("{} run {i}: {}", candidate.name, fmt_duration(&duration));
+            output_hash = fnv1a(Format::Text.render(&stations).as_bytes());
+            runs.push(duration);
+        }
+
+        progress.completed.push(RunnerStats {
+            runner: candidate.name.clone(),
+            stats: BenchStats::from_runs(&runs),
+            runs,
+            output_hash,
+            determinism: candidate.determinism,
+            peak_memory: measured.then(peak_memory).flatten(),
+            resumed: false,
+        });
+        progress.save(progress_path)?;
+    }
+
+    // Report the results in the order the candidates were given
+    let mut results = Vec::with_capacity(candidates.len());
+    for candidate in candidates {
+        let idx = progress
+            .completed
+            .iter()
+            .position(|s| s.runner == candidate.name)
+            .expect("Every candidate has completed");
+        results.push(progress.completed.swap_remove(idx));
+    }
+    Ok(results)
+}
+
+/// Start measuring the peak memory use of the process afresh, returning whether that's possible
+#[cfg(target_os = "linux")]
+fn reset_peak_memory() -> bool {
+    // Resets the "high water mark" of the resident set size (see proc(5))
+    std::fs::write("/proc/self/clear_refs", "5").is_ok()
+}
+
+#[cfg(not(target_os = "linux"))]
+fn reset_peak_memory() -> bool {
+    false
+}
+
+/// The peak resident memory of the process since [`reset_peak_memory`], in bytes
+fn peak_memory() -> Option<u64> {
+    let status = std::fs::read_to_string("/proc/self/status").ok()?;
+    let line = status
+        .lines()
+        .find_map(|line| line.strip_prefix("VmHWM:"))?;
+    let kib: u64 = line.trim().strip_suffix("kB")?.trim().parse().ok()?;
+    Some(kib * 1024)
+}
+
+/// Render the comparison results as a markdown table, with its columns aligned.
+///
+/// Deltas and output checks are relative to the first runner. The fastest runner within the
+/// memory `budget` (if any) is in bold, runners over it are struck through, and runners on the
+/// Pareto frontier of time & memory are noted as such (see [`tradeoffs`]). With colour, the
+/// fastest runner overall is green, & regressions & mismatched output are red.
+pub fn render_table(results: &[RunnerStats], budget: Option<u64>, styler: &Styler) -> String {
+    let mut table = Table::new(&[
+        "Runner",
+        "Runtime",
+        "Delta",
+        "Peak memory",
+        "Output",
+        "Determinism",
+        "Notes",
+    ]);
+    let Some(reference) = results.first() else {
+        return table.render(styler);
+    };
+
+    let costs: Vec<Cost> = results
+        .iter()
+        .map(|stats| Cost {
+            time: stats.stats.mean,
+            memory: stats.peak_memory,
+        })
+        .collect();
+    let weighed = tradeoffs::weigh(&costs, budget);
+    let fastest = results.iter().map(|stats| stats.stats.mean).min();
+
+    for (stats, tradeoff) in results.iter().zip(weighed) {
+        let runtime = format!(
+            "{} ± {}",
+            fmt_duration(&stats.stats.mean),
+            fmt_duration(&stats.stats.std_dev)
+        );
+        let (delta, output) = if stats.runner == reference.runner {
+            (Cell::from("N/A"), Cell::from("reference"))
+        } else {
+            let base = reference.stats.mean.as_secs_f64();
+            let delta = if base > 0.0 {
+                let delta = (stats.stats.mean.as_secs_f64() - base) / base * 100.0;
+                Cell::styled_if(format!("{delta:+.2}%"), delta > 0.0, Style::Bad)
+            } else {
+                Cell::from("N/A")
+            };
+            let output = if stats.output_hash == reference.output_hash {
+                Cell::from("ok")
+            } else {
+                Cell::styled("MISMATCH", Style::Bad)
+            };
+            (delta, output)
+        };
+        let memory = stats
+            .peak_memory
+            .map_or_else(|| String::from("N/A"), fmt_bytes);
+        let runner = if tradeoff.winner {
+            format!("**{}**", stats.runner)
+        } else if tradeoff.over_budget {
+            format!("~~{}~~", stats.runner)
+        } else {
+            stats.runner.clone()
+        };
+        let notes: Vec<&str> = [
+            (stats.resumed, "resumed"),
+            (tradeoff.over_budget, "over budget"),
+            (tradeoff.pareto, "pareto-optimal"),
+        ]
+        .into_iter()
+        .filter_map(|(noted, note)| noted.then_some(note))
+        .collect();
+
+        table.row(vec![
+            Cell::styled_if(runner, Some(stats.stats.mean) == fastest, Style::Good),
+            runtime.into(),
+            delta,
+            memory.into(),
+            output,
+            stats.determinism.to_string().into(),
+            notes.join(", ").into(),
+        ]);
+    }
+
+    table.render(styler)
+}
+
+/// A footnote for the table listing the settings the runners were compared with which were
+/// given explicitly, & where each came from; `None` if there are none
+pub fn settings_footnote(config: &Config) -> Option<String> {
+    let provenance = schema::Config::from(config).provenance();
+    (!provenance.is_empty()).then(|| format!("Settings: {}", provenance.join(", ")))
+}
+
+/// Check that every runner produced the same output as the first
+pub fn check_outputs(results: &[RunnerStats]) -> Result<(), Box<dyn std::error::Error>> {
+    let Some(reference) = results.first() else {
+        return Ok(());
+    };
+    let mismatched: Vec<&str> = results
+        .iter()
+        .filter(|s| s.output_hash != reference.output_hash)
+        .map(|s| s.runner.as_str())
+        .collect();
+
+    if mismatched.is_empty() {
+        Ok(())
+    } else {
+        Err(format!(
+            "Output of {} differs from {}",
+            mismatched.join(", "),
+            reference.runner
+        )
+        .into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Layer;
+    use crate::runners::tests::TEST_DATA;
+    use std::cell::Cell;
+    use std::rc::Rc;
+
+    /// A candidate which runs the given runner & counts how often it was invoked
+    fn counted(runner: Runner, count: &Rc<Cell<usize>>) -> Candidate<'static> {
+        let count = Rc::clone(count);
+        Candidate {
+            name: runner.to_string(),
+            determinism: runner.determinism(),
+            run: Box::new(move |config: &Config| {
+                count.set(count.get() + 1);
+                runners::run(&config.with_runner(runner, Source::Auto))
+            }),
+        }
+    }
+
+    /// A candidate which fails the first time it is invoked, like a runner running out of memory
+    fn flaky(failed: &Rc<Cell<bool>>) -> Candidate<'static> {
+        let failed = Rc::clone(failed);
+        Candidate {
+            name: String::from("flaky"),
+            determinism: Determinism::BestEffort,
+            run: Box::new(move |config: &Config| {
+                if !failed.replace(true) {
+                    return Err("memory allocation failed".into());
+                }
+                runners::run(config)
+            }),
+        }
+    }
+
+    fn fixture(dir: &Path) -> Config {
+        let input = dir.join("measurements.txt");
+        std::fs::write(&input, TEST_DATA).unwrap();
+        Config::resolve(&input, Layer::default(), Layer::default(), Layer::default())
+    }
+
+    #[test]
+    fn resume_after_failure() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = tempfile::tempdir()?;
+        let config = fixture(dir.path());
+        let progress_path = dir.path().join("progress.json");
+
+        let counts: Vec<Rc<Cell<usize>>> = (0..3).map(|_| Rc::default()).collect();
+        let failed = Rc::default();
+        let candidates = || {
+            vec![
+                counted(Runner::Baseline, &counts[0]),
+                counted(Runner::RustcHash, &counts[1]),
+                flaky(&failed),
+                counted(Runner::AHash, &counts[2]),
+            ]
+        };
+
+        // The third runner fails, but the first two are saved
+        let err = compare(&config, &candidates(), 2, &progress_path, false).unwrap_err();
+        assert!(err.to_string().contains("flaky"), "{err}");
+        let counts_after_failure: Vec<usize> = counts.iter().map(|c| c.get()).collect();
+        assert_eq!(counts_after_failure, vec![2, 2, 0]);
+
+        // Resuming only runs what is left
+        let results = compare(&config, &candidates(), 2, &progress_path, true)?;
+        let counts_after_resume: Vec<usize> = counts.iter().map(|c| c.get()).collect();
+        assert_eq!(counts_after_resume, vec![2, 2, 2]);
+
+        let names: Vec<&str> = results.iter().map(|s| s.runner.as_str()).collect();
+        assert_eq!(names, vec!["baseline", "rustc-hash", "flaky", "ahash"]);
+        let resumed: Vec<bool> = results.iter().map(|s| s.resumed).collect();
+        assert_eq!(resumed, vec![true, true, false, false]);
+        check_outputs(&results)?;
+
+        let table = render_table(&results, None, &Styler::PLAIN);
+        assert_eq!(table.matches("resumed").count(), 2, "{table}");
+
+        Ok(())
+    }
+
+    /// The table for a few made-up runners with a 2 GiB budget; see `tests/data/compare/`
+    #[test]
+    fn tradeoff_table() {
+        const GIB: u64 = 1 << 30;
+        let stats = |runner: &str, secs: u64, peak_memory: Option<u64>| {
+            let runs = vec![Duration::from_secs(secs); 3];
+            RunnerStats {
+                runner: String::from(runner),
+                stats: BenchStats::from_runs(&runs),
+                runs,
+                output_hash: 42,
+                determinism: Determinism::BitExact,
+                peak_memory,
+                resumed: false,
+            }
+        };
+        let results = [
+            stats("baseline", 60, Some(GIB / 2)),
+            // Dominated by the next runner
+            stats("slow-and-big", 70, Some(3 * GIB)),
+            stats("mid", 30, Some(GIB + GIB / 2)),
+            stats("fast-but-big", 10, Some(4 * GIB)),
+            stats("unmeasured", 40, None),
+        ];
+
+        let table = render_table(&results, Some(2 * GIB), &Styler::PLAIN);
+        assert_eq!(table, include_str!("../tests/data/compare/tradeoffs.md"));
+    }
+
+    /// The text with its SGR escapes taken out
+    fn strip_ansi(text: &str) -> String {
+        let mut stripped = String::with_capacity(text.len());
+        let mut rest = text;
+        while let Some(idx) = rest.find('\x1b') {
+            stripped.push_str(&rest[..idx]);
+            let end = rest[idx..].find('m').expect("an SGR escape ends with an m");
+            rest = &rest[idx + end + 1..];
+        }
+        stripped.push_str(rest);
+        stripped
+    }
+
+    /// The same table as [`tradeoff_table`], coloured, with a runner whose output differs
+    #[test]
+    fn colored_table() {
+        const GIB: u64 = 1 << 30;
+        let stats = |runner: &str, secs: u64, peak_memory: Option<u64>, output_hash: u64| {
+            let runs = vec![Duration::from_secs(secs); 3];
+            RunnerStats {
+                runner: String::from(runner),
+                stats: BenchStats::from_runs(&runs),
+                runs,
+                output_hash,
+                determinism: Determinism::BitExact,
+                peak_memory,
+                resumed: false,
+            }
+        };
+        let results = [
+            stats("baseline", 60, Some(GIB / 2), 42),
+            stats("slow-and-big", 70, Some(3 * GIB), 42),
+            stats("mid", 30, Some(GIB + GIB / 2), 42),
+            stats("fast-but-big", 10, Some(4 * GIB), 42),
+            stats("mismatched", 40, None, 7),
+        ];
+
+        let table = render_table(&results, Some(2 * GIB), &Styler::COLORED);
+        assert_eq!(table, include_str!("../tests/data/compare/tradeoffs.ansi"));
+        // The colour is all that's different
+        let plain = render_table(&results, Some(2 * GIB), &Styler::PLAIN);
+        assert!(!plain.contains('\x1b'));
+        assert_eq!(strip_ansi(&table), plain);
+    }
+
+    #[test]
+    fn resume_rejects_changed_input() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = tempfile::tempdir()?;
+        let config = fixture(dir.path());
+        let progress_path = dir.path().join("progress.json");
+
+        let count = Rc::default();
+        compare(
+            &config,
+            &[counted(Runner::Baseline, &count)],
+            1,
+            &progress_path,
+            false,
+        )?;
+
+        std::fs::write(&config.canonical_input.value, "Hamburg;12.0\n")?;
+        let err = compare(
+            &config,
+            &[counted(Runner::Baseline, &count)],
+            1,
+            &progress_path,
+            true,
+        )
+        .unwrap_err();
+        assert!(err.to_string().contains("has changed"), "{err}");
+
+        Ok(())
+    }
+
+    #[test]
+    fn resume_rejects_other_allocator() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = tempfile::tempdir()?;
+        let config = fixture(dir.path());
+        let progress_path = dir.path().join("progress.json");
+
+        let count = Rc::default();
+        compare(
+            &config,
+            &[counted(Runner::Baseline, &count)],
+            1,
+            &progress_path,
+            false,
+        )?;
+
+        let mut saved: serde_json::Value = serde_json::from_slice(&std::fs::read(&progress_path)?)?;
+        assert_eq!(saved["allocator"], ALLOCATOR);
+        saved["allocator"] = "some-other-allocator".into();
+        std::fs::write(&progress_path, serde_json::to_vec(&saved)?)?;
+
+        let err = compare(
+            &config,
+            &[counted(Runner::Baseline, &count)],
+            1,
+            &progress_path,
+            true,
+        )
+        .unwrap_err();
+        assert!(err.to_string().contains("some-other-allocator"), "{err}");
+
+        Ok(())
+    }
+
+    #[test]
+    fn settings_footnote() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = fixture(dir.path());
+        // Threads detected from the machine aren't worth a footnote
+        assert_eq!(super::settings_footnote(&config), None);
+
+        let cli = Layer {
+            threads: Some(2),
+            ..Layer::default()
+        };
+        let env = Layer {
+            max_skipped: Some(5),
+            ..Layer::default()
+        };
+        let config = Config::resolve(&config.input.value, cli, env, Layer::default());
+        assert_eq!(
+            super::settings_footnote(&config).as_deref(),
+            Some("Settings: threads = 2 (cli), max-skipped = 5 (env)")
+        );
+    }
+}
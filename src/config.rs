@@ -0,0 +1,1131 @@
+// 1BRC - my take on the 1 Billion Row Challenge
+// Copyright (C) 2024  Charles German <5donuts@pm.me>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Layered configuration for a run.
+//!
+//! Each setting is resolved from (in order of precedence) the command line, the environment,
+//! a config file, and finally a built-in or automatically-detected default. The resulting
+//! [`Config`] remembers where each value came from so it can be shown to the user.
+
+use std::fmt::Display;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize, Serializer};
+
+use crate::aggregate::{Delimiter, Dialect, Encoding, KeyFormat};
+use crate::block_sizing::{BlockSizeLog, BlockSizing};
+use crate::helpers::{ResultsBuffer, StationInfo};
+use crate::manifest::ManifestRecorder;
+use crate::progress::Progress;
+use crate::sample::Sample;
+use crate::topology::Cpus;
+use crate::{Determinism, Runner};
+
+/// Prefix for environment variables that override config values
+const ENV_PREFIX: &str = "ONEBRC_";
+
+/// Default capacity of the buffered reader used by the line-oriented runners.
+///
+/// This matches the default capacity of [`std::io::BufReader`].
+pub const DEFAULT_BUFFER_SIZE: usize = 8 * 1024;
+
+/// Default maximum length of a single line of input.
+///
+/// The spec allows station names of up to 100 bytes, so this leaves plenty of headroom.
+pub const DEFAULT_MAX_LINE_LENGTH: usize = 1024;
+
+/// Default fraction of the input to sample for station names.
+///
+/// With the official input, the first 1% is enough to see every station many times over.
+pub const DEFAULT_SAMPLE_FRACTION: f64 = 0.01;
+
+/// Where the value of a particular setting came from
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Source {
+    /// The built-in default value
+    #[default]
+    Default,
+
+    /// The value was detected or derived automatically
+    Auto,
+
+    /// The value was read from a config file
+    Config,
+
+    /// The value was read from an environment variable
+    Env,
+
+    /// The value was passed on the command line
+    Cli,
+
+    /// The value was picked by timing the candidates with `--auto-tune`
+    Tuned,
+}
+
+impl Display for Source {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        use Source::*;
+        let s = match self {
+            Default => "default",
+            Auto => "auto",
+            Config => "config",
+            Env => "env",
+            Cli => "cli",
+            Tuned => "auto-tuned",
+        };
+        write!(f, "{s}")
+    }
+}
+
+/// A configuration value along with the [`Source`] it came from
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize)]
+pub struct Setting<T> {
+    pub value: T,
+    pub source: Source,
+}
+
+impl<T> Setting<T> {
+    pub fn new(value: T, source: Source) -> Self {
+        Self { value, source }
+    }
+}
+
+/// A partial set of configuration values from a single layer (CLI, env, or config file)
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct Layer {
+    pub runner: Option<Runner>,
+    pub buffer_size: Option<usize>,
+    pub max_line_length: Option<usize>,
+    pub station_cache: Option<bool>,
+    pub strict: Option<bool>,
+    pub num_chunks: Option<usize>,
+    pub threads: Option<usize>,
+    pub sample_fraction: Option<f64>,
+    pub timings: Option<bool>,
+    pub key_format: Option<KeyFormat>,
+    pub track_extents: Option<bool>,
+    pub case_insensitive: Option<bool>,
+    pub fallback: Option<bool>,
+    pub max_skipped: Option<u64>,
+    pub delimiter: Option<Delimiter>,
+    pub decimal_comma: Option<bool>,
+    pub encoding: Option<Encoding>,
+    pub max_memory: Option<u64>,
+    pub block_sizing: Option<BlockSizing>,
+}
+
+impl Layer {
+    /// Read a layer from the environment using the provided lookup function.
+    ///
+    /// Variables are named after the config keys, e.g. `ONEBRC_BUFFER_SIZE`.
+    pub fn from_env<F>(lookup: F) -> Result<Self, Box<dyn std::error::Error>>
+    where
+        F: Fn(&str) -> Option<String>,
+    {
+        let var = |key: &str| lookup(&format!("{ENV_PREFIX}{key}"));
+
+        let runner = match var("RUNNER") {
+            Some(s) => Some(
+                Runner::from_str(&s, true)
+                    .map_err(|e| format!("Invalid value for {ENV_PREFIX}RUNNER: {e}"))?,
+            ),
+            None => None,
+        };
+        let buffer_size = match var("BUFFER_SIZE") {
+            Some(s) => Some(
+                s.parse()
+                    .map_err(|e| format!("Invalid value for {ENV_PREFIX}BUFFER_SIZE: {e}"))?,
+            ),
+            None => None,
+        };
+        let max_line_length = match var("MAX_LINE_LENGTH") {
+            Some(s) => Some(
+                s.parse()
+                    .map_err(|e| format!("Invalid value for {ENV_PREFIX}MAX_LINE_LENGTH: {e}"))?,
+            ),
+            None => None,
+        };
+
+        let station_cache = match var("STATION_CACHE") {
+            Some(s) => Some(parse_bool(&s).ok_or_else(|| {
+                format!("Invalid value for {ENV_PREFIX}STATION_CACHE: expected true or false")
+            })?),
+            None => None,
+        };
+        let strict = match var("STRICT") {
+            Some(s) => Some(parse_bool(&s).ok_or_else(|| {
+                format!("Invalid value for {ENV_PREFIX}STRICT: expected true or false")
+            })?),
+            None => None,
+        };
+
+        let num_chunks = match var("NUM_CHUNKS") {
+            Some(s) => Some(
+                s.parse()
+                    .map_err(|e| format!("Invalid value for {ENV_PREFIX}NUM_CHUNKS: {e}"))?,
+            ),
+            None => None,
+        };
+
+        let threads = match var("THREADS") {
+            Some(s) => Some(
+                s.parse()
+                    .map_err(|e| format!("Invalid value for {ENV_PREFIX}THREADS: {e}"))?,
+            ),
+            None => None,
+        };
+
+        let sample_fraction = match var("SAMPLE_FRACTION") {
+            Some(s) => Some(
+                s.parse()
+                    .map_err(|e| format!("Invalid value for {ENV_PREFIX}SAMPLE_FRACTION: {e}"))?,
+            ),
+            None => None,
+        };
+
+        let timings = match var("TIMINGS") {
+            Some(s) => Some(parse_bool(&s).ok_or_else(|| {
+                format!("Invalid value for {ENV_PREFIX}TIMINGS: expected true or false")
+            })?),
+            None => None,
+        };
+
+        let key_format = match var("KEY_FORMAT") {
+            Some(s) => Some(
+                KeyFormat::from_str(&s, true)
+                    .map_err(|e| format!("Invalid value for {ENV_PREFIX}KEY_FORMAT: {e}"))?,
+            ),
+            None => None,
+        };
+
+        let track_extents = match var("TRACK_EXTENTS") {
+            Some(s) => Some(parse_bool(&s).ok_or_else(|| {
+                format!("Invalid value for {ENV_PREFIX}TRACK_EXTENTS: expected true or false")
+            })?),
+            None => None,
+        };
+
+        let case_insensitive = match var("CASE_INSENSITIVE") {
+            Some(s) => Some(parse_bool(&s).ok_or_else(|| {
+                format!("Invalid value for {ENV_PREFIX}CASE_INSENSITIVE: expected true or false")
+            })?),
+            None => None,
+        };
+
+        let fallback = match var("FALLBACK") {
+            Some(s) => Some(parse_bool(&s).ok_or_else(|| {
+                format!("Invalid value for {ENV_PREFIX}FALLBACK: expected true or false")
+            })?),
+            None => None,
+        };
+
+        let max_skipped = match var("MAX_SKIPPED") {
+            Some(s) => Some(
+                s.parse()
+                    .map_err(|e| format!("Invalid value for {ENV_PREFIX}MAX_SKIPPED: {e}"))?,
+            ),
+            None => None,
+        };
+
+        let delimiter = match var("DELIMITER") {
+            Some(s) => Some(
+                s.parse()
+                    .map_err(|e| format!("Invalid value for {ENV_PREFIX}DELIMITER: {e}"))?,
+            ),
+            None => None,
+        };
+
+        let decimal_comma = match var("DECIMAL_COMMA") {
+            Some(s) => Some(parse_bool(&s).ok_or_else(|| {
+                format!("Invalid value for {ENV_PREFIX}DECIMAL_COMMA: expected true or false")
+            })?),
+            None => None,
+        };
+
+        let encoding = match var("ENCODING") {
+            Some(s) => Some(
+                Encoding::from_str(&s, true)
+                    .map_err(|e| format!("Invalid value for {ENV_PREFIX}ENCODING: {e}"))?,
+            ),
+            None => None,
+        };
+
+        let max_memory = match var("MAX_MEMORY") {
+            Some(s) => Some(
+                s.parse()
+                    .map_err(|e| format!("Invalid value for {ENV_PREFIX}MAX_MEMORY: {e}"))?,
+            ),
+            None => None,
+        };
+
+        let block_sizing = match var("BLOCK_SIZING") {
+            Some(s) => Some(
+                s.parse()
+                    .map_err(|e| format!("Invalid value for {ENV_PREFIX}BLOCK_SIZING: {e}"))?,
+            ),
+            None => None,
+        };
+
+        Ok(Self {
+            runner,
+            buffer_size,
+            max_line_length,
+            station_cache,
+            strict,
+            num_chunks,
+            threads,
+            sample_fraction,
+            timings,
+            key_format,
+            track_extents,
+            case_insensitive,
+            fallback,
+            max_skipped,
+            delimiter,
+            decimal_comma,
+            encoding,
+            max_memory,
+            block_sizing,
+        })
+    }
+
+    /// Read a layer from a TOML config file
+    pub fn from_file(path: &Path) -> Result<Self, Box<dyn std::error::Error>> {
+        let contents = std::fs::read_to_string(path)?;
+        toml::from_str(&contents)
+            .map_err(|e| format!("Invalid config file {}: {e}", path.display()).into())
+    }
+}
+
+/// Serialize a path setting, replacing anything that isn't valid UTF-8.
+///
+/// Paths on Unix are arbitrary bytes, which serde refuses to serialize as a string.
+fn serialize_path_lossy<S: Serializer>(
+    setting: &Setting<PathBuf>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    Setting::new(setting.value.to_string_lossy(), setting.source).serialize(serializer)
+}
+
+/// One chunk per available core
+fn default_num_chunks(cpus: &Cpus) -> usize {
+    default_threads(cpus)
+}
+
+/// One thread per available core
+fn default_threads(cpus: &Cpus) -> usize {
+    cpus.available().unwrap_or(1)
+}
+
+/// Parse a boolean from an environment variable
+fn parse_bool(s: &str) -> Option<bool> {
+    match s.to_ascii_lowercase().as_str() {
+        "1" | "true" | "yes" | "on" => Some(true),
+        "0" | "false" | "no" | "off" => Some(false),
+        _ => None,
+    }
+}
+
+/// The effective configuration for a run.
+///
+/// This is built once by [`Config::resolve`] and is what actually drives the run, so the
+/// banner it prints always reflects what ran.
+#[derive(Debug, Clone, Serialize)]
+pub struct Config {
+    pub runner: Setting<Runner>,
+    pub hasher: Setting<&'static str>,
+    pub buffer_size: Setting<usize>,
+    pub max_line_length: Setting<usize>,
+    pub station_cache: Setting<bool>,
+
+    /// Reject irregular input (byte-order marks, blank lines) rather than skipping over it
+    pub strict: Setting<bool>,
+
+    /// How many newline-aligned chunks the input is split into for parallel processing
+    pub num_chunks: Setting<usize>,
+
+    /// How many threads parallel runners process chunks on
+    pub threads: Setting<usize>,
+
+    /// The CPUs detected, which the default number of threads & chunks are based on
+    #[serde(skip)]
+    pub cpus: Cpus,
+
+    /// The fraction of the input sampled up front to discover station names, for the runners
+    /// which do so
+    pub sample_fraction: Setting<f64>,
+
+    /// Also time how long aggregating the measurements takes, separately from sorting the
+    /// results
+    pub timings: Setting<bool>,
+
+    /// What the measurements are grouped by
+    pub key_format: Setting<KeyFormat>,
+
+    /// Record the first & last row each station is on
+    pub track_extents: Setting<bool>,
+
+    /// Merge stations whose names only differ in case, shown with the spelling seen first
+    pub case_insensitive: Setting<bool>,
+
+    /// Whether to retry with the next runner in line if the runner can't run in this
+    /// environment (see [`Runner::fallback`]); on unless a runner was picked explicitly
+    pub fallback: Setting<bool>,
+
+    /// How many malformed lines may be skipped (& counted) before giving up; with none, the
+    /// first one is an error
+    pub max_skipped: Setting<u64>,
+
+    /// The character between the fields of each line
+    pub delimiter: Setting<Delimiter>,
+
+    /// Whether measurements are written with a decimal comma, e.g. `12,3`
+    pub decimal_comma: Setting<bool>,
+
+    /// How the station names are encoded
+    pub encoding: Setting<Encoding>,
+
+    /// Roughly how many bytes the per-thread maps may take up before the stations seen least
+    /// often are spilled to temporary files; 0 for no limit. Only the sampled-dense runner has a
+    /// limit.
+    pub max_memory: Setting<u64>,
+
+    /// Whether the pipelined runner's blocks are a fixed size, or resized as it reads them
+    pub block_sizing: Setting<BlockSizing>,
+
+    /// The global allocator this was built with; see [`ALLOCATOR`](crate::ALLOCATOR)
+    pub allocator: Setting<&'static str>,
+
+    /// The input path as given by the user, for messages
+    #[serde(serialize_with = "serialize_path_lossy")]
+    pub input: Setting<PathBuf>,
+
+    /// The input path with symlinks & relative components resolved, for reading the input and
+    /// for anything keyed by which file it is (e.g. caches & saved progress)
+    #[serde(serialize_with = "serialize_path_lossy")]
+    pub canonical_input: Setting<PathBuf>,
+    pub input_size: Setting<u64>,
+
+    /// The sorted names of stations known to be in the input ahead of time, if any
+    #[serde(skip)]
+    pub known_stations: Option<Arc<[String]>>,
+
+    /// How chatty to be on stderr; `0` only reports errors
+    #[serde(skip)]
+    pub verbose: u8,
+
+    /// Whether to print warnings about the configuration or input
+    #[serde(skip)]
+    pub warnings: bool,
+
+    /// The (line-aligned) bytes of the input to solve, rather than all of it, for
+    /// `--byte-range`
+    #[serde(skip)]
+    pub byte_range: Option<Range<u64>>,
+
+    /// The blocks of the input to solve, rather than all of them, for `--sample-rate`
+    #[serde(skip)]
+    pub sample: Option<Sample>,
+
+    /// Where parallel runners list the blocks they read, for `--chunk-manifest`
+    #[serde(skip)]
+    pub chunk_manifest: Option<Arc<ManifestRecorder>>,
+
+    /// Where the results of one run are kept for the next to reuse, when benchmarking
+    #[serde(skip)]
+    pub results_buffer: Option<Arc<Mutex<ResultsBuffer>>>,
+
+    /// Where the pipelined runner lists the size of each block it reads with adaptive
+    /// [`Config::block_sizing`], for `--timings`
+    #[serde(skip)]
+    pub block_sizes: Option<Arc<BlockSizeLog>>,
+
+    /// Where the readers count what they've read & runners publish snapshots of the stations,
+    /// for `--tui`
+    #[serde(skip)]
+    pub progress: Option<Arc<Progress>>,
+}
+
+impl Config {
+    /// Merge the provided layers into the effective configuration.
+    ///
+    /// Values set on the command line win over those from the environment, which win over
+    /// those from a config file; anything left unset falls back to its default. An input which
+    /// can't be canonicalized is left as given, for the runner to fail to open; see
+    /// [`Config::resolve_canonical`] to report it sooner.
+    pub fn resolve(input: &Path, cli: Layer, env: Layer, file: Layer) -> Self {
+        let canonical_input = std::fs::canonicalize(input).unwrap_or_else(|_| input.to_path_buf());
+        Self::resolve_canonical(input, canonical_input, cli, env, file)
+    }
+
+    /// Like [`Config::resolve`], but with the input already canonicalized by the caller
+    pub fn resolve_canonical(
+        input: &Path,
+        canonical_input: PathBuf,
+        cli: Layer,
+        env: Layer,
+        file: Layer,
+    ) -> Self {
+        fn pick<T>(cli: Option<T>, env: Option<T>, file: Option<T>, default: T) -> Setting<T> {
+            cli.map(|v| Setting::new(v, Source::Cli))
+                .or_else(|| env.map(|v| Setting::new(v, Source::Env)))
+                .or_else(|| file.map(|v| Setting::new(v, Source::Config)))
+                .unwrap_or_else(|| Setting::new(default, Source::Default))
+        }
+
+        let runner = pick(cli.runner, env.runner, file.runner, Runner::default());
+        let buffer_size = pick(
+            cli.buffer_size,
+            env.buffer_size,
+            file.buffer_size,
+            DEFAULT_BUFFER_SIZE,
+        );
+        let max_line_length = pick(
+            cli.max_line_length,
+            env.max_line_length,
+            file.max_line_length,
+            DEFAULT_MAX_LINE_LENGTH,
+        );
+        let station_cache = pick(
+            cli.station_cache,
+            env.station_cache,
+            file.station_cache,
+            false,
+        );
+        let strict = pick(cli.strict, env.strict, file.strict, false);
+        let cpus = Cpus::detect();
+        let num_chunks = match pick(cli.num_chunks, env.num_chunks, file.num_chunks, 0) {
+            Setting {
+                source: Source::Default,
+                ..
+            } => Setting::new(default_num_chunks(&cpus), Source::Auto),
+            setting => setting,
+        };
+        let threads = match pick(cli.threads, env.threads, file.threads, 0) {
+            Setting {
+                source: Source::Default,
+                ..
+            } => Setting::new(default_threads(&cpus), Source::Auto),
+            setting => setting,
+        };
+        let sample_fraction = pick(
+            cli.sample_fraction,
+            env.sample_fraction,
+            file.sample_fraction,
+            DEFAULT_SAMPLE_FRACTION,
+        );
+        let timings = pick(cli.timings, env.timings, file.timings, false);
+        let key_format = pick(
+            cli.key_format,
+            env.key_format,
+            file.key_format,
+            KeyFormat::default(),
+        );
+        let track_extents = pick(
+            cli.track_extents,
+            env.track_extents,
+            file.track_extents,
+            false,
+        );
+        let case_insensitive = pick(
+            cli.case_insensitive,
+            env.case_insensitive,
+            file.case_insensitive,
+            false,
+        );
+        // A runner which was picked explicitly is the one wanted, even if another would work
+        let fallback = match pick(cli.fallback, env.fallback, file.fallback, false) {
+            Setting {
+                source: Source::Default,
+                ..
+            } => Setting::new(runner.source == Source::Default, Source::Auto),
+            setting => setting,
+        };
+        let max_skipped = pick(cli.max_skipped, env.max_skipped, file.max_skipped, 0);
+        let delimiter = pick(
+            cli.delimiter,
+            env.delimiter,
+            file.delimiter,
+            Delimiter::default(),
+        );
+        let decimal_comma = pick(
+            cli.decimal_comma,
+            env.decimal_comma,
+            file.decimal_comma,
+            false,
+        );
+        let encoding = pick(
+            cli.encoding,
+            env.encoding,
+            file.encoding,
+            Encoding::default(),
+        );
+        let max_memory = pick(cli.max_memory, env.max_memory, file.max_memory, 0);
+        let block_sizing = pick(
+            cli.block_sizing,
+            env.block_sizing,
+            file.block_sizing,
+            BlockSizing::default(),
+        );
+        let hasher = Setting::new(runner.value.hasher(), Source::Auto);
+
+        let input_size = std::fs::metadata(&canonical_input)
+            .map(|m| m.len())
+            .unwrap_or(0);
+
+        Self {
+            runner,
+            hasher,
+            buffer_size,
+            max_line_length,
+            station_cache,
+            strict,
+            num_chunks,
+            threads,
+            cpus,
+            sample_fraction,
+            timings,
+            key_format,
+            track_extents,
+            case_insensitive,
+            fallback,
+            max_skipped,
+            delimiter,
+            decimal_comma,
+            encoding,
+            max_memory,
+            block_sizing,
+            allocator: Setting::new(crate::ALLOCATOR, Source::Auto),
+            input: Setting::new(input.to_path_buf(), Source::Cli),
+            canonical_input: Setting::new(canonical_input, Source::Auto),
+            input_size: Setting::new(input_size, Source::Auto),
+            known_stations: None,
+            verbose: 0,
+            warnings: true,
+            byte_range: None,
+            sample: None,
+            chunk_manifest: None,
+            results_buffer: None,
+            block_sizes: None,
+            progress: None,
+        }
+    }
+}
+
+impl Config {
+    /// How the lines of the input are laid out
+    pub fn dialect(&self) -> Dialect {
+        Dialect {
+            key_format: self.key_format.value,
+            delimiter: self.delimiter.value,
+            decimal_comma: self.decimal_comma.value,
+            encoding: self.encoding.value,
+        }
+    }
+
+    /// How reproducible the configured runner's results are with these settings: its
+    /// [`Runner::determinism`], unless a setting makes them less so
+    pub fn determinism(&self) -> Determinism {
+        match (self.runner.value, self.block_sizing.value) {
+            // Adaptive blocks are sized by how long reads take & how far behind the parsers are,
+            // so where each block ends (& which parser sums it) changes from run to run
+            (Runner::Pipelined, BlockSizing::Adaptive { .. }) => Determinism::BestEffort,
+            (runner, _) => runner.determinism(),
+        }
+    }
+
+    /// Whether a runner should count the row each station is on: to track extents, or to know
+    /// which spelling of a case-insensitive station was first if it only folds names at the end
+    pub fn track_rows(&self) -> bool {
+        self.track_extents.value || self.case_insensitive.value
+    }
+
+    /// The storage of the last run's results, for the next run to reuse; empty unless
+    /// [`Config::results_buffer`] is set
+    pub fn take_results_buffer(&self) -> ResultsBuffer {
+        self.results_buffer
+            .as_ref()
+            .map(|buffer| std::mem::take(&mut *buffer.lock().unwrap_or_else(|e| e.into_inner())))
+            .unwrap_or_default()
+    }
+
+    /// Keep the storage of results which are no longer needed for the next run, if
+    /// [`Config::results_buffer`] is set
+    pub fn recycle_results(&self, stations: Vec<StationInfo>) {
+        if let Some(buffer) = &self.results_buffer {
+            buffer
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .recycle(stations);
+        }
+    }
+
+    /// A copy of this configuration with the runner (and everything derived from it) replaced
+    pub fn with_runner(&self, runner: Runner, source: Source) -> Self {
+        Self {
+            runner: Setting::new(runner, source),
+            hasher: Setting::new(runner.hasher(), Source::Auto),
+            ..self.clone()
+        }
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        let runner = Runner::default();
+        let cpus = Cpus::detect();
+        Self {
+            runner: Setting::new(runner, Source::Default),
+            hasher: Setting::new(runner.hasher(), Source::Auto),
+            buffer_size: Setting::new(DEFAULT_BUFFER_SIZE, Source::Default),
+            max_line_length: Setting::new(DEFAULT_MAX_LINE_LENGTH, Source::Default),
+            station_cache: Setting::new(false, Source::Default),
+            strict: Setting::new(false, Source::Default),
+            num_chunks: Setting::new(default_num_chunks(&cpus), Source::Auto),
+            threads: Setting::new(default_threads(&cpus), Source::Auto),
+            cpus,
+            sample_fraction: Setting::new(DEFAULT_SAMPLE_FRACTION, Source::Default),
+            timings: Setting::new(false, Source::Default),
+            key_format: Setting::new(KeyFormat::default(), Source::Default),
+            track_extents: Setting::new(false, Source::Default),
+            case_insensitive: Setting::new(false, Source::Default),
+            fallback: Setting::new(true, Source::Auto),
+            max_skipped: Setting::new(0, Source::Default),
+            delimiter: Setting::new(Delimiter::default(), Source::Default),
+            decimal_comma: Setting::new(false, Source::Default),
+            encoding: Setting::new(Encoding::default(), Source::Default),
+            max_memory: Setting::new(0, Source::Default),
+            block_sizing: Setting::new(BlockSizing::default(), Source::Default),
+            allocator: Setting::new(crate::ALLOCATOR, Source::Auto),
+            input: Setting::default(),
+            canonical_input: Setting::default(),
+            input_size: Setting::default(),
+            known_stations: None,
+            verbose: 0,
+            warnings: true,
+            byte_range: None,
+            sample: None,
+            chunk_manifest: None,
+            results_buffer: None,
+            block_sizes: None,
+            progress: None,
+        }
+    }
+}
+
+impl Display for Config {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "Effective configuration:")?;
+        writeln!(
+            f,
+            "  runner:        {} ({})",
+            self.runner.value, self.runner.source
+        )?;
+        writeln!(
+            f,
+            "  hasher:        {} ({})",
+            self.hasher.value, self.hasher.source
+        )?;
+        writeln!(
+            f,
+            "  allocator:     {} ({})",
+            self.allocator.value, self.allocator.source
+        )?;
+        writeln!(
+            f,
+            "  buffer size:   {} bytes ({})",
+            self.buffer_size.value, self.buffer_size.source
+        )?;
+        writeln!(
+            f,
+            "  max line:      {} bytes ({})",
+            self.max_line_length.value, self.max_line_length.source
+        )?;
+        writeln!(
+            f,
+            "  station cache: {} ({})",
+            if self.station_cache.value {
+                "on"
+            } else {
+                "off"
+            },
+            self.station_cache.source
+        )?;
+        writeln!(
+            f,
+            "  input mode:    {} ({})",
+            if self.strict.value {
+                "strict"
+            } else {
+                "lenient"
+            },
+            self.strict.source
+        )?;
+        writeln!(
+            f,
+            "  chunks:        {} ({})",
+            self.num_chunks.value, self.num_chunks.source
+        )?;
+        writeln!(
+            f,
+            "  threads:       {} ({})",
+            self.threads.value, self.threads.source
+        )?;
+        writeln!(f, "  cpus:          {}", self.cpus)?;
+        writeln!(
+            f,
+            "  sample:        {}% ({})",
+            self.sample_fraction.value * 100.0,
+            self.sample_fraction.source
+        )?;
+        writeln!(
+            f,
+            "  timings:       {} ({})",
+            if self.timings.value {
+                "aggregation & total"
+            } else {
+                "total"
+            },
+            self.timings.source
+        )?;
+        writeln!(
+            f,
+            "  key format:    {} ({})",
+            self.key_format.value, self.key_format.source
+        )?;
+        writeln!(
+            f,
+            "  extents:       {} ({})",
+            if self.track_extents.value {
+                "tracked"
+            } else {
+                "off"
+            },
+            self.track_extents.source
+        )?;
+        writeln!(
+            f,
+            "  station case:  {} ({})",
+            if self.case_insensitive.value {
+                "folded"
+            } else {
+                "exact"
+            },
+            self.case_insensitive.source
+        )?;
+        writeln!(
+            f,
+            "  fallback:      {} ({})",
+            if self.fallback.value { "on" } else { "off" },
+            self.fallback.source
+        )?;
+        writeln!(
+            f,
+            "  max skipped:   {} ({})",
+            self.max_skipped.value, self.max_skipped.source
+        )?;
+        writeln!(
+            f,
+            "  delimiter:     {} ({})",
+            self.delimiter.value, self.delimiter.source
+        )?;
+        writeln!(
+            f,
+            "  decimal mark:  {} ({})",
+            if self.decimal_comma.value {
+                "comma"
+            } else {
+                "point"
+            },
+            self.decimal_comma.source
+        )?;
+        writeln!(
+            f,
+            "  encoding:      {} ({})",
+            self.encoding.value, self.encoding.source
+        )?;
+        match self.max_memory.value {
+            0 => writeln!(f, "  max memory:    unbounded ({})", self.max_memory.source)?,
+            bytes => writeln!(
+                f,
+                "  max memory:    {bytes} bytes ({})",
+                self.max_memory.source
+            )?,
+        }
+        writeln!(
+            f,
+            "  block sizing:  {} ({})",
+            self.block_sizing.value, self.block_sizing.source
+        )?;
+        writeln!(
+            f,
+            "  input:         {} ({})",
+            self.input.value.display(),
+            self.input.source
+        )?;
+        writeln!(
+            f,
+            "  canonical:     {} ({})",
+            self.canonical_input.value.display(),
+            self.canonical_input.source
+        )?;
+        write!(
+            f,
+            "  input size:    {} bytes ({})",
+            self.input_size.value, self.input_size.source
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn env(vars: &[(&str, &str)]) -> impl Fn(&str) -> Option<String> {
+        let vars: HashMap<String, String> = vars
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+        move |key| vars.get(key).cloned()
+    }
+
+    #[test]
+    fn env_and_cli_overrides() -> Result<(), Box<dyn std::error::Error>> {
+        let env = Layer::from_env(env(&[
+            ("ONEBRC_RUNNER", "baseline"),
+            ("ONEBRC_BUFFER_SIZE", "1024"),
+        ]))?;
+        let cli = Layer {
+            buffer_size: Some(4096),
+            ..Default::default()
+        };
+        let config = Config::resolve(Path::new("measurements.txt"), cli, env, Layer::default());
+
+        assert_eq!(config.runner, Setting::new(Runner::Baseline, Source::Env));
+        assert_eq!(config.buffer_size, Setting::new(4096, Source::Cli));
+        assert_eq!(config.hasher.source, Source::Auto);
+        // The runner was picked explicitly, so is the one wanted
+        assert_eq!(config.fallback, Setting::new(false, Source::Auto));
+        assert_eq!(
+            config.allocator,
+            Setting::new(crate::ALLOCATOR, Source::Auto)
+        );
+
+        let banner = config.to_string();
+        assert!(
+            banner.contains("runner:        baseline (env)"),
+            "banner does not reflect env override:\n{banner}"
+        );
+        assert!(
+            banner.contains("buffer size:   4096 bytes (cli)"),
+            "banner does not reflect CLI override:\n{banner}"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn config_file_and_defaults() -> Result<(), Box<dyn std::error::Error>> {
+        let file: Layer = toml::from_str("runner = \"rustc-hash\"")?;
+        let config = Config::resolve(
+            Path::new("measurements.txt"),
+            Layer::default(),
+            Layer::from_env(env(&[]))?,
+            file,
+        );
+
+        assert_eq!(
+            config.runner,
+            Setting::new(Runner::RustcHash, Source::Config)
+        );
+        assert_eq!(
+            config.buffer_size,
+            Setting::new(DEFAULT_BUFFER_SIZE, Source::Default)
+        );
+
+        let config = Config::resolve(
+            Path::new("measurements.txt"),
+            Layer::default(),
+            Layer::from_env(env(&[("ONEBRC_FALLBACK", "true")]))?,
+            toml::from_str("runner = \"mmap\"")?,
+        );
+        assert_eq!(config.fallback, Setting::new(true, Source::Env));
+        let config = Config::resolve(
+            Path::new("measurements.txt"),
+            Layer::default(),
+            Layer::default(),
+            Layer::default(),
+        );
+        assert_eq!(config.fallback, Setting::new(true, Source::Auto));
+
+        Ok(())
+    }
+
+    #[test]
+    fn invalid_env_value() {
+        assert!(Layer::from_env(env(&[("ONEBRC_RUNNER", "nope")])).is_err());
+        assert!(Layer::from_env(env(&[("ONEBRC_BUFFER_SIZE", "big")])).is_err());
+        assert!(Layer::from_env(env(&[("ONEBRC_STRICT", "maybe")])).is_err());
+        assert!(Layer::from_env(env(&[("ONEBRC_DELIMITER", ".")])).is_err());
+        assert!(Layer::from_env(env(&[("ONEBRC_MAX_MEMORY", "1GB")])).is_err());
+        assert!(Layer::from_env(env(&[("ONEBRC_BLOCK_SIZING", "adaptive:2-1")])).is_err());
+        assert!(Layer::from_env(env(&[("ONEBRC_CASE_INSENSITIVE", "sometimes")])).is_err());
+        assert!(Layer::from_env(env(&[("ONEBRC_FALLBACK", "maybe")])).is_err());
+        assert!(Layer::from_env(env(&[("ONEBRC_ENCODING", "ebcdic")])).is_err());
+    }
+
+    #[test]
+    fn dialect() -> Result<(), Box<dyn std::error::Error>> {
+        let file: Layer = toml::from_str("delimiter = \"\\t\"\ndecimal-comma = true")?;
+        let env = Layer::from_env(env(&[("ONEBRC_DELIMITER", ",")]))?;
+        let config = Config::resolve(Path::new("measurements.txt"), Layer::default(), env, file);
+
+        assert_eq!(config.delimiter.source, Source::Env);
+        assert_eq!(
+            config.dialect(),
+            Dialect {
+                key_format: KeyFormat::Station,
+                delimiter: Delimiter::new(b',')?,
+                decimal_comma: true,
+                encoding: Encoding::Utf8,
+            }
+        );
+        let banner = config.to_string();
+        assert!(banner.contains("delimiter:     , (env)"), "{banner}");
+        assert!(banner.contains("decimal mark:  comma (config)"), "{banner}");
+
+        let file: Layer = toml::from_str("delimiter = \"tab\"")?;
+        assert_eq!(file.delimiter, Some(Delimiter::new(b'\t')?));
+
+        Ok(())
+    }
+
+    /// Write the test data to `name` in `dir`
+    fn input_file(dir: &Path, name: impl AsRef<std::ffi::OsStr>) -> PathBuf {
+        let path = dir.join(name.as_ref());
+        std::fs::write(&path, crate::runners::tests::TEST_DATA).unwrap();
+        path
+    }
+
+    fn resolve_cached(input: &Path) -> Config {
+        let cli = Layer {
+            station_cache: Some(true),
+            ..Default::default()
+        };
+        Config::resolve(input, cli, Layer::default(), Layer::default())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn symlinked_input() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = tempfile::tempdir()?;
+        let real = input_file(dir.path(), "measurements.txt");
+        let link = dir.path().join("link.txt");
+        std::os::unix::fs::symlink(&real, &link)?;
+
+        let config = resolve_cached(&link);
+        assert_eq!(config.input.value, link);
+        assert_eq!(config.canonical_input.value, real.canonicalize()?);
+        assert!(config.to_string().contains("link.txt"));
+
+        // The cache belongs to the real file, so it's shared with runs using the real path
+        crate::runners::run(&config)?;
+        assert!(!crate::station_cache::StationCache::path_for(&link).exists());
+        let real_config = resolve_cached(&real);
+        assert!(
+            crate::station_cache::StationCache::load(&real_config.canonical_input.value)?.is_some()
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn relative_input_from_another_directory() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = tempfile::tempdir()?;
+        std::fs::create_dir(dir.path().join("data"))?;
+        input_file(&dir.path().join("data"), "measurements.txt");
+
+        // The working directory is shared by every test, so put it back no matter what
+        struct RestoreCwd(PathBuf);
+        impl Drop for RestoreCwd {
+            fn drop(&mut self) {
+                let _ = std::env::set_current_dir(&self.0);
+            }
+        }
+        let _restore = RestoreCwd(std::env::current_dir()?);
+
+        std::env::set_current_dir(dir.path().join("data"))?;
+        let config = resolve_cached(Path::new("../data/./measurements.txt"));
+        std::env::set_current_dir(dir.path())?;
+
+        assert_eq!(config.input.value, Path::new("../data/./measurements.txt"));
+        assert!(config.canonical_input.value.is_absolute());
+        let (stations, _) = crate::runners::run(&config)?;
+        assert_eq!(stations, *crate::runners::tests::EXPECTED_RESULT);
+
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn non_utf8_input() -> Result<(), Box<dyn std::error::Error>> {
+        use std::os::unix::ffi::OsStrExt;
+
+        let dir = tempfile::tempdir()?;
+        let name = std::ffi::OsStr::from_bytes(b"measurements-\xff.txt");
+        let input = input_file(dir.path(), name);
+
+        let config = resolve_cached(&input);
+        let (stations, _) = crate::runners::run(&config)?;
+        assert_eq!(stations, *crate::runners::tests::EXPECTED_RESULT);
+
+        // Shown & serialized lossily rather than failing
+        assert!(config.to_string().contains("measurements-\u{FFFD}.txt"));
+        let json = serde_json::to_string(&config)?;
+        assert!(json.contains("measurements-\u{FFFD}.txt"), "{json}");
+
+        Ok(())
+    }
+
+    #[test]
+    fn adaptive_blocks_are_best_effort() {
+        let adaptive = |runner| Config {
+            block_sizing: Setting::new("adaptive".parse().unwrap(), Source::Cli),
+            ..Config::default().with_runner(runner, Source::Cli)
+        };
+        assert_eq!(
+            adaptive(Runner::Pipelined).determinism(),
+            Determinism::BestEffort
+        );
+        assert_eq!(
+            Config::default()
+                .with_runner(Runner::Pipelined, Source::Cli)
+                .determinism(),
+            Determinism::RoundedExact
+        );
+        // Only the pipelined runner sizes its blocks adaptively
+        for &runner in Runner::value_variants() {
+            if runner != Runner::Pipelined {
+                assert_eq!(adaptive(runner).determinism(), runner.determinism());
+            }
+        }
+    }
+}
@@ -0,0 +1,1184 @@
+// 1BRC - my take on the 1 Billion Row Challenge
+// Copyright (C) 2024  Charles German <5donuts@pm.me>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! The parsing & accumulation at the heart of the crate, with nothing from `std`.
+//!
+//! Everything here only needs `core` & `alloc` (for the odd measurement spelled unusually, & for
+//! station names decoded from other encodings), so it builds with the `no-std-core` feature &
+//! none of the others for `#![no_std]` targets, like a microcontroller on a gateway which
+//! preprocesses the lines its sensors send. There, [`StationTable`] keeps the stations in storage
+//! the caller provides (e.g. a `static` array), so aggregating never allocates.
+//!
+//! The rest of the crate builds on these: [`aggregate`](crate::aggregate) re-exports them, & the
+//! `inline-table` runner's table wraps a [`StationTable`] in storage it grows as needed.
+
+use alloc::string::{FromUtf8Error, String};
+use alloc::vec::Vec;
+use core::fmt::{self, Display};
+use core::str::{FromStr, Utf8Error};
+
+use serde::{Deserialize, Serialize};
+
+/// A measurement, in degrees Celsius
+pub type Temperature = f32;
+
+/// Running min/max/mean data for a station, updated as each measurement is read.
+///
+/// Only finite measurements are ever recorded, so `min`, `max`, and `sum` stay finite once there
+/// is at least one measurement (and a mean is never `NaN`). Anything else passed to
+/// [`push`](StationData::push) is a bug in strict mode, and is skipped & counted in `skipped`
+/// otherwise.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct StationData {
+    pub min: Temperature,
+    pub max: Temperature,
+
+    // Rather than compute a new average at each step, just keep a rolling sum
+    // of all the measurements and calculate the average at the end.
+    pub sum: f32,
+    pub cnt: u32,
+
+    /// How many non-finite measurements were left out
+    pub skipped: u32,
+
+    /// The first & last rows the station was on, if [recorded](StationData::record_row);
+    /// `u64::MAX` & `0` until then
+    pub first_row: u64,
+    pub last_row: u64,
+}
+
+/// The first & last rows (1-based line numbers) a station's measurements were on
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct Extents {
+    pub first_row: u64,
+    pub last_row: u64,
+}
+
+impl StationData {
+    /// Instantiate a new record of measurements for a station
+    pub fn new(measurement: Temperature, strict: bool) -> Self {
+        let mut data = Self::empty();
+        data.push(measurement, strict);
+        data
+    }
+
+    /// Instantiate a record for a station that has no measurements yet
+    pub const fn empty() -> Self {
+        Self {
+            min: f32::INFINITY,
+            max: f32::NEG_INFINITY,
+            sum: 0.0,
+            cnt: 0,
+            skipped: 0,
+            first_row: u64::MAX,
+            last_row: 0,
+        }
+    }
+
+    /// Record an additional measurement for this station.
+    ///
+    /// A non-finite measurement is skipped (see the type's docs); in strict mode, that fails a
+    /// debug assertion first since the parser should never have let it through.
+    #[inline]
+    pub fn push(&mut self, measurement: Temperature, strict: bool) {
+        if !measurement.is_finite() {
+            debug_assert!(
+                !strict,
+                "non-finite measurement {measurement} in strict mode"
+            );
+            self.skipped += 1;
+            return;
+        }
+
+        // These can't be an if/else-if chain; an empty record needs both updated
+        if measurement < self.min {
+            self.min = measurement;
+        }
+        if measurement > self.max {
+            self.max = measurement;
+        }
+
+        self.sum += measurement;
+        self.cnt += 1;
+    }
+
+    /// Combine the measurements recorded in `other` into this record
+    #[inline]
+    pub fn merge(&mut self, other: &Self) {
+        if other.min < self.min {
+            self.min = other.min;
+        }
+        if other.max > self.max {
+            self.max = other.max;
+        }
+
+        self.sum += other.sum;
+        self.cnt += other.cnt;
+        self.skipped += other.skipped;
+        self.first_row = self.first_row.min(other.first_row);
+        self.last_row = self.last_row.max(other.last_row);
+    }
+
+    /// The mean measurement. Stations with no measurements are left out of the results, so
+    /// there's never a mean to take of none.
+    pub fn avg(&self) -> f32 {
+        debug_assert!(self.cnt > 0, "A station with no measurements has no mean");
+        self.sum / self.cnt as f32
+    }
+
+    /// Record that the station was on the given (1-based) row.
+    ///
+    /// This is extra work in the hot loop, so callers only do it when asked to track extents.
+    #[inline]
+    pub fn record_row(&mut self, row: u64) {
+        self.first_row = self.first_row.min(row);
+        self.last_row = self.last_row.max(row);
+    }
+
+    /// The first & last rows the station was on, if any were recorded
+    pub fn extents(&self) -> Option<Extents> {
+        (self.last_row > 0).then_some(Extents {
+            first_row: self.first_row,
+            last_row: self.last_row,
+        })
+    }
+}
+
+/// What the measurements in the input are grouped by
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "native", derive(clap::ValueEnum))]
+#[serde(rename_all = "kebab-case")]
+pub enum KeyFormat {
+    /// `Hamburg;12.3`, grouped by station
+    #[default]
+    Station,
+
+    /// `2023-07;Hamburg;12.3`, grouped by station & month.
+    ///
+    /// The key is the month & station together as they appear in the input (e.g.
+    /// `2023-07;Hamburg`), so results are ordered by month, then station.
+    MonthStation,
+}
+
+impl Display for KeyFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            KeyFormat::Station => "station",
+            KeyFormat::MonthStation => "month-station",
+        };
+        write!(f, "{s}")
+    }
+}
+
+/// The character between the fields of a line: `;` unless the input was exported with
+/// something else, e.g. a tab or a comma
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Delimiter(u8);
+
+impl Delimiter {
+    /// A delimiter can be any ASCII character which can't be part of a measurement or end a line
+    pub fn new(byte: u8) -> Result<Self, &'static str> {
+        match byte {
+            b'0'..=b'9' | b'-' | b'+' | b'.' | b'\n' | b'\r' => {
+                Err("the delimiter can't be a digit, sign, '.', or line break")
+            }
+            _ if !byte.is_ascii() => Err("the delimiter must be an ASCII character"),
+            _ => Ok(Self(byte)),
+        }
+    }
+
+    pub fn byte(self) -> u8 {
+        self.0
+    }
+}
+
+impl Default for Delimiter {
+    fn default() -> Self {
+        Self(b';')
+    }
+}
+
+impl FromStr for Delimiter {
+    type Err = &'static str;
+
+    /// Parse a single character, or `\t` or `tab` for a tab
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "\\t" | "tab" => Ok(Self(b'\t')),
+            _ => match s.as_bytes() {
+                [byte] => Self::new(*byte),
+                _ => Err("the delimiter must be a single ASCII character, or \\t for a tab"),
+            },
+        }
+    }
+}
+
+impl Display for Delimiter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.0 {
+            b'\t' => write!(f, "\\t"),
+            byte => write!(f, "{}", byte as char),
+        }
+    }
+}
+
+impl Serialize for Delimiter {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for Delimiter {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+/// How the station names in the input are encoded.
+///
+/// Measurements & delimiters are ASCII, which every encoding here agrees on, so only lines with
+/// other bytes in them (i.e. in their station names) are ever transcoded.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "native", derive(clap::ValueEnum))]
+#[serde(rename_all = "kebab-case")]
+pub enum Encoding {
+    #[default]
+    Utf8,
+
+    /// ISO-8859-1, where each byte is the code point of the same value. Only with the
+    /// `encodings` feature.
+    #[cfg(feature = "encodings")]
+    Latin1,
+
+    /// Latin-1 with printable characters (e.g. `€`) in place of most C1 control codes, as
+    /// exported by older Windows tools. Only with the `encodings` feature.
+    #[cfg(feature = "encodings")]
+    #[cfg_attr(feature = "native", value(name = "windows-1252"))]
+    #[serde(rename = "windows-1252")]
+    Windows1252,
+}
+
+impl Encoding {
+    /// Read `bytes` (e.g. a line, or a station name) as UTF-8, transcoding them into `buf` first
+    /// if they're in another encoding & not all ASCII.
+    ///
+    /// Only UTF-8 can be invalid; every byte means something in the other encodings.
+    #[inline]
+    pub fn decode<'a>(self, bytes: &'a [u8], buf: &'a mut String) -> Result<&'a str, Utf8Error> {
+        if self == Encoding::Utf8 || bytes.is_ascii() {
+            return core::str::from_utf8(bytes);
+        }
+        buf.clear();
+        self.transcode(bytes, buf);
+        Ok(buf)
+    }
+
+    /// Read `bytes` as UTF-8 like [`decode`](Encoding::decode), but into a `String` of their own
+    pub fn decode_owned(self, bytes: Vec<u8>) -> Result<String, FromUtf8Error> {
+        if self == Encoding::Utf8 || bytes.is_ascii() {
+            return String::from_utf8(bytes);
+        }
+        let mut name = String::new();
+        self.transcode(&bytes, &mut name);
+        Ok(name)
+    }
+
+    /// Append `bytes`, in this encoding, to `out` as UTF-8
+    #[cfg(feature = "encodings")]
+    fn transcode(self, bytes: &[u8], out: &mut String) {
+        match self {
+            Encoding::Utf8 => unreachable!("UTF-8 is never transcoded"),
+            Encoding::Latin1 => out.push_str(&encoding_rs::mem::decode_latin1(bytes)),
+            Encoding::Windows1252 => {
+                let mut decoder = encoding_rs::WINDOWS_1252.new_decoder_without_bom_handling();
+                out.reserve(
+                    decoder
+                        .max_utf8_buffer_length(bytes.len())
+                        .expect("A line is far shorter than usize::MAX"),
+                );
+                let (result, _, _) = decoder.decode_to_string(bytes, out, true);
+                debug_assert_eq!(result, encoding_rs::CoderResult::InputEmpty);
+            }
+        }
+    }
+
+    #[cfg(not(feature = "encodings"))]
+    fn transcode(self, _: &[u8], _: &mut String) {
+        unreachable!("Only UTF-8 is supported without the `encodings` feature")
+    }
+}
+
+impl Display for Encoding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            Encoding::Utf8 => "utf8",
+            #[cfg(feature = "encodings")]
+            Encoding::Latin1 => "latin1",
+            #[cfg(feature = "encodings")]
+            Encoding::Windows1252 => "windows-1252",
+        };
+        write!(f, "{s}")
+    }
+}
+
+/// How the fields of each line of the input are laid out
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Dialect {
+    pub key_format: KeyFormat,
+    pub delimiter: Delimiter,
+
+    /// Whether measurements are written with a decimal comma, e.g. `12,3`
+    pub decimal_comma: bool,
+
+    /// How the station names are encoded
+    pub encoding: Encoding,
+}
+
+impl From<KeyFormat> for Dialect {
+    fn from(key_format: KeyFormat) -> Self {
+        Self {
+            key_format,
+            ..Self::default()
+        }
+    }
+}
+
+/// The most distinct stations the challenge allows in one input
+pub const MAX_STATIONS: usize = 10_000;
+
+/// Why a line has no measurement where one was expected
+pub const MISSING_SEPARATOR: &str = "missing ';' between station and measurement";
+
+/// Why a line has no measurement where one was expected, with a [`Delimiter`] other than `;`
+pub const MISSING_DELIMITER: &str = "missing delimiter between station and measurement";
+
+/// Why a line whose fields & decimal marks are both commas wasn't accepted in strict mode
+pub const AMBIGUOUS_COMMAS: &str =
+    "ambiguous line: the key would contain a comma, so it's unclear where the measurement starts";
+
+/// Why a measurement couldn't be parsed as a number at all
+pub const INVALID_MEASUREMENT: &str = "invalid measurement";
+
+/// Why a measurement wasn't accepted in strict mode
+pub const NON_CANONICAL_MEASUREMENT: &str =
+    "measurement must have 1-2 integer digits and exactly 1 decimal digit";
+
+/// Why a measurement which is a number, but outside the spec's -99.9 to 99.9 (or too large to
+/// represent at all), wasn't accepted
+pub const OUT_OF_RANGE: &str = "measurement out of range";
+
+/// The largest magnitude of a measurement the spec allows
+pub const MAX_MEASUREMENT: Temperature = 99.9;
+
+/// Whether `field` is a month in the `YYYY-MM` format
+pub(crate) fn is_month(field: &[u8]) -> bool {
+    match field {
+        [y0, y1, y2, y3, b'-', m0, m1] => {
+            [y0, y1, y2, y3, m0, m1].iter().all(|b| b.is_ascii_digit())
+                && matches!((m0, m1), (b'0', b'1'..=b'9') | (b'1', b'0'..=b'2'))
+        }
+        _ => false,
+    }
+}
+
+/// Find the delimiter between a line's key (see [`KeyFormat`]) and its measurement
+pub(crate) fn key_end(line: &[u8], dialect: Dialect, strict: bool) -> Result<usize, &'static str> {
+    let delimiter = dialect.delimiter.byte();
+    if dialect.decimal_comma && delimiter == b',' {
+        return comma_key_end(line, dialect.key_format, strict);
+    }
+
+    let missing = if delimiter == b';' {
+        MISSING_SEPARATOR
+    } else {
+        MISSING_DELIMITER
+    };
+    let first = line.iter().position(|&b| b == delimiter).ok_or(missing)?;
+    match dialect.key_format {
+        KeyFormat::Station => Ok(first),
+        KeyFormat::MonthStation => {
+            if !is_month(&line[..first]) {
+                return Err(MISSING_MONTH);
+            }
+            let rest = &line[first + 1..];
+            let second = rest.iter().position(|&b| b == delimiter).ok_or(missing)?;
+            Ok(first + 1 + second)
+        }
+    }
+}
+
+/// Why a line didn't start with a month with [`KeyFormat::MonthStation`]
+const MISSING_MONTH: &str = "expected a YYYY-MM month before the station";
+
+/// Find where the key ends in a line whose fields & decimal marks are both commas, e.g.
+/// `Hamburg,12,3`.
+///
+/// This splits on the last comma before a number at the end of the line, so `Hamburg,12,3` is
+/// read as `12,3` at `Hamburg`. Any further commas are taken to be part of the key, which is an
+/// error in strict mode since the line could be read more than one way.
+fn comma_key_end(line: &[u8], key_format: KeyFormat, strict: bool) -> Result<usize, &'static str> {
+    let is_integer = |field: &[u8]| {
+        let digits = match field {
+            [b'-' | b'+', digits @ ..] => digits,
+            digits => digits,
+        };
+        !digits.is_empty() && digits.iter().all(u8::is_ascii_digit)
+    };
+
+    let last = line
+        .iter()
+        .rposition(|&b| b == b',')
+        .ok_or(MISSING_DELIMITER)?;
+    let end = match line[..last].iter().rposition(|&b| b == b',') {
+        Some(before) if is_integer(&line[before + 1..last]) && is_integer(&line[last + 1..]) => {
+            before
+        }
+        _ => last,
+    };
+
+    let key = &line[..end];
+    let key_commas = match key_format {
+        KeyFormat::Station => 0,
+        KeyFormat::MonthStation => {
+            let month_end = key.iter().position(|&b| b == b',').ok_or(MISSING_MONTH)?;
+            if !is_month(&key[..month_end]) {
+                return Err(MISSING_MONTH);
+            }
+            1
+        }
+    };
+    if strict && key.iter().filter(|&&b| b == b',').count() > key_commas {
+        return Err(AMBIGUOUS_COMMAS);
+    }
+    Ok(end)
+}
+
+/// Parse a line's measurement, explaining the likely cause if it's actually a line with a month
+/// in the wrong key format.
+pub(crate) fn parse_value(
+    key: &[u8],
+    measurement: &str,
+    strict: bool,
+    dialect: Dialect,
+) -> Result<f32, &'static str> {
+    let parsed = if !dialect.decimal_comma {
+        parse_measurement(measurement, strict)
+    } else if measurement.contains('.') {
+        // Most likely a thousands separator
+        Err(INVALID_MEASUREMENT)
+    } else {
+        parse_measurement(&measurement.replacen(',', ".", 1), strict)
+    };
+
+    parsed.map_err(|reason| {
+        let delimiter = dialect.delimiter.byte();
+        if dialect.key_format == KeyFormat::Station
+            && is_month(key)
+            && measurement.as_bytes().contains(&delimiter)
+        {
+            "line starts with a month; use the month-station key format for these"
+        } else {
+            reason
+        }
+    })
+}
+
+/// Split a line of input into its key (see [`KeyFormat`]) & measurement.
+///
+/// In strict mode, the measurement must be in the canonical form (see [`parse_fixed`]);
+/// otherwise other spellings of a number (`12`, `12.00`, `+12.3`, ...) are accepted too. The
+/// [`Dialect`] says how the fields are separated, and whether measurements have a decimal comma.
+///
+/// ```
+/// use onebrc::core_aggregate::{parse_line, Delimiter, Dialect, KeyFormat};
+///
+/// let dialect = Dialect::default();
+/// assert_eq!(parse_line("Hamburg;12.0", true, dialect), Ok(("Hamburg", 12.0)));
+/// assert_eq!(parse_line("Hamburg;+12", false, dialect), Ok(("Hamburg", 12.0)));
+/// assert!(parse_line("Hamburg;+12", true, dialect).is_err());
+///
+/// assert_eq!(
+///     parse_line("2023-07;Hamburg;12.0", true, KeyFormat::MonthStation.into()),
+///     Ok(("2023-07;Hamburg", 12.0))
+/// );
+///
+/// let european = Dialect {
+///     delimiter: Delimiter::new(b',')?,
+///     decimal_comma: true,
+///     ..Dialect::default()
+/// };
+/// assert_eq!(parse_line("Hamburg,12,3", true, european), Ok(("Hamburg", 12.3)));
+/// # Ok::<(), &str>(())
+/// ```
+pub fn parse_line(
+    line: &str,
+    strict: bool,
+    dialect: Dialect,
+) -> Result<(&str, Temperature), &'static str> {
+    let idx = key_end(line.as_bytes(), dialect, strict)?;
+    let (key, measurement) = (&line[..idx], &line[idx + 1..]);
+    Ok((
+        key,
+        parse_value(key.as_bytes(), measurement, strict, dialect)?,
+    ))
+}
+
+/// Parse a measurement, trying the fast fixed-layout parser first.
+///
+/// Anything the fast parser doesn't recognize falls back to the (slower) standard library
+/// parser, unless running in strict mode where it is an error. A number outside the spec's
+/// range of ±[`MAX_MEASUREMENT`] is [`OUT_OF_RANGE`].
+pub fn parse_measurement(s: &str, strict: bool) -> Result<Temperature, &'static str> {
+    if let Some(measurement) = parse_fixed(s) {
+        return Ok(measurement);
+    }
+    if strict {
+        return Err(NON_CANONICAL_MEASUREMENT);
+    }
+
+    match s.parse::<f32>() {
+        Ok(m) if m.is_finite() && m.abs() <= MAX_MEASUREMENT => Ok(m),
+        Ok(m) if m.is_finite() => Err(OUT_OF_RANGE),
+        // A number too large for an f32 parses as infinity, rather than failing
+        Ok(m) if m.is_infinite() && !s.to_ascii_lowercase().contains("inf") => Err(OUT_OF_RANGE),
+        _ => Err(INVALID_MEASUREMENT),
+    }
+}
+
+/// Parse a measurement in the canonical form from the challenge spec: an optional `-`, one or
+/// two integer digits, a `.`, and exactly one decimal digit.
+///
+/// Returns `None` for anything else, rather than guessing.
+pub fn parse_fixed(s: &str) -> Option<Temperature> {
+    // Both operands are exact, so this rounds the same way as parsing the decimal would
+    parse_tenths(s.as_bytes()).map(|tenths| tenths as f32 / 10.0)
+}
+
+/// Parse a measurement in the canonical form (see [`parse_fixed`]) as a whole number of tenths,
+/// e.g. `-12.3` as `-123`.
+///
+/// ```
+/// use onebrc::core_aggregate::parse_tenths;
+///
+/// assert_eq!(parse_tenths(b"-99.9"), Some(-999));
+/// assert_eq!(parse_tenths(b"5.7"), Some(57));
+/// assert_eq!(parse_tenths(b"-0.0"), Some(0));
+/// assert_eq!(parse_tenths(b"5.75"), None);
+/// ```
+#[inline]
+pub fn parse_tenths(s: &[u8]) -> Option<i16> {
+    let digit = |b: u8| b.is_ascii_digit().then(|| (b - b'0') as i16);
+
+    let (sign, rest) = match s {
+        [b'-', rest @ ..] => (-1, rest),
+        rest => (1, rest),
+    };
+    let tenths = match *rest {
+        [a, b'.', b] => digit(a)? * 10 + digit(b)?,
+        [a, b, b'.', c] => digit(a)? * 100 + digit(b)? * 10 + digit(c)?,
+        _ => return None,
+    };
+    Some(sign * tenths)
+}
+
+/// How full a [`StationTable`] may get, as a fraction of its entries; any fuller, & probes for
+/// stations which aren't there get long
+pub const MAX_LOAD: (usize, usize) = (5, 8);
+
+/// Set in the hash of every occupied entry, so a hash of `0` marks an empty one
+const OCCUPIED: u64 = 1 << 63;
+
+/// Hash a station's name with 64-bit FNV-1a, marked [`OCCUPIED`]
+#[inline]
+fn hash(name: &[u8]) -> u64 {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for &byte in name {
+        hash ^= byte as u64;
+        hash = hash.wrapping_mul(0x0100_0000_01b3);
+    }
+    hash | OCCUPIED
+}
+
+/// A station in a [`StationTable`], whose name is in the table's buffer of names
+#[derive(Debug, Clone, Copy)]
+pub struct Entry {
+    hash: u64,
+    name_start: usize,
+    name_len: usize,
+    data: StationData,
+}
+
+impl Entry {
+    /// An entry with no station in it, e.g. to fill the storage for a table with
+    pub const EMPTY: Self = Self {
+        hash: 0,
+        name_start: 0,
+        name_len: 0,
+        data: StationData::empty(),
+    };
+}
+
+/// Why a station couldn't be added to a [`StationTable`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Full {
+    /// There are as many stations as the entries have room for (see [`MAX_LOAD`])
+    Entries,
+
+    /// There's no room left for the station's name
+    Names,
+}
+
+impl Display for Full {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Full::Entries => write!(f, "no room for another station"),
+            Full::Names => write!(f, "no room for another station's name"),
+        }
+    }
+}
+
+/// The number of entries a [`StationTable`] needs to hold `stations` stations: a power of two
+/// with room for them within [`MAX_LOAD`]
+pub const fn entries_for(stations: usize) -> usize {
+    let (num, den) = MAX_LOAD;
+    (stations * den).div_ceil(num).next_power_of_two()
+}
+
+/// A linear-probing hash table of stations, in storage the caller provides: every entry in `E`
+/// (a slice, array, or `Vec` of [`Entry`]s), & every name one after another in `N` (of bytes).
+///
+/// The table never allocates, so it holds as many stations as fit: [`get_mut`] says when it's
+/// [`Full`] rather than growing. A caller which can allocate can then move the stations into more
+/// entries with [`rehash`], or give the names more room with [`grow_names`].
+///
+/// ```
+/// use onebrc::core_aggregate::{entries_for, Entry, StationTable};
+///
+/// let mut entries = [Entry::EMPTY; entries_for(4)];
+/// let mut names = [0; 64];
+/// let mut table = StationTable::new(&mut entries[..], &mut names[..]);
+/// table.get_mut(b"Hamburg")?.push(12.0, true);
+/// table.get_mut(b"Bulawayo")?.push(8.9, true);
+/// table.get_mut(b"Hamburg")?.push(34.2, true);
+///
+/// assert_eq!(table.len(), 2);
+/// assert_eq!(table.get(b"Hamburg").map(|data| data.max), Some(34.2));
+/// # Ok::<(), onebrc::core_aggregate::Full>(())
+/// ```
+///
+/// [`get_mut`]: StationTable::get_mut
+/// [`rehash`]: StationTable::rehash
+/// [`grow_names`]: StationTable::grow_names
+pub struct StationTable<E, N> {
+    entries: E,
+    names: N,
+
+    /// How many bytes of `names` are taken
+    names_len: usize,
+    len: usize,
+
+    /// The entry of the station the last measurement was for, until the stations move
+    last: Option<usize>,
+}
+
+impl<E, N> StationTable<E, N>
+where
+    E: AsRef<[Entry]> + AsMut<[Entry]>,
+    N: AsRef<[u8]> + AsMut<[u8]>,
+{
+    /// A table with no stations, keeping them in `entries`, which must be a power of two of them
+    /// (any stations already in them are forgotten), & their names in `names`
+    pub fn new(mut entries: E, names: N) -> Self {
+        assert!(
+            entries.as_ref().len().is_power_of_two(),
+            "A table's entries must be a power of two of them"
+        );
+        entries.as_mut().fill(Entry::EMPTY);
+        Self {
+            entries,
+            names,
+            names_len: 0,
+            len: 0,
+            last: None,
+        }
+    }
+
+    /// How many stations are in the table
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// How many stations the table can hold without more entries
+    pub fn capacity(&self) -> usize {
+        let (num, den) = MAX_LOAD;
+        self.entries.as_ref().len() * num / den
+    }
+
+    #[inline]
+    fn name(&self, entry: &Entry) -> &[u8] {
+        &self.names.as_ref()[entry.name_start..entry.name_start + entry.name_len]
+    }
+
+    /// The data for the station called `name`, added to the table if it's not there yet & there's
+    /// room for it
+    #[inline]
+    pub fn get_mut(&mut self, name: &[u8]) -> Result<&mut StationData, Full> {
+        // Lines often come in runs for the same station, which needn't be hashed at all
+        if let Some(idx) = self.last {
+            if self.name(&self.entries.as_ref()[idx]) == name {
+                return Ok(&mut self.entries.as_mut()[idx].data);
+            }
+        }
+
+        let hash = hash(name);
+        let idx = self.probe(name, hash);
+        if self.entries.as_ref()[idx].hash == 0 {
+            self.room_for(name)?;
+            let name_start = self.names_len;
+            self.names.as_mut()[name_start..name_start + name.len()].copy_from_slice(name);
+            self.names_len += name.len();
+            self.entries.as_mut()[idx] = Entry {
+                hash,
+                name_start,
+                name_len: name.len(),
+                data: StationData::empty(),
+            };
+            self.len += 1;
+        }
+        self.last = Some(idx);
+        Ok(&mut self.entries.as_mut()[idx].data)
+    }
+
+    /// Whether there's room to add a station called `name`, or which storage is [`Full`] if not
+    pub fn room_for(&self, name: &[u8]) -> Result<(), Full> {
+        if self.len + 1 > self.capacity() {
+            return Err(Full::Entries);
+        }
+        if self.names.as_ref().len() - self.names_len < name.len() {
+            return Err(Full::Names);
+        }
+        Ok(())
+    }
+
+    /// The data for the station called `name`, if it's in the table
+    pub fn get(&self, name: &[u8]) -> Option<&StationData> {
+        let entry = &self.entries.as_ref()[self.probe(name, hash(name))];
+        (entry.hash != 0).then_some(&entry.data)
+    }
+
+    /// Find the entry holding the given station, or the empty entry where it belongs
+    #[inline]
+    fn probe(&self, name: &[u8], hash: u64) -> usize {
+        let entries = self.entries.as_ref();
+        let mask = entries.len() - 1;
+        let mut idx = hash as usize & mask;
+        loop {
+            let entry = &entries[idx];
+            // Different names can share a hash (or just the bits which picked the first entry),
+            // so a station is only found by its full name
+            if entry.hash == 0 || (entry.hash == hash && self.name(entry) == name) {
+                return idx;
+            }
+            idx = (idx + 1) & mask;
+        }
+    }
+
+    /// Every station in the table, by name, in no particular order
+    pub fn iter(&self) -> impl Iterator<Item = (&[u8], &StationData)> {
+        self.entries
+            .as_ref()
+            .iter()
+            .filter(|entry| entry.hash != 0)
+            .map(|entry| (self.name(entry), &entry.data))
+    }
+
+    /// Move every station into `entries`, which must be a power of two of them with room for
+    /// them all, & give back the entries they were in. The names stay where they are.
+    pub fn rehash(&mut self, mut entries: E) -> E {
+        let new = entries.as_mut();
+        assert!(
+            new.len().is_power_of_two() && new.len() * MAX_LOAD.0 / MAX_LOAD.1 >= self.len,
+            "The new entries must be a power of two of them, with room for every station"
+        );
+        new.fill(Entry::EMPTY);
+        let mask = new.len() - 1;
+        for entry in self.entries.as_ref().iter().filter(|entry| entry.hash != 0) {
+            let mut idx = entry.hash as usize & mask;
+            while new[idx].hash != 0 {
+                idx = (idx + 1) & mask;
+            }
+            new[idx] = *entry;
+        }
+        // Every station has moved
+        self.last = None;
+        core::mem::replace(&mut self.entries, entries)
+    }
+
+    /// Give the names more room with `grow`, which mustn't change the names already there (e.g.
+    /// by only adding to the end of a `Vec`)
+    pub fn grow_names(&mut self, grow: impl FnOnce(&mut N)) {
+        grow(&mut self.names);
+        debug_assert!(self.names.as_ref().len() >= self.names_len);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_lines() {
+        for strict in [false, true] {
+            assert_eq!(
+                parse_line("Hamburg;12.0", strict, Dialect::default()),
+                Ok(("Hamburg", 12.0))
+            );
+            assert_eq!(
+                parse_line("St. John's;-5.3", strict, Dialect::default()),
+                Ok(("St. John's", -5.3))
+            );
+            assert!(parse_line("Hamburg 12.0", strict, Dialect::default()).is_err());
+            assert!(parse_line("Hamburg;warm", strict, Dialect::default()).is_err());
+        }
+    }
+
+    #[test]
+    fn month_keys() {
+        let month = KeyFormat::MonthStation.into();
+        assert_eq!(
+            parse_line("2023-07;Hamburg;12.3", false, month),
+            Ok(("2023-07;Hamburg", 12.3))
+        );
+        assert!(parse_line("Hamburg;12.3", false, month).is_err());
+        assert!(parse_line("2023-7;Hamburg;12.3", false, month).is_err());
+        assert!(parse_line("2023-00;Hamburg;12.3", false, month).is_err());
+        assert!(parse_line("2023-07;Hamburg", false, month).is_err());
+
+        // A month-station line read by station is still an error, but says why
+        assert_eq!(
+            parse_line("2023-07;Hamburg;12.3", false, Dialect::default()),
+            Err("line starts with a month; use the month-station key format for these")
+        );
+    }
+
+    #[test]
+    fn non_canonical_measurements() {
+        let cases = [
+            ("12", Some(12.0)),
+            ("12.00", Some(12.0)),
+            ("+12.3", Some(12.3)),
+            ("12.", Some(12.0)),
+            ("-0.50", Some(-0.5)),
+            ("NaN", None),
+            ("inf", None),
+            ("1.2.3", None),
+            ("", None),
+            ("99.90", Some(99.9)),
+            ("150.0", None),
+            ("-100", None),
+            ("1e40", None),
+        ];
+
+        for (input, expected) in cases {
+            // The fast parser must not guess at anything outside the canonical form
+            assert_eq!(parse_fixed(input), None, "fixed parser accepted {input:?}");
+
+            assert_eq!(
+                parse_measurement(input, false).ok(),
+                expected,
+                "lenient parse of {input:?}"
+            );
+            assert!(
+                parse_measurement(input, true).is_err(),
+                "strict mode accepted {input:?}"
+            );
+        }
+
+        // Outside the spec's range, whether or not it fits in an f32
+        for input in ["150.0", "-100", "99.95", "1e40"] {
+            assert_eq!(
+                parse_measurement(input, false),
+                Err(OUT_OF_RANGE),
+                "{input}"
+            );
+        }
+    }
+
+    #[test]
+    fn canonical_measurements_match_std() {
+        for tenths in -999..=999 {
+            let s = format!("{:.1}", tenths as f32 / 10.0);
+            assert_eq!(parse_fixed(&s), s.parse().ok(), "{s}");
+        }
+        assert_eq!(parse_fixed("-0.0"), Some(-0.0));
+        assert_eq!(parse_fixed("05.5"), Some(5.5));
+    }
+
+    #[test]
+    fn delimiters() {
+        let dialect = |delimiter, decimal_comma| Dialect {
+            delimiter: Delimiter::new(delimiter).unwrap(),
+            decimal_comma,
+            ..Dialect::default()
+        };
+
+        for strict in [false, true] {
+            let tab = dialect(b'\t', false);
+            assert_eq!(
+                parse_line("Hamburg\t12.3", strict, tab),
+                Ok(("Hamburg", 12.3))
+            );
+            assert_eq!(
+                parse_line("St. John's\t-5.3", strict, tab),
+                Ok(("St. John's", -5.3))
+            );
+            assert_eq!(
+                parse_line("Hamburg;12.3", strict, tab),
+                Err(MISSING_DELIMITER)
+            );
+
+            let comma = dialect(b',', false);
+            assert_eq!(
+                parse_line("Hamburg,12.3", strict, comma),
+                Ok(("Hamburg", 12.3))
+            );
+            assert_eq!(
+                parse_line("Hamburg,-0.5", strict, comma),
+                Ok(("Hamburg", -0.5))
+            );
+
+            let semicolon_comma = dialect(b';', true);
+            assert_eq!(
+                parse_line("Hamburg;12,3", strict, semicolon_comma),
+                Ok(("Hamburg", 12.3))
+            );
+            assert_eq!(
+                parse_line("Hamburg;12.3", strict, semicolon_comma),
+                Err(INVALID_MEASUREMENT)
+            );
+        }
+
+        assert!(parse_line("Hamburg,12,3", true, dialect(b',', false)).is_err());
+        assert!(Delimiter::new(b'.').is_err());
+        assert!(Delimiter::new(b'7').is_err());
+        assert_eq!("\\t".parse(), Ok(Delimiter(b'\t')));
+        assert_eq!(
+            "tab".parse::<Delimiter>().map(|d| d.to_string()),
+            Ok(String::from("\\t"))
+        );
+        assert!("ab".parse::<Delimiter>().is_err());
+    }
+
+    #[test]
+    fn comma_delimiter_and_decimal_comma() {
+        let dialect = Dialect {
+            delimiter: Delimiter::new(b',').unwrap(),
+            decimal_comma: true,
+            ..Dialect::default()
+        };
+
+        for strict in [false, true] {
+            assert_eq!(
+                parse_line("Hamburg,12,3", strict, dialect),
+                Ok(("Hamburg", 12.3))
+            );
+            assert_eq!(
+                parse_line("Hamburg,-1,5", strict, dialect),
+                Ok(("Hamburg", -1.5))
+            );
+            assert_eq!(
+                parse_line(
+                    "2023-07,Hamburg,12,3",
+                    strict,
+                    Dialect {
+                        key_format: KeyFormat::MonthStation,
+                        ..dialect
+                    }
+                ),
+                Ok(("2023-07,Hamburg", 12.3))
+            );
+            assert_eq!(
+                parse_line("Hamburg", strict, dialect),
+                Err(MISSING_DELIMITER)
+            );
+        }
+
+        // Without a decimal part, the last field is the whole measurement
+        assert_eq!(
+            parse_line("Hamburg,12", false, dialect),
+            Ok(("Hamburg", 12.0))
+        );
+        assert_eq!(
+            parse_line("Hamburg,12", true, dialect),
+            Err(NON_CANONICAL_MEASUREMENT)
+        );
+
+        // A comma in the station name could also be read as a decimal comma: the split is
+        // before the number at the end of the line, but that's only a guess
+        assert_eq!(
+            parse_line("Washington, D.C.,1,2", false, dialect),
+            Ok(("Washington, D.C.", 1.2))
+        );
+        assert_eq!(
+            parse_line("Hamburg,1,2,3", false, dialect),
+            Ok(("Hamburg,1", 2.3))
+        );
+        for line in ["Washington, D.C.,1,2", "Hamburg,1,2,3"] {
+            assert_eq!(
+                parse_line(line, true, dialect),
+                Err(AMBIGUOUS_COMMAS),
+                "{line}"
+            );
+        }
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    fn non_finite_strict() {
+        for measurement in [f32::NAN, f32::INFINITY, f32::NEG_INFINITY] {
+            let result = std::panic::catch_unwind(|| {
+                StationData::new(1.0, true).push(measurement, true);
+            });
+            assert!(result.is_err(), "{measurement} was accepted in strict mode");
+        }
+    }
+
+    #[cfg(feature = "encodings")]
+    #[test]
+    fn encodings() {
+        let mut buf = String::new();
+        for encoding in [Encoding::Utf8, Encoding::Latin1, Encoding::Windows1252] {
+            assert_eq!(
+                encoding.decode(b"Hamburg;12.0", &mut buf),
+                Ok("Hamburg;12.0")
+            );
+        }
+        assert!(Encoding::Utf8.decode(b"Z\xFCrich", &mut buf).is_err());
+        assert_eq!(
+            Encoding::Latin1.decode(b"Z\xFCrich", &mut buf),
+            Ok("Zürich")
+        );
+        assert_eq!(
+            Encoding::Windows1252.decode(b"Z\xFCrich", &mut buf),
+            Ok("Zürich")
+        );
+
+        // The encodings only differ in what 0x80-0x9F mean
+        assert_eq!(Encoding::Latin1.decode(b"\x80", &mut buf), Ok("\u{80}"));
+        assert_eq!(Encoding::Windows1252.decode(b"\x80", &mut buf), Ok("€"));
+        assert_eq!(
+            Encoding::Windows1252.decode_owned(b"\x93S\xE3o Paulo\x94".to_vec()),
+            Ok(String::from("“São Paulo”"))
+        );
+    }
+
+    #[test]
+    fn non_finite_lenient() {
+        let mut data = StationData::new(1.0, false);
+        for measurement in [f32::NAN, f32::INFINITY, f32::NEG_INFINITY, 3.0] {
+            data.push(measurement, false);
+        }
+        assert_eq!((data.min, data.max, data.avg()), (1.0, 3.0, 2.0));
+        assert_eq!((data.cnt, data.skipped), (2, 3));
+    }
+
+    /// Thousands of stations, so plenty of them start probing from an entry which is taken
+    #[test]
+    fn probing() {
+        const ENTRIES: usize = 1 << 14;
+        let mut table = StationTable::new(vec![Entry::EMPTY; ENTRIES], vec![0; 1 << 20]);
+        for i in 0..ENTRIES / 2 {
+            table
+                .get_mut(format!("Station {i}").as_bytes())
+                .unwrap()
+                .push(i as f32, true);
+        }
+        assert_eq!(table.len(), ENTRIES / 2);
+        let mask = ENTRIES - 1;
+        let displaced = table
+            .entries
+            .iter()
+            .enumerate()
+            .filter(|(idx, entry)| entry.hash != 0 && entry.hash as usize & mask != *idx)
+            .count();
+        assert!(displaced > 100, "only {displaced} stations were displaced");
+
+        // Each station is still found by its own name, not just one which landed nearby
+        for i in 0..ENTRIES / 2 {
+            let data = *table.get_mut(format!("Station {i}").as_bytes()).unwrap();
+            assert_eq!((data.cnt, data.min), (1, i as f32));
+        }
+        assert_eq!(table.len(), ENTRIES / 2);
+    }
+
+    /// A table in fixed storage says which of it is full, & keeps what it has
+    #[test]
+    fn fixed_capacity() {
+        let mut entries = [Entry::EMPTY; 8];
+        let mut names = [0; 18];
+        let mut table = StationTable::new(&mut entries[..], &mut names[..]);
+        assert_eq!(table.capacity(), 5);
+
+        table.get_mut(b"Hamburg").unwrap().push(12.0, true);
+        table.get_mut(b"Bulawayo").unwrap().push(8.9, true);
+        // Only 3 of the 18 bytes of names are left
+        assert_eq!(table.get_mut(b"Palembang").unwrap_err(), Full::Names);
+        for name in [&b"A"[..], b"B", b"C"] {
+            table.get_mut(name).unwrap().push(1.0, true);
+        }
+        assert_eq!(table.get_mut(b"D").unwrap_err(), Full::Entries);
+        // Stations already there can still be updated
+        table.get_mut(b"Hamburg").unwrap().push(34.2, true);
+
+        assert_eq!(table.len(), 5);
+        let hamburg = table.get(b"Hamburg").unwrap();
+        assert_eq!((hamburg.min, hamburg.max, hamburg.cnt), (12.0, 34.2, 2));
+        assert!(table.get(b"Palembang").is_none());
+        let mut names: Vec<&[u8]> = table.iter().map(|(name, _)| name).collect();
+        names.sort();
+        assert_eq!(names, [&b"A"[..], b"B", b"Bulawayo", b"C", b"Hamburg"]);
+    }
+
+    /// Stations moved into more entries, & names given more room, are found as before
+    #[test]
+    fn grown_storage() {
+        let mut table = StationTable::new(vec![Entry::EMPTY; 8], vec![0; 8]);
+        table.get_mut(b"Hamburg").unwrap().push(12.0, true);
+        assert_eq!(table.get_mut(b"Bulawayo").unwrap_err(), Full::Names);
+        table.grow_names(|names| names.resize(64, 0));
+        for i in 0..4 {
+            table
+                .get_mut(format!("S{i}").as_bytes())
+                .unwrap()
+                .push(i as f32, true);
+        }
+        assert_eq!(table.get_mut(b"Bulawayo").unwrap_err(), Full::Entries);
+
+        let old = table.rehash(vec![Entry::EMPTY; 16]);
+        assert_eq!(old.len(), 8);
+        table.get_mut(b"Bulawayo").unwrap().push(8.9, true);
+        assert_eq!(table.len(), 6);
+        assert_eq!(table.get(b"Hamburg").map(|data| data.max), Some(12.0));
+        for i in 0..4 {
+            let data = table.get(format!("S{i}").as_bytes()).unwrap();
+            assert_eq!(data.min, i as f32);
+        }
+    }
+}
@@ -0,0 +1,204 @@
+// 1BRC - my take on the 1 Billion Row Challenge
+// Copyright (C) 2024  Charles German <5donuts@pm.me>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Answering repeated queries about an input without aggregating it again.
+//!
+//! Aggregating the input is by far the expensive part, so a [`LoadedDataset`] does it once and
+//! keeps the sorted results around; looking a station up, or listing the stations with a given
+//! prefix, is then a binary search.
+
+use crate::helpers::StationInfo;
+
+/// A statistic stations can be ranked by (see [`LoadedDataset::top_n`])
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "native", derive(clap::ValueEnum))]
+pub enum Stat {
+    Min,
+    Mean,
+    Max,
+
+    /// The number of measurements
+    Count,
+}
+
+/// The aggregated results of an input, ready to be queried.
+///
+/// ```
+/// use onebrc::dataset::{LoadedDataset, Stat};
+///
+/// let dataset = LoadedDataset::new(onebrc::solve(
+///     "Hamburg;12.0\nBulawayo;8.9\nHalifax;-3.2\nHamburg;34.2\n",
+/// )?);
+///
+/// let names = |stations: &[&onebrc::helpers::StationInfo]| -> Vec<String> {
+///     stations.iter().map(|s| s.name().to_owned()).collect()
+/// };
+/// assert_eq!(dataset.get("Hamburg").map(|s| s.count()), Some(2));
+/// assert_eq!(names(&dataset.filter("Ha").iter().collect::<Vec<_>>()), ["Halifax", "Hamburg"]);
+/// assert_eq!(names(&dataset.top_n(Stat::Max, 2)), ["Hamburg", "Bulawayo"]);
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+#[derive(Debug, Clone)]
+pub struct LoadedDataset {
+    /// Sorted by name
+    stations: Vec<StationInfo>,
+}
+
+impl LoadedDataset {
+    /// Wrap the results of a run (or [`solve`](crate::solve)), sorting them if need be
+    pub fn new(mut stations: Vec<StationInfo>) -> Self {
+        if !stations.is_sorted() {
+            stations.sort_unstable();
+        }
+        Self { stations }
+    }
+
+    /// Aggregate the configured input with the configured runner.
+    ///
+    /// The stats of the run are returned alongside the dataset, e.g. to report how long loading
+    /// it took.
+    #[cfg(feature = "native")]
+    pub fn load(
+        config: &crate::config::Config,
+    ) -> Result<(Self, crate::helpers::RunStats), Box<dyn std::error::Error>> {
+        let (stations, stats) = crate::runners::run(config)?;
+        Ok((Self::new(stations), stats))
+    }
+
+    /// The station (or station & month) with exactly this name, if it's in the input
+    pub fn get(&self, name: &str) -> Option<&StationInfo> {
+        self.stations
+            .binary_search_by(|station| station.name().cmp(name))
+            .ok()
+            .map(|idx| &self.stations[idx])
+    }
+
+    /// The stations whose names start with `prefix`, in order
+    pub fn filter(&self, prefix: &str) -> &[StationInfo] {
+        let start = self
+            .stations
+            .partition_point(|station| station.name() < prefix);
+        let len =
+            self.stations[start..].partition_point(|station| station.name().starts_with(prefix));
+        &self.stations[start..start + len]
+    }
+
+    /// The `n` stations with the highest `by`, highest first; ties are in name order
+    pub fn top_n(&self, by: Stat, n: usize) -> Vec<&StationInfo> {
+        let key = |station: &StationInfo| match by {
+            Stat::Min => station.min(),
+            Stat::Mean => station.avg(),
+            Stat::Max => station.max(),
+            Stat::Count => station.count() as f32,
+        };
+        let mut ranked: Vec<&StationInfo> = self.stations.iter().collect();
+        // Stable, so ties stay in name order
+        ranked.sort_by(|a, b| key(b).total_cmp(&key(a)));
+        ranked.truncate(n);
+        ranked
+    }
+
+    /// Every station, in order
+    pub fn stations(&self) -> &[StationInfo] {
+        &self.stations
+    }
+
+    /// A copy of every station, in order
+    pub fn to_sorted_vec(&self) -> Vec<StationInfo> {
+        self.stations.clone()
+    }
+
+    pub fn len(&self) -> usize {
+        self.stations.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.stations.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::output::Format;
+
+    /// The dataset of the runners' test data
+    #[cfg(feature = "native")]
+    fn fixture() -> Result<LoadedDataset, Box<dyn std::error::Error>> {
+        use crate::config::{Config, Setting, Source};
+
+        let dir = tempfile::tempdir()?;
+        let input = dir.path().join("measurements.txt");
+        std::fs::write(&input, crate::runners::tests::TEST_DATA)?;
+        let config = Config {
+            canonical_input: Setting::new(input, Source::Cli),
+            ..Config::default()
+        };
+        let (dataset, _) = LoadedDataset::load(&config)?;
+        Ok(dataset)
+    }
+
+    #[cfg(feature = "native")]
+    #[test]
+    fn queries() -> Result<(), Box<dyn std::error::Error>> {
+        let dataset = fixture()?;
+        assert_eq!(dataset.len(), 5);
+        let names = |stations: &[&StationInfo]| -> Vec<String> {
+            stations.iter().map(|s| s.name().to_owned()).collect()
+        };
+
+        let shimanto = dataset.get("Shimanto").unwrap();
+        assert_eq!((shimanto.count(), shimanto.max()), (4, 74.9));
+        assert!(dataset.get("Shiman").is_none());
+        assert!(dataset.get("Hamburg").is_none());
+
+        let z: Vec<_> = dataset.filter("Z").iter().collect();
+        assert_eq!(names(&z), ["Zverevo"]);
+        let all: Vec<_> = dataset.filter("").iter().collect();
+        assert_eq!(all.len(), 5);
+        assert!(dataset.filter("Hamburg").is_empty());
+        assert!(dataset.filter("\u{10FFFF}").is_empty());
+
+        assert_eq!(
+            names(&dataset.top_n(Stat::Max, 2)),
+            ["Zverevo", "Paidiipalli"]
+        );
+        assert_eq!(names(&dataset.top_n(Stat::Min, 1)), ["Paidiipalli"]);
+        assert_eq!(
+            names(&dataset.top_n(Stat::Count, 3)),
+            ["Shimanto", "Aïn el Mediour", "Glens Falls"]
+        );
+        assert_eq!(dataset.top_n(Stat::Mean, 10).len(), 5);
+        assert!(dataset.top_n(Stat::Mean, 0).is_empty());
+
+        assert_eq!(
+            Format::Text.render(&dataset.to_sorted_vec()),
+            Format::Text.render(&crate::runners::tests::EXPECTED_RESULT)
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn sorts_unsorted_stations() {
+        let stations = ["Zverevo=1.0/1.0/1.0", "Abéché=2.0/2.0/2.0"]
+            .iter()
+            .map(|s| s.parse().unwrap())
+            .collect();
+        let dataset = LoadedDataset::new(stations);
+        assert_eq!(dataset.stations()[0].name(), "Abéché");
+        assert!(dataset.get("Zverevo").is_some());
+    }
+}
@@ -0,0 +1,470 @@
+// 1BRC - my take on the 1 Billion Row Challenge
+// Copyright (C) 2024  Charles German <5donuts@pm.me>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Explain what most likely held a run back (see `--diagnose`).
+//!
+//! Each rule looks at the [`Facts`] gathered about a finished run & may produce a [`Finding`],
+//! with a rough estimate of how much of the run it cost. Facts which nothing measures yet are
+//! `None`: a rule for one can be written (and tested) before its instrumentation lands, and just
+//! stays quiet until then.
+
+use std::fmt::Display;
+use std::ops::Range;
+
+use crate::helpers::{fmt_duration, RunStats, StationInfo};
+use crate::manifest::ChunkEntry;
+use crate::topology::{Storage, Topology, ROTATIONAL_MAX_THREADS};
+use crate::Runner;
+
+/// What's known about a finished run
+#[derive(Debug, Clone)]
+pub struct Facts {
+    pub runner: Runner,
+
+    /// How many threads the run was configured with
+    pub threads: usize,
+    pub topology: Topology,
+
+    /// The size of the input, in bytes
+    pub input_bytes: u64,
+
+    /// How many measurements made it into the results
+    pub rows: u64,
+    pub stations: usize,
+    pub stats: RunStats,
+
+    /// How many measurements each worker aggregated, if the run recorded them
+    pub worker_rows: Option<Vec<u64>>,
+
+    /// The fraction of the input in the page cache before the run, from 0 to 1
+    pub cache_residency: Option<f64>,
+
+    /// How many times the station map had to grow
+    pub table_resizes: Option<u64>,
+
+    /// How many allocations the run made
+    pub allocations: Option<u64>,
+}
+
+impl Facts {
+    /// The facts about a run of `runner` which produced `stations`
+    pub fn new(
+        runner: Runner,
+        threads: usize,
+        topology: Topology,
+        input_bytes: u64,
+        stations: &[StationInfo],
+        stats: RunStats,
+    ) -> Self {
+        Self {
+            runner,
+            threads,
+            topology,
+            input_bytes,
+            rows: stations.iter().map(|s| s.count() as u64).sum(),
+            stations: stations.len(),
+            stats,
+            worker_rows: None,
+            cache_residency: None,
+            table_resizes: None,
+            allocations: None,
+        }
+    }
+
+    /// Record how many measurements each worker aggregated, from the blocks a run recorded (see
+    /// `--chunk-manifest`) & the byte range each worker was given
+    pub fn worker_rows(mut self, chunks: &[ChunkEntry], ranges: &[Range<u64>]) -> Self {
+        let mut rows = vec![0; ranges.len()];
+        for chunk in chunks {
+            if let Some(worker) = ranges.iter().position(|r| r.contains(&chunk.start)) {
+                rows[worker] += chunk.rows;
+            }
+        }
+        self.worker_rows = Some(rows);
+        self
+    }
+}
+
+/// Something which probably slowed a run down
+#[derive(Debug, Clone, PartialEq)]
+pub struct Finding {
+    /// A short name for the kind of problem, e.g. `imbalance`
+    pub rule: &'static str,
+
+    /// A rough estimate of the fraction of the run (from 0 to 1) it cost
+    pub impact: f64,
+
+    /// What was seen & what to try, for a person to read
+    pub message: String,
+}
+
+impl Display for Finding {
+    /// e.g. `[~67%] imbalance: one worker aggregated 3.0x the average ...`
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "[~{:.0}%] {}: {}",
+            self.impact * 100.0,
+            self.rule,
+            self.message
+        )
+    }
+}
+
+/// A check for a single kind of problem
+type Rule = fn(&Facts) -> Option<Finding>;
+
+/// Every rule, in no particular order; findings are ranked by their impact
+const RULES: &[Rule] = &[
+    io_bound,
+    imbalance,
+    oversubscribed,
+    single_threaded,
+    mis_sized,
+    allocations,
+    dirty_data,
+    slow_results,
+];
+
+/// Below this much of the input in the page cache, a run is most likely waiting on the disk
+const COLD_CACHE: f64 = 0.5;
+
+/// The busiest worker doing this many times the average is worth mentioning
+const IMBALANCE: f64 = 1.5;
+
+/// Inputs smaller than this are over too quickly for using more threads to matter
+const SMALL_INPUT: u64 = 64 * 1024 * 1024;
+
+/// A map growing more times than this means it started out far too small
+const RESIZES: u64 = 4;
+
+/// More allocations than this per measurement means one (or more) for most lines
+const ALLOCATIONS_PER_ROW: f64 = 0.5;
+
+/// Building the results taking more than this fraction of the run is worth mentioning
+const SLOW_RESULTS: f64 = 0.2;
+
+/// Run every rule against `facts`, most impactful finding first
+pub fn diagnose(facts: &Facts) -> Vec<Finding> {
+    let mut findings: Vec<Finding> = RULES.iter().filter_map(|rule| rule(facts)).collect();
+    findings.sort_by(|a, b| b.impact.total_cmp(&a.impact));
+    findings
+}
+
+fn io_bound(facts: &Facts) -> Option<Finding> {
+    let resident = facts.cache_residency?;
+    if resident >= COLD_CACHE {
+        return None;
+    }
+    let disk = match facts.topology.storage {
+        Storage::Rotational => " from a spinning disk",
+        _ => "",
+    };
+    Some(Finding {
+        rule: "io-bound",
+        impact: 1.0 - resident,
+        message: format!(
+            "only {:.0}% of the input was in the page cache, so most of it was read{disk}; run \
+             again with a warm cache to time the runner rather than the storage",
+            resident * 100.0
+        ),
+    })
+}
+
+fn imbalance(facts: &Facts) -> Option<Finding> {
+    let rows = facts.worker_rows.as_ref()?;
+    let busiest = *rows.iter().max()?;
+    let mean = rows.iter().sum::<u64>() as f64 / rows.len() as f64;
+    if rows.len() < 2 || busiest == 0 || (busiest as f64) < mean * IMBALANCE {
+        return None;
+    }
+    Some(Finding {
+        rule: "imbalance",
+        // The others sat idle while the busiest worker finished
+        impact: 1.0 - mean / busiest as f64,
+        message: format!(
+            "one worker aggregated {:.1}x the average of {} workers' measurements; long runs of \
+             the same station (or of long names) in part of the input make for uneven ranges, \
+             which a runner sharing out smaller blocks (e.g. sampled-dense) avoids",
+            busiest as f64 / mean,
+            rows.len()
+        ),
+    })
+}
+
+fn oversubscribed(facts: &Facts) -> Option<Finding> {
+    if !facts.runner.is_parallel() {
+        return None;
+    }
+    let useful = facts.topology.max_useful_threads()?;
+    if facts.threads <= useful {
+        return None;
+    }
+    let limit = match facts.topology.storage {
+        Storage::Rotational if useful == ROTATIONAL_MAX_THREADS => {
+            format!("a spinning disk which can only keep {useful} busy")
+        }
+        _ => format!("{useful} cores"),
+    };
+    Some(Finding {
+        rule: "oversubscribed",
+        impact: 1.0 - useful as f64 / facts.threads as f64,
+        message: format!(
+            "{} threads took turns on {limit}; try --threads {useful}",
+            facts.threads
+        ),
+    })
+}
+
+fn single_threaded(facts: &Facts) -> Option<Finding> {
+    let cores = facts.topology.cores?;
+    if facts.runner.is_parallel() || cores < 2 || facts.input_bytes < SMALL_INPUT {
+        return None;
+    }
+    Some(Finding {
+        rule: "single-threaded",
+        impact: 1.0 - 1.0 / cores as f64,
+        message: format!(
+            "the {} runner used 1 of {cores} cores; a parallel runner (e.g. par-mmap) can use \
+             them all",
+            facts.runner
+        ),
+    })
+}
+
+fn mis_sized(facts: &Facts) -> Option<Finding> {
+    let resizes = facts.table_resizes?;
+    if resizes <= RESIZES {
+        return None;
+    }
+    Some(Finding {
+        rule: "mis-sized",
+        // Each resize rehashes everything so far; with few stations that's cheap
+        impact: 0.05,
+        message: format!(
+            "the station map grew {resizes} times to fit {} stations; --station-cache sizes it \
+             up front on later runs",
+            facts.stations
+        ),
+    })
+}
+
+fn allocations(facts: &Facts) -> Option<Finding> {
+    let allocations = facts.allocations?;
+    let per_row = allocations as f64 / facts.rows.max(1) as f64;
+    if per_row <= ALLOCATIONS_PER_ROW {
+        return None;
+    }
+    Some(Finding {
+        rule: "allocations",
+        impact: (per_row / (per_row + 1.0)).min(0.5),
+        message: format!(
+            "{allocations} allocations for {} measurements ({per_row:.1} each); a runner which \
+             borrows names from its buffer (e.g. table or mmap) avoids allocating per line",
+            facts.rows
+        ),
+    })
+}
+
+fn dirty_data(facts: &Facts) -> Option<Finding> {
+    let skipped = facts.stats.skipped.total();
+    let ignored = facts.stats.ignored_non_finite;
+    if skipped + ignored == 0 {
+        return None;
+    }
+    let lines = facts.rows + skipped + ignored;
+    let mut problems = Vec::new();
+    if skipped > 0 {
+        problems.push(facts.stats.skipped.to_string());
+    }
+    if ignored > 0 {
+        problems.push(format!("ignored {ignored} non-finite measurements"));
+    }
+    Some(Finding {
+        rule: "dirty-data",
+        // Malformed lines take the slow path through the parser & the error handling
+        impact: (skipped + ignored) as f64 / lines as f64,
+        message: format!(
+            "{}; cleaning the input keeps every line on the fast path",
+            problems.join(", ")
+        ),
+    })
+}
+
+fn slow_results(facts: &Facts) -> Option<Finding> {
+    let aggregated = facts.stats.timings.aggregated?;
+    let total = facts.stats.timings.total;
+    let building = total.checked_sub(aggregated)?;
+    let fraction = building.as_secs_f64() / total.as_secs_f64();
+    if fraction.is_nan() || fraction <= SLOW_RESULTS {
+        return None;
+    }
+    Some(Finding {
+        rule: "slow-results",
+        impact: fraction,
+        message: format!(
+            "building the sorted results of {} stations took {} of the run; --station-cache \
+             skips sorting stations already seen",
+            facts.stations,
+            fmt_duration(&building)
+        ),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::helpers::Timings;
+    use crate::topology::Filesystem;
+    use std::time::Duration;
+
+    /// The facts about a clean, well-configured run on 8 cores
+    fn facts() -> Facts {
+        Facts {
+            runner: Runner::ParMmap,
+            threads: 8,
+            topology: Topology {
+                cores: Some(8),
+                storage: Storage::SolidState,
+                filesystem: Filesystem::unknown(),
+            },
+            input_bytes: 1 << 30,
+            rows: 1_000_000,
+            stations: 413,
+            stats: RunStats::new(Timings {
+                aggregated: Some(Duration::from_millis(950)),
+                total: Duration::from_secs(1),
+            }),
+            worker_rows: Some(vec![125_000; 8]),
+            cache_residency: Some(1.0),
+            table_resizes: Some(0),
+            allocations: Some(413),
+        }
+    }
+
+    fn rules(facts: &Facts) -> Vec<&'static str> {
+        diagnose(facts).iter().map(|f| f.rule).collect()
+    }
+
+    #[test]
+    fn clean_run() {
+        assert!(diagnose(&facts()).is_empty());
+
+        // Facts nobody measured don't fire anything either
+        let unknown = Facts {
+            worker_rows: None,
+            cache_residency: None,
+            table_resizes: None,
+            allocations: None,
+            ..facts()
+        };
+        assert!(diagnose(&unknown).is_empty());
+    }
+
+    #[test]
+    fn io_bound() {
+        let cold = Facts {
+            cache_residency: Some(0.1),
+            ..facts()
+        };
+        let findings = diagnose(&cold);
+        assert_eq!(findings[0].rule, "io-bound");
+        assert!((findings[0].impact - 0.9).abs() < 1e-9);
+    }
+
+    #[test]
+    fn imbalance() {
+        let mut rows = vec![100_000; 8];
+        rows[3] = 300_000;
+        let uneven = Facts {
+            worker_rows: Some(rows),
+            ..facts()
+        };
+        let findings = diagnose(&uneven);
+        assert_eq!(rules(&uneven), ["imbalance"]);
+        assert!(findings[0].message.contains("2.4x"));
+
+        let single = Facts {
+            worker_rows: Some(vec![1_000_000]),
+            ..facts()
+        };
+        assert!(diagnose(&single).is_empty());
+    }
+
+    #[test]
+    fn threads() {
+        let oversubscribed = Facts {
+            threads: 32,
+            ..facts()
+        };
+        assert_eq!(rules(&oversubscribed), ["oversubscribed"]);
+        assert!(diagnose(&oversubscribed)[0].message.contains("--threads 8"));
+
+        let single = Facts {
+            runner: Runner::AHash,
+            threads: 32,
+            ..facts()
+        };
+        assert_eq!(rules(&single), ["single-threaded"]);
+
+        let small = Facts {
+            input_bytes: 1024,
+            ..single
+        };
+        assert!(diagnose(&small).is_empty());
+    }
+
+    #[test]
+    fn mis_sized_and_allocations() {
+        let slow = Facts {
+            table_resizes: Some(12),
+            allocations: Some(2_000_000),
+            ..facts()
+        };
+        assert_eq!(rules(&slow), ["allocations", "mis-sized"]);
+    }
+
+    #[test]
+    fn dirty_data() {
+        let mut dirty = facts();
+        dirty.stats.skipped.bad_temperature = 250_000;
+        dirty.stats.ignored_non_finite = 10;
+        let findings = diagnose(&dirty);
+        assert_eq!(findings.len(), 1);
+        assert!(findings[0].message.contains("250,000 bad temperature"));
+        assert!(findings[0].message.contains("10 non-finite"));
+        assert!((findings[0].impact - 0.2).abs() < 1e-3);
+    }
+
+    #[test]
+    fn ranked_by_impact() {
+        let mut everything = Facts {
+            threads: 16,
+            cache_residency: Some(0.4),
+            table_resizes: Some(12),
+            ..facts()
+        };
+        everything.stats.timings.aggregated = Some(Duration::from_millis(700));
+        assert_eq!(
+            rules(&everything),
+            ["io-bound", "oversubscribed", "slow-results", "mis-sized"]
+        );
+        assert_eq!(
+            diagnose(&everything)[0].to_string(),
+            "[~60%] io-bound: only 40% of the input was in the page cache, so most of it was \
+             read; run again with a warm cache to time the runner rather than the storage"
+        );
+    }
+}
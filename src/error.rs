@@ -0,0 +1,388 @@
+// 1BRC - my take on the 1 Billion Row Challenge
+// Copyright (C) 2024  Charles German <5donuts@pm.me>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::fmt::Display;
+use std::io;
+use std::str::Utf8Error;
+
+use serde::Serialize;
+
+use crate::aggregate::{
+    INVALID_MEASUREMENT, MISSING_DELIMITER, MISSING_SEPARATOR, NON_CANONICAL_MEASUREMENT,
+    OUT_OF_RANGE,
+};
+
+/// How many bytes of invalid UTF-8 to show in [`ChallengeError::InvalidUtf8`]
+const PREVIEW_LEN: usize = 8;
+
+/// Errors a runner can encounter while solving the challenge
+#[derive(Debug)]
+pub enum ChallengeError {
+    /// An I/O error occurred while reading the input
+    Io(io::Error),
+
+    /// The runner needs something the environment it's in doesn't provide (e.g. the filesystem
+    /// doesn't support mapping the input into memory), which another runner might not need
+    Unsupported {
+        /// What the runner needed, e.g. "mapping the input into memory"
+        what: &'static str,
+        source: io::Error,
+    },
+
+    /// A line in the input was longer than the configured maximum line length
+    LineTooLong {
+        /// Byte offset of the start of the offending line
+        offset: u64,
+        /// The maximum line length, in bytes
+        limit: usize,
+    },
+
+    /// A line in the input couldn't be parsed
+    MalformedLine {
+        /// The (1-based) line number of the offending line
+        line: u64,
+        /// Why the line couldn't be parsed
+        reason: &'static str,
+    },
+
+    /// A line in the input was not valid UTF-8
+    InvalidUtf8 {
+        /// Byte offset of the first invalid byte
+        offset: u64,
+        /// The bytes from the first invalid byte on (up to a few of them)
+        preview: Vec<u8>,
+    },
+
+    /// The input started with a UTF-8 byte-order mark (only an error in strict mode)
+    ByteOrderMark,
+
+    /// No line boundary could be found near where the input was being split into chunks
+    NoLineBoundary {
+        /// Byte offset of the start of the region searched for a newline
+        start: u64,
+        /// Byte offset just past the end of the region searched
+        end: u64,
+    },
+
+    /// More malformed lines were skipped than allowed
+    TooManySkipped {
+        /// The most malformed lines allowed
+        limit: u64,
+        /// The lines skipped, including the one which went over the limit
+        skipped: SkippedLines,
+    },
+}
+
+impl Display for ChallengeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        use ChallengeError::*;
+        match self {
+            Io(e) => write!(f, "I/O error: {e}"),
+            Unsupported { what, source } => write!(f, "{what} failed: {source}"),
+            LineTooLong { offset, limit } => write!(
+                f,
+                "Line at byte offset {offset} is longer than the maximum line length of {limit} bytes"
+            ),
+            MalformedLine { line, reason } => write!(f, "Malformed line {line}: {reason}"),
+            InvalidUtf8 { offset, preview } => {
+                write!(f, "Invalid UTF-8 at byte offset {offset}:")?;
+                for b in preview {
+                    write!(f, " {b:02x}")?;
+                }
+                Ok(())
+            }
+            ByteOrderMark => write!(f, "Input starts with a UTF-8 byte-order mark"),
+            NoLineBoundary { start, end } => write!(
+                f,
+                "No newline between byte offsets {start} and {end}; a line there is longer than the maximum line length"
+            ),
+            TooManySkipped { limit, skipped } => {
+                write!(f, "More than {limit} malformed lines: {}", skipped.breakdown())
+            }
+        }
+    }
+}
+
+impl ChallengeError {
+    /// A short, stable name for the kind of error, for machine-readable output
+    pub fn kind(&self) -> &'static str {
+        use ChallengeError::*;
+        match self {
+            Io(_) => "io",
+            Unsupported { .. } => "unsupported",
+            LineTooLong { .. } => "line-too-long",
+            MalformedLine { .. } => "malformed-line",
+            InvalidUtf8 { .. } => "invalid-utf8",
+            ByteOrderMark => "byte-order-mark",
+            NoLineBoundary { .. } => "no-line-boundary",
+            TooManySkipped { .. } => "too-many-skipped",
+        }
+    }
+
+    /// The byte offset in the input the error is at, if it's at one
+    pub fn offset(&self) -> Option<u64> {
+        use ChallengeError::*;
+        match self {
+            LineTooLong { offset, .. } | InvalidUtf8 { offset, .. } => Some(*offset),
+            ByteOrderMark => Some(0),
+            NoLineBoundary { start, .. } => Some(*start),
+            Io(_) | Unsupported { .. } | MalformedLine { .. } | TooManySkipped { .. } => None,
+        }
+    }
+
+    /// Whether the error is down to the environment the runner is in rather than the input, so
+    /// another runner might succeed where this one failed.
+    ///
+    /// Errors about the input itself never are: any other runner would give an equally wrong
+    /// answer, or fail the same way.
+    pub fn is_environmental(&self) -> bool {
+        matches!(self, Self::Unsupported { .. })
+    }
+
+    /// The (1-based) line number in the input the error is on, if known
+    pub fn line(&self) -> Option<u64> {
+        match self {
+            Self::MalformedLine { line, .. } => Some(*line),
+            _ => None,
+        }
+    }
+
+    /// Locate the invalid UTF-8 in `bytes`, which start at byte offset `offset` in the input
+    pub fn invalid_utf8(offset: u64, bytes: &[u8], error: Utf8Error) -> Self {
+        let start = error.valid_up_to();
+        let end = bytes.len().min(start + PREVIEW_LEN);
+        Self::InvalidUtf8 {
+            offset: offset + start as u64,
+            preview: bytes[start..end].to_vec(),
+        }
+    }
+}
+
+/// Why a malformed line was skipped
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SkipCategory {
+    NoSemicolon,
+    BadTemperature,
+    InvalidUtf8,
+    TooLong,
+    OutOfRange,
+
+    /// Anything else, e.g. a missing month with `--key-format month-station`
+    Other,
+}
+
+impl SkipCategory {
+    /// The category of the malformed line `error` is about, or `None` if it isn't about a
+    /// single line which could be skipped
+    pub fn of(error: &ChallengeError) -> Option<Self> {
+        use ChallengeError::*;
+        match error {
+            MalformedLine { reason, .. } => Some(match *reason {
+                MISSING_SEPARATOR | MISSING_DELIMITER => Self::NoSemicolon,
+                OUT_OF_RANGE => Self::OutOfRange,
+                INVALID_MEASUREMENT | NON_CANONICAL_MEASUREMENT => Self::BadTemperature,
+                _ => Self::Other,
+            }),
+            InvalidUtf8 { .. } => Some(Self::InvalidUtf8),
+            LineTooLong { .. } => Some(Self::TooLong),
+            Io(_)
+            | Unsupported { .. }
+            | ByteOrderMark
+            | NoLineBoundary { .. }
+            | TooManySkipped { .. } => None,
+        }
+    }
+}
+
+impl Display for SkipCategory {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let s = match self {
+            Self::NoSemicolon => "no semicolon",
+            Self::BadTemperature => "bad temperature",
+            Self::InvalidUtf8 => "invalid UTF-8",
+            Self::TooLong => "too long",
+            Self::OutOfRange => "out of range",
+            Self::Other => "other",
+        };
+        write!(f, "{s}")
+    }
+}
+
+/// How many malformed lines were skipped, by [category](SkipCategory).
+///
+/// Malformed lines are only skipped when asked to with `--max-skipped`; otherwise the first one
+/// is an error.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct SkippedLines {
+    pub no_semicolon: u64,
+    pub bad_temperature: u64,
+    pub invalid_utf8: u64,
+    pub too_long: u64,
+    pub out_of_range: u64,
+    pub other: u64,
+}
+
+impl SkippedLines {
+    pub fn total(&self) -> u64 {
+        self.counts().iter().map(|(_, n)| n).sum()
+    }
+
+    /// Skip `lines` lines with the malformation `error` describes, unless that would make more
+    /// than `limit` in all.
+    ///
+    /// Errors which aren't about a malformed line are passed back as they are, as is `error`
+    /// when no lines may be skipped at all.
+    pub fn skip(
+        &mut self,
+        error: ChallengeError,
+        lines: u64,
+        limit: u64,
+    ) -> Result<(), ChallengeError> {
+        let Some(category) = SkipCategory::of(&error) else {
+            return Err(error);
+        };
+        if limit == 0 {
+            return Err(error);
+        }
+
+        *self.count_mut(category) += lines;
+        self.check(limit)
+    }
+
+    /// Fail if more than `limit` lines have been skipped
+    pub fn check(&self, limit: u64) -> Result<(), ChallengeError> {
+        if self.total() > limit {
+            return Err(ChallengeError::TooManySkipped {
+                limit,
+                skipped: *self,
+            });
+        }
+        Ok(())
+    }
+
+    pub fn merge(&mut self, other: &Self) {
+        for (category, n) in other.counts() {
+            *self.count_mut(category) += n;
+        }
+    }
+
+    fn counts(&self) -> [(SkipCategory, u64); 6] {
+        [
+            (SkipCategory::NoSemicolon, self.no_semicolon),
+            (SkipCategory::BadTemperature, self.bad_temperature),
+            (SkipCategory::InvalidUtf8, self.invalid_utf8),
+            (SkipCategory::TooLong, self.too_long),
+            (SkipCategory::OutOfRange, self.out_of_range),
+            (SkipCategory::Other, self.other),
+        ]
+    }
+
+    fn count_mut(&mut self, category: SkipCategory) -> &mut u64 {
+        match category {
+            SkipCategory::NoSemicolon => &mut self.no_semicolon,
+            SkipCategory::BadTemperature => &mut self.bad_temperature,
+            SkipCategory::InvalidUtf8 => &mut self.invalid_utf8,
+            SkipCategory::TooLong => &mut self.too_long,
+            SkipCategory::OutOfRange => &mut self.out_of_range,
+            SkipCategory::Other => &mut self.other,
+        }
+    }
+
+    /// The non-zero counts, most common first, e.g. `1,200 bad temperature, 4 no semicolon`
+    pub fn breakdown(&self) -> String {
+        let mut counts: Vec<_> = self.counts().into_iter().filter(|(_, n)| *n > 0).collect();
+        counts.sort_by_key(|(_, n)| std::cmp::Reverse(*n));
+        counts
+            .iter()
+            .map(|(category, n)| format!("{} {category}", fmt_count(*n)))
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+}
+
+impl Display for SkippedLines {
+    /// e.g. `skipped 1,204 malformed lines: 1,200 bad temperature, 4 no semicolon`
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let total = self.total();
+        let lines = if total == 1 { "line" } else { "lines" };
+        write!(
+            f,
+            "skipped {} malformed {lines}: {}",
+            fmt_count(total),
+            self.breakdown()
+        )
+    }
+}
+
+/// Format a count with thousands separators, e.g. `1,204`
+fn fmt_count(n: u64) -> String {
+    let digits = n.to_string();
+    let mut out = String::with_capacity(digits.len() * 4 / 3);
+    for (i, c) in digits.chars().enumerate() {
+        if i > 0 && (digits.len() - i).is_multiple_of(3) {
+            out.push(',');
+        }
+        out.push(c);
+    }
+    out
+}
+
+impl std::error::Error for ChallengeError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Io(e) | Self::Unsupported { source: e, .. } => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for ChallengeError {
+    fn from(e: io::Error) -> Self {
+        Self::Io(e)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn skipped_summary() {
+        let mut skipped = SkippedLines::default();
+        let malformed = |reason| ChallengeError::MalformedLine { line: 1, reason };
+        skipped.skip(malformed(MISSING_SEPARATOR), 4, 2000).unwrap();
+        skipped
+            .skip(malformed(INVALID_MEASUREMENT), 1200, 2000)
+            .unwrap();
+        assert_eq!(
+            skipped.to_string(),
+            "skipped 1,204 malformed lines: 1,200 bad temperature, 4 no semicolon"
+        );
+
+        // Only malformed lines can be skipped, and only when allowed to
+        let bom = skipped.skip(ChallengeError::ByteOrderMark, 1, 2000);
+        assert!(matches!(bom, Err(ChallengeError::ByteOrderMark)));
+        let strict = SkippedLines::default().skip(malformed(OUT_OF_RANGE), 1, 0);
+        assert!(matches!(strict, Err(ChallengeError::MalformedLine { .. })));
+
+        let err = skipped
+            .skip(malformed(OUT_OF_RANGE), 797, 2000)
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "More than 2000 malformed lines: 1,200 bad temperature, 797 out of range, 4 no semicolon"
+        );
+    }
+}
@@ -0,0 +1,237 @@
+// 1BRC - my take on the 1 Billion Row Challenge
+// Copyright (C) 2024  Charles German <5donuts@pm.me>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Checking a result against the stations it's expected to have, for `--expect-stations`.
+//!
+//! A station in the result but not on the list usually means a corrupted or mistyped name got
+//! through; a station on the list but not in the result just wasn't measured, which is worth
+//! knowing but not wrong.
+
+use std::collections::BTreeSet;
+use std::fmt;
+use std::path::Path;
+
+use crate::helpers::StationInfo;
+use crate::style::{Style, Styler};
+
+/// The names of every station a result may have
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ExpectedStations(BTreeSet<String>);
+
+impl ExpectedStations {
+    /// Load a list of stations, one per line
+    pub fn load(path: &Path) -> Result<Self, Box<dyn std::error::Error>> {
+        let contents = std::fs::read_to_string(path)
+            .map_err(|e| format!("Unable to read {}: {e}", path.display()))?;
+        Ok(Self::parse(&contents))
+    }
+
+    /// Parse a list of stations, one per line. Names are taken exactly as written (only the line
+    /// ending is stripped), and blank lines are skipped.
+    pub fn parse(contents: &str) -> Self {
+        let names = contents
+            .lines()
+            .filter(|line| !line.is_empty())
+            .map(str::to_owned)
+            .collect();
+        Self(names)
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Compare the stations in a result with the list, byte for byte. With
+    /// [`KeyFormat::MonthStation`](crate::aggregate::KeyFormat::MonthStation), each station is
+    /// checked whatever month it was grouped by.
+    pub fn check(&self, stations: &[StationInfo]) -> StationCheck {
+        let seen: BTreeSet<&str> = stations.iter().map(StationInfo::station).collect();
+        StationCheck {
+            unexpected: seen
+                .iter()
+                .filter(|name| !self.0.contains(**name))
+                .map(|name| name.to_string())
+                .collect(),
+            missing: self
+                .0
+                .iter()
+                .filter(|name| !seen.contains(name.as_str()))
+                .cloned()
+                .collect(),
+        }
+    }
+
+    /// The stations in a result, with a [missing](StationInfo::missing) one for each station on
+    /// the list which isn't in it, in order, for `--emit-missing`
+    pub fn with_missing(&self, stations: &[StationInfo]) -> Vec<StationInfo> {
+        let mut all = stations.to_vec();
+        all.extend(
+            self.check(stations)
+                .missing
+                .into_iter()
+                .map(StationInfo::missing),
+        );
+        all.sort_unstable();
+        all
+    }
+}
+
+/// How a result differs from the [`ExpectedStations`], each list in alphabetical order
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct StationCheck {
+    /// Stations in the result which aren't on the list
+    pub unexpected: Vec<String>,
+
+    /// Stations on the list which aren't in the result
+    pub missing: Vec<String>,
+}
+
+impl StationCheck {
+    /// Whether every station in the result is on the list
+    pub fn passed(&self) -> bool {
+        self.unexpected.is_empty()
+    }
+}
+
+impl StationCheck {
+    /// Describe the check, with unexpected stations in red, missing ones in yellow, & a pass in
+    /// green when colouring
+    pub fn render(&self, styler: &Styler) -> String {
+        if self.unexpected.is_empty() && self.missing.is_empty() {
+            return styler
+                .paint(
+                    "Every expected station is in the result, and no others",
+                    Style::Good,
+                )
+                .into_owned();
+        }
+        let mut sections = Vec::new();
+        if !self.unexpected.is_empty() {
+            sections.push((
+                "Unexpected",
+                "in the result but not expected",
+                &self.unexpected,
+                Style::Bad,
+            ));
+        }
+        if !self.missing.is_empty() {
+            sections.push((
+                "Missing",
+                "expected but not in the result",
+                &self.missing,
+                Style::Warning,
+            ));
+        }
+        let mut rendered = String::new();
+        for (i, (label, what, names, style)) in sections.into_iter().enumerate() {
+            if i > 0 {
+                rendered.push('\n');
+            }
+            let heading = format!("{label} stations ({}, {what}):", names.len());
+            rendered.push_str(&styler.paint(&heading, style));
+            for name in names {
+                // Debug-quoted, so stray whitespace or control characters are visible
+                rendered.push_str(&format!("\n  {name:?}"));
+            }
+        }
+        rendered
+    }
+}
+
+impl fmt::Display for StationCheck {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.render(&Styler::PLAIN))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::aggregate::KeyFormat;
+
+    fn result(names: &[&str]) -> Vec<StationInfo> {
+        names
+            .iter()
+            .map(|name| {
+                StationInfo::new(name.to_string(), 1.0, 1.0, 1.0, 1)
+                    .keyed_by(KeyFormat::MonthStation)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn parse() {
+        let expected = ExpectedStations::parse("Hamburg\r\n\nBulawayo\n Palembang\n");
+        assert_eq!(expected.len(), 3);
+        let check = expected.check(&result(&["Bulawayo", "Hamburg", " Palembang"]));
+        assert_eq!(check, StationCheck::default());
+    }
+
+    #[test]
+    fn unexpected_and_missing() {
+        let expected = ExpectedStations::parse("Hamburg\nBulawayo\nPalembang\n");
+        let check = expected.check(&result(&["Bulawayo", "Hamburg", "Hamburgg"]));
+        assert_eq!(check.unexpected, ["Hamburgg"]);
+        assert_eq!(check.missing, ["Palembang"]);
+        assert!(!check.passed());
+        assert_eq!(
+            check.to_string(),
+            "Unexpected stations (1, in the result but not expected):\n  \"Hamburgg\"\n\
+             Missing stations (1, expected but not in the result):\n  \"Palembang\""
+        );
+        // Only the headings are coloured
+        assert_eq!(
+            check.render(&Styler::COLORED),
+            "\x1b[31mUnexpected stations (1, in the result but not expected):\x1b[0m\n  \"Hamburgg\"\n\
+             \x1b[33mMissing stations (1, expected but not in the result):\x1b[0m\n  \"Palembang\""
+        );
+    }
+
+    /// Names are compared byte for byte: neither case nor Unicode normalization is ignored
+    #[test]
+    fn byte_exact() {
+        let expected = ExpectedStations::parse("Zürich\n");
+        let decomposed = "Zu\u{308}rich";
+        let check = expected.check(&result(&[decomposed, "ZÜRICH"]));
+        assert_eq!(check.unexpected, [decomposed, "ZÜRICH"]);
+        assert_eq!(check.missing, ["Zürich"]);
+    }
+
+    #[test]
+    fn with_missing() {
+        let expected = ExpectedStations::parse("Hamburg\nBulawayo\nPalembang\n");
+        let stations = expected.with_missing(&result(&["Bulawayo", "Hamburg"]));
+        let names: Vec<_> = stations.iter().map(StationInfo::name).collect();
+        assert_eq!(names, ["Bulawayo", "Hamburg", "Palembang"]);
+        assert!(!stations[1].is_missing());
+        assert!(stations[2].is_missing());
+    }
+
+    #[test]
+    fn months_are_ignored() {
+        let expected = ExpectedStations::parse("Hamburg\n");
+        let check = expected.check(&result(&["2023-07;Hamburg", "2023-08;Hamburg"]));
+        assert!(check.passed());
+        assert!(check.missing.is_empty());
+        assert_eq!(
+            check.to_string(),
+            "Every expected station is in the result, and no others"
+        );
+    }
+}
@@ -0,0 +1,389 @@
+// 1BRC - my take on the 1 Billion Row Challenge
+// Copyright (C) 2024  Charles German <5donuts@pm.me>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! A C ABI for running the challenge from other languages.
+//!
+//! ```c
+//! OneBrcResult *result = NULL;
+//! if (onebrc_run("measurements.txt", ONEBRC_RUNNER_A_HASH, &result) != ONEBRC_OK) {
+//!     fprintf(stderr, "%s\n", onebrc_last_error_message());
+//!     return 1;
+//! }
+//! for (size_t i = 0; i < onebrc_result_len(result); i++) {
+//!     OneBrcStation station;
+//!     onebrc_result_station(result, i, &station);
+//!     printf("%.*s=%.1f\n", (int)station.name_len, station.name, station.mean);
+//! }
+//! onebrc_free_result(result);
+//! ```
+//!
+//! The header (`include/onebrc.h`) is generated by cbindgen when building with the `ffi` feature.
+
+use std::cell::RefCell;
+use std::ffi::{c_char, c_int, CStr, CString};
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::path::PathBuf;
+
+use crate::config::{Config, Layer};
+use crate::error::ChallengeError;
+use crate::helpers::StationInfo;
+use crate::runners;
+use crate::Runner;
+
+/// The call succeeded
+pub const ONEBRC_OK: c_int = 0;
+/// A required pointer argument was null
+pub const ONEBRC_ERR_NULL_ARGUMENT: c_int = 1;
+/// The runner kind isn't one of the `ONEBRC_RUNNER_*` constants
+pub const ONEBRC_ERR_INVALID_RUNNER: c_int = 2;
+/// The input couldn't be read
+pub const ONEBRC_ERR_IO: c_int = 3;
+/// The input was read, but isn't valid challenge input
+pub const ONEBRC_ERR_INVALID_INPUT: c_int = 4;
+/// An index was past the end of the result
+pub const ONEBRC_ERR_OUT_OF_BOUNDS: c_int = 5;
+/// Anything else, including a panic inside the library
+pub const ONEBRC_ERR_OTHER: c_int = 6;
+
+pub const ONEBRC_RUNNER_BASELINE: c_int = 0;
+pub const ONEBRC_RUNNER_RUSTC_HASH: c_int = 1;
+pub const ONEBRC_RUNNER_A_HASH: c_int = 2;
+pub const ONEBRC_RUNNER_TABLE: c_int = 3;
+pub const ONEBRC_RUNNER_TABLE_PREFETCH: c_int = 4;
+pub const ONEBRC_RUNNER_SAMPLED_DENSE: c_int = 5;
+pub const ONEBRC_RUNNER_CACHED_TABLE: c_int = 6;
+pub const ONEBRC_RUNNER_MMAP: c_int = 7;
+pub const ONEBRC_RUNNER_PAR_MMAP: c_int = 8;
+pub const ONEBRC_RUNNER_SCOPED_THREADS: c_int = 9;
+pub const ONEBRC_RUNNER_MEMCHR: c_int = 10;
+pub const ONEBRC_RUNNER_FIXED_POINT: c_int = 11;
+pub const ONEBRC_RUNNER_BYTE_KEYS: c_int = 12;
+pub const ONEBRC_RUNNER_INLINE_TABLE: c_int = 13;
+pub const ONEBRC_RUNNER_PERFECT_HASH: c_int = 14;
+/// Only available in builds with the `io-uring` feature
+pub const ONEBRC_RUNNER_IO_URING: c_int = 15;
+/// Only available in builds with the `tokio` feature
+pub const ONEBRC_RUNNER_TOKIO: c_int = 16;
+pub const ONEBRC_RUNNER_PIPELINED: c_int = 17;
+pub const ONEBRC_RUNNER_CROSSBEAM: c_int = 18;
+pub const ONEBRC_RUNNER_BRANCHLESS: c_int = 19;
+pub const ONEBRC_RUNNER_SIMD: c_int = 20;
+pub const ONEBRC_RUNNER_SWAR: c_int = 21;
+pub const ONEBRC_RUNNER_UNCHECKED: c_int = 22;
+pub const ONEBRC_RUNNER_PRESIZED: c_int = 23;
+pub const ONEBRC_RUNNER_ENTRY_REF: c_int = 24;
+pub const ONEBRC_RUNNER_FADVISE: c_int = 25;
+/// Only available in builds with the `direct-io` feature
+pub const ONEBRC_RUNNER_DIRECT_IO: c_int = 26;
+pub const ONEBRC_RUNNER_WORK_QUEUE: c_int = 27;
+pub const ONEBRC_RUNNER_COMPACT_KEYS: c_int = 28;
+pub const ONEBRC_RUNNER_HASH_KEYS: c_int = 29;
+pub const ONEBRC_RUNNER_DOUBLE_BUFFERED: c_int = 30;
+/// Only available in builds with the `affinity` feature
+pub const ONEBRC_RUNNER_THREAD_PER_CORE: c_int = 31;
+
+/// The (opaque) result of a successful [`onebrc_run`]
+pub struct OneBrcResult {
+    stations: Vec<StationInfo>,
+}
+
+/// A single station from a [`OneBrcResult`].
+///
+/// `name` is *not* NUL-terminated; it's `name_len` bytes of UTF-8 which remain valid until the
+/// result is freed.
+#[repr(C)]
+#[derive(Debug)]
+pub struct OneBrcStation {
+    pub name: *const c_char,
+    pub name_len: usize,
+    pub min: f32,
+    pub mean: f32,
+    pub max: f32,
+    pub count: u32,
+}
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+/// Remember `message` as the last error on this thread & return `code`
+fn fail(code: c_int, message: impl ToString) -> c_int {
+    // Interior NULs would truncate the message, so replace them
+    let message = message.to_string().replace('\0', "\u{FFFD}");
+    let message = CString::new(message).expect("NULs were replaced");
+    LAST_ERROR.with(|last| *last.borrow_mut() = Some(message));
+    code
+}
+
+fn runner_for(kind: c_int) -> Option<Runner> {
+    match kind {
+        ONEBRC_RUNNER_BASELINE => Some(Runner::Baseline),
+        ONEBRC_RUNNER_RUSTC_HASH => Some(Runner::RustcHash),
+        ONEBRC_RUNNER_A_HASH => Some(Runner::AHash),
+        ONEBRC_RUNNER_TABLE => Some(Runner::Table),
+        ONEBRC_RUNNER_TABLE_PREFETCH => Some(Runner::TablePrefetch),
+        ONEBRC_RUNNER_SAMPLED_DENSE => Some(Runner::SampledDense),
+        ONEBRC_RUNNER_CACHED_TABLE => Some(Runner::CachedTable),
+        ONEBRC_RUNNER_MMAP => Some(Runner::Mmap),
+        ONEBRC_RUNNER_PAR_MMAP => Some(Runner::ParMmap),
+        ONEBRC_RUNNER_SCOPED_THREADS => Some(Runner::ScopedThreads),
+        ONEBRC_RUNNER_MEMCHR => Some(Runner::Memchr),
+        ONEBRC_RUNNER_FIXED_POINT => Some(Runner::FixedPoint),
+        ONEBRC_RUNNER_BYTE_KEYS => Some(Runner::ByteKeys),
+        ONEBRC_RUNNER_INLINE_TABLE => Some(Runner::InlineTable),
+        ONEBRC_RUNNER_PERFECT_HASH => Some(Runner::PerfectHash),
+        ONEBRC_RUNNER_PIPELINED => Some(Runner::Pipelined),
+        ONEBRC_RUNNER_CROSSBEAM => Some(Runner::Crossbeam),
+        ONEBRC_RUNNER_BRANCHLESS => Some(Runner::Branchless),
+        ONEBRC_RUNNER_SIMD => Some(Runner::Simd),
+        ONEBRC_RUNNER_SWAR => Some(Runner::Swar),
+        ONEBRC_RUNNER_UNCHECKED => Some(Runner::Unchecked),
+        ONEBRC_RUNNER_PRESIZED => Some(Runner::Presized),
+        ONEBRC_RUNNER_ENTRY_REF => Some(Runner::EntryRef),
+        ONEBRC_RUNNER_FADVISE => Some(Runner::Fadvise),
+        ONEBRC_RUNNER_WORK_QUEUE => Some(Runner::WorkQueue),
+        ONEBRC_RUNNER_COMPACT_KEYS => Some(Runner::CompactKeys),
+        ONEBRC_RUNNER_HASH_KEYS => Some(Runner::HashKeys),
+        ONEBRC_RUNNER_DOUBLE_BUFFERED => Some(Runner::DoubleBuffered),
+        #[cfg(feature = "io-uring")]
+        ONEBRC_RUNNER_IO_URING => Some(Runner::IoUring),
+        #[cfg(feature = "tokio")]
+        ONEBRC_RUNNER_TOKIO => Some(Runner::Tokio),
+        #[cfg(feature = "direct-io")]
+        ONEBRC_RUNNER_DIRECT_IO => Some(Runner::DirectIo),
+        #[cfg(feature = "affinity")]
+        ONEBRC_RUNNER_THREAD_PER_CORE => Some(Runner::ThreadPerCore),
+        _ => None,
+    }
+}
+
+#[cfg(unix)]
+fn path_from(path: &CStr) -> Option<PathBuf> {
+    use std::os::unix::ffi::OsStrExt;
+    Some(std::ffi::OsStr::from_bytes(path.to_bytes()).into())
+}
+
+#[cfg(not(unix))]
+fn path_from(path: &CStr) -> Option<PathBuf> {
+    path.to_str().ok().map(PathBuf::from)
+}
+
+/// Solve the challenge for the input at `path` (a NUL-terminated path) with the given runner.
+///
+/// On success, returns [`ONEBRC_OK`] and stores a result in `*out` which must be released with
+/// [`onebrc_free_result`]. Otherwise, returns one of the `ONEBRC_ERR_*` codes and leaves `*out`
+/// untouched; see [`onebrc_last_error_message`] for the details.
+///
+/// # Safety
+/// `path` must be a valid NUL-terminated string and `out` must be valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn onebrc_run(
+    path: *const c_char,
+    runner_kind: c_int,
+    out: *mut *mut OneBrcResult,
+) -> c_int {
+    if path.is_null() || out.is_null() {
+        return fail(ONEBRC_ERR_NULL_ARGUMENT, "path and out must not be null");
+    }
+    let Some(runner) = runner_for(runner_kind) else {
+        return fail(
+            ONEBRC_ERR_INVALID_RUNNER,
+            format!("Unknown runner kind {runner_kind}"),
+        );
+    };
+    let Some(path) = path_from(CStr::from_ptr(path)) else {
+        return fail(ONEBRC_ERR_IO, "path is not valid UTF-8");
+    };
+
+    let result = catch_unwind(AssertUnwindSafe(|| {
+        let cli = Layer {
+            runner: Some(runner),
+            ..Default::default()
+        };
+        let config = Config::resolve(&path, cli, Layer::default(), Layer::default());
+        runners::run(&config)
+    }));
+
+    match result {
+        Ok(Ok((stations, _))) => {
+            *out = Box::into_raw(Box::new(OneBrcResult { stations }));
+            ONEBRC_OK
+        }
+        Ok(Err(e)) => {
+            let code = match e.downcast_ref::<ChallengeError>() {
+                Some(ChallengeError::Io(_)) => ONEBRC_ERR_IO,
+                Some(_) => ONEBRC_ERR_INVALID_INPUT,
+                None if e.is::<std::io::Error>() => ONEBRC_ERR_IO,
+                None => ONEBRC_ERR_OTHER,
+            };
+            fail(code, e)
+        }
+        Err(_) => fail(ONEBRC_ERR_OTHER, "panicked while solving the challenge"),
+    }
+}
+
+/// The number of stations in `result`, or 0 if it is null
+///
+/// # Safety
+/// `result` must be null or a result returned by [`onebrc_run`] which hasn't been freed.
+#[no_mangle]
+pub unsafe extern "C" fn onebrc_result_len(result: *const OneBrcResult) -> usize {
+    result.as_ref().map_or(0, |r| r.stations.len())
+}
+
+/// Store the `idx`-th station (in alphabetical order) of `result` in `*out`
+///
+/// # Safety
+/// `result` must be a result returned by [`onebrc_run`] which hasn't been freed, and `out` must
+/// be valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn onebrc_result_station(
+    result: *const OneBrcResult,
+    idx: usize,
+    out: *mut OneBrcStation,
+) -> c_int {
+    if result.is_null() || out.is_null() {
+        return fail(ONEBRC_ERR_NULL_ARGUMENT, "result and out must not be null");
+    }
+    let result = &*result;
+    let Some(station) = result.stations.get(idx) else {
+        return fail(
+            ONEBRC_ERR_OUT_OF_BOUNDS,
+            format!("No station at index {idx}"),
+        );
+    };
+
+    *out = OneBrcStation {
+        name: station.name().as_ptr().cast(),
+        name_len: station.name().len(),
+        min: station.min(),
+        mean: station.avg(),
+        max: station.max(),
+        count: station.count(),
+    };
+    ONEBRC_OK
+}
+
+/// Release a result returned by [`onebrc_run`]; does nothing if it is null
+///
+/// # Safety
+/// `result` must be null or a result returned by [`onebrc_run`] which hasn't been freed.
+#[no_mangle]
+pub unsafe extern "C" fn onebrc_free_result(result: *mut OneBrcResult) {
+    if !result.is_null() {
+        drop(Box::from_raw(result));
+    }
+}
+
+/// A description of the last error on the calling thread, or null if there hasn't been one.
+///
+/// The string remains valid until the next failing call on the same thread.
+#[no_mangle]
+pub extern "C" fn onebrc_last_error_message() -> *const c_char {
+    LAST_ERROR.with(|last| {
+        last.borrow()
+            .as_ref()
+            .map_or(std::ptr::null(), |message| message.as_ptr())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::runners::tests::{EXPECTED_RESULT, TEST_DATA};
+    use std::ptr;
+
+    #[test]
+    fn round_trip() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = tempfile::tempdir()?;
+        let input = dir.path().join("measurements.txt");
+        std::fs::write(&input, TEST_DATA)?;
+        let path = CString::new(input.to_str().unwrap())?;
+
+        // The runners behind features aren't in every build
+        let kinds = (ONEBRC_RUNNER_BASELINE..=ONEBRC_RUNNER_PERFECT_HASH).chain([
+            ONEBRC_RUNNER_PIPELINED,
+            ONEBRC_RUNNER_CROSSBEAM,
+            ONEBRC_RUNNER_BRANCHLESS,
+            ONEBRC_RUNNER_SIMD,
+            ONEBRC_RUNNER_SWAR,
+            ONEBRC_RUNNER_UNCHECKED,
+            ONEBRC_RUNNER_PRESIZED,
+            ONEBRC_RUNNER_ENTRY_REF,
+            ONEBRC_RUNNER_FADVISE,
+            ONEBRC_RUNNER_WORK_QUEUE,
+            ONEBRC_RUNNER_COMPACT_KEYS,
+            ONEBRC_RUNNER_HASH_KEYS,
+            ONEBRC_RUNNER_DOUBLE_BUFFERED,
+        ]);
+        for kind in kinds {
+            let mut result = ptr::null_mut();
+            let code = unsafe { onebrc_run(path.as_ptr(), kind, &mut result) };
+            assert_eq!(code, ONEBRC_OK, "runner kind {kind}");
+
+            assert_eq!(unsafe { onebrc_result_len(result) }, EXPECTED_RESULT.len());
+            for (idx, expected) in EXPECTED_RESULT.iter().enumerate() {
+                let mut station = OneBrcStation {
+                    name: ptr::null(),
+                    name_len: 0,
+                    min: 0.0,
+                    mean: 0.0,
+                    max: 0.0,
+                    count: 0,
+                };
+                let code = unsafe { onebrc_result_station(result, idx, &mut station) };
+                assert_eq!(code, ONEBRC_OK);
+
+                let name = unsafe {
+                    std::slice::from_raw_parts(station.name.cast::<u8>(), station.name_len)
+                };
+                assert_eq!(std::str::from_utf8(name)?, expected.name());
+                assert_eq!(station.min, expected.min());
+                assert_eq!(station.max, expected.max());
+                assert!((station.mean - expected.avg()).abs() < 1e-4, "{station:?}");
+                assert_eq!(station.count, expected.count());
+            }
+
+            let mut station = unsafe { std::mem::zeroed() };
+            let code =
+                unsafe { onebrc_result_station(result, EXPECTED_RESULT.len(), &mut station) };
+            assert_eq!(code, ONEBRC_ERR_OUT_OF_BOUNDS);
+
+            unsafe { onebrc_free_result(result) };
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn errors() {
+        let mut result = ptr::null_mut();
+
+        let missing = CString::new("/definitely/not/a/real/file").unwrap();
+        let code = unsafe { onebrc_run(missing.as_ptr(), ONEBRC_RUNNER_A_HASH, &mut result) };
+        assert_eq!(code, ONEBRC_ERR_IO);
+        assert!(result.is_null());
+        let message = unsafe { CStr::from_ptr(onebrc_last_error_message()) };
+        assert!(!message.to_bytes().is_empty());
+
+        let code = unsafe { onebrc_run(missing.as_ptr(), 42, &mut result) };
+        assert_eq!(code, ONEBRC_ERR_INVALID_RUNNER);
+        let message = unsafe { CStr::from_ptr(onebrc_last_error_message()) };
+        assert!(message.to_str().unwrap().contains("42"));
+
+        let code = unsafe { onebrc_run(ptr::null(), ONEBRC_RUNNER_A_HASH, &mut result) };
+        assert_eq!(code, ONEBRC_ERR_NULL_ARGUMENT);
+    }
+}
@@ -0,0 +1,135 @@
+// 1BRC - my take on the 1 Billion Row Challenge
+// Copyright (C) 2024  Charles German <5donuts@pm.me>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Cheap checks that an input file hasn't changed between invocations
+
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::Path;
+use std::time::UNIX_EPOCH;
+
+use serde::{Deserialize, Serialize};
+
+/// How much of the start of the file is hashed into its [`Fingerprint`]
+const HEAD_LEN: u64 = 64 * 1024;
+
+/// Identify the contents of an input file without reading the whole thing.
+///
+/// This is the length & modification time of the file along with a hash of its first
+/// [`HEAD_LEN`] bytes. It's not bulletproof, but it catches a regenerated or swapped input,
+/// which is what matters when reusing results from a previous invocation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Fingerprint {
+    pub len: u64,
+    pub mtime_nanos: u128,
+    pub head_hash: u64,
+}
+
+impl Fingerprint {
+    pub fn of(path: &Path) -> io::Result<Self> {
+        let f = File::open(path)?;
+        let metadata = f.metadata()?;
+        let mtime_nanos = metadata
+            .modified()?
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_nanos())
+            .unwrap_or(0);
+
+        let mut head = Vec::new();
+        f.take(HEAD_LEN).read_to_end(&mut head)?;
+
+        Ok(Self {
+            len: metadata.len(),
+            mtime_nanos,
+            head_hash: fnv1a(&head),
+        })
+    }
+}
+
+/// A stable key identifying a (canonical) path, even if it isn't valid UTF-8
+pub fn path_key(path: &Path) -> u64 {
+    fnv1a(path.as_os_str().as_encoded_bytes())
+}
+
+/// Hash some bytes with 64-bit FNV-1a.
+///
+/// Unlike the standard library's hashers, this is guaranteed to be stable across builds,
+/// so it is safe to persist.
+pub fn fnv1a(bytes: &[u8]) -> u64 {
+    fnv1a_extend(FNV_OFFSET_BASIS, bytes)
+}
+
+/// Hash the whole contents of a file with [`fnv1a`], without reading it all into memory at once
+pub fn fnv1a_file(path: &Path) -> io::Result<u64> {
+    let mut f = File::open(path)?;
+    let mut buf = vec![0; 64 * 1024];
+    let mut hash = FNV_OFFSET_BASIS;
+    loop {
+        match f.read(&mut buf) {
+            Ok(0) => return Ok(hash),
+            Ok(n) => hash = fnv1a_extend(hash, &buf[..n]),
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+const FNV_OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+
+/// Continue an [`fnv1a`] hash with some more bytes
+fn fnv1a_extend(hash: u64, bytes: &[u8]) -> u64 {
+    const PRIME: u64 = 0x0000_0100_0000_01b3;
+
+    bytes
+        .iter()
+        .fold(hash, |hash, &b| (hash ^ b as u64).wrapping_mul(PRIME))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fnv1a_known_values() {
+        assert_eq!(fnv1a(b""), 0xcbf2_9ce4_8422_2325);
+        assert_eq!(fnv1a(b"a"), 0xaf63_dc4c_8601_ec8c);
+    }
+
+    #[test]
+    fn whole_file() -> io::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("measurements.txt");
+        // Bigger than the buffer it's read through, so the hash spans several reads
+        let contents = "Hamburg;12.0\n".repeat(10_000);
+        std::fs::write(&path, &contents)?;
+
+        assert_eq!(fnv1a_file(&path)?, fnv1a(contents.as_bytes()));
+        Ok(())
+    }
+
+    #[test]
+    fn detects_changes() -> io::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("measurements.txt");
+        std::fs::write(&path, "Hamburg;12.0\n")?;
+        let before = Fingerprint::of(&path)?;
+        assert_eq!(before, Fingerprint::of(&path)?);
+
+        std::fs::write(&path, "Hamburg;13.0\n")?;
+        assert_ne!(before, Fingerprint::of(&path)?);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,295 @@
+// 1BRC - my take on the 1 Billion Row Challenge
+// Copyright (C) 2024  Charles German <5donuts@pm.me>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//! Generate inputs laid out in patterns which stress runners in different ways, along with their
+//! exact expected results.
+//!
+//! Each station's measurements alternate between two values either side of a base temperature,
+//! both multiples of 0.5. Its results then follow from how many lines it's on alone, so they're
+//! computed rather than aggregated, and they're exact: sums of multiples of 0.5 are exact in an
+//! `f32` up to 2^23, and the mean never lands on a tie when it's rounded. Runners which sum in
+//! `f32` drift from these results once a station's sum grows past that, which is one of the
+//! things the patterns are for.
+
+use std::io::{self, Write};
+
+use crate::helpers::StationInfo;
+
+/// How the lines of a generated input are laid out
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "native", derive(clap::ValueEnum))]
+pub enum Pattern {
+    /// A single station on every line, e.g. for the consecutive-station fast path
+    Repeated,
+
+    /// Every station in turn, over & over, so no two lines in a row are for the same station
+    #[default]
+    RoundRobin,
+
+    /// All of each station's lines together, one station after another
+    Sorted,
+}
+
+/// A seeded, reproducible input of `rows` lines laid out in a [`Pattern`]
+///
+/// ```
+/// use onebrc::generate::{Generator, Pattern};
+///
+/// let generator = Generator::new(Pattern::Sorted, 6, 2, 1);
+/// let mut input = Vec::new();
+/// generator.write(&mut input)?;
+///
+/// let expected = generator.expected();
+/// assert_eq!(onebrc::solve(std::str::from_utf8(&input)?)?, expected);
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Generator {
+    pub pattern: Pattern,
+    pub rows: u64,
+    pub stations: usize,
+    pub seed: u64,
+}
+
+/// A station's measurements alternate between `base + amplitude` & `base - amplitude`, in tenths
+#[derive(Debug, Clone, Copy)]
+struct Station {
+    base: i32,
+    amplitude: i32,
+}
+
+impl Generator {
+    /// A generator of `rows` lines over `stations` stations (at least one); a repeated pattern
+    /// only ever uses the first
+    pub fn new(pattern: Pattern, rows: u64, stations: usize, seed: u64) -> Self {
+        Self {
+            pattern,
+            rows,
+            stations: stations.max(1),
+            seed,
+        }
+    }
+
+    /// Write every line of the input
+    pub fn write<W: Write>(&self, out: W) -> io::Result<()> {
+        let mut out = io::BufWriter::new(out);
+
+        // Each station only ever has two lines, so they're rendered once & copied from then on
+        let lines: Vec<[Vec<u8>; 2]> = self
+            .stations()
+            .iter()
+            .enumerate()
+            .map(|(id, station)| {
+                let line = |tenths: i32| {
+                    let sign = if tenths < 0 { "-" } else { "" };
+                    let (whole, frac) = (tenths.abs() / 10, tenths.abs() % 10);
+                    format!("{};{sign}{whole}.{frac}\n", name(id)).into_bytes()
+                };
+                [
+                    line(station.base + station.amplitude),
+                    line(station.base - station.amplitude),
+                ]
+            })
+            .collect();
+
+        match self.pattern {
+            Pattern::RoundRobin => {
+                let stations = self.stations as u64;
+                for row in 0..self.rows {
+                    let (round, id) = (row / stations, (row % stations) as usize);
+                    out.write_all(&lines[id][(round % 2) as usize])?;
+                }
+            }
+            Pattern::Repeated | Pattern::Sorted => {
+                for (id, [high, low]) in lines.iter().enumerate() {
+                    let count = self.count(id);
+                    // A run of the same station is just its pair of lines, repeated
+                    let pair = [high.as_slice(), low.as_slice()].concat();
+                    for _ in 0..count / 2 {
+                        out.write_all(&pair)?;
+                    }
+                    if count % 2 == 1 {
+                        out.write_all(high)?;
+                    }
+                }
+            }
+        }
+        out.flush()
+    }
+
+    /// The exact result of aggregating the input, sorted by station
+    pub fn expected(&self) -> Vec<StationInfo> {
+        let tenths = |tenths: i64| tenths as f32 / 10.0;
+        let mut expected: Vec<StationInfo> = self
+            .stations()
+            .iter()
+            .enumerate()
+            .filter_map(|(id, station)| {
+                let count = self.count(id);
+                if count == 0 {
+                    return None;
+                }
+
+                // Every pair of lines cancels out the amplitude, so only an odd one out adds it
+                let (base, amplitude) = (station.base as i64, station.amplitude as i64);
+                let high = base + amplitude;
+                let low = if count > 1 { base - amplitude } else { high };
+                let sum = count as i64 * base + (count % 2) as i64 * amplitude;
+                // The sum is a multiple of 0.5, so exact, & dividing it the same way a runner
+                // does gives the same bits
+                let mean = tenths(sum) / count as f32;
+                Some(StationInfo::new(
+                    name(id),
+                    tenths(low),
+                    tenths(high),
+                    mean,
+                    count as u32,
+                ))
+            })
+            .collect();
+        expected.sort_unstable();
+        expected
+    }
+
+    /// The base & amplitude of each station, drawn from the seed
+    fn stations(&self) -> Vec<Station> {
+        let mut state = self.seed ^ 0x9e37_79b9_7f4a_7c15;
+        // xorshift64, as in the benchmarks; the state must never be zero
+        if state == 0 {
+            state = 1;
+        }
+        let mut next = move || {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state
+        };
+
+        let stations = match self.pattern {
+            Pattern::Repeated => 1,
+            Pattern::RoundRobin | Pattern::Sorted => self.stations,
+        };
+        (0..stations)
+            .map(|_| {
+                // Multiples of 5 tenths, with the base in -50.0..=50.0 & the amplitude in
+                // 0.5..=49.5, so every measurement is in -99.5..=99.5
+                let base = (next() % 201) as i32 * 5 - 500;
+                let amplitude = (next() % 99) as i32 * 5 + 5;
+                Station { base, amplitude }
+            })
+            .collect()
+    }
+
+    /// How many lines the station with the given id is on
+    fn count(&self, id: usize) -> u64 {
+        match self.pattern {
+            Pattern::Repeated => self.rows,
+            Pattern::RoundRobin | Pattern::Sorted => {
+                let stations = self.stations as u64;
+                self.rows / stations + u64::from((id as u64) < self.rows % stations)
+            }
+        }
+    }
+}
+
+/// The name of the station with the given id
+fn name(id: usize) -> String {
+    format!("Station {id:04}")
+}
+
+#[cfg(all(test, feature = "native"))]
+mod tests {
+    use super::*;
+    use crate::config::{Config, Source};
+    use crate::output::Format;
+    use crate::runners;
+    use crate::Runner;
+
+    const PATTERNS: [Pattern; 3] = [Pattern::Repeated, Pattern::RoundRobin, Pattern::Sorted];
+
+    fn baseline(input: &[u8]) -> Vec<StationInfo> {
+        let config = Config::default().with_runner(Runner::Baseline, Source::Cli);
+        let (stations, _) = runners::run_with(io::Cursor::new(input), &config).unwrap();
+        stations
+    }
+
+    #[test]
+    fn verified_by_baseline() -> io::Result<()> {
+        for pattern in PATTERNS {
+            let generator = Generator::new(pattern, 100_000, 413, 7);
+            let mut input = Vec::new();
+            generator.write(&mut input)?;
+
+            assert_eq!(input.iter().filter(|&&b| b == b'\n').count(), 100_000);
+            assert_eq!(
+                Format::Text.render(&baseline(&input)),
+                Format::Text.render(&generator.expected()),
+                "{pattern:?}"
+            );
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn few_rows() -> io::Result<()> {
+        // Including stations on no lines, one line, & an odd number of lines
+        for pattern in PATTERNS {
+            for rows in [0, 1, 2, 3, 4, 7, 11] {
+                let generator = Generator::new(pattern, rows, 4, 3);
+                let mut input = Vec::new();
+                generator.write(&mut input)?;
+
+                assert_eq!(
+                    baseline(&input),
+                    generator.expected(),
+                    "{pattern:?} with {rows} rows"
+                );
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn seeded() -> io::Result<()> {
+        let generate = |seed| -> io::Result<Vec<u8>> {
+            let mut input = Vec::new();
+            Generator::new(Pattern::RoundRobin, 1000, 10, seed).write(&mut input)?;
+            Ok(input)
+        };
+
+        assert_eq!(generate(1)?, generate(1)?);
+        assert_ne!(generate(1)?, generate(2)?);
+        Ok(())
+    }
+
+    #[test]
+    fn layouts() -> io::Result<()> {
+        let stations = |pattern| -> io::Result<Vec<String>> {
+            let mut input = Vec::new();
+            Generator::new(pattern, 6, 3, 0).write(&mut input)?;
+            Ok(String::from_utf8(input)
+                .unwrap()
+                .lines()
+                .map(|line| line.split_once(';').unwrap().0.to_owned())
+                .collect())
+        };
+
+        let [a, b, c] = [0, 1, 2].map(name);
+        let [a, b, c] = [a.as_str(), b.as_str(), c.as_str()];
+        assert_eq!(stations(Pattern::Repeated)?, [a, a, a, a, a, a]);
+        assert_eq!(stations(Pattern::RoundRobin)?, [a, b, c, a, b, c]);
+        assert_eq!(stations(Pattern::Sorted)?, [a, a, b, b, c, c]);
+        Ok(())
+    }
+}
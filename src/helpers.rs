@@ -15,22 +15,144 @@
 
 use std::cmp::{Eq, Ord, PartialEq, PartialOrd};
 use std::fmt::Display;
+#[cfg(feature = "native")]
 use std::io;
-use std::time::Duration;
+#[cfg(feature = "native")]
+use std::io::Write;
+#[cfg(feature = "native")]
+use std::path::Path;
+use std::str::FromStr;
+#[cfg(feature = "native")]
+use std::sync::atomic::{AtomicBool, Ordering};
+#[cfg(feature = "native")]
+use std::time::{Duration, Instant};
 
-/// A helper type to represent min/max/avg data for a station
-#[derive(Debug)]
-pub struct StationInfo((String, f32, f32, f32));
+use crate::aggregate::{is_month, Extents, KeyFormat};
+#[cfg(feature = "native")]
+use crate::config::Config;
+#[cfg(feature = "native")]
+use crate::error::SkippedLines;
+#[cfg(feature = "native")]
+use crate::temp::TEMP_FILES;
+#[cfg(feature = "native")]
+use crate::Runner;
+
+/// A helper type to represent min/max/avg data (and the number of measurements) for a station.
+///
+/// It can be parsed back from its [displayed](Display) form, e.g. to write the expected results
+/// of a test; the number of measurements isn't part of that, so is always 0.
+///
+/// A station can also be [missing](StationInfo::missing): expected, but with no measurements at
+/// all, so no stats to show. Runners never produce these; they're only added to the output when
+/// asked for, e.g. with `--emit-missing`.
+///
+/// ```
+/// use onebrc::helpers::StationInfo;
+///
+/// let hamburg: StationInfo = "Hamburg=12.0/23.1/34.2".parse()?;
+/// assert_eq!(hamburg.name(), "Hamburg");
+/// assert_eq!(hamburg.avg(), 23.1);
+/// assert_eq!(hamburg.to_string(), "Hamburg=12.0/23.1/34.2");
+///
+/// let monthly: StationInfo = "Hamburg (2023-07)=12.0/12.0/12.0".parse()?;
+/// assert_eq!(monthly.name(), "2023-07;Hamburg");
+/// assert_eq!(monthly.month(), Some("2023-07"));
+///
+/// let expected: Vec<StationInfo> = ["Bulawayo=8.9/8.9/8.9", "Hamburg=12.0/23.1/34.2"]
+///     .iter()
+///     .map(|s| s.parse())
+///     .collect::<Result<_, _>>()?;
+/// let actual = onebrc::solve("Hamburg;12.0\nBulawayo;8.9\nHamburg;34.2\n")?;
+/// assert!(actual.iter().zip(&expected).all(|(a, e)| a.eq_rounded(e)));
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+#[derive(Debug, Clone)]
+pub struct StationInfo((String, f32, f32, f32, u32), Option<Extents>, bool, bool);
+
+/// The length of the `YYYY-MM` month at the start of a month-station key
+const MONTH_LEN: usize = 7;
 
 impl StationInfo {
-    pub fn new(name: String, min: f32, max: f32, avg: f32) -> Self {
-        Self((name, min, max, avg))
+    pub fn new(name: String, min: f32, max: f32, avg: f32, count: u32) -> Self {
+        Self((name, min, max, avg, count), None, false, false)
+    }
+
+    /// A station with no measurements, shown as `name=-/-/-` (or with `null` stats in structured
+    /// output). Its stats are all NaN, so check [`is_missing`](StationInfo::is_missing) before
+    /// using them.
+    ///
+    /// ```
+    /// use onebrc::helpers::StationInfo;
+    ///
+    /// let absent = StationInfo::missing(String::from("Palembang"));
+    /// assert!(absent.is_missing());
+    /// assert_eq!(absent.count(), 0);
+    /// assert_eq!(absent.to_string(), "Palembang=-/-/-");
+    /// ```
+    pub fn missing(name: String) -> Self {
+        Self((name, f32::NAN, f32::NAN, f32::NAN, 0), None, true, false)
+    }
+
+    /// Whether this station had no measurements; see [`StationInfo::missing`]
+    pub fn is_missing(&self) -> bool {
+        self.2
+    }
+
+    /// The min, mean & max, unless the station is [missing](StationInfo::missing)
+    pub fn stats(&self) -> Option<[f32; 3]> {
+        (!self.is_missing()).then(|| [self.min(), self.avg(), self.max()])
+    }
+
+    /// Attach the first & last rows the station was on, if they were tracked
+    pub fn with_extents(mut self, extents: Option<Extents>) -> Self {
+        self.1 = extents;
+        self
     }
 
     pub fn name(&self) -> &str {
         &self.0 .0
     }
 
+    /// Take the name to be a key in the given format, so a [`KeyFormat::MonthStation`] key (a
+    /// month, the delimiter & the station) is shown as a station & a month. A name keyed by
+    /// station alone is kept whole, whatever delimiters are in it.
+    ///
+    /// ```
+    /// use onebrc::aggregate::KeyFormat;
+    /// use onebrc::helpers::StationInfo;
+    ///
+    /// let monthly = StationInfo::new(String::from("2023-07\tHamburg"), 1.0, 1.0, 1.0, 1)
+    ///     .keyed_by(KeyFormat::MonthStation);
+    /// assert_eq!(monthly.station(), "Hamburg");
+    /// assert_eq!(monthly.month(), Some("2023-07"));
+    ///
+    /// let station = StationInfo::new(String::from("2023-07;Hamburg"), 1.0, 1.0, 1.0, 1)
+    ///     .keyed_by(KeyFormat::Station);
+    /// assert_eq!(station.station(), "2023-07;Hamburg");
+    /// assert_eq!(station.month(), None);
+    /// ```
+    pub fn keyed_by(mut self, key_format: KeyFormat) -> Self {
+        let name = self.name();
+        self.3 = key_format == KeyFormat::MonthStation
+            && name.is_char_boundary(MONTH_LEN + 1)
+            && is_month(&name.as_bytes()[..MONTH_LEN]);
+        self
+    }
+
+    /// The station this is for, without the month it was grouped by (if any); see
+    /// [`keyed_by`](StationInfo::keyed_by)
+    pub fn station(&self) -> &str {
+        match self.3 {
+            true => &self.name()[MONTH_LEN + 1..],
+            false => self.name(),
+        }
+    }
+
+    /// The `YYYY-MM` month this was grouped by, if any; see [`keyed_by`](StationInfo::keyed_by)
+    pub fn month(&self) -> Option<&str> {
+        self.3.then(|| &self.name()[..MONTH_LEN])
+    }
+
     pub fn min(&self) -> f32 {
         self.0 .1
     }
@@ -42,18 +164,105 @@ impl StationInfo {
     pub fn avg(&self) -> f32 {
         self.0 .3
     }
+
+    pub fn count(&self) -> u32 {
+        self.0 .4
+    }
+
+    pub fn extents(&self) -> Option<Extents> {
+        self.1
+    }
+
+    /// Whether both stations have the same name & stats once rounded for display.
+    ///
+    /// Unlike `==`, which only compares names (for sorting), this compares the results as a
+    /// person would read them, ignoring the number of measurements.
+    ///
+    /// ```
+    /// use onebrc::helpers::StationInfo;
+    ///
+    /// let a = StationInfo::new(String::from("Hamburg"), 12.0, 34.2, 23.1, 2);
+    /// let b = StationInfo::new(String::from("Hamburg"), 12.0, 34.2, 23.14, 3);
+    /// let c = StationInfo::new(String::from("Hamburg"), 12.0, 34.2, 23.2, 2);
+    /// assert!(a.eq_rounded(&b));
+    /// assert!(!a.eq_rounded(&c));
+    /// assert_eq!(a, c);
+    /// ```
+    pub fn eq_rounded(&self, other: &Self) -> bool {
+        self.to_string() == other.to_string()
+    }
+}
+
+impl FromStr for StationInfo {
+    type Err = &'static str;
+
+    /// Parse a station in its [displayed](Display) form, e.g. `Hamburg=12.0/23.1/34.2`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (key, stats) = s
+            .rsplit_once('=')
+            .ok_or("missing '=' between station and stats")?;
+        let stats: Vec<f32> = stats
+            .split('/')
+            .map(|v| v.parse().map_err(|_| "invalid stat"))
+            .collect::<Result<_, _>>()?;
+        let [min, avg, max] = stats[..] else {
+            return Err("expected min/mean/max stats");
+        };
+
+        // A month is displayed after the station, e.g. `Hamburg (2023-07)`
+        let station = match key.strip_suffix(')').and_then(|k| k.rsplit_once(" (")) {
+            Some((station, month)) if is_month(month.as_bytes()) => {
+                Self::new(format!("{month};{station}"), min, max, avg, 0)
+                    .keyed_by(KeyFormat::MonthStation)
+            }
+            _ => Self::new(key.to_owned(), min, max, avg, 0),
+        };
+        Ok(station)
+    }
 }
 
 impl Display for StationInfo {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(
-            f,
-            "{}={:.1}/{:.1}/{:.1}",
-            self.name(),
-            self.min(),
-            self.avg(),
-            self.max()
-        )
+        write!(f, "{}", self.station())?;
+        if let Some(month) = self.month() {
+            write!(f, " ({month})")?;
+        }
+        match self.stats() {
+            Some([min, avg, max]) => {
+                write!(f, "={}/{}/{}", Tenths(min), Tenths(avg), Tenths(max))
+            }
+            None => write!(f, "=-/-/-"),
+        }
+    }
+}
+
+/// A measurement rounded to one decimal place for display.
+///
+/// Anything which rounds to zero is shown as `0.0`, even from below: the mean of measurements
+/// which sum to zero can come out a hair under it depending on the order they were summed in,
+/// and `-0.0` isn't a measurement anyone wrote down.
+pub(crate) struct Tenths(pub f32);
+
+impl Tenths {
+    /// The measurement as it's displayed, as the nearest `f64` (e.g. for JSON output), without
+    /// formatting it first.
+    ///
+    /// Scaling an `f32` by ten is exact in an `f64`, so rounding that to even is the same as
+    /// rounding the measurement's exact decimal value to one place, as `{:.1}` does.
+    pub(crate) fn rounded(&self) -> f64 {
+        // Adding zero turns a negative zero into a positive one
+        (f64::from(self.0) * 10.0).round_ties_even() / 10.0 + 0.0
+    }
+}
+
+impl Display for Tenths {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        // Exactly the negative measurements `{:.1}` would show as `-0.0`
+        if self.0.is_sign_negative() && self.0 > -0.05 {
+            f.write_str("0.0")
+        } else {
+            write!(f, "{:.1}", self.0)
+        }
     }
 }
 
@@ -68,35 +277,348 @@ impl Eq for StationInfo {}
 
 impl PartialOrd for StationInfo {
     fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
-        self.name().partial_cmp(other.name())
+        Some(self.cmp(other))
     }
 }
 
 impl Ord for StationInfo {
     fn cmp(&self, other: &Self) -> std::cmp::Ordering {
-        self.partial_cmp(other)
-            .expect("Unable to unwrap StationInfo::partial_cmp")
+        self.name().cmp(other.name())
+    }
+}
+
+/// The storage of results which are no longer needed, for the next run to fill in again rather
+/// than allocating its own, e.g. between the runs of a benchmark.
+///
+/// ```
+/// use onebrc::helpers::{ResultsBuffer, StationInfo};
+///
+/// let mut buffer = ResultsBuffer::default();
+/// buffer.recycle(vec![StationInfo::new(String::from("Hamburg"), 12.0, 34.2, 23.1, 2)]);
+///
+/// let name = buffer.name("Bulawayo");
+/// assert_eq!(name, "Bulawayo");
+/// assert!(name.capacity() >= "Hamburg".len());
+/// assert!(buffer.take_stations().capacity() >= 1);
+/// ```
+#[derive(Debug, Default)]
+pub struct ResultsBuffer {
+    stations: Vec<StationInfo>,
+    names: Vec<String>,
+}
+
+impl ResultsBuffer {
+    /// Keep the list & names of `stations`, emptied, for reuse.
+    ///
+    /// Every name is grown to fit the longest of them, so any station can take any name
+    /// without it being reallocated.
+    pub fn recycle(&mut self, mut stations: Vec<StationInfo>) {
+        let longest = stations.iter().map(|s| s.name().len()).max().unwrap_or(0);
+        self.names.extend(stations.drain(..).map(|station| {
+            let mut name = station.0 .0;
+            name.clear();
+            name.reserve(longest);
+            name
+        }));
+        if stations.capacity() > self.stations.capacity() {
+            self.stations = stations;
+        }
+    }
+
+    /// A copy of `name`, in a recycled `String` if there are any left
+    pub fn name(&mut self, name: &str) -> String {
+        match self.names.pop() {
+            Some(mut spare) => {
+                spare.push_str(name);
+                spare
+            }
+            None => name.to_owned(),
+        }
+    }
+
+    /// The recycled (empty) list of stations, if any
+    pub fn take_stations(&mut self) -> Vec<StationInfo> {
+        std::mem::take(&mut self.stations)
+    }
+}
+
+/// How long a runner took to solve the challenge
+#[cfg(feature = "native")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Timings {
+    /// How long it took to aggregate the measurements, if [`Config::timings`] is set
+    pub aggregated: Option<Duration>,
+
+    /// How long it took to produce the sorted list of stations, i.e. the whole run
+    pub total: Duration,
+}
+
+#[cfg(feature = "native")]
+impl Timings {
+    /// Timings for a run which started at `start` and finished aggregating at `aggregated`,
+    /// ending now
+    pub fn since(start: Instant, aggregated: Instant, config: &Config) -> Self {
+        let total = start.elapsed();
+        Self {
+            aggregated: config
+                .timings
+                .value
+                .then(|| aggregated.duration_since(start)),
+            total,
+        }
+    }
+}
+
+#[cfg(feature = "native")]
+impl Display for Timings {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", fmt_duration(&self.total))?;
+        if let Some(aggregated) = &self.aggregated {
+            write!(f, " (aggregated in {})", fmt_duration(aggregated))?;
+        }
+        Ok(())
+    }
+}
+
+/// What a runner measured about a run, besides the results themselves
+#[cfg(feature = "native")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RunStats {
+    pub timings: Timings,
+
+    /// Measurements which weren't finite numbers, so were left out of the results
+    pub ignored_non_finite: u64,
+
+    /// Malformed lines which were skipped (see `--max-skipped`)
+    pub skipped: SkippedLines,
+
+    /// The runner which couldn't run in this environment, if another ran in its place
+    pub fallback: Option<Fallback>,
+}
+
+/// A runner which couldn't run in the environment it was in, & the runner which ran instead
+#[cfg(feature = "native")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Fallback {
+    pub from: Runner,
+    pub to: Runner,
+}
+
+#[cfg(feature = "native")]
+impl Display for Fallback {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "fell back from the {} runner to {}", self.from, self.to)
+    }
+}
+
+#[cfg(feature = "native")]
+impl RunStats {
+    /// Stats for a run which took `timings` and left out no measurements
+    pub fn new(timings: Timings) -> Self {
+        Self {
+            timings,
+            ignored_non_finite: 0,
+            skipped: SkippedLines::default(),
+            fallback: None,
+        }
+    }
+
+    /// Record how many non-finite measurements were left out
+    pub fn ignored_non_finite(mut self, ignored: u64) -> Self {
+        self.ignored_non_finite = ignored;
+        self
+    }
+
+    /// Record which malformed lines were skipped
+    pub fn skipped(mut self, skipped: SkippedLines) -> Self {
+        self.skipped = skipped;
+        self
+    }
+
+    /// Record that another runner ran in place of the one configured
+    pub fn fell_back(mut self, fallback: Fallback) -> Self {
+        self.fallback = Some(fallback);
+        self
     }
 }
 
 /// Helper type to represent the result of attempting the 1BRC Challenge.
 ///
-/// When `Ok`, get the list of alphabetically-sorted [`StationInfo`] and a [`Duration`]
-/// representing the amount of time it took to produce that result.
+/// When `Ok`, get the list of alphabetically-sorted [`StationInfo`] and the [`RunStats`],
+/// including the amount of time it took to produce that result.
 /// Otherwise, get the [`Error`](std::error::Error) encountered while computing the result.
-pub type ChallengeResult = Result<(Vec<StationInfo>, Duration), Box<dyn std::error::Error>>;
+#[cfg(feature = "native")]
+pub type ChallengeResult = Result<(Vec<StationInfo>, RunStats), Box<dyn std::error::Error>>;
 
+#[cfg(feature = "native")]
 pub trait ChallengeRunner {
     /// Solve the 1 Billion Row Challenge
     ///
     /// # Parameters
     /// * `input` - [`Path`] to the file containing the challenge input
+    /// * `config` - The effective [`Config`] for this run
     ///
     /// # Returns
-    /// A [`Duration`] indicatating how long it took to solve the challenge,
+    /// The [`RunStats`] indicatating how long it took to solve the challenge,
     /// not including the amount of time it took to print the output, or some
     /// error encountered while attempting to solve the challenge.
-    fn run<R>(input: R) -> ChallengeResult
+    fn run<R>(input: R, config: &Config) -> ChallengeResult
     where
         R: io::Read + io::Seek;
 }
+
+#[cfg(feature = "native")]
+/// Helper function to format a [`Duration`] with a nice seconds/ms structure.
+///
+/// Anything under a millisecond (e.g. a run over a tiny input) is shown in microseconds or
+/// nanoseconds instead, rather than as no time at all.
+///
+/// ```
+/// use std::time::Duration;
+/// use onebrc::helpers::fmt_duration;
+///
+/// assert_eq!(fmt_duration(&Duration::from_millis(12_345)), "12s 345ms");
+/// assert_eq!(fmt_duration(&Duration::from_nanos(412_345)), "412.3µs");
+/// assert_eq!(fmt_duration(&Duration::from_nanos(850)), "850ns");
+/// ```
+pub fn fmt_duration(duration: &Duration) -> String {
+    if duration.as_millis() == 0 {
+        let nanos = duration.subsec_nanos();
+        return if nanos < 1000 {
+            format!("{nanos}ns")
+        } else {
+            format!("{:.1}µs", nanos as f64 / 1000.0)
+        };
+    }
+
+    // Display the time it took to compute the results
+    let seconds = duration.as_secs();
+    let millis = duration.subsec_millis();
+    format!("{seconds}s {millis:0>3}ms")
+}
+
+/// Format a number of bytes in the largest binary unit it has at least one of
+///
+/// ```
+/// use onebrc::helpers::fmt_bytes;
+///
+/// assert_eq!(fmt_bytes(512), "512 B");
+/// assert_eq!(fmt_bytes(1536), "1.5 KiB");
+/// assert_eq!(fmt_bytes(3 << 30), "3.0 GiB");
+/// ```
+pub fn fmt_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["KiB", "MiB", "GiB", "TiB"];
+    if bytes < 1024 {
+        return format!("{bytes} B");
+    }
+    let mut value = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    format!("{value:.1} {}", UNITS[unit])
+}
+
+/// Parse a number of bytes, optionally with a decimal (`KB`, `MB`, ...) or binary (`KiB`, `MiB`,
+/// ...) unit
+///
+/// ```
+/// use onebrc::helpers::parse_bytes;
+///
+/// assert_eq!(parse_bytes("4096"), Ok(4096));
+/// assert_eq!(parse_bytes("2GiB"), Ok(2 << 30));
+/// assert_eq!(parse_bytes("1.5 MB"), Ok(1_500_000));
+/// assert!(parse_bytes("2 parsecs").is_err());
+/// ```
+pub fn parse_bytes(s: &str) -> Result<u64, String> {
+    let s = s.trim();
+    let split = s
+        .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+        .unwrap_or(s.len());
+    let (number, unit) = s.split_at(split);
+    let scale: u64 = match unit.trim().to_ascii_lowercase().as_str() {
+        "" | "b" => 1,
+        "kb" => 1_000,
+        "mb" => 1_000_000,
+        "gb" => 1_000_000_000,
+        "tb" => 1_000_000_000_000,
+        "kib" => 1 << 10,
+        "mib" => 1 << 20,
+        "gib" => 1 << 30,
+        "tib" => 1 << 40,
+        unit => return Err(format!("unknown unit '{unit}'; expected e.g. B, MB or GiB")),
+    };
+    let number: f64 = number
+        .parse()
+        .map_err(|_| format!("'{s}' isn't a number of bytes"))?;
+    Ok((number * scale as f64).round() as u64)
+}
+
+#[cfg(feature = "native")]
+/// Replace the contents of the file at `path` all at once.
+///
+/// The contents are written to a temporary file alongside `path` which is then renamed over it,
+/// so readers never see a partially-written file.
+pub fn write_atomically(path: &Path, contents: &[u8]) -> io::Result<()> {
+    let mut tmp_name = path.file_name().unwrap_or_default().to_os_string();
+    tmp_name.push(format!(".{}.tmp", std::process::id()));
+    let tmp_path = path.with_file_name(tmp_name);
+
+    TEMP_FILES.register(&tmp_path);
+    let result = std::fs::write(&tmp_path, contents).and_then(|_| std::fs::rename(&tmp_path, path));
+    match &result {
+        Ok(()) => TEMP_FILES.forget(&tmp_path),
+        Err(_) => TEMP_FILES.release(&tmp_path),
+    }
+    result
+}
+
+#[cfg(feature = "native")]
+/// Set once a write to stdout fails because whatever was reading it went away
+static STDOUT_CLOSED: AtomicBool = AtomicBool::new(false);
+
+#[cfg(feature = "native")]
+/// Whether stdout has been closed by its reader (e.g. when piped into `head`)
+pub fn stdout_closed() -> bool {
+    STDOUT_CLOSED.load(Ordering::Relaxed)
+}
+
+#[cfg(feature = "native")]
+/// Write to stdout, quietly discarding the output once the reader has closed the pipe.
+///
+/// Unlike `print!`, this doesn't panic on a broken pipe, so the rest of the run (writing reports,
+/// saving progress, etc.) still happens when the output is piped into something like `head`.
+pub fn write_stdout(contents: &str) -> io::Result<()> {
+    if stdout_closed() {
+        return Ok(());
+    }
+
+    let mut stdout = io::stdout().lock();
+    match stdout
+        .write_all(contents.as_bytes())
+        .and_then(|_| stdout.flush())
+    {
+        Err(e) if e.kind() == io::ErrorKind::BrokenPipe => {
+            STDOUT_CLOSED.store(true, Ordering::Relaxed);
+            Ok(())
+        }
+        result => result,
+    }
+}
+
+/// Like `println!`, but stops printing rather than panicking if stdout is closed early.
+///
+/// See [`write_stdout`](crate::helpers::write_stdout).
+#[cfg(feature = "native")]
+#[macro_export]
+macro_rules! outln {
+    () => {
+        $crate::outln!("")
+    };
+    ($($arg:tt)*) => {
+        if let Err(e) = $crate::helpers::write_stdout(&format!("{}\n", format_args!($($arg)*))) {
+            panic!("failed printing to stdout: {e}");
+        }
+    };
+}
@@ -0,0 +1,506 @@
+// 1BRC - my take on the 1 Billion Row Challenge
+// Copyright (C) 2024  Charles German <5donuts@pm.me>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! My take on the One Billion Row Challenge.
+//!
+//! The core of the crate (parsing & aggregating measurements and formatting the results) has no
+//! dependencies on the OS so it can be built for targets like `wasm32-unknown-unknown`. The
+//! runners, which read the input from disk and time themselves, are behind the `native` feature
+//! (enabled by default).
+//!
+//! The parsing & per-station accumulation under that don't even need `std`: with the
+//! `no-std-core` feature alone, the crate is `#![no_std]` & only has the
+//! [`core_aggregate`](crate::core_aggregate) module, for targets without an OS at all.
+
+#![cfg_attr(not(feature = "std"), no_std)]
+
+extern crate alloc;
+
+#[cfg(feature = "no-std-core")]
+pub mod core_aggregate;
+
+#[cfg(feature = "std")]
+pub mod aggregate;
+#[cfg(feature = "std")]
+pub mod dataset;
+#[cfg(feature = "std")]
+pub mod error;
+#[cfg(feature = "std")]
+pub mod generate;
+#[cfg(feature = "std")]
+pub mod helpers;
+#[cfg(feature = "std")]
+pub mod metadata;
+#[cfg(feature = "std")]
+pub mod output;
+#[cfg(feature = "std")]
+pub mod sample;
+
+#[cfg(feature = "native")]
+pub mod block_sizing;
+#[cfg(feature = "native")]
+pub mod blocks;
+#[cfg(feature = "native")]
+pub mod boundaries;
+#[cfg(feature = "native")]
+pub mod compare;
+#[cfg(feature = "native")]
+pub mod config;
+#[cfg(feature = "native")]
+pub mod diagnose;
+#[cfg(feature = "native")]
+pub mod expected;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+#[cfg(feature = "native")]
+pub mod fingerprint;
+#[cfg(feature = "native")]
+pub mod manifest;
+#[cfg(feature = "native")]
+pub mod partial;
+#[cfg(feature = "native")]
+pub mod plan;
+#[cfg(feature = "native")]
+pub mod platform;
+#[cfg(feature = "native")]
+pub mod progress;
+#[cfg(feature = "native")]
+pub mod reader;
+#[cfg(feature = "native")]
+pub mod report;
+#[cfg(feature = "native")]
+pub mod runners;
+#[cfg(feature = "native")]
+pub mod spill;
+#[cfg(feature = "native")]
+pub mod station_cache;
+#[cfg(feature = "native")]
+pub mod stats;
+#[cfg(feature = "native")]
+pub mod style;
+#[cfg(feature = "native")]
+pub mod table;
+#[cfg(feature = "native")]
+pub mod temp;
+#[cfg(feature = "native")]
+pub mod topology;
+#[cfg(feature = "tui")]
+pub mod tui;
+#[cfg(feature = "native")]
+pub mod tune;
+#[cfg(feature = "native")]
+pub mod window;
+
+#[cfg(feature = "wasm")]
+pub mod wasm;
+
+#[cfg(feature = "native")]
+use clap::ValueEnum;
+#[cfg(feature = "native")]
+use serde::{Deserialize, Serialize};
+#[cfg(feature = "std")]
+use std::hash::RandomState;
+
+#[cfg(feature = "std")]
+use crate::aggregate::Aggregator;
+#[cfg(feature = "std")]
+use crate::error::ChallengeError;
+#[cfg(feature = "std")]
+use crate::helpers::StationInfo;
+
+#[cfg(all(feature = "mimalloc", feature = "jemalloc"))]
+compile_error!("The `mimalloc` & `jemalloc` features each replace the global allocator; enable at most one of them");
+
+#[cfg(all(feature = "io-uring", not(target_os = "linux")))]
+compile_error!("The `io-uring` feature needs io_uring, which is only available on Linux");
+
+#[cfg(all(feature = "direct-io", not(target_os = "linux")))]
+compile_error!("The `direct-io` feature needs O_DIRECT, which is only supported here on Linux");
+
+#[cfg(feature = "mimalloc")]
+#[global_allocator]
+static GLOBAL: mimalloc::MiMalloc = mimalloc::MiMalloc;
+
+#[cfg(all(feature = "jemalloc", not(feature = "mimalloc")))]
+#[global_allocator]
+static GLOBAL: tikv_jemallocator::Jemalloc = tikv_jemallocator::Jemalloc;
+
+/// The global allocator this build uses, picked with the `mimalloc` & `jemalloc` features.
+///
+/// Results from builds with different allocators aren't directly comparable, so this is shown
+/// in the configuration & recorded in reports.
+pub const ALLOCATOR: &str = if cfg!(feature = "mimalloc") {
+    "mimalloc"
+} else if cfg!(feature = "jemalloc") {
+    "jemalloc"
+} else {
+    "system"
+};
+
+/// Solve the challenge for an input that's already in memory, with the default settings.
+///
+/// Returns the alphabetically-sorted stations, or the first malformed line's error. To read the
+/// input from a file, or change the settings, use the [`runners`](crate::runners) or an
+/// [`Aggregator`] instead.
+///
+/// ```
+/// let stations = onebrc::solve("Hamburg;12.0\nBulawayo;8.9\nPalembang;38.8\nHamburg;34.2\n")?;
+///
+/// let names: Vec<_> = stations.iter().map(|s| s.name()).collect();
+/// assert_eq!(names, ["Bulawayo", "Hamburg", "Palembang"]);
+/// assert_eq!(stations[1].to_string(), "Hamburg=12.0/23.1/34.2");
+///
+/// let err = onebrc::solve("Hamburg;12.0\nHamburg 34.2\n").unwrap_err();
+/// assert_eq!(
+///     err.to_string(),
+///     "Malformed line 2: missing ';' between station and measurement"
+/// );
+/// # Ok::<(), onebrc::error::ChallengeError>(())
+/// ```
+#[cfg(feature = "std")]
+pub fn solve(input: &str) -> Result<Vec<StationInfo>, ChallengeError> {
+    let mut aggregator: Aggregator<RandomState> = Aggregator::new();
+    aggregator.ingest_str(input)?;
+    Ok(aggregator.into_sorted())
+}
+
+/// The available strategies for solving the challenge
+#[cfg(feature = "native")]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum Runner {
+    /// Iterate through the input line-by-line
+    Baseline,
+
+    /// Use the same approach as `baseline` with the `FxHasher` from the `rustc-hash` crate.
+    ///
+    /// This hashing algorithm has a number of speed improvements over the hasher used by the
+    /// standard library, but is not as robust a hasher. For this use case, that's an acceptable
+    /// trade-off to make.
+    RustcHash,
+
+    /// Use the same approach as `baseline` with the `AHasher` from the `ahash` crate.
+    ///
+    /// This hashing algorithm uses AES-NI instructions to speed up hashing. However, like the
+    /// `rustc-hash` crate, is not cryptographically secure.
+    #[default]
+    #[value(name = "ahash", alias = "a-hash")]
+    #[serde(rename = "ahash", alias = "a-hash")]
+    AHash,
+
+    /// Use the same approach as `baseline` with a purpose-built open-addressing table keyed by
+    /// FNV-1a hashes rather than a `HashMap`.
+    Table,
+
+    /// Use the same approach as `table`, but software-pipeline the loop so the table slot for
+    /// each line is prefetched while the previous line's stats are still being updated.
+    TablePrefetch,
+
+    /// Use the same approach as `table`, with a small direct-mapped cache of recently-seen
+    /// stations in front of the table so most lookups don't need to probe it.
+    CachedTable,
+
+    /// Use the same approach as `ahash`, but map the input into memory & aggregate the mapped
+    /// bytes directly rather than copying them through a read buffer.
+    Mmap,
+
+    /// Map the input into memory, split it into newline-aligned chunks, and aggregate the chunks
+    /// on several threads into maps of their own which are merged at the end.
+    ParMmap,
+
+    /// Sample the start of the input to assign each station a dense id, then aggregate blocks of
+    /// lines on several threads into flat arrays indexed by those ids. Stations missing from the
+    /// sample fall back to a small map per thread.
+    SampledDense,
+
+    /// Split the input into one newline-aligned range per thread & aggregate each range on a
+    /// scoped thread reading it through a file handle of its own, merging the threads' maps at
+    /// the end.
+    ScopedThreads,
+
+    /// Use the same approach as `mmap`, but find the end of each line & the separator before its
+    /// measurement with the vectorized searches from the `memchr` crate.
+    Memchr,
+
+    /// Use the same approach as `ahash`, but parse each measurement straight into a whole number
+    /// of tenths of a degree & keep exact integer sums, only converting back to floats at the end.
+    FixedPoint,
+
+    /// Use the same approach as `ahash`, but read the input in blocks & key the map by the bytes
+    /// of each name, borrowed from an arena they're copied into the first time they're seen, so
+    /// no `String`s are built until the results are.
+    ByteKeys,
+
+    /// Use the same approach as `table`, but with every station stored inline in one flat array
+    /// sized for the challenge's 10,000 stations, and every name in one buffer, rather than each
+    /// in an allocation of its own.
+    InlineTable,
+
+    /// Use the same approach as `baseline`, but keep the official generator's stations in an
+    /// array indexed by a perfect hash of their names, built when the runner is first used, so
+    /// each is found with a single probe. Any other stations are kept in a map.
+    PerfectHash,
+
+    /// Read the input in blocks of whole lines on one thread, handing them round-robin to a
+    /// fixed number of parser threads over bounded channels, so reading & parsing overlap with
+    /// only a few blocks in memory at once. Each parser's map is merged at the end.
+    Pipelined,
+
+    /// Read the input into a fixed set of buffers on one thread, handing each block to whichever
+    /// parser thread is free over a bounded `crossbeam` channel & getting the buffer back over
+    /// another once it's parsed, so the buffers are only allocated once.
+    Crossbeam,
+
+    /// Use the same approach as `ahash`, but parse each measurement with a parser specialized for
+    /// the challenge's `-?\d{1,2}\.\d` format, which decodes both lengths with the same few
+    /// arithmetic ops rather than branching on the number of digits.
+    Branchless,
+
+    /// Use the same approach as `memchr`, but find every newline & delimiter in the input by
+    /// comparing 32 bytes at a time with portable SIMD vectors from the `wide` crate, so the loop
+    /// over the lines jumps straight from one to the next.
+    Simd,
+
+    /// Use the same approach as `simd`, but find every newline & delimiter eight bytes at a time
+    /// with bit tricks on a `u64` (SIMD within a register), which needs no vector instructions.
+    Swar,
+
+    /// Use the same approach as `memchr`, but keep every line as bytes & key the map by the bytes
+    /// of each name, which only becomes a `&str` (unchecked) the first time it's seen. Relies on
+    /// the challenge's promise that the input is valid UTF-8: other input is undefined behaviour,
+    /// except in debug builds, which check each new name.
+    Unchecked,
+
+    /// Use the same approach as `ahash`, but size the map for the challenge's 10,000 stations (or
+    /// every known station, if there are more) up front, so it's never resized during the run.
+    Presized,
+
+    /// Use the same approach as `rustc-hash`, but with the map from the `hashbrown` crate, looked
+    /// up with its `entry_ref` API so each line's station is hashed once even when it's new, and
+    /// its name only copied into a `String` the first time it's seen.
+    EntryRef,
+
+    /// Use the same approach as `ahash`, but advise the kernel the input will be read
+    /// sequentially (with `posix_fadvise`) & drop the pages already read from the page cache as
+    /// it goes, so a file bigger than RAM doesn't thrash the cache. The hints are only given on
+    /// Linux; elsewhere it's the same as `ahash`.
+    Fadvise,
+
+    /// Split the input into many small newline-aligned ranges, all queued up front, which a fixed
+    /// number of worker threads take off the queue one at a time until it's empty, so a slow
+    /// worker (or a slow range) doesn't leave the others idle. Every worker reads the same file
+    /// handle with positioned reads, & the ranges' maps are merged in order at the end.
+    WorkQueue,
+
+    /// Use the same approach as `byte-keys`, but key the map by names of up to 22 bytes stored
+    /// inline in the keys themselves, rather than borrowed from an arena, so most lookups never
+    /// follow a pointer. Longer names are kept on the heap.
+    CompactKeys,
+
+    /// Use the same approach as `byte-keys`, but key the map by a 64-bit hash of each name, so
+    /// finding a station only compares hashes. Each hit is checked against the name stored with
+    /// it, & a name whose hash collides with another's is kept in a second map keyed by name.
+    HashKeys,
+
+    /// Read the input into one of two buffers on the calling thread while a single parser
+    /// thread works through the other, swapping them once both are done, so reading & parsing
+    /// overlap with only one extra thread & two allocations.
+    DoubleBuffered,
+
+    /// Use the same approach as `ahash`, but read the input through io_uring, with reads of the
+    /// next few blocks always queued while the current one is parsed. Only built with the
+    /// `io-uring` feature, on Linux.
+    #[cfg(feature = "io-uring")]
+    IoUring,
+
+    /// Use the same approach as `ahash`, but read the input around the page cache with
+    /// `O_DIRECT`, into buffers aligned to the disk's blocks. Only built with the `direct-io`
+    /// feature, on Linux.
+    #[cfg(feature = "direct-io")]
+    DirectIo,
+
+    /// Read the input asynchronously on a tokio runtime, parsing up to `--threads` blocks at once
+    /// on tokio's blocking threads & merging their maps in order. Only built with the `tokio`
+    /// feature.
+    #[cfg(feature = "tokio")]
+    Tokio,
+
+    /// Use the same approach as `scoped-threads`, but with a thread for each core available,
+    /// whatever `--threads` says, each pinned to its own core & aggregating into an FxHash map.
+    /// Only built with the `affinity` feature.
+    #[cfg(feature = "affinity")]
+    ThreadPerCore,
+}
+
+#[cfg(feature = "native")]
+impl Runner {
+    /// The hashing algorithm used by this runner's station map
+    pub fn hasher(self) -> &'static str {
+        use Runner::*;
+        match self {
+            Baseline | ScopedThreads | WorkQueue | Pipelined | Crossbeam => "SipHash-1-3",
+            RustcHash | EntryRef => "FxHasher",
+            AHash | Presized | Fadvise | Mmap | Memchr | Simd | Swar | Unchecked | FixedPoint
+            | ByteKeys | CompactKeys | HashKeys | DoubleBuffered | Branchless => "AHasher",
+            Table | TablePrefetch | CachedTable | InlineTable | PerfectHash => "FNV-1a",
+            SampledDense | ParMmap => "AHasher",
+            #[cfg(feature = "io-uring")]
+            IoUring => "AHasher",
+            #[cfg(feature = "direct-io")]
+            DirectIo => "AHasher",
+            #[cfg(feature = "tokio")]
+            Tokio => "SipHash-1-3",
+            #[cfg(feature = "affinity")]
+            ThreadPerCore => "FxHasher",
+        }
+    }
+
+    /// Whether this runner splits the input into chunks processed in parallel
+    pub fn is_parallel(self) -> bool {
+        use Runner::*;
+        match self {
+            Baseline | RustcHash | AHash | Presized | EntryRef | Fadvise | Table
+            | TablePrefetch | CachedTable | Mmap | Memchr | Simd | Swar | Unchecked
+            | FixedPoint | ByteKeys | CompactKeys | HashKeys | DoubleBuffered | InlineTable
+            | PerfectHash | Branchless => false,
+            SampledDense | ParMmap | ScopedThreads | WorkQueue | Pipelined | Crossbeam => true,
+            #[cfg(feature = "io-uring")]
+            IoUring => false,
+            #[cfg(feature = "direct-io")]
+            DirectIo => false,
+            #[cfg(feature = "tokio")]
+            Tokio => true,
+            #[cfg(feature = "affinity")]
+            ThreadPerCore => true,
+        }
+    }
+
+    /// The runner to retry with if this one can't run in the environment it's in (e.g. mapping
+    /// the input into memory isn't supported), if any.
+    ///
+    /// Each runner falls back to the next best one which needs less of the environment, ending
+    /// with the `baseline`, which only needs to read the input.
+    pub fn fallback(self) -> Option<Runner> {
+        use Runner::*;
+        match self {
+            // Still in parallel, but reading the input through file handles rather than a map
+            ParMmap => Some(ScopedThreads),
+            SampledDense | ScopedThreads | WorkQueue | Pipelined | Crossbeam | Mmap | Memchr
+            | Simd | Swar | Unchecked => Some(AHash),
+            RustcHash | Presized | EntryRef | Fadvise | Table | TablePrefetch | CachedTable
+            | FixedPoint | ByteKeys | CompactKeys | HashKeys | DoubleBuffered | InlineTable
+            | PerfectHash | Branchless => Some(AHash),
+            #[cfg(feature = "io-uring")]
+            IoUring => Some(AHash),
+            #[cfg(feature = "direct-io")]
+            DirectIo => Some(AHash),
+            // Still in parallel, but on threads of its own
+            #[cfg(feature = "tokio")]
+            Tokio => Some(ScopedThreads),
+            // Still a range per thread, but as many threads as `--threads` says, unpinned
+            #[cfg(feature = "affinity")]
+            ThreadPerCore => Some(ScopedThreads),
+            AHash => Some(Baseline),
+            Baseline => None,
+        }
+    }
+
+    /// How reproducible this runner's results are with the default settings; checked by the
+    /// runners' tests. [`Config::determinism`](config::Config::determinism) accounts for the
+    /// settings which make a runner less reproducible.
+    pub fn determinism(self) -> Determinism {
+        use Runner::*;
+        match self {
+            Baseline | RustcHash | AHash | Presized | EntryRef | Fadvise | Table
+            | TablePrefetch | CachedTable | Mmap | Memchr | Simd | Swar | Unchecked
+            | FixedPoint | ByteKeys | CompactKeys | HashKeys | DoubleBuffered | InlineTable
+            | PerfectHash | Branchless => Determinism::BitExact,
+            #[cfg(feature = "io-uring")]
+            IoUring => Determinism::BitExact,
+            #[cfg(feature = "direct-io")]
+            DirectIo => Determinism::BitExact,
+            // The workers' sums are merged in a fixed order, but which lines each worker sums
+            // depends on the number of threads & the block size
+            SampledDense => Determinism::RoundedExact,
+            // The chunks' maps are merged in order, but where the chunks start depends on the
+            // number of chunks
+            ParMmap => Determinism::RoundedExact,
+            // Likewise, but there's a range per thread
+            ScopedThreads => Determinism::RoundedExact,
+            // The ranges' maps are merged in order too, & where the ranges start only depends on
+            // the length of the input, so the bits are the same whatever the settings; but the
+            // ranges of a large input aren't summed in the input's order
+            WorkQueue => Determinism::RoundedExact,
+            // With fixed-size blocks, each parser sums the same blocks in the same order every
+            // run, but not the order of the input; adaptive ones are best-effort (see
+            // `Config::determinism`)
+            Pipelined => Determinism::RoundedExact,
+            // One map per block, merged in block order, so which task parsed a block makes no
+            // difference, but where the blocks start does
+            #[cfg(feature = "tokio")]
+            Tokio => Determinism::RoundedExact,
+            // Whichever parser is free takes the next block, so which blocks each one sums
+            // changes from run to run
+            Crossbeam => Determinism::BestEffort,
+            // Like `scoped-threads`, but the number of ranges is the number of cores
+            #[cfg(feature = "affinity")]
+            ThreadPerCore => Determinism::RoundedExact,
+        }
+    }
+}
+
+/// How far a runner's results can be relied on to be the same from run to run.
+///
+/// Measurements are summed as floats, so the sums (& means) depend on the order they're added in.
+#[cfg(feature = "native")]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Determinism {
+    /// The same bits on every run & every machine for the same input: measurements are summed in
+    /// the order they appear (or exactly, as integers), whatever the settings
+    BitExact,
+
+    /// The same bits on every run with the same `--threads`, `--buffer-size` & `--num-chunks`, but
+    /// with other values the sums are added in another order, so only the rounded results are the
+    /// same. Only with fixed `--block-sizing`: the `pipelined` runner's adaptive blocks end
+    /// wherever the timing of the reads puts them, which makes it best-effort.
+    RoundedExact,
+
+    /// Nothing is promised beyond the rounded results usually being the same
+    #[default]
+    BestEffort,
+}
+
+#[cfg(feature = "native")]
+impl std::fmt::Display for Determinism {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let s = match self {
+            Determinism::BitExact => "bit-exact",
+            Determinism::RoundedExact => "rounded-exact",
+            Determinism::BestEffort => "best-effort",
+        };
+        write!(f, "{s}")
+    }
+}
+
+#[cfg(feature = "native")]
+impl std::fmt::Display for Runner {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let value = self
+            .to_possible_value()
+            .expect("Runner variants are never skipped");
+        write!(f, "{}", value.get_name())
+    }
+}
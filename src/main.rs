@@ -13,16 +13,45 @@
 // You should have received a copy of the GNU General Public License
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
+use std::borrow::Cow;
 use std::path::{Path, PathBuf};
-use std::time::Duration;
+use std::process::ExitCode;
+use std::time::{Duration, Instant};
 
-use clap::Parser;
-use serde::Serialize;
+use clap::{Parser, ValueEnum};
 
-use helpers::ChallengeRunner;
-
-mod helpers;
-mod runners;
+use onebrc::aggregate::{Delimiter, Encoding, KeyFormat};
+use onebrc::block_sizing::BlockSizing;
+use onebrc::compare::{self, Candidate};
+use onebrc::config::{Config, Layer};
+use onebrc::diagnose::{diagnose, Facts};
+use onebrc::error::SkippedLines;
+use onebrc::expected::ExpectedStations;
+use onebrc::fingerprint::fnv1a;
+use onebrc::generate::{Generator, Pattern};
+use onebrc::helpers::{
+    fmt_bytes, fmt_duration, parse_bytes, ChallengeResult, RunStats, StationInfo, Timings,
+};
+use onebrc::manifest::Manifest;
+use onebrc::metadata::Metadata;
+use onebrc::outln;
+use onebrc::output::{Format, MultiSink, OutputSpec, StdoutSink};
+use onebrc::partial;
+use onebrc::plan::{chunk_boundaries, Plan};
+use onebrc::report::failure::{self, ErrorReport, PartialStats};
+use onebrc::report::merge::{Machine, Matrix};
+use onebrc::report::repro::Bundle;
+use onebrc::report::session::{Session, SessionLog, SessionReport};
+use onebrc::report::Report;
+use onebrc::runners;
+use onebrc::sample::{parse_rate, Sample};
+use onebrc::stats::{BenchStats, BENCH_RUNS};
+use onebrc::style::{ColorChoice, Styler};
+use onebrc::temp::TEMP_FILES;
+use onebrc::topology::Topology;
+use onebrc::tune::{Mode, Tuning, Tunings};
+use onebrc::window::{first_lines, ByteRange, Window};
+use onebrc::Runner;
 
 // TODO: add a debug command that shows how a particular station's data (the first one read)
 // changes over time. For some reason a lot of stations have weirdly similar data that I'm not sure
@@ -30,97 +59,1227 @@ mod runners;
 
 // TODO: start benchmarking disk usage, memory usage, CPU usage for the blog post
 
-#[derive(Debug, Default, Clone, Copy, Serialize, clap::ValueEnum)]
-#[serde(rename_all = "kebab-case")]
-enum Runner {
-    /// Iterate through the input line-by-line
-    Baseline,
-
-    /// Use the same approach as `baseline` with the `FxHasher` from the `rustc-hash` crate.
-    ///
-    /// This hashing algorithm has a number of speed improvements over the hasher used by the
-    /// standard library, but is not as robust a hasher. For this use case, that's an acceptable
-    /// trade-off to make.
-    RustcHash,
-
-    /// Use the same approach as `baseline` with the `AHasher` from the `ahash` crate.
-    ///
-    /// This hashing algorithm uses AES-NI instructions to speed up hashing. However, like the
-    /// `rustc-hash` crate, is not cryptographically secure.
-#[default]
-    AHash,
-}
-
 #[derive(Debug, Parser)]
 #[clap(
     author,
     version,
     about,
-    long_about = r#"My take on the 1 Billion Row Challenge
-
-Copyright (C) 2024 Charles German <5donuts@pm.me>
+    after_long_help = r#"Copyright (C) 2024 Charles German <5donuts@pm.me>
 This program is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY.
 See the GNU General Public License for more details. You should have received a copy of the
 GNU General Public License along with this program. If not, see <https://www.gnu.org/licenses/>."#
 )]
 struct Args {
-    /// The runner to use to solve the challenge
+    /// The runner to use to solve the challenge [default: ahash]
+    ///
+    /// May also be set with the `ONEBRC_RUNNER` environment variable or the `runner` key in
+    /// the config file.
+    #[clap(short, long, value_enum)]
+    runner: Option<Runner>,
+
+    /// Capacity in bytes of the buffer used to read the input [default: 8192]
+    ///
+    /// May also be set with the `ONEBRC_BUFFER_SIZE` environment variable or the `buffer-size`
+    /// key in the config file.
+    #[clap(long)]
+    buffer_size: Option<usize>,
+
+    /// Maximum length in bytes of a single line of input [default: 1024]
+    ///
+    /// Lines longer than this are treated as an error. May also be set with the
+    /// `ONEBRC_MAX_LINE_LENGTH` environment variable or the `max-line-length` key in the config
+    /// file.
+    #[clap(long)]
+    max_line_length: Option<usize>,
+
+    /// Cache the sorted station names in a `<INPUT>.stations` sidecar file
+    ///
+    /// Later runs against the same (unchanged) input use the cache to size the station map up
+    /// front and skip the final sort. The sidecar is kept next to the input with any symlinks
+    /// resolved, so every path to the same file shares it. May also be set with the `ONEBRC_STATION_CACHE`
+    /// environment variable or the `station-cache` key in the config file.
+    #[clap(long, action)]
+    station_cache: bool,
+
+    /// Reject irregular input instead of skipping over it
+    ///
+    /// By default, a UTF-8 byte-order mark at the start of the input and blank lines are
+    /// ignored. In strict mode they are reported as errors. May also be set with the
+    /// `ONEBRC_STRICT` environment variable or the `strict` key in the config file.
+    #[clap(long, action)]
+    strict: bool,
+
+    /// Skip up to this many malformed lines rather than stopping at the first one [default: 0]
+    ///
+    /// Skipped lines are counted by what was wrong with them (e.g. no semicolon, or a bad
+    /// temperature), and a summary is shown after the run. More than this many is an error. May
+    /// also be set with the `ONEBRC_MAX_SKIPPED` environment variable or the `max-skipped` key in
+    /// the config file.
+    #[clap(long)]
+    max_skipped: Option<u64>,
+
+    /// Also time aggregating the measurements separately from sorting & formatting the results
+    ///
+    /// The `Solved in` time is unaffected. May also be set with the `ONEBRC_TIMINGS`
+    /// environment variable or the `timings` key in the config file.
+    #[clap(long, action)]
+    timings: bool,
+
+    /// What the measurements are grouped by [default: station]
+    ///
+    /// With `month-station`, each line starts with a `YYYY-MM` month (e.g.
+    /// `2023-07;Hamburg;12.3`) and the results are per station per month, ordered by month. May
+    /// also be set with the `ONEBRC_KEY_FORMAT` environment variable or the `key-format` key in
+    /// the config file.
+    #[clap(long, value_enum)]
+    key_format: Option<KeyFormat>,
+
+    /// The character between the fields of each line [default: ;]
+    ///
+    /// For exports like `Hamburg\t12.3` or `Hamburg,12.3`; pass `\t` or `tab` for a tab. May
+    /// also be set with the `ONEBRC_DELIMITER` environment variable or the `delimiter` key in the
+    /// config file.
+    #[clap(long, value_name = "CHAR")]
+    delimiter: Option<Delimiter>,
+
+    /// Read measurements written with a decimal comma, e.g. `12,3`
+    ///
+    /// With `--delimiter ,` too, the measurement is taken to be the number at the end of each
+    /// line (`Hamburg,12,3` is 12.3 at Hamburg); in strict mode, a line with any other commas is
+    /// an error since it could be read more than one way. May also be set with the
+    /// `ONEBRC_DECIMAL_COMMA` environment variable or the `decimal-comma` key in the config file.
+    #[clap(long, action)]
+    decimal_comma: bool,
+
+    /// How the station names in the input are encoded [default: utf8]
+    ///
+    /// For legacy exports with names like `Zürich` in Latin-1 or Windows-1252, which are
+    /// transcoded to UTF-8 as they're read; those encodings are only available in builds with
+    /// the `encodings` feature. The encoding is never guessed: in UTF-8, such a name is invalid.
+    /// May also be set with the `ONEBRC_ENCODING` environment variable or the `encoding` key in
+    /// the config file.
+    #[clap(long, value_enum)]
+    encoding: Option<Encoding>,
+
+    /// Roughly how many bytes the per-thread maps may use, for inputs with a huge number of
+    /// distinct stations [default: 0, for no limit]
+    ///
+    /// Once a thread's share is used up, the stations it has seen least often are written out
+    /// to a temporary file and merged back in at the end; the results are the same either way.
+    /// Only the sampled-dense runner has a limit. May also be set with the `ONEBRC_MAX_MEMORY`
+    /// environment variable or the `max-memory` key in the config file.
+    #[clap(long, value_name = "BYTES")]
+    max_memory: Option<u64>,
+
+    /// How the pipelined runner sizes the blocks it reads: `fixed`, `adaptive`, or
+    /// `adaptive:MIN-MAX` [default: fixed]
+    ///
+    /// Adaptive blocks start at 1 MiB & are doubled while the parsers are left waiting for the
+    /// reader, or halved while they fall behind or a read takes more than 20ms, within the bounds
+    /// given (64KiB-16MiB by default, e.g. `adaptive:256KiB-4MiB`). With `--timings`, the size of
+    /// each block is shown after the run. May also be set with the `ONEBRC_BLOCK_SIZING`
+    /// environment variable or the `block-sizing` key in the config file.
+    #[clap(long, value_name = "SIZING")]
+    block_sizing: Option<BlockSizing>,
+
+    /// Record the first & last row each station is on, for auditing the input
+    ///
+    /// The rows are only shown in JSON output (`first_row` & `last_row`), and tracking them
+    /// makes the run a little slower. May also be set with the `ONEBRC_TRACK_EXTENTS`
+    /// environment variable or the `track-extents` key in the config file.
+    #[clap(long, action)]
+    track_extents: bool,
+
+    /// Merge stations whose names only differ in case (e.g. `istanbul` & `Istanbul`)
+    ///
+    /// Names are compared with Unicode case folding, and each station is shown with the
+    /// spelling seen first in the input. May also be set with the `ONEBRC_CASE_INSENSITIVE`
+    /// environment variable or the `case-insensitive` key in the config file.
+    #[clap(long, action)]
+    case_insensitive: bool,
+
+    /// Whether to retry with the next best runner if the runner can't run in this environment
+    /// (e.g. mapping the input into memory isn't supported) [default: true unless a runner is
+    /// picked explicitly]
+    ///
+    /// Errors about the input itself never fall back. Each downgrade is shown on stderr. May also
+    /// be set with the `ONEBRC_FALLBACK` environment variable or the `fallback` key in the config
+    /// file.
+    #[clap(long, value_name = "BOOL", num_args = 0..=1, default_missing_value = "true")]
+    fallback: Option<bool>,
+
+    /// How many newline-aligned chunks to split the input into for parallel runners
+    /// [default: one per core]
+    ///
+    /// May also be set with the `ONEBRC_NUM_CHUNKS` environment variable or the `num-chunks` key
+    /// in the config file.
+    #[clap(long)]
+    num_chunks: Option<usize>,
+
+    /// How many threads parallel runners use [default: one per core]
+    ///
+    /// A warning is shown if this is more than the machine can make use of. May also be set with
+    /// the `ONEBRC_THREADS` environment variable or the `threads` key in the config file.
+    #[clap(long)]
+    threads: Option<usize>,
+
+    /// Fraction of the input the `sampled-dense` runner samples for station names [default: 0.01]
+    ///
+    /// May also be set with the `ONEBRC_SAMPLE_FRACTION` environment variable or the
+    /// `sample-fraction` key in the config file.
+    #[clap(long)]
+    sample_fraction: Option<f64>,
+
+    /// Don't print warnings about the configuration or input
+    #[clap(long, action)]
+    no_warnings: bool,
+
+    /// Keep the temporary files the run creates (e.g. spill files), & list them when it's done
+    ///
+    /// Without it, they're removed when the run ends, fails, or is interrupted with Ctrl-C.
+    #[clap(long, action)]
+    keep_temp: bool,
+
+    /// Report more about the input & run on stderr
+    #[clap(short, long, action = clap::ArgAction::Count)]
+    verbose: u8,
+
+    /// Path to a TOML config file
+    ///
+    /// Values set on the command line or in the environment take precedence over those in the
+    /// config file.
+    #[clap(short, long, value_parser)]
+    config: Option<PathBuf>,
+
+    /// Write a JSON report of the effective configuration & timings to this path
+    #[clap(long, value_parser)]
+    report: Option<PathBuf>,
+
+    /// If the run fails, write a JSON report of why to this path
+    ///
+    /// The report has the kind of error, its message, where in the input it happened (if
+    /// anywhere), the effective configuration, the runs which completed, and when it failed. A
+    /// successful run removes a report left at the path by an earlier failure.
+    #[clap(long, value_name = "PATH", value_parser)]
+    error_report: Option<PathBuf>,
+
+    /// Also write the result to this file; may be given more than once
+    ///
+    /// The format of each file may be given with a `:FORMAT` suffix (e.g. `out.json:json`);
+    /// otherwise it is inferred from the file extension. Files are replaced atomically.
+    #[clap(short, long, value_name = "PATH[:FORMAT]")]
+    output: Vec<OutputSpec>,
+
+    /// The format of the result printed to stdout
     #[clap(short, long, default_value_t, value_enum)]
-    runner: Runner,
+    format: Format,
+
+    /// Don't print the result to stdout
+    #[clap(short, long, action)]
+    quiet: bool,
+
+    /// When to colour the `--compare` & `--merge-reports` tables & the `--expect-stations`
+    /// check
+    ///
+    /// With `auto`, only what's printed to a terminal is coloured, & nothing is if `NO_COLOR`
+    /// is set. Results, `--output` files & reports are never coloured.
+    #[clap(long, value_enum, value_name = "WHEN", default_value = "auto")]
+    color: ColorChoice,
+
+    /// Show a live dashboard of the run's progress, the hottest & coldest stations so far, &
+    /// the lines skipped, while it runs
+    ///
+    /// Only shown when stdout is a terminal; otherwise the run goes ahead without it.
+    #[cfg(feature = "tui")]
+    #[clap(long, action, conflicts_with_all = ["bench", "compare", "auto_tune", "quiet"])]
+    tui: bool,
 
     /// Path to the file containing the challenge input
-    #[clap(value_parser)]
-    input: PathBuf,
+    #[clap(value_parser, required_unless_present_any = ["merge_reports", "diff_manifests", "list_runners", "generate", "reduce"])]
+    input: Option<PathBuf>,
 
     /// Benchmark the selected runner
     ///
     /// The runner is invoked five times sequentially with the fastest and slowest times discarded.
     /// Then, the mean & standard deviation of runtimes is displayed.
-    #[clap(short, long, action)]
+    #[clap(short, long, action, conflicts_with = "compare")]
     bench: bool,
+
+    /// Pick the buffer size by timing the runner on the start of the input with each of a few
+    /// sizes, unless it was given explicitly
+    ///
+    /// The pick is shown as `auto-tuned` in the configuration & in `--report`s, so numbers from
+    /// tuned settings can be told apart from those from defaults.
+    #[clap(long, action, conflicts_with_all = ["compare", "explain", "aggregate_partial"])]
+    auto_tune: bool,
+
+    /// With `--bench --auto-tune`, tune again before every run rather than once up front
+    ///
+    /// Each round's pick & how long it took are shown & written to the `--report`, so the cost
+    /// & consistency of tuning can be studied.
+    #[clap(long, action, requires_all = ["bench", "auto_tune"])]
+    retune_each_run: bool,
+
+    /// With `--auto-tune`, tune once up front & use the pick for every run (the default)
+    #[clap(
+        long,
+        action,
+        requires = "auto_tune",
+        conflicts_with = "retune_each_run"
+    )]
+    tune_once: bool,
+
+    /// Run the selected runner this many times, showing each time & the fastest
+    ///
+    /// Unlike `--bench`, nothing is discarded or summarized. The result is only printed once, but
+    /// every run must produce the same result, which makes this a quick check for flaky runners.
+    #[clap(long, value_name = "N", default_value_t = 1, value_parser = clap::value_parser!(u32).range(1..), conflicts_with_all = ["bench", "compare", "explain"])]
+    repeat: u32,
+
+    /// Log the benchmark as a session under this name, or report on the sessions logged under it
+    ///
+    /// With `--bench`, the runs are appended to `<INPUT>.session-<NAME>.jsonl` along with the
+    /// time, the commit the binary was built from, and the effective configuration. With
+    /// `--session-report`, the sessions logged so far are compared instead.
+    #[clap(long, value_name = "NAME")]
+    session: Option<String>,
+
+    /// Report how stable the benchmark results logged for `--session` are across sessions
+    ///
+    /// Shows each session's mean, the overall mean, how the variance between sessions compares
+    /// to the variance within them, and whether the results are drifting over time.
+    #[clap(long, action, requires = "session", conflicts_with_all = ["bench", "compare", "explain"])]
+    session_report: bool,
+
+    /// Compare the `--report`s of benchmarks or comparisons run on different machines
+    ///
+    /// Prints a markdown table of each runner's throughput on each machine, in GB/s and GB/s per
+    /// core, with the fastest runner on each machine in bold. Machines are named after their
+    /// report files.
+    #[clap(long, value_name = "REPORT", num_args = 1.., conflicts_with_all = ["bench", "compare", "explain", "session"])]
+    merge_reports: Vec<PathBuf>,
+
+    /// List each block of the input the run read to this path, for finding where rows went missing
+    ///
+    /// Each block is listed with its byte range, how many measurements were recorded from it,
+    /// and a hash of its bytes. Parallel runners list the blocks their workers aggregated; other
+    /// runners don't read in blocks, so the same ranges are listed from a separate
+    /// single-threaded pass. Compare two manifests with `--diff-manifests`.
+    #[clap(long, value_name = "PATH", value_parser, conflicts_with_all = ["bench", "compare", "explain", "session_report"])]
+    chunk_manifest: Option<PathBuf>,
+
+    /// Only solve these bytes of the input, e.g. `4GiB..5GiB`, for narrowing down where results
+    /// diverge
+    ///
+    /// The start moves forward to the next line (unless it's 0) & the end to the end of the line
+    /// it falls in, so adjacent ranges cover every line exactly once; the aligned range is
+    /// printed before the run. Byte offsets in errors & the rows of `--track-extents` are counted
+    /// from the start of the range, and the station cache is neither read nor written.
+    #[clap(long, value_name = "START..END", conflicts_with_all = ["explain", "auto_tune", "diagnose", "chunk_manifest"])]
+    byte_range: Option<ByteRange>,
+
+    /// Only solve the first LINES lines of the input (or all of it, if there are fewer)
+    ///
+    /// Every runner solves exactly those lines, including the parallel ones, which split the
+    /// bytes up to the end of the last line between their threads like a whole input; the number
+    /// of bytes is printed before the run. Blank & malformed lines count towards the limit.
+    #[clap(long, value_name = "LINES", conflicts_with_all = ["explain", "auto_tune", "diagnose", "chunk_manifest", "byte_range"])]
+    limit: Option<u64>,
+
+    /// Only solve a random sample of about RATE of the input (e.g. 0.01), for a quick look at a
+    /// new input
+    ///
+    /// The input is split into blocks of `--buffer-size` bytes, aligned to lines, and each is
+    /// picked or not from the `--sample-seed`, so the same seed always solves the same lines.
+    /// The results are labelled as sampled, with each station's count; its min & max are only
+    /// those observed in the sample. Byte offsets in errors are counted within the sample, and
+    /// the station cache is neither read nor written.
+    #[clap(long, value_name = "RATE", value_parser = parse_rate, conflicts_with_all = ["explain", "auto_tune", "diagnose", "chunk_manifest", "aggregate_partial", "expect_stations"])]
+    sample_rate: Option<f64>,
+
+    /// The seed the blocks of a `--sample-rate` are picked with [default: 0]
+    #[clap(long, value_name = "SEED", requires = "sample_rate")]
+    sample_seed: Option<u64>,
+
+    /// Find the first block where two `--chunk-manifest`s disagree
+    ///
+    /// Exits with an error describing the block if there is one. The manifests must be of runs
+    /// with the same buffer size & max line length for their blocks to line up.
+    #[clap(long, value_names = ["FIRST", "SECOND"], num_args = 2, conflicts_with_all = ["bench", "compare", "explain", "session", "merge_reports"])]
+    diff_manifests: Vec<PathBuf>,
+
+    /// List every runner with its hasher, whether it runs in parallel, and how reproducible its
+    /// results are, then exit
+    ///
+    /// Bit-exact runners give the same bits on every run; rounded-exact runners only do with the
+    /// same `--threads` & `--buffer-size`, and otherwise agree once the results are rounded.
+    #[clap(long, action, conflicts_with_all = ["bench", "compare", "explain", "merge_reports", "diff_manifests"])]
+    list_runners: bool,
+
+    /// Generate an input laid out in a `--pattern` to this path instead of running, with its
+    /// expected result in `<PATH>.expected`
+    ///
+    /// The expected result is worked out from the pattern rather than by aggregating the input,
+    /// as `--format text` would print it. The same `--seed` always generates the same input.
+    #[clap(long, value_name = "PATH", value_parser, conflicts_with_all = ["bench", "compare", "explain", "merge_reports", "diff_manifests", "list_runners", "verify_repro"])]
+    generate: Option<PathBuf>,
+
+    /// How the lines of a `--generate`d input are laid out [default: round-robin]
+    ///
+    /// `repeated` is the first station on every line, `round-robin` is every station in turn,
+    /// and `sorted` is all of each station's lines together.
+    #[clap(long, value_enum, requires = "generate")]
+    pattern: Option<Pattern>,
+
+    /// How many lines to `--generate` [default: 1000000000]
+    #[clap(long, requires = "generate")]
+    rows: Option<u64>,
+
+    /// How many stations a `--generate`d input has [default: 413]
+    #[clap(long, requires = "generate")]
+    stations: Option<usize>,
+
+    /// The seed a `--generate`d input's measurements are drawn from [default: 0]
+    #[clap(long, requires = "generate")]
+    seed: Option<u64>,
+
+    /// Print the execution plan in the selected `--format` and exit without running
+    ///
+    /// The plan covers how the input will be split up & read, the number of threads, an estimate
+    /// of peak memory use, and which platform fast paths are available & used.
+    #[clap(long, action, conflicts_with_all = ["bench", "compare"])]
+    explain: bool,
+
+    /// After the run, explain what most likely slowed it down
+    ///
+    /// Findings (e.g. skipped lines, too many threads for the machine, or one worker doing most
+    /// of the work) are ranked by a rough estimate of how much of the run each cost. With
+    /// `--chunk-manifest`, the scoped-threads runner's workers are checked for imbalance too.
+    #[clap(long, action, conflicts_with_all = ["bench", "compare", "explain"])]
+    diagnose: bool,
+
+    /// After the run, check the stations in the result against this list of every station
+    /// expected, one per line
+    ///
+    /// Stations in the result which aren't on the list, and stations on the list which aren't in
+    /// the result, are printed to stderr. Names are compared byte for byte. An unexpected station
+    /// is an error in strict mode, and otherwise only a warning; a missing one never is.
+    #[clap(long, value_name = "FILE", value_parser, conflicts_with_all = ["bench", "compare", "explain"])]
+    expect_stations: Option<PathBuf>,
+
+    /// Write the stations on the `--expect-stations` list which had no measurements too, as
+    /// `name=-/-/-` (with `null` stats in JSON, and empty ones in CSV & TSV)
+    ///
+    /// Without this, a station with no measurements is never written.
+    #[clap(long, action, requires = "expect_stations")]
+    emit_missing: bool,
+
+    /// Join the columns of this CSV onto the results written as JSON, CSV or TSV
+    ///
+    /// The first column is the station's name, & every other column is added to each station
+    /// with metadata as it is (numbers as numbers). Stations without any are still written, with
+    /// empty (`null`) columns, and how many there were is printed to stderr. A station may only
+    /// have one row. The text format is unaffected.
+    #[clap(long, value_name = "CSV", value_parser, conflicts_with_all = ["bench", "compare", "explain"])]
+    metadata: Option<PathBuf>,
+
+    /// Benchmark every runner against the input & compare the results
+    ///
+    /// Progress is saved after each runner completes so an interrupted comparison can be picked
+    /// back up with `--resume`.
+    #[clap(long, action)]
+    compare: bool,
+
+    /// After benchmarking, bundle everything needed to reproduce the result into this gzipped
+    /// tarball
+    ///
+    /// The bundle holds the benchmark's report (with the effective configuration, the machine,
+    /// and the allocator), the commit the binary was built from, a hash of the whole input, and a
+    /// hash of the result. Check it with `--verify-repro`.
+    #[clap(long, value_name = "BUNDLE", value_parser, requires = "bench")]
+    export_repro: Option<PathBuf>,
+
+    /// Re-run the benchmark in an `--export-repro` bundle against the input & check it reproduces
+    ///
+    /// The recorded configuration is used in place of any from the command line, environment, or
+    /// a config file, and the input must be the same file the bundle was exported from. Shows
+    /// whether the result matches the recorded one (exiting with an error if not) and how the
+    /// timings compare.
+    #[clap(long, value_name = "BUNDLE", value_parser, conflicts_with_all = ["bench", "compare", "explain", "session_report", "merge_reports", "diff_manifests", "list_runners"])]
+    verify_repro: Option<PathBuf>,
+
+    /// Aggregate the input into a partial result at this path instead of solving it, for
+    /// `--reduce` to merge with the partial results of other shards of the input
+    ///
+    /// The partial result is a small binary file with the name & running stats of each station,
+    /// in a versioned format. First & last rows aren't tracked, since rows are only counted
+    /// within each shard.
+    #[clap(long, value_name = "PATH", value_parser, conflicts_with_all = ["bench", "compare", "explain", "diagnose", "chunk_manifest", "track_extents"])]
+    aggregate_partial: Option<PathBuf>,
+
+    /// Merge the partial results written by `--aggregate-partial` into the result, printed &
+    /// written as usual
+    ///
+    /// No input is needed. Partial results written by a different version of the format are
+    /// refused. Pass the `--key-format` the partial results were aggregated with, as they don't
+    /// record it.
+    #[clap(long, value_name = "PART", num_args = 1.., conflicts_with_all = ["bench", "compare", "explain", "merge_reports", "diff_manifests", "list_runners", "generate", "aggregate_partial"])]
+    reduce: Vec<PathBuf>,
+
+    /// Skip runners whose results were already saved by a previous `--compare`
+    #[clap(long, action, requires = "compare")]
+    resume: bool,
+
+    /// Where `--compare` saves its progress [default: <INPUT>.compare-progress.json]
+    #[clap(long, value_parser, requires = "compare")]
+    progress_file: Option<PathBuf>,
+
+    /// The most memory a runner may use to win a `--compare`, e.g. `2GiB`
+    ///
+    /// Runners whose peak memory use was over the budget are struck through in the table, and
+    /// the fastest runner within it is in bold. Peak memory is only measured on Linux.
+    #[clap(long, value_name = "BYTES", value_parser = parse_bytes, requires = "compare")]
+    memory_budget: Option<u64>,
 }
 
-fn main() -> Result<(), Box<dyn std::error::Error>> {
+impl Args {
+    fn input(&self) -> &Path {
+        self.input
+            .as_deref()
+            .expect("An input is required unless merging reports")
+    }
+}
+
+fn main() -> ExitCode {
     let args = Args::parse();
-    let runner = args.runner;
-    let input = &args.input;
+    TEMP_FILES.keep(args.keep_temp);
+    // An interrupted run never gets as far as dropping the owners of its temporary files
+    if let Err(e) = ctrlc::set_handler(|| {
+        #[cfg(feature = "tui")]
+        onebrc::tui::restore();
+        list_kept(TEMP_FILES.cleanup());
+        std::process::exit(130);
+    }) {
+        eprintln!("Warning: temporary files won't be removed if the run is interrupted ({e})");
+    }
 
-    if args.bench {
-        benchmark(runner, input)
-    } else {
-        run(runner, input, true).and_then(|_| Ok(()))
+    let result = try_main(&args);
+    list_kept(TEMP_FILES.cleanup());
+    // Returning the error from `main` would print its `Debug` form, quoting (& escaping) messages
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("Error: {e}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+/// List the temporary files kept for `--keep-temp`
+fn list_kept(kept: Vec<PathBuf>) {
+    if kept.is_empty() {
+        return;
+    }
+    eprintln!("Kept temporary files:");
+    for path in kept {
+        eprintln!("  {}", path.display());
     }
 }
 
-/// Run the selected [`Runner`] against the provided input.
-/// If `print_output = true`, print the result to stdout.
-/// Return the duration it took to compute the result.
+fn try_main(args: &Args) -> Result<(), Box<dyn std::error::Error>> {
+    if !args.merge_reports.is_empty() {
+        return merge_reports(&args.merge_reports, args.color);
+    }
+    if let [first, second] = args.diff_manifests.as_slice() {
+        return diff_manifests(first, second);
+    }
+    if args.list_runners {
+        list_runners();
+        return Ok(());
+    }
+    if let Some(path) = &args.generate {
+        return generate(args, path);
+    }
+    if let Some(bundle) = &args.verify_repro {
+        return verify_repro(bundle, args.input());
+    }
+    if !args.reduce.is_empty() {
+        return reduce(args);
+    }
+
+    let started = Instant::now();
+    let resolved = resolve_config(args).and_then(|config| auto_tune(args, config));
+    let (config, tuning) = match resolved {
+        Ok(resolved) => resolved,
+        Err(e) => {
+            if let Some(path) = &args.error_report {
+                write_error_report(path, args, None, e.as_ref(), PartialStats::default());
+            }
+            return Err(e);
+        }
+    };
+    eprintln!("{config}\n");
+    if let (Some(requested), Some(window)) = (&args.byte_range, &config.byte_range) {
+        eprintln!(
+            "Solving bytes {}..{} of the input ({}), aligned to lines from {requested}\n",
+            window.start,
+            window.end,
+            fmt_bytes(window.end - window.start)
+        );
+    }
+    if let (Some(lines), Some(window)) = (args.limit, &config.byte_range) {
+        eprintln!(
+            "Solving the first {lines} lines of the input, bytes {}..{} ({})\n",
+            window.start,
+            window.end,
+            fmt_bytes(window.end - window.start)
+        );
+    }
+    if let Some(sample) = &config.sample {
+        eprintln!(
+            "Solving a sample of {sample}, in blocks of {}\n",
+            fmt_bytes(config.buffer_size.value as u64)
+        );
+    }
+
+    let mut completed = Vec::new();
+    let result = run(args, &config, tuning, &mut completed);
+    if let Some(path) = &args.error_report {
+        match &result {
+            Ok(()) => {
+                if let Err(e) = failure::remove_stale(path) {
+                    eprintln!(
+                        "Warning: couldn't remove the error report from a previous run at {}: {e}",
+                        path.display()
+                    );
+                }
+            }
+            Err(e) => {
+                let stats = PartialStats::new(&completed, started.elapsed());
+                write_error_report(path, args, Some(&config), e.as_ref(), stats);
+            }
+        }
+    }
+    result
+}
+
+/// Do whatever the arguments ask for, adding the stats of each completed run to `completed`
 fn run(
-    runner: Runner,
-    input: &Path,
-    print_output: bool,
-) -> Result<Duration, Box<dyn std::error::Error>> {
-    use Runner::*;
-    let f = std::fs::File::open(input)?;
-    let (station_info, duration) = match runner {
-        Baseline => runners::Baseline::run(f),
-        RustcHash => runners::RustcHash::run(f),
-        AHash => runners::AHash::run(f),
-    }?;
+    args: &Args,
+    config: &Config,
+    tuning: Option<Tunings>,
+    completed: &mut Vec<RunStats>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    if args.session.is_some() && !(args.bench || args.session_report) {
+        return Err("--session needs either --bench or --session-report".into());
+    }
+    if args.session_report {
+        let log = session_log(args, config)?;
+        let report = SessionReport::analyze(&log.load()?)?;
+        match args.format {
+            Format::Text => outln!("{report}"),
+            Format::Json => outln!("{}", serde_json::to_string_pretty(&report)?),
+            Format::Csv | Format::Tsv => {
+                return Err("--session-report can only be printed as text or json".into())
+            }
+        }
+        return Ok(());
+    }
+
+    if args.explain {
+        let plan = Plan::build(config)?;
+        match args.format {
+            Format::Text => outln!("{plan}"),
+            Format::Json => outln!("{}", serde_json::to_string_pretty(&plan)?),
+            Format::Csv | Format::Tsv => {
+                return Err("--explain can only be printed as text or json".into())
+            }
+        }
+        return Ok(());
+    }
+
+    if let Some(path) = &args.aggregate_partial {
+        let input = std::fs::File::open(&config.canonical_input.value)?;
+        let stations = match &config.byte_range {
+            Some(window) => partial::aggregate(Window::new(input, window.clone())?, config)?,
+            None => partial::aggregate(input, config)?,
+        };
+        partial::write(path, &stations)?;
+        eprintln!(
+            "Wrote a partial result of {} stations to {}",
+            stations.len(),
+            path.display()
+        );
+        return Ok(());
+    }
+
+    let topology = Topology::detect(&config.canonical_input.value);
+    if config.warnings {
+        if let Some(warning) = runners::parallelism_warning(config, &topology) {
+            eprintln!("Warning: {warning}\n");
+        }
+    }
+
+    let report = if args.bench {
+        let (report, result) = benchmark(config, topology, tuning, completed)?;
+        if let Some(path) = &args.export_repro {
+            Bundle::new(&report, &result)?.write(path)?;
+            eprintln!("Wrote a reproducibility bundle to {}", path.display());
+        }
+        if args.session.is_some() {
+            let log = session_log(args, config)?;
+            let samples = report.runs.iter().map(|t| t.total).collect();
+            log.append(&Session::new(config, samples))?;
+            eprintln!("Logged the session to {}", log.path().display());
+        }
+        report
+    } else if args.compare {
+        let progress_path = args.progress_file.clone().unwrap_or_else(|| {
+            let mut path = config.canonical_input.value.clone().into_os_string();
+            path.push(".compare-progress.json");
+            PathBuf::from(path)
+        });
+        let results = compare::compare(
+            config,
+            &Candidate::all(config),
+            BENCH_RUNS,
+            &progress_path,
+            args.resume,
+        )?;
+
+        let styler = Styler::stdout(args.color);
+        outln!(
+            "\n{}",
+            compare::render_table(&results, args.memory_budget, &styler)
+        );
+        if let Some(footnote) = compare::settings_footnote(config) {
+            outln!("{footnote}");
+        }
+        compare::check_outputs(&results)?;
+
+        Report {
+            config,
+            topology,
+            runs: Vec::new(),
+            mean: None,
+            std_dev: None,
+            aggregated: None,
+            skipped: SkippedLines::default(),
+            fallback: None,
+            compare: results,
+            tuning: None,
+        }
+    } else {
+        // Loaded up front, so a bad file is found before the run rather than after it
+        let metadata = load_metadata(args)?;
+        let expected = load_expected(args)?;
+        let (station_info, runs) = run_repeatedly(config, args.repeat, args.quiet, completed)?;
+
+        let mut sinks = MultiSink::new();
+        if !args.quiet {
+            sinks.push(Box::new(StdoutSink::new(args.format)));
+        }
+        for spec in &args.output {
+            sinks.push_file(&spec.path, spec.format);
+        }
+        join_metadata(&mut sinks, metadata, &station_info);
+        if let Some(sample) = config.sample {
+            sinks.sampled(sample);
+        }
+        sinks.emit(&emitted(args, &expected, &station_info))?;
+        if let Some(path) = &args.chunk_manifest {
+            write_chunk_manifest(path, config)?;
+        }
+
+        if !args.quiet {
+            let fastest = runs
+                .iter()
+                .min_by_key(|t| t.total)
+                .expect("There is at least one run");
+            if runs.len() == 1 {
+                outln!("\nSolved in {fastest}");
+            } else {
+                outln!("\nBest of {}: {fastest}", runs.len());
+            }
+            // Only the pipelined runner sizes its blocks as it goes
+            if let Some(sizes) = config.block_sizes.as_ref().filter(|log| !log.is_empty()) {
+                outln!("Block sizes: {sizes}");
+            }
+        }
+        if args.diagnose {
+            let stats = *completed.last().expect("There is at least one run");
+            print_diagnosis(config, &topology, &station_info, stats)?;
+        }
+        if let Some((path, expected)) = &expected {
+            check_stations(
+                path,
+                expected,
+                &station_info,
+                config.strict.value,
+                args.color,
+            )?;
+        }
+
+        Report {
+            config,
+            topology,
+            runs,
+            mean: None,
+            std_dev: None,
+            aggregated: None,
+            skipped: completed.last().map(|s| s.skipped).unwrap_or_default(),
+            fallback: completed.last().and_then(|s| s.fallback),
+            compare: Vec::new(),
+            tuning,
+        }
+    };
+
+    if let Some(path) = &args.report {
+        report.write(path)?;
+    }
+
+    Ok(())
+}
+
+/// Write a report of `error` for `--error-report`.
+///
+/// Failing to do so is only a warning, so it doesn't hide the error being reported.
+fn write_error_report(
+    path: &Path,
+    args: &Args,
+    config: Option<&Config>,
+    error: &(dyn std::error::Error + 'static),
+    stats: PartialStats,
+) {
+    let report = ErrorReport::new(error, args.input(), config, stats);
+    match report.write(path) {
+        Ok(()) => eprintln!("Wrote an error report to {}", path.display()),
+        Err(e) => eprintln!(
+            "Warning: couldn't write the error report to {}: {e}",
+            path.display()
+        ),
+    }
+}
+
+/// Explain what most likely slowed the last run down for `--diagnose`
+fn print_diagnosis(
+    config: &Config,
+    topology: &Topology,
+    stations: &[StationInfo],
+    stats: RunStats,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let input = &config.canonical_input.value;
+    let len = std::fs::metadata(input)?.len();
+    let mut facts = Facts::new(
+        config.runner.value,
+        config.threads.value,
+        topology.clone(),
+        len,
+        stations,
+        stats,
+    );
+    // Each of the scoped-threads runner's workers has a range of its own, so what each one did
+    // can be worked out from the blocks they recorded
+    if let (Runner::ScopedThreads, Some(recorder)) = (config.runner.value, &config.chunk_manifest) {
+        let mut f = std::fs::File::open(input)?;
+        let ranges = chunk_boundaries(
+            &mut f,
+            len,
+            config.threads.value,
+            config.max_line_length.value,
+        )?;
+        facts = facts.worker_rows(&recorder.entries(), &ranges);
+    }
+
+    let findings = diagnose(&facts);
+    if findings.is_empty() {
+        outln!("\nDiagnosis: nothing obviously slowed the run down");
+        return Ok(());
+    }
+    outln!("\nDiagnosis (most likely first):");
+    for finding in findings {
+        outln!("  {finding}");
+    }
+    Ok(())
+}
+
+/// Write the blocks the last run read for `--chunk-manifest`
+fn write_chunk_manifest(path: &Path, config: &Config) -> Result<(), Box<dyn std::error::Error>> {
+    let manifest = match &config.chunk_manifest {
+        Some(recorder) if config.runner.value.is_parallel() => Manifest {
+            runner: config.runner.value.to_string(),
+            reference: false,
+            chunks: recorder.entries(),
+        },
+        _ => {
+            let input = std::fs::File::open(&config.canonical_input.value)?;
+            Manifest::reference(input, config)?
+        }
+    };
+    manifest.write(path)?;
+    eprintln!(
+        "Wrote a manifest of {} blocks ({} rows) to {}",
+        manifest.chunks.len(),
+        manifest.rows(),
+        path.display()
+    );
+    Ok(())
+}
 
-    if print_output {
-        // Display the results with wrapping '{ ... }' and ',' between each entry, but
-        // not following the last entry.
-        print!("{{");
-        for i in 0..(station_info.len() - 1) {
-            print!("{}", station_info[i]);
-            print!(", ");
+/// Compare two manifests for `--diff-manifests`
+fn diff_manifests(first: &Path, second: &Path) -> Result<(), Box<dyn std::error::Error>> {
+    let (a, b) = (Manifest::load(first)?, Manifest::load(second)?);
+    match a.diff(&b) {
+        Some(divergence) => Err(format!("The manifests diverge: {divergence}").into()),
+        None => {
+            outln!(
+                "The manifests match: {} blocks, {} rows",
+                a.chunks.len(),
+                a.rows()
+            );
+            Ok(())
         }
-        println!("{}}}\n", station_info.iter().last().unwrap());
-        println!("Solved in {}", fmt_duration(&duration));
     }
+}
+
+/// Print a markdown table of the runners for `--list-runners`
+fn list_runners() {
+    outln!("| Runner | Hasher | Parallel | Determinism |");
+    outln!("| ------ | ------ | -------- | ----------- |");
+    for &runner in Runner::value_variants() {
+        outln!(
+            "| {runner} | {} | {} | {} |",
+            runner.hasher(),
+            if runner.is_parallel() { "yes" } else { "no" },
+            runner.determinism()
+        );
+    }
+}
+
+/// Write an input & its expected result for `--generate`
+fn generate(args: &Args, path: &Path) -> Result<(), Box<dyn std::error::Error>> {
+    let generator = Generator::new(
+        args.pattern.unwrap_or_default(),
+        args.rows.unwrap_or(1_000_000_000),
+        args.stations.unwrap_or(413),
+        args.seed.unwrap_or_default(),
+    );
+    generator.write(std::fs::File::create(path)?)?;
+
+    let mut expected_path = path.as_os_str().to_owned();
+    expected_path.push(".expected");
+    let expected_path = PathBuf::from(expected_path);
+    let expected = Format::Text.render(&generator.expected());
+    std::fs::write(&expected_path, format!("{expected}\n"))?;
+
+    eprintln!(
+        "Generated {} lines to {}, with the expected result in {}",
+        generator.rows,
+        path.display(),
+        expected_path.display()
+    );
+    Ok(())
+}
 
-    Ok(duration)
+/// Check a bundle reproduces against `input` for `--verify-repro`
+fn verify_repro(path: &Path, input: &Path) -> Result<(), Box<dyn std::error::Error>> {
+    let verification = Bundle::load(path)?.verify(input, BENCH_RUNS)?;
+    for warning in &verification.warnings {
+        eprintln!("Warning: {warning}");
+    }
+    outln!("{verification}");
+    if !verification.matches() {
+        return Err("The result doesn't match the bundle's".into());
+    }
+    Ok(())
+}
+
+/// Print the matrix of runners & machines for `--merge-reports`
+fn merge_reports(paths: &[PathBuf], color: ColorChoice) -> Result<(), Box<dyn std::error::Error>> {
+    let machines: Vec<Machine> = paths
+        .iter()
+        .map(|path| Machine::load(path))
+        .collect::<Result<_, _>>()?;
+    let matrix = Matrix::build(&machines);
+    for warning in &matrix.warnings {
+        eprintln!("Warning: {warning}");
+    }
+    outln!("{}", matrix.render(&Styler::stdout(color)));
+    Ok(())
+}
+
+/// Merge the partial results of `--reduce` & emit the result like a run would
+fn reduce(args: &Args) -> Result<(), Box<dyn std::error::Error>> {
+    let metadata = load_metadata(args)?;
+    let expected = load_expected(args)?;
+    let partials = args
+        .reduce
+        .iter()
+        .map(|path| partial::read(path))
+        .collect::<Result<Vec<_>, _>>()?;
+    // Partial results don't record how they were keyed
+    let key_format = args.key_format.unwrap_or_default();
+    let station_info: Vec<StationInfo> = partial::reduce(partials, args.case_insensitive)
+        .into_iter()
+        .map(|station| station.keyed_by(key_format))
+        .collect();
+
+    let mut sinks = MultiSink::new();
+    if !args.quiet {
+        sinks.push(Box::new(StdoutSink::new(args.format)));
+    }
+    for spec in &args.output {
+        sinks.push_file(&spec.path, spec.format);
+    }
+    join_metadata(&mut sinks, metadata, &station_info);
+    sinks.emit(&emitted(args, &expected, &station_info))?;
+    if let Some((path, expected)) = &expected {
+        check_stations(path, expected, &station_info, args.strict, args.color)?;
+    }
+    Ok(())
+}
+
+/// Load the file given with `--metadata`, if any
+fn load_metadata(args: &Args) -> Result<Option<(&Path, Metadata)>, Box<dyn std::error::Error>> {
+    let Some(path) = &args.metadata else {
+        return Ok(None);
+    };
+    Ok(Some((path, Metadata::load(path)?)))
+}
+
+/// Load the list given with `--expect-stations`, if any
+fn load_expected(
+    args: &Args,
+) -> Result<Option<(&Path, ExpectedStations)>, Box<dyn std::error::Error>> {
+    let Some(path) = &args.expect_stations else {
+        return Ok(None);
+    };
+    Ok(Some((path, ExpectedStations::load(path)?)))
+}
+
+/// The stations to write: the result, plus the expected stations it doesn't have with
+/// `--emit-missing`
+fn emitted<'a>(
+    args: &Args,
+    expected: &Option<(&Path, ExpectedStations)>,
+    station_info: &'a [StationInfo],
+) -> Cow<'a, [StationInfo]> {
+    match expected {
+        Some((_, expected)) if args.emit_missing => Cow::Owned(expected.with_missing(station_info)),
+        _ => Cow::Borrowed(station_info),
+    }
+}
+
+/// Join the `--metadata` onto the results written, saying how many stations it had no row for
+fn join_metadata(
+    sinks: &mut MultiSink,
+    metadata: Option<(&Path, Metadata)>,
+    station_info: &[StationInfo],
+) {
+    let Some((path, metadata)) = metadata else {
+        return;
+    };
+    let unmatched = metadata.unmatched(station_info);
+    if unmatched > 0 {
+        eprintln!(
+            "{unmatched} of {} stations have no metadata in {}",
+            station_info.len(),
+            path.display()
+        );
+    }
+    sinks.metadata(metadata);
+}
+
+/// Check the stations in a result against the list in `path` for `--expect-stations`
+fn check_stations(
+    path: &Path,
+    expected: &ExpectedStations,
+    station_info: &[StationInfo],
+    strict: bool,
+    color: ColorChoice,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let check = expected.check(station_info);
+    eprintln!("\n{}", check.render(&Styler::stderr(color)));
+    if check.passed() {
+        return Ok(());
+    }
+    let unexpected = check.unexpected.len();
+    let message = format!(
+        "{unexpected} station{} in the result {} not in {}",
+        if unexpected == 1 { "" } else { "s" },
+        if unexpected == 1 { "is" } else { "are" },
+        path.display()
+    );
+    if strict {
+        return Err(message.into());
+    }
+    eprintln!("Warning: {message}");
+    Ok(())
+}
+
+/// Build the effective [`Config`] from the command line, environment, and config file
+fn resolve_config(args: &Args) -> Result<Config, Box<dyn std::error::Error>> {
+    let cli = Layer {
+        runner: args.runner,
+        buffer_size: args.buffer_size,
+        max_line_length: args.max_line_length,
+        station_cache: args.station_cache.then_some(true),
+        strict: args.strict.then_some(true),
+        num_chunks: args.num_chunks,
+        threads: args.threads,
+        sample_fraction: args.sample_fraction,
+        timings: args.timings.then_some(true),
+        key_format: args.key_format,
+        track_extents: args.track_extents.then_some(true),
+        case_insensitive: args.case_insensitive.then_some(true),
+        fallback: args.fallback,
+        max_skipped: args.max_skipped,
+        delimiter: args.delimiter,
+        decimal_comma: args.decimal_comma.then_some(true),
+        encoding: args.encoding,
+        max_memory: args.max_memory,
+        block_sizing: args.block_sizing,
+    };
+    let env = Layer::from_env(|key| std::env::var(key).ok())?;
+    let file = match &args.config {
+        Some(path) => Layer::from_file(path)?,
+        None => Layer::default(),
+    };
+
+    // An input which can't be found is reported here, naming it as the user typed it, rather
+    // than by a runner failing to open it
+    let input = args.input();
+    let canonical_input =
+        std::fs::canonicalize(input).map_err(|e| format!("{}: {e}", input.display()))?;
+    let mut config = Config::resolve_canonical(input, canonical_input, cli, env, file);
+    config.verbose = args.verbose;
+    config.warnings = !args.no_warnings;
+    config.chunk_manifest = args.chunk_manifest.is_some().then(Default::default);
+    config.block_sizes = (config.timings.value && config.block_sizing.value != BlockSizing::Fixed)
+        .then(Default::default);
+    #[cfg(feature = "tui")]
+    if args.tui {
+        use std::io::IsTerminal;
+        if std::io::stdout().is_terminal() {
+            config.progress = Some(Default::default());
+        } else if config.warnings {
+            eprintln!("Warning: not showing --tui, as stdout isn't a terminal");
+        }
+    }
+    // Each run of a benchmark builds its results in the storage of the last one's
+    config.results_buffer = args.bench.then(Default::default);
+
+    if let Some(range) = &args.byte_range {
+        let mut input = std::fs::File::open(&config.canonical_input.value)?;
+        let len = input.metadata()?.len();
+        config.byte_range = Some(range.align(&mut input, len, config.max_line_length.value)?);
+    }
+    if let Some(lines) = args.limit {
+        let mut input = std::fs::File::open(&config.canonical_input.value)?;
+        let len = input.metadata()?.len();
+        config.byte_range = Some(first_lines(&mut input, len, lines)?);
+    }
+    config.sample = args
+        .sample_rate
+        .map(|rate| Sample::new(rate, args.sample_seed.unwrap_or(0)));
+    Ok(config)
+}
+
+/// Tune the settings left at their defaults for `--auto-tune`, returning the tuned configuration
+/// along with how it was tuned
+fn auto_tune(
+    args: &Args,
+    config: Config,
+) -> Result<(Config, Option<Tunings>), Box<dyn std::error::Error>> {
+    if !args.auto_tune {
+        return Ok((config, None));
+    }
+    let Some(tuning) = Tuning::run(&config)? else {
+        if config.warnings {
+            eprintln!("Warning: there's nothing to tune, as the buffer size was given explicitly");
+        }
+        return Ok((config, None));
+    };
+    eprintln!("{tuning}");
+
+    let mode = if args.retune_each_run {
+        Mode::EachRun
+    } else {
+        Mode::Once
+    };
+    let tuned = tuning.apply(&config);
+    let tunings = Tunings {
+        mode,
+        rounds: vec![tuning],
+    };
+    Ok((tuned, Some(tunings)))
+}
+
+/// The log of the sessions named by `--session`
+fn session_log(args: &Args, config: &Config) -> Result<SessionLog, Box<dyn std::error::Error>> {
+    let name = args.session.as_deref().unwrap_or_default();
+    SessionLog::for_input(&config.canonical_input.value, name)
+}
+
+/// Run the selected [`Runner`] `repeat` times, returning the result of the last run along with
+/// the duration of each.
+///
+/// When running more than once, each run's time is printed as it completes (unless `quiet`), and
+/// it's an error for any run to produce a different result from the first. The stats of each run
+/// are added to `completed` as it completes.
+fn run_repeatedly(
+    config: &Config,
+    repeat: u32,
+    quiet: bool,
+    completed: &mut Vec<RunStats>,
+) -> Result<(Vec<StationInfo>, Vec<Timings>), Box<dyn std::error::Error>> {
+    let mut runs = Vec::with_capacity(repeat as usize);
+    let mut first_hash = None;
+    let mut result = Vec::new();
+    for i in 1..=repeat {
+        let (station_info, stats) = run_watched(config)?;
+        completed.push(stats);
+        warn_ignored(config, &stats);
+        let timings = stats.timings;
+        if repeat > 1 {
+            if !quiet {
+                outln!("Run {i}: {timings}");
+            }
+            let hash = fnv1a(Format::Text.render(&station_info).as_bytes());
+            if *first_hash.get_or_insert(hash) != hash {
+                return Err(format!(
+                    "Run {i} of {} produced a different result than run 1",
+                    config.runner.value
+                )
+                .into());
+            }
+        }
+        runs.push(timings);
+        result = station_info;
+    }
+    Ok((result, runs))
+}
+
+/// Solve the challenge once, on the `--tui` dashboard if there is one
+fn run_watched(config: &Config) -> ChallengeResult {
+    #[cfg(feature = "tui")]
+    if let Some(progress) = &config.progress {
+        return onebrc::tui::watch(progress, || runners::run(config));
+    }
+    runners::run(config)
+}
+
+/// Warn about any measurements a run left out of its results
+fn warn_ignored(config: &Config, stats: &RunStats) {
+    if config.warnings && stats.ignored_non_finite > 0 {
+        eprintln!(
+            "Warning: ignored {} non-finite measurements",
+            stats.ignored_non_finite
+        );
+    }
+    if config.warnings && stats.skipped.total() > 0 {
+        eprintln!("Warning: {}", stats.skipped);
+    }
 }
 
 /// Benchmark the selected [`Runner`] using the provided input
@@ -128,54 +1287,97 @@ fn run(
 /// The runner is invoked five times. The fastest and slowest times are discarded.
 /// Then, the mean and standard deviation of runs is calculated.
 ///
-/// All times as well as the benchmark result are shown to the user.
-fn benchmark(runner: Runner, input: &Path) -> Result<(), Box<dyn std::error::Error>> {
+/// All times as well as the benchmark result are shown to the user. The stats of each run are
+/// added to `completed` as it completes, and the result of the last run is returned along with
+/// the report.
+fn benchmark<'a>(
+    config: &'a Config,
+    topology: Topology,
+    mut tuning: Option<Tunings>,
+    completed: &mut Vec<RunStats>,
+) -> Result<(Report<'a>, Vec<StationInfo>), Box<dyn std::error::Error>> {
     // Collect the run results
-    let durations: Result<Vec<Duration>, _> = (1..=5)
-        .into_iter()
-        .map(|i| {
-            run(runner, input, false).and_then(|duration| {
-                println!("Run {i}: {}", fmt_duration(&duration));
-                Ok(duration)
-            })
-        })
-        .collect();
-    let mut durations = durations?;
-    durations.sort();
-
-    // Drop the highest & lowest runs
-    let durations = &durations[1..4];
-
-    // Compute some basic stats
-    let duration_millis: Vec<_> = durations
-        .iter()
-        .map(|d| d.as_secs() * 1000 + d.subsec_millis() as u64)
-        .collect();
-    let mean =
-        duration_millis.iter().map(|&m| m as f64).sum::<f64>() / duration_millis.len() as f64;
-    let variance = duration_millis
-        .iter()
-        .map(|&m| (m as f64 - mean).powf(2.0))
-        .sum::<f64>()
-        / duration_millis.len() as f64;
-    let std_dev = variance.sqrt();
+    let mut result = Vec::new();
+    let mut runs = Vec::with_capacity(BENCH_RUNS);
+    for i in 1..=BENCH_RUNS {
+        // The first run uses the settings tuned up front
+        let retuned;
+        let (run_config, round) = match &mut tuning {
+            Some(tunings) if tunings.mode == Mode::EachRun => {
+                if i > 1 {
+                    let round = Tuning::run(config)?.expect("The settings were tuned before");
+                    retuned = round.apply(config);
+                    tunings.rounds.push(round);
+                    (&retuned, tunings.rounds.last())
+                } else {
+                    (config, tunings.rounds.last())
+                }
+            }
+            _ => (config, None),
+        };
 
-    // Convert the stats back to durations & print the result
-    let mean = Duration::from_millis(mean.ceil() as u64);
-    let std_dev = Duration::from_millis(std_dev.ceil() as u64);
+        let (station_info, stats) = runners::run(run_config)?;
+        config.recycle_results(std::mem::replace(&mut result, station_info));
+        completed.push(stats);
+        warn_ignored(config, &stats);
+        match round {
+            Some(round) => outln!(
+                "Run {i}: {} (buffer size {} tuned in {})",
+                stats.timings,
+                round.buffer_size,
+                fmt_duration(&round.elapsed)
+            ),
+            None => outln!("Run {i}: {}", stats.timings),
+        }
+        runs.push(stats.timings);
+    }
+    let totals: Vec<Duration> = runs.iter().map(|t| t.total).collect();
+    let BenchStats { mean, std_dev } = BenchStats::from_runs(&totals);
 
-    println!(
+    outln!(
         "\nMean: {} ± {}",
         fmt_duration(&mean),
         fmt_duration(&std_dev)
     );
-    Ok(())
-}
 
-/// Helper function to format a [`Duration`] with a nice seconds/ms structure
-fn fmt_duration(duration: &Duration) -> String {
-    // Display the time it took to compute the results
-    let seconds = duration.as_secs();
-    let millis = duration.subsec_millis();
-    format!("{seconds}s {millis:0>3}ms")
+    // Only if every run timed its aggregation, which they all do if any does
+    let aggregated: Option<Vec<Duration>> = runs.iter().map(|t| t.aggregated).collect();
+    let aggregated = aggregated.map(|aggregated| BenchStats::from_runs(&aggregated));
+    if let Some(stats) = &aggregated {
+        outln!(
+            "Mean aggregation: {} ± {}",
+            fmt_duration(&stats.mean),
+            fmt_duration(&stats.std_dev)
+        );
+    }
+
+    if let Some(tunings) = tuning.as_ref().filter(|t| t.mode == Mode::EachRun) {
+        let elapsed: Vec<Duration> = tunings.rounds.iter().map(|t| t.elapsed).collect();
+        let stats = BenchStats::from_runs(&elapsed);
+        let picks: Vec<String> = tunings
+            .rounds
+            .iter()
+            .map(|t| t.buffer_size.to_string())
+            .collect();
+        outln!(
+            "Mean tuning: {} ± {} (buffer sizes picked: {})",
+            fmt_duration(&stats.mean),
+            fmt_duration(&stats.std_dev),
+            picks.join(", ")
+        );
+    }
+
+    let report = Report {
+        config,
+        topology,
+        runs,
+        mean: Some(mean),
+        std_dev: Some(std_dev),
+        aggregated,
+        skipped: completed.last().map(|s| s.skipped).unwrap_or_default(),
+        fallback: completed.last().and_then(|s| s.fallback),
+        compare: Vec::new(),
+        tuning,
+    };
+    Ok((report, result))
 }
@@ -0,0 +1,373 @@
+// 1BRC - my take on the 1 Billion Row Challenge
+// Copyright (C) 2024  Charles German <5donuts@pm.me>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Per-block manifests of a run, for finding where a parallel run lost rows.
+//!
+//! With `--chunk-manifest`, each block of the input a parallel runner aggregates is listed with
+//! its byte range, how many measurements were recorded from it, and a hash of its bytes. Other
+//! runners don't read the input in blocks, so for them the same ranges are listed from a
+//! single-threaded [reference pass](Manifest::reference) instead. [`Manifest::diff`] then finds
+//! the first block where two manifests disagree.
+
+use std::fmt;
+use std::io::{self, Read, Seek};
+use std::ops::Range;
+use std::path::Path;
+use std::sync::Mutex;
+
+use serde::{Deserialize, Serialize};
+
+use crate::blocks::{Block, BlockReader};
+use crate::config::Config;
+use crate::error::{ChallengeError, SkippedLines};
+use crate::fingerprint::fnv1a;
+
+/// What was read from a single block of the input
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ChunkEntry {
+    /// Byte offset of the start of the block
+    pub start: u64,
+
+    /// Byte offset just past the end of the block
+    pub end: u64,
+
+    /// How many measurements were recorded from the block
+    pub rows: u64,
+
+    /// The 64-bit FNV-1a hash of the block's bytes
+    pub hash: u64,
+}
+
+impl ChunkEntry {
+    pub fn new<D: AsRef<[u8]>>(block: &Block<D>, rows: u64) -> Self {
+        let data = block.data.as_ref();
+        Self {
+            start: block.offset,
+            end: block.offset + data.len() as u64,
+            rows,
+            hash: fnv1a(data),
+        }
+    }
+
+    fn range(&self) -> Range<u64> {
+        self.start..self.end
+    }
+}
+
+/// Collects the [`ChunkEntry`]s of a run from its workers
+#[derive(Debug, Default)]
+pub struct ManifestRecorder {
+    entries: Mutex<Vec<ChunkEntry>>,
+}
+
+impl ManifestRecorder {
+    /// Forget the entries of any previous run
+    pub fn start(&self) {
+        self.lock().clear();
+    }
+
+    pub fn record(&self, entry: ChunkEntry) {
+        self.lock().push(entry);
+    }
+
+    /// The entries recorded since the run started, in the order of the input
+    pub fn entries(&self) -> Vec<ChunkEntry> {
+        let mut entries = self.lock().clone();
+        entries.sort_unstable_by_key(|entry| entry.start);
+        entries
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Vec<ChunkEntry>> {
+        // A worker which panicked while holding the lock fails the whole run anyway
+        self.entries.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// The blocks a run read, as written by `--chunk-manifest`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Manifest {
+    /// The runner which read the blocks
+    pub runner: String,
+
+    /// Whether the blocks were listed by a reference pass, rather than by the runner
+    pub reference: bool,
+
+    pub chunks: Vec<ChunkEntry>,
+}
+
+impl Manifest {
+    /// List the blocks `config` splits the input into from a single-threaded pass, reading them
+    /// exactly as a parallel runner's workers would.
+    pub fn reference<R: Read + Seek>(input: R, config: &Config) -> Result<Self, ChallengeError> {
+        let mut reader = BlockReader::new(input, config);
+        let mut skipped = SkippedLines::default();
+        let mut chunks = Vec::new();
+        loop {
+            let block = match reader.next_block() {
+                Ok(Some(block)) => block,
+                Ok(None) => break,
+                // Lines too long for a block aren't in any block
+                Err(ChallengeError::LineTooLong { .. }) => continue,
+                Err(e) => return Err(e),
+            };
+            // Only the rows matter here; the run itself reports any malformed lines
+            let mut rows = 0;
+            let _ = block.for_each_record(
+                config.max_line_length.value,
+                config.strict.value,
+                config.dialect(),
+                u64::MAX,
+                &mut skipped,
+                |_, _, _| rows += 1,
+            );
+            chunks.push(ChunkEntry::new(&block, rows));
+        }
+
+        Ok(Self {
+            runner: config.runner.value.to_string(),
+            reference: true,
+            chunks,
+        })
+    }
+
+    pub fn load(path: &Path) -> Result<Self, Box<dyn std::error::Error>> {
+        let contents = std::fs::read_to_string(path)?;
+        serde_json::from_str(&contents)
+            .map_err(|e| format!("Invalid chunk manifest {}: {e}", path.display()).into())
+    }
+
+    pub fn write(&self, path: &Path) -> io::Result<()> {
+        let json = serde_json::to_string_pretty(self)?;
+        std::fs::write(path, json + "\n")
+    }
+
+    /// The total number of measurements recorded
+    pub fn rows(&self) -> u64 {
+        self.chunks.iter().map(|chunk| chunk.rows).sum()
+    }
+
+    /// The first block where this manifest & `other` disagree, if any
+    pub fn diff(&self, other: &Self) -> Option<Divergence> {
+        let longest = self.chunks.len().max(other.chunks.len());
+        (0..longest).find_map(|index| {
+            let kind = match (self.chunks.get(index), other.chunks.get(index)) {
+                (Some(a), Some(b)) if a.range() != b.range() => DivergenceKind::Range {
+                    first: a.range(),
+                    second: b.range(),
+                },
+                (Some(a), Some(b)) if a.rows != b.rows => DivergenceKind::Rows {
+                    range: a.range(),
+                    first: a.rows,
+                    second: b.rows,
+                },
+                (Some(a), Some(b)) if a.hash != b.hash => DivergenceKind::Hash { range: a.range() },
+                (Some(_), Some(_)) => return None,
+                (Some(a), None) => DivergenceKind::Missing {
+                    range: a.range(),
+                    in_first: true,
+                },
+                (None, Some(b)) => DivergenceKind::Missing {
+                    range: b.range(),
+                    in_first: false,
+                },
+                (None, None) => unreachable!("Both manifests have fewer than {longest} blocks"),
+            };
+            Some(Divergence { index, kind })
+        })
+    }
+}
+
+/// The first block where two [`Manifest`]s disagree
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Divergence {
+    /// The (0-based) position of the block in the manifests
+    pub index: usize,
+    pub kind: DivergenceKind,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DivergenceKind {
+    /// The blocks cover different bytes, so the input was split up differently (e.g. with a
+    /// different buffer size)
+    Range {
+        first: Range<u64>,
+        second: Range<u64>,
+    },
+
+    /// A different number of measurements was recorded from the same bytes
+    Rows {
+        range: Range<u64>,
+        first: u64,
+        second: u64,
+    },
+
+    /// The bytes themselves are different, so the input changed between the runs
+    Hash { range: Range<u64> },
+
+    /// Only one of the manifests has the block
+    Missing { range: Range<u64>, in_first: bool },
+}
+
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let index = self.index;
+        match &self.kind {
+            DivergenceKind::Range { first, second } => write!(
+                f,
+                "block {index} covers bytes {first:?} in the first manifest but {second:?} in the second"
+            ),
+            DivergenceKind::Rows {
+                range,
+                first,
+                second,
+            } => write!(
+                f,
+                "block {index} (bytes {range:?}) has {first} rows in the first manifest but {second} in the second"
+            ),
+            DivergenceKind::Hash { range } => write!(
+                f,
+                "block {index} (bytes {range:?}) has different contents in each manifest"
+            ),
+            DivergenceKind::Missing { range, in_first } => write!(
+                f,
+                "block {index} (bytes {range:?}) is only in the {} manifest",
+                if *in_first { "first" } else { "second" }
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{Setting, Source};
+    use crate::helpers::ChallengeRunner;
+    use crate::runners::tests::*;
+    use crate::runners::SampledDense;
+    use crate::Runner;
+    use std::error;
+    use std::sync::Arc;
+
+    /// The manifest of a sampled-dense run over the test data, a few lines per block
+    fn parallel_manifest(config: &Config) -> Result<Manifest, Box<dyn error::Error>> {
+        let recorder = Arc::new(ManifestRecorder::default());
+        let config = Config {
+            chunk_manifest: Some(Arc::clone(&recorder)),
+            ..config.clone()
+        };
+        SampledDense::run(io::Cursor::new(TEST_DATA.as_bytes()), &config)?;
+        Ok(Manifest {
+            runner: Runner::SampledDense.to_string(),
+            reference: false,
+            chunks: recorder.entries(),
+        })
+    }
+
+    fn config() -> Config {
+        Config {
+            runner: Setting::new(Runner::SampledDense, Source::Cli),
+            buffer_size: Setting::new(40, Source::Cli),
+            max_line_length: Setting::new(20, Source::Cli),
+            threads: Setting::new(3, Source::Cli),
+            ..Config::default()
+        }
+    }
+
+    #[test]
+    fn sums_to_totals() -> Result<(), Box<dyn error::Error>> {
+        let config = config();
+        let manifest = parallel_manifest(&config)?;
+        assert!(manifest.chunks.len() > 3, "{manifest:?}");
+
+        // The blocks cover the whole input, in order, & every row is in one of them
+        let mut end = 0;
+        for chunk in &manifest.chunks {
+            assert_eq!(chunk.start, end);
+            let bytes = &TEST_DATA.as_bytes()[chunk.start as usize..chunk.end as usize];
+            assert_eq!(chunk.hash, fnv1a(bytes));
+            end = chunk.end;
+        }
+        assert_eq!(end, TEST_DATA.len() as u64);
+        let total: u32 = EXPECTED_RESULT.iter().map(|s| s.count()).sum();
+        assert_eq!(manifest.rows(), total as u64);
+
+        // A second run reads the same blocks, as does a reference pass
+        assert_eq!(parallel_manifest(&config)?, manifest);
+        let reference = Manifest::reference(io::Cursor::new(TEST_DATA.as_bytes()), &config)?;
+        assert!(reference.reference);
+        assert_eq!(reference.chunks, manifest.chunks);
+        assert_eq!(manifest.diff(&reference), None);
+
+        Ok(())
+    }
+
+    #[test]
+    fn diff_finds_first_divergence() -> Result<(), Box<dyn error::Error>> {
+        let manifest = parallel_manifest(&config())?;
+        let range = |index: usize| manifest.chunks[index].range();
+
+        let mut lost_row = manifest.clone();
+        lost_row.chunks[2].rows -= 1;
+        lost_row.chunks[3].hash ^= 1;
+        assert_eq!(
+            manifest.diff(&lost_row),
+            Some(Divergence {
+                index: 2,
+                kind: DivergenceKind::Rows {
+                    range: range(2),
+                    first: manifest.chunks[2].rows,
+                    second: manifest.chunks[2].rows - 1,
+                },
+            })
+        );
+
+        let mut changed = manifest.clone();
+        changed.chunks[1].hash ^= 1;
+        assert_eq!(
+            manifest.diff(&changed).map(|d| d.kind),
+            Some(DivergenceKind::Hash { range: range(1) })
+        );
+
+        let mut truncated = manifest.clone();
+        truncated.chunks.pop();
+        let last = manifest.chunks.len() - 1;
+        let divergence = manifest.diff(&truncated).unwrap();
+        assert_eq!(divergence.index, last);
+        assert_eq!(
+            divergence.to_string(),
+            format!(
+                "block {last} (bytes {:?}) is only in the first manifest",
+                range(last)
+            )
+        );
+
+        let resplit = Manifest::reference(
+            io::Cursor::new(TEST_DATA.as_bytes()),
+            &Config {
+                buffer_size: Setting::new(64, Source::Cli),
+                ..config()
+            },
+        )?;
+        assert!(matches!(
+            manifest.diff(&resplit),
+            Some(Divergence {
+                kind: DivergenceKind::Range { .. },
+                ..
+            })
+        ));
+
+        Ok(())
+    }
+}
@@ -0,0 +1,329 @@
+// 1BRC - my take on the 1 Billion Row Challenge
+// Copyright (C) 2024  Charles German <5donuts@pm.me>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Extra columns about each station (e.g. its country & coordinates) to join onto structured
+//! results, for `--metadata`.
+//!
+//! The metadata is a CSV file whose first column is the station's name; every other column is
+//! passed through as it is, without a fixed schema. Fields which are JSON numbers are kept as
+//! numbers, empty fields are `null`, and anything else is a string.
+
+use std::collections::HashMap;
+#[cfg(feature = "native")]
+use std::path::Path;
+
+use serde::ser::{Serialize, SerializeMap, Serializer};
+use serde_json::Value;
+
+use crate::helpers::StationInfo;
+
+/// Columns of the results, which a metadata column can't share a name with
+const RESULT_COLUMNS: [&str; 7] = [
+    "name",
+    "month",
+    "min",
+    "mean",
+    "max",
+    "first_row",
+    "last_row",
+];
+
+/// Metadata columns for each station, keyed by name
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Metadata {
+    /// The names of the columns after the key column, in order
+    columns: Vec<String>,
+
+    /// Each station's value for every column
+    rows: HashMap<String, Vec<Value>>,
+}
+
+impl Metadata {
+    /// Load the metadata CSV at `path`
+    #[cfg(feature = "native")]
+    pub fn load(path: &Path) -> Result<Self, Box<dyn std::error::Error>> {
+        let contents = std::fs::read_to_string(path)
+            .map_err(|e| format!("Unable to read {}: {e}", path.display()))?;
+        Self::parse(&contents)
+            .map_err(|e| format!("Invalid metadata {}: {e}", path.display()).into())
+    }
+
+    /// Parse metadata from CSV with a header row.
+    ///
+    /// Every row must have as many fields as the header, and each station may only have one
+    /// row: a join which could pick either of two rows isn't one anyone should rely on.
+    pub fn parse(csv: &str) -> Result<Self, String> {
+        let mut records = records(csv)?.into_iter();
+        let Some((_, header)) = records.next() else {
+            return Err(String::from("there is no header row"));
+        };
+        let columns: Vec<String> = header.into_iter().skip(1).collect();
+        for (idx, column) in columns.iter().enumerate() {
+            if RESULT_COLUMNS.contains(&column.as_str()) {
+                return Err(format!(
+                    "column `{column}` would clash with the results' own `{column}`"
+                ));
+            }
+            if columns[..idx].contains(column) {
+                return Err(format!("column `{column}` appears more than once"));
+            }
+        }
+
+        let mut rows = HashMap::new();
+        let mut first_lines: HashMap<String, usize> = HashMap::new();
+        for (line, record) in records {
+            if record.len() != columns.len() + 1 {
+                return Err(format!(
+                    "line {line} has {} fields, but the header has {}",
+                    record.len(),
+                    columns.len() + 1
+                ));
+            }
+            let mut fields = record.into_iter();
+            let name = fields.next().expect("Every record has a field");
+            if let Some(first) = first_lines.get(&name) {
+                return Err(format!("{name:?} is on both line {first} & line {line}"));
+            }
+            first_lines.insert(name.clone(), line);
+            rows.insert(name, fields.map(|field| value(&field)).collect());
+        }
+
+        Ok(Self { columns, rows })
+    }
+
+    /// The names of the metadata columns, in order
+    pub fn columns(&self) -> &[String] {
+        &self.columns
+    }
+
+    /// How many stations there is metadata for
+    pub fn len(&self) -> usize {
+        self.rows.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rows.is_empty()
+    }
+
+    /// The metadata for a station in the results, matched by name (whatever month it's for).
+    /// `None` if there's none, in which case every column is `null` in the output.
+    pub fn get(&self, station: &StationInfo) -> Option<&[Value]> {
+        self.rows.get(station.station()).map(Vec::as_slice)
+    }
+
+    /// The columns to attach to a station's entry in structured output
+    pub fn columns_for<'a>(&'a self, station: &StationInfo) -> Columns<'a> {
+        Columns {
+            names: &self.columns,
+            values: self.get(station),
+        }
+    }
+
+    /// How many of the stations have no metadata
+    pub fn unmatched(&self, stations: &[StationInfo]) -> usize {
+        stations.iter().filter(|s| self.get(s).is_none()).count()
+    }
+}
+
+/// A station's metadata columns, serialized as a map of column names to values (all `null` if
+/// there was no row for the station)
+#[derive(Debug, Clone, Copy)]
+pub struct Columns<'a> {
+    names: &'a [String],
+    values: Option<&'a [Value]>,
+}
+
+impl Columns<'_> {
+    /// Each column's value, in order
+    pub fn values(&self) -> impl Iterator<Item = &Value> {
+        (0..self.names.len()).map(|idx| self.values.map_or(&Value::Null, |values| &values[idx]))
+    }
+}
+
+impl Serialize for Columns<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(Some(self.names.len()))?;
+        for (name, value) in self.names.iter().zip(self.values()) {
+            map.serialize_entry(name, value)?;
+        }
+        map.end()
+    }
+}
+
+/// A field of the CSV as it should appear in JSON
+fn value(field: &str) -> Value {
+    if field.is_empty() {
+        return Value::Null;
+    }
+    match serde_json::from_str::<serde_json::Number>(field) {
+        Ok(number) => Value::Number(number),
+        Err(_) => Value::String(field.to_owned()),
+    }
+}
+
+/// Split CSV into records of fields, each with the line it starts on. Fields may be quoted as in
+/// RFC 4180 (with `""` for a `"`), in which case they may contain commas & line breaks. Blank
+/// lines are skipped.
+fn records(csv: &str) -> Result<Vec<(usize, Vec<String>)>, String> {
+    let mut records = Vec::new();
+    let mut chars = csv
+        .strip_prefix('\u{FEFF}')
+        .unwrap_or(csv)
+        .chars()
+        .peekable();
+    let mut line = 1;
+    while chars.peek().is_some() {
+        let start = line;
+        let mut record = Vec::new();
+        let mut field = String::new();
+        let mut quoted = false;
+        loop {
+            match chars.next() {
+                Some('"') if quoted => {
+                    if chars.next_if_eq(&'"').is_some() {
+                        field.push('"');
+                    } else {
+                        quoted = false;
+                    }
+                }
+                Some('"') if field.is_empty() => quoted = true,
+                Some(c) if quoted => {
+                    if c == '\n' {
+                        line += 1;
+                    }
+                    field.push(c);
+                }
+                Some(',') => record.push(std::mem::take(&mut field)),
+                Some('\r') if chars.peek() == Some(&'\n') => {}
+                Some('\n') | None => {
+                    line += 1;
+                    break;
+                }
+                Some(c) => field.push(c),
+            }
+            if quoted && chars.peek().is_none() {
+                return Err(format!("the quoted field on line {start} is never closed"));
+            }
+        }
+        record.push(field);
+        if record.len() > 1 || !record[0].is_empty() {
+            records.push((start, record));
+        }
+    }
+    Ok(records)
+}
+
+#[cfg(all(test, feature = "native"))]
+mod tests {
+    use super::*;
+    use crate::aggregate::KeyFormat;
+
+    const METADATA: &str = "station,country,lat,lon\n\
+        Hamburg,Germany,53.55,9.99\n\
+        \"Washington, D.C.\",United States,38.9,-77.04\n\
+        Bulawayo,Zimbabwe,,\n";
+
+    fn station(name: &str) -> StationInfo {
+        StationInfo::new(name.to_string(), 1.0, 1.0, 1.0, 1)
+    }
+
+    #[test]
+    fn matched() -> Result<(), String> {
+        let metadata = Metadata::parse(METADATA)?;
+        assert_eq!(metadata.columns(), ["country", "lat", "lon"]);
+        assert_eq!(metadata.len(), 3);
+
+        let columns = serde_json::to_value(metadata.columns_for(&station("Washington, D.C.")))
+            .map_err(|e| e.to_string())?;
+        assert_eq!(
+            columns,
+            serde_json::json!({ "country": "United States", "lat": 38.9, "lon": -77.04 })
+        );
+        let columns = serde_json::to_value(metadata.columns_for(&station("Bulawayo")))
+            .map_err(|e| e.to_string())?;
+        assert_eq!(
+            columns,
+            serde_json::json!({ "country": "Zimbabwe", "lat": null, "lon": null })
+        );
+        // Grouped by month, it's still the same station
+        let monthly = station("2023-07;Hamburg").keyed_by(KeyFormat::MonthStation);
+        assert!(metadata.get(&monthly).is_some());
+
+        Ok(())
+    }
+
+    #[test]
+    fn unmatched() -> Result<(), String> {
+        let metadata = Metadata::parse(METADATA)?;
+        let stations = [station("Hamburg"), station("Palembang"), station("hamburg")];
+        assert_eq!(metadata.unmatched(&stations), 2);
+
+        let columns =
+            serde_json::to_value(metadata.columns_for(&stations[1])).map_err(|e| e.to_string())?;
+        assert_eq!(
+            columns,
+            serde_json::json!({ "country": null, "lat": null, "lon": null })
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn duplicate_rows() {
+        let csv = format!("{METADATA}Hamburg,Deutschland,53.55,9.99\n");
+        assert_eq!(
+            Metadata::parse(&csv),
+            Err(String::from("\"Hamburg\" is on both line 2 & line 5"))
+        );
+    }
+
+    #[test]
+    fn malformed() {
+        let errors = [
+            ("", "there is no header row"),
+            ("name,min\nHamburg,1\n", "clash with the results' own `min`"),
+            ("name,a,a\n", "column `a` appears more than once"),
+            (
+                "name,a\nHamburg\n",
+                "line 2 has 1 fields, but the header has 2",
+            ),
+            (
+                "name,a\n\"Hamburg,1\n",
+                "the quoted field on line 2 is never closed",
+            ),
+        ];
+        for (csv, expected) in errors {
+            let error = Metadata::parse(csv).unwrap_err();
+            assert!(error.contains(expected), "{csv:?}: {error}");
+        }
+    }
+
+    #[test]
+    fn quoting() -> Result<(), String> {
+        let csv = "\u{FEFF}name,note\r\n\r\n\"St. John's\",\"says \"\"hi\"\"\non two lines\"\r\nOslo,007\n";
+        let metadata = Metadata::parse(csv)?;
+        assert_eq!(
+            metadata.get(&station("St. John's")),
+            Some(&[Value::from("says \"hi\"\non two lines")][..])
+        );
+        // Not a JSON number, so not a number
+        assert_eq!(
+            metadata.get(&station("Oslo")),
+            Some(&[Value::from("007")][..])
+        );
+
+        Ok(())
+    }
+}
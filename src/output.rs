@@ -0,0 +1,1064 @@
+// 1BRC - my take on the 1 Billion Row Challenge
+// Copyright (C) 2024  Charles German <5donuts@pm.me>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Formatting of results & the sinks they are written to
+
+use std::borrow::Cow;
+use std::fmt::{Display, Write as _};
+#[cfg(feature = "native")]
+use std::fs::File;
+#[cfg(feature = "native")]
+use std::io::{self, Write};
+#[cfg(feature = "native")]
+use std::path::{Path, PathBuf};
+#[cfg(feature = "native")]
+use std::str::FromStr;
+
+#[cfg(feature = "native")]
+use clap::ValueEnum;
+use serde::{Serialize, Serializer as _};
+
+use crate::aggregate::Extents;
+#[cfg(feature = "native")]
+use crate::helpers::write_stdout;
+use crate::helpers::{StationInfo, Tenths};
+use crate::metadata::{Columns, Metadata};
+use crate::sample::Sample;
+#[cfg(feature = "native")]
+use crate::temp::TEMP_FILES;
+
+/// The format a result document is rendered in
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "native", derive(clap::ValueEnum))]
+pub enum Format {
+    /// The challenge's `{name=min/mean/max, ...}` format
+    #[default]
+    Text,
+
+    /// A JSON array of `{"name", "min", "mean", "max"}` objects, with a `"month"` too when
+    /// grouped by month and `"first_row"` & `"last_row"` when tracking extents
+    Json,
+
+    /// Comma-separated values with the same columns as the JSON objects, after a header row
+    Csv,
+
+    /// Tab-separated values, otherwise the same as CSV
+    Tsv,
+}
+
+impl Format {
+    /// Render the (sorted) list of stations as a complete document in this format.
+    ///
+    /// The document is written straight into a string sized for it up front, so rendering
+    /// doesn't allocate per station, however many there are.
+    pub fn render(self, stations: &[StationInfo]) -> String {
+        self.render_with(stations, None, None)
+    }
+
+    /// Render the stations like [`Format::render`], with the [`Metadata`] columns of each
+    /// station after its own in JSON, CSV & TSV (the text format has nowhere to put them).
+    ///
+    /// The results of a [`Sample`] are labelled as sampled, with each station's count & its
+    /// min & max named as only observed: the text format starts with a line saying so & gives
+    /// each station's count after it, the JSON array becomes the `"stations"` of an object with
+    /// the `"sampled"` settings, and CSV & TSV have `observed_min`, `observed_max` & `count`
+    /// columns.
+    pub fn render_with(
+        self,
+        stations: &[StationInfo],
+        metadata: Option<&Metadata>,
+        sample: Option<&Sample>,
+    ) -> String {
+        let mut doc = String::with_capacity(self.capacity(stations, metadata, sample.is_some()));
+        match self {
+            Format::Text => {
+                if let Some(sample) = sample {
+                    writeln!(
+                        doc,
+                        "Sampled data from {sample}: each station is observed min/mean/observed \
+                         max (n measurements sampled)"
+                    )
+                    .expect("Writing to a String cannot fail");
+                }
+                // Wrap the entries with '{ ... }' and put ', ' between each entry, but
+                // not following the last entry.
+                doc.push('{');
+                for (idx, station) in stations.iter().enumerate() {
+                    if idx > 0 {
+                        doc.push_str(", ");
+                    }
+                    write!(doc, "{station}").expect("Writing to a String cannot fail");
+                    if sample.is_some() {
+                        write!(doc, " (n={})", station.count())
+                            .expect("Writing to a String cannot fail");
+                    }
+                }
+                doc.push_str("}\n");
+            }
+            Format::Json => {
+                let mut buf = doc.into_bytes();
+                let mut serializer = serde_json::Serializer::pretty(&mut buf);
+                let entries = stations.iter().map(|station| Entry {
+                    metadata: metadata.map(|metadata| metadata.columns_for(station)),
+                    ..Entry::from(station)
+                });
+                match sample {
+                    Some(sample) => SampledDocument {
+                        sampled: sample,
+                        stations: entries
+                            .zip(stations)
+                            .map(|(entry, station)| SampledEntry::new(entry, station.count()))
+                            .collect(),
+                    }
+                    .serialize(&mut serializer),
+                    None => (&mut serializer).collect_seq(entries),
+                }
+                .expect("Serializing station entries cannot fail");
+                doc = String::from_utf8(buf).expect("serde_json only writes UTF-8");
+                doc.push('\n');
+            }
+            Format::Csv => delimited(stations, metadata, sample.is_some(), ',', &mut doc),
+            Format::Tsv => delimited(stations, metadata, sample.is_some(), '\t', &mut doc),
+        }
+        doc
+    }
+
+    /// Roughly how long the document for the stations is in this format: every name, plus the
+    /// most each station's measurements & punctuation take up (measurements are at most five
+    /// characters, e.g. `-99.9`), plus the metadata columns' names & each station's values, plus
+    /// the labels & counts of a sample.
+    fn capacity(
+        self,
+        stations: &[StationInfo],
+        metadata: Option<&Metadata>,
+        sampled: bool,
+    ) -> usize {
+        let months = stations.iter().any(|s| s.month().is_some());
+        let extents = stations.iter().any(|s| s.extents().is_some());
+        let (per_station, per_month, per_extents, fixed) = match self {
+            // `=-99.9/-99.9/-99.9, `, ` (YYYY-MM)`, & the braces
+            Format::Text => (20, 10, 0, 3),
+            // Six lines of indented keys & values, with up to 20 digits per row number
+            Format::Json => (80, 24, 70, 4),
+            Format::Csv | Format::Tsv => (20, 8, 42, 48),
+        };
+        let names: usize = stations.iter().map(|s| s.name().len()).sum();
+        let per_station = per_station
+            + if months { per_month } else { 0 }
+            + if extents { per_extents } else { 0 };
+        let metadata = match metadata {
+            Some(metadata) if self != Format::Text => {
+                let names: usize = metadata.columns().iter().map(|c| c.len() + 8).sum();
+                let values: usize = stations
+                    .iter()
+                    .filter_map(|s| metadata.get(s))
+                    .flatten()
+                    .map(|v| v.to_string().len())
+                    .sum();
+                names + values + stations.len() * (names + metadata.columns().len() * 4)
+            }
+            _ => 0,
+        };
+        // The label & ` (n=4294967295)`, or the longer keys & `"count": 4294967295,`
+        let sample = match sampled {
+            true => 160 + stations.len() * 40,
+            false => 0,
+        };
+        fixed + names + stations.len() * per_station + metadata + sample
+    }
+}
+
+/// Render the stations as a header row & a row per station, separated by `delimiter`.
+///
+/// Fields containing the delimiter, a `"` or a line break are quoted as in RFC 4180 (with any
+/// `"`s doubled), so names like `Washington, D.C.` stay in one column. Measurements are always
+/// written with a `.` decimal point, whatever the locale. Metadata columns come last, with
+/// `null`s left empty. A sample's min & max are named `observed_min` & `observed_max`, with a
+/// `count` column after them.
+fn delimited(
+    stations: &[StationInfo],
+    metadata: Option<&Metadata>,
+    sampled: bool,
+    delimiter: char,
+    doc: &mut String,
+) {
+    let months = stations.iter().any(|s| s.month().is_some());
+    let extents = stations.iter().any(|s| s.extents().is_some());
+
+    let header = ["name"]
+        .into_iter()
+        .chain(months.then_some("month"))
+        .chain(
+            match sampled {
+                true => &["observed_min", "mean", "observed_max", "count"][..],
+                false => &["min", "mean", "max"][..],
+            }
+            .iter()
+            .copied(),
+        )
+        .chain(
+            extents
+                .then_some(["first_row", "last_row"])
+                .into_iter()
+                .flatten(),
+        )
+        .chain(
+            metadata
+                .into_iter()
+                .flat_map(|m| m.columns())
+                .map(String::as_str),
+        );
+    for (idx, column) in header.enumerate() {
+        if idx > 0 {
+            doc.push(delimiter);
+        }
+        doc.push_str(column);
+    }
+    doc.push('\n');
+
+    for station in stations {
+        doc.push_str(&escape(station.station(), delimiter));
+        if months {
+            doc.push(delimiter);
+            doc.push_str(&escape(station.month().unwrap_or_default(), delimiter));
+        }
+        match station.stats() {
+            Some(stats) => {
+                for value in stats {
+                    write!(doc, "{delimiter}{}", Tenths(value))
+                        .expect("Writing to a String cannot fail");
+                }
+            }
+            // Left empty, like any other missing value
+            None => (0..3).for_each(|_| doc.push(delimiter)),
+        }
+        if sampled {
+            write!(doc, "{delimiter}{}", station.count()).expect("Writing to a String cannot fail");
+        }
+        if extents {
+            let extents = station.extents();
+            for row in [extents.map(|e| e.first_row), extents.map(|e| e.last_row)] {
+                doc.push(delimiter);
+                if let Some(row) = row {
+                    write!(doc, "{row}").expect("Writing to a String cannot fail");
+                }
+            }
+        }
+        if let Some(metadata) = metadata {
+            for value in metadata.columns_for(station).values() {
+                doc.push(delimiter);
+                match value {
+                    serde_json::Value::Null => {}
+                    serde_json::Value::String(s) => doc.push_str(&escape(s, delimiter)),
+                    value => write!(doc, "{value}").expect("Writing to a String cannot fail"),
+                }
+            }
+        }
+        doc.push('\n');
+    }
+}
+
+/// Quote a field of delimiter-separated output, if it needs it
+fn escape(field: &str, delimiter: char) -> Cow<'_, str> {
+    if field.contains([delimiter, '"', '\n', '\r']) {
+        Cow::Owned(format!("\"{}\"", field.replace('"', "\"\"")))
+    } else {
+        Cow::Borrowed(field)
+    }
+}
+
+impl Display for Format {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let s = match self {
+            Format::Text => "text",
+            Format::Json => "json",
+            Format::Csv => "csv",
+            Format::Tsv => "tsv",
+        };
+        write!(f, "{s}")
+    }
+}
+
+/// A single station in structured (e.g. JSON) output
+#[derive(Debug, Serialize)]
+pub struct Entry<'a> {
+    pub name: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub month: Option<&'a str>,
+
+    /// `null` for a [missing](StationInfo::missing) station, as are the mean & max
+    pub min: Option<f64>,
+    pub mean: Option<f64>,
+    pub max: Option<f64>,
+
+    /// Only with `--track-extents`
+    #[serde(flatten)]
+    pub extents: Option<Extents>,
+
+    /// Only with `--metadata`
+    #[serde(flatten)]
+    pub metadata: Option<Columns<'a>>,
+}
+
+impl<'a> From<&'a StationInfo> for Entry<'a> {
+    fn from(station: &'a StationInfo) -> Self {
+        // Round the same way the text format does so the two never disagree
+        let [min, mean, max] = match station.stats() {
+            Some(stats) => stats.map(|v| Some(Tenths(v).rounded())),
+            None => [None; 3],
+        };
+        Self {
+            name: station.station(),
+            month: station.month(),
+            min,
+            mean,
+            max,
+            extents: station.extents(),
+            metadata: None,
+        }
+    }
+}
+
+/// A single station in structured output of a [`Sample`], whose min & max are only the extremes
+/// observed in the sample, with how many measurements its figures are from
+#[derive(Debug, Serialize)]
+pub struct SampledEntry<'a> {
+    pub name: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub month: Option<&'a str>,
+    pub observed_min: Option<f64>,
+    pub mean: Option<f64>,
+    pub observed_max: Option<f64>,
+    pub count: u32,
+
+    #[serde(flatten)]
+    pub extents: Option<Extents>,
+    #[serde(flatten)]
+    pub metadata: Option<Columns<'a>>,
+}
+
+impl<'a> SampledEntry<'a> {
+    pub fn new(entry: Entry<'a>, count: u32) -> Self {
+        Self {
+            name: entry.name,
+            month: entry.month,
+            observed_min: entry.min,
+            mean: entry.mean,
+            observed_max: entry.max,
+            count,
+            extents: entry.extents,
+            metadata: entry.metadata,
+        }
+    }
+}
+
+/// The JSON document of a [`Sample`]'s results
+#[derive(Serialize)]
+struct SampledDocument<'a> {
+    sampled: &'a Sample,
+    stations: Vec<SampledEntry<'a>>,
+}
+
+#[cfg(feature = "native")]
+/// A destination for a result document given on the command line as `PATH[:FORMAT]`.
+///
+/// When no format is given, it is inferred from the file extension.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OutputSpec {
+    pub path: PathBuf,
+    pub format: Format,
+}
+
+#[cfg(feature = "native")]
+impl FromStr for OutputSpec {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.is_empty() {
+            return Err(String::from("output path cannot be empty"));
+        }
+
+        // Only treat the suffix as a format if it is one; paths may contain ':' too
+        if let Some((path, format)) = s.rsplit_once(':') {
+            if let Ok(format) = Format::from_str(format, true) {
+                if path.is_empty() {
+                    return Err(String::from("output path cannot be empty"));
+                }
+                return Ok(Self {
+                    path: PathBuf::from(path),
+                    format,
+                });
+            }
+        }
+
+        let path = PathBuf::from(s);
+        let format = match path.extension().and_then(|e| e.to_str()) {
+            Some("json") => Format::Json,
+            Some("csv") => Format::Csv,
+            Some("tsv") => Format::Tsv,
+            _ => Format::Text,
+        };
+        Ok(Self { path, format })
+    }
+}
+
+#[cfg(feature = "native")]
+/// Somewhere a result document can be written to
+pub trait OutputSink {
+    /// The format documents written to this sink should be rendered in
+    fn format(&self) -> Format;
+
+    /// A human-readable description of this sink for error messages
+    fn describe(&self) -> String;
+
+    /// Write (part of) a rendered document to the sink
+    fn write(&mut self, document: &str) -> io::Result<()>;
+
+    /// Flush any buffered output
+    fn flush(&mut self) -> io::Result<()>;
+
+    /// Finish writing to the sink, making the document visible all at once where possible
+    fn finalize(self: Box<Self>) -> io::Result<()>;
+}
+
+#[cfg(feature = "native")]
+/// Write documents to stdout
+pub struct StdoutSink {
+    format: Format,
+}
+
+#[cfg(feature = "native")]
+impl StdoutSink {
+    pub fn new(format: Format) -> Self {
+        Self { format }
+    }
+}
+
+#[cfg(feature = "native")]
+impl OutputSink for StdoutSink {
+    fn format(&self) -> Format {
+        self.format
+    }
+
+    fn describe(&self) -> String {
+        String::from("stdout")
+    }
+
+    fn write(&mut self, document: &str) -> io::Result<()> {
+        write_stdout(document)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        write_stdout("")
+    }
+
+    fn finalize(mut self: Box<Self>) -> io::Result<()> {
+        self.flush()
+    }
+}
+
+#[cfg(feature = "native")]
+/// Write documents to a file atomically.
+///
+/// The document is written to a temporary file alongside the destination which is only renamed
+/// over the destination by [`OutputSink::finalize`]. If anything fails before then (or the sink
+/// is dropped without being finalized), the temporary file is removed and any existing file at
+/// the destination is left untouched.
+pub struct FileSink {
+    format: Format,
+    path: PathBuf,
+    tmp_path: PathBuf,
+    file: Option<io::BufWriter<File>>,
+}
+
+#[cfg(feature = "native")]
+impl FileSink {
+    pub fn create(path: &Path, format: Format) -> io::Result<Self> {
+        let mut tmp_name = path.file_name().unwrap_or_default().to_os_string();
+        tmp_name.push(format!(".{}.tmp", std::process::id()));
+        let tmp_path = path.with_file_name(tmp_name);
+        let file = File::create(&tmp_path)?;
+        TEMP_FILES.register(&tmp_path);
+
+        Ok(Self {
+            format,
+            path: path.to_path_buf(),
+            tmp_path,
+            file: Some(io::BufWriter::new(file)),
+        })
+    }
+
+    fn file(&mut self) -> io::Result<&mut io::BufWriter<File>> {
+        self.file
+            .as_mut()
+            .ok_or_else(|| io::Error::other("file sink was already finalized"))
+    }
+}
+
+#[cfg(feature = "native")]
+impl OutputSink for FileSink {
+    fn format(&self) -> Format {
+        self.format
+    }
+
+    fn describe(&self) -> String {
+        self.path.display().to_string()
+    }
+
+    fn write(&mut self, document: &str) -> io::Result<()> {
+        self.file()?.write_all(document.as_bytes())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file()?.flush()
+    }
+
+    fn finalize(mut self: Box<Self>) -> io::Result<()> {
+        let file = self.file()?;
+        file.flush()?;
+        file.get_ref().sync_all()?;
+        self.file = None;
+        let renamed = std::fs::rename(&self.tmp_path, &self.path);
+        match &renamed {
+            Ok(()) => TEMP_FILES.forget(&self.tmp_path),
+            Err(_) => TEMP_FILES.release(&self.tmp_path),
+        }
+        renamed
+    }
+}
+
+#[cfg(feature = "native")]
+impl Drop for FileSink {
+    fn drop(&mut self) {
+        // Only clean up if we never got around to renaming the file into place
+        if self.file.take().is_some() {
+            TEMP_FILES.release(&self.tmp_path);
+        }
+    }
+}
+
+/// Collect documents in memory
+#[cfg(feature = "native")]
+pub struct MemorySink {
+    format: Format,
+    buf: std::rc::Rc<std::cell::RefCell<String>>,
+}
+
+#[cfg(feature = "native")]
+impl MemorySink {
+    /// Create a new sink along with a handle to read back what was written to it
+    pub fn new(format: Format) -> (Self, std::rc::Rc<std::cell::RefCell<String>>) {
+        let buf = std::rc::Rc::default();
+        let sink = Self {
+            format,
+            buf: std::rc::Rc::clone(&buf),
+        };
+        (sink, buf)
+    }
+}
+
+#[cfg(feature = "native")]
+impl OutputSink for MemorySink {
+    fn format(&self) -> Format {
+        self.format
+    }
+
+    fn describe(&self) -> String {
+        String::from("memory")
+    }
+
+    fn write(&mut self, document: &str) -> io::Result<()> {
+        self.buf.borrow_mut().push_str(document);
+        Ok(())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn finalize(self: Box<Self>) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(feature = "native")]
+/// Fan a result out to any number of [`OutputSink`]s.
+///
+/// A failure in one sink doesn't stop the document from being written to the others; all of the
+/// failures are reported together once every sink has been tried. That includes a file which
+/// couldn't be created in the first place (see [`MultiSink::push_file`]).
+#[derive(Default)]
+pub struct MultiSink {
+    /// Each sink, or why it couldn't be created, in the order they were added
+    sinks: Vec<Result<Box<dyn OutputSink>, (String, io::Error)>>,
+    metadata: Option<Metadata>,
+    sample: Option<Sample>,
+}
+
+#[cfg(feature = "native")]
+impl MultiSink {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn push(&mut self, sink: Box<dyn OutputSink>) {
+        self.sinks.push(Ok(sink));
+    }
+
+    /// Write to the file at `path` in `format`. If it can't be created, that's reported by
+    /// [`MultiSink::emit`] once the other sinks have been written to, like a failure to write it.
+    pub fn push_file(&mut self, path: &Path, format: Format) {
+        let sink = FileSink::create(path, format)
+            .map(|sink| Box::new(sink) as Box<dyn OutputSink>)
+            .map_err(|e| (path.display().to_string(), e));
+        self.sinks.push(sink);
+    }
+
+    /// Join these metadata columns onto the results written in a structured format
+    pub fn metadata(&mut self, metadata: Metadata) {
+        self.metadata = Some(metadata);
+    }
+
+    /// Label the results written as those of a sample
+    pub fn sampled(&mut self, sample: Sample) {
+        self.sample = Some(sample);
+    }
+
+    /// Render the stations in each sink's format, write them out, and finalize every sink
+    pub fn emit(self, stations: &[StationInfo]) -> Result<(), Box<dyn std::error::Error>> {
+        let mut rendered: Vec<(Format, String)> = Vec::new();
+        let mut failures = Vec::new();
+
+        let metadata = self.metadata.as_ref();
+        for sink in self.sinks {
+            let mut sink = match sink {
+                Ok(sink) => sink,
+                Err((description, e)) => {
+                    eprintln!("Failed to write results to {description}: {e}");
+                    failures.push(description);
+                    continue;
+                }
+            };
+            let format = sink.format();
+            let document = match rendered.iter().find(|(f, _)| *f == format) {
+                Some((_, doc)) => doc,
+                None => {
+                    rendered.push((
+                        format,
+                        format.render_with(stations, metadata, self.sample.as_ref()),
+                    ));
+                    &rendered.last().unwrap().1
+                }
+            };
+
+            let description = sink.describe();
+            let result = sink.write(document).and_then(|_| sink.finalize());
+            if let Err(e) = result {
+                eprintln!("Failed to write results to {description}: {e}");
+                failures.push(description);
+            }
+        }
+
+        if failures.is_empty() {
+            Ok(())
+        } else {
+            Err(format!("Failed to write results to: {}", failures.join(", ")).into())
+        }
+    }
+}
+
+#[cfg(all(test, feature = "native"))]
+mod tests {
+    use super::*;
+    use crate::aggregate::KeyFormat;
+    use crate::runners::tests::EXPECTED_RESULT;
+
+    /// A sink which always fails, like a full disk would
+    struct FailingSink;
+
+    impl OutputSink for FailingSink {
+        fn format(&self) -> Format {
+            Format::Text
+        }
+
+        fn describe(&self) -> String {
+            String::from("failing")
+        }
+
+        fn write(&mut self, _document: &str) -> io::Result<()> {
+            Err(io::Error::other("no space left on device"))
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+
+        fn finalize(self: Box<Self>) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn output_spec() {
+        let spec: OutputSpec = "out.json:json".parse().unwrap();
+        assert_eq!(spec.path, PathBuf::from("out.json"));
+        assert_eq!(spec.format, Format::Json);
+
+        let spec: OutputSpec = "results.txt:json".parse().unwrap();
+        assert_eq!(spec.path, PathBuf::from("results.txt"));
+        assert_eq!(spec.format, Format::Json);
+
+        let spec: OutputSpec = "out.json".parse().unwrap();
+        assert_eq!(spec.format, Format::Json);
+
+        let spec: OutputSpec = "out.csv".parse().unwrap();
+        assert_eq!(spec.format, Format::Csv);
+        let spec: OutputSpec = "out.txt:tsv".parse().unwrap();
+        assert_eq!(spec.format, Format::Tsv);
+
+        let spec: OutputSpec = "dir:with:colons/out".parse().unwrap();
+        assert_eq!(spec.path, PathBuf::from("dir:with:colons/out"));
+        assert_eq!(spec.format, Format::Text);
+
+        assert!(":json".parse::<OutputSpec>().is_err());
+    }
+
+    #[test]
+    fn optional_entry_fields() {
+        let stations = [
+            StationInfo::new(String::from("2023-07;Hamburg"), 1.0, 3.0, 2.0, 2)
+                .keyed_by(KeyFormat::MonthStation),
+        ];
+        assert_eq!(
+            Format::Text.render(&stations),
+            "{Hamburg (2023-07)=1.0/2.0/3.0}\n"
+        );
+
+        let json: serde_json::Value =
+            serde_json::from_str(&Format::Json.render(&stations)).unwrap();
+        assert_eq!(json[0]["name"], "Hamburg");
+        assert_eq!(json[0]["month"], "2023-07");
+
+        let stations = [
+            StationInfo::new(String::from("Hamburg"), 1.0, 3.0, 2.0, 2).with_extents(Some(
+                Extents {
+                    first_row: 4,
+                    last_row: 10,
+                },
+            )),
+        ];
+        let json: serde_json::Value =
+            serde_json::from_str(&Format::Json.render(&stations)).unwrap();
+        assert_eq!(json[0]["first_row"], 4);
+        assert_eq!(json[0]["last_row"], 10);
+
+        // Entries without a month or extents don't get them
+        let json: serde_json::Value =
+            serde_json::from_str(&Format::Json.render(&EXPECTED_RESULT)).unwrap();
+        assert!(json[0].get("month").is_none());
+        assert!(json[0].get("first_row").is_none());
+    }
+
+    #[test]
+    fn missing_stations() {
+        let stations = [
+            StationInfo::missing(String::from("Absent")),
+            StationInfo::new(String::from("Hamburg"), 1.0, 3.0, 2.0, 2),
+        ];
+        assert_eq!(
+            Format::Text.render(&stations),
+            "{Absent=-/-/-, Hamburg=1.0/2.0/3.0}\n"
+        );
+        assert_eq!(
+            Format::Csv.render(&stations),
+            "name,min,mean,max\nAbsent,,,\nHamburg,1.0,2.0,3.0\n"
+        );
+
+        let json: serde_json::Value =
+            serde_json::from_str(&Format::Json.render(&stations)).unwrap();
+        assert_eq!(json[0]["name"], "Absent");
+        for stat in ["min", "mean", "max"] {
+            assert!(json[0][stat].is_null(), "{json}");
+        }
+        assert_eq!(json[1]["mean"], 2.0);
+    }
+
+    /// Stations with names which need quoting in CSV or TSV, or both
+    fn awkward_names() -> Vec<StationInfo> {
+        [
+            "Hamburg",
+            "Washington, D.C.",
+            r#"The "Quoted" One"#,
+            "Line\nBreak",
+            "Tab\tStop",
+        ]
+        .into_iter()
+        .map(|name| StationInfo::new(String::from(name), -5.0, 12.3, 3.25, 2))
+        .collect()
+    }
+
+    #[test]
+    fn csv() {
+        assert_eq!(
+            Format::Csv.render(&awkward_names()),
+            r#"name,min,mean,max
+Hamburg,-5.0,3.2,12.3
+"Washington, D.C.",-5.0,3.2,12.3
+"The ""Quoted"" One",-5.0,3.2,12.3
+"Line
+Break",-5.0,3.2,12.3
+Tab	Stop,-5.0,3.2,12.3
+"#
+        );
+    }
+
+    #[test]
+    fn tsv() {
+        assert_eq!(
+            Format::Tsv.render(&awkward_names()),
+            "\
+name\tmin\tmean\tmax
+Hamburg\t-5.0\t3.2\t12.3
+Washington, D.C.\t-5.0\t3.2\t12.3
+\"The \"\"Quoted\"\" One\"\t-5.0\t3.2\t12.3
+\"Line\nBreak\"\t-5.0\t3.2\t12.3
+\"Tab\tStop\"\t-5.0\t3.2\t12.3
+"
+        );
+    }
+
+    #[test]
+    fn metadata_columns() {
+        let metadata =
+            Metadata::parse("station,country,note\nHamburg,Germany,\"a, b\"\nBulawayo,,1.5\n")
+                .unwrap();
+        let metadata = Some(&metadata);
+        let stations: Vec<StationInfo> = ["Bulawayo", "Hamburg", "Palembang"]
+            .into_iter()
+            .map(|name| StationInfo::new(String::from(name), 1.0, 3.0, 2.0, 2))
+            .collect();
+
+        assert_eq!(
+            Format::Csv.render_with(&stations, metadata, None),
+            "name,min,mean,max,country,note\n\
+             Bulawayo,1.0,2.0,3.0,,1.5\n\
+             Hamburg,1.0,2.0,3.0,Germany,\"a, b\"\n\
+             Palembang,1.0,2.0,3.0,,\n"
+        );
+        let json: serde_json::Value =
+            serde_json::from_str(&Format::Json.render_with(&stations, metadata, None)).unwrap();
+        assert_eq!(json[0]["country"], serde_json::Value::Null);
+        assert_eq!(json[0]["note"], 1.5);
+        assert_eq!(json[1]["country"], "Germany");
+        assert_eq!(json[2]["name"], "Palembang");
+        assert_eq!(json[2]["note"], serde_json::Value::Null);
+        assert_eq!(
+            Format::Text.render_with(&stations, metadata, None),
+            Format::Text.render(&stations)
+        );
+    }
+
+    #[test]
+    fn delimited_optional_columns() {
+        let stations = [
+            StationInfo::new(String::from("2023-07;Hamburg"), 1.0, 3.0, 2.0, 2)
+                .with_extents(Some(Extents {
+                    first_row: 4,
+                    last_row: 10,
+                }))
+                .keyed_by(KeyFormat::MonthStation),
+            StationInfo::new(String::from("2023-08;Hamburg"), 1.0, 3.0, 2.0, 2)
+                .keyed_by(KeyFormat::MonthStation),
+        ];
+        assert_eq!(
+            Format::Csv.render(&stations),
+            "\
+name,month,min,mean,max,first_row,last_row
+Hamburg,2023-07,1.0,2.0,3.0,4,10
+Hamburg,2023-08,1.0,2.0,3.0,,
+"
+        );
+        assert_eq!(Format::Csv.render(&[]), "name,min,mean,max\n");
+    }
+
+    #[test]
+    fn no_negative_zero() {
+        // A mean a hair under zero, from summing measurements which cancel out
+        let stations = [StationInfo::new(
+            String::from("Hamburg"),
+            -99.9,
+            99.9,
+            -1e-6,
+            8,
+        )];
+        assert_eq!(Format::Text.render(&stations), "{Hamburg=-99.9/0.0/99.9}\n");
+        let json: serde_json::Value =
+            serde_json::from_str(&Format::Json.render(&stations)).unwrap();
+        assert!(json[0]["mean"].as_f64().unwrap().is_sign_positive());
+        assert_eq!(Tenths(-0.04).to_string(), "0.0");
+        assert_eq!(Tenths(-0.06).to_string(), "-0.1");
+        // Either side of where `{:.1}` rounds to `-0.1`
+        assert_eq!(Tenths(-0.049_999_997).to_string(), "0.0");
+        assert_eq!(Tenths(-0.05).to_string(), "-0.1");
+    }
+
+    #[test]
+    fn sampled() {
+        let sample = Sample::new(0.1, 3);
+        let stations = &EXPECTED_RESULT[..2];
+        let render = |format: Format| format.render_with(stations, None, Some(&sample));
+
+        assert_eq!(
+            render(Format::Text),
+            "Sampled data from 10% of the input's blocks (seed 3): each station is observed \
+             min/mean/observed max (n measurements sampled)\n\
+             {Aïn el Mediour=5.7/26.6/47.6 (n=2), Glens Falls=-47.5/-20.5/6.6 (n=2)}\n"
+        );
+        let json: serde_json::Value = serde_json::from_str(&render(Format::Json)).unwrap();
+        assert_eq!(json["sampled"], serde_json::json!({"rate": 0.1, "seed": 3}));
+        assert_eq!(
+            json["stations"][1],
+            serde_json::json!({
+                "name": "Glens Falls",
+                "observed_min": -47.5,
+                "mean": -20.5,
+                "observed_max": 6.6,
+                "count": 2
+            })
+        );
+        assert_eq!(
+            render(Format::Csv),
+            "name,observed_min,mean,observed_max,count\n\
+             Aïn el Mediour,5.7,26.6,47.6,2\n\
+             Glens Falls,-47.5,-20.5,6.6,2\n"
+        );
+    }
+
+    /// JSON entries are rounded without formatting the measurements, to the same values as
+    /// parsing them back from the text format would give
+    #[test]
+    fn json_rounding_matches_text() {
+        let ties = [0.25, 0.35, -0.25, 0.05, -0.05, 0.15, 2.45, 99.95, -99.95];
+        let values = (-200_000..=200_000)
+            .map(|i| i as f32 / 1000.0)
+            .chain(ties)
+            .chain([f32::MAX, f32::MIN, 1e-30, -1e-30]);
+        for value in values {
+            let text = Tenths(value).to_string();
+            assert_eq!(format!("{value:.1}").replace("-0.0", "0.0"), text);
+            let parsed: f64 = text.parse().unwrap();
+            let rounded = Tenths(value).rounded();
+            assert_eq!(rounded.to_bits(), parsed.to_bits(), "{value} as {text}");
+        }
+    }
+
+    #[test]
+    fn multi_sink_fan_out() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("out.json");
+
+        let (text, text_buf) = MemorySink::new(Format::Text);
+        let (json, json_buf) = MemorySink::new(Format::Json);
+        let mut sinks = MultiSink::new();
+        sinks.push(Box::new(text));
+        sinks.push(Box::new(json));
+        sinks.push(Box::new(FileSink::create(&path, Format::Json)?));
+        sinks.emit(&EXPECTED_RESULT)?;
+
+        assert_eq!(
+            *text_buf.borrow(),
+            "{Aïn el Mediour=5.7/26.6/47.6, Glens Falls=-47.5/-20.5/6.6, Paidiipalli=91.1/91.1/91.1, Shimanto=20.9/38.4/74.9, Zverevo=87.6/92.8/98.1}\n"
+        );
+        assert_eq!(*json_buf.borrow(), std::fs::read_to_string(&path)?);
+
+        let json: serde_json::Value = serde_json::from_str(&json_buf.borrow())?;
+        assert_eq!(json[0]["name"], "Aïn el Mediour");
+        assert_eq!(json[1]["mean"], -20.5);
+        assert_eq!(json.as_array().unwrap().len(), EXPECTED_RESULT.len());
+
+        Ok(())
+    }
+
+    #[test]
+    fn failing_sink_does_not_stop_others() -> Result<(), Box<dyn std::error::Error>> {
+        let (mem, buf) = MemorySink::new(Format::Text);
+        let mut sinks = MultiSink::new();
+        sinks.push(Box::new(FailingSink));
+        sinks.push(Box::new(mem));
+
+        let err = sinks.emit(&EXPECTED_RESULT).unwrap_err();
+        assert!(err.to_string().contains("failing"), "{err}");
+        assert_eq!(*buf.borrow(), Format::Text.render(&EXPECTED_RESULT));
+
+        Ok(())
+    }
+
+    #[test]
+    fn unopenable_file_does_not_stop_others() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = tempfile::tempdir()?;
+        let missing = dir.path().join("missing").join("out.txt");
+        let good = dir.path().join("out.json");
+        let (mem, buf) = MemorySink::new(Format::Text);
+        let mut sinks = MultiSink::new();
+        sinks.push_file(&missing, Format::Text);
+        sinks.push(Box::new(mem));
+        sinks.push_file(&good, Format::Json);
+
+        let err = sinks.emit(&EXPECTED_RESULT).unwrap_err();
+        assert!(
+            err.to_string().contains(&missing.display().to_string()),
+            "{err}"
+        );
+        assert_eq!(*buf.borrow(), Format::Text.render(&EXPECTED_RESULT));
+        assert_eq!(
+            std::fs::read_to_string(&good)?,
+            Format::Json.render(&EXPECTED_RESULT)
+        );
+        assert!(!missing.exists());
+
+        Ok(())
+    }
+
+    #[test]
+    fn file_sink_is_atomic() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("out.txt");
+        std::fs::write(&path, "previous results\n")?;
+
+        // Simulate a failure part-way through writing by dropping the sink before finalizing
+        {
+            let mut sink = FileSink::create(&path, Format::Text)?;
+            sink.write("{partial")?;
+            sink.flush()?;
+        }
+        assert_eq!(std::fs::read_to_string(&path)?, "previous results\n");
+        assert_eq!(
+            std::fs::read_dir(dir.path())?.count(),
+            1,
+            "temporary file was left behind"
+        );
+
+        // Completing the write replaces the file all at once
+        let mut sinks = MultiSink::new();
+        sinks.push(Box::new(FileSink::create(&path, Format::Text)?));
+        sinks.emit(&EXPECTED_RESULT)?;
+        assert_eq!(
+            std::fs::read_to_string(&path)?,
+            Format::Text.render(&EXPECTED_RESULT)
+        );
+
+        Ok(())
+    }
+}
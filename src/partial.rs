@@ -0,0 +1,222 @@
+// 1BRC - my take on the 1 Billion Row Challenge
+// Copyright (C) 2024  Charles German <5donuts@pm.me>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Partial aggregates of a shard of the input, for solving the challenge in two stages: each
+//! shard is aggregated where it's stored (`--aggregate-partial`), and the partials are merged
+//! into the result somewhere else (`--reduce`).
+//!
+//! A partial file is [`MAGIC`], then the [`VERSION`] of the format & the number of stations, then
+//! a `(name, StationData)` record per station laid out like those of a
+//! [spill file](crate::spill), all little-endian. The format only ever changes along with its
+//! version, and files of any other version are refused rather than misread.
+
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, BufReader, Read};
+use std::path::Path;
+
+use crate::aggregate::{fold_case, Aggregator, StationData};
+use crate::config::Config;
+use crate::error::ChallengeError;
+use crate::helpers::{write_atomically, StationInfo};
+use crate::reader::LineReader;
+use crate::spill::{read_array, read_record, write_record};
+
+/// The first bytes of every partial file
+pub const MAGIC: &[u8; 8] = b"1BRCPART";
+
+/// The version of the partial format written by this build, & the only one it reads
+pub const VERSION: u32 = 1;
+
+/// Aggregate a shard of the input into the name & data of each station, in the order they were
+/// first seen.
+///
+/// Rows are only counted within the shard, so the first & last rows of each station aren't
+/// tracked; everything else in `config` which affects the result (e.g. the dialect, or merging
+/// stations case-insensitively) is honored.
+pub fn aggregate<R>(input: R, config: &Config) -> Result<Vec<(String, StationData)>, ChallengeError>
+where
+    R: io::Read + io::Seek,
+{
+    let mut aggregator: Aggregator = Aggregator::new()
+        .strict(config.strict.value)
+        .dialect(config.dialect())
+        .case_insensitive(config.case_insensitive.value)
+        .max_skipped(config.max_skipped.value);
+    let mut lines = LineReader::new(input, config);
+    loop {
+        match lines.next_line() {
+            Ok(Some(line)) => aggregator.ingest_line(line)?,
+            Ok(None) => break,
+            Err(e) => aggregator.skip_unreadable(e)?,
+        }
+    }
+    Ok(aggregator.into_stations())
+}
+
+/// Write a partial aggregate to `path`, replacing it all at once
+pub fn write(path: &Path, stations: &[(String, StationData)]) -> io::Result<()> {
+    let mut contents = Vec::new();
+    contents.extend_from_slice(MAGIC);
+    contents.extend_from_slice(&VERSION.to_le_bytes());
+    contents.extend_from_slice(&(stations.len() as u64).to_le_bytes());
+    for (name, data) in stations {
+        write_record(&mut contents, name, data)?;
+    }
+    write_atomically(path, &contents)
+}
+
+/// Read the partial aggregate at `path`, checking it's in this build's version of the format
+pub fn read(path: &Path) -> io::Result<Vec<(String, StationData)>> {
+    let invalid = |reason: String| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("{}: {reason}", path.display()),
+        )
+    };
+    let mut r = BufReader::new(File::open(path)?);
+
+    let magic: [u8; 8] =
+        read_array(&mut r).map_err(|_| invalid("not a partial aggregate".into()))?;
+    if &magic != MAGIC {
+        return Err(invalid("not a partial aggregate".into()));
+    }
+    let version = u32::from_le_bytes(read_array(&mut r)?);
+    if version != VERSION {
+        return Err(invalid(format!(
+            "version {version} of the partial format, but only version {VERSION} can be read"
+        )));
+    }
+
+    let len = u64::from_le_bytes(read_array(&mut r)?);
+    let stations = (0..len)
+        .map(|_| read_record(&mut r))
+        .collect::<io::Result<Vec<_>>>()
+        .map_err(|e| invalid(format!("truncated or corrupt ({e})")))?;
+    if r.read(&mut [0])? != 0 {
+        return Err(invalid(format!("more than the {len} stations it lists")));
+    }
+    Ok(stations)
+}
+
+/// Merge partial aggregates into the alphabetically-sorted list of stations.
+///
+/// With `case_insensitive`, stations whose names only differ in case are merged under the
+/// spelling in the earliest partial (see [`fold_case`]).
+pub fn reduce(
+    partials: impl IntoIterator<Item = Vec<(String, StationData)>>,
+    case_insensitive: bool,
+) -> Vec<StationInfo> {
+    let mut ids: HashMap<String, usize> = HashMap::new();
+    let mut merged: Vec<(String, StationData)> = Vec::new();
+    for (name, data) in partials.into_iter().flatten() {
+        let key = match case_insensitive {
+            true => fold_case(&name),
+            false => name.clone(),
+        };
+        match ids.entry(key) {
+            Entry::Occupied(id) => merged[*id.get()].1.merge(&data),
+            Entry::Vacant(id) => {
+                id.insert(merged.len());
+                merged.push((name, data));
+            }
+        }
+    }
+
+    let mut stations: Vec<StationInfo> = merged
+        .into_iter()
+        .filter(|(_, data)| data.cnt > 0)
+        .map(|(name, data)| StationInfo::new(name, data.min, data.max, data.avg(), data.cnt))
+        .collect();
+    stations.sort_unstable();
+    stations
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::runners::tests::{EXPECTED_RESULT, TEST_DATA};
+    use std::error;
+
+    #[test]
+    fn round_trip() -> Result<(), Box<dyn error::Error>> {
+        let dir = tempfile::tempdir()?;
+        let (first, _) = TEST_DATA.split_at(TEST_DATA.len() / 2);
+        let first = &first[..=first.rfind('\n').expect("There are two lines")];
+        let rest = &TEST_DATA[first.len()..];
+        assert_eq!(format!("{first}{rest}"), TEST_DATA);
+
+        let mut paths = Vec::new();
+        for (idx, shard) in [first, rest].into_iter().enumerate() {
+            let stations = aggregate(io::Cursor::new(shard), &Config::default())?;
+            let path = dir.path().join(format!("{idx}.part"));
+            write(&path, &stations)?;
+            assert_eq!(read(&path)?, stations);
+            paths.push(path);
+        }
+
+        let partials = paths
+            .iter()
+            .map(|p| read(p))
+            .collect::<io::Result<Vec<_>>>()?;
+        let actual = reduce(partials, false);
+        assert_eq!(actual, *EXPECTED_RESULT);
+        assert!(actual
+            .iter()
+            .zip(&*EXPECTED_RESULT)
+            .all(|(a, e)| a.eq_rounded(e)));
+
+        Ok(())
+    }
+
+    #[test]
+    fn refuses_other_versions() -> Result<(), Box<dyn error::Error>> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("station.part");
+        write(&path, &[("Hamburg".into(), StationData::new(12.0, false))])?;
+
+        let mut contents = std::fs::read(&path)?;
+        contents[MAGIC.len()..MAGIC.len() + 4].copy_from_slice(&2u32.to_le_bytes());
+        std::fs::write(&path, &contents)?;
+        let e = read(&path).unwrap_err();
+        assert!(
+            e.to_string().contains("version 2 of the partial format"),
+            "{e}"
+        );
+
+        std::fs::write(&path, b"Hamburg;12.0\n")?;
+        let e = read(&path).unwrap_err();
+        assert!(e.to_string().ends_with("not a partial aggregate"), "{e}");
+
+        Ok(())
+    }
+
+    #[test]
+    fn reduce_case_insensitive() {
+        let partial =
+            |name: &str, measurement| vec![(name.to_owned(), StationData::new(measurement, false))];
+        let partials = [
+            partial("Hamburg", 1.0),
+            partial("HAMBURG", 3.0),
+            partial("Bulawayo", 2.0),
+        ];
+
+        let actual = reduce(partials.clone(), true);
+        let names: Vec<_> = actual.iter().map(|s| (s.name(), s.count())).collect();
+        assert_eq!(names, [("Bulawayo", 1), ("Hamburg", 2)]);
+        assert_eq!(reduce(partials, false).len(), 3);
+    }
+}
@@ -0,0 +1,464 @@
+// 1BRC - my take on the 1 Billion Row Challenge
+// Copyright (C) 2024  Charles German <5donuts@pm.me>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Work out how a run will proceed without actually running it.
+//!
+//! Planning only looks at the input's size & probes the bytes around chunk boundaries, so it is
+//! cheap enough to do up front (see `--explain`) and can be tested separately from the runners.
+
+use std::fmt::Display;
+use std::fs::File;
+use std::io::{Read, Seek};
+use std::ops::Range;
+
+use serde::Serialize;
+
+use crate::aggregate::MAX_STATIONS;
+use crate::block_sizing::BlockSizing;
+use crate::boundaries::nearest_newline_after;
+use crate::config::Config;
+use crate::error::ChallengeError;
+use crate::helpers::StationInfo;
+use crate::table::StationTable;
+use crate::Runner;
+
+/// A rough guess at the average length of a station name, in bytes
+pub(crate) const AVG_NAME_LEN: usize = 16;
+
+/// How many chunks to list when printing a plan as text
+const SHOWN_CHUNKS: usize = 4;
+
+/// Everything decided about a run before the input is read in bulk
+#[derive(Debug, Serialize)]
+pub struct Plan<'a> {
+    /// The effective configuration the plan was made with
+    pub config: &'a Config,
+
+    /// How many threads will be aggregating measurements
+    pub threads: usize,
+
+    /// Newline-aligned byte ranges the input is split into for parallel processing
+    pub chunks: Vec<Range<u64>>,
+
+    /// How many reads of `buffer-size` bytes it will take to get through the input
+    pub blocks: u64,
+
+    /// A rough estimate of the peak memory used for reading & aggregating, in bytes
+    pub peak_memory: u64,
+
+    pub platform: Platform,
+}
+
+/// The platform-specific fast paths available & whether the runner will use them
+#[derive(Debug, Serialize)]
+pub struct Platform {
+    /// The widest SIMD instruction set detected on this CPU
+    pub simd: &'static str,
+    pub mmap: bool,
+    pub io_uring: bool,
+    pub prefetch: bool,
+}
+
+impl<'a> Plan<'a> {
+    /// Plan a run of the given configuration
+    pub fn build(config: &'a Config) -> Result<Self, ChallengeError> {
+        let mut f = File::open(&config.canonical_input.value)?;
+        let len = f.metadata()?.len();
+        let chunks = chunk_boundaries(
+            &mut f,
+            len,
+            config.num_chunks.value,
+            config.max_line_length.value,
+        )?;
+
+        let runner = config.runner.value;
+        let threads = match runner {
+            // A fixed number of parsers, whatever the chunks
+            Runner::Pipelined => crate::runners::Pipelined::WORKERS,
+            Runner::Crossbeam => crate::runners::Crossbeam::WORKERS,
+            // Likewise, whatever the ranges queued
+            Runner::WorkQueue => crate::runners::WorkQueue::WORKERS,
+            // One per core, whatever the chunks
+            #[cfg(feature = "affinity")]
+            Runner::ThreadPerCore => crate::runners::ThreadPerCore::threads(),
+            _ if runner.is_parallel() => chunks.len(),
+            _ => 1,
+        };
+        let buffer_size = config.buffer_size.value.max(1) as u64;
+
+        Ok(Self {
+            config,
+            threads,
+            blocks: len.div_ceil(buffer_size),
+            peak_memory: estimate_peak_memory(config, threads),
+            chunks,
+            platform: Platform {
+                simd: detect_simd(),
+                mmap: matches!(
+                    runner,
+                    Runner::Mmap
+                        | Runner::Memchr
+                        | Runner::Simd
+                        | Runner::Swar
+                        | Runner::Unchecked
+                        | Runner::ParMmap
+                ),
+                io_uring: uses_io_uring(runner),
+                prefetch: runner == Runner::TablePrefetch,
+            },
+        })
+    }
+}
+
+impl Display for Plan<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let used = |b: bool| if b { "yes" } else { "no" };
+
+        writeln!(f, "Execution plan:")?;
+        writeln!(f, "  runner:        {}", self.config.runner.value)?;
+        writeln!(f, "  threads:       {}", self.threads)?;
+        writeln!(
+            f,
+            "  reads:         {} of up to {} bytes",
+            self.blocks, self.config.buffer_size.value
+        )?;
+        writeln!(f, "  chunks:        {}", self.chunks.len())?;
+        for (i, chunk) in self.chunks.iter().take(SHOWN_CHUNKS).enumerate() {
+            writeln!(
+                f,
+                "    #{i}: bytes {}..{} ({} bytes)",
+                chunk.start,
+                chunk.end,
+                chunk.end - chunk.start
+            )?;
+        }
+        if self.chunks.len() > SHOWN_CHUNKS {
+            writeln!(f, "    ... {} more", self.chunks.len() - SHOWN_CHUNKS)?;
+        }
+        writeln!(
+            f,
+            "  peak memory:   ~{} bytes (estimated)",
+            self.peak_memory
+        )?;
+        writeln!(f, "  SIMD:          {} (detected)", self.platform.simd)?;
+        writeln!(f, "  mmap:          {}", used(self.platform.mmap))?;
+        writeln!(f, "  io_uring:      {}", used(self.platform.io_uring))?;
+        write!(f, "  prefetch:      {}", used(self.platform.prefetch))
+    }
+}
+
+/// Split `len` bytes of input into (up to) `n` chunks of roughly equal size.
+///
+/// Each boundary is moved forward to the start of the next line so no line is split between
+/// chunks. Chunks which end up empty (e.g. more chunks than lines) are dropped.
+///
+/// A boundary with no newline within `max_line_length` bytes of it is in a line which is too
+/// long, so it's dropped rather than searching any further: the chunk before it takes the whole
+/// line, & reports it (or skips it) the same way a runner reading line by line would.
+pub fn chunk_boundaries<R>(
+    input: &mut R,
+    len: u64,
+    n: usize,
+    max_line_length: usize,
+) -> Result<Vec<Range<u64>>, ChallengeError>
+where
+    R: Read + Seek,
+{
+    let n = n.max(1) as u64;
+    let mut starts = vec![0];
+    for i in 1..n {
+        let target = (len * i / n).max(*starts.last().unwrap());
+        match nearest_newline_after(input, target, max_line_length) {
+            Ok(start) => starts.push(start.min(len)),
+            Err(ChallengeError::NoLineBoundary { .. }) => continue,
+            Err(e) => return Err(e),
+        }
+    }
+    starts.push(len);
+
+    Ok(starts
+        .windows(2)
+        .map(|w| w[0]..w[1])
+        .filter(|chunk| !chunk.is_empty())
+        .collect())
+}
+
+/// Estimate the memory needed for the read buffers & station maps of every thread
+fn estimate_peak_memory(config: &Config, threads: usize) -> u64 {
+    let stations = config
+        .known_stations
+        .as_ref()
+        .map_or(MAX_STATIONS, |names| names.len());
+
+    let map = match config.runner.value {
+        Runner::Table | Runner::TablePrefetch | Runner::CachedTable => {
+            StationTable::estimated_size(stations) + stations * AVG_NAME_LEN
+        }
+        Runner::InlineTable => {
+            crate::runners::InlineTable::estimated_size(stations) + stations * AVG_NAME_LEN
+        }
+        Runner::PerfectHash => {
+            crate::runners::PerfectHash::estimated_size(stations) + stations * AVG_NAME_LEN
+        }
+        Runner::SampledDense => {
+            // A flat array of every station, plus the overflow map which is empty if the sample
+            // found every station; both are kept within each thread's share of any memory limit
+            let map = stations * std::mem::size_of::<crate::aggregate::StationData>();
+            match config.max_memory.value {
+                0 => map,
+                limit => map.min(usize::try_from(limit).unwrap_or(usize::MAX) / threads.max(1)),
+            }
+        }
+        Runner::Baseline
+        | Runner::RustcHash
+        | Runner::EntryRef
+        | Runner::Fadvise
+        | Runner::AHash
+        | Runner::Mmap
+        | Runner::Memchr
+        | Runner::Simd
+        | Runner::Swar
+        | Runner::Unchecked
+        | Runner::FixedPoint
+        | Runner::Branchless
+        | Runner::ByteKeys
+        | Runner::CompactKeys
+        | Runner::HashKeys
+        | Runner::DoubleBuffered
+        | Runner::ParMmap
+        | Runner::ScopedThreads
+        | Runner::Pipelined
+        | Runner::Crossbeam => hash_map_size(stations),
+        // Every range's map is kept until they're all merged, split between the threads
+        Runner::WorkQueue => {
+            use crate::runners::WorkQueue;
+            let ranges = config
+                .input_size
+                .value
+                .div_ceil(WorkQueue::CHUNK_SIZE)
+                .max(1);
+            hash_map_size(stations)
+                * usize::try_from(ranges)
+                    .unwrap_or(usize::MAX)
+                    .div_ceil(threads.max(1))
+        }
+        // The map is sized for the most stations the challenge allows, whether or not they turn up
+        Runner::Presized => hash_map_size(stations.max(MAX_STATIONS)),
+        #[cfg(feature = "io-uring")]
+        Runner::IoUring => hash_map_size(stations),
+        #[cfg(feature = "direct-io")]
+        Runner::DirectIo => hash_map_size(stations),
+        #[cfg(feature = "tokio")]
+        Runner::Tokio => hash_map_size(stations),
+        #[cfg(feature = "affinity")]
+        Runner::ThreadPerCore => hash_map_size(stations),
+    };
+    let buffer = match config.runner.value {
+        // A mapped input is paged in by the OS rather than copied into a buffer
+        Runner::Mmap
+        | Runner::Memchr
+        | Runner::Simd
+        | Runner::Swar
+        | Runner::Unchecked
+        | Runner::ParMmap => 0,
+        // A buffer for each read in flight, as well as the one lines are read from
+        #[cfg(feature = "io-uring")]
+        Runner::IoUring => {
+            config.buffer_size.value * (crate::runners::IoUring::DEPTH + 1)
+                + config.max_line_length.value
+        }
+        // The aligned buffer read into, as well as the one lines are read from
+        #[cfg(feature = "direct-io")]
+        Runner::DirectIo => {
+            config
+                .buffer_size
+                .value
+                .max(1)
+                .next_multiple_of(crate::runners::DirectIo::ALIGN)
+                + config.buffer_size.value
+                + config.max_line_length.value
+        }
+        // The blocks queued for each parser, as well as the one it's parsing
+        Runner::Pipelined => {
+            let block_size = match config.block_sizing.value {
+                BlockSizing::Fixed => crate::runners::Pipelined::BLOCK_SIZE,
+                BlockSizing::Adaptive { max, .. } => max,
+            };
+            block_size * (crate::runners::Pipelined::QUEUE_DEPTH + 1) + config.max_line_length.value
+        }
+        // The buffers are shared by every parser, so split them between the threads
+        Runner::Crossbeam => {
+            use crate::runners::Crossbeam;
+            (Crossbeam::BLOCK_SIZE + config.max_line_length.value) * Crossbeam::BUFFERS
+                / Crossbeam::WORKERS
+        }
+        // One buffer being read into while the other's parsed
+        Runner::DoubleBuffered => {
+            (config.buffer_size.value + config.max_line_length.value)
+                * crate::runners::DoubleBuffered::BUFFERS
+        }
+        _ => config.buffer_size.value + config.max_line_length.value,
+    };
+    let per_thread = buffer + map;
+    let output = stations * (std::mem::size_of::<StationInfo>() + AVG_NAME_LEN);
+
+    (per_thread * threads + output) as u64
+}
+
+/// Estimate the memory needed for a `HashMap` of `stations` stations & their names
+fn hash_map_size(stations: usize) -> usize {
+    // Hash maps keep at least 1/8 of their buckets empty, with a control byte per bucket
+    let bucket = std::mem::size_of::<(String, crate::aggregate::StationData)>() + 1;
+    stations * bucket * 8 / 7 + stations * AVG_NAME_LEN
+}
+
+/// Whether the runner reads the input through io_uring
+#[cfg(feature = "io-uring")]
+fn uses_io_uring(runner: Runner) -> bool {
+    runner == Runner::IoUring
+}
+
+#[cfg(not(feature = "io-uring"))]
+fn uses_io_uring(_: Runner) -> bool {
+    false
+}
+
+#[cfg(target_arch = "x86_64")]
+fn detect_simd() -> &'static str {
+    if is_x86_feature_detected!("avx512bw") {
+        "AVX-512"
+    } else if is_x86_feature_detected!("avx2") {
+        "AVX2"
+    } else if is_x86_feature_detected!("sse4.2") {
+        "SSE4.2"
+    } else {
+        "SSE2"
+    }
+}
+
+#[cfg(target_arch = "aarch64")]
+fn detect_simd() -> &'static str {
+    "NEON"
+}
+
+#[cfg(not(any(target_arch = "x86_64", target_arch = "aarch64")))]
+fn detect_simd() -> &'static str {
+    "none"
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{Layer, Setting, Source};
+    use crate::runners::tests::TEST_DATA;
+    use std::io;
+
+    #[test]
+    fn three_chunks() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = tempfile::tempdir()?;
+        let input = dir.path().join("measurements.txt");
+        std::fs::write(&input, TEST_DATA)?;
+        let mut config =
+            Config::resolve(&input, Layer::default(), Layer::default(), Layer::default());
+        config.num_chunks = Setting::new(3, Source::Cli);
+
+        let plan = Plan::build(&config)?;
+        assert_eq!(plan.chunks.len(), 3, "{plan}");
+
+        // Contiguous, so neither overlapping nor missing anything
+        assert_eq!(plan.chunks[0].start, 0);
+        assert_eq!(plan.chunks[2].end, TEST_DATA.len() as u64);
+        for w in plan.chunks.windows(2) {
+            assert_eq!(w[0].end, w[1].start, "{plan}");
+        }
+
+        // Every chunk starts at the start of a line
+        for chunk in &plan.chunks {
+            let start = chunk.start as usize;
+            assert!(start == 0 || TEST_DATA.as_bytes()[start - 1] == b'\n');
+        }
+
+        Ok(())
+    }
+
+    /// `count` lines of `line_len` bytes each (including the newline)
+    fn lines(count: usize, line_len: usize) -> Vec<u8> {
+        let line = format!("{};12.3\n", "x".repeat(line_len - 6));
+        line.repeat(count).into_bytes()
+    }
+
+    /// Check the chunks are contiguous, cover the input, and each start at the start of a line
+    fn assert_aligned(input: &[u8], chunks: &[Range<u64>]) {
+        assert_eq!(chunks.first().map(|c| c.start), Some(0));
+        assert_eq!(chunks.last().map(|c| c.end), Some(input.len() as u64));
+        for w in chunks.windows(2) {
+            assert_eq!(w[0].end, w[1].start, "{chunks:?}");
+        }
+        for chunk in chunks {
+            let start = chunk.start as usize;
+            assert!(start == 0 || input[start - 1] == b'\n', "{chunks:?}");
+        }
+    }
+
+    #[test]
+    fn more_chunks_than_lines() -> Result<(), ChallengeError> {
+        let input = "A;1.0\nB;2.0\n";
+        let chunks = chunk_boundaries(&mut io::Cursor::new(input), input.len() as u64, 8, 1024)?;
+        assert_eq!(chunks, vec![0..6, 6..12]);
+        Ok(())
+    }
+
+    #[test]
+    fn long_lines() -> Result<(), ChallengeError> {
+        // Lines which fit in the first probe, and ones which need it to grow (too long for strict
+        // mode, but still within the maximum line length)
+        for line_len in [90, 300] {
+            let input = lines(50, line_len);
+            let chunks =
+                chunk_boundaries(&mut io::Cursor::new(&input), input.len() as u64, 7, 1024)?;
+            assert_eq!(chunks.len(), 7, "{line_len}-byte lines");
+            assert_aligned(&input, &chunks);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn no_line_boundary() {
+        // A region with no newlines longer than the maximum line length
+        let mut input = lines(10, 90);
+        input.extend(std::iter::repeat_n(b'x', 4000));
+        input.extend(lines(10, 90));
+
+        // The boundary in it is dropped, for the first chunk to find the line too long
+        let chunks =
+            chunk_boundaries(&mut io::Cursor::new(&input), input.len() as u64, 2, 1024).unwrap();
+        assert_eq!(chunks, vec![0..input.len() as u64]);
+        // Boundaries either side of it are kept, & one chunk has all of it
+        let chunks =
+            chunk_boundaries(&mut io::Cursor::new(&input), input.len() as u64, 8, 1024).unwrap();
+        assert_aligned(&input, &chunks);
+        let (start, end) = (900, 4900);
+        assert!(chunks.len() > 2, "{chunks:?}");
+        assert!(
+            chunks.iter().any(|c| c.start <= start && c.end > end),
+            "{chunks:?}"
+        );
+
+        // Unless the maximum line length allows it
+        let chunks =
+            chunk_boundaries(&mut io::Cursor::new(&input), input.len() as u64, 2, 8192).unwrap();
+        assert_aligned(&input, &chunks);
+    }
+}
@@ -0,0 +1,120 @@
+// 1BRC - my take on the 1 Billion Row Challenge
+// Copyright (C) 2024  Charles German <5donuts@pm.me>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Hints to the OS about how the input will be read, & reading it without a shared cursor.
+//!
+//! The hints only change how the page cache treats the input, never what's read, so on platforms
+//! without them each is a no-op which always succeeds.
+
+use std::fs::File;
+use std::io;
+use std::ops::Range;
+
+/// Tell the kernel the whole of `file` will be read from start to end, so it reads further ahead
+#[cfg(target_os = "linux")]
+pub fn advise_sequential(file: &File) -> io::Result<()> {
+    fadvise(file, 0..0, libc::POSIX_FADV_SEQUENTIAL)
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn advise_sequential(_file: &File) -> io::Result<()> {
+    Ok(())
+}
+
+/// Tell the kernel the bytes of `file` in `range` won't be read again, so their pages can be
+/// dropped from the page cache rather than pushing out pages which will be
+#[cfg(target_os = "linux")]
+pub fn drop_cached(file: &File, range: Range<u64>) -> io::Result<()> {
+    if range.is_empty() {
+        return Ok(());
+    }
+    fadvise(file, range, libc::POSIX_FADV_DONTNEED)
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn drop_cached(_file: &File, _range: Range<u64>) -> io::Result<()> {
+    Ok(())
+}
+
+/// Read from `offset` in `file` into `buf`, without moving (or needing exclusive use of) the
+/// file's cursor, so any number of threads can read the same file handle at once (pread(2))
+#[cfg(unix)]
+pub fn read_at(file: &File, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+    std::os::unix::fs::FileExt::read_at(file, buf, offset)
+}
+
+/// Read from `offset` in `file` into `buf`. Windows moves the cursor regardless, but never needs
+/// it to be anywhere in particular first, so concurrent reads are still safe.
+#[cfg(windows)]
+pub fn read_at(file: &File, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+    std::os::windows::fs::FileExt::seek_read(file, buf, offset)
+}
+
+/// Give the kernel `advice` about `range` of `file`, where an empty range at the start means
+/// the whole file (see posix_fadvise(2))
+#[cfg(target_os = "linux")]
+fn fadvise(file: &File, range: Range<u64>, advice: libc::c_int) -> io::Result<()> {
+    use std::os::fd::AsRawFd;
+
+    let offset = libc::off_t::try_from(range.start).map_err(io::Error::other)?;
+    let len = libc::off_t::try_from(range.end - range.start).map_err(io::Error::other)?;
+    // SAFETY: the descriptor belongs to `file`, which is borrowed for the whole call, & the
+    // advice only affects caching
+    match unsafe { libc::posix_fadvise(file.as_raw_fd(), offset, len, advice) } {
+        0 => Ok(()),
+        // Unlike most calls, the error is returned rather than left in `errno`
+        errno => Err(io::Error::from_raw_os_error(errno)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+
+    #[test]
+    fn hints_leave_contents_alone() -> io::Result<()> {
+        let mut file = tempfile::tempfile()?;
+        std::io::Write::write_all(&mut file, &[b'x'; 8192])?;
+        std::io::Seek::rewind(&mut file)?;
+
+        advise_sequential(&file)?;
+        drop_cached(&file, 0..4096)?;
+        drop_cached(&file, 4096..4096)?;
+
+        let mut contents = Vec::new();
+        file.read_to_end(&mut contents)?;
+        assert_eq!(contents, [b'x'; 8192]);
+
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn read_at_leaves_the_cursor() -> io::Result<()> {
+        let mut file = tempfile::tempfile()?;
+        std::io::Write::write_all(&mut file, b"Hamburg;12.0\nBulawayo;8.9\n")?;
+        std::io::Seek::rewind(&mut file)?;
+
+        let mut buf = [0; 8];
+        assert_eq!(read_at(&file, &mut buf, 13)?, 8);
+        assert_eq!(&buf, b"Bulawayo");
+        let mut first = [0; 7];
+        file.read_exact(&mut first)?;
+        assert_eq!(&first, b"Hamburg");
+
+        Ok(())
+    }
+}
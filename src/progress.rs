@@ -0,0 +1,452 @@
+// 1BRC - my take on the 1 Billion Row Challenge
+// Copyright (C) 2024  Charles German <5donuts@pm.me>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Live progress of a run, for watching it from another thread (e.g. with `--tui`).
+//!
+//! A run given a [`Progress`] (see [`Config::progress`](crate::config::Config::progress)) has
+//! its [`LineReader`](crate::reader::LineReader)s & [`BlockReader`](crate::blocks::BlockReader)s
+//! count the bytes each thread reads as they go, & the runners built on an
+//! [`Aggregator`](crate::aggregate::Aggregator) publish a [`Snapshot`] of the stations so far a
+//! few times a second. Anyone watching takes a [`Reading`] whenever they like & turns it into a
+//! [`View`], which is all plain data, so what's shown can be worked out (& tested) apart from
+//! how it's drawn.
+
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread::{self, ThreadId};
+use std::time::{Duration, Instant};
+
+use crate::error::SkippedLines;
+use crate::helpers::{fmt_bytes, RunStats, StationInfo};
+
+/// How often runners publish a [`Snapshot`], at most
+pub const SNAPSHOT_EVERY: Duration = Duration::from_millis(250);
+
+/// How many lines a runner aggregates between checking whether a [`Snapshot`] is due
+pub const CHECK_EVERY: u32 = 1 << 16;
+
+/// How many bytes a reader reads between counting them, at least (other than at the end of the
+/// input), so the readers' threads don't contend on the count
+pub const REPORT_EVERY: u64 = 64 * 1024;
+
+/// How many of the hottest & coldest stations a [`View`] lists
+pub const TOP: usize = 10;
+
+/// The stations aggregated so far, & the lines left out of them
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Snapshot {
+    /// In no particular order
+    pub stations: Vec<StationInfo>,
+    pub skipped: SkippedLines,
+    pub ignored_non_finite: u64,
+}
+
+/// Collects the progress of a run from its threads
+#[derive(Debug, Default)]
+pub struct Progress {
+    state: Mutex<State>,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    started: Option<Instant>,
+
+    /// How many bytes of the input there are to read
+    total: u64,
+
+    /// How many bytes each thread has read, in the order they started reading
+    threads: Vec<(ThreadId, u64)>,
+    snapshot: Option<Arc<Snapshot>>,
+    snapshot_at: Option<Instant>,
+}
+
+impl Progress {
+    /// Forget any previous run, & start timing one with `total` bytes to read
+    pub fn start(&self, total: u64) {
+        *self.lock() = State {
+            started: Some(Instant::now()),
+            total,
+            ..State::default()
+        };
+    }
+
+    /// Count `bytes` more read by the calling thread
+    pub fn advance(&self, bytes: u64) {
+        let id = thread::current().id();
+        let mut state = self.lock();
+        match state.threads.iter_mut().find(|(thread, _)| *thread == id) {
+            Some((_, read)) => *read += bytes,
+            None => state.threads.push((id, bytes)),
+        }
+    }
+
+    /// Whether it's been at least [`SNAPSHOT_EVERY`] since the last snapshot was published
+    pub fn snapshot_due(&self) -> bool {
+        self.lock()
+            .snapshot_at
+            .is_none_or(|at| at.elapsed() >= SNAPSHOT_EVERY)
+    }
+
+    /// Publish the latest snapshot of the stations, in place of the last
+    pub fn publish(&self, snapshot: Snapshot) {
+        let mut state = self.lock();
+        state.snapshot = Some(Arc::new(snapshot));
+        state.snapshot_at = Some(Instant::now());
+    }
+
+    /// How the run's going right now
+    pub fn reading(&self) -> Reading {
+        let state = self.lock();
+        Reading {
+            elapsed: state.started.map(|s| s.elapsed()).unwrap_or_default(),
+            total: state.total,
+            threads: state.threads.iter().map(|&(_, read)| read).collect(),
+            snapshot: state.snapshot.clone(),
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, State> {
+        // A thread which panicked while holding the lock fails the whole run anyway
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// The progress of a run at one moment
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Reading {
+    pub elapsed: Duration,
+    pub total: u64,
+
+    /// How many bytes each thread has read, in the order they started reading
+    pub threads: Vec<u64>,
+    pub snapshot: Option<Arc<Snapshot>>,
+}
+
+/// What to show of a [`Reading`]
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct View {
+    /// How much of the input has been read, from 0 to 1
+    pub ratio: f64,
+
+    /// How much has been read, of how much, & how fast
+    pub label: String,
+
+    pub threads: Vec<ThreadActivity>,
+
+    /// The [`TOP`] stations with the highest means, highest first
+    pub hottest: Vec<StationRow>,
+
+    /// The [`TOP`] stations with the lowest means, lowest first
+    pub coldest: Vec<StationRow>,
+
+    /// How many stations have been seen
+    pub stations: usize,
+    pub skipped: u64,
+    pub ignored_non_finite: u64,
+}
+
+/// How much one thread has read
+#[derive(Debug, Clone, PartialEq)]
+pub struct ThreadActivity {
+    pub label: String,
+
+    /// Its share of the input, from 0 to 1
+    pub ratio: f64,
+}
+
+/// A station in a [`View`]'s tables
+#[derive(Debug, Clone, PartialEq)]
+pub struct StationRow {
+    pub name: String,
+    pub mean: f32,
+    pub min: f32,
+    pub max: f32,
+    pub count: u32,
+}
+
+impl From<&StationInfo> for StationRow {
+    fn from(station: &StationInfo) -> Self {
+        Self {
+            name: station.name().to_owned(),
+            mean: station.avg(),
+            min: station.min(),
+            max: station.max(),
+            count: station.count(),
+        }
+    }
+}
+
+impl View {
+    /// What to show of a run in progress, from its latest snapshot (if it's published any)
+    pub fn new(reading: &Reading) -> Self {
+        let read: u64 = reading.threads.iter().sum();
+        let mut view = Self {
+            ratio: ratio(read, reading.total),
+            label: label(read, reading.total, reading.elapsed),
+            threads: threads(&reading.threads, reading.total),
+            ..Self::default()
+        };
+        if let Some(snapshot) = &reading.snapshot {
+            view.show_stations(&snapshot.stations);
+            view.skipped = snapshot.skipped.total();
+            view.ignored_non_finite = snapshot.ignored_non_finite;
+        }
+        view
+    }
+
+    /// What to show of a finished run, from its results rather than its last snapshot
+    pub fn finished(reading: &Reading, stations: &[StationInfo], stats: &RunStats) -> Self {
+        let mut view = Self::new(reading);
+        view.ratio = 1.0;
+        view.show_stations(stations);
+        view.skipped = stats.skipped.total();
+        view.ignored_non_finite = stats.ignored_non_finite;
+        view
+    }
+
+    fn show_stations(&mut self, stations: &[StationInfo]) {
+        let (hottest, coldest) = extremes(stations, TOP);
+        self.hottest = hottest;
+        self.coldest = coldest;
+        self.stations = stations.iter().filter(|s| !s.is_missing()).count();
+    }
+}
+
+/// `part` of `whole`, from 0 to 1, or 0 if there's no whole to speak of
+fn ratio(part: u64, whole: u64) -> f64 {
+    match whole {
+        0 => 0.0,
+        whole => (part as f64 / whole as f64).clamp(0.0, 1.0),
+    }
+}
+
+/// e.g. `512.0 MiB / 1.0 GiB (50%), 256.0 MiB/s`
+fn label(read: u64, total: u64, elapsed: Duration) -> String {
+    let secs = elapsed.as_secs_f64();
+    let throughput = if secs > 0.0 {
+        (read as f64 / secs) as u64
+    } else {
+        0
+    };
+    format!(
+        "{} / {} ({:.0}%), {}/s",
+        fmt_bytes(read),
+        fmt_bytes(total),
+        ratio(read, total) * 100.0,
+        fmt_bytes(throughput),
+    )
+}
+
+/// Each thread's share of the `total`
+fn threads(read: &[u64], total: u64) -> Vec<ThreadActivity> {
+    read.iter()
+        .enumerate()
+        .map(|(idx, &read)| ThreadActivity {
+            label: format!("thread {}", idx + 1),
+            ratio: ratio(read, total),
+        })
+        .collect()
+}
+
+/// The `n` stations with the highest means, highest first, & the `n` with the lowest, lowest
+/// first. Stations with the same mean are listed by name.
+pub fn extremes(stations: &[StationInfo], n: usize) -> (Vec<StationRow>, Vec<StationRow>) {
+    let mut rows: Vec<StationRow> = stations
+        .iter()
+        .filter(|station| !station.is_missing())
+        .map(StationRow::from)
+        .collect();
+    rows.sort_by(|a, b| a.mean.total_cmp(&b.mean).then_with(|| a.name.cmp(&b.name)));
+    let coldest = rows.iter().take(n).cloned().collect();
+    rows.sort_by(|a, b| b.mean.total_cmp(&a.mean).then_with(|| a.name.cmp(&b.name)));
+    rows.truncate(n);
+    (rows, coldest)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::helpers::Timings;
+
+    fn station(name: &str, mean: f32) -> StationInfo {
+        StationInfo::new(name.to_owned(), mean - 1.0, mean + 1.0, mean, 3)
+    }
+
+    #[test]
+    fn progress() {
+        let progress = Progress::default();
+        progress.start(1000);
+        assert!(progress.snapshot_due());
+        progress.advance(100);
+        thread::scope(|s| {
+            s.spawn(|| progress.advance(300));
+        });
+        progress.advance(50);
+
+        let reading = progress.reading();
+        assert_eq!((reading.total, reading.threads), (1000, vec![150, 300]));
+        assert_eq!(reading.snapshot, None);
+
+        progress.publish(Snapshot {
+            stations: vec![station("Hamburg", 12.0)],
+            ..Snapshot::default()
+        });
+        assert!(!progress.snapshot_due());
+        assert_eq!(progress.reading().snapshot.unwrap().stations.len(), 1);
+
+        // Starting again forgets the last run
+        progress.start(10);
+        assert_eq!(progress.reading().threads, Vec::<u64>::new());
+        assert!(progress.snapshot_due());
+    }
+
+    /// Both kinds of reader count every byte they read, once
+    #[test]
+    fn readers_count_everything() -> Result<(), Box<dyn std::error::Error>> {
+        use crate::config::{Config, Setting, Source};
+        use crate::runners::tests::TEST_DATA;
+        use crate::Runner;
+
+        for runner in [Runner::Baseline, Runner::ByteKeys, Runner::DoubleBuffered] {
+            let progress = Arc::new(Progress::default());
+            let config = Config {
+                buffer_size: Setting::new(16, Source::Cli),
+                progress: Some(progress.clone()),
+                ..Config::default().with_runner(runner, Source::Cli)
+            };
+            progress.start(TEST_DATA.len() as u64);
+            crate::runners::run_with(std::io::Cursor::new(TEST_DATA), &config)?;
+            let read: u64 = progress.reading().threads.iter().sum();
+            assert_eq!(read, TEST_DATA.len() as u64, "{runner}");
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn view_before_snapshot() {
+        let reading = Reading {
+            elapsed: Duration::from_secs(2),
+            total: 4 << 20,
+            threads: vec![1 << 20, 2 << 20],
+            snapshot: None,
+        };
+        let view = View::new(&reading);
+        assert_eq!(view.ratio, 0.75);
+        assert_eq!(view.label, "3.0 MiB / 4.0 MiB (75%), 1.5 MiB/s");
+        assert_eq!(
+            view.threads,
+            [
+                ThreadActivity {
+                    label: "thread 1".to_owned(),
+                    ratio: 0.25
+                },
+                ThreadActivity {
+                    label: "thread 2".to_owned(),
+                    ratio: 0.5
+                },
+            ]
+        );
+        assert!(view.hottest.is_empty() && view.coldest.is_empty());
+        assert_eq!((view.stations, view.skipped), (0, 0));
+
+        // Nothing to read, or no time taken yet, is nothing done rather than a division by zero
+        let view = View::new(&Reading::default());
+        assert_eq!(view.ratio, 0.0);
+        assert_eq!(view.label, "0 B / 0 B (0%), 0 B/s");
+    }
+
+    #[test]
+    fn view_of_snapshot() {
+        let skipped = SkippedLines {
+            no_semicolon: 2,
+            bad_temperature: 1,
+            ..SkippedLines::default()
+        };
+        let mut stations: Vec<_> = (0..15)
+            .map(|i| station(&format!("Station {i:02}"), i as f32))
+            .collect();
+        stations.push(station("Also 14", 14.0));
+        stations.push(StationInfo::missing("Nowhere".to_owned()));
+        let reading = Reading {
+            elapsed: Duration::from_secs(1),
+            total: 100,
+            threads: vec![100],
+            snapshot: Some(Arc::new(Snapshot {
+                stations,
+                skipped,
+                ignored_non_finite: 4,
+            })),
+        };
+
+        let view = View::new(&reading);
+        let names = |rows: &[StationRow]| -> Vec<String> {
+            rows.iter().map(|row| row.name.clone()).collect()
+        };
+        assert_eq!(view.hottest.len(), TOP);
+        assert_eq!(
+            names(&view.hottest[..3]),
+            ["Also 14", "Station 14", "Station 13"]
+        );
+        assert_eq!(
+            view.hottest[0],
+            StationRow {
+                name: "Also 14".to_owned(),
+                mean: 14.0,
+                min: 13.0,
+                max: 15.0,
+                count: 3
+            }
+        );
+        assert_eq!(view.coldest.len(), TOP);
+        assert_eq!(names(&view.coldest[..2]), ["Station 00", "Station 01"]);
+        assert_eq!(view.stations, 16);
+        assert_eq!((view.skipped, view.ignored_non_finite), (3, 4));
+    }
+
+    /// A finished run is shown from its results, however long ago the last snapshot was
+    #[test]
+    fn finished_view() {
+        let reading = Reading {
+            elapsed: Duration::from_secs(1),
+            total: 100,
+            threads: vec![90],
+            snapshot: Some(Arc::new(Snapshot {
+                stations: vec![station("Hamburg", 12.0)],
+                ..Snapshot::default()
+            })),
+        };
+        let stations = [station("Hamburg", 12.5), station("Palembang", 38.8)];
+        let skipped = SkippedLines {
+            too_long: 1,
+            ..SkippedLines::default()
+        };
+        let timings = Timings {
+            aggregated: None,
+            total: Duration::from_secs(1),
+        };
+        let stats = RunStats::new(timings)
+            .skipped(skipped)
+            .ignored_non_finite(2);
+
+        let view = View::finished(&reading, &stations, &stats);
+        assert_eq!(view.ratio, 1.0);
+        assert_eq!(view.hottest[0].name, "Palembang");
+        assert_eq!(view.coldest[0].mean, 12.5);
+        assert_eq!(
+            (view.stations, view.skipped, view.ignored_non_finite),
+            (2, 1, 2)
+        );
+    }
+}
@@ -0,0 +1,313 @@
+// 1BRC - my take on the 1 Billion Row Challenge
+// Copyright (C) 2024  Charles German <5donuts@pm.me>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::io::{self, BufRead, BufReader};
+use std::sync::Arc;
+
+use crate::aggregate::Encoding;
+use crate::boundaries::first_newline;
+use crate::config::Config;
+use crate::error::ChallengeError;
+use crate::progress::{Progress, REPORT_EVERY};
+
+/// The UTF-8 encoding of U+FEFF, which some Windows tools prepend to text files
+const BOM: &[u8] = b"\xEF\xBB\xBF";
+
+/// Read the input line-by-line through a fixed-size block buffer.
+///
+/// A line that straddles the end of a block is carried over into a separate line buffer which
+/// grows as needed, up to the configured maximum line length. Past that, reading fails with
+/// [`ChallengeError::LineTooLong`] rather than allocating without bound, no matter how small
+/// the block size is relative to the line. The rest of that line is skipped if reading carries
+/// on afterwards.
+///
+/// A UTF-8 byte-order mark at the very start of the input is stripped (with a warning at `-v`)
+/// unless running in strict mode, where it's reported as [`ChallengeError::ByteOrderMark`].
+///
+/// Lines in an [`Encoding`] other than UTF-8 are transcoded as they're read, unless they're all
+/// ASCII.
+pub struct LineReader<R> {
+    inner: BufReader<R>,
+    line: Vec<u8>,
+
+    /// The current line transcoded to UTF-8, if it needed transcoding
+    decoded: String,
+    encoding: Encoding,
+    offset: u64,
+    max_line_length: usize,
+    strict: bool,
+    verbose: u8,
+    skip_rest: bool,
+
+    /// Where to count what's been read, & how much of it has been counted so far
+    progress: Option<Arc<Progress>>,
+    reported: u64,
+}
+
+impl<R: io::Read> LineReader<R> {
+    pub fn new(input: R, config: &Config) -> Self {
+        Self {
+            // An empty block buffer would be indistinguishable from the end of the input
+            inner: BufReader::with_capacity(config.buffer_size.value.max(1), input),
+            line: Vec::new(),
+            decoded: String::new(),
+            encoding: config.encoding.value,
+            offset: 0,
+            max_line_length: config.max_line_length.value,
+            strict: config.strict.value,
+            verbose: config.verbose,
+            skip_rest: false,
+            progress: config.progress.clone(),
+            reported: 0,
+        }
+    }
+
+    /// Read the next line, without its trailing newline (`\n` or `\r\n`).
+    ///
+    /// Returns `Ok(None)` once the input is exhausted.
+    pub fn next_line(&mut self) -> Result<Option<&str>, ChallengeError> {
+        let Some((start, _)) = self.next_line_bytes()? else {
+            return Ok(None);
+        };
+        self.encoding
+            .decode(&self.line, &mut self.decoded)
+            .map(Some)
+            .map_err(|e| ChallengeError::invalid_utf8(start, &self.line, e))
+    }
+
+    /// Read the next line like [`next_line`](LineReader::next_line), but without checking it's
+    /// valid UTF-8 (or transcoding it), along with the byte offset it starts at.
+    pub fn next_line_bytes(&mut self) -> Result<Option<(u64, &[u8])>, ChallengeError> {
+        self.line.clear();
+        if self.skip_rest {
+            self.skip_line()?;
+        }
+        let mut start = self.offset;
+
+        loop {
+            let block = self.inner.fill_buf()?;
+            if block.is_empty() {
+                self.report(0);
+                // A final line without a trailing newline is still a line
+                if self.line.is_empty() {
+                    return Ok(None);
+                }
+                break;
+            }
+
+            let (chunk, found_newline) = match first_newline(block) {
+                Some(idx) => (&block[..idx], true),
+                None => (block, false),
+            };
+            if self.line.len() + chunk.len() > self.max_line_length {
+                self.skip_rest = true;
+                return Err(ChallengeError::LineTooLong {
+                    offset: start,
+                    limit: self.max_line_length,
+                });
+            }
+            self.line.extend_from_slice(chunk);
+
+            let consumed = chunk.len() + found_newline as usize;
+            self.inner.consume(consumed);
+            self.offset += consumed as u64;
+
+            if found_newline {
+                break;
+            }
+        }
+
+        self.report(REPORT_EVERY);
+        if self.line.last() == Some(&b'\r') {
+            self.line.pop();
+        }
+
+        // Checked once the whole first line is read so it works with any block size
+        if start == 0 {
+            let skip = leading_bom(&self.line, self.strict, self.verbose)?;
+            self.line.drain(..skip);
+            start += skip as u64;
+        }
+
+        Ok(Some((start, &self.line)))
+    }
+
+    /// Count what's been read since it was last counted, if it's at least `every` bytes
+    fn report(&mut self, every: u64) {
+        if let Some(progress) = &self.progress {
+            let unreported = self.offset - self.reported;
+            if unreported >= every.max(1) {
+                progress.advance(unreported);
+                self.reported = self.offset;
+            }
+        }
+    }
+
+    /// Skip past the next newline, e.g. to get to the end of a line which was too long
+    fn skip_line(&mut self) -> io::Result<()> {
+        loop {
+            let block = self.inner.fill_buf()?;
+            if block.is_empty() {
+                break;
+            }
+
+            let (consumed, found_newline) = match first_newline(block) {
+                Some(idx) => (idx + 1, true),
+                None => (block.len(), false),
+            };
+            self.inner.consume(consumed);
+            self.offset += consumed as u64;
+
+            if found_newline {
+                break;
+            }
+        }
+        self.skip_rest = false;
+        Ok(())
+    }
+}
+
+/// How many bytes of a byte-order mark to skip at the start of the input.
+///
+/// In strict mode, a byte-order mark is an error instead.
+pub(crate) fn leading_bom(
+    start: &[u8],
+    strict: bool,
+    verbose: u8,
+) -> Result<usize, ChallengeError> {
+    if !start.starts_with(BOM) {
+        return Ok(0);
+    }
+    if strict {
+        return Err(ChallengeError::ByteOrderMark);
+    }
+    if verbose > 0 {
+        eprintln!("Warning: ignoring the UTF-8 byte-order mark at the start of the input");
+    }
+    Ok(BOM.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{Setting, Source};
+
+    /// Build some input containing a single 5 KiB line between two short ones
+    fn long_line_input() -> (String, String) {
+        let long_name = "x".repeat(5 * 1024);
+        let input = format!("Short;1.0\n{long_name};2.0\nShort;3.0\n");
+        (input, long_name)
+    }
+
+    fn config(buffer_size: usize, max_line_length: usize) -> Config {
+        Config {
+            buffer_size: Setting::new(buffer_size, Source::Cli),
+            max_line_length: Setting::new(max_line_length, Source::Cli),
+            ..Default::default()
+        }
+    }
+
+    fn read_all(input: &str, config: &Config) -> Result<Vec<String>, ChallengeError> {
+        let mut reader = LineReader::new(input.as_bytes(), config);
+        let mut lines = Vec::new();
+        while let Some(line) = reader.next_line()? {
+            lines.push(line.to_owned());
+        }
+        Ok(lines)
+    }
+
+    #[test]
+    fn line_too_long() {
+        let (input, _) = long_line_input();
+
+        for buffer_size in [1, 7, 64] {
+            match read_all(&input, &config(buffer_size, 1024)) {
+                Err(ChallengeError::LineTooLong { offset, limit }) => {
+                    assert_eq!(offset, 10, "wrong offset with buffer size {buffer_size}");
+                    assert_eq!(limit, 1024);
+                }
+                other => {
+                    panic!("expected LineTooLong with buffer size {buffer_size}, got {other:?}")
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn after_line_too_long() {
+        let (input, _) = long_line_input();
+
+        for buffer_size in [1, 7, 64] {
+            let mut reader = LineReader::new(input.as_bytes(), &config(buffer_size, 1024));
+            assert_eq!(reader.next_line().unwrap(), Some("Short;1.0"));
+            assert!(reader.next_line().is_err());
+            assert_eq!(reader.next_line().unwrap(), Some("Short;3.0"));
+            assert_eq!(reader.next_line().unwrap(), None);
+        }
+    }
+
+    #[test]
+    fn raised_limit() -> Result<(), ChallengeError> {
+        let (input, long_name) = long_line_input();
+
+        for buffer_size in [1, 7, 64] {
+            let lines = read_all(&input, &config(buffer_size, 8 * 1024))?;
+            assert_eq!(
+                lines,
+                vec![
+                    String::from("Short;1.0"),
+                    format!("{long_name};2.0"),
+                    String::from("Short;3.0"),
+                ],
+                "wrong lines with buffer size {buffer_size}"
+            );
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn line_endings() -> Result<(), ChallengeError> {
+        let lines = read_all("A;1.0\r\nB;2.0", &config(4, 1024))?;
+        assert_eq!(lines, vec![String::from("A;1.0"), String::from("B;2.0")]);
+        Ok(())
+    }
+
+    #[test]
+    fn byte_order_mark() -> Result<(), ChallengeError> {
+        let input = "\u{FEFF}A;1.0\nB;2.0\n";
+
+        // Split the BOM across blocks too
+        for buffer_size in [1, 2, 64] {
+            let lines = read_all(input, &config(buffer_size, 1024))?;
+            assert_eq!(lines, vec![String::from("A;1.0"), String::from("B;2.0")]);
+        }
+
+        let strict = Config {
+            strict: Setting::new(true, Source::Cli),
+            ..config(64, 1024)
+        };
+        assert!(matches!(
+            read_all(input, &strict),
+            Err(ChallengeError::ByteOrderMark)
+        ));
+
+        // Only a BOM at the very start of the input is special
+        let lines = read_all("A;1.0\n\u{FEFF}B;2.0\n", &strict)?;
+        assert_eq!(lines[1], "\u{FEFF}B;2.0");
+
+        Ok(())
+    }
+}
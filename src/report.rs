@@ -0,0 +1,98 @@
+// 1BRC - my take on the 1 Billion Row Challenge
+// Copyright (C) 2024  Charles German <5donuts@pm.me>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Machine-readable reports of runs & benchmarks
+
+pub mod failure;
+pub mod merge;
+pub mod repro;
+pub mod schema;
+pub mod session;
+pub mod tradeoffs;
+
+use std::path::Path;
+use std::time::Duration;
+
+use crate::compare::RunnerStats;
+use crate::config::Config;
+use crate::error::SkippedLines;
+use crate::helpers::{Fallback, Timings};
+use crate::stats::BenchStats;
+use crate::topology::Topology;
+use crate::tune::Tunings;
+
+/// The timings for a single run or a benchmark, written as a [`schema::Report`]
+#[derive(Debug)]
+pub struct Report<'a> {
+    /// The effective configuration the timings were collected with
+    pub config: &'a Config,
+
+    /// The parallelism detected on the machine the timings were collected on
+    pub topology: Topology,
+
+    /// The timings of every run, in the order they were executed
+    pub runs: Vec<Timings>,
+
+    /// The mean of the runs kept by the benchmark, if benchmarking
+    pub mean: Option<Duration>,
+
+    /// The standard deviation of the runs kept by the benchmark, if benchmarking
+    pub std_dev: Option<Duration>,
+
+    /// Statistics for just the aggregation part of the runs kept by the benchmark, if
+    /// benchmarking with [`Config::timings`] set
+    pub aggregated: Option<BenchStats>,
+
+    /// The malformed lines each run skipped, which is the same for every run of the same input
+    pub skipped: SkippedLines,
+
+    /// The runner which ran in place of the configured one, if it couldn't run
+    pub fallback: Option<Fallback>,
+
+    /// The results for each runner, if comparing runners
+    pub compare: Vec<RunnerStats>,
+
+    /// How the settings were tuned, if they were
+    pub tuning: Option<Tunings>,
+}
+
+impl Report<'_> {
+    /// Write the report as JSON to the given path
+    pub fn write(&self, path: &Path) -> Result<(), Box<dyn std::error::Error>> {
+        let f = std::fs::File::create(path)?;
+        serde_json::to_writer_pretty(f, &schema::Report::from(self))?;
+        Ok(())
+    }
+}
+
+impl From<&Report<'_>> for schema::Report {
+    fn from(report: &Report<'_>) -> Self {
+        Self {
+            schema_version: schema::SCHEMA_VERSION,
+            config: report.config.into(),
+            topology: (&report.topology).into(),
+            runs: report.runs.iter().map(|t| t.total).collect(),
+            aggregated_runs: report.runs.iter().filter_map(|t| t.aggregated).collect(),
+            mean: report.mean,
+            std_dev: report.std_dev,
+            aggregated_mean: report.aggregated.map(|s| s.mean),
+            aggregated_std_dev: report.aggregated.map(|s| s.std_dev),
+            skipped: (&report.skipped).into(),
+            fallback: report.fallback.as_ref().map(Into::into),
+            compare: report.compare.iter().map(Into::into).collect(),
+            tuning: report.tuning.as_ref().map(Into::into),
+        }
+    }
+}
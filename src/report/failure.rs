@@ -0,0 +1,150 @@
+// 1BRC - my take on the 1 Billion Row Challenge
+// Copyright (C) 2024  Charles German <5donuts@pm.me>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! A machine-readable report of why a run failed, for unattended runs whose stderr may not be
+//! kept (or kept whole).
+//!
+//! With `--error-report <PATH>`, a failure writes an [`ErrorReport`] to the path, and a success
+//! removes any report left there by an earlier failure, so the file only exists while the last
+//! run failed.
+
+use std::error::Error;
+use std::io;
+use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use serde::Serialize;
+
+use crate::config::Config;
+use crate::error::{ChallengeError, SkippedLines};
+use crate::helpers::RunStats;
+use crate::report::schema;
+
+/// Why a run failed, written as JSON
+#[derive(Debug, Serialize)]
+pub struct ErrorReport {
+    /// The version of the [report schema](schema::SCHEMA_VERSION) the config is written with
+    pub schema_version: u32,
+
+    /// When the run failed, in seconds since the Unix epoch
+    pub timestamp: u64,
+
+    /// The kind of error (see [`ChallengeError::kind`]), or `other` for errors outside the
+    /// challenge itself, e.g. a bad config file
+    pub kind: &'static str,
+
+    /// The error message, as printed on stderr
+    pub message: String,
+
+    /// The input path as given by the user
+    pub input: String,
+
+    /// The byte offset in the input the error is at, if it's at one
+    pub offset: Option<u64>,
+
+    /// The line number in the input the error is on, if known
+    pub line: Option<u64>,
+
+    /// The effective configuration, unless the failure was in working it out
+    pub config: Option<schema::Config>,
+
+    /// What was done before the failure
+    pub stats: PartialStats,
+}
+
+/// The runs completed before a failure, and how long was spent in all
+#[derive(Debug, Default, Serialize)]
+pub struct PartialStats {
+    /// The total duration of each run which completed
+    pub runs: Vec<Duration>,
+
+    /// The aggregation part of each run which completed, with [`Config::timings`] set
+    pub aggregated_runs: Vec<Duration>,
+
+    /// How many non-finite measurements the completed runs ignored between them
+    pub ignored_non_finite: u64,
+
+    /// The malformed lines the completed runs skipped between them
+    pub skipped: SkippedLines,
+
+    /// How long was spent before failing, including the failed run
+    pub elapsed: Duration,
+}
+
+impl PartialStats {
+    /// The stats of the given completed runs, `elapsed` after starting
+    pub fn new(completed: &[RunStats], elapsed: Duration) -> Self {
+        Self {
+            runs: completed.iter().map(|s| s.timings.total).collect(),
+            aggregated_runs: completed
+                .iter()
+                .filter_map(|s| s.timings.aggregated)
+                .collect(),
+            ignored_non_finite: completed.iter().map(|s| s.ignored_non_finite).sum(),
+            skipped: completed
+                .iter()
+                .fold(SkippedLines::default(), |mut total, s| {
+                    total.merge(&s.skipped);
+                    total
+                }),
+            elapsed,
+        }
+    }
+}
+
+impl ErrorReport {
+    /// A report of `error`, which just ended a run on `input`
+    pub fn new(
+        error: &(dyn Error + 'static),
+        input: &Path,
+        config: Option<&Config>,
+        stats: PartialStats,
+    ) -> Self {
+        let challenge = error.downcast_ref::<ChallengeError>();
+        let kind = match challenge {
+            Some(e) => e.kind(),
+            None if error.is::<io::Error>() => "io",
+            None => "other",
+        };
+        Self {
+            schema_version: schema::SCHEMA_VERSION,
+            timestamp: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |d| d.as_secs()),
+            kind,
+            message: error.to_string(),
+            input: input.to_string_lossy().into_owned(),
+            offset: challenge.and_then(ChallengeError::offset),
+            line: challenge.and_then(ChallengeError::line),
+            config: config.map(Into::into),
+            stats,
+        }
+    }
+
+    /// Write the report as JSON to the given path
+    pub fn write(&self, path: &Path) -> Result<(), Box<dyn Error>> {
+        let f = std::fs::File::create(path)?;
+        serde_json::to_writer_pretty(f, self)?;
+        Ok(())
+    }
+}
+
+/// Remove the report a previous failed run left at `path`, if there is one
+pub fn remove_stale(path: &Path) -> io::Result<()> {
+    match std::fs::remove_file(path) {
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+        result => result,
+    }
+}
@@ -0,0 +1,431 @@
+// 1BRC - my take on the 1 Billion Row Challenge
+// Copyright (C) 2024  Charles German <5donuts@pm.me>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Compare reports from several machines.
+//!
+//! `--merge-reports` loads the `--report` JSON of benchmarks or comparisons run on different
+//! machines and shows how fast each runner was on each machine as throughput, so machines with
+//! different inputs & core counts can be compared.
+
+use std::collections::BTreeSet;
+use std::fmt::Display;
+use std::path::Path;
+use std::time::Duration;
+
+use crate::report::schema::{self, SCHEMA_VERSION};
+use crate::style::{Cell, Style, Styler, Table};
+
+const BYTES_PER_GB: f64 = 1_000_000_000.0;
+
+/// A report from a single machine
+#[derive(Debug)]
+pub struct Machine {
+    /// What to call the machine, from the name of its report
+    pub name: String,
+    pub report: schema::Report,
+}
+
+impl Machine {
+    /// Load the report at `path`, naming the machine after the file
+    pub fn load(path: &Path) -> Result<Self, Box<dyn std::error::Error>> {
+        let json = std::fs::read_to_string(path)
+            .map_err(|e| format!("Unable to read {}: {e}", path.display()))?;
+        let name = path
+            .file_stem()
+            .unwrap_or(path.as_os_str())
+            .to_string_lossy()
+            .into_owned();
+        Self::parse(name, &json)
+            .map_err(|e| format!("Invalid report {}: {e}", path.display()).into())
+    }
+
+    /// Parse a report from JSON
+    pub fn parse(name: String, json: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        let value: serde_json::Value = serde_json::from_str(json)?;
+        let version = value["schema_version"].as_u64().unwrap_or_default();
+        if version > SCHEMA_VERSION as u64 {
+            return Err(format!(
+                "schema version {version} is newer than this build understands ({SCHEMA_VERSION})"
+            )
+            .into());
+        }
+        Ok(Self {
+            name,
+            report: serde_json::from_value(value)?,
+        })
+    }
+
+    /// The mean time of each runner in the report
+    fn results(&self) -> Vec<(&str, Duration)> {
+        let report = &self.report;
+        if !report.compare.is_empty() {
+            return report
+                .compare
+                .iter()
+                .map(|r| (r.runner.as_str(), r.stats.mean))
+                .collect();
+        }
+
+        let runner = report.config.runner.value.as_str();
+        match report.mean {
+            Some(mean) => vec![(runner, mean)],
+            None if report.runs.is_empty() => Vec::new(),
+            None => {
+                let total: Duration = report.runs.iter().sum();
+                vec![(runner, total / report.runs.len() as u32)]
+            }
+        }
+    }
+}
+
+/// How fast a runner was on a machine
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Throughput {
+    pub gb_per_sec: f64,
+
+    /// Throughput per core, if the number of cores is known
+    pub per_core: Option<f64>,
+}
+
+/// The throughput of every runner (rows) on every machine (columns)
+#[derive(Debug)]
+pub struct Matrix {
+    pub machines: Vec<String>,
+    pub runners: Vec<String>,
+    pub cells: Vec<Vec<Option<Throughput>>>,
+
+    /// Anything which makes the machines less comparable than they look
+    pub warnings: Vec<String>,
+
+    /// The settings each machine ran with which were given explicitly or tuned (see
+    /// [`schema::Config::provenance`]), shown as footnotes to its column
+    pub settings: Vec<Vec<String>>,
+}
+
+impl Matrix {
+    /// Normalize each machine's results by the size of its input & its number of cores
+    pub fn build(machines: &[Machine]) -> Self {
+        let mut warnings = Vec::new();
+
+        let versions: BTreeSet<u32> = machines.iter().map(|m| m.report.schema_version).collect();
+        if versions.len() > 1 {
+            let versions: Vec<String> = versions.iter().map(u32::to_string).collect();
+            warnings.push(format!(
+                "The reports have different schema versions ({}); fields missing from older \
+                 versions are left at their defaults",
+                versions.join(", ")
+            ));
+        }
+        let sizes: BTreeSet<u64> = machines
+            .iter()
+            .map(|m| m.report.config.input_size.value)
+            .collect();
+        if sizes.len() > 1 {
+            let sizes: Vec<String> = sizes.iter().map(|s| format!("{s} bytes")).collect();
+            warnings.push(format!(
+                "The reports are of different inputs ({}); each is normalized by its own input \
+                 size, but throughput isn't always linear in it",
+                sizes.join(", ")
+            ));
+        }
+
+        let allocators: BTreeSet<&str> = machines
+            .iter()
+            .map(|m| m.report.config.allocator.value.as_str())
+            .collect();
+        let mixed_allocators = allocators.len() > 1;
+        if mixed_allocators {
+            let allocators: Vec<&str> = allocators.into_iter().collect();
+            warnings.push(format!(
+                "The reports were built with different allocators ({}); each machine is labelled \
+                 with its own",
+                allocators.join(", ")
+            ));
+        }
+        let names = machines
+            .iter()
+            .map(|m| {
+                if mixed_allocators {
+                    format!("{} ({})", m.name, m.report.config.allocator.value)
+                } else {
+                    m.name.clone()
+                }
+            })
+            .collect();
+
+        let mut runners: Vec<String> = Vec::new();
+        for machine in machines {
+            for (runner, _) in machine.results() {
+                if !runners.iter().any(|r| r == runner) {
+                    runners.push(runner.to_owned());
+                }
+            }
+            if machine.report.config.input_size.value == 0 {
+                warnings.push(format!(
+                    "{} doesn't record the size of its input; its throughput is unknown",
+                    machine.name
+                ));
+            }
+        }
+
+        let cells = runners
+            .iter()
+            .map(|runner| {
+                machines
+                    .iter()
+                    .map(|machine| {
+                        let (_, mean) = machine.results().into_iter().find(|(r, _)| r == runner)?;
+                        let size = machine.report.config.input_size.value;
+                        if size == 0 || mean.is_zero() {
+                            return None;
+                        }
+                        let gb_per_sec = size as f64 / BYTES_PER_GB / mean.as_secs_f64();
+                        Some(Throughput {
+                            gb_per_sec,
+                            per_core: machine
+                                .report
+                                .topology
+                                .cores
+                                .map(|cores| gb_per_sec / cores as f64),
+                        })
+                    })
+                    .collect()
+            })
+            .collect();
+
+        Self {
+            machines: names,
+            runners,
+            cells,
+            warnings,
+            settings: machines
+                .iter()
+                .map(|m| m.report.config.provenance())
+                .collect(),
+        }
+    }
+
+    /// The row of the fastest runner on the given machine, if any ran there
+    fn best(&self, machine: usize) -> Option<usize> {
+        (0..self.runners.len())
+            .filter_map(|row| Some((row, self.cells[row][machine]?.gb_per_sec)))
+            .max_by(|(_, a), (_, b)| a.total_cmp(b))
+            .map(|(row, _)| row)
+    }
+}
+
+impl Matrix {
+    /// Render the matrix as a markdown table with its columns aligned, with the fastest runner on
+    /// each machine in bold (& green, with colour) & the settings of any machine which didn't
+    /// run with the defaults in footnotes
+    pub fn render(&self, styler: &Styler) -> String {
+        let mut footnotes = Vec::new();
+        let headers: Vec<String> = self
+            .machines
+            .iter()
+            .zip(&self.settings)
+            .map(|(machine, settings)| {
+                if settings.is_empty() {
+                    return machine.clone();
+                }
+                footnotes.push(settings.join(", "));
+                format!("{machine}[^{}]", footnotes.len())
+            })
+            .collect();
+
+        let mut table = Table::new(
+            &std::iter::once("Runner")
+                .chain(headers.iter().map(String::as_str))
+                .collect::<Vec<_>>(),
+        );
+        let best: Vec<Option<usize>> = (0..self.machines.len()).map(|m| self.best(m)).collect();
+        for (row, runner) in self.runners.iter().enumerate() {
+            let mut cells = vec![Cell::from(runner.as_str())];
+            for (col, cell) in self.cells[row].iter().enumerate() {
+                let Some(throughput) = cell else {
+                    cells.push(Cell::from("-"));
+                    continue;
+                };
+                let mut text = format!("{:.2} GB/s", throughput.gb_per_sec);
+                if let Some(per_core) = throughput.per_core {
+                    text.push_str(&format!(", {per_core:.3}/core"));
+                }
+                cells.push(if best[col] == Some(row) {
+                    Cell::styled(format!("**{text}**"), Style::Good)
+                } else {
+                    Cell::from(text)
+                });
+            }
+            table.row(cells);
+        }
+
+        let mut rendered = table.render(styler);
+        if !footnotes.is_empty() {
+            rendered.push('\n');
+        }
+        for (idx, footnote) in footnotes.iter().enumerate() {
+            rendered.push_str(&format!("[^{}]: {footnote}\n", idx + 1));
+        }
+        rendered
+    }
+}
+
+impl Display for Matrix {
+    /// The matrix [rendered](Matrix::render) without colour
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.render(&Styler::PLAIN))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config;
+
+    /// The JSON of a comparison on a machine with `cores` cores of an input of `size` bytes
+    fn comparison(version: u32, cores: usize, size: u64, results: &[(&str, u64)]) -> String {
+        let mut config = schema::Config::from(&config::Config::default());
+        config.input_size.value = size;
+        let report = schema::Report {
+            schema_version: version,
+            config,
+            topology: schema::Topology {
+                cores: Some(cores),
+                storage: String::from("solid-state"),
+                filesystem: None,
+            },
+            runs: Vec::new(),
+            aggregated_runs: Vec::new(),
+            mean: None,
+            std_dev: None,
+            aggregated_mean: None,
+            aggregated_std_dev: None,
+            skipped: schema::Skipped::default(),
+            fallback: None,
+            compare: results
+                .iter()
+                .map(|&(runner, ms)| schema::RunnerResult {
+                    runner: String::from(runner),
+                    runs: vec![Duration::from_millis(ms)],
+                    stats: schema::Stats {
+                        mean: Duration::from_millis(ms),
+                        std_dev: Duration::ZERO,
+                    },
+                    output_hash: 0,
+                    determinism: None,
+                    peak_memory: None,
+                })
+                .collect(),
+            tuning: None,
+        };
+        serde_json::to_string(&report).unwrap()
+    }
+
+    #[test]
+    fn matrix() -> Result<(), Box<dyn std::error::Error>> {
+        let laptop = comparison(
+            SCHEMA_VERSION,
+            4,
+            1_000_000_000,
+            &[("baseline", 2000), ("a-hash", 1000), ("table", 500)],
+        );
+        let server = comparison(2, 16, 2_000_000_000, &[("baseline", 1000), ("a-hash", 500)]);
+        let machines = [
+            Machine::parse(String::from("laptop"), &laptop)?,
+            Machine::parse(String::from("server"), &server)?,
+        ];
+
+        let matrix = Matrix::build(&machines);
+        assert_eq!(
+            matrix.to_string(),
+            "\
+| Runner   | laptop                    | server                    |
+| -------- | ------------------------- | ------------------------- |
+| baseline | 0.50 GB/s, 0.125/core     | 2.00 GB/s, 0.125/core     |
+| a-hash   | 1.00 GB/s, 0.250/core     | **4.00 GB/s, 0.250/core** |
+| table    | **2.00 GB/s, 0.500/core** | -                         |
+"
+        );
+
+        assert_eq!(matrix.warnings.len(), 2, "{:?}", matrix.warnings);
+        assert!(matrix.warnings[0].contains(&format!("schema versions (2, {SCHEMA_VERSION})")));
+        assert!(matrix.warnings[1].contains("different inputs"));
+        Ok(())
+    }
+
+    #[test]
+    fn labels_allocators() -> Result<(), Box<dyn std::error::Error>> {
+        let json = comparison(SCHEMA_VERSION, 4, 1_000_000_000, &[("baseline", 1000)]);
+        let machine =
+            |name: &str, allocator: &str| -> Result<Machine, Box<dyn std::error::Error>> {
+                let mut machine = Machine::parse(String::from(name), &json)?;
+                machine.report.config.allocator.value = String::from(allocator);
+                Ok(machine)
+            };
+
+        let matrix = Matrix::build(&[machine("a", "system")?, machine("b", "system")?]);
+        assert_eq!(matrix.machines, vec!["a", "b"]);
+        assert!(matrix.warnings.is_empty(), "{:?}", matrix.warnings);
+
+        let matrix = Matrix::build(&[
+            machine("system", "system")?,
+            machine("mimalloc", "mimalloc")?,
+        ]);
+        assert_eq!(
+            matrix.machines,
+            vec!["system (system)", "mimalloc (mimalloc)"]
+        );
+        assert_eq!(matrix.warnings.len(), 1, "{:?}", matrix.warnings);
+        assert!(matrix.warnings[0].contains("(mimalloc, system)"));
+        Ok(())
+    }
+
+    /// Machines which didn't run with the defaults say what they ran with, & where each
+    /// setting came from
+    #[test]
+    fn settings_footnotes() -> Result<(), Box<dyn std::error::Error>> {
+        let json = comparison(SCHEMA_VERSION, 4, 1_000_000_000, &[("baseline", 1000)]);
+        let mut tuned = Machine::parse(String::from("tuned"), &json)?;
+        let config = &mut tuned.report.config;
+        config.threads = schema::Setting {
+            value: 4,
+            source: String::from("cli"),
+        };
+        config.buffer_size = schema::Setting {
+            value: 65536,
+            source: String::from("auto-tuned"),
+        };
+        let machines = [Machine::parse(String::from("stock"), &json)?, tuned];
+
+        let matrix = Matrix::build(&machines);
+        assert_eq!(
+            matrix.to_string(),
+            "\
+| Runner   | stock                     | tuned[^1]                 |
+| -------- | ------------------------- | ------------------------- |
+| baseline | **1.00 GB/s, 0.250/core** | **1.00 GB/s, 0.250/core** |
+
+[^1]: buffer-size = 65536 (auto-tuned), threads = 4 (cli)
+"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn newer_schema() {
+        let json = comparison(SCHEMA_VERSION + 1, 4, 1, &[]);
+        assert!(Machine::parse(String::from("future"), &json).is_err());
+    }
+}
@@ -0,0 +1,566 @@
+// 1BRC - my take on the 1 Billion Row Challenge
+// Copyright (C) 2024  Charles German <5donuts@pm.me>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//! Bundles of everything needed to reproduce a benchmark result on another machine.
+//!
+//! `--bench --export-repro <BUNDLE>` packs the benchmark's report (with the effective
+//! configuration, the machine, and the allocator) into a gzipped tarball, along with the commit
+//! the binary was built from, a hash of the whole input, and a hash of the result. Then,
+//! `--verify-repro <BUNDLE> <INPUT>` re-runs the benchmark with the recorded configuration &
+//! reports whether the result is the same, and how the timings compare.
+//!
+//! A bundle holds three files:
+//!
+//! - `repro.json`: a [`Repro`]
+//! - `report.json`: the benchmark's [`schema::Report`], as written by `--report`
+//! - `CHECKSUMS`: the FNV-1a hash of each of the other files, so a bundle which was edited or
+//!   damaged after it was exported is rejected rather than verified against. It's not a
+//!   signature; anyone determined to can recompute the hashes.
+
+use std::collections::BTreeMap;
+use std::fmt::Display;
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+use std::time::Duration;
+
+use clap::ValueEnum;
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use serde::{Deserialize, Serialize};
+
+use crate::aggregate::{Encoding, KeyFormat};
+use crate::config::{Config, Layer};
+use crate::fingerprint::{fnv1a, fnv1a_file};
+use crate::helpers::{fmt_duration, StationInfo};
+use crate::output::Format;
+use crate::report::session::GIT_COMMIT;
+use crate::report::{schema, Report};
+use crate::runners;
+use crate::stats::BenchStats;
+use crate::Runner;
+
+/// The version of the bundle format, written to every bundle's `repro.json`
+pub const BUNDLE_VERSION: u32 = 1;
+
+const REPRO: &str = "repro.json";
+const REPORT: &str = "report.json";
+const CHECKSUMS: &str = "CHECKSUMS";
+
+/// What a bundle records about a benchmark besides its report
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Repro {
+    pub bundle_version: u32,
+
+    /// The commit the benchmarked binary was built from
+    pub commit: String,
+
+    /// The runner which was benchmarked
+    pub runner: String,
+
+    /// The input the benchmark was run against
+    pub input: InputDigest,
+
+    /// The 64-bit FNV-1a hash of the result, as printed with `--format text`
+    pub output_hash: u64,
+}
+
+/// Identifies the contents of an input, wherever it's kept
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct InputDigest {
+    pub len: u64,
+
+    /// The 64-bit FNV-1a hash of the whole input
+    pub hash: u64,
+}
+
+impl InputDigest {
+    pub fn of(path: &Path) -> std::io::Result<Self> {
+        Ok(Self {
+            len: std::fs::metadata(path)?.len(),
+            hash: fnv1a_file(path)?,
+        })
+    }
+}
+
+/// A benchmark packaged up to be reproduced
+#[derive(Debug, Clone, PartialEq)]
+pub struct Bundle {
+    pub repro: Repro,
+    pub report: schema::Report,
+}
+
+impl Bundle {
+    /// Bundle a benchmark's report with the result it produced
+    pub fn new(
+        report: &Report<'_>,
+        result: &[StationInfo],
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let config = report.config;
+        Ok(Self {
+            repro: Repro {
+                bundle_version: BUNDLE_VERSION,
+                commit: String::from(GIT_COMMIT),
+                runner: config.runner.value.to_string(),
+                input: InputDigest::of(&config.canonical_input.value)?,
+                output_hash: output_hash(result),
+            },
+            report: report.into(),
+        })
+    }
+
+    /// Write the bundle as a gzipped tarball to the given path
+    pub fn write(&self, path: &Path) -> Result<(), Box<dyn std::error::Error>> {
+        let repro = serde_json::to_vec_pretty(&self.repro)?;
+        let report = serde_json::to_vec_pretty(&self.report)?;
+        let checksums = format!(
+            "{:016x}  {REPRO}\n{:016x}  {REPORT}\n",
+            fnv1a(&repro),
+            fnv1a(&report)
+        );
+
+        let encoder = GzEncoder::new(File::create(path)?, Compression::default());
+        let mut tar = tar::Builder::new(encoder);
+        for (name, contents) in [
+            (REPRO, repro.as_slice()),
+            (REPORT, report.as_slice()),
+            (CHECKSUMS, checksums.as_bytes()),
+        ] {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(contents.len() as u64);
+            header.set_mode(0o644);
+            tar.append_data(&mut header, name, contents)?;
+        }
+        tar.into_inner()?.finish()?;
+        Ok(())
+    }
+
+    /// Read the bundle at the given path, checking none of its files have been changed
+    pub fn load(path: &Path) -> Result<Self, Box<dyn std::error::Error>> {
+        let invalid = |e: &dyn Display| format!("Invalid bundle {}: {e}", path.display());
+
+        let mut files = BTreeMap::new();
+        let mut tar = tar::Archive::new(GzDecoder::new(File::open(path)?));
+        for entry in tar.entries().map_err(|e| invalid(&e))? {
+            let mut entry = entry.map_err(|e| invalid(&e))?;
+            let name = entry.path().map_err(|e| invalid(&e))?;
+            let name = name.to_string_lossy().into_owned();
+            if ![REPRO, REPORT, CHECKSUMS].contains(&name.as_str()) {
+                return Err(invalid(&format!("unexpected file {name}")).into());
+            }
+
+            let mut contents = Vec::new();
+            entry.read_to_end(&mut contents).map_err(|e| invalid(&e))?;
+            if files.insert(name.clone(), contents).is_some() {
+                return Err(invalid(&format!("{name} is in it more than once")).into());
+            }
+        }
+
+        let file = |name: &str| {
+            files
+                .get(name)
+                .ok_or_else(|| invalid(&format!("{name} is missing")))
+        };
+        let checksums = String::from_utf8_lossy(file(CHECKSUMS)?).into_owned();
+        for name in [REPRO, REPORT] {
+            let recorded = checksums
+                .lines()
+                .find_map(|line| match line.split_once("  ") {
+                    Some((hash, listed)) if listed == name => u64::from_str_radix(hash, 16).ok(),
+                    _ => None,
+                })
+                .ok_or_else(|| invalid(&format!("{CHECKSUMS} has no checksum for {name}")))?;
+            if fnv1a(file(name)?) != recorded {
+                return Err(invalid(&format!(
+                    "{name} doesn't match its checksum; it was changed after the bundle was exported"
+                ))
+                .into());
+            }
+        }
+
+        let repro: Repro = serde_json::from_slice(file(REPRO)?).map_err(|e| invalid(&e))?;
+        if repro.bundle_version > BUNDLE_VERSION {
+            return Err(invalid(&format!(
+                "it's version {}, but only versions up to {BUNDLE_VERSION} are supported",
+                repro.bundle_version
+            ))
+            .into());
+        }
+        let report: schema::Report =
+            serde_json::from_slice(file(REPORT)?).map_err(|e| invalid(&e))?;
+        if report.config.runner.value != repro.runner {
+            return Err(invalid(&format!(
+                "{REPRO} is for the {} runner but {REPORT} is for {}",
+                repro.runner, report.config.runner.value
+            ))
+            .into());
+        }
+
+        Ok(Self { repro, report })
+    }
+
+    /// The recorded configuration, as a layer which takes precedence over any other
+    pub fn layer(&self) -> Result<Layer, Box<dyn std::error::Error>> {
+        let config = &self.report.config;
+        Ok(Layer {
+            runner: Some(Runner::from_str(&config.runner.value, true)?),
+            buffer_size: Some(config.buffer_size.value),
+            max_line_length: Some(config.max_line_length.value),
+            station_cache: Some(config.station_cache.value),
+            strict: Some(config.strict.value),
+            num_chunks: Some(config.num_chunks.value),
+            threads: Some(config.threads.value),
+            sample_fraction: Some(config.sample_fraction.value),
+            timings: Some(config.timings.value),
+            key_format: Some(KeyFormat::from_str(&config.key_format.value, true)?),
+            track_extents: Some(config.track_extents.value),
+            case_insensitive: Some(config.case_insensitive.value),
+            fallback: Some(config.fallback.value),
+            max_skipped: Some(config.max_skipped.value),
+            delimiter: Some(config.delimiter.value.parse()?),
+            decimal_comma: Some(config.decimal_comma.value),
+            encoding: Some(Encoding::from_str(&config.encoding.value, true)?),
+            max_memory: Some(config.max_memory.value),
+            block_sizing: Some(config.block_sizing.value.parse()?),
+        })
+    }
+
+    /// Benchmark the recorded configuration against `input` with the given number of runs, &
+    /// compare the result & timings with the recorded ones.
+    ///
+    /// It's an error for the input to differ from the one the bundle was exported from, since
+    /// the results couldn't be expected to match.
+    pub fn verify(
+        &self,
+        input: &Path,
+        runs: usize,
+    ) -> Result<Verification, Box<dyn std::error::Error>> {
+        let digest = InputDigest::of(input)?;
+        if digest != self.repro.input {
+            return Err(format!(
+                "{} isn't the input the bundle was exported from ({} bytes with hash {:016x}, \
+                 rather than {} bytes with hash {:016x})",
+                input.display(),
+                digest.len,
+                digest.hash,
+                self.repro.input.len,
+                self.repro.input.hash
+            )
+            .into());
+        }
+
+        let mut warnings = Vec::new();
+        if self.repro.commit != GIT_COMMIT {
+            warnings.push(format!(
+                "the bundle is from commit {}, but this binary was built from {GIT_COMMIT}",
+                self.repro.commit
+            ));
+        }
+        let allocator = &self.report.config.allocator.value;
+        if allocator != crate::ALLOCATOR {
+            warnings.push(format!(
+                "the bundle was benchmarked with the {allocator} allocator, but this binary uses \
+                 {}; the timings aren't comparable",
+                crate::ALLOCATOR
+            ));
+        }
+
+        let config = Config::resolve(input, self.layer()?, Layer::default(), Layer::default());
+        let mut outputs = Vec::with_capacity(runs);
+        let mut totals = Vec::with_capacity(runs);
+        for _ in 0..runs {
+            let (stations, stats) = runners::run(&config)?;
+            outputs.push(output_hash(&stations));
+            totals.push(stats.timings.total);
+        }
+
+        Ok(Verification {
+            expected_output: self.repro.output_hash,
+            outputs,
+            recorded: self.report.mean.zip(self.report.std_dev),
+            timings: BenchStats::from_runs(&totals),
+            warnings,
+        })
+    }
+}
+
+/// The outcome of checking a [`Bundle`] reproduces
+#[derive(Debug, Clone, PartialEq)]
+pub struct Verification {
+    /// The hash of the result the bundle recorded
+    pub expected_output: u64,
+
+    /// The hash of each run's result
+    pub outputs: Vec<u64>,
+
+    /// The mean & standard deviation of the recorded benchmark
+    pub recorded: Option<(Duration, Duration)>,
+
+    /// The timings of the new runs
+    pub timings: BenchStats,
+
+    /// Anything which makes the comparison less meaningful, e.g. a different commit
+    pub warnings: Vec<String>,
+}
+
+impl Verification {
+    /// Whether every run produced the recorded result
+    pub fn matches(&self) -> bool {
+        self.outputs
+            .iter()
+            .all(|&hash| hash == self.expected_output)
+    }
+
+    /// How much slower (or, if negative, faster) the new runs were than the recorded ones, as a
+    /// percentage of the recorded mean
+    pub fn delta(&self) -> Option<f64> {
+        let (recorded, _) = self.recorded?;
+        let recorded = recorded.as_secs_f64();
+        (recorded > 0.0).then(|| (self.timings.mean.as_secs_f64() - recorded) / recorded * 100.0)
+    }
+}
+
+impl Display for Verification {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.matches() {
+            writeln!(f, "Result: match ({:016x})", self.expected_output)?;
+        } else {
+            let mismatched = self
+                .outputs
+                .iter()
+                .filter(|&&hash| hash != self.expected_output)
+                .count();
+            writeln!(
+                f,
+                "Result: MISMATCH in {mismatched} of {} runs (expected {:016x}, got {:016x})",
+                self.outputs.len(),
+                self.expected_output,
+                self.outputs
+                    .iter()
+                    .find(|&&hash| hash != self.expected_output)
+                    .expect("At least one run mismatched")
+            )?;
+        }
+
+        let BenchStats { mean, std_dev } = self.timings;
+        write!(
+            f,
+            "Mean: {} ± {}",
+            fmt_duration(&mean),
+            fmt_duration(&std_dev)
+        )?;
+        match (self.recorded, self.delta()) {
+            (Some((recorded, recorded_std_dev)), Some(delta)) => write!(
+                f,
+                " (recorded {} ± {}, {delta:+.2}%)",
+                fmt_duration(&recorded),
+                fmt_duration(&recorded_std_dev)
+            ),
+            _ => write!(f, " (no recorded timings)"),
+        }
+    }
+}
+
+/// Hash a result the same way whichever runner produced it
+fn output_hash(result: &[StationInfo]) -> u64 {
+    fnv1a(Format::Text.render(result).as_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::SkippedLines;
+    use crate::runners::tests::TEST_DATA;
+    use crate::topology::Topology;
+    use std::error;
+    use std::path::PathBuf;
+
+    /// Benchmark the test data & export a bundle of it, returning the directory holding both
+    /// along with the paths of the input & bundle
+    fn export() -> Result<(tempfile::TempDir, PathBuf, PathBuf), Box<dyn error::Error>> {
+        let dir = tempfile::tempdir()?;
+        let input = dir.path().join("measurements.txt");
+        std::fs::write(&input, TEST_DATA)?;
+
+        let config = Config::resolve(&input, Layer::default(), Layer::default(), Layer::default());
+        let mut runs = Vec::new();
+        let mut result = Vec::new();
+        for _ in 0..3 {
+            let (stations, stats) = runners::run(&config)?;
+            runs.push(stats.timings);
+            result = stations;
+        }
+        let totals: Vec<Duration> = runs.iter().map(|t| t.total).collect();
+        let BenchStats { mean, std_dev } = BenchStats::from_runs(&totals);
+        let report = Report {
+            config: &config,
+            topology: Topology::detect(&input),
+            runs,
+            mean: Some(mean),
+            std_dev: Some(std_dev),
+            aggregated: None,
+            skipped: SkippedLines::default(),
+            fallback: None,
+            compare: Vec::new(),
+            tuning: None,
+        };
+
+        let bundle = dir.path().join("bundle.tar.gz");
+        Bundle::new(&report, &result)?.write(&bundle)?;
+        Ok((dir, input, bundle))
+    }
+
+    /// Rewrite one of the files in a bundle, leaving the others (including the checksums) as-is
+    fn rewrite(
+        path: &Path,
+        name: &str,
+        edit: impl Fn(&mut Vec<u8>),
+    ) -> Result<(), Box<dyn error::Error>> {
+        let mut files = Vec::new();
+        let mut tar = tar::Archive::new(GzDecoder::new(File::open(path)?));
+        for entry in tar.entries()? {
+            let mut entry = entry?;
+            let file_name = entry.path()?.to_string_lossy().into_owned();
+            let mut contents = Vec::new();
+            entry.read_to_end(&mut contents)?;
+            if file_name == name {
+                edit(&mut contents);
+            }
+            files.push((file_name, contents));
+        }
+
+        let mut tar =
+            tar::Builder::new(GzEncoder::new(File::create(path)?, Compression::default()));
+        for (name, contents) in files {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(contents.len() as u64);
+            header.set_mode(0o644);
+            tar.append_data(&mut header, name, contents.as_slice())?;
+        }
+        tar.into_inner()?.finish()?;
+        Ok(())
+    }
+
+    #[test]
+    fn round_trip() -> Result<(), Box<dyn error::Error>> {
+        let (_dir, input, path) = export()?;
+
+        let bundle = Bundle::load(&path)?;
+        assert_eq!(bundle.repro.bundle_version, BUNDLE_VERSION);
+        assert_eq!(bundle.repro.commit, GIT_COMMIT);
+        assert_eq!(bundle.repro.runner, Runner::default().to_string());
+        assert_eq!(bundle.repro.input.len, TEST_DATA.len() as u64);
+        assert_eq!(bundle.report.runs.len(), 3);
+
+        let verification = bundle.verify(&input, 3)?;
+        assert!(verification.matches(), "{verification}");
+        assert!(
+            verification.warnings.is_empty(),
+            "{:?}",
+            verification.warnings
+        );
+        assert!(verification.delta().is_some());
+        assert!(verification.to_string().starts_with("Result: match"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn recorded_config() -> Result<(), Box<dyn error::Error>> {
+        let (_dir, input, path) = export()?;
+        let bundle = Bundle::load(&path)?;
+
+        // Every setting comes from the bundle now, so only the values are the same
+        let values =
+            |config: &schema::Config| -> Result<Vec<serde_json::Value>, serde_json::Error> {
+                let json = serde_json::to_value(config)?;
+                Ok(json
+                    .as_object()
+                    .expect("The config is an object")
+                    .values()
+                    .map(|setting| setting["value"].clone())
+                    .collect())
+            };
+        let config = Config::resolve(&input, bundle.layer()?, Layer::default(), Layer::default());
+        assert_eq!(
+            values(&schema::Config::from(&config))?,
+            values(&bundle.report.config)?
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn tampered_file() -> Result<(), Box<dyn error::Error>> {
+        let (_dir, _, path) = export()?;
+        rewrite(&path, REPORT, |report| {
+            let mut json: serde_json::Value = serde_json::from_slice(report).unwrap();
+            json["runs"][0]["nanos"] = 1.into();
+            *report = serde_json::to_vec_pretty(&json).unwrap();
+        })?;
+
+        let err = Bundle::load(&path)
+            .expect_err("A tampered report was loaded")
+            .to_string();
+        assert!(
+            err.contains("report.json doesn't match its checksum"),
+            "{err}"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn tampered_result() -> Result<(), Box<dyn error::Error>> {
+        let (_dir, input, path) = export()?;
+
+        // Even with the checksum updated to match, a different result can't be reproduced
+        let bundle = Bundle::load(&path)?;
+        let mut repro = bundle.repro.clone();
+        repro.output_hash ^= 1;
+        let changed = serde_json::to_vec_pretty(&repro)?;
+        let checksum = fnv1a(&changed);
+        rewrite(&path, REPRO, |contents| contents.clone_from(&changed))?;
+        rewrite(&path, CHECKSUMS, |checksums| {
+            let text = String::from_utf8_lossy(checksums).into_owned();
+            let (_, rest) = text.split_once('\n').unwrap();
+            *checksums = format!("{checksum:016x}  {REPRO}\n{rest}").into_bytes();
+        })?;
+
+        let verification = Bundle::load(&path)?.verify(&input, 1)?;
+        assert!(!verification.matches());
+        assert!(verification.to_string().starts_with("Result: MISMATCH"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn other_input() -> Result<(), Box<dyn error::Error>> {
+        let (dir, _, path) = export()?;
+        let other = dir.path().join("other.txt");
+        std::fs::write(&other, format!("{TEST_DATA}Hamburg;12.0\n"))?;
+
+        let err = Bundle::load(&path)?
+            .verify(&other, 1)
+            .expect_err("Verified a different input");
+        assert!(
+            err.to_string()
+                .contains("isn't the input the bundle was exported from"),
+            "{err}"
+        );
+
+        Ok(())
+    }
+}
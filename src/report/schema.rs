@@ -0,0 +1,554 @@
+// 1BRC - my take on the 1 Billion Row Challenge
+// Copyright (C) 2024  Charles German <5donuts@pm.me>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! The versioned JSON schema of reports.
+//!
+//! These structs are what's actually written by `--report`, kept separate from the internal
+//! types so the internals can change without breaking anything reading the reports. Any change
+//! to their shape must bump [`SCHEMA_VERSION`] and check in a sample of the new version under
+//! `tests/data/reports/`; the tests below enforce this.
+
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+use crate::aggregate::{Delimiter, Encoding, KeyFormat};
+use crate::block_sizing::BlockSizing;
+use crate::compare::RunnerStats;
+use crate::config;
+use crate::error::SkippedLines;
+use crate::helpers;
+use crate::stats::BenchStats;
+use crate::topology;
+use crate::tune;
+
+/// The version of the report schema, written to every report as `schema_version`
+pub const SCHEMA_VERSION: u32 = 16;
+
+/// A report of the timings for a single run, a benchmark, or a comparison
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Report {
+    pub schema_version: u32,
+    pub config: Config,
+    pub topology: Topology,
+
+    /// The duration of every run, in the order they were executed
+    pub runs: Vec<Duration>,
+
+    /// How long each run spent aggregating measurements, if timed (since v2)
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub aggregated_runs: Vec<Duration>,
+
+    /// The mean of the runs kept by the benchmark, if benchmarking
+    pub mean: Option<Duration>,
+
+    /// The standard deviation of the runs kept by the benchmark, if benchmarking
+    pub std_dev: Option<Duration>,
+
+    /// The mean aggregation time of the runs kept by the benchmark, if timed (since v2)
+    #[serde(default)]
+    pub aggregated_mean: Option<Duration>,
+
+    /// The standard deviation of the aggregation times, if timed (since v2)
+    #[serde(default)]
+    pub aggregated_std_dev: Option<Duration>,
+
+    /// The malformed lines skipped by each run (since v5)
+    #[serde(default)]
+    pub skipped: Skipped,
+
+    /// The runner which ran in place of the configured one, if it couldn't run (since v11)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fallback: Option<Fallback>,
+
+    /// The results for each runner, if comparing runners
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub compare: Vec<RunnerResult>,
+
+    /// How the settings whose source is `auto-tuned` were picked, if any were (since v14)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tuning: Option<Tuning>,
+}
+
+/// A configuration value & where it came from (e.g. `cli`, `env`, or `default`)
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Setting<T> {
+    pub value: T,
+    pub source: String,
+}
+
+impl<T> Setting<T> {
+    fn from_config<U>(setting: &config::Setting<U>, value: impl FnOnce(&U) -> T) -> Self {
+        Self {
+            value: value(&setting.value),
+            source: setting.source.to_string(),
+        }
+    }
+}
+
+/// The effective configuration of the run(s)
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Config {
+    pub runner: Setting<String>,
+    pub hasher: Setting<String>,
+    pub buffer_size: Setting<usize>,
+    pub max_line_length: Setting<usize>,
+    pub station_cache: Setting<bool>,
+    pub strict: Setting<bool>,
+    pub num_chunks: Setting<usize>,
+    pub threads: Setting<usize>,
+    pub sample_fraction: Setting<f64>,
+
+    /// Since v2
+    #[serde(default)]
+    pub timings: Setting<bool>,
+
+    /// Since v3; earlier reports were always grouped by station
+    #[serde(default = "station_key_format")]
+    pub key_format: Setting<String>,
+
+    /// Since v4
+    #[serde(default)]
+    pub track_extents: Setting<bool>,
+
+    /// Since v10
+    #[serde(default)]
+    pub case_insensitive: Setting<bool>,
+
+    /// Since v11
+    #[serde(default)]
+    pub fallback: Setting<bool>,
+
+    /// Since v5; earlier versions never skipped malformed lines
+    #[serde(default)]
+    pub max_skipped: Setting<u64>,
+
+    /// Since v6; earlier inputs were always delimited by `;`
+    #[serde(default = "default_delimiter")]
+    pub delimiter: Setting<String>,
+
+    /// Since v6
+    #[serde(default)]
+    pub decimal_comma: Setting<bool>,
+
+    /// Since v13; earlier inputs were always UTF-8
+    #[serde(default = "default_encoding")]
+    pub encoding: Setting<String>,
+
+    /// Since v7; 0 (no limit) before then
+    #[serde(default)]
+    pub max_memory: Setting<u64>,
+
+    /// Since v15; the pipelined runner's blocks were always a fixed size before then
+    #[serde(default = "default_block_sizing")]
+    pub block_sizing: Setting<String>,
+
+    /// Since v8; earlier builds always used the system allocator
+    #[serde(default = "system_allocator")]
+    pub allocator: Setting<String>,
+    pub input: Setting<String>,
+    pub canonical_input: Setting<String>,
+    pub input_size: Setting<u64>,
+}
+
+fn station_key_format() -> Setting<String> {
+    Setting {
+        value: KeyFormat::Station.to_string(),
+        source: config::Source::Default.to_string(),
+    }
+}
+
+fn system_allocator() -> Setting<String> {
+    Setting {
+        value: String::from("system"),
+        source: config::Source::Auto.to_string(),
+    }
+}
+
+fn default_delimiter() -> Setting<String> {
+    Setting {
+        value: Delimiter::default().to_string(),
+        source: config::Source::Default.to_string(),
+    }
+}
+
+fn default_encoding() -> Setting<String> {
+    Setting {
+        value: Encoding::default().to_string(),
+        source: config::Source::Default.to_string(),
+    }
+}
+
+fn default_block_sizing() -> Setting<String> {
+    Setting {
+        value: BlockSizing::default().to_string(),
+        source: config::Source::Default.to_string(),
+    }
+}
+
+impl From<&config::Config> for Config {
+    fn from(config: &config::Config) -> Self {
+        let path = |p: &std::path::PathBuf| p.to_string_lossy().into_owned();
+        Self {
+            runner: Setting::from_config(&config.runner, |r| r.to_string()),
+            hasher: Setting::from_config(&config.hasher, |h| h.to_string()),
+            buffer_size: Setting::from_config(&config.buffer_size, |&v| v),
+            max_line_length: Setting::from_config(&config.max_line_length, |&v| v),
+            station_cache: Setting::from_config(&config.station_cache, |&v| v),
+            strict: Setting::from_config(&config.strict, |&v| v),
+            num_chunks: Setting::from_config(&config.num_chunks, |&v| v),
+            threads: Setting::from_config(&config.threads, |&v| v),
+            sample_fraction: Setting::from_config(&config.sample_fraction, |&v| v),
+            timings: Setting::from_config(&config.timings, |&v| v),
+            key_format: Setting::from_config(&config.key_format, |k| k.to_string()),
+            track_extents: Setting::from_config(&config.track_extents, |&v| v),
+            case_insensitive: Setting::from_config(&config.case_insensitive, |&v| v),
+            fallback: Setting::from_config(&config.fallback, |&v| v),
+            max_skipped: Setting::from_config(&config.max_skipped, |&v| v),
+            delimiter: Setting::from_config(&config.delimiter, |d| d.to_string()),
+            decimal_comma: Setting::from_config(&config.decimal_comma, |&v| v),
+            encoding: Setting::from_config(&config.encoding, |e| e.to_string()),
+            max_memory: Setting::from_config(&config.max_memory, |&v| v),
+            block_sizing: Setting::from_config(&config.block_sizing, |b| b.to_string()),
+            allocator: Setting::from_config(&config.allocator, |&a| a.to_owned()),
+            input: Setting::from_config(&config.input, path),
+            canonical_input: Setting::from_config(&config.canonical_input, path),
+            input_size: Setting::from_config(&config.input_size, |&v| v),
+        }
+    }
+}
+
+impl Config {
+    /// Each setting which was given explicitly or tuned, as `name = value (source)`, e.g.
+    /// `threads = 4 (cli)`. Defaults & values detected from the machine are left out, as are
+    /// the runner & the input, which are shown elsewhere.
+    pub fn provenance(&self) -> Vec<String> {
+        fn entry<T: std::fmt::Display>(name: &str, setting: &Setting<T>) -> Option<String> {
+            let implicit = [config::Source::Default, config::Source::Auto]
+                .iter()
+                .any(|source| setting.source == source.to_string());
+            (!implicit).then(|| format!("{name} = {} ({})", setting.value, setting.source))
+        }
+        [
+            entry("buffer-size", &self.buffer_size),
+            entry("max-line-length", &self.max_line_length),
+            entry("station-cache", &self.station_cache),
+            entry("strict", &self.strict),
+            entry("num-chunks", &self.num_chunks),
+            entry("threads", &self.threads),
+            entry("sample-fraction", &self.sample_fraction),
+            entry("key-format", &self.key_format),
+            entry("track-extents", &self.track_extents),
+            entry("case-insensitive", &self.case_insensitive),
+            entry("max-skipped", &self.max_skipped),
+            entry("delimiter", &self.delimiter),
+            entry("decimal-comma", &self.decimal_comma),
+            entry("encoding", &self.encoding),
+            entry("max-memory", &self.max_memory),
+            entry("block-sizing", &self.block_sizing),
+        ]
+        .into_iter()
+        .flatten()
+        .collect()
+    }
+}
+
+/// How the tuned settings were picked
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Tuning {
+    /// `once`, or `each-run` if tuned again before every run of a benchmark
+    pub mode: String,
+
+    /// The buffer size each round of tuning picked, in order
+    pub buffer_sizes: Vec<usize>,
+
+    /// How long each round of tuning took, in order
+    pub durations: Vec<Duration>,
+}
+
+impl From<&tune::Tunings> for Tuning {
+    fn from(tunings: &tune::Tunings) -> Self {
+        Self {
+            mode: tunings.mode.to_string(),
+            buffer_sizes: tunings.rounds.iter().map(|t| t.buffer_size).collect(),
+            durations: tunings.rounds.iter().map(|t| t.elapsed).collect(),
+        }
+    }
+}
+
+/// A runner which couldn't run, & the runner which ran in its place
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Fallback {
+    pub from: String,
+    pub to: String,
+}
+
+impl From<&helpers::Fallback> for Fallback {
+    fn from(fallback: &helpers::Fallback) -> Self {
+        Self {
+            from: fallback.from.to_string(),
+            to: fallback.to.to_string(),
+        }
+    }
+}
+
+/// How many malformed lines were skipped, in all & by what was wrong with them
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Skipped {
+    pub total: u64,
+    pub no_semicolon: u64,
+    pub bad_temperature: u64,
+    pub invalid_utf8: u64,
+    pub too_long: u64,
+    pub out_of_range: u64,
+    pub other: u64,
+}
+
+impl From<&SkippedLines> for Skipped {
+    fn from(skipped: &SkippedLines) -> Self {
+        Self {
+            total: skipped.total(),
+            no_semicolon: skipped.no_semicolon,
+            bad_temperature: skipped.bad_temperature,
+            invalid_utf8: skipped.invalid_utf8,
+            too_long: skipped.too_long,
+            out_of_range: skipped.out_of_range,
+            other: skipped.other,
+        }
+    }
+}
+
+/// The parallelism detected on the machine the timings were collected on
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Topology {
+    pub cores: Option<usize>,
+
+    /// `rotational`, `solid-state`, or `unknown`
+    pub storage: String,
+
+    /// The filesystem the input was on (since v16)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub filesystem: Option<Filesystem>,
+}
+
+impl From<&topology::Topology> for Topology {
+    fn from(topology: &topology::Topology) -> Self {
+        Self {
+            cores: topology.cores,
+            storage: serde_json::to_value(topology.storage)
+                .ok()
+                .and_then(|v| v.as_str().map(str::to_owned))
+                .unwrap_or_else(|| String::from("unknown")),
+            filesystem: Some((&topology.filesystem).into()),
+        }
+    }
+}
+
+/// The filesystem the input was on, as far as it could be told
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Filesystem {
+    /// e.g. `ext4`, `tmpfs`, `nfs` or `fuse.s3fs`, or `unknown`
+    pub kind: String,
+
+    /// What was mounted, e.g. `/dev/nvme0n1p2` or `server:/export`, if known
+    pub device: Option<String>,
+
+    /// Whether the filesystem appeared to be on another machine, if that could be told
+    pub network: Option<bool>,
+}
+
+impl From<&topology::Filesystem> for Filesystem {
+    fn from(filesystem: &topology::Filesystem) -> Self {
+        Self {
+            kind: filesystem.kind.clone(),
+            device: filesystem.device.clone(),
+            network: filesystem.network,
+        }
+    }
+}
+
+/// The benchmark results for a single runner in a comparison
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RunnerResult {
+    pub runner: String,
+    pub runs: Vec<Duration>,
+    pub stats: Stats,
+
+    /// Hash of the runner's output, used to check all runners agree
+    pub output_hash: u64,
+
+    /// How reproducible the runner's output is: `bit-exact`, `rounded-exact` or `best-effort`
+    /// (since v9)
+    #[serde(default)]
+    pub determinism: Option<String>,
+
+    /// The peak resident memory of the process while the runner ran, in bytes, if it could be
+    /// measured (since v12)
+    #[serde(default)]
+    pub peak_memory: Option<u64>,
+}
+
+impl From<&RunnerStats> for RunnerResult {
+    fn from(stats: &RunnerStats) -> Self {
+        Self {
+            runner: stats.runner.clone(),
+            runs: stats.runs.clone(),
+            stats: stats.stats.into(),
+            output_hash: stats.output_hash,
+            determinism: Some(stats.determinism.to_string()),
+            peak_memory: stats.peak_memory,
+        }
+    }
+}
+
+/// Summary statistics for a set of benchmark runs
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Stats {
+    pub mean: Duration,
+    pub std_dev: Duration,
+}
+
+impl From<BenchStats> for Stats {
+    fn from(stats: BenchStats) -> Self {
+        Self {
+            mean: stats.mean,
+            std_dev: stats.std_dev,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fingerprint::fnv1a;
+    use serde_json::Value;
+    use std::path::Path;
+
+    const SAMPLES: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/data/reports");
+
+    /// A report with every field populated, so its JSON shows the full shape of the schema
+    fn full_report() -> Report {
+        let config = config::Config::default();
+        let topology = topology::Topology {
+            cores: Some(8),
+            storage: topology::Storage::SolidState,
+            filesystem: topology::Filesystem {
+                kind: String::from("ext4"),
+                device: Some(String::from("/dev/nvme0n1p2")),
+                network: Some(false),
+            },
+        };
+        let runs = vec![Duration::from_millis(1500), Duration::from_millis(1600)];
+        let stats = BenchStats::from_runs(&runs);
+        let compare = RunnerStats {
+            runner: String::from("baseline"),
+            runs: runs.clone(),
+            stats,
+            output_hash: 42,
+            determinism: crate::Determinism::BitExact,
+            peak_memory: Some(1 << 30),
+            resumed: false,
+        };
+
+        Report {
+            schema_version: SCHEMA_VERSION,
+            config: (&config).into(),
+            topology: (&topology).into(),
+            aggregated_runs: runs.clone(),
+            runs,
+            mean: Some(stats.mean),
+            std_dev: Some(stats.std_dev),
+            aggregated_mean: Some(stats.mean),
+            aggregated_std_dev: Some(stats.std_dev),
+            skipped: Skipped::default(),
+            fallback: Some(Fallback {
+                from: String::from("mmap"),
+                to: String::from("ahash"),
+            }),
+            compare: vec![(&compare).into()],
+            tuning: Some(Tuning {
+                mode: tune::Mode::EachRun.to_string(),
+                buffer_sizes: vec![64 << 10, 16 << 10],
+                durations: vec![Duration::from_millis(150), Duration::from_millis(120)],
+            }),
+        }
+    }
+
+    /// Describe the shape of some JSON (keys & types, but not values)
+    fn shape(value: &Value) -> String {
+        match value {
+            Value::Null => String::from("null"),
+            Value::Bool(_) => String::from("bool"),
+            Value::Number(_) => String::from("number"),
+            Value::String(_) => String::from("string"),
+            Value::Array(items) => format!("[{}]", items.first().map(shape).unwrap_or_default()),
+            Value::Object(fields) => {
+                let mut fields: Vec<String> = fields
+                    .iter()
+                    .map(|(key, value)| format!("{key}:{}", shape(value)))
+                    .collect();
+                fields.sort();
+                format!("{{{}}}", fields.join(","))
+            }
+        }
+    }
+
+    #[test]
+    fn round_trip() -> Result<(), Box<dyn std::error::Error>> {
+        let report = full_report();
+        let json = serde_json::to_string(&report)?;
+        assert_eq!(serde_json::from_str::<Report>(&json)?, report);
+        Ok(())
+    }
+
+    #[test]
+    fn reads_every_previous_version() -> Result<(), Box<dyn std::error::Error>> {
+        for version in 1..=SCHEMA_VERSION {
+            let path = Path::new(SAMPLES).join(format!("v{version}.json"));
+            let sample = std::fs::read_to_string(&path)
+                .map_err(|e| format!("Missing sample {}: {e}", path.display()))?;
+            let report: Report = serde_json::from_str(&sample)
+                .map_err(|e| format!("Can't read {}: {e}", path.display()))?;
+            assert_eq!(report.schema_version, version);
+        }
+        Ok(())
+    }
+
+    /// Fails if the shape of the schema changes without bumping [`SCHEMA_VERSION`].
+    ///
+    /// When bumping the version, add a line for it to `fingerprints.txt` with the fingerprint
+    /// this test reports, along with a `v{N}.json` sample.
+    #[test]
+    fn version_bump_policy() -> Result<(), Box<dyn std::error::Error>> {
+        let value = serde_json::to_value(full_report())?;
+        let fingerprint = format!("{:016x}", fnv1a(shape(&value).as_bytes()));
+
+        let golden = std::fs::read_to_string(Path::new(SAMPLES).join("fingerprints.txt"))?;
+        let expected = golden
+            .lines()
+            .filter(|line| !line.starts_with('#'))
+            .filter_map(|line| line.split_once(' '))
+            .find(|(version, _)| version.parse() == Ok(SCHEMA_VERSION))
+            .map(|(_, fingerprint)| fingerprint.trim());
+
+        assert_eq!(
+            expected,
+            Some(fingerprint.as_str()),
+            "The report schema changed shape; bump SCHEMA_VERSION and record its fingerprint \
+             & a sample in tests/data/reports"
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,399 @@
+// 1BRC - my take on the 1 Billion Row Challenge
+// Copyright (C) 2024  Charles German <5donuts@pm.me>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! A log of benchmark sessions, for checking results are stable across days & reboots rather than
+//! just within one process.
+//!
+//! Each `--bench --session <NAME>` appends a line of JSON to the session's log next to the input,
+//! and `--session-report --session <NAME>` analyzes every session logged so far.
+
+use std::fmt::Display;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+
+use crate::config;
+use crate::helpers::fmt_duration;
+use crate::report::schema;
+use crate::stats::{linear_trend, VarianceComponents};
+
+/// The commit the binary was built from, embedded by the build script
+pub const GIT_COMMIT: &str = env!("ONEBRC_GIT_COMMIT");
+
+/// How many within-session standard deviations the session means may drift by, from the first
+/// session to the last, before it's flagged
+const DRIFT_TOLERANCE: f64 = 2.0;
+
+const SECONDS_PER_DAY: f64 = 24.0 * 60.0 * 60.0;
+
+/// A single benchmark session, as logged
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Session {
+    /// The version of the [report schema](schema::SCHEMA_VERSION) the config was logged with
+    pub schema_version: u32,
+
+    /// When the session finished, in seconds since the Unix epoch
+    pub timestamp: u64,
+
+    /// The commit the benchmarked binary was built from
+    pub commit: String,
+
+    pub config: schema::Config,
+
+    /// The duration of every run in the session
+    pub samples: Vec<Duration>,
+}
+
+impl Session {
+    /// A session of the given runs which just finished
+    pub fn new(config: &config::Config, samples: Vec<Duration>) -> Self {
+        Self {
+            schema_version: schema::SCHEMA_VERSION,
+            timestamp: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |d| d.as_secs()),
+            commit: String::from(GIT_COMMIT),
+            config: config.into(),
+            samples,
+        }
+    }
+}
+
+/// The log of every session with a given name
+pub struct SessionLog {
+    path: PathBuf,
+}
+
+impl SessionLog {
+    /// The log of the named sessions against the given input, e.g.
+    /// `measurements.txt.session-laptop.jsonl`
+    pub fn for_input(input: &Path, name: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        let valid = |c: char| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.');
+        if name.is_empty() || name.starts_with('.') || !name.chars().all(valid) {
+            return Err(format!(
+                "Invalid session name {name:?}; use letters, digits, '-', '_', and '.'"
+            )
+            .into());
+        }
+
+        let mut path = input.as_os_str().to_os_string();
+        path.push(format!(".session-{name}.jsonl"));
+        Ok(Self {
+            path: PathBuf::from(path),
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Add a session to the end of the log
+    pub fn append(&self, session: &Session) -> Result<(), Box<dyn std::error::Error>> {
+        let mut line = serde_json::to_vec(session)?;
+        line.push(b'\n');
+
+        // A single write so an interrupted append can only ever leave a partial last line
+        let mut f = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        f.write_all(&line)?;
+        Ok(())
+    }
+
+    /// Read every session in the log, oldest first
+    pub fn load(&self) -> Result<Vec<Session>, Box<dyn std::error::Error>> {
+        let contents = std::fs::read_to_string(&self.path)
+            .map_err(|e| format!("Unable to read {}: {e}", self.path.display()))?;
+        let mut sessions: Vec<Session> = contents
+            .lines()
+            .enumerate()
+            .filter(|(_, line)| !line.trim().is_empty())
+            .map(|(i, line)| {
+                serde_json::from_str(line).map_err(|e| {
+                    format!(
+                        "Invalid session on line {} of {}: {e}",
+                        i + 1,
+                        self.path.display()
+                    )
+                })
+            })
+            .collect::<Result<_, _>>()?;
+        sessions.sort_by_key(|s| s.timestamp);
+        Ok(sessions)
+    }
+}
+
+/// Summary of a single session in a [`SessionReport`]
+#[derive(Debug, Clone, Serialize)]
+pub struct SessionSummary {
+    pub timestamp: u64,
+    pub commit: String,
+    pub runner: String,
+    pub samples: usize,
+
+    /// The mean of the session's samples, in milliseconds
+    pub mean_ms: f64,
+
+    /// The standard deviation of the session's samples, in milliseconds
+    pub std_dev_ms: f64,
+}
+
+/// The stability of benchmark results across sessions
+#[derive(Debug, Clone, Serialize)]
+pub struct SessionReport {
+    pub sessions: Vec<SessionSummary>,
+
+    /// The mean of every sample in every session, in milliseconds
+    pub mean_ms: f64,
+
+    /// The pooled variance of samples within their sessions, in milliseconds²
+    pub within_variance: f64,
+
+    /// The variance of the session means, in milliseconds²
+    pub between_variance: f64,
+
+    /// How fast the session means are changing, in milliseconds per day
+    pub trend_ms_per_day: Option<f64>,
+
+    /// Whether the trend moves the means by more than the noise within sessions would explain
+    pub drifting: bool,
+}
+
+impl SessionReport {
+    /// Analyze the given sessions
+    pub fn analyze(sessions: &[Session]) -> Result<Self, Box<dyn std::error::Error>> {
+        let sessions: Vec<&Session> = sessions.iter().filter(|s| !s.samples.is_empty()).collect();
+        if sessions.is_empty() {
+            return Err("No sessions with any samples to report on".into());
+        }
+
+        let groups: Vec<Vec<f64>> = sessions
+            .iter()
+            .map(|s| s.samples.iter().map(|d| d.as_secs_f64() * 1000.0).collect())
+            .collect();
+        let components = VarianceComponents::of(&groups);
+
+        let summaries: Vec<SessionSummary> = sessions
+            .iter()
+            .zip(&groups)
+            .map(|(session, samples)| {
+                let own = VarianceComponents::of(std::slice::from_ref(samples));
+                SessionSummary {
+                    timestamp: session.timestamp,
+                    commit: session.commit.clone(),
+                    runner: session.config.runner.value.clone(),
+                    samples: samples.len(),
+                    mean_ms: own.mean,
+                    std_dev_ms: own.within.sqrt(),
+                }
+            })
+            .collect();
+
+        let points: Vec<(f64, f64)> = summaries
+            .iter()
+            .map(|s| (s.timestamp as f64 / SECONDS_PER_DAY, s.mean_ms))
+            .collect();
+        let trend = linear_trend(&points);
+        let span_days = match (points.first(), points.last()) {
+            (Some(first), Some(last)) => last.0 - first.0,
+            _ => 0.0,
+        };
+        let drifting = match trend {
+            Some(slope) if components.within.is_finite() => {
+                (slope * span_days).abs() > DRIFT_TOLERANCE * components.within.sqrt()
+            }
+            _ => false,
+        };
+
+        Ok(Self {
+            sessions: summaries,
+            mean_ms: components.mean,
+            within_variance: components.within,
+            between_variance: components.between,
+            trend_ms_per_day: trend,
+            drifting,
+        })
+    }
+}
+
+impl Display for SessionReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let ms = |ms: f64| fmt_duration(&Duration::from_secs_f64(ms.max(0.0) / 1000.0));
+
+        writeln!(
+            f,
+            "| Session | Finished (UTC) | Commit | Runner | Runs | Mean |\n| ------- | -------------- | ------ | ------ | ---- | ---- |"
+        )?;
+        for (i, session) in self.sessions.iter().enumerate() {
+            writeln!(
+                f,
+                "| {} | {} | {} | {} | {} | {} ± {} |",
+                i + 1,
+                fmt_timestamp(session.timestamp),
+                session.commit,
+                session.runner,
+                session.samples,
+                ms(session.mean_ms),
+                ms(session.std_dev_ms)
+            )?;
+        }
+
+        writeln!(f, "\nOverall mean:     {}", ms(self.mean_ms))?;
+        let std_dev = |variance: f64| {
+            if variance.is_finite() {
+                ms(variance.sqrt())
+            } else {
+                String::from("N/A")
+            }
+        };
+        writeln!(f, "Within sessions:  ± {}", std_dev(self.within_variance))?;
+        writeln!(f, "Between sessions: ± {}", std_dev(self.between_variance))?;
+        if self.within_variance > 0.0 && self.between_variance.is_finite() {
+            writeln!(
+                f,
+                "Between/within variance: {:.2} (around 1 means sessions agree)",
+                self.between_variance / self.within_variance
+            )?;
+        }
+
+        match self.trend_ms_per_day {
+            Some(slope) => write!(
+                f,
+                "Trend: {slope:+.1}ms per day{}",
+                if self.drifting {
+                    " (DRIFTING: more than the noise within sessions explains)"
+                } else {
+                    ""
+                }
+            ),
+            None => write!(
+                f,
+                "Trend: N/A (sessions from more than one time are needed)"
+            ),
+        }
+    }
+}
+
+/// Format seconds since the Unix epoch as a UTC date & time, e.g. `2024-03-01 14:05:09`
+fn fmt_timestamp(timestamp: u64) -> String {
+    let days = (timestamp / 86_400) as i64;
+    let secs = timestamp % 86_400;
+
+    // Days to a civil date; see http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + (month <= 2) as i64;
+
+    format!(
+        "{year:04}-{month:02}-{day:02} {:02}:{:02}:{:02}",
+        secs / 3600,
+        secs / 60 % 60,
+        secs % 60
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DAY: u64 = 86_400;
+
+    /// A session on the given day with the given run times, in milliseconds
+    fn session(day: u64, samples: &[u64]) -> Session {
+        Session {
+            timestamp: 1_700_000_000 + day * DAY,
+            samples: samples.iter().copied().map(Duration::from_millis).collect(),
+            ..Session::new(&config::Config::default(), Vec::new())
+        }
+    }
+
+    #[test]
+    fn stable_sessions() -> Result<(), Box<dyn std::error::Error>> {
+        let sessions = [
+            session(0, &[1000, 1010, 990]),
+            session(1, &[1005, 995, 1000]),
+            session(2, &[990, 1010, 1000]),
+        ];
+        let report = SessionReport::analyze(&sessions)?;
+
+        assert_eq!(report.sessions.len(), 3);
+        assert_eq!(report.mean_ms, 1000.0);
+        assert!(
+            report.between_variance < report.within_variance,
+            "{report:?}"
+        );
+        assert!(!report.drifting, "{report:?}");
+        Ok(())
+    }
+
+    #[test]
+    fn drifting_sessions() -> Result<(), Box<dyn std::error::Error>> {
+        // Getting 50ms slower every day, with only a few ms of noise in each session
+        let sessions: Vec<Session> = (0..5)
+            .map(|day| {
+                let base = 1000 + day * 50;
+                session(day, &[base - 2, base, base + 2])
+            })
+            .collect();
+        let report = SessionReport::analyze(&sessions)?;
+
+        let trend = report.trend_ms_per_day.unwrap();
+        assert!((trend - 50.0).abs() < 1e-6, "{trend}");
+        assert!(
+            report.between_variance > report.within_variance,
+            "{report:?}"
+        );
+        assert!(report.drifting, "{report:?}");
+        assert!(report.to_string().contains("DRIFTING"), "{report}");
+        Ok(())
+    }
+
+    #[test]
+    fn log_round_trip() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = tempfile::tempdir()?;
+        let log = SessionLog::for_input(&dir.path().join("measurements.txt"), "laptop")?;
+        assert!(log
+            .path()
+            .ends_with("measurements.txt.session-laptop.jsonl"));
+
+        // Appended out of order, but loaded oldest first
+        let sessions = [session(1, &[1000]), session(0, &[1100, 1200])];
+        for session in &sessions {
+            log.append(session)?;
+        }
+        assert_eq!(log.load()?, vec![sessions[1].clone(), sessions[0].clone()]);
+
+        assert!(SessionLog::for_input(dir.path(), "../escape").is_err());
+        assert!(SessionLog::for_input(dir.path(), "").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn timestamps() {
+        assert_eq!(fmt_timestamp(0), "1970-01-01 00:00:00");
+        assert_eq!(fmt_timestamp(951_827_696), "2000-02-29 12:34:56");
+        assert_eq!(fmt_timestamp(1_709_301_909), "2024-03-01 14:05:09");
+    }
+}
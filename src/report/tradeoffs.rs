@@ -0,0 +1,168 @@
+// 1BRC - my take on the 1 Billion Row Challenge
+// Copyright (C) 2024  Charles German <5donuts@pm.me>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Weigh how fast each runner in a comparison was against how much memory it used.
+//!
+//! A runner is on the *Pareto frontier* if no other runner was at least as fast while using at
+//! most as much memory (& strictly better at one of them); every other runner is a worse pick
+//! whatever the constraints. With a memory budget (`--memory-budget`), the *winner* is the
+//! fastest runner which stayed within it.
+
+use std::time::Duration;
+
+/// What running a runner cost
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cost {
+    /// The mean duration of its runs
+    pub time: Duration,
+
+    /// Its peak memory use in bytes, if that could be measured
+    pub memory: Option<u64>,
+}
+
+impl Cost {
+    /// Whether this is at least as good as `other` on both counts, & better on one of them.
+    ///
+    /// Costs without a measured memory use can't be compared, so never dominate or are dominated.
+    fn dominates(&self, other: &Cost) -> bool {
+        match (self.memory, other.memory) {
+            (Some(memory), Some(other_memory)) => {
+                self.time <= other.time
+                    && memory <= other_memory
+                    && (self.time < other.time || memory < other_memory)
+            }
+            _ => false,
+        }
+    }
+}
+
+/// How a runner weighs up against the others it was compared with
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Tradeoff {
+    /// It used more memory than the budget, so can't be the winner
+    pub over_budget: bool,
+
+    /// No other runner dominates it in both time & memory; never set if its memory use wasn't
+    /// measured
+    pub pareto: bool,
+
+    /// It was the fastest runner within the budget
+    pub winner: bool,
+}
+
+/// Weigh up the cost of each runner against the others' & the memory `budget`, if any.
+///
+/// A runner whose memory use wasn't measured isn't known to be over the budget, so it's still
+/// in the running to be the winner. Of runners equally fast, the first wins.
+pub fn weigh(costs: &[Cost], budget: Option<u64>) -> Vec<Tradeoff> {
+    let mut tradeoffs: Vec<Tradeoff> = costs
+        .iter()
+        .map(|cost| Tradeoff {
+            over_budget: cost
+                .memory
+                .zip(budget)
+                .is_some_and(|(memory, budget)| memory > budget),
+            pareto: cost.memory.is_some() && !costs.iter().any(|other| other.dominates(cost)),
+            winner: false,
+        })
+        .collect();
+
+    let winner = costs
+        .iter()
+        .zip(&tradeoffs)
+        .enumerate()
+        .filter(|(_, (_, tradeoff))| !tradeoff.over_budget)
+        .min_by_key(|(_, (cost, _))| cost.time)
+        .map(|(idx, _)| idx);
+    if let Some(idx) = winner {
+        tradeoffs[idx].winner = true;
+    }
+    tradeoffs
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const GIB: u64 = 1 << 30;
+
+    fn cost(secs: u64, memory: Option<u64>) -> Cost {
+        Cost {
+            time: Duration::from_secs(secs),
+            memory,
+        }
+    }
+
+    fn pareto(tradeoffs: &[Tradeoff]) -> Vec<bool> {
+        tradeoffs.iter().map(|t| t.pareto).collect()
+    }
+
+    fn winner(tradeoffs: &[Tradeoff]) -> Option<usize> {
+        tradeoffs.iter().position(|t| t.winner)
+    }
+
+    #[test]
+    fn dominated() {
+        let costs = [
+            cost(10, Some(GIB)),
+            // Slower & bigger than the first
+            cost(12, Some(2 * GIB)),
+            // As fast as the first, but bigger
+            cost(10, Some(2 * GIB)),
+            // As big as the first, but slower
+            cost(11, Some(GIB)),
+        ];
+        let tradeoffs = weigh(&costs, None);
+        assert_eq!(pareto(&tradeoffs), [true, false, false, false]);
+        assert_eq!(winner(&tradeoffs), Some(0));
+    }
+
+    #[test]
+    fn not_dominated() {
+        let costs = [
+            cost(10, Some(4 * GIB)),
+            cost(20, Some(GIB)),
+            cost(15, Some(2 * GIB)),
+            // The same as another non-dominated runner, which doesn't dominate it either
+            cost(15, Some(2 * GIB)),
+            // Unmeasured, so not on the frontier, but it doesn't push anything off it either
+            cost(5, None),
+        ];
+        let tradeoffs = weigh(&costs, None);
+        assert_eq!(pareto(&tradeoffs), [true, true, true, true, false]);
+        assert_eq!(winner(&tradeoffs), Some(4));
+    }
+
+    #[test]
+    fn over_budget() {
+        let costs = [
+            cost(10, Some(4 * GIB)),
+            cost(20, Some(GIB)),
+            cost(15, Some(2 * GIB)),
+            cost(12, Some(3 * GIB)),
+        ];
+        let tradeoffs = weigh(&costs, Some(2 * GIB));
+        let over: Vec<bool> = tradeoffs.iter().map(|t| t.over_budget).collect();
+        assert_eq!(over, [true, false, false, true]);
+        // Exactly at the budget is within it
+        assert_eq!(winner(&tradeoffs), Some(2));
+        // Going over the budget doesn't take a runner off the frontier
+        assert_eq!(pareto(&tradeoffs), [true, true, true, true]);
+
+        let tradeoffs = weigh(&costs, Some(GIB / 2));
+        assert!(tradeoffs.iter().all(|t| t.over_budget));
+        assert_eq!(winner(&tradeoffs), None);
+    }
+}
@@ -13,91 +13,23 @@
 // You should have received a copy of the GNU General Public License
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
-use std::collections::HashMap;
-use std::io::{BufRead, BufReader};
-use std::time::Instant;
+//! Use the same approach as `baseline` with the `AHasher` from the `ahash` crate.
 
 use ahash::RandomState;
 
+use crate::config::Config;
 use crate::helpers::*;
 
-struct StationData {
-    min: f32,
-    max: f32,
-
-    // Rather than compute a new average at each step, just keep a rolling sum
-    // of all the measurements and calculate the average at the end.
-    sum: f32,
-    cnt: u32,
-}
-
-impl StationData {
-    /// Instantiate a new record of measurements for a station
-    fn new(measurement: f32) -> Self {
-        Self {
-            min: measurement,
-            max: measurement,
-            sum: measurement,
-            cnt: 1,
-        }
-    }
-
-    /// Record an additional measurement for this station
-    fn push(&mut self, measurement: f32) {
-        if measurement < self.min {
-            self.min = measurement;
-        } else if measurement > self.max {
-            self.max = measurement;
-        }
-
-        self.sum += measurement;
-        self.cnt += 1;
-    }
-
-    fn avg(&self) -> f32 {
-        self.sum / self.cnt as f32
-    }
-}
+use super::baseline::run_hashed;
 
 pub struct Runner;
 
 impl ChallengeRunner for Runner {
-    fn run<R>(input: R) -> ChallengeResult
+    fn run<R>(input: R, config: &Config) -> ChallengeResult
     where
         R: std::io::Read + std::io::Seek,
     {
-        let start = Instant::now();
-
-        // Open the input with a BufReader to reduce the number of file I/O operations we're doing
-        // Then, go through each line in the file & parse out the station data, updating the map
-        // of stations as we go.
-        let mut map: HashMap<String, StationData, RandomState> = HashMap::default();
-        for line in BufReader::new(input).lines() {
-            let line = line?;
-            let mut parts = line.split(';');
-            let station = parts.next().unwrap();
-            let measurement = parts.next().unwrap().parse::<f32>()?;
-
-            if let Some(station_data) = map.get_mut(station) {
-                station_data.push(measurement);
-            } else {
-                let station_data = StationData::new(measurement);
-                map.insert(station.to_owned(), station_data);
-            }
-        }
-
-        // Build the alphabetically-sorted list of stations
-        let mut stations: Vec<StationInfo> = map
-            .into_iter()
-            .map(|(key, val)| StationInfo::new(key, val.min, val.max, val.avg()))
-            .collect();
-        stations.sort_unstable();
-
-        // Compute the time it took to generate the list of sorted stations
-        let stop = Instant::now();
-        let duration = stop.duration_since(start);
-
-        Ok((stations, duration))
+        run_hashed::<RandomState, R>(input, config, 0)
     }
 }
 
@@ -111,7 +43,7 @@ mod tests {
     fn correctness() -> Result<(), Box<dyn error::Error>> {
         let input = io::Cursor::new(TEST_DATA.as_bytes());
 
-        let (actual, _) = Runner::run(input)?;
+        let (actual, _) = Runner::run(input, &Config::default())?;
         assert_eq!(
             actual, *EXPECTED_RESULT,
             "actual != expected for baseline runner"
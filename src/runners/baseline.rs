@@ -13,90 +13,83 @@
 // You should have received a copy of the GNU General Public License
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
-use std::collections::HashMap;
-use std::io::{BufRead, BufReader};
+//! Iterate through the input line-by-line.
+
+use std::hash::{BuildHasher, RandomState};
 use std::time::Instant;
 
+use crate::aggregate::Aggregator;
+use crate::config::Config;
 use crate::helpers::*;
-
-struct StationData {
-    min: f32,
-    max: f32,
-
-    // Rather than compute a new average at each step, just keep a rolling sum
-    // of all the measurements and calculate the average at the end.
-    sum: f32,
-    cnt: u32,
-}
-
-impl StationData {
-    /// Instantiate a new record of measurements for a station
-    fn new(measurement: f32) -> Self {
-        Self {
-            min: measurement,
-            max: measurement,
-            sum: measurement,
-            cnt: 1,
-        }
-    }
-
-    /// Record an additional measurement for this station
-    fn push(&mut self, measurement: f32) {
-        if measurement < self.min {
-            self.min = measurement;
-        } else if measurement > self.max {
-            self.max = measurement;
-        }
-
-        self.sum += measurement;
-        self.cnt += 1;
-    }
-
-    fn avg(&self) -> f32 {
-        self.sum / self.cnt as f32
-    }
-}
+use crate::progress::CHECK_EVERY;
+use crate::reader::LineReader;
 
 pub struct Runner;
 
 impl ChallengeRunner for Runner {
-    fn run<R>(input: R) -> ChallengeResult
+    fn run<R>(input: R, config: &Config) -> ChallengeResult
     where
         R: std::io::Read + std::io::Seek,
     {
-        let start = Instant::now();
-
-        // Open the input with a BufReader to reduce the number of file I/O operations we're doing
-        // Then, go through each line in the file & parse out the station data, updating the map
-        // of stations as we go.
-        let mut map: HashMap<String, StationData> = HashMap::new();
-        for line in BufReader::new(input).lines() {
-            let line = line?;
-            let mut parts = line.split(';');
-            let station = parts.next().unwrap();
-            let measurement = parts.next().unwrap().parse::<f32>()?;
+        run_hashed::<RandomState, R>(input, config, 0)
+    }
+}
 
-            if let Some(station_data) = map.get_mut(station) {
-                station_data.push(measurement);
-            } else {
-                let station_data = StationData::new(measurement);
-                map.insert(station.to_owned(), station_data);
+/// The baseline runner with the stations' map hashed by `S` & room for `capacity` stations up
+/// front, so the `rustc-hash`, `ahash` & `presized` runners differ from it (& each other) only in
+/// their hasher & how big their map starts.
+pub(super) fn run_hashed<S, R>(input: R, config: &Config, capacity: usize) -> ChallengeResult
+where
+    S: BuildHasher + Default,
+    R: std::io::Read + std::io::Seek,
+{
+    let start = Instant::now();
+
+    // Open the input with a LineReader to reduce the number of file I/O operations we're doing
+    // Then, go through each line in the file & parse out the station data, updating the map
+    // of stations as we go.
+    let mut aggregator: Aggregator<S> =
+        Aggregator::with_known_stations(config.known_stations.clone())
+            .strict(config.strict.value)
+            .dialect(config.dialect())
+            .track_extents(config.track_extents.value)
+            .case_insensitive(config.case_insensitive.value)
+            .max_skipped(config.max_skipped.value)
+            .with_capacity(capacity)
+            .reuse_results(config.take_results_buffer());
+    let mut lines = LineReader::new(input, config);
+    let progress = config.progress.as_deref();
+    let mut unchecked = 0;
+    loop {
+        match lines.next_line() {
+            Ok(Some(line)) => aggregator.ingest_line(line)?,
+            Ok(None) => break,
+            Err(e) => aggregator.skip_unreadable(e)?,
+        }
+        if let Some(progress) = progress {
+            unchecked += 1;
+            if unchecked == CHECK_EVERY {
+                unchecked = 0;
+                if progress.snapshot_due() {
+                    progress.publish(aggregator.snapshot());
+                }
             }
         }
+    }
 
-        // Build the alphabetically-sorted list of stations
-        let mut stations: Vec<StationInfo> = map
-            .into_iter()
-            .map(|(key, val)| StationInfo::new(key, val.min, val.max, val.avg()))
-            .collect();
-        stations.sort_unstable();
+    let aggregated = Instant::now();
+    let ignored = aggregator.ignored_non_finite();
+    let skipped = aggregator.skipped();
 
-        // Compute the time it took to generate the list of sorted stations
-        let stop = Instant::now();
-        let duration = stop.duration_since(start);
+    // Build the alphabetically-sorted list of stations
+    let stations = aggregator.into_sorted();
 
-        Ok((stations, duration))
-    }
+    // Compute the time it took to generate the list of sorted stations
+    let stats = RunStats::new(Timings::since(start, aggregated, config))
+        .ignored_non_finite(ignored)
+        .skipped(skipped);
+
+    Ok((stations, stats))
 }
 
 #[cfg(test)]
@@ -109,7 +102,7 @@ mod tests {
     fn correctness() -> Result<(), Box<dyn error::Error>> {
         let input = io::Cursor::new(TEST_DATA.as_bytes());
 
-        let (actual, _) = Runner::run(input)?;
+        let (actual, _) = Runner::run(input, &Config::default())?;
         assert_eq!(
             actual, *EXPECTED_RESULT,
             "actual != expected for baseline runner"
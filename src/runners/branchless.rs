@@ -0,0 +1,178 @@
+// 1BRC - my take on the 1 Billion Row Challenge
+// Copyright (C) 2024  Charles German <5donuts@pm.me>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Use the same approach as `ahash`, but parse each measurement with a parser specialized for the
+//! challenge's `-?\d{1,2}\.\d` format, which decodes both lengths with the same few arithmetic ops
+//! rather than branching on the number of digits.
+
+use std::collections::HashMap;
+use std::time::Instant;
+
+use ahash::RandomState;
+
+use crate::aggregate::{merge_case_variants_by_row, parse_record, KeyFormat, StationData};
+use crate::config::Config;
+use crate::error::SkippedLines;
+use crate::helpers::*;
+use crate::reader::LineReader;
+
+use super::parse::parse_tenths_branchless;
+
+pub struct Runner;
+
+impl ChallengeRunner for Runner {
+    /// Like the `ahash` runner, but parsing each canonical measurement with
+    /// [`parse_tenths_branchless`] rather than the usual parser.
+    ///
+    /// Anything other than a canonical measurement (e.g. `12.34` or `+5`, outside strict mode)
+    /// is parsed the usual way, or rejected in strict mode. Both parsers give the same `f32` for
+    /// a canonical measurement, so the results are the same bits as the `ahash` runner's.
+    fn run<R>(input: R, config: &Config) -> ChallengeResult
+    where
+        R: std::io::Read + std::io::Seek,
+    {
+        let start = Instant::now();
+
+        let strict = config.strict.value;
+        let max_skipped = config.max_skipped.value;
+        let track_rows = config.track_rows();
+        let dialect = config.dialect();
+        let delimiter = dialect.delimiter.byte();
+        // With a decimal comma (or a month before the station), the first delimiter might not be
+        // the one before the measurement
+        let split = dialect.key_format == KeyFormat::Station && !dialect.decimal_comma;
+
+        let known = config
+            .known_stations
+            .as_ref()
+            .map_or(0, |names| names.len());
+        let mut stations: HashMap<String, StationData, RandomState> =
+            HashMap::with_capacity_and_hasher(known, RandomState::new());
+        let mut skipped = SkippedLines::default();
+        let mut lines = LineReader::new(input, config);
+        let mut line_number = 0;
+        loop {
+            let line = match lines.next_line() {
+                Ok(Some(line)) => line,
+                Ok(None) => break,
+                Err(e) => {
+                    line_number += 1;
+                    skipped.skip(e, 1, max_skipped)?;
+                    continue;
+                }
+            };
+            line_number += 1;
+
+            // The delimiter is ASCII, so it's never in the middle of a character
+            let fast = match line.bytes().position(|b| b == delimiter) {
+                Some(idx) if split => parse_tenths_branchless(&line.as_bytes()[idx + 1..])
+                    .ok()
+                    .map(|tenths| (&line[..idx], tenths as f32 / 10.0)),
+                _ => None,
+            };
+            let (station, measurement) = match fast {
+                Some(record) => record,
+                None => match parse_record(line, line_number, strict, dialect) {
+                    Ok(Some(record)) => record,
+                    Ok(None) => continue,
+                    Err(e) => {
+                        skipped.skip(e, 1, max_skipped)?;
+                        continue;
+                    }
+                },
+            };
+
+            if !stations.contains_key(station) {
+                stations.insert(station.to_owned(), StationData::empty());
+            }
+            let data = stations
+                .get_mut(station)
+                .expect("The station was just added");
+            data.push(measurement, strict);
+            if track_rows {
+                data.record_row(line_number);
+            }
+        }
+
+        let aggregated = Instant::now();
+        let ignored = stations.values().map(|data| data.skipped as u64).sum();
+
+        // Spellings of the same station were kept apart until now, with the rows they were first on
+        let stations: Vec<(String, StationData)> = if config.case_insensitive.value {
+            merge_case_variants_by_row(stations, config.track_extents.value)
+        } else {
+            stations.into_iter().collect()
+        };
+
+        // Build the alphabetically-sorted list of stations
+        let mut stations: Vec<StationInfo> = stations
+            .into_iter()
+            .filter(|(_, data)| data.cnt > 0)
+            .map(|(name, data)| {
+                StationInfo::new(name, data.min, data.max, data.avg(), data.cnt)
+                    .with_extents(data.extents())
+            })
+            .collect();
+        stations.sort_unstable();
+
+        let stats = RunStats::new(Timings::since(start, aggregated, config))
+            .ignored_non_finite(ignored)
+            .skipped(skipped);
+
+        Ok((stations, stats))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Source;
+    use crate::runners::tests::*;
+    use std::{error, io};
+
+    #[test]
+    fn correctness() -> Result<(), Box<dyn error::Error>> {
+        let input = io::Cursor::new(TEST_DATA.as_bytes());
+
+        let (actual, _) = Runner::run(input, &Config::default())?;
+        assert_eq!(
+            actual, *EXPECTED_RESULT,
+            "actual != expected for branchless runner"
+        );
+
+        Ok(())
+    }
+
+    /// Non-canonical measurements fall back to the usual parser, & are errors in strict mode
+    #[test]
+    fn non_canonical() -> Result<(), Box<dyn error::Error>> {
+        let input = "A;-99.9\nA;99.9\nB;+5\nB;12.34\nC;-0.0\n";
+        let (actual, _) = Runner::run(io::Cursor::new(input), &Config::default())?;
+        let actual: Vec<_> = actual.iter().map(|s| s.to_string()).collect();
+        assert_eq!(
+            actual,
+            ["A=-99.9/0.0/99.9", "B=5.0/8.7/12.3", "C=0.0/0.0/0.0"]
+        );
+
+        let strict = Config {
+            strict: crate::config::Setting::new(true, Source::Cli),
+            ..Config::default()
+        };
+        let error = Runner::run(io::Cursor::new(input), &strict).unwrap_err();
+        assert!(error.to_string().contains("line 3"), "{error}");
+
+        Ok(())
+    }
+}
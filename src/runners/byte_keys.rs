@@ -0,0 +1,256 @@
+// 1BRC - my take on the 1 Billion Row Challenge
+// Copyright (C) 2024  Charles German <5donuts@pm.me>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Use the same approach as `ahash`, but read the input in blocks & key the map by the bytes of
+//! each name, borrowed from an arena they're copied into the first time they're seen, so no
+//! `String`s are built until the results are.
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::time::Instant;
+
+use ahash::RandomState;
+
+use crate::aggregate::{merge_case_variants_by_row, StationData};
+use crate::blocks::BlockReader;
+use crate::config::Config;
+use crate::error::SkippedLines;
+use crate::helpers::*;
+
+pub struct Runner;
+
+/// The size of each page of a [`KeyArena`]; a page holds the names of hundreds of stations
+const PAGE_SIZE: usize = 64 * 1024;
+
+/// Copies of station names which stay put until the arena is dropped, so a map can borrow them
+/// after the blocks they were first seen in are gone.
+///
+/// Names are copied into the free space at the end of the last page, and a new page is started
+/// once one is full, so nothing ever moves.
+#[derive(Default)]
+struct KeyArena {
+    pages: RefCell<Vec<Vec<u8>>>,
+}
+
+impl KeyArena {
+    /// An arena whose first page has room for at least `bytes` bytes of names, e.g. every name
+    /// on the list of known stations, so the stations expected never need more than one page
+    fn with_capacity(bytes: usize) -> Self {
+        Self {
+            pages: RefCell::new(vec![Vec::with_capacity(PAGE_SIZE.max(bytes))]),
+        }
+    }
+
+    /// Copy `key` into the arena
+    fn alloc(&self, key: &[u8]) -> &[u8] {
+        let mut pages = self.pages.borrow_mut();
+        let fits = pages
+            .last()
+            .is_some_and(|page| page.capacity() - page.len() >= key.len());
+        if !fits {
+            pages.push(Vec::with_capacity(PAGE_SIZE.max(key.len())));
+        }
+
+        let page = pages.last_mut().expect("There is always a page with room");
+        let start = page.len();
+        page.extend_from_slice(key);
+        let copy = page[start..].as_ptr();
+
+        // SAFETY: a page is never filled past its capacity, so its buffer is never reallocated
+        // & the copy stays where it is until the pages are dropped along with the arena, which
+        // the returned slice borrows. Bytes which were handed out are never written to again.
+        unsafe { std::slice::from_raw_parts(copy, key.len()) }
+    }
+}
+
+impl ChallengeRunner for Runner {
+    /// Read the input in blocks & key the map of stations by the bytes of their names rather
+    /// than `String`s. A name is only copied (into a [`KeyArena`]) the first time it's seen, and
+    /// only becomes a `String` once the results are built.
+    fn run<R>(mut input: R, config: &Config) -> ChallengeResult
+    where
+        R: std::io::Read + std::io::Seek,
+    {
+        let start = Instant::now();
+
+        let strict = config.strict.value;
+        let max_skipped = config.max_skipped.value;
+        let (known, known_bytes) = config.known_stations.as_ref().map_or((0, 0), |names| {
+            (names.len(), names.iter().map(String::len).sum())
+        });
+        // Declared first so it outlives the map borrowing from it
+        let arena = KeyArena::with_capacity(known_bytes);
+        let mut stations: HashMap<&[u8], StationData, RandomState> =
+            HashMap::with_capacity_and_hasher(known, RandomState::new());
+        let mut skipped = SkippedLines::default();
+
+        let mut reader = BlockReader::new(&mut input, config).count_lines(config.track_rows());
+        let failure = loop {
+            let block = match reader.next_block() {
+                Ok(Some(block)) => block,
+                Ok(None) => break None,
+                // Lines too long to fit in a block are skipped, like any other malformed line
+                Err(e) => {
+                    skipped.skip(e, 1, max_skipped)?;
+                    continue;
+                }
+            };
+
+            let result = block.for_each_record(
+                config.max_line_length.value,
+                strict,
+                config.dialect(),
+                max_skipped,
+                &mut skipped,
+                |station, measurement, line| {
+                    let station = station.as_bytes();
+                    if !stations.contains_key(station) {
+                        stations.insert(arena.alloc(station), StationData::empty());
+                    }
+                    let data = stations
+                        .get_mut(station)
+                        .expect("The station was just added");
+                    data.push(measurement, strict);
+                    if let Some(first_line) = block.first_line {
+                        data.record_row(first_line + line - 1);
+                    }
+                },
+            );
+            // The block is dropped here, but the names in the map were copied out of it
+            if let Err(failure) = result {
+                break Some(failure);
+            }
+        };
+        if let Some(failure) = failure {
+            return Err(failure.locate(&mut input).into());
+        }
+
+        let aggregated = Instant::now();
+        let ignored = stations.values().map(|data| data.skipped as u64).sum();
+
+        // Every name came from a `&str`, so is valid UTF-8
+        let named = stations.into_iter().map(|(name, data)| {
+            let name =
+                String::from_utf8(name.to_vec()).expect("Names were checked as they were read");
+            (name, data)
+        });
+        let named: Vec<(String, StationData)> = if config.case_insensitive.value {
+            merge_case_variants_by_row(named, config.track_extents.value)
+        } else {
+            named.collect()
+        };
+
+        // Build the alphabetically-sorted list of stations
+        let mut stations: Vec<StationInfo> = named
+            .into_iter()
+            .filter(|(_, data)| data.cnt > 0)
+            .map(|(name, data)| {
+                StationInfo::new(name, data.min, data.max, data.avg(), data.cnt)
+                    .with_extents(data.extents())
+            })
+            .collect();
+        stations.sort_unstable();
+
+        let stats = RunStats::new(Timings::since(start, aggregated, config))
+            .ignored_non_finite(ignored)
+            .skipped(skipped);
+
+        Ok((stations, stats))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{Setting, Source};
+    use crate::runners::tests::*;
+    use crate::Runner as Kind;
+    use std::{error, io};
+
+    #[test]
+    fn correctness() -> Result<(), Box<dyn error::Error>> {
+        let input = io::Cursor::new(TEST_DATA.as_bytes());
+
+        let (actual, _) = Runner::run(input, &Config::default())?;
+        assert_eq!(
+            actual, *EXPECTED_RESULT,
+            "actual != expected for byte-keys runner"
+        );
+
+        Ok(())
+    }
+
+    /// The names borrowed by the map must outlive the blocks they were first seen in
+    #[test]
+    fn keys_survive_blocks() -> Result<(), Box<dyn error::Error>> {
+        let input = TEST_DATA.repeat(50);
+        let config = Config {
+            buffer_size: Setting::new(20, Source::Cli),
+            ..Config::default().with_runner(Kind::ByteKeys, Source::Cli)
+        };
+        let baseline = Config::default().with_runner(Kind::Baseline, Source::Cli);
+
+        let (expected, _) = crate::runners::run_with(io::Cursor::new(&input), &baseline)?;
+        let (actual, _) = Runner::run(io::Cursor::new(&input), &config)?;
+        let render = |stations: &[StationInfo]| -> Vec<_> {
+            stations
+                .iter()
+                .map(|s| (s.to_string(), s.count()))
+                .collect()
+        };
+        assert_eq!(render(&actual), render(&expected));
+        assert_eq!(actual[3].count(), 200);
+
+        Ok(())
+    }
+
+    #[test]
+    fn arena_pages() {
+        let arena = KeyArena::default();
+        let long = vec![b'x'; PAGE_SIZE + 1];
+        let keys: Vec<&[u8]> = (0..10_000)
+            .map(|i| arena.alloc(format!("Station {i}").as_bytes()))
+            .chain([arena.alloc(&long), arena.alloc(b"")])
+            .collect();
+
+        assert_eq!(keys[0], b"Station 0");
+        assert_eq!(keys[9_999], b"Station 9999");
+        assert_eq!(keys[10_000], &long[..]);
+        assert!(arena.pages.borrow().len() > 2);
+    }
+
+    /// Names copied in before the arena outgrows the capacity it was made with are left where
+    /// they were
+    #[test]
+    fn arena_outgrows_capacity() {
+        let arena = KeyArena::with_capacity(PAGE_SIZE * 2);
+        let first = arena.alloc(b"Hamburg");
+        let first_ptr = first.as_ptr();
+
+        let names: Vec<String> = (0..20_000).map(|i| format!("Station {i:05}")).collect();
+        let keys: Vec<&[u8]> = names
+            .iter()
+            .map(|name| arena.alloc(name.as_bytes()))
+            .collect();
+        assert!(names.iter().map(String::len).sum::<usize>() > PAGE_SIZE * 2);
+        assert!(arena.pages.borrow().len() > 1);
+
+        assert_eq!(first, b"Hamburg");
+        assert_eq!(first.as_ptr(), first_ptr);
+        for (name, key) in names.iter().zip(keys) {
+            assert_eq!(name.as_bytes(), key);
+        }
+    }
+}
@@ -0,0 +1,105 @@
+// 1BRC - my take on the 1 Billion Row Challenge
+// Copyright (C) 2024  Charles German <5donuts@pm.me>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Use the same approach as `table`, with a small direct-mapped cache of recently-seen stations in
+//! front of the table so most lookups don't need to probe it.
+
+use std::time::Instant;
+
+use crate::aggregate::parse_record_bytes;
+use crate::config::Config;
+use crate::helpers::*;
+use crate::reader::LineReader;
+use crate::table::{CachedTable, StationTable};
+
+pub struct Runner;
+
+impl ChallengeRunner for Runner {
+    fn run<R>(input: R, config: &Config) -> ChallengeResult
+    where
+        R: std::io::Read + std::io::Seek,
+    {
+        let start = Instant::now();
+
+        // Same as the table runner, but looking stations up in a small direct-mapped cache
+        // before probing the table
+        let mut table = CachedTable::new(
+            StationTable::with_known_stations(config.known_stations.clone())
+                .strict(config.strict.value)
+                .track_extents(config.track_extents.value)
+                .case_insensitive(config.case_insensitive.value)
+                .encoding(config.encoding.value)
+                .max_skipped(config.max_skipped.value),
+        );
+        let mut lines = LineReader::new(input, config);
+        let dialect = config.dialect();
+        let mut line_number = 0;
+        loop {
+            let (offset, line) = match lines.next_line_bytes() {
+                Ok(Some(line)) => line,
+                Ok(None) => break,
+                Err(e) => {
+                    line_number += 1;
+                    table.table_mut().skip(e)?;
+                    continue;
+                }
+            };
+            line_number += 1;
+            match parse_record_bytes(line, line_number, config.strict.value, dialect) {
+                Ok(Some((station, measurement))) => {
+                    table.push(station, measurement, offset, line_number)
+                }
+                Ok(None) => {}
+                Err(e) => table.table_mut().skip(e)?,
+            }
+        }
+
+        let aggregated = Instant::now();
+        let mut table = table.into_inner();
+        table.skip_invalid_names()?;
+        let ignored = table.ignored_non_finite();
+        let skipped = table.skipped();
+
+        // Build the alphabetically-sorted list of stations
+        let stations = table.into_sorted()?;
+
+        // Compute the time it took to generate the list of sorted stations
+        let stats = RunStats::new(Timings::since(start, aggregated, config))
+            .ignored_non_finite(ignored)
+            .skipped(skipped);
+
+        Ok((stations, stats))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::runners::tests::*;
+    use std::{error, io};
+
+    #[test]
+    fn correctness() -> Result<(), Box<dyn error::Error>> {
+        let input = io::Cursor::new(TEST_DATA.as_bytes());
+
+        let (actual, _) = Runner::run(input, &Config::default())?;
+        assert_eq!(
+            actual, *EXPECTED_RESULT,
+            "actual != expected for cached-table runner"
+        );
+
+        Ok(())
+    }
+}
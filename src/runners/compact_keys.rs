@@ -0,0 +1,265 @@
+// 1BRC - my take on the 1 Billion Row Challenge
+// Copyright (C) 2024  Charles German <5donuts@pm.me>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Use the same approach as `byte-keys`, but key the map by names of up to 22 bytes stored inline
+//! in the keys themselves, rather than borrowed from an arena, so most lookups never follow a
+//! pointer. Longer names are kept on the heap.
+//!
+//! Most station names are short, so rather than each key pointing at a heap allocation (as a
+//! `String` does), which every comparison has to follow, a [`CompactKey`] keeps names of up to
+//! [`INLINE`] bytes in the key, right there in the map's bucket. Longer names, up to the
+//! challenge's 100 bytes (or anything else the input has), spill to the heap as usual.
+
+use std::borrow::Borrow;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+use std::time::Instant;
+
+use ahash::RandomState;
+
+use crate::aggregate::{merge_case_variants_by_row, StationData};
+use crate::blocks::BlockReader;
+use crate::config::Config;
+use crate::error::SkippedLines;
+use crate::helpers::*;
+
+pub struct Runner;
+
+/// The longest name a [`CompactKey`] keeps inline; with its length & the enum's tag, that's 24
+/// bytes, the same as a `String`
+const INLINE: usize = 22;
+
+/// The bytes of a station's name, inline if there are at most [`INLINE`] of them, or on the heap
+/// otherwise.
+///
+/// It hashes & compares like the `[u8]` it [borrows](Borrow) as, so a map of them can be looked
+/// up with a name straight out of the input, without building a key.
+#[derive(Debug, Clone)]
+enum CompactKey {
+    Inline { len: u8, bytes: [u8; INLINE] },
+    Heap(Box<[u8]>),
+}
+
+impl CompactKey {
+    fn new(name: &[u8]) -> Self {
+        if name.len() > INLINE {
+            return Self::Heap(name.into());
+        }
+        let mut bytes = [0; INLINE];
+        bytes[..name.len()].copy_from_slice(name);
+        Self::Inline {
+            len: name.len() as u8,
+            bytes,
+        }
+    }
+
+    fn as_bytes(&self) -> &[u8] {
+        match self {
+            Self::Inline { len, bytes } => &bytes[..*len as usize],
+            Self::Heap(bytes) => bytes,
+        }
+    }
+}
+
+impl Borrow<[u8]> for CompactKey {
+    fn borrow(&self) -> &[u8] {
+        self.as_bytes()
+    }
+}
+
+impl Hash for CompactKey {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.as_bytes().hash(state);
+    }
+}
+
+impl PartialEq for CompactKey {
+    fn eq(&self, other: &Self) -> bool {
+        self.as_bytes() == other.as_bytes()
+    }
+}
+
+impl Eq for CompactKey {}
+
+impl ChallengeRunner for Runner {
+    /// Read the input in blocks like the [`byte-keys`](super::ByteKeys) runner, but key the map
+    /// by [`CompactKey`]s, which only allocate for names longer than [`INLINE`] bytes.
+    fn run<R>(mut input: R, config: &Config) -> ChallengeResult
+    where
+        R: std::io::Read + std::io::Seek,
+    {
+        let start = Instant::now();
+
+        let strict = config.strict.value;
+        let max_skipped = config.max_skipped.value;
+        let known = config
+            .known_stations
+            .as_ref()
+            .map_or(0, |names| names.len());
+        let mut stations: HashMap<CompactKey, StationData, RandomState> =
+            HashMap::with_capacity_and_hasher(known, RandomState::new());
+        let mut skipped = SkippedLines::default();
+
+        let mut reader = BlockReader::new(&mut input, config).count_lines(config.track_rows());
+        let failure = loop {
+            let block = match reader.next_block() {
+                Ok(Some(block)) => block,
+                Ok(None) => break None,
+                // Lines too long to fit in a block are skipped, like any other malformed line
+                Err(e) => {
+                    skipped.skip(e, 1, max_skipped)?;
+                    continue;
+                }
+            };
+
+            let result = block.for_each_record(
+                config.max_line_length.value,
+                strict,
+                config.dialect(),
+                max_skipped,
+                &mut skipped,
+                |station, measurement, line| {
+                    let station = station.as_bytes();
+                    let data = match stations.get_mut(station) {
+                        Some(data) => data,
+                        None => stations
+                            .entry(CompactKey::new(station))
+                            .or_insert(StationData::empty()),
+                    };
+                    data.push(measurement, strict);
+                    if let Some(first_line) = block.first_line {
+                        data.record_row(first_line + line - 1);
+                    }
+                },
+            );
+            if let Err(failure) = result {
+                break Some(failure);
+            }
+        };
+        if let Some(failure) = failure {
+            return Err(failure.locate(&mut input).into());
+        }
+
+        let aggregated = Instant::now();
+        let ignored = stations.values().map(|data| data.skipped as u64).sum();
+
+        // Every name came from a `&str`, so is valid UTF-8
+        let named = stations.into_iter().map(|(name, data)| {
+            let name = String::from_utf8(name.as_bytes().to_vec())
+                .expect("Names were checked as they were read");
+            (name, data)
+        });
+        let named: Vec<(String, StationData)> = if config.case_insensitive.value {
+            merge_case_variants_by_row(named, config.track_extents.value)
+        } else {
+            named.collect()
+        };
+
+        // Build the alphabetically-sorted list of stations
+        let mut stations: Vec<StationInfo> = named
+            .into_iter()
+            .filter(|(_, data)| data.cnt > 0)
+            .map(|(name, data)| {
+                StationInfo::new(name, data.min, data.max, data.avg(), data.cnt)
+                    .with_extents(data.extents())
+            })
+            .collect();
+        stations.sort_unstable();
+
+        let stats = RunStats::new(Timings::since(start, aggregated, config))
+            .ignored_non_finite(ignored)
+            .skipped(skipped);
+
+        Ok((stations, stats))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::runners::tests::*;
+    use std::{error, io};
+
+    #[test]
+    fn correctness() -> Result<(), Box<dyn error::Error>> {
+        let input = io::Cursor::new(TEST_DATA.as_bytes());
+
+        let (actual, _) = Runner::run(input, &Config::default())?;
+        assert_eq!(
+            actual, *EXPECTED_RESULT,
+            "actual != expected for compact-keys runner"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn inline_boundary() {
+        assert_eq!(size_of::<CompactKey>(), size_of::<String>());
+
+        let at = "x".repeat(INLINE);
+        let past = "x".repeat(INLINE + 1);
+        let inline = |name: &[u8]| matches!(CompactKey::new(name), CompactKey::Inline { .. });
+        assert!(inline(at.as_bytes()));
+        assert!(!inline(past.as_bytes()));
+        assert!(inline(b""));
+
+        // Where the name is kept makes no difference to how it compares or hashes
+        let hash = |bytes: &[u8]| RandomState::with_seeds(1, 2, 3, 4).hash_one(bytes);
+        for name in [&at, &past] {
+            let key = CompactKey::new(name.as_bytes());
+            assert_eq!(key.as_bytes(), name.as_bytes());
+            assert_eq!(
+                RandomState::with_seeds(1, 2, 3, 4).hash_one(&key),
+                hash(name.as_bytes())
+            );
+        }
+        assert_ne!(
+            CompactKey::new(at.as_bytes()),
+            CompactKey::new(past.as_bytes())
+        );
+    }
+
+    /// Names either side of the boundary, & the longest the challenge allows, are all
+    /// aggregated like any other
+    #[test]
+    fn long_names() -> Result<(), Box<dyn error::Error>> {
+        // Multi-byte characters, so the boundary falls on bytes rather than characters
+        let at = format!("{}ü", "a".repeat(INLINE - 2));
+        let past = format!("{}ü", "a".repeat(INLINE - 1));
+        let longest = "é".repeat(50);
+        assert_eq!(
+            (at.len(), past.len(), longest.len()),
+            (INLINE, INLINE + 1, 100)
+        );
+
+        let input = format!(
+            "{at};1.0\n{past};2.0\n{longest};3.0\n{at};-1.0\n{past};4.0\n{longest};5.0\nHamburg;12.0\n"
+        );
+        let (actual, _) = Runner::run(io::Cursor::new(input.as_bytes()), &Config::default())?;
+        let render: Vec<String> = actual.iter().map(ToString::to_string).collect();
+        assert_eq!(
+            render,
+            [
+                "Hamburg=12.0/12.0/12.0".to_string(),
+                format!("{past}=2.0/3.0/4.0"),
+                format!("{at}=-1.0/0.0/1.0"),
+                format!("{longest}=3.0/4.0/5.0"),
+            ]
+        );
+
+        Ok(())
+    }
+}
@@ -0,0 +1,409 @@
+// 1BRC - my take on the 1 Billion Row Challenge
+// Copyright (C) 2024  Charles German <5donuts@pm.me>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Read the input into a fixed set of buffers on one thread, handing each block to whichever parser
+//! thread is free over a bounded `crossbeam` channel & getting the buffer back over another once
+//! it's parsed, so the buffers are only allocated once.
+
+use std::collections::HashMap;
+use std::io::Read;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Instant;
+
+use crossbeam_channel::{bounded, Receiver, Sender};
+
+use crate::aggregate::{merge_case_variants_by_row, StationData};
+use crate::blocks::{Block, BlockFailure, BlockReader};
+use crate::config::Config;
+use crate::error::{ChallengeError, SkippedLines};
+use crate::helpers::*;
+use crate::manifest::ChunkEntry;
+
+pub struct Runner;
+
+impl Runner {
+    /// Roughly how many bytes the reader reads into each buffer
+    pub const BLOCK_SIZE: usize = 1 << 20;
+
+    /// How many threads parse the blocks read
+    pub const WORKERS: usize = 4;
+
+    /// How many buffers are allocated up front, which is all the blocks there are ever in memory
+    /// at once: each is either being read into, queued, or being parsed
+    pub const BUFFERS: usize = 2 * Self::WORKERS;
+}
+
+/// What a single parser thread aggregated from the blocks it took
+struct Partial {
+    stations: HashMap<String, StationData>,
+
+    /// Malformed lines skipped in its blocks
+    skipped: SkippedLines,
+}
+
+impl Partial {
+    fn new() -> Self {
+        Self {
+            stations: HashMap::new(),
+            skipped: SkippedLines::default(),
+        }
+    }
+}
+
+impl ChallengeRunner for Runner {
+    /// Read the input on the calling thread into a fixed set of buffers, handing each block
+    /// read to whichever of [`Runner::WORKERS`] parser threads is free over one bounded channel.
+    /// The parsers send each buffer back over another once they're done with it, for the reader
+    /// to read the next block into, so the buffers are only allocated once.
+    fn run<R>(mut input: R, config: &Config) -> ChallengeResult
+    where
+        R: std::io::Read + std::io::Seek,
+    {
+        let start = Instant::now();
+        let mut pipeline = Pipeline::new(Runner::BLOCK_SIZE, Runner::WORKERS, config);
+        match pipeline.run(&mut input, config)? {
+            Ok(partials) => finish(partials, start, config),
+            Err(failure) => Err(failure.locate(&mut input).into()),
+        }
+    }
+}
+
+/// The buffers blocks are read into, & how many threads parse them
+struct Pipeline {
+    block_size: usize,
+    workers: usize,
+
+    /// How big each buffer is allocated
+    capacity: usize,
+
+    /// The buffers not in use, which is all of them between runs
+    buffers: Vec<Vec<u8>>,
+}
+
+impl Pipeline {
+    /// Allocate [`Runner::BUFFERS`] buffers, each big enough for a block of `block_size` bytes
+    /// & the longest line which can be carried over into it
+    fn new(block_size: usize, workers: usize, config: &Config) -> Self {
+        let capacity = block_size + config.max_line_length.value + 1;
+        Self {
+            block_size,
+            workers: workers.max(1),
+            capacity,
+            buffers: (0..Runner::BUFFERS)
+                .map(|_| Vec::with_capacity(capacity))
+                .collect(),
+        }
+    }
+
+    /// Read `input` through the buffers, & collect what each parser aggregated along with the
+    /// lines skipped while reading.
+    ///
+    /// Which parser takes which block depends on which is free first, so the order measurements
+    /// are summed in varies from run to run. The error closest to the start of the input (once
+    /// no more lines can be skipped) is returned as the inner `Err` for the caller to
+    /// [locate](BlockFailure::locate).
+    fn run<R: Read>(
+        &mut self,
+        input: &mut R,
+        config: &Config,
+    ) -> Result<Result<Vec<Partial>, BlockFailure>, ChallengeError> {
+        if let Some(recorder) = &config.chunk_manifest {
+            recorder.start();
+        }
+        let max_skipped = config.max_skipped.value;
+        let mut reader = BlockReader::new(input, config)
+            .block_size(self.block_size)
+            .count_lines(config.track_rows());
+        let mut read = Partial::new();
+
+        // Every buffer fits in either channel, so sending never blocks on the other end
+        let (free, returned) = bounded(self.buffers.len());
+        for buffer in self.buffers.drain(..) {
+            free.send(buffer).expect("The receiver is still here");
+        }
+        let (blocks, queued) = bounded(Runner::BUFFERS);
+        let stop = AtomicBool::new(false);
+
+        let (failure, results) = std::thread::scope(|s| {
+            let parsers: Vec<_> = (0..self.workers)
+                .map(|_| {
+                    let (queued, free, stop) = (queued.clone(), free.clone(), &stop);
+                    s.spawn(move || parse_blocks(queued, free, stop, config))
+                })
+                .collect();
+            drop(queued);
+
+            let mut failure = None;
+            // A parser which gave up at a malformed line stops the reader, as there's no point
+            // reading any further
+            while !stop.load(Ordering::Relaxed) {
+                let buffer = returned.recv().expect("A sender is still here");
+                let block = match reader.next_block_into(buffer) {
+                    Ok(Some(block)) => block,
+                    Ok(None) => break,
+                    // Lines too long to fit in a block are skipped here, like any other malformed
+                    // line. The buffer went with the error, so another takes its place.
+                    Err(e) => {
+                        let offset = e.offset().unwrap_or_default();
+                        free.send(Vec::with_capacity(self.capacity))
+                            .expect("The receiver is still here");
+                        match read.skipped.skip(e, 1, max_skipped) {
+                            Ok(()) => continue,
+                            Err(error) => {
+                                failure = Some(BlockFailure {
+                                    block_offset: offset,
+                                    offset,
+                                    error,
+                                });
+                                break;
+                            }
+                        }
+                    }
+                };
+                if blocks.send(block).is_err() {
+                    break;
+                }
+            }
+            drop(blocks);
+
+            let results: Vec<Result<Partial, BlockFailure>> = parsers
+                .into_iter()
+                .map(|parser| parser.join().expect("Parser thread panicked"))
+                .collect();
+            (failure, results)
+        });
+        drop(free);
+        self.buffers.extend(returned.try_iter());
+
+        // Report the error closest to the start of the input, like a sequential runner would
+        let mut partials = Vec::with_capacity(results.len() + 1);
+        let mut first_failure = failure;
+        for result in results {
+            match result {
+                Ok(partial) => partials.push(partial),
+                Err(failure) => {
+                    if first_failure
+                        .as_ref()
+                        .is_none_or(|first| failure.offset < first.offset)
+                    {
+                        first_failure = Some(failure);
+                    }
+                }
+            }
+        }
+        if let Some(failure) = first_failure {
+            return Ok(Err(failure));
+        }
+        partials.push(read);
+        Ok(Ok(partials))
+    }
+}
+
+/// Aggregate every line of the blocks a parser thread takes into a map of its own, sending each
+/// block's buffer back once it's parsed, until the reader is done or a line can't be skipped
+fn parse_blocks(
+    blocks: Receiver<Block>,
+    free: Sender<Vec<u8>>,
+    stop: &AtomicBool,
+    config: &Config,
+) -> Result<Partial, BlockFailure> {
+    let mut partial = Partial::new();
+    for block in blocks {
+        let mut rows = 0;
+        let stations = &mut partial.stations;
+        let parsed = block.for_each_record(
+            config.max_line_length.value,
+            config.strict.value,
+            config.dialect(),
+            config.max_skipped.value,
+            &mut partial.skipped,
+            |station, measurement, line| {
+                rows += 1;
+                if !stations.contains_key(station) {
+                    stations.insert(station.to_owned(), StationData::empty());
+                }
+                let data = stations
+                    .get_mut(station)
+                    .expect("The station was just added");
+                data.push(measurement, config.strict.value);
+                if let Some(first_line) = block.first_line {
+                    data.record_row(first_line + line - 1);
+                }
+            },
+        );
+        if parsed.is_err() {
+            stop.store(true, Ordering::Relaxed);
+        } else if let Some(recorder) = &config.chunk_manifest {
+            recorder.record(ChunkEntry::new(&block, rows));
+        }
+        // Sent back even after a failure, so the reader wakes up to see it should stop. It may
+        // have stopped already, in which case the buffer isn't needed.
+        let _ = free.send(block.data);
+        parsed?;
+    }
+    Ok(partial)
+}
+
+/// Merge the parsers' maps & build the sorted list of stations
+fn finish(partials: Vec<Partial>, start: Instant, config: &Config) -> ChallengeResult {
+    let mut skipped = SkippedLines::default();
+    let mut totals: HashMap<String, StationData> = HashMap::new();
+    for partial in partials {
+        for (name, data) in partial.stations {
+            totals
+                .entry(name)
+                .or_insert_with(StationData::empty)
+                .merge(&data);
+        }
+        // Each thread only knows about the lines skipped in its blocks
+        skipped.merge(&partial.skipped);
+    }
+    skipped.check(config.max_skipped.value)?;
+
+    let aggregated = Instant::now();
+    let ignored = totals.values().map(|data| data.skipped as u64).sum();
+
+    // Spellings of the same station were kept apart until now, with the rows they were first on
+    let totals: Vec<(String, StationData)> = if config.case_insensitive.value {
+        merge_case_variants_by_row(totals, config.track_extents.value)
+    } else {
+        totals.into_iter().collect()
+    };
+
+    // Build the alphabetically-sorted list of stations
+    let mut stations: Vec<StationInfo> = totals
+        .into_iter()
+        .filter(|(_, data)| data.cnt > 0)
+        .map(|(name, data)| {
+            StationInfo::new(name, data.min, data.max, data.avg(), data.cnt)
+                .with_extents(data.extents())
+        })
+        .collect();
+    stations.sort_unstable();
+
+    let stats = RunStats::new(Timings::since(start, aggregated, config))
+        .ignored_non_finite(ignored)
+        .skipped(skipped);
+
+    Ok((stations, stats))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{Setting, Source};
+    use crate::runners::tests::*;
+    use crate::Runner as Kind;
+    use std::{error, io};
+
+    fn config() -> Config {
+        Config::default().with_runner(Kind::Crossbeam, Source::Cli)
+    }
+
+    #[test]
+    fn correctness() -> Result<(), Box<dyn error::Error>> {
+        let input = io::Cursor::new(TEST_DATA.as_bytes());
+
+        let (actual, _) = Runner::run(input, &Config::default())?;
+        assert_eq!(
+            actual, *EXPECTED_RESULT,
+            "actual != expected for crossbeam runner"
+        );
+
+        Ok(())
+    }
+
+    /// Many tiny blocks go round the buffers many times over, & each station's first & last rows
+    /// still come out the same as reading the input in order
+    #[test]
+    fn many_tiny_blocks() -> Result<(), Box<dyn error::Error>> {
+        let input = format!("{TEST_DATA}\n東京;12.3\n{TEST_DATA}東京;-4.5\n").repeat(20);
+        let config = Config {
+            track_extents: Setting::new(true, Source::Cli),
+            ..config()
+        };
+        // The sums are added in whatever order the parsers get to them, so the means can be a
+        // hair apart, but nothing else can be
+        let exact = |stations: &[StationInfo]| -> Vec<_> {
+            stations
+                .iter()
+                .map(|s| {
+                    let stats = (s.min(), s.max(), s.count());
+                    (s.name().to_owned(), stats, s.extents())
+                })
+                .collect()
+        };
+        let baseline = config.with_runner(Kind::Baseline, Source::Cli);
+        let (expected, _) = crate::runners::run_with(io::Cursor::new(&input), &baseline)?;
+
+        for block_size in [1, 2, 3, 7, 16, 64] {
+            for workers in [1, 3, 8] {
+                let what = format!("{block_size} bytes, {workers} workers");
+                let mut pipeline = Pipeline::new(block_size, workers, &config);
+                let partials = match pipeline.run(&mut io::Cursor::new(&input), &config)? {
+                    Ok(partials) => partials,
+                    Err(failure) => panic!("{}", failure.error),
+                };
+                let (actual, _) = finish(partials, Instant::now(), &config)?;
+                assert_eq!(exact(&actual), exact(&expected), "{what}");
+                for (actual, expected) in actual.iter().zip(&expected) {
+                    assert!((actual.avg() - expected.avg()).abs() < 1e-3, "{what}");
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// The buffers are allocated once & come back to the pipeline where they started, apart from
+    /// the one the end of the input was read into
+    #[test]
+    fn buffers_round_trip() -> Result<(), Box<dyn error::Error>> {
+        let config = config();
+        let mut pipeline = Pipeline::new(16, 3, &config);
+        let mut before: Vec<*const u8> = pipeline.buffers.iter().map(|b| b.as_ptr()).collect();
+        before.sort_unstable();
+
+        let input = TEST_DATA.repeat(10);
+        let _ = pipeline.run(&mut io::Cursor::new(&input), &config)?;
+        let after: Vec<*const u8> = pipeline.buffers.iter().map(|b| b.as_ptr()).collect();
+        assert_eq!(after.len(), Runner::BUFFERS - 1);
+        assert!(after.iter().all(|ptr| before.binary_search(ptr).is_ok()));
+
+        Ok(())
+    }
+
+    /// A parser giving up on a malformed line stops the reader, & the error is located from the
+    /// start of the input
+    #[test]
+    fn malformed_line() {
+        let malformed = format!("{TEST_DATA}{TEST_DATA}Nowhere\n{TEST_DATA}");
+        let baseline = Config::default().with_runner(Kind::Baseline, Source::Cli);
+        let expected = crate::runners::run_with(io::Cursor::new(&malformed), &baseline)
+            .expect_err("The baseline runner accepted a malformed line");
+
+        let config = config();
+        for block_size in [16, 1 << 10] {
+            let mut input = io::Cursor::new(&malformed);
+            let mut pipeline = Pipeline::new(block_size, 3, &config);
+            let failure = pipeline
+                .run(&mut input, &config)
+                .expect("Reading from memory can't fail")
+                .err()
+                .expect("A malformed line was accepted");
+            assert_eq!(failure.locate(&mut input).to_string(), expected.to_string());
+        }
+    }
+}
@@ -0,0 +1,417 @@
+// 1BRC - my take on the 1 Billion Row Challenge
+// Copyright (C) 2024  Charles German <5donuts@pm.me>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Use the same approach as `ahash`, but read the input around the page cache with `O_DIRECT`, into
+//! buffers aligned to the disk's blocks. Only built with the `direct-io` feature, on Linux.
+//!
+//! Reads of a file opened with `O_DIRECT` go straight from the disk into the buffer they're given,
+//! which has to start at a multiple of the disk's logical block size, as do the offset & length of
+//! each read. Everything is read in whole blocks of [`Runner::ALIGN`] bytes into an
+//! [`AlignedBuf`], apart from the tail of a file whose length isn't a multiple of them, which is
+//! read through a second, ordinary handle. The blocks are then read as lines the same way as the
+//! [`ahash`](super::AHash) runner's reads, which stitches together the lines split between them,
+//! so comparing the two shows what the page cache costs (or saves).
+
+use std::alloc::{self, Layout};
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read};
+use std::ops::{Deref, DerefMut};
+use std::os::unix::fs::{FileExt, OpenOptionsExt};
+use std::path::Path;
+use std::ptr::NonNull;
+use std::time::Instant;
+
+use ahash::RandomState;
+
+use crate::aggregate::Aggregator;
+use crate::config::Config;
+use crate::error::ChallengeError;
+use crate::helpers::*;
+use crate::reader::LineReader;
+
+pub struct Runner;
+
+impl Runner {
+    /// What the buffer, offset & length of each direct read are aligned to. 4 KiB is a multiple
+    /// of the logical block size of any disk in common use.
+    pub const ALIGN: usize = 4096;
+
+    /// Read the file at `path` with `O_DIRECT`, in blocks of the buffer size rounded up to a
+    /// multiple of [`Runner::ALIGN`].
+    pub fn run_file(path: &Path, config: &Config) -> ChallengeResult {
+        let start = Instant::now();
+        // Opened first, so a missing input is reported as such rather than as O_DIRECT failing
+        let buffered = File::open(path)?;
+        let len = buffered.metadata()?.len();
+        let direct = OpenOptions::new()
+            .read(true)
+            .custom_flags(libc::O_DIRECT)
+            .open(path)
+            .map_err(|source| ChallengeError::Unsupported {
+                what: "opening the input with O_DIRECT",
+                source,
+            })?;
+
+        let reader = DirectReader::new(direct, buffered, len, config.buffer_size.value);
+        aggregate(reader, start, config)
+    }
+}
+
+impl ChallengeRunner for Runner {
+    /// Only a file can be opened with `O_DIRECT`, so any other input (e.g. a `Cursor` in tests) is
+    /// read the same way as the `ahash` runner reads it instead; see [`Runner::run_file`].
+    fn run<R>(input: R, config: &Config) -> ChallengeResult
+    where
+        R: std::io::Read + std::io::Seek,
+    {
+        aggregate(input, Instant::now(), config)
+    }
+}
+
+fn aggregate<R: Read>(input: R, start: Instant, config: &Config) -> ChallengeResult {
+    let mut aggregator: Aggregator<RandomState> =
+        Aggregator::with_known_stations(config.known_stations.clone())
+            .strict(config.strict.value)
+            .dialect(config.dialect())
+            .track_extents(config.track_extents.value)
+            .case_insensitive(config.case_insensitive.value)
+            .max_skipped(config.max_skipped.value)
+            .reuse_results(config.take_results_buffer());
+    let mut lines = LineReader::new(input, config);
+    loop {
+        match lines.next_line() {
+            Ok(Some(line)) => aggregator.ingest_line(line)?,
+            Ok(None) => break,
+            Err(e) => aggregator.skip_unreadable(e)?,
+        }
+    }
+
+    let aggregated = Instant::now();
+    let ignored = aggregator.ignored_non_finite();
+    let skipped = aggregator.skipped();
+    let stations = aggregator.into_sorted();
+
+    let stats = RunStats::new(Timings::since(start, aggregated, config))
+        .ignored_non_finite(ignored)
+        .skipped(skipped);
+
+    Ok((stations, stats))
+}
+
+/// A zeroed buffer whose start & length are both multiples of [`Runner::ALIGN`]
+struct AlignedBuf {
+    ptr: NonNull<u8>,
+    len: usize,
+}
+
+impl AlignedBuf {
+    /// A buffer of at least `len` bytes, rounded up to a whole number (& at least one) of
+    /// [`Runner::ALIGN`]-byte blocks
+    fn new(len: usize) -> Self {
+        let len = len.max(1).next_multiple_of(Runner::ALIGN);
+        let layout = Self::layout(len);
+        // SAFETY: the layout's size is never zero
+        let ptr = unsafe { alloc::alloc_zeroed(layout) };
+        let ptr = NonNull::new(ptr).unwrap_or_else(|| alloc::handle_alloc_error(layout));
+        Self { ptr, len }
+    }
+
+    fn layout(len: usize) -> Layout {
+        Layout::from_size_align(len, Runner::ALIGN).expect("The buffer fits in memory")
+    }
+}
+
+impl Deref for AlignedBuf {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        // SAFETY: the buffer is `len` bytes long, initialized (to zero) when it was allocated, &
+        // only freed when it's dropped
+        unsafe { std::slice::from_raw_parts(self.ptr.as_ptr(), self.len) }
+    }
+}
+
+impl DerefMut for AlignedBuf {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        // SAFETY: as for `deref`, & the buffer is borrowed mutably through `self`
+        unsafe { std::slice::from_raw_parts_mut(self.ptr.as_ptr(), self.len) }
+    }
+}
+
+impl Drop for AlignedBuf {
+    fn drop(&mut self) {
+        // SAFETY: the buffer was allocated in `new` with the same layout
+        unsafe { alloc::dealloc(self.ptr.as_ptr(), Self::layout(self.len)) }
+    }
+}
+
+/// Something which can be read at a given offset without moving a cursor, like a [`File`]
+trait ReadAt {
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> io::Result<usize>;
+}
+
+impl ReadAt for File {
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+        FileExt::read_at(self, buf, offset)
+    }
+}
+
+/// Fill the whole of `buf` from `offset` in `source`, picking up after any short reads
+fn read_exact_at<S: ReadAt>(source: &S, buf: &mut [u8], offset: u64) -> io::Result<()> {
+    let mut done = 0;
+    while done < buf.len() {
+        match source.read_at(&mut buf[done..], offset + done as u64) {
+            Ok(0) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    "the input got shorter while it was being read",
+                ))
+            }
+            Ok(n) => done += n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(())
+}
+
+/// Reads an input of `len` bytes in blocks of whole [`Runner::ALIGN`]s from `direct`, which is
+/// only ever read at aligned offsets into aligned buffers, & any tail shorter than that from
+/// `buffered`.
+struct DirectReader<D, B> {
+    direct: D,
+    buffered: B,
+    len: u64,
+    buf: AlignedBuf,
+
+    /// Where the next block starts in the input
+    offset: u64,
+
+    /// How much of the buffer the current block fills
+    filled: usize,
+
+    /// How far into the current block has been consumed
+    pos: usize,
+}
+
+impl<D: ReadAt, B: ReadAt> DirectReader<D, B> {
+    fn new(direct: D, buffered: B, len: u64, block_size: usize) -> Self {
+        Self {
+            direct,
+            buffered,
+            len,
+            buf: AlignedBuf::new(block_size),
+            offset: 0,
+            filled: 0,
+            pos: 0,
+        }
+    }
+
+    /// Read the next block into the buffer: as many whole aligned blocks as fit, or else the tail
+    fn fill(&mut self) -> io::Result<()> {
+        let want = (self.len - self.offset).min(self.buf.len() as u64) as usize;
+        let whole = want - want % Runner::ALIGN;
+        if whole > 0 {
+            read_exact_at(&self.direct, &mut self.buf[..whole], self.offset)?;
+            self.filled = whole;
+        } else {
+            // Fewer than `ALIGN` bytes are left, which can't be read directly on their own
+            read_exact_at(&self.buffered, &mut self.buf[..want], self.offset)?;
+            self.filled = want;
+        }
+        self.offset += self.filled as u64;
+        self.pos = 0;
+        Ok(())
+    }
+}
+
+impl<D: ReadAt, B: ReadAt> Read for DirectReader<D, B> {
+    fn read(&mut self, out: &mut [u8]) -> io::Result<usize> {
+        if self.pos == self.filled {
+            if self.offset == self.len || out.is_empty() {
+                return Ok(0);
+            }
+            self.fill()?;
+        }
+        let n = out.len().min(self.filled - self.pos);
+        out[..n].copy_from_slice(&self.buf[self.pos..self.pos + n]);
+        self.pos += n;
+        Ok(n)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{Setting, Source};
+    use crate::runners::tests::*;
+    use std::cell::Cell;
+    use std::error;
+
+    const ALIGN: usize = Runner::ALIGN;
+
+    /// An in-memory input which checks every read is one `O_DIRECT` would allow, & counts the
+    /// bytes read
+    struct Reference<'a> {
+        data: &'a [u8],
+        aligned: bool,
+        read: Cell<usize>,
+    }
+
+    impl<'a> Reference<'a> {
+        fn new(data: &'a [u8], aligned: bool) -> Self {
+            Self {
+                data,
+                aligned,
+                read: Cell::new(0),
+            }
+        }
+    }
+
+    impl ReadAt for Reference<'_> {
+        fn read_at(&self, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+            if self.aligned {
+                assert_eq!(offset as usize % ALIGN, 0, "unaligned offset {offset}");
+                assert_eq!(buf.len() % ALIGN, 0, "unaligned length {}", buf.len());
+                assert_eq!(buf.as_ptr() as usize % ALIGN, 0, "unaligned buffer");
+            }
+            let rest = self.data.get(offset as usize..).unwrap_or_default();
+            let n = buf.len().min(rest.len());
+            buf[..n].copy_from_slice(&rest[..n]);
+            self.read.set(self.read.get() + n);
+            Ok(n)
+        }
+    }
+
+    /// Read `data` back through a [`DirectReader`] with blocks of `block_size`, `chunk` bytes at
+    /// a time, returning what was read & how much of it came through the buffered handle
+    fn read_back(data: &[u8], block_size: usize, chunk: usize) -> io::Result<(Vec<u8>, usize)> {
+        let (direct, buffered) = (Reference::new(data, true), Reference::new(data, false));
+        let mut reader = DirectReader::new(&direct, &buffered, data.len() as u64, block_size);
+        let mut read = Vec::new();
+        let mut buf = vec![0; chunk];
+        loop {
+            match reader.read(&mut buf)? {
+                0 => return Ok((read, buffered.read.get())),
+                n => read.extend_from_slice(&buf[..n]),
+            }
+        }
+    }
+
+    impl<S: ReadAt> ReadAt for &S {
+        fn read_at(&self, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+            (*self).read_at(buf, offset)
+        }
+    }
+
+    #[test]
+    fn aligned_buf() {
+        for (len, rounded) in [
+            (0, ALIGN),
+            (1, ALIGN),
+            (ALIGN, ALIGN),
+            (ALIGN + 1, 2 * ALIGN),
+        ] {
+            let mut buf = AlignedBuf::new(len);
+            assert_eq!(buf.len(), rounded);
+            assert_eq!(buf.as_ptr() as usize % ALIGN, 0);
+            assert!(buf.iter().all(|&b| b == 0));
+            buf[rounded - 1] = 1;
+            assert_eq!(buf[rounded - 1], 1);
+        }
+    }
+
+    /// Only a tail shorter than a block is read without `O_DIRECT`, whatever the sizes
+    #[test]
+    fn tail() -> io::Result<()> {
+        let data: Vec<u8> = (0..5 * ALIGN + 100).map(|i| (i % 251) as u8).collect();
+        for len in [
+            0,
+            1,
+            ALIGN - 1,
+            ALIGN,
+            ALIGN + 1,
+            3 * ALIGN,
+            5 * ALIGN + 100,
+        ] {
+            for block_size in [1, ALIGN, 2 * ALIGN, 3 * ALIGN - 5, 16 * ALIGN] {
+                for chunk in [7, ALIGN, 1 << 20] {
+                    let (read, buffered) = read_back(&data[..len], block_size, chunk)?;
+                    assert_eq!(read, &data[..len], "{len} bytes in blocks of {block_size}");
+                    assert_eq!(
+                        buffered,
+                        len % ALIGN,
+                        "{len} bytes in blocks of {block_size}"
+                    );
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn shrinking_input() {
+        let data = vec![b'x'; 2 * ALIGN];
+        let (direct, buffered) = (Reference::new(&data, true), Reference::new(&data, false));
+        let mut reader = DirectReader::new(&direct, &buffered, 3 * ALIGN as u64, ALIGN);
+        let err = io::copy(&mut reader, &mut io::sink()).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn correctness() -> Result<(), Box<dyn error::Error>> {
+        let input = io::Cursor::new(TEST_DATA.as_bytes());
+
+        let (actual, _) = Runner::run(input, &Config::default())?;
+        assert_eq!(
+            actual, *EXPECTED_RESULT,
+            "actual != expected for direct-io runner"
+        );
+
+        Ok(())
+    }
+
+    /// A file read directly, in blocks which split lines & with a tail, gives the same results
+    /// as reading it through the page cache. Filesystems which don't support `O_DIRECT` (like
+    /// tmpfs) can't run this.
+    #[test]
+    fn run_file() -> Result<(), Box<dyn error::Error>> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("measurements.txt");
+        let data = TEST_DATA.repeat(200);
+        assert_ne!(data.len() % ALIGN, 0);
+        std::fs::write(&path, &data)?;
+
+        let (expected, _) = Runner::run(io::Cursor::new(data.as_bytes()), &Config::default())?;
+        for buffer_size in [1, ALIGN, 3 * ALIGN] {
+            let config = Config {
+                buffer_size: Setting::new(buffer_size, Source::Cli),
+                ..Config::default()
+            };
+            match Runner::run_file(&path, &config) {
+                Ok((actual, _)) => {
+                    assert_eq!(bits(&actual), bits(&expected), "buffer size {buffer_size}")
+                }
+                Err(e) => match e.downcast_ref::<ChallengeError>() {
+                    Some(ChallengeError::Unsupported { .. }) => return Ok(()),
+                    _ => return Err(e),
+                },
+            }
+        }
+
+        Ok(())
+    }
+}
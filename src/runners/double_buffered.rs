@@ -0,0 +1,300 @@
+// 1BRC - my take on the 1 Billion Row Challenge
+// Copyright (C) 2024  Charles German <5donuts@pm.me>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Read the input into one of two buffers on the calling thread while a single parser thread works
+//! through the other, swapping them once both are done, so reading & parsing overlap with only one
+//! extra thread & two allocations.
+//!
+//! A serial runner leaves the CPU idle while it waits on a read, & the disk idle while it
+//! parses. Here there are only ever [`Runner::BUFFERS`] buffers: the calling thread reads into
+//! one while a single parser thread works through the other, & once the parser's done it hands
+//! its buffer back to be read into next. The tail of a line cut off at the end of a buffer is
+//! carried over to the start of the next by the [`BlockReader`], so the parser only ever sees
+//! whole lines, & the last buffer is as short as what's left of the input.
+
+use std::collections::HashMap;
+use std::io::Read;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::time::Instant;
+
+use ahash::RandomState;
+
+use crate::aggregate::{merge_case_variants_by_row, StationData};
+use crate::blocks::{Block, BlockFailure, BlockReader};
+use crate::config::Config;
+use crate::error::{ChallengeError, SkippedLines};
+use crate::helpers::*;
+
+pub struct Runner;
+
+impl Runner {
+    /// How many buffers are passed back & forth between the reader & the parser
+    pub const BUFFERS: usize = 2;
+}
+
+/// What was aggregated from the input
+struct Parsed {
+    stations: HashMap<String, StationData, RandomState>,
+
+    /// Malformed lines skipped by the reader & the parser
+    skipped: SkippedLines,
+}
+
+impl ChallengeRunner for Runner {
+    /// Read the input in blocks of `--buffer-size` bytes on the calling thread, parsing each on
+    /// a thread of its own while the next is read into the other buffer.
+    fn run<R>(mut input: R, config: &Config) -> ChallengeResult
+    where
+        R: std::io::Read + std::io::Seek,
+    {
+        let start = Instant::now();
+        match overlap(&mut input, config)? {
+            Ok(parsed) => finish(parsed, start, config),
+            Err(failure) => Err(failure.locate(&mut input).into()),
+        }
+    }
+}
+
+/// Read `input` into one buffer while the other is parsed, swapping them once both are done.
+///
+/// As with the `pipelined` runner, the inner `Err` is the first line which couldn't be skipped,
+/// for the caller to [locate](BlockFailure::locate).
+fn overlap<R: Read>(
+    input: &mut R,
+    config: &Config,
+) -> Result<Result<Parsed, BlockFailure>, ChallengeError> {
+    let max_skipped = config.max_skipped.value;
+    let mut reader = BlockReader::new(input, config).count_lines(config.track_rows());
+    let mut skipped = SkippedLines::default();
+
+    let (filled, to_parse) = mpsc::sync_channel(1);
+    let (emptied, to_fill) = mpsc::channel();
+
+    let (failure, parsed) = std::thread::scope(|s| {
+        let parser = s.spawn(move || parse_blocks(to_parse, emptied, config));
+
+        // Neither buffer has been allocated yet
+        let mut unallocated = Runner::BUFFERS;
+        let mut failure = None;
+        loop {
+            let buffer = if unallocated > 0 {
+                unallocated -= 1;
+                Vec::new()
+            } else {
+                match to_fill.recv() {
+                    Ok(buffer) => buffer,
+                    // The parser gave up at a malformed line, so there's no point reading on
+                    Err(_) => break,
+                }
+            };
+            let block = match reader.next_block_into(buffer) {
+                Ok(Some(block)) => block,
+                Ok(None) => break,
+                // Lines too long to fit in a buffer are skipped here, like any other malformed
+                // line; the buffer went with the error, so another takes its place
+                Err(e) => {
+                    unallocated += 1;
+                    let offset = e.offset().unwrap_or_default();
+                    match skipped.skip(e, 1, max_skipped) {
+                        Ok(()) => continue,
+                        Err(error) => {
+                            failure = Some(BlockFailure {
+                                block_offset: offset,
+                                offset,
+                                error,
+                            });
+                            break;
+                        }
+                    }
+                }
+            };
+            if filled.send(block).is_err() {
+                break;
+            }
+        }
+        drop(filled);
+
+        (failure, parser.join().expect("Parser thread panicked"))
+    });
+
+    // The parser is behind the reader, so any line it gave up at comes first
+    Ok(match (parsed, failure) {
+        (Err(failure), _) | (Ok(_), Some(failure)) => Err(failure),
+        (Ok(mut parsed), None) => {
+            parsed.skipped.merge(&skipped);
+            Ok(parsed)
+        }
+    })
+}
+
+/// Aggregate every line of the blocks read, handing each block's buffer back to be read into
+/// again once it's parsed, until the reader is done or a line can't be skipped
+fn parse_blocks(
+    blocks: Receiver<Block>,
+    emptied: Sender<Vec<u8>>,
+    config: &Config,
+) -> Result<Parsed, BlockFailure> {
+    let strict = config.strict.value;
+    let known = config
+        .known_stations
+        .as_ref()
+        .map_or(0, |names| names.len());
+    let mut parsed = Parsed {
+        stations: HashMap::with_capacity_and_hasher(known, RandomState::new()),
+        skipped: SkippedLines::default(),
+    };
+    for block in blocks {
+        let stations = &mut parsed.stations;
+        block.for_each_record(
+            config.max_line_length.value,
+            strict,
+            config.dialect(),
+            config.max_skipped.value,
+            &mut parsed.skipped,
+            |station, measurement, line| {
+                if !stations.contains_key(station) {
+                    stations.insert(station.to_owned(), StationData::empty());
+                }
+                let data = stations
+                    .get_mut(station)
+                    .expect("The station was just added");
+                data.push(measurement, strict);
+                if let Some(first_line) = block.first_line {
+                    data.record_row(first_line + line - 1);
+                }
+            },
+        )?;
+        // The reader's finished if nothing's waiting for the buffer
+        let _ = emptied.send(block.data);
+    }
+    Ok(parsed)
+}
+
+/// Build the sorted list of stations
+fn finish(parsed: Parsed, start: Instant, config: &Config) -> ChallengeResult {
+    // The reader & the parser only knew about the lines each of them skipped
+    parsed.skipped.check(config.max_skipped.value)?;
+
+    let aggregated = Instant::now();
+    let ignored = parsed
+        .stations
+        .values()
+        .map(|data| data.skipped as u64)
+        .sum();
+
+    let named: Vec<(String, StationData)> = if config.case_insensitive.value {
+        merge_case_variants_by_row(parsed.stations, config.track_extents.value)
+    } else {
+        parsed.stations.into_iter().collect()
+    };
+
+    // Build the alphabetically-sorted list of stations
+    let mut stations: Vec<StationInfo> = named
+        .into_iter()
+        .filter(|(_, data)| data.cnt > 0)
+        .map(|(name, data)| {
+            StationInfo::new(name, data.min, data.max, data.avg(), data.cnt)
+                .with_extents(data.extents())
+        })
+        .collect();
+    stations.sort_unstable();
+
+    let stats = RunStats::new(Timings::since(start, aggregated, config))
+        .ignored_non_finite(ignored)
+        .skipped(parsed.skipped);
+
+    Ok((stations, stats))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{Setting, Source};
+    use crate::runners::tests::*;
+    use crate::Runner as Kind;
+    use std::{error, io};
+
+    fn config(buffer_size: usize) -> Config {
+        Config {
+            buffer_size: Setting::new(buffer_size, Source::Cli),
+            ..Config::default().with_runner(Kind::DoubleBuffered, Source::Cli)
+        }
+    }
+
+    /// The results of the `baseline` runner for `input`
+    fn expected(input: &str) -> Result<Vec<StationInfo>, Box<dyn error::Error>> {
+        let baseline = Config::default().with_runner(Kind::Baseline, Source::Cli);
+        let (expected, _) = crate::runners::run_with(io::Cursor::new(input), &baseline)?;
+        Ok(expected)
+    }
+
+    #[test]
+    fn correctness() -> Result<(), Box<dyn error::Error>> {
+        let input = io::Cursor::new(TEST_DATA.as_bytes());
+
+        let (actual, _) = Runner::run(input, &Config::default())?;
+        assert_eq!(
+            actual, *EXPECTED_RESULT,
+            "actual != expected for double-buffered runner"
+        );
+
+        Ok(())
+    }
+
+    /// At these sizes the buffers are swapped many times over, with the swaps landing in the
+    /// middle of lines, & of the multi-byte characters in some names
+    #[test]
+    fn swap_mid_line() -> Result<(), Box<dyn error::Error>> {
+        let input = format!("{TEST_DATA}東京;12.3\nAïn el Mediour;-1.0\n東京;-4.5");
+        let expected = expected(&input)?;
+        assert!(expected.iter().any(|station| station.name() == "東京"));
+
+        for buffer_size in 1..=48 {
+            let (actual, _) = Runner::run(io::Cursor::new(&input), &config(buffer_size))?;
+            assert_eq!(bits(&actual), bits(&expected), "buffer size {buffer_size}");
+        }
+
+        Ok(())
+    }
+
+    /// An input which fits in the first buffer is read in one short block, with or without a
+    /// newline at the end
+    #[test]
+    fn smaller_than_buffer() -> Result<(), Box<dyn error::Error>> {
+        for input in [TEST_DATA, TEST_DATA.trim_end(), "Hamburg;12.0", ""] {
+            let (actual, _) = Runner::run(io::Cursor::new(input), &config(1 << 20))?;
+            assert_eq!(bits(&actual), bits(&expected(input)?), "{input:?}");
+        }
+
+        Ok(())
+    }
+
+    /// A line the parser can't skip is reported with its number in the whole input, however
+    /// many buffers were read before it
+    #[test]
+    fn malformed_line() {
+        let input = format!("{TEST_DATA}Hamburg;warm\n{TEST_DATA}");
+        for buffer_size in [7, 64, 1 << 20] {
+            let err = Runner::run(io::Cursor::new(&input), &config(buffer_size)).unwrap_err();
+            assert!(
+                matches!(
+                    err.downcast_ref(),
+                    Some(ChallengeError::MalformedLine { line: 12, .. })
+                ),
+                "buffer size {buffer_size}: {err}"
+            );
+        }
+    }
+}
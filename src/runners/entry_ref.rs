@@ -0,0 +1,141 @@
+// 1BRC - my take on the 1 Billion Row Challenge
+// Copyright (C) 2024  Charles German <5donuts@pm.me>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Use the same approach as `rustc-hash`, but with the map from the `hashbrown` crate, looked up
+//! with its `entry_ref` API so each line's station is hashed once even when it's new, and its name
+//! only copied into a `String` the first time it's seen.
+//!
+//! Looking a station up with `get_mut` & then inserting it with `insert(station.to_owned(), ..)`
+//! hashes the name twice whenever it's new. `hashbrown`'s `entry_ref` API finds the entry (or
+//! where it would go) with one hash of the borrowed name, and only copies it into a `String` if
+//! the entry is vacant. Everything else is as in the [`rustc-hash`](super::RustcHash) runner:
+//! the same hasher, reading lines through the same [`LineReader`] & parsing them the same way,
+//! so a benchmark of the two only measures the lookups.
+
+use std::time::Instant;
+
+use hashbrown::HashMap;
+use rustc_hash::FxBuildHasher;
+
+use crate::aggregate::{merge_case_variants_by_row, parse_record, StationData};
+use crate::config::Config;
+use crate::error::SkippedLines;
+use crate::helpers::*;
+use crate::reader::LineReader;
+
+pub struct Runner;
+
+impl ChallengeRunner for Runner {
+    fn run<R>(input: R, config: &Config) -> ChallengeResult
+    where
+        R: std::io::Read + std::io::Seek,
+    {
+        let start = Instant::now();
+
+        let strict = config.strict.value;
+        let max_skipped = config.max_skipped.value;
+        let dialect = config.dialect();
+        let track_rows = config.track_rows();
+
+        let known = config
+            .known_stations
+            .as_ref()
+            .map_or(0, |names| names.len());
+        let mut stations: HashMap<String, StationData, FxBuildHasher> =
+            HashMap::with_capacity_and_hasher(known, FxBuildHasher);
+        let mut skipped = SkippedLines::default();
+
+        let mut lines = LineReader::new(input, config);
+        let mut line_number = 0;
+        loop {
+            let line = match lines.next_line() {
+                Ok(Some(line)) => line,
+                Ok(None) => break,
+                Err(e) => {
+                    line_number += 1;
+                    skipped.skip(e, 1, max_skipped)?;
+                    continue;
+                }
+            };
+            line_number += 1;
+
+            let (station, measurement) = match parse_record(line, line_number, strict, dialect) {
+                Ok(Some(record)) => record,
+                Ok(None) => continue,
+                Err(e) => {
+                    skipped.skip(e, 1, max_skipped)?;
+                    continue;
+                }
+            };
+
+            // One hash, whether or not the station's been seen before; the name's only copied
+            // if it hasn't
+            let data = stations
+                .entry_ref(station)
+                .or_insert_with(StationData::empty);
+            data.push(measurement, strict);
+            if track_rows {
+                data.record_row(line_number);
+            }
+        }
+
+        let aggregated = Instant::now();
+        let ignored = stations.values().map(|data| data.skipped as u64).sum();
+
+        let named = stations.into_iter();
+        let named: Vec<(String, StationData)> = if config.case_insensitive.value {
+            merge_case_variants_by_row(named, config.track_extents.value)
+        } else {
+            named.collect()
+        };
+
+        // Build the alphabetically-sorted list of stations
+        let mut stations: Vec<StationInfo> = named
+            .into_iter()
+            .filter(|(_, data)| data.cnt > 0)
+            .map(|(name, data)| {
+                StationInfo::new(name, data.min, data.max, data.avg(), data.cnt)
+                    .with_extents(data.extents())
+            })
+            .collect();
+        stations.sort_unstable();
+
+        let stats = RunStats::new(Timings::since(start, aggregated, config))
+            .ignored_non_finite(ignored)
+            .skipped(skipped);
+
+        Ok((stations, stats))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::runners::tests::*;
+    use std::{error, io};
+
+    #[test]
+    fn correctness() -> Result<(), Box<dyn error::Error>> {
+        let input = io::Cursor::new(TEST_DATA.as_bytes());
+
+        let (actual, _) = Runner::run(input, &Config::default())?;
+        assert_eq!(
+            actual, *EXPECTED_RESULT,
+            "actual != expected for entry-ref runner"
+        );
+
+        Ok(())
+    }
+}
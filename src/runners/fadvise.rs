@@ -0,0 +1,185 @@
+// 1BRC - my take on the 1 Billion Row Challenge
+// Copyright (C) 2024  Charles German <5donuts@pm.me>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Use the same approach as `ahash`, but advise the kernel the input will be read sequentially
+//! (with `posix_fadvise`) & drop the pages already read from the page cache as it goes, so a file
+//! bigger than RAM doesn't thrash the cache. The hints are only given on Linux; elsewhere it's the
+//! same as `ahash`.
+//!
+//! Reading a file bigger than RAM through the page cache pushes out pages which will be needed
+//! again (& eventually the input's own pages which haven't been read yet). This runner reads the
+//! input exactly like the [`ahash`](super::AHash) runner, but first advises the kernel it'll be
+//! read sequentially, so it reads further ahead, & then drops the pages it has read from the
+//! cache every [`DROP_BEHIND`] bytes. On platforms without the hints, it's the `ahash` runner.
+
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::Path;
+
+use ahash::RandomState;
+
+use crate::config::Config;
+use crate::helpers::*;
+use crate::platform::{advise_sequential, drop_cached};
+use crate::window::Window;
+
+use super::baseline::run_hashed;
+
+/// How far the reads get past the last pages dropped before the pages in between are dropped
+pub const DROP_BEHIND: u64 = 16 * 1024 * 1024;
+
+pub struct Runner;
+
+impl Runner {
+    /// Solve the file at `path`, with hints about how it's read (see the [module](self) docs)
+    pub fn run_file(path: &Path, config: &Config) -> ChallengeResult {
+        let file = File::open(path)?;
+        // They're only hints, so the run goes ahead without them
+        if let Err(e) = advise_sequential(&file) {
+            if config.verbose > 0 {
+                eprintln!("Unable to advise sequential reads of the input: {e}");
+            }
+        }
+
+        let input = DropBehind::new(file, DROP_BEHIND);
+        match &config.byte_range {
+            Some(window) => {
+                run_hashed::<RandomState, _>(Window::new(input, window.clone())?, config, 0)
+            }
+            None => run_hashed::<RandomState, _>(input, config, 0),
+        }
+    }
+}
+
+impl ChallengeRunner for Runner {
+    /// Any input other than a file is read like the `ahash` runner would, with no hints; see
+    /// [`Runner::run_file`].
+    fn run<R>(input: R, config: &Config) -> ChallengeResult
+    where
+        R: io::Read + io::Seek,
+    {
+        run_hashed::<RandomState, R>(input, config, 0)
+    }
+}
+
+/// A file whose pages are dropped from the page cache once they've been read past
+struct DropBehind {
+    file: File,
+
+    /// How many bytes to read past the pages last dropped before dropping the rest
+    interval: u64,
+
+    /// Where the next read starts
+    pos: u64,
+
+    /// Where the pages which haven't been dropped start
+    dropped: u64,
+}
+
+impl DropBehind {
+    fn new(file: File, interval: u64) -> Self {
+        Self {
+            file,
+            interval,
+            pos: 0,
+            dropped: 0,
+        }
+    }
+}
+
+impl Read for DropBehind {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.file.read(buf)?;
+        self.pos += n as u64;
+        if self.pos - self.dropped >= self.interval {
+            // Dropping pages is only a hint, so failing to doesn't fail the read
+            let _ = drop_cached(&self.file, self.dropped..self.pos);
+            self.dropped = self.pos;
+        }
+        Ok(n)
+    }
+}
+
+impl Seek for DropBehind {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.pos = self.file.seek(pos)?;
+        // Pages skipped over were never read, so there's nothing behind them to drop
+        self.dropped = self.pos;
+        Ok(self.pos)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Source;
+    use crate::runners::tests::*;
+    use crate::Runner as Kind;
+    use std::error;
+
+    #[test]
+    fn correctness() -> Result<(), Box<dyn error::Error>> {
+        let input = io::Cursor::new(TEST_DATA.as_bytes());
+
+        let (actual, _) = Runner::run(input, &Config::default())?;
+        assert_eq!(
+            actual, *EXPECTED_RESULT,
+            "actual != expected for fadvise runner"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn run_file() -> Result<(), Box<dyn error::Error>> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("measurements.txt");
+        std::fs::write(&path, TEST_DATA)?;
+        let config = Config::default().with_runner(Kind::Fadvise, Source::Cli);
+
+        let (actual, _) = Runner::run_file(&path, &config)?;
+        assert_eq!(actual, *EXPECTED_RESULT);
+
+        Ok(())
+    }
+
+    /// Dropping the pages behind the reads (here every few bytes) doesn't change what's read
+    #[test]
+    fn drop_behind() -> io::Result<()> {
+        let mut file = tempfile::tempfile()?;
+        std::io::Write::write_all(&mut file, TEST_DATA.as_bytes())?;
+        file.rewind()?;
+
+        let mut input = DropBehind::new(file, 7);
+        let mut buf = [0; 5];
+        let mut read = Vec::new();
+        loop {
+            match input.read(&mut buf)? {
+                0 => break,
+                n => read.extend_from_slice(&buf[..n]),
+            }
+        }
+        assert_eq!(read, TEST_DATA.as_bytes());
+        assert!(input.dropped > 0 && input.pos - input.dropped < 7);
+
+        assert_eq!(input.seek(SeekFrom::Start(13))?, 13);
+        assert_eq!(input.dropped, 13);
+        let mut rest = String::new();
+        input.read_to_string(&mut rest)?;
+        assert_eq!(rest, TEST_DATA[13..]);
+
+        Ok(())
+    }
+}
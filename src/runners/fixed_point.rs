@@ -0,0 +1,267 @@
+// 1BRC - my take on the 1 Billion Row Challenge
+// Copyright (C) 2024  Charles German <5donuts@pm.me>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Use the same approach as `ahash`, but parse each measurement straight into a whole number of
+//! tenths of a degree & keep exact integer sums, only converting back to floats at the end.
+
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+use std::time::Instant;
+
+use ahash::RandomState;
+
+use crate::aggregate::{fold_case, parse_record, parse_tenths, Extents, KeyFormat, OUT_OF_RANGE};
+use crate::config::Config;
+use crate::error::{ChallengeError, SkippedLines};
+use crate::helpers::*;
+use crate::reader::LineReader;
+
+pub struct Runner;
+
+/// Running min/max/sum data for a station, in whole tenths of a degree.
+///
+/// Every measurement in the challenge has exactly one decimal, so these are exact: the sum
+/// doesn't drift however many measurements there are, or whatever order they're added in.
+#[derive(Debug, Clone, Copy)]
+struct FixedData {
+    min: i16,
+    max: i16,
+    sum: i64,
+    cnt: u32,
+
+    /// The first & last rows the station was on, if counting rows; `u64::MAX` & `0` until then
+    first_row: u64,
+    last_row: u64,
+}
+
+impl FixedData {
+    fn empty() -> Self {
+        Self {
+            min: i16::MAX,
+            max: i16::MIN,
+            sum: 0,
+            cnt: 0,
+            first_row: u64::MAX,
+            last_row: 0,
+        }
+    }
+
+    #[inline]
+    fn push(&mut self, tenths: i16) {
+        self.min = self.min.min(tenths);
+        self.max = self.max.max(tenths);
+        self.sum += tenths as i64;
+        self.cnt += 1;
+    }
+
+    fn merge(&mut self, other: &Self) {
+        self.min = self.min.min(other.min);
+        self.max = self.max.max(other.max);
+        self.sum += other.sum;
+        self.cnt += other.cnt;
+        self.first_row = self.first_row.min(other.first_row);
+        self.last_row = self.last_row.max(other.last_row);
+    }
+
+    /// Convert back to degrees, only now that every measurement has been added up
+    fn info(&self, name: String, track_extents: bool) -> StationInfo {
+        let degrees = |tenths: i16| tenths as f32 / 10.0;
+        let avg = (self.sum as f64 / self.cnt as f64 / 10.0) as f32;
+        let extents = (track_extents && self.last_row > 0).then_some(Extents {
+            first_row: self.first_row,
+            last_row: self.last_row,
+        });
+        StationInfo::new(name, degrees(self.min), degrees(self.max), avg, self.cnt)
+            .with_extents(extents)
+    }
+}
+
+/// A measurement in any other form than the canonical one, to the nearest tenth; `None` if
+/// that's more than an `i16` can hold
+fn to_tenths(measurement: f32) -> Option<i16> {
+    let tenths = (measurement as f64 * 10.0).round();
+    (i16::MIN as f64..=i16::MAX as f64)
+        .contains(&tenths)
+        .then_some(tenths as i16)
+}
+
+impl ChallengeRunner for Runner {
+    /// Like the `ahash` runner, but parsing each canonical measurement straight from its digits
+    /// into whole tenths, and summing those as integers.
+    ///
+    /// Anything other than a canonical measurement (e.g. `12.34` or `+5`, outside strict mode) is
+    /// parsed the usual way & rounded to the nearest tenth, or skipped as out of range if it's
+    /// beyond ±3276.7.
+    fn run<R>(input: R, config: &Config) -> ChallengeResult
+    where
+        R: std::io::Read + std::io::Seek,
+    {
+        let start = Instant::now();
+
+        let strict = config.strict.value;
+        let max_skipped = config.max_skipped.value;
+        let track_rows = config.track_rows();
+        let dialect = config.dialect();
+        let delimiter = dialect.delimiter.byte();
+        // With a decimal comma (or a month before the station), the first delimiter might not be
+        // the one before the measurement
+        let split = dialect.key_format == KeyFormat::Station && !dialect.decimal_comma;
+
+        let known = config
+            .known_stations
+            .as_ref()
+            .map_or(0, |names| names.len());
+        let mut stations: HashMap<String, FixedData, RandomState> =
+            HashMap::with_capacity_and_hasher(known, RandomState::new());
+        let mut skipped = SkippedLines::default();
+        let mut lines = LineReader::new(input, config);
+        let mut line_number = 0;
+        loop {
+            let line = match lines.next_line() {
+                Ok(Some(line)) => line,
+                Ok(None) => break,
+                Err(e) => {
+                    line_number += 1;
+                    skipped.skip(e, 1, max_skipped)?;
+                    continue;
+                }
+            };
+            line_number += 1;
+
+            // The delimiter is ASCII, so it's never in the middle of a character
+            let fast = match line.bytes().position(|b| b == delimiter) {
+                Some(idx) if split => {
+                    parse_tenths(&line.as_bytes()[idx + 1..]).map(|t| (&line[..idx], t))
+                }
+                _ => None,
+            };
+            let (station, tenths) = match fast {
+                Some(record) => record,
+                None => match parse_record(line, line_number, strict, dialect) {
+                    Ok(Some((station, measurement))) => match to_tenths(measurement) {
+                        Some(tenths) => (station, tenths),
+                        None => {
+                            let e = ChallengeError::MalformedLine {
+                                line: line_number,
+                                reason: OUT_OF_RANGE,
+                            };
+                            skipped.skip(e, 1, max_skipped)?;
+                            continue;
+                        }
+                    },
+                    Ok(None) => continue,
+                    Err(e) => {
+                        skipped.skip(e, 1, max_skipped)?;
+                        continue;
+                    }
+                },
+            };
+
+            if !stations.contains_key(station) {
+                stations.insert(station.to_owned(), FixedData::empty());
+            }
+            let data = stations
+                .get_mut(station)
+                .expect("The station was just added");
+            data.push(tenths);
+            if track_rows {
+                data.first_row = data.first_row.min(line_number);
+                data.last_row = line_number;
+            }
+        }
+
+        let aggregated = Instant::now();
+
+        // Spellings of the same station were kept apart until now, with the rows they were first
+        // on, so they're merged under the first spelling
+        let mut stations: Vec<(String, FixedData)> = stations.into_iter().collect();
+        if config.case_insensitive.value {
+            stations.sort_by_key(|(_, data)| data.first_row);
+            let mut ids: HashMap<String, usize> = HashMap::new();
+            let mut merged: Vec<(String, FixedData)> = Vec::new();
+            for (name, data) in stations {
+                match ids.entry(fold_case(&name)) {
+                    Entry::Occupied(id) => merged[*id.get()].1.merge(&data),
+                    Entry::Vacant(id) => {
+                        id.insert(merged.len());
+                        merged.push((name, data));
+                    }
+                }
+            }
+            stations = merged;
+        }
+
+        // Build the alphabetically-sorted list of stations
+        let track_extents = config.track_extents.value;
+        let mut stations: Vec<StationInfo> = stations
+            .into_iter()
+            .map(|(name, data)| data.info(name, track_extents))
+            .collect();
+        stations.sort_unstable();
+
+        let stats = RunStats::new(Timings::since(start, aggregated, config)).skipped(skipped);
+
+        Ok((stations, stats))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::runners::tests::*;
+    use std::{error, io};
+
+    #[test]
+    fn correctness() -> Result<(), Box<dyn error::Error>> {
+        let input = io::Cursor::new(TEST_DATA.as_bytes());
+
+        let (actual, _) = Runner::run(input, &Config::default())?;
+        assert_eq!(
+            actual, *EXPECTED_RESULT,
+            "actual != expected for fixed-point runner"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn edge_values() -> Result<(), Box<dyn error::Error>> {
+        let input = "A;-99.9\nA;99.9\nB;0.0\nB;-0.0\nC;5.7\nC;-5.7\nC;5.7\n";
+        let (actual, _) = Runner::run(io::Cursor::new(input), &Config::default())?;
+        let actual: Vec<_> = actual.iter().map(|s| s.to_string()).collect();
+        assert_eq!(
+            actual,
+            ["A=-99.9/0.0/99.9", "B=0.0/0.0/0.0", "C=-5.7/1.9/5.7"]
+        );
+
+        assert_eq!(to_tenths(12.34), Some(123));
+        assert_eq!(to_tenths(-7.0), Some(-70));
+        assert_eq!(to_tenths(3276.7), Some(i16::MAX));
+        assert_eq!(to_tenths(5000.0), None);
+
+        Ok(())
+    }
+
+    /// Sums in floating point drift once there are enough measurements; these don't
+    #[test]
+    fn exact_sums() -> Result<(), Box<dyn error::Error>> {
+        let input = "Hamburg;0.1\n".repeat(1_000_000);
+        let (actual, _) = Runner::run(io::Cursor::new(input), &Config::default())?;
+        assert_eq!(actual[0].avg(), 0.1);
+        assert_eq!(actual[0].count(), 1_000_000);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,279 @@
+// 1BRC - my take on the 1 Billion Row Challenge
+// Copyright (C) 2024  Charles German <5donuts@pm.me>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Use the same approach as `byte-keys`, but key the map by a 64-bit hash of each name, so finding
+//! a station only compares hashes. Each hit is checked against the name stored with it, & a name
+//! whose hash collides with another's is kept in a second map keyed by name.
+//!
+//! The name is hashed once per line, & the map (which doesn't hash its keys again) only has to
+//! compare that hash to find the station. The name itself is kept alongside the station's data,
+//! for the results & to check each hit against: two names with the same hash would otherwise be
+//! aggregated together. When they do collide, the station which got there second goes into a
+//! second, ordinary map keyed by the whole name, so the results are always right, if slower for
+//! those stations.
+
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+use std::hash::{BuildHasher, BuildHasherDefault, Hasher};
+use std::time::Instant;
+
+use ahash::RandomState;
+
+use crate::aggregate::{merge_case_variants_by_row, StationData};
+use crate::blocks::BlockReader;
+use crate::config::Config;
+use crate::error::SkippedLines;
+use crate::helpers::*;
+
+pub struct Runner;
+
+/// Passes on the `u64` it's given, which is already a hash, as it is
+#[derive(Debug, Default, Clone, Copy)]
+struct Prehashed(u64);
+
+impl Hasher for Prehashed {
+    fn write(&mut self, _: &[u8]) {
+        unreachable!("Only the hashes of names are used as keys");
+    }
+
+    fn write_u64(&mut self, hash: u64) {
+        self.0 = hash;
+    }
+
+    fn finish(&self) -> u64 {
+        self.0
+    }
+}
+
+/// A station found by the hash of its name
+struct Hashed {
+    name: Box<[u8]>,
+    data: StationData,
+}
+
+/// The stations seen so far, keyed by the hashes of their names from `S`
+struct HashKeyed<S> {
+    hasher: S,
+    stations: HashMap<u64, Hashed, BuildHasherDefault<Prehashed>>,
+
+    /// Stations whose names hash the same as one already in `stations`, keyed by the whole name
+    collided: HashMap<Box<[u8]>, StationData, RandomState>,
+}
+
+impl<S: BuildHasher> HashKeyed<S> {
+    fn new(hasher: S, capacity: usize) -> Self {
+        Self {
+            hasher,
+            stations: HashMap::with_capacity_and_hasher(capacity, Default::default()),
+            collided: HashMap::default(),
+        }
+    }
+
+    /// The data for the station called `name`, added if it's new
+    #[inline]
+    fn get_mut(&mut self, name: &[u8]) -> &mut StationData {
+        match self.stations.entry(self.hasher.hash_one(name)) {
+            Entry::Occupied(entry) if *entry.get().name == *name => &mut entry.into_mut().data,
+            Entry::Occupied(_) => {
+                // Only allocate the key the first time this name collides
+                if !self.collided.contains_key(name) {
+                    self.collided.insert(name.into(), StationData::empty());
+                }
+                self.collided.get_mut(name).expect("Inserted just above")
+            }
+            Entry::Vacant(entry) => {
+                let hashed = Hashed {
+                    name: name.into(),
+                    data: StationData::empty(),
+                };
+                &mut entry.insert(hashed).data
+            }
+        }
+    }
+
+    /// Every station, whichever map it ended up in
+    fn into_stations(self) -> impl Iterator<Item = (Box<[u8]>, StationData)> {
+        self.stations
+            .into_values()
+            .map(|hashed| (hashed.name, hashed.data))
+            .chain(self.collided)
+    }
+}
+
+impl ChallengeRunner for Runner {
+    /// Read the input in blocks like the [`byte-keys`](super::ByteKeys) runner, but key the map
+    /// by the names' hashes, checking each hit against the name stored with it.
+    fn run<R>(input: R, config: &Config) -> ChallengeResult
+    where
+        R: std::io::Read + std::io::Seek,
+    {
+        aggregate(input, config, RandomState::new())
+    }
+}
+
+/// Aggregate `input`, keying the stations by the hashes of their names from `hasher`
+fn aggregate<R, S>(mut input: R, config: &Config, hasher: S) -> ChallengeResult
+where
+    R: std::io::Read + std::io::Seek,
+    S: BuildHasher,
+{
+    let start = Instant::now();
+
+    let strict = config.strict.value;
+    let max_skipped = config.max_skipped.value;
+    let known = config
+        .known_stations
+        .as_ref()
+        .map_or(0, |names| names.len());
+    let mut stations = HashKeyed::new(hasher, known);
+    let mut skipped = SkippedLines::default();
+
+    let mut reader = BlockReader::new(&mut input, config).count_lines(config.track_rows());
+    let failure = loop {
+        let block = match reader.next_block() {
+            Ok(Some(block)) => block,
+            Ok(None) => break None,
+            // Lines too long to fit in a block are skipped, like any other malformed line
+            Err(e) => {
+                skipped.skip(e, 1, max_skipped)?;
+                continue;
+            }
+        };
+
+        let result = block.for_each_record(
+            config.max_line_length.value,
+            strict,
+            config.dialect(),
+            max_skipped,
+            &mut skipped,
+            |station, measurement, line| {
+                let data = stations.get_mut(station.as_bytes());
+                data.push(measurement, strict);
+                if let Some(first_line) = block.first_line {
+                    data.record_row(first_line + line - 1);
+                }
+            },
+        );
+        if let Err(failure) = result {
+            break Some(failure);
+        }
+    };
+    if let Some(failure) = failure {
+        return Err(failure.locate(&mut input).into());
+    }
+
+    let aggregated = Instant::now();
+    let stations: Vec<_> = stations.into_stations().collect();
+    let ignored = stations.iter().map(|(_, data)| data.skipped as u64).sum();
+
+    // Every name came from a `&str`, so is valid UTF-8
+    let named = stations.into_iter().map(|(name, data)| {
+        let name =
+            String::from_utf8(name.into_vec()).expect("Names were checked as they were read");
+        (name, data)
+    });
+    let named: Vec<(String, StationData)> = if config.case_insensitive.value {
+        merge_case_variants_by_row(named, config.track_extents.value)
+    } else {
+        named.collect()
+    };
+
+    // Build the alphabetically-sorted list of stations
+    let mut stations: Vec<StationInfo> = named
+        .into_iter()
+        .filter(|(_, data)| data.cnt > 0)
+        .map(|(name, data)| {
+            StationInfo::new(name, data.min, data.max, data.avg(), data.cnt)
+                .with_extents(data.extents())
+        })
+        .collect();
+    stations.sort_unstable();
+
+    let stats = RunStats::new(Timings::since(start, aggregated, config))
+        .ignored_non_finite(ignored)
+        .skipped(skipped);
+
+    Ok((stations, stats))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::runners::tests::*;
+    use std::{error, io};
+
+    /// Hashes every name to the same value, so every station after the first collides
+    #[derive(Default)]
+    struct Constant;
+
+    impl Hasher for Constant {
+        fn write(&mut self, _: &[u8]) {}
+
+        fn finish(&self) -> u64 {
+            0x1b7c
+        }
+    }
+
+    #[test]
+    fn correctness() -> Result<(), Box<dyn error::Error>> {
+        let input = io::Cursor::new(TEST_DATA.as_bytes());
+
+        let (actual, _) = Runner::run(input, &Config::default())?;
+        assert_eq!(
+            actual, *EXPECTED_RESULT,
+            "actual != expected for hash-keys runner"
+        );
+
+        Ok(())
+    }
+
+    /// Two names with the same hash are kept apart, the second in the map of collisions
+    #[test]
+    fn collision() {
+        let mut stations = HashKeyed::new(BuildHasherDefault::<Constant>::default(), 0);
+        stations.get_mut(b"Hamburg").push(12.0, true);
+        stations.get_mut(b"Palembang").push(38.8, true);
+        stations.get_mut(b"Hamburg").push(-3.0, true);
+        stations.get_mut(b"Palembang").push(21.2, true);
+
+        assert_eq!(stations.stations.len(), 1);
+        assert_eq!(stations.collided.len(), 1);
+        let mut found: Vec<_> = stations
+            .into_stations()
+            .map(|(name, data)| (name, data.cnt, data.min, data.max))
+            .collect();
+        found.sort_unstable_by(|a, b| a.0.cmp(&b.0));
+        assert_eq!(
+            found,
+            [
+                (b"Hamburg"[..].into(), 2, -3.0, 12.0),
+                (b"Palembang"[..].into(), 2, 21.2, 38.8),
+            ]
+        );
+    }
+
+    /// Even with every name colliding, the results are the same bits as when none of them do
+    #[test]
+    fn all_colliding() -> Result<(), Box<dyn error::Error>> {
+        let input = || io::Cursor::new(TEST_DATA.as_bytes());
+        let hasher = BuildHasherDefault::<Constant>::default();
+
+        let (expected, _) = Runner::run(input(), &Config::default())?;
+        let (actual, _) = aggregate(input(), &Config::default(), hasher)?;
+        assert_eq!(bits(&actual), bits(&expected));
+
+        Ok(())
+    }
+}
@@ -0,0 +1,224 @@
+// 1BRC - my take on the 1 Billion Row Challenge
+// Copyright (C) 2024  Charles German <5donuts@pm.me>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Use the same approach as `table`, but with every station stored inline in one flat array sized
+//! for the challenge's 10,000 stations, and every name in one buffer, rather than each in an
+//! allocation of its own.
+
+use std::time::Instant;
+
+use crate::aggregate::{merge_case_variants_by_row, parse_record, StationData};
+use crate::config::Config;
+use crate::core_aggregate::{entries_for, Entry, Full, StationTable};
+use crate::error::SkippedLines;
+use crate::helpers::*;
+use crate::reader::LineReader;
+
+pub struct Runner;
+
+impl Runner {
+    /// How many bytes the entries of a table holding `stations` stations take up.
+    ///
+    /// This doesn't include the station names themselves.
+    pub fn estimated_size(stations: usize) -> usize {
+        InlineTable::entries_for(stations) * std::mem::size_of::<Entry>()
+    }
+}
+
+/// The fewest entries a table has: a power of two, so the challenge's 10,000 stations fill no
+/// more than [`MAX_LOAD`](crate::core_aggregate::MAX_LOAD) of it
+const ENTRIES: usize = 1 << 14;
+
+/// How many bytes of names a table has room for before it needs more; enough for hundreds of
+/// stations
+const NAMES: usize = 64 * 1024;
+
+/// A [`StationTable`] in storage which grows as needed: every station is stored inline in one
+/// flat array and every name in one buffer, rather than each in an allocation of its own.
+///
+/// It starts out with room for the challenge's 10,000 stations, and doubles its entries (or its
+/// names) whenever they're full after that.
+struct InlineTable {
+    table: StationTable<Vec<Entry>, Vec<u8>>,
+}
+
+impl InlineTable {
+    fn with_capacity(stations: usize) -> Self {
+        Self {
+            table: StationTable::new(
+                vec![Entry::EMPTY; Self::entries_for(stations)],
+                vec![0; NAMES],
+            ),
+        }
+    }
+
+    /// The number of entries for a table holding `stations` stations without growing
+    fn entries_for(stations: usize) -> usize {
+        entries_for(stations).max(ENTRIES)
+    }
+
+    /// The data for the station called `name`, added to the table if it's not there yet
+    #[inline]
+    fn get_mut(&mut self, name: &[u8]) -> &mut StationData {
+        while let Err(full) = self.table.room_for(name) {
+            match full {
+                Full::Entries => {
+                    let entries = Self::entries_for(self.table.len() * 2);
+                    self.table.rehash(vec![Entry::EMPTY; entries]);
+                }
+                // The names already there stay where they are
+                Full::Names => self.table.grow_names(|names| {
+                    names.resize((names.len() * 2).max(names.len() + name.len()), 0)
+                }),
+            }
+        }
+        self.table
+            .get_mut(name)
+            .expect("There's room for another station")
+    }
+
+    /// Every station in the table, in no particular order
+    fn into_stations(self) -> Vec<(String, StationData)> {
+        self.table
+            .iter()
+            .map(|(name, data)| {
+                // Every name came from a `&str`, so is valid UTF-8
+                let name = std::str::from_utf8(name).expect("Names were checked as they were read");
+                (name.to_owned(), *data)
+            })
+            .collect()
+    }
+}
+
+impl ChallengeRunner for Runner {
+    /// Same as the baseline, but updating an [`InlineTable`] of stations rather than a `HashMap`
+    fn run<R>(input: R, config: &Config) -> ChallengeResult
+    where
+        R: std::io::Read + std::io::Seek,
+    {
+        let start = Instant::now();
+
+        let strict = config.strict.value;
+        let max_skipped = config.max_skipped.value;
+        let track_rows = config.track_rows();
+        let dialect = config.dialect();
+
+        let mut table = InlineTable::with_capacity(
+            config
+                .known_stations
+                .as_ref()
+                .map_or(0, |names| names.len()),
+        );
+        let mut skipped = SkippedLines::default();
+        let mut lines = LineReader::new(input, config);
+        let mut line_number = 0;
+        loop {
+            let line = match lines.next_line() {
+                Ok(Some(line)) => line,
+                Ok(None) => break,
+                Err(e) => {
+                    line_number += 1;
+                    skipped.skip(e, 1, max_skipped)?;
+                    continue;
+                }
+            };
+            line_number += 1;
+
+            match parse_record(line, line_number, strict, dialect) {
+                Ok(Some((station, measurement))) => {
+                    let data = table.get_mut(station.as_bytes());
+                    data.push(measurement, strict);
+                    if track_rows {
+                        data.record_row(line_number);
+                    }
+                }
+                Ok(None) => {}
+                Err(e) => skipped.skip(e, 1, max_skipped)?,
+            }
+        }
+
+        let aggregated = Instant::now();
+        let ignored = table
+            .table
+            .iter()
+            .map(|(_, data)| data.skipped as u64)
+            .sum();
+
+        let named: Vec<(String, StationData)> = if config.case_insensitive.value {
+            merge_case_variants_by_row(table.into_stations(), config.track_extents.value)
+        } else {
+            table.into_stations()
+        };
+
+        // Build the alphabetically-sorted list of stations
+        let mut stations: Vec<StationInfo> = named
+            .into_iter()
+            .filter(|(_, data)| data.cnt > 0)
+            .map(|(name, data)| {
+                StationInfo::new(name, data.min, data.max, data.avg(), data.cnt)
+                    .with_extents(data.extents())
+            })
+            .collect();
+        stations.sort_unstable();
+
+        let stats = RunStats::new(Timings::since(start, aggregated, config))
+            .ignored_non_finite(ignored)
+            .skipped(skipped);
+
+        Ok((stations, stats))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Source;
+    use crate::runners::tests::*;
+    use crate::Runner as Kind;
+    use std::fmt::Write;
+    use std::{error, io};
+
+    #[test]
+    fn correctness() -> Result<(), Box<dyn error::Error>> {
+        let input = io::Cursor::new(TEST_DATA.as_bytes());
+
+        let (actual, _) = Runner::run(input, &Config::default())?;
+        assert_eq!(
+            actual, *EXPECTED_RESULT,
+            "actual != expected for inline-table runner"
+        );
+
+        Ok(())
+    }
+
+    /// More stations than the table starts out with room for
+    #[test]
+    fn grows() -> Result<(), Box<dyn error::Error>> {
+        let mut input = String::new();
+        for i in 0..25_000 {
+            writeln!(input, "Station {};{}.{}", i % 12_000, i % 100, i % 10)?;
+        }
+        let baseline = Config::default().with_runner(Kind::Baseline, Source::Cli);
+
+        let (expected, _) = crate::runners::run_with(io::Cursor::new(&input), &baseline)?;
+        let (actual, _) = Runner::run(io::Cursor::new(&input), &Config::default())?;
+        assert_eq!(actual.len(), 12_000);
+        assert!(actual.iter().zip(&expected).all(|(a, e)| a.eq_rounded(e)));
+        assert!(InlineTable::entries_for(12_000) > ENTRIES);
+        assert_eq!(InlineTable::entries_for(10_000), ENTRIES);
+
+        Ok(())
+    }
+}
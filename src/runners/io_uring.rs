@@ -0,0 +1,346 @@
+// 1BRC - my take on the 1 Billion Row Challenge
+// Copyright (C) 2024  Charles German <5donuts@pm.me>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Use the same approach as `ahash`, but read the input through io_uring, with reads of the next
+//! few blocks always queued while the current one is parsed. Only built with the `io-uring`
+//! feature, on Linux.
+
+use std::fs::File;
+use std::io::{self, Read};
+use std::os::fd::AsRawFd;
+use std::path::Path;
+use std::time::Instant;
+
+use ahash::RandomState;
+use io_uring::{opcode, types, IoUring};
+
+use crate::aggregate::Aggregator;
+use crate::config::Config;
+use crate::error::ChallengeError;
+use crate::helpers::*;
+use crate::reader::LineReader;
+
+pub struct Runner;
+
+impl Runner {
+    /// How many blocks of the input are queued to be read at once
+    pub const DEPTH: usize = 4;
+
+    /// Read the file at `path` through io_uring, with the next few blocks of it always queued to
+    /// be read while the current one is parsed.
+    pub fn run_file(path: &Path, config: &Config) -> ChallengeResult {
+        let start = Instant::now();
+        let reader = UringReader::open(path, config.buffer_size.value)?;
+        aggregate(reader, start, config)
+    }
+}
+
+impl ChallengeRunner for Runner {
+    /// Only a file can be read through io_uring, so any other input (e.g. a `Cursor` in tests) is
+    /// read the same way as the `ahash` runner reads it instead; see [`Runner::run_file`].
+    fn run<R>(input: R, config: &Config) -> ChallengeResult
+    where
+        R: std::io::Read + std::io::Seek,
+    {
+        aggregate(input, Instant::now(), config)
+    }
+}
+
+fn aggregate<R: Read>(input: R, start: Instant, config: &Config) -> ChallengeResult {
+    let mut aggregator: Aggregator<RandomState> =
+        Aggregator::with_known_stations(config.known_stations.clone())
+            .strict(config.strict.value)
+            .dialect(config.dialect())
+            .track_extents(config.track_extents.value)
+            .case_insensitive(config.case_insensitive.value)
+            .max_skipped(config.max_skipped.value)
+            .reuse_results(config.take_results_buffer());
+    let mut lines = LineReader::new(input, config);
+    loop {
+        match lines.next_line() {
+            Ok(Some(line)) => aggregator.ingest_line(line)?,
+            Ok(None) => break,
+            Err(e) => aggregator.skip_unreadable(e)?,
+        }
+    }
+
+    let aggregated = Instant::now();
+    let ignored = aggregator.ignored_non_finite();
+    let skipped = aggregator.skipped();
+    let stations = aggregator.into_sorted();
+
+    let stats = RunStats::new(Timings::since(start, aggregated, config))
+        .ignored_non_finite(ignored)
+        .skipped(skipped);
+
+    Ok((stations, stats))
+}
+
+/// A buffer for one block of the input
+#[derive(Debug)]
+struct Slot {
+    buf: Vec<u8>,
+
+    /// How many bytes of the block there are to read, which is less than the buffer for the
+    /// last block
+    len: usize,
+
+    /// How many bytes of the block have been read so far
+    filled: usize,
+}
+
+/// Reads a file in consecutive blocks of a fixed size through io_uring, keeping the reads of the
+/// next [`Runner::DEPTH`] blocks in flight while the current one is consumed.
+///
+/// A read which comes back short of the end of its block (which the kernel is allowed to do) is
+/// queued again for the rest of the block, so the blocks are always whole apart from the last.
+struct UringReader {
+    ring: IoUring,
+    file: File,
+    len: u64,
+    block_size: usize,
+    slots: Vec<Slot>,
+
+    /// The block being consumed
+    current: u64,
+
+    /// How far into the current block has been consumed
+    pos: usize,
+
+    /// How many blocks have been queued to be read
+    queued: u64,
+
+    /// How many reads have been submitted without completing yet
+    in_flight: usize,
+}
+
+impl UringReader {
+    /// Open the file at `path` & queue reads of its first few blocks of `block_size` bytes
+    fn open(path: &Path, block_size: usize) -> Result<Self, ChallengeError> {
+        let file = File::open(path)?;
+        let len = file.metadata()?.len();
+        let ring =
+            IoUring::new(Runner::DEPTH as u32).map_err(|source| ChallengeError::Unsupported {
+                what: "setting up io_uring",
+                source,
+            })?;
+
+        // An empty block would be indistinguishable from the end of the input
+        let block_size = block_size.max(1);
+        let mut reader = Self {
+            ring,
+            file,
+            len,
+            block_size,
+            slots: (0..Runner::DEPTH)
+                .map(|_| Slot {
+                    buf: vec![0; block_size],
+                    len: 0,
+                    filled: 0,
+                })
+                .collect(),
+            current: 0,
+            pos: 0,
+            queued: 0,
+            in_flight: 0,
+        };
+        while reader.queued < reader.blocks().min(Runner::DEPTH as u64) {
+            reader.queue_next()?;
+        }
+        Ok(reader)
+    }
+
+    /// The number of blocks in the file
+    fn blocks(&self) -> u64 {
+        self.len.div_ceil(self.block_size as u64)
+    }
+
+    /// Queue the read of the next block which isn't queued yet
+    fn queue_next(&mut self) -> io::Result<()> {
+        let block = self.queued;
+        let offset = block * self.block_size as u64;
+        let slot = &mut self.slots[block as usize % Runner::DEPTH];
+        slot.len = (self.len - offset).min(self.block_size as u64) as usize;
+        slot.filled = 0;
+        self.queued += 1;
+        self.submit(block)
+    }
+
+    /// Submit a read of whatever's left of the given block
+    fn submit(&mut self, block: u64) -> io::Result<()> {
+        let slot = &mut self.slots[block as usize % Runner::DEPTH];
+        let offset = block * self.block_size as u64 + slot.filled as u64;
+        let rest = &mut slot.buf[slot.filled..slot.len];
+        let read = opcode::Read::new(
+            types::Fd(self.file.as_raw_fd()),
+            rest.as_mut_ptr(),
+            rest.len() as u32,
+        )
+        .offset(offset)
+        .build()
+        .user_data(block);
+
+        // SAFETY: the buffer is only touched again once the read completes, & the reader waits
+        // for every read in flight before it's dropped (along with the buffers)
+        unsafe { self.ring.submission().push(&read) }
+            .expect("There's never more than one read in flight per slot");
+        self.ring.submit()?;
+        self.in_flight += 1;
+        Ok(())
+    }
+
+    /// Wait for the next read to complete, queueing it again if it came back short
+    fn complete_one(&mut self) -> io::Result<()> {
+        self.ring.submit_and_wait(1)?;
+        let Some(completion) = self.ring.completion().next() else {
+            return Ok(());
+        };
+        self.in_flight -= 1;
+
+        let block = completion.user_data();
+        let read = usize::try_from(completion.result())
+            .map_err(|_| io::Error::from_raw_os_error(-completion.result()))?;
+        let slot = &mut self.slots[block as usize % Runner::DEPTH];
+        if read == 0 && slot.filled < slot.len {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "the input got shorter while it was being read",
+            ));
+        }
+        slot.filled += read;
+        if slot.filled < slot.len {
+            self.submit(block)?;
+        }
+        Ok(())
+    }
+}
+
+impl Read for UringReader {
+    fn read(&mut self, out: &mut [u8]) -> io::Result<usize> {
+        if self.current == self.blocks() || out.is_empty() {
+            return Ok(0);
+        }
+        let idx = self.current as usize % Runner::DEPTH;
+        while self.slots[idx].filled < self.slots[idx].len {
+            self.complete_one()?;
+        }
+
+        let slot = &self.slots[idx];
+        let n = out.len().min(slot.len - self.pos);
+        out[..n].copy_from_slice(&slot.buf[self.pos..self.pos + n]);
+        self.pos += n;
+
+        // The whole block has been consumed, so its buffer is free for the next block to queue
+        if self.pos == slot.len {
+            self.pos = 0;
+            self.current += 1;
+            if self.queued < self.blocks() {
+                self.queue_next()?;
+            }
+        }
+        Ok(n)
+    }
+}
+
+impl Drop for UringReader {
+    fn drop(&mut self) {
+        // The kernel may still write to the buffers of any reads in flight
+        while self.in_flight > 0 {
+            if self.ring.submit_and_wait(1).is_err() {
+                break;
+            }
+            while self.ring.completion().next().is_some() {
+                self.in_flight -= 1;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::runners::tests::*;
+    use std::error;
+
+    /// Read a file of `contents` through a [`UringReader`] with blocks of `block_size` bytes,
+    /// `chunk` bytes at a time
+    fn read_back(contents: &[u8], block_size: usize, chunk: usize) -> io::Result<Vec<u8>> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("measurements.txt");
+        std::fs::write(&path, contents)?;
+
+        let mut reader = UringReader::open(&path, block_size).map_err(io::Error::other)?;
+        let mut read = Vec::new();
+        let mut buf = vec![0; chunk];
+        loop {
+            match reader.read(&mut buf)? {
+                0 => return Ok(read),
+                n => read.extend_from_slice(&buf[..n]),
+            }
+        }
+    }
+
+    #[test]
+    fn correctness() -> Result<(), Box<dyn error::Error>> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("measurements.txt");
+        std::fs::write(&path, TEST_DATA)?;
+
+        let (actual, _) = Runner::run_file(&path, &Config::default())?;
+        assert_eq!(
+            actual, *EXPECTED_RESULT,
+            "actual != expected for io-uring runner"
+        );
+
+        Ok(())
+    }
+
+    /// The last block is shorter than the rest, & shorter than a read of the reader
+    #[test]
+    fn last_short_read() -> Result<(), Box<dyn error::Error>> {
+        let contents = TEST_DATA.as_bytes();
+        for block_size in [7, 64, contents.len() - 1, contents.len(), 1 << 16] {
+            assert_eq!(
+                read_back(contents, block_size, 13)?,
+                contents,
+                "{block_size}"
+            );
+            assert_eq!(read_back(contents, block_size, 1 << 20)?, contents);
+        }
+        assert_eq!(read_back(b"", 64, 13)?, b"");
+
+        Ok(())
+    }
+
+    /// Lines straddle the blocks (& the queue of blocks wraps around many times) at every block
+    /// size up to a few lines long
+    #[test]
+    fn lines_split_across_blocks() -> Result<(), Box<dyn error::Error>> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("measurements.txt");
+        std::fs::write(&path, TEST_DATA)?;
+
+        for buffer_size in 1..=64 {
+            let config = Config {
+                buffer_size: crate::config::Setting::new(buffer_size, crate::config::Source::Cli),
+                ..Config::default()
+            };
+            let (actual, _) = Runner::run_file(&path, &config)?;
+            assert_eq!(actual, *EXPECTED_RESULT, "buffer size {buffer_size}");
+        }
+
+        Ok(())
+    }
+}
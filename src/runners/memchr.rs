@@ -0,0 +1,210 @@
+// 1BRC - my take on the 1 Billion Row Challenge
+// Copyright (C) 2024  Charles German <5donuts@pm.me>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Use the same approach as `mmap`, but find the end of each line & the separator before its
+//! measurement with the vectorized searches from the `memchr` crate.
+
+use std::path::Path;
+use std::time::Instant;
+
+use ahash::RandomState;
+use memchr::memchr;
+
+use crate::aggregate::{parse_fixed, Aggregator, KeyFormat};
+use crate::config::Config;
+use crate::error::ChallengeError;
+use crate::helpers::*;
+use crate::reader::leading_bom;
+
+use super::mmap::map_file;
+
+pub struct Runner;
+
+impl Runner {
+    /// Map the file at `path` into memory & search its bytes in place, like the
+    /// [`mmap`](super::Mmap) runner.
+    pub fn run_file(path: &Path, config: &Config) -> ChallengeResult {
+        let start = Instant::now();
+        match map_file(path, config.byte_range.as_ref())? {
+            Some(map) => aggregate(&map, start, config),
+            None => aggregate(&[], start, config),
+        }
+    }
+}
+
+impl ChallengeRunner for Runner {
+    /// Any input other than a file is read into memory in one go & searched from there; see
+    /// [`Runner::run_file`].
+    fn run<R>(mut input: R, config: &Config) -> ChallengeResult
+    where
+        R: std::io::Read + std::io::Seek,
+    {
+        let start = Instant::now();
+
+        let mut data = Vec::new();
+        input.read_to_end(&mut data)?;
+        aggregate(&data, start, config)
+    }
+}
+
+/// The lines of a buffer, found with `memchr` rather than checking each byte in turn.
+///
+/// A final newline ends the last line rather than starting an empty one, so the last line
+/// doesn't need a newline at all, and an empty buffer has no lines.
+struct Lines<'a> {
+    rest: &'a [u8],
+}
+
+impl<'a> Iterator for Lines<'a> {
+    type Item = &'a [u8];
+
+    #[inline]
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.rest.is_empty() {
+            return None;
+        }
+        match memchr(b'\n', self.rest) {
+            Some(idx) => {
+                let line = &self.rest[..idx];
+                self.rest = &self.rest[idx + 1..];
+                Some(line)
+            }
+            None => Some(std::mem::take(&mut self.rest)),
+        }
+    }
+}
+
+/// Aggregate every line in `data`, splitting the lines & their fields with `memchr`.
+///
+/// Lines in the canonical format are split here & handed to the aggregator as a station &
+/// measurement, borrowed straight from `data`. Anything else (a blank line, a measurement
+/// [`parse_fixed`] doesn't recognize, a month before the station, ...) goes through
+/// [`Aggregator::ingest_line`] instead, so it's accepted or reported exactly as the other runners
+/// would.
+pub(super) fn aggregate(data: &[u8], start: Instant, config: &Config) -> ChallengeResult {
+    let mut aggregator: Aggregator<RandomState> =
+        Aggregator::with_known_stations(config.known_stations.clone())
+            .strict(config.strict.value)
+            .dialect(config.dialect())
+            .track_extents(config.track_extents.value)
+            .case_insensitive(config.case_insensitive.value)
+            .max_skipped(config.max_skipped.value)
+            .reuse_results(config.take_results_buffer());
+    let max_line_length = config.max_line_length.value;
+
+    // With a decimal comma (or a month before the station), the first delimiter might not be the
+    // one before the measurement
+    let dialect = config.dialect();
+    let delimiter = dialect.delimiter.byte();
+    let split = dialect.key_format == KeyFormat::Station && !dialect.decimal_comma;
+
+    let mut decoded = String::new();
+    let mut offset = 0;
+    for line in (Lines { rest: data }) {
+        let mut line_start = offset;
+        offset += line.len() as u64 + 1;
+
+        if line.len() > max_line_length {
+            aggregator.skip_unreadable(ChallengeError::LineTooLong {
+                offset: line_start,
+                limit: max_line_length,
+            })?;
+            continue;
+        }
+        let mut line = line.strip_suffix(b"\r").unwrap_or(line);
+
+        if line_start == 0 {
+            match leading_bom(line, config.strict.value, config.verbose) {
+                Ok(skip) => {
+                    line = &line[skip..];
+                    line_start += skip as u64;
+                }
+                Err(e) => {
+                    aggregator.skip_unreadable(e)?;
+                    continue;
+                }
+            }
+        }
+
+        let line = match dialect.encoding.decode(line, &mut decoded) {
+            Ok(line) => line,
+            Err(e) => {
+                aggregator.skip_unreadable(ChallengeError::invalid_utf8(line_start, line, e))?;
+                continue;
+            }
+        };
+
+        // The delimiter is ASCII, so it's never in the middle of a character
+        let record = match memchr(delimiter, line.as_bytes()) {
+            Some(idx) if split => parse_fixed(&line[idx + 1..]).map(|m| (&line[..idx], m)),
+            _ => None,
+        };
+        match record {
+            Some((station, measurement)) => aggregator.ingest_record(station, measurement),
+            None => aggregator.ingest_line(line)?,
+        }
+    }
+
+    let aggregated = Instant::now();
+    let ignored = aggregator.ignored_non_finite();
+    let skipped = aggregator.skipped();
+    let stations = aggregator.into_sorted();
+
+    let stats = RunStats::new(Timings::since(start, aggregated, config))
+        .ignored_non_finite(ignored)
+        .skipped(skipped);
+
+    Ok((stations, stats))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Source;
+    use crate::runners::tests::*;
+    use crate::Runner as Kind;
+    use std::{error, io};
+
+    fn config() -> Config {
+        Config::default().with_runner(Kind::Memchr, Source::Cli)
+    }
+
+    #[test]
+    fn correctness() -> Result<(), Box<dyn error::Error>> {
+        let input = io::Cursor::new(TEST_DATA.as_bytes());
+
+        let (actual, _) = Runner::run(input, &config())?;
+        assert_eq!(
+            actual, *EXPECTED_RESULT,
+            "actual != expected for memchr runner"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn final_line_without_newline() {
+        let lines = |data: &'static [u8]| Lines { rest: data }.collect::<Vec<_>>();
+        assert_eq!(lines(b"A;1.0\nB;2.0"), [&b"A;1.0"[..], b"B;2.0"]);
+        assert_eq!(lines(b"A;1.0\nB;2.0\n"), [&b"A;1.0"[..], b"B;2.0"]);
+        assert_eq!(lines(b"A;1.0\n\n"), [&b"A;1.0"[..], b""]);
+        assert_eq!(lines(b"\n"), [&b""[..]]);
+        assert!(lines(b"").is_empty());
+
+        let input = TEST_DATA.trim_end();
+        let (actual, _) = Runner::run(io::Cursor::new(input), &config()).unwrap();
+        assert_eq!(actual, *EXPECTED_RESULT);
+    }
+}
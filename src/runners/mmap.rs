@@ -0,0 +1,216 @@
+// 1BRC - my take on the 1 Billion Row Challenge
+// Copyright (C) 2024  Charles German <5donuts@pm.me>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Use the same approach as `ahash`, but map the input into memory & aggregate the mapped bytes
+//! directly rather than copying them through a read buffer.
+
+use std::fs::File;
+use std::io;
+use std::ops::Range;
+use std::path::Path;
+use std::time::Instant;
+
+use ahash::RandomState;
+use memmap2::{Mmap, MmapOptions};
+
+use crate::aggregate::Aggregator;
+use crate::config::Config;
+use crate::error::ChallengeError;
+use crate::helpers::*;
+use crate::reader::leading_bom;
+
+pub struct Runner;
+
+impl Runner {
+    /// Map the file at `path` into memory & aggregate its bytes in place, without copying them
+    /// into a buffer first.
+    pub fn run_file(path: &Path, config: &Config) -> ChallengeResult {
+        let start = Instant::now();
+        match map_file(path, config.byte_range.as_ref())? {
+            Some(map) => aggregate(&map, start, config),
+            None => aggregate(&[], start, config),
+        }
+    }
+}
+
+/// Map the file at `path` (or only the bytes of it in `window`) into memory, or `None` if there's
+/// nothing to map.
+///
+/// Mapping an empty file fails on some platforms, and there's nothing to map anyway. Failing to
+/// map a file which could be opened is [unsupported](ChallengeError::Unsupported) rather than an
+/// I/O error, since reading it another way may well work.
+pub(super) fn map_file(
+    path: &Path,
+    window: Option<&Range<u64>>,
+) -> Result<Option<Mmap>, ChallengeError> {
+    let file = File::open(path)?;
+    let window = match window {
+        Some(window) => window.clone(),
+        None => 0..file.metadata()?.len(),
+    };
+    if window.is_empty() {
+        return Ok(None);
+    }
+    let len =
+        usize::try_from(window.end - window.start).map_err(|_| ChallengeError::Unsupported {
+            what: "mapping the input into memory",
+            source: io::Error::other("the input is too big to map"),
+        })?;
+
+    // SAFETY: the map is only ever read. Like any other runner, we assume nothing truncates or
+    // rewrites the input while it's being solved; if something does, a read can fault.
+    let map = unsafe { MmapOptions::new().offset(window.start).len(len).map(&file) }.map_err(
+        |source| ChallengeError::Unsupported {
+            what: "mapping the input into memory",
+            source,
+        },
+    )?;
+    Ok(Some(map))
+}
+
+impl ChallengeRunner for Runner {
+    /// Only a file can be mapped, so any other input (e.g. a `Cursor` in tests) is read into
+    /// memory in one go & aggregated from there instead; see [`Runner::run_file`].
+    fn run<R>(mut input: R, config: &Config) -> ChallengeResult
+    where
+        R: std::io::Read + std::io::Seek,
+    {
+        let start = Instant::now();
+
+        let mut data = Vec::new();
+        input.read_to_end(&mut data)?;
+        aggregate(&data, start, config)
+    }
+}
+
+/// Aggregate every line in `data`, following the same rules as a
+/// [`LineReader`](crate::reader::LineReader): over-long lines, a leading byte-order mark & invalid
+/// UTF-8 are all reported the same way, at the same offsets.
+fn aggregate(data: &[u8], start: Instant, config: &Config) -> ChallengeResult {
+    let mut aggregator: Aggregator<RandomState> =
+        Aggregator::with_known_stations(config.known_stations.clone())
+            .strict(config.strict.value)
+            .dialect(config.dialect())
+            .track_extents(config.track_extents.value)
+            .case_insensitive(config.case_insensitive.value)
+            .max_skipped(config.max_skipped.value)
+            .reuse_results(config.take_results_buffer());
+    let max_line_length = config.max_line_length.value;
+
+    // A final newline ends the last line rather than starting an empty one, and empty input has
+    // no lines at all
+    let body = data.strip_suffix(b"\n").unwrap_or(data);
+    let mut lines = body.split(|&b| b == b'\n');
+    if data.is_empty() {
+        lines.next();
+    }
+
+    let encoding = config.encoding.value;
+    let mut decoded = String::new();
+    let mut offset = 0;
+    for line in lines {
+        let mut line_start = offset;
+        offset += line.len() as u64 + 1;
+
+        if line.len() > max_line_length {
+            aggregator.skip_unreadable(ChallengeError::LineTooLong {
+                offset: line_start,
+                limit: max_line_length,
+            })?;
+            continue;
+        }
+        let mut line = line.strip_suffix(b"\r").unwrap_or(line);
+
+        if line_start == 0 {
+            match leading_bom(line, config.strict.value, config.verbose) {
+                Ok(skip) => {
+                    line = &line[skip..];
+                    line_start += skip as u64;
+                }
+                Err(e) => {
+                    aggregator.skip_unreadable(e)?;
+                    continue;
+                }
+            }
+        }
+
+        match encoding.decode(line, &mut decoded) {
+            Ok(line) => aggregator.ingest_line(line)?,
+            Err(e) => {
+                aggregator.skip_unreadable(ChallengeError::invalid_utf8(line_start, line, e))?
+            }
+        }
+    }
+
+    let aggregated = Instant::now();
+    let ignored = aggregator.ignored_non_finite();
+    let skipped = aggregator.skipped();
+    let stations = aggregator.into_sorted();
+
+    let stats = RunStats::new(Timings::since(start, aggregated, config))
+        .ignored_non_finite(ignored)
+        .skipped(skipped);
+
+    Ok((stations, stats))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::runners::tests::*;
+    use crate::Runner as Kind;
+    use std::{error, io, io::Write};
+
+    #[test]
+    fn correctness() -> Result<(), Box<dyn error::Error>> {
+        let input = io::Cursor::new(TEST_DATA.as_bytes());
+
+        let (actual, _) = Runner::run(input, &Config::default())?;
+        assert_eq!(
+            actual, *EXPECTED_RESULT,
+            "actual != expected for mmap runner"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn mapped_file() -> Result<(), Box<dyn error::Error>> {
+        let mut file = tempfile::NamedTempFile::new()?;
+        file.write_all(TEST_DATA.as_bytes())?;
+
+        let mut config = Config::default();
+        config.runner.value = Kind::Mmap;
+        config.canonical_input.value = file.path().to_owned();
+
+        let (actual, _) = crate::runners::run(&config)?;
+        assert_eq!(
+            actual, *EXPECTED_RESULT,
+            "mapping the file changed the result"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn mapped_empty_file() -> Result<(), Box<dyn error::Error>> {
+        let file = tempfile::NamedTempFile::new()?;
+
+        let (actual, _) = Runner::run_file(file.path(), &Config::default())?;
+        assert!(actual.is_empty());
+
+        Ok(())
+    }
+}
@@ -13,21 +13,355 @@
 // You should have received a copy of the GNU General Public License
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
+mod ahash;
 mod baseline;
+mod branchless;
+mod byte_keys;
+mod cached_table;
+mod compact_keys;
+mod crossbeam;
+#[cfg(feature = "direct-io")]
+mod direct_io;
+mod double_buffered;
+mod entry_ref;
+mod fadvise;
+mod fixed_point;
+mod hash_keys;
+mod inline_table;
+#[cfg(feature = "io-uring")]
+mod io_uring;
+mod memchr;
+mod mmap;
+mod par_mmap;
+mod parse;
+mod perfect_hash;
+mod pipelined;
+mod presized;
 mod rustc_hash;
-mod ahash;
+mod sampled_dense;
+mod scoped_threads;
+mod simd;
+mod swar;
+mod table;
+mod table_prefetch;
+#[cfg(feature = "affinity")]
+mod thread_per_core;
+#[cfg(feature = "tokio")]
+mod tokio;
+mod unchecked;
+mod work_queue;
 
+pub use ahash::Runner as AHash;
 pub use baseline::Runner as Baseline;
+pub use branchless::Runner as Branchless;
+pub use byte_keys::Runner as ByteKeys;
+pub use cached_table::Runner as CachedTable;
+pub use compact_keys::Runner as CompactKeys;
+pub use crossbeam::Runner as Crossbeam;
+#[cfg(feature = "direct-io")]
+pub use direct_io::Runner as DirectIo;
+pub use double_buffered::Runner as DoubleBuffered;
+pub use entry_ref::Runner as EntryRef;
+pub use fadvise::Runner as Fadvise;
+pub use fixed_point::Runner as FixedPoint;
+pub use hash_keys::Runner as HashKeys;
+pub use inline_table::Runner as InlineTable;
+#[cfg(feature = "io-uring")]
+pub use io_uring::Runner as IoUring;
+pub use memchr::Runner as Memchr;
+pub use mmap::Runner as Mmap;
+pub use par_mmap::Runner as ParMmap;
+pub use perfect_hash::Runner as PerfectHash;
+pub use pipelined::Runner as Pipelined;
+pub use presized::Runner as Presized;
 pub use rustc_hash::Runner as RustcHash;
-pub use ahash::Runner as AHash;
+pub use sampled_dense::Runner as SampledDense;
+pub use scoped_threads::Runner as ScopedThreads;
+pub use simd::Runner as Simd;
+pub use swar::Runner as Swar;
+pub use table::Runner as Table;
+pub use table_prefetch::Runner as TablePrefetch;
+#[cfg(feature = "affinity")]
+pub use thread_per_core::Runner as ThreadPerCore;
+#[cfg(feature = "tokio")]
+pub use tokio::Runner as Tokio;
+pub use unchecked::Runner as Unchecked;
+pub use work_queue::Runner as WorkQueue;
+
+use std::borrow::Cow;
+use std::io;
+
+use crate::config::{Config, Source};
+use crate::error::ChallengeError;
+use crate::helpers::{ChallengeResult, ChallengeRunner, Fallback};
+use crate::sample::Sampled;
+use crate::station_cache::StationCache;
+use crate::topology::Topology;
+use crate::window::Window;
+use crate::Runner;
+
+/// Run the configured [`Runner`] against the configured input.
+///
+/// If the station cache is enabled, the runner is primed with the cached station names for the
+/// input (loading the cache isn't included in the runner's duration). The cache is (re)written
+/// afterwards if it was missing, unusable, or out of date.
+///
+/// If the runner can't run in this environment & [`Config::fallback`] is on, the run is retried
+/// with each runner it [falls back](Runner::fallback) to in turn.
+pub fn run(config: &Config) -> ChallengeResult {
+    with_fallback(config, run_once)
+}
+
+/// Run `attempt` with the configured runner, and if it fails because of the environment (see
+/// [`ChallengeError::is_environmental`]) & [`Config::fallback`] is on, with the runner it falls
+/// back to, & so on. Any other error is returned straight away.
+fn with_fallback(config: &Config, attempt: impl Fn(&Config) -> ChallengeResult) -> ChallengeResult {
+    let mut current = Cow::Borrowed(config);
+    loop {
+        let e = match attempt(&current) {
+            Ok((stations, stats)) if current.runner.value == config.runner.value => {
+                return Ok((stations, stats))
+            }
+            Ok((stations, stats)) => {
+                let fallback = Fallback {
+                    from: config.runner.value,
+                    to: current.runner.value,
+                };
+                return Ok((stations, stats.fell_back(fallback)));
+            }
+            Err(e) => e,
+        };
+
+        let environmental = e
+            .downcast_ref::<ChallengeError>()
+            .is_some_and(ChallengeError::is_environmental);
+        let next = current.runner.value.fallback();
+        match next {
+            Some(next) if environmental && config.fallback.value => {
+                eprintln!(
+                    "Warning: the {} runner failed ({e}); falling back to the {next} runner",
+                    current.runner.value
+                );
+                current = Cow::Owned(current.with_runner(next, Source::Auto));
+            }
+            _ => return Err(e),
+        }
+    }
+}
+
+/// Run the configured [`Runner`], primed from the station cache if it's enabled
+fn run_once(config: &Config) -> ChallengeResult {
+    if let Some(progress) = &config.progress {
+        let total = match &config.byte_range {
+            Some(range) => range.end - range.start,
+            None => config.input_size.value,
+        };
+        progress.start(total);
+    }
+
+    // The stations in part of the input needn't be all of them, so aren't cached
+    if !config.station_cache.value || config.byte_range.is_some() || config.sample.is_some() {
+        return dispatch(config);
+    }
+
+    let input = &config.canonical_input.value;
+    let known_stations = match StationCache::load(input) {
+        Ok(names) => names,
+        Err(e) => {
+            eprintln!(
+                "Ignoring station cache {}: {e}",
+                StationCache::path_for(input).display()
+            );
+            None
+        }
+    };
+
+    let mut primed = config.clone();
+    primed.known_stations = known_stations.clone().map(Into::into);
+    let (stations, stats) = dispatch(&primed)?;
+
+    let names: Vec<String> = stations.iter().map(|s| s.name().to_owned()).collect();
+    if known_stations.as_ref() != Some(&names) {
+        if let Err(e) = StationCache::save(input, names) {
+            eprintln!(
+                "Unable to write station cache {}: {e}",
+                StationCache::path_for(input).display()
+            );
+        }
+    }
+
+    Ok((stations, stats))
+}
+
+/// A warning to show if the configured thread count is more than the machine can make use of.
+///
+/// The default thread count only matters to parallel runners, but a count the user asked for
+/// explicitly is always checked.
+pub fn parallelism_warning(config: &Config, topology: &Topology) -> Option<String> {
+    if !config.runner.value.is_parallel() && config.threads.source == Source::Auto {
+        return None;
+    }
+    topology.check_threads(config.threads.value)
+}
+
+/// Invoke the configured [`Runner`] on the configured input
+fn dispatch(config: &Config) -> ChallengeResult {
+    keyed(dispatch_runner(config), config)
+}
+
+/// Take each station's name to be a key in the configured format; see
+/// [`StationInfo::keyed_by`](crate::helpers::StationInfo::keyed_by)
+fn keyed(result: ChallengeResult, config: &Config) -> ChallengeResult {
+    let key_format = config.key_format.value;
+    let (stations, stats) = result?;
+    let stations = stations
+        .into_iter()
+        .map(|station| station.keyed_by(key_format))
+        .collect();
+    Ok((stations, stats))
+}
+
+/// Run the configured [`Runner`] on the configured input, however it reads it best
+fn dispatch_runner(config: &Config) -> ChallengeResult {
+    // A sample is only some blocks of the file, so is always read through a reader over them
+    if let Some(sample) = config.sample {
+        let f = std::fs::File::open(&config.canonical_input.value)?;
+        let (block_size, max_line_length) =
+            (config.buffer_size.value, config.max_line_length.value);
+        return match &config.byte_range {
+            Some(window) => {
+                let window = Window::new(f, window.clone())?;
+                run_runner(
+                    Sampled::new(window, sample, block_size, max_line_length)?,
+                    config,
+                )
+            }
+            None => run_runner(
+                Sampled::new(f, sample, block_size, max_line_length)?,
+                config,
+            ),
+        };
+    }
+
+    // Mapping the input needs the file itself rather than a reader over it
+    match config.runner.value {
+        Runner::Mmap => return self::Mmap::run_file(&config.canonical_input.value, config),
+        Runner::ParMmap => return self::ParMmap::run_file(&config.canonical_input.value, config),
+        Runner::Memchr => return self::Memchr::run_file(&config.canonical_input.value, config),
+        Runner::Simd => return self::Simd::run_file(&config.canonical_input.value, config),
+        Runner::Swar => return self::Swar::run_file(&config.canonical_input.value, config),
+        Runner::Unchecked => {
+            return self::Unchecked::run_file(&config.canonical_input.value, config)
+        }
+        // The hints are given on the file itself
+        Runner::Fadvise => return self::Fadvise::run_file(&config.canonical_input.value, config),
+        // Each thread opens the file for itself
+        Runner::ScopedThreads => {
+            return self::ScopedThreads::run_file(&config.canonical_input.value, config)
+        }
+        // Likewise, a thread for each core
+        #[cfg(feature = "affinity")]
+        Runner::ThreadPerCore => {
+            return self::ThreadPerCore::run_file(&config.canonical_input.value, config)
+        }
+        // Every worker reads the file itself, at the offsets of its ranges
+        Runner::WorkQueue => {
+            return self::WorkQueue::run_file(&config.canonical_input.value, config)
+        }
+        // The reads are queued against the file itself, so only the whole file can be read this
+        // way; a `--byte-range` is read like any other input
+        #[cfg(feature = "io-uring")]
+        Runner::IoUring if config.byte_range.is_none() => {
+            return self::IoUring::run_file(&config.canonical_input.value, config)
+        }
+        // Likewise, the file is opened with O_DIRECT
+        #[cfg(feature = "direct-io")]
+        Runner::DirectIo if config.byte_range.is_none() => {
+            return self::DirectIo::run_file(&config.canonical_input.value, config)
+        }
+        // The file is read asynchronously; likewise
+        #[cfg(feature = "tokio")]
+        Runner::Tokio if config.byte_range.is_none() => {
+            return self::Tokio::run_file(&config.canonical_input.value, config)
+        }
+        _ => {}
+    }
+    let f = std::fs::File::open(&config.canonical_input.value)?;
+    match &config.byte_range {
+        Some(window) => run_runner(Window::new(f, window.clone())?, config),
+        None => run_runner(f, config),
+    }
+}
+
+/// Invoke the configured [`Runner`] on the given input
+pub fn run_with<R>(input: R, config: &Config) -> ChallengeResult
+where
+    R: io::Read + io::Seek,
+{
+    keyed(run_runner(input, config), config)
+}
+
+/// Like [`run_with`], but leaving the names as the runner keyed them
+fn run_runner<R>(input: R, config: &Config) -> ChallengeResult
+where
+    R: io::Read + io::Seek,
+{
+    use Runner::*;
+    match config.runner.value {
+        Baseline => self::Baseline::run(input, config),
+        RustcHash => self::RustcHash::run(input, config),
+        AHash => self::AHash::run(input, config),
+        Presized => self::Presized::run(input, config),
+        EntryRef => self::EntryRef::run(input, config),
+        Fadvise => self::Fadvise::run(input, config),
+        Table => self::Table::run(input, config),
+        TablePrefetch => self::TablePrefetch::run(input, config),
+        CachedTable => self::CachedTable::run(input, config),
+        Mmap => self::Mmap::run(input, config),
+        ParMmap => self::ParMmap::run(input, config),
+        SampledDense => self::SampledDense::run(input, config),
+        ScopedThreads => self::ScopedThreads::run(input, config),
+        WorkQueue => self::WorkQueue::run(input, config),
+        CompactKeys => self::CompactKeys::run(input, config),
+        HashKeys => self::HashKeys::run(input, config),
+        DoubleBuffered => self::DoubleBuffered::run(input, config),
+        Memchr => self::Memchr::run(input, config),
+        FixedPoint => self::FixedPoint::run(input, config),
+        ByteKeys => self::ByteKeys::run(input, config),
+        InlineTable => self::InlineTable::run(input, config),
+        PerfectHash => self::PerfectHash::run(input, config),
+        Pipelined => self::Pipelined::run(input, config),
+        Crossbeam => self::Crossbeam::run(input, config),
+        Branchless => self::Branchless::run(input, config),
+        Simd => self::Simd::run(input, config),
+        Swar => self::Swar::run(input, config),
+        Unchecked => self::Unchecked::run(input, config),
+        #[cfg(feature = "io-uring")]
+        IoUring => self::IoUring::run(input, config),
+        #[cfg(feature = "direct-io")]
+        DirectIo => self::DirectIo::run(input, config),
+        #[cfg(feature = "tokio")]
+        Tokio => self::Tokio::run(input, config),
+        #[cfg(feature = "affinity")]
+        ThreadPerCore => self::ThreadPerCore::run(input, config),
+    }
+}
 
 #[cfg(test)]
-mod tests {
+pub mod tests {
+    use super::*;
+    use crate::aggregate::{Delimiter, Extents, KeyFormat};
+    use crate::block_sizing::BlockSizing;
+    use crate::config::Setting;
+    use crate::error::{ChallengeError, SkippedLines};
     use crate::helpers::*;
+    use crate::output::Format;
+    use crate::Determinism;
+    use clap::ValueEnum;
     use once_cell::sync::Lazy;
 
     /// Some test data for runners to use when checking correctness
-    pub static TEST_DATA: &'static str = r#"Glens Falls;-47.5
+    pub static TEST_DATA: &str = r#"Glens Falls;-47.5
 Shimanto;30.3
 Zverevo;98.1
 Shimanto;74.9
@@ -43,11 +377,1296 @@ Glens Falls;6.6
     /// The expected result each runner should return when given [`TEST_DATA`] as input
     pub static EXPECTED_RESULT: Lazy<Vec<StationInfo>> = Lazy::new(|| {
         vec![
-            StationInfo::new(String::from("Aïn el Mediour"), 5.7, 47.6, 26.65),
-            StationInfo::new(String::from("Glens Falls"), -47.5, 6.6, -20.45),
-            StationInfo::new(String::from("Paidiipalli"), 91.1, 91.1, 91.1),
-            StationInfo::new(String::from("Shimanto"), 20.9, 74.9, 38.4),
-            StationInfo::new(String::from("Zverevo"), 87.6, 87.6, 87.6),
+            StationInfo::new(String::from("Aïn el Mediour"), 5.7, 47.6, 26.65, 2),
+            StationInfo::new(String::from("Glens Falls"), -47.5, 6.6, -20.45, 2),
+            StationInfo::new(String::from("Paidiipalli"), 91.1, 91.1, 91.1, 1),
+            StationInfo::new(String::from("Shimanto"), 20.9, 74.9, 38.4, 4),
+            StationInfo::new(String::from("Zverevo"), 87.6, 98.1, 92.85, 2),
         ]
     });
+
+    /// Run every runner on `input`, in strict mode or not
+    fn run_all(input: &str, strict: bool) -> Vec<(Runner, ChallengeResult)> {
+        let input = input.to_owned();
+        Runner::value_variants()
+            .iter()
+            .map(|&runner| {
+                let config = Config {
+                    strict: Setting::new(strict, Source::Cli),
+                    ..Config::default().with_runner(runner, Source::Cli)
+                };
+                (runner, run_with(io::Cursor::new(input.clone()), &config))
+            })
+            .collect()
+    }
+
+    /// The output of the given runner on the clean [`TEST_DATA`]
+    fn clean(runner: Runner) -> String {
+        let config = Config::default().with_runner(runner, Source::Cli);
+        let (stations, _) = run_with(io::Cursor::new(TEST_DATA), &config).unwrap();
+        Format::Text.render(&stations)
+    }
+
+    #[test]
+    fn every_module_has_a_runner() -> Result<(), Box<dyn std::error::Error>> {
+        // Each runner lives in its own module here, named after it (`table_prefetch.rs` for
+        // `--runner table-prefetch`), so nothing can be added without a way to pick it. The
+        // shared tests below run every runner already, but each module should also check its
+        // runner on its own against `TEST_DATA`, & open with the same description as `--help`
+        // gives it.
+        let dir = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("src/runners");
+        let names: Vec<String> = Runner::value_variants()
+            .iter()
+            .map(|runner| runner.to_string().replace('-', ""))
+            .collect();
+        let mut modules = 0;
+        for entry in std::fs::read_dir(dir)? {
+            let path = entry?.path();
+            let module = path
+                .file_stem()
+                .and_then(|s| s.to_str())
+                .unwrap_or_default();
+            // Runners behind a feature which isn't enabled have a module but no `--runner`
+            let gated = [
+                ("io_uring", cfg!(feature = "io-uring")),
+                ("direct_io", cfg!(feature = "direct-io")),
+                ("tokio", cfg!(feature = "tokio")),
+                ("thread_per_core", cfg!(feature = "affinity")),
+            ];
+            let enabled = gated.iter().all(|&(gated, on)| gated != module || on);
+            // `parse` is shared by several runners rather than being one
+            if module == "mod" || module == "parse" || !enabled {
+                continue;
+            }
+            modules += 1;
+            let runner = names
+                .iter()
+                .position(|name| *name == module.replace('_', ""))
+                .map(|idx| Runner::value_variants()[idx])
+                .unwrap_or_else(|| panic!("runners/{module}.rs has no --runner"));
+            let source = std::fs::read_to_string(&path)?;
+            assert!(
+                source.contains("fn correctness()"),
+                "runners/{module}.rs has no correctness test"
+            );
+
+            // The first paragraph of the module's doc comment, which clap shows without its
+            // final full stop
+            let summary: Vec<&str> = source
+                .lines()
+                .skip_while(|line| !line.starts_with("//!"))
+                .map_while(|line| line.strip_prefix("//!"))
+                .map(str::trim)
+                .take_while(|line| !line.is_empty())
+                .collect();
+            let help = runner.to_possible_value().unwrap();
+            let help = help.get_help().map(ToString::to_string);
+            assert_eq!(
+                Some(summary.join(" ").trim_end_matches('.')),
+                help.as_deref(),
+                "runners/{module}.rs doesn't open with the --help description of {runner}"
+            );
+        }
+        assert_eq!(modules, names.len());
+
+        Ok(())
+    }
+
+    #[test]
+    fn runner_names() {
+        let mut seen = std::collections::BTreeSet::new();
+        for &runner in Runner::value_variants() {
+            let value = runner.to_possible_value().unwrap();
+            let name = value.get_name().to_owned();
+            assert!(seen.insert(name.clone()), "--runner {name} is used twice");
+            let word = |w: &str| {
+                !w.is_empty()
+                    && w.bytes()
+                        .all(|b| b.is_ascii_lowercase() || b.is_ascii_digit())
+            };
+            assert!(
+                name.split('-').all(word),
+                "--runner {name} isn't kebab-case"
+            );
+            // Reports & progress files name runners the same way as the CLI
+            assert_eq!(runner.to_string(), name);
+            assert_eq!(serde_json::to_value(runner).unwrap(), name);
+        }
+    }
+
+    /// The input made of `lines` (station & measurement in tenths), & the exact results for it,
+    /// extents included
+    fn reference(lines: &[(&str, i32)]) -> (String, Vec<StationInfo>) {
+        let mut input = String::new();
+        let mut expected: std::collections::BTreeMap<&str, (i32, i32, i32, u32, u64, u64)> =
+            Default::default();
+        for (row, &(station, tenths)) in lines.iter().enumerate() {
+            let sign = if tenths < 0 { "-" } else { "" };
+            input.push_str(&format!(
+                "{station};{sign}{}.{}\n",
+                tenths.abs() / 10,
+                tenths.abs() % 10
+            ));
+            let row = row as u64 + 1;
+            let e = expected
+                .entry(station)
+                .or_insert((i32::MAX, i32::MIN, 0, 0, row, row));
+            *e = (
+                e.0.min(tenths),
+                e.1.max(tenths),
+                e.2 + tenths,
+                e.3 + 1,
+                e.4,
+                row,
+            );
+        }
+        let expected: Vec<StationInfo> = expected
+            .into_iter()
+            .map(|(name, (min, max, sum, cnt, first_row, last_row))| {
+                let tenths = |t: i32| t as f32 / 10.0;
+                StationInfo::new(
+                    name.to_owned(),
+                    tenths(min),
+                    tenths(max),
+                    sum as f32 / 10.0 / cnt as f32,
+                    cnt,
+                )
+                .with_extents(Some(Extents {
+                    first_row,
+                    last_row,
+                }))
+            })
+            .collect();
+        (input, expected)
+    }
+
+    /// Check every runner aggregates `lines` (station & measurement in tenths) correctly,
+    /// extents included. The measurements should be whole degrees so the sums are exact.
+    fn check_every_runner(lines: &[(&str, i32)]) {
+        let (input, expected) = reference(lines);
+        for &runner in Runner::value_variants() {
+            let config = Config {
+                track_extents: Setting::new(true, Source::Cli),
+                threads: Setting::new(3, Source::Cli),
+                buffer_size: Setting::new(256, Source::Cli),
+                ..Config::default().with_runner(runner, Source::Cli)
+            };
+            let (actual, _) = run_with(io::Cursor::new(&input), &config).unwrap();
+            assert_eq!(
+                Format::Text.render(&actual),
+                Format::Text.render(&expected),
+                "{runner}"
+            );
+            let extents: Vec<_> = actual.iter().map(StationInfo::extents).collect();
+            let expected_extents: Vec<_> = expected.iter().map(StationInfo::extents).collect();
+            assert_eq!(extents, expected_extents, "{runner}");
+        }
+    }
+
+    #[test]
+    fn sorted_by_station() {
+        // Long runs of each station, some of whose names only differ in their last byte
+        let names: Vec<String> = (0..12).map(|i| format!("Station {i:02}")).collect();
+        let mut lines = Vec::new();
+        for (i, name) in names.iter().enumerate() {
+            for j in 0..150 {
+                lines.push((name.as_str(), ((i as i32 * 37 + j * 13) % 199 - 99) * 10));
+            }
+        }
+        check_every_runner(&lines);
+    }
+
+    #[test]
+    fn alternating_stations() {
+        // Never the same station twice in a row, including names which are prefixes of others
+        let names = ["Hamburg", "Hamburg2", "Hamburh", "Bulawayo", "Hamburg2"];
+        let lines: Vec<_> = (0..2000)
+            .map(|i| (names[i % names.len()], ((i as i32 * 71) % 199 - 99) * 10))
+            .collect();
+        check_every_runner(&lines);
+    }
+
+    #[test]
+    fn global_allocator() {
+        // Run under `--features mimalloc` & `--features jemalloc` too, to smoke-test every runner
+        // on each allocator
+        let expected = if cfg!(feature = "mimalloc") {
+            "mimalloc"
+        } else if cfg!(feature = "jemalloc") {
+            "jemalloc"
+        } else {
+            "system"
+        };
+        assert_eq!(crate::ALLOCATOR, expected);
+
+        for &runner in Runner::value_variants() {
+            let config = Config::default().with_runner(runner, Source::Cli);
+            let (actual, _) = run_with(io::Cursor::new(TEST_DATA), &config).unwrap();
+            assert_eq!(actual, *EXPECTED_RESULT, "{runner}");
+        }
+    }
+
+    /// Each runner (& thread count) to check at every buffer size in [`buffer_size_matrix`]. Every
+    /// runner belongs here, even `mmap` which ignores the buffer size, so none is forgotten.
+    const BUFFER_SIZE_MATRIX: &[(Runner, usize)] = &[
+        (Runner::Baseline, 1),
+        (Runner::RustcHash, 1),
+        (Runner::AHash, 1),
+        (Runner::Table, 1),
+        (Runner::TablePrefetch, 1),
+        (Runner::CachedTable, 1),
+        (Runner::Mmap, 1),
+        (Runner::ParMmap, 1),
+        (Runner::ParMmap, 4),
+        (Runner::SampledDense, 1),
+        (Runner::SampledDense, 4),
+        (Runner::ScopedThreads, 1),
+        (Runner::ScopedThreads, 4),
+        (Runner::Memchr, 1),
+        (Runner::FixedPoint, 1),
+        (Runner::ByteKeys, 1),
+        (Runner::InlineTable, 1),
+        (Runner::PerfectHash, 1),
+        (Runner::Pipelined, 1),
+        (Runner::Crossbeam, 1),
+        (Runner::Branchless, 1),
+        (Runner::Simd, 1),
+        (Runner::Swar, 1),
+        (Runner::Unchecked, 1),
+        (Runner::Presized, 1),
+        (Runner::EntryRef, 1),
+        (Runner::Fadvise, 1),
+        (Runner::WorkQueue, 1),
+        (Runner::CompactKeys, 1),
+        (Runner::HashKeys, 1),
+        (Runner::DoubleBuffered, 1),
+        #[cfg(feature = "io-uring")]
+        (Runner::IoUring, 1),
+        #[cfg(feature = "direct-io")]
+        (Runner::DirectIo, 1),
+        #[cfg(feature = "tokio")]
+        (Runner::Tokio, 1),
+        #[cfg(feature = "tokio")]
+        (Runner::Tokio, 4),
+        #[cfg(feature = "affinity")]
+        (Runner::ThreadPerCore, 1),
+    ];
+
+    #[test]
+    fn buffer_size_matrix() {
+        // ~200 KB of lines from 400 stations with names from 2 to a few hundred bytes long, some
+        // of them multi-byte characters. The measurements are whole degrees so the sums are
+        // exact, whichever order they're added in.
+        let alphabet = ['a', 'Z', ' ', 'é', '東', '-', '\'', '.', 'q'];
+        let names: Vec<String> = (0..400)
+            .map(|i| {
+                let len = (i * 37) % 100;
+                let mut name: String = (0..len)
+                    .map(|j| alphabet[(i * 7 + j * 13) % alphabet.len()])
+                    .collect();
+                name.push_str(&i.to_string());
+                name
+            })
+            .collect();
+        let mut lines = Vec::new();
+        let mut state: u64 = 1;
+        let mut len = 0;
+        while len < 200_000 {
+            state = state
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+            let name = names[(state >> 33) as usize % names.len()].as_str();
+            lines.push((name, ((state >> 20) % 199) as i32 * 10 - 990));
+            len += name.len() + 7;
+        }
+        let (input, expected) = reference(&lines);
+        let longest = input.lines().map(str::len).max().unwrap();
+
+        // Primes which don't line up with anything, a block smaller than a line, & blocks which
+        // (almost) hold the whole input
+        let sizes = [
+            1,
+            61,
+            127,
+            8191,
+            longest - 1,
+            input.len() - 1,
+            input.len(),
+            input.len() + 1,
+        ];
+        for &(runner, threads) in BUFFER_SIZE_MATRIX {
+            for buffer_size in sizes {
+                let config = Config {
+                    track_extents: Setting::new(true, Source::Cli),
+                    threads: Setting::new(threads, Source::Cli),
+                    buffer_size: Setting::new(buffer_size, Source::Cli),
+                    ..Config::default().with_runner(runner, Source::Cli)
+                };
+                let (actual, _) = run_with(io::Cursor::new(&input), &config).unwrap();
+                let what =
+                    format!("{runner} on {threads} threads with a {buffer_size} byte buffer");
+                assert_eq!(
+                    Format::Text.render(&actual),
+                    Format::Text.render(&expected),
+                    "{what}"
+                );
+                let extents: Vec<_> = actual.iter().map(StationInfo::extents).collect();
+                let expected_extents: Vec<_> = expected.iter().map(StationInfo::extents).collect();
+                assert_eq!(extents, expected_extents, "{what}");
+            }
+        }
+
+        for runner in Runner::value_variants() {
+            assert!(
+                BUFFER_SIZE_MATRIX.iter().any(|(r, _)| r == runner),
+                "{runner} is missing from BUFFER_SIZE_MATRIX"
+            );
+        }
+    }
+
+    /// The exact bits of each station's stats, which the rounded output can hide differences in
+    pub(crate) fn bits(stations: &[StationInfo]) -> Vec<(String, u32, u32, u32, u32)> {
+        stations
+            .iter()
+            .map(|s| {
+                let (min, avg, max) = (s.min().to_bits(), s.avg().to_bits(), s.max().to_bits());
+                (s.name().to_owned(), min, avg, max, s.count())
+            })
+            .collect()
+    }
+
+    #[test]
+    fn bit_exact_runners() {
+        // Measurements which aren't exact in binary & mostly cancel out, so their sums depend on
+        // the order they're added in
+        let mut input = String::new();
+        for i in 0..10_000 {
+            let tenths: i32 = (i * 7919) % 1999 - 999;
+            let sign = if tenths < 0 { "-" } else { "" };
+            input.push_str(&format!(
+                "Station {};{sign}{}.{}\n",
+                i % 5,
+                tenths.abs() / 10,
+                tenths.abs() % 10
+            ));
+        }
+
+        // None of these settings should make any difference to a bit-exact runner. The
+        // fixed-point runner's sums are exact rather than summed in order, so it's only compared
+        // with itself.
+        let (mut summed, mut exact) = (None, None);
+        for &runner in Runner::value_variants() {
+            if runner.determinism() != Determinism::BitExact {
+                continue;
+            }
+            let reference = match runner {
+                Runner::FixedPoint => &mut exact,
+                _ => &mut summed,
+            };
+            for (threads, buffer_size) in [(1, 61), (1, 4096), (4, 61), (4, 64 * 1024)] {
+                let config = Config {
+                    threads: Setting::new(threads, Source::Cli),
+                    buffer_size: Setting::new(buffer_size, Source::Cli),
+                    ..Config::default().with_runner(runner, Source::Cli)
+                };
+                for _ in 0..2 {
+                    let (actual, _) = run_with(io::Cursor::new(&input), &config).unwrap();
+                    let actual = bits(&actual);
+                    let reference = reference.get_or_insert_with(|| actual.clone());
+                    assert_eq!(
+                        &actual, reference,
+                        "{runner} on {threads} threads with a {buffer_size} byte buffer"
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn rounded_exact_runners() {
+        // Measurements which aren't exact in binary, in pairs either side of each station's mean
+        // (a whole number of tenths) so the exact mean is nowhere near rounding either way. The
+        // second of each pair comes much later, in a different order.
+        let names: Vec<String> = (0..50).map(|i| format!("Station {i}")).collect();
+        let (mut firsts, mut seconds) = (Vec::new(), Vec::new());
+        for i in 0..10_000 {
+            let station = (i * 31) % names.len();
+            let mean = (station as i32 * 37) % 1000 - 500;
+            let offset = (i as i32 * 7919) % 499;
+            firsts.push((names[station].as_str(), mean + offset));
+            seconds.push((names[station].as_str(), mean - offset));
+        }
+        let mut lines = firsts;
+        lines.extend((0..seconds.len()).map(|i| seconds[(i * 7001) % seconds.len()]));
+        let (input, expected) = reference(&lines);
+
+        let adaptive = BlockSizing::Adaptive {
+            min: 1024,
+            max: 16 * 1024,
+        };
+        for &runner in Runner::value_variants() {
+            if runner.determinism() != Determinism::RoundedExact {
+                continue;
+            }
+            for block_sizing in [BlockSizing::Fixed, adaptive] {
+                for (threads, buffer_size) in [(1, 61), (2, 4096), (4, 61), (4, 64 * 1024)] {
+                    let config = Config {
+                        threads: Setting::new(threads, Source::Cli),
+                        buffer_size: Setting::new(buffer_size, Source::Cli),
+                        block_sizing: Setting::new(block_sizing, Source::Cli),
+                        ..Config::default().with_runner(runner, Source::Cli)
+                    };
+                    let what = format!(
+                        "{runner} on {threads} threads with a {buffer_size} byte buffer & \
+                         {block_sizing} blocks"
+                    );
+                    let (first, _) = run_with(io::Cursor::new(&input), &config).unwrap();
+                    assert_eq!(
+                        Format::Text.render(&first),
+                        Format::Text.render(&expected),
+                        "{what}"
+                    );
+                    // With the same settings, the bits are the same too, unless they make the
+                    // runner best-effort
+                    if config.determinism() != Determinism::RoundedExact {
+                        continue;
+                    }
+                    let (second, _) = run_with(io::Cursor::new(&input), &config).unwrap();
+                    assert_eq!(bits(&first), bits(&second), "{what}");
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn boundary_measurements() {
+        // Only measurements at the edges of the canonical form: one station for each, & one
+        // with all of them. Every station's sum is exact, so the means are too.
+        let boundaries = [
+            "99.9", "-99.9", "0.0", "-0.0", "9.9", "-9.9", "10.0", "-10.0",
+        ];
+        let mut input = String::new();
+        for i in 0..400 {
+            let measurement = boundaries[i % boundaries.len()];
+            input.push_str(&format!("Only {measurement};{measurement}\n"));
+            input.push_str(&format!("Every boundary;{measurement}\n"));
+        }
+        let expected = "\
+Every boundary=-99.9/0.0/99.9
+Only -0.0=0.0/0.0/0.0
+Only -10.0=-10.0/-10.0/-10.0
+Only -9.9=-9.9/-9.9/-9.9
+Only -99.9=-99.9/-99.9/-99.9
+Only 0.0=0.0/0.0/0.0
+Only 10.0=10.0/10.0/10.0
+Only 9.9=9.9/9.9/9.9
+Only 99.9=99.9/99.9/99.9
+";
+
+        for &runner in Runner::value_variants() {
+            for strict in [false, true] {
+                let config = Config {
+                    strict: Setting::new(strict, Source::Cli),
+                    threads: Setting::new(3, Source::Cli),
+                    buffer_size: Setting::new(256, Source::Cli),
+                    ..Config::default().with_runner(runner, Source::Cli)
+                };
+                let (actual, _) = run_with(io::Cursor::new(&input), &config).unwrap();
+                let actual: String = actual.iter().map(|s| format!("{s}\n")).collect();
+                assert_eq!(actual, expected, "{runner}");
+            }
+        }
+    }
+
+    /// The runners which report input which isn't valid UTF-8: all of them, except the
+    /// `unchecked` runner outside of debug builds, for which it's undefined behaviour
+    fn checks_utf8() -> impl Iterator<Item = &'static Runner> {
+        Runner::value_variants()
+            .iter()
+            .filter(|&&runner| runner != Runner::Unchecked || cfg!(debug_assertions))
+    }
+
+    #[test]
+    fn invalid_utf8_offset() {
+        // Plenty of lines so the parallel runners split the input up, with one station name
+        // corrupted part way through a line near the end
+        let mut input = Vec::new();
+        let mut expected = 0;
+        for i in 0..2000 {
+            if i == 1500 {
+                expected = input.len() as u64 + 3;
+                input.extend_from_slice(b"Sta\xFFion;12.3\n");
+            } else {
+                input.extend_from_slice(format!("Station {};{}.5\n", i % 50, i % 90).as_bytes());
+            }
+        }
+
+        for &runner in checks_utf8() {
+            for strict in [false, true] {
+                let config = Config {
+                    strict: Setting::new(strict, Source::Cli),
+                    threads: Setting::new(4, Source::Cli),
+                    buffer_size: Setting::new(4096, Source::Cli),
+                    ..Config::default().with_runner(runner, Source::Cli)
+                };
+                let err = run_with(io::Cursor::new(input.clone()), &config).unwrap_err();
+                match err.downcast_ref::<ChallengeError>() {
+                    Some(ChallengeError::InvalidUtf8 { offset, preview }) => {
+                        assert_eq!(*offset, expected, "{runner}");
+                        // Runners which check names lazily only have the name to show
+                        assert!(preview.starts_with(b"\xFFion"), "{runner}: {preview:?}");
+                    }
+                    _ => panic!("{runner}: unexpected error {err}"),
+                }
+                assert!(
+                    err.to_string()
+                        .contains(&format!("offset {expected}: ff 69 6f 6e")),
+                    "{err}"
+                );
+            }
+        }
+    }
+
+    /// `Zürich` & `São Paulo` in Latin-1, on which Windows-1252 agrees
+    const LATIN1_DATA: &[u8] = include_bytes!("../../tests/data/latin1.txt");
+
+    #[cfg(feature = "encodings")]
+    #[test]
+    fn legacy_encodings() {
+        use crate::aggregate::Encoding;
+
+        // Every character in Latin-1 is the code point of its byte
+        let utf8 = "Zürich;12.0\nSão Paulo;25.3\nZürich;-3.5\nHamburg;8.0\nSão Paulo;19.1\n";
+        assert_eq!(
+            utf8.chars().map(|c| c as u8).collect::<Vec<_>>(),
+            LATIN1_DATA
+        );
+        let baseline = Config::default().with_runner(Runner::Baseline, Source::Cli);
+        let (expected, _) = run_with(io::Cursor::new(&utf8), &baseline).unwrap();
+        let names: Vec<&str> = expected.iter().map(|s| s.name()).collect();
+        assert_eq!(names, ["Hamburg", "São Paulo", "Zürich"]);
+
+        for &runner in Runner::value_variants() {
+            for encoding in [Encoding::Latin1, Encoding::Windows1252] {
+                let config = Config {
+                    encoding: Setting::new(encoding, Source::Cli),
+                    threads: Setting::new(3, Source::Cli),
+                    buffer_size: Setting::new(16, Source::Cli),
+                    ..Config::default().with_runner(runner, Source::Cli)
+                };
+                let (actual, _) = run_with(io::Cursor::new(LATIN1_DATA), &config).unwrap();
+                // Each line's measurement reached the station it was on, not just its name
+                assert_eq!(
+                    Format::Text.render(&actual),
+                    Format::Text.render(&expected),
+                    "{runner}, {encoding}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn latin1_as_utf8() {
+        for &runner in checks_utf8() {
+            let config = Config {
+                threads: Setting::new(3, Source::Cli),
+                ..Config::default().with_runner(runner, Source::Cli)
+            };
+            let err = run_with(io::Cursor::new(LATIN1_DATA), &config).unwrap_err();
+            match err.downcast_ref::<ChallengeError>() {
+                // The `ü` of the first `Zürich`
+                Some(ChallengeError::InvalidUtf8 { offset: 1, preview }) => {
+                    assert!(preview.starts_with(b"\xFCrich"), "{runner}: {preview:?}");
+                }
+                _ => panic!("{runner}: unexpected error {err}"),
+            }
+        }
+    }
+
+    #[test]
+    fn month_station() {
+        // August comes first in the input but last in the results
+        let mut input = String::new();
+        for month in ["2023-08", "2023-07"] {
+            for line in TEST_DATA.lines() {
+                input.push_str(&format!("{month};{line}\n"));
+            }
+        }
+        input.push_str("2023-07;Shimanto;-10.0\n");
+
+        let mut expected = Vec::new();
+        for month in ["2023-07", "2023-08"] {
+            for station in EXPECTED_RESULT.iter() {
+                let (min, avg, count) = match (month, station.name()) {
+                    ("2023-07", "Shimanto") => (-10.0, 28.72, 5),
+                    _ => (station.min(), station.avg(), station.count()),
+                };
+                expected.push(
+                    StationInfo::new(
+                        format!("{month};{}", station.name()),
+                        min,
+                        station.max(),
+                        avg,
+                        count,
+                    )
+                    .keyed_by(KeyFormat::MonthStation),
+                );
+            }
+        }
+        let expected = Format::Text.render(&expected);
+
+        for &runner in Runner::value_variants() {
+            let config = Config {
+                key_format: Setting::new(KeyFormat::MonthStation, Source::Cli),
+                ..Config::default().with_runner(runner, Source::Cli)
+            };
+            let (stations, _) = run_with(io::Cursor::new(input.clone()), &config).unwrap();
+            assert_eq!(Format::Text.render(&stations), expected, "{runner}");
+            assert_eq!(stations[0].station(), "Aïn el Mediour", "{runner}");
+            assert_eq!(stations[0].month(), Some("2023-07"), "{runner}");
+        }
+    }
+
+    #[test]
+    fn mixed_key_formats() {
+        let months = "2023-07;Hamburg;12.3\nBulawayo;8.9\n";
+        let stations = "Hamburg;12.3\n2023-07;Bulawayo;8.9\n";
+        for &runner in Runner::value_variants() {
+            let config = Config {
+                key_format: Setting::new(KeyFormat::MonthStation, Source::Cli),
+                ..Config::default().with_runner(runner, Source::Cli)
+            };
+            let err = run_with(io::Cursor::new(months), &config).unwrap_err();
+            assert!(
+                err.to_string().contains("Malformed line 2") && err.to_string().contains("YYYY-MM"),
+                "{runner}: {err}"
+            );
+
+            let config = Config::default().with_runner(runner, Source::Cli);
+            let err = run_with(io::Cursor::new(stations), &config).unwrap_err();
+            assert!(
+                err.to_string().contains("Malformed line 2")
+                    && err.to_string().contains("month-station"),
+                "{runner}: {err}"
+            );
+        }
+    }
+
+    #[test]
+    fn delimiters() {
+        for (delimiter, decimal_comma) in [(b'\t', false), (b',', false), (b',', true)] {
+            let input: String = TEST_DATA
+                .lines()
+                .map(|line| {
+                    let (station, measurement) = line.split_once(';').unwrap();
+                    let measurement = match decimal_comma {
+                        true => measurement.replace('.', ","),
+                        false => measurement.to_owned(),
+                    };
+                    format!("{station}{}{measurement}\n", delimiter as char)
+                })
+                .collect();
+
+            for &runner in Runner::value_variants() {
+                let config = Config {
+                    delimiter: Setting::new(Delimiter::new(delimiter).unwrap(), Source::Cli),
+                    decimal_comma: Setting::new(decimal_comma, Source::Cli),
+                    strict: Setting::new(true, Source::Cli),
+                    ..Config::default().with_runner(runner, Source::Cli)
+                };
+                let (stations, _) = run_with(io::Cursor::new(input.clone()), &config).unwrap();
+                assert_eq!(
+                    Format::Text.render(&stations),
+                    clean(runner),
+                    "{runner} with {:?}",
+                    config.dialect()
+                );
+            }
+        }
+    }
+
+    /// A `;` is only another character in a name once it isn't the delimiter, and a month is
+    /// only split off a key in the month-station format, whatever the delimiter
+    #[test]
+    fn semicolons_in_names() {
+        let tab = Setting::new(Delimiter::new(b'\t').unwrap(), Source::Cli);
+        for &runner in Runner::value_variants() {
+            let config = Config {
+                delimiter: tab.clone(),
+                ..Config::default().with_runner(runner, Source::Cli)
+            };
+            let input = "Foo;Bar\t12.3\n2023-07;Hamburg\t-1.0\n";
+            let (stations, _) = run_with(io::Cursor::new(input), &config).unwrap();
+            assert_eq!(
+                Format::Text.render(&stations),
+                "{2023-07;Hamburg=-1.0/-1.0/-1.0, Foo;Bar=12.3/12.3/12.3}\n",
+                "{runner}"
+            );
+            assert_eq!(stations[1].station(), "Foo;Bar", "{runner}");
+            assert_eq!(stations[1].month(), None, "{runner}");
+
+            let config = Config {
+                key_format: Setting::new(KeyFormat::MonthStation, Source::Cli),
+                ..config
+            };
+            let input = "2023-07\tFoo;Bar\t12.3\n";
+            let (stations, _) = run_with(io::Cursor::new(input), &config).unwrap();
+            assert_eq!(
+                Format::Text.render(&stations),
+                "{Foo;Bar (2023-07)=12.3/12.3/12.3}\n",
+                "{runner}"
+            );
+            assert_eq!(stations[0].month(), Some("2023-07"), "{runner}");
+        }
+    }
+
+    #[test]
+    fn track_extents() {
+        let mut input = String::new();
+        for row in 1..=30 {
+            let station = match row {
+                5 | 27 => "Abéché",
+                _ => ["Hamburg", "Bulawayo", "Palembang"][row % 3],
+            };
+            input.push_str(&format!("{station};{}.5\n", row % 20));
+        }
+        let expected = [
+            ("Abéché", 5, 27),
+            ("Bulawayo", 1, 28),
+            ("Hamburg", 3, 30),
+            ("Palembang", 2, 29),
+        ];
+
+        let config = Config {
+            track_extents: Setting::new(true, Source::Cli),
+            buffer_size: Setting::new(140, Source::Cli),
+            threads: Setting::new(3, Source::Cli),
+            ..Config::default()
+        };
+        let mut reader = crate::blocks::BlockReader::new(input.as_bytes(), &config);
+        let mut blocks = 0;
+        while reader.next_block().unwrap().is_some() {
+            blocks += 1;
+        }
+        assert_eq!(
+            blocks, 3,
+            "The parallel runners should split the input in 3"
+        );
+
+        for &runner in Runner::value_variants() {
+            let config = config.with_runner(runner, Source::Cli);
+            let (stations, _) = run_with(io::Cursor::new(input.clone()), &config).unwrap();
+            let extents: Vec<_> = stations
+                .iter()
+                .map(|s| {
+                    let extents = s.extents().unwrap();
+                    (s.name(), extents.first_row, extents.last_row)
+                })
+                .collect();
+            assert_eq!(extents, expected, "{runner}");
+
+            let config = Config {
+                track_extents: Setting::new(false, Source::Cli),
+                ..config
+            };
+            let (stations, _) = run_with(io::Cursor::new(input.clone()), &config).unwrap();
+            assert!(stations.iter().all(|s| s.extents().is_none()), "{runner}");
+        }
+    }
+
+    /// A runner which can't run here falls back down the chain to one which can, but only if
+    /// it's down to the environment rather than the input
+    /// Every runner solves only the bytes of a `--byte-range`, splitting them up between its
+    /// threads like a whole input, and adjacent ranges add up to the whole file
+    #[test]
+    fn byte_range() -> Result<(), Box<dyn std::error::Error>> {
+        use crate::window::ByteRange;
+
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("measurements.txt");
+        let data = format!("{TEST_DATA}Hamburg;12.0\n").repeat(40);
+        std::fs::write(&path, &data)?;
+        let len = data.len() as u64;
+
+        let mut file = std::fs::File::open(&path)?;
+        let split = len / 3;
+        let windows = [
+            ByteRange {
+                start: 0,
+                end: split,
+            }
+            .align(&mut file, len, 1024)?,
+            ByteRange {
+                start: split,
+                end: len,
+            }
+            .align(&mut file, len, 1024)?,
+        ];
+        assert_eq!(windows[0].end, windows[1].start);
+        assert!(!windows[0].is_empty() && windows[0].end < len);
+
+        // The means of the test data are halfway between tenths, so can round either way
+        // depending on the order they're summed in; the counts & extremes can't
+        let counts = |stations: &[StationInfo]| -> Vec<(String, u32, f32, f32)> {
+            stations
+                .iter()
+                .map(|s| (s.name().to_owned(), s.count(), s.min(), s.max()))
+                .collect()
+        };
+        let baseline = Config::default().with_runner(Runner::Baseline, Source::Cli);
+        let (whole, _) = run_with(io::Cursor::new(&data), &baseline)?;
+
+        for &runner in Runner::value_variants() {
+            let mut total: Vec<(String, u32, f32, f32)> = Vec::new();
+            for window in &windows {
+                let bytes = &data[window.start as usize..window.end as usize];
+                let (expected, _) = run_with(io::Cursor::new(bytes), &baseline)?;
+
+                let mut config = Config {
+                    canonical_input: Setting::new(path.clone(), Source::Cli),
+                    threads: Setting::new(3, Source::Cli),
+                    ..Config::default().with_runner(runner, Source::Cli)
+                };
+                config.byte_range = Some(window.clone());
+                let (actual, _) = run(&config)?;
+                assert_eq!(counts(&actual), counts(&expected), "{runner} on {window:?}");
+
+                for (name, count, min, max) in counts(&actual) {
+                    match total.iter_mut().find(|(n, ..)| *n == name) {
+                        Some(total) => {
+                            total.1 += count;
+                            total.2 = total.2.min(min);
+                            total.3 = total.3.max(max);
+                        }
+                        None => total.push((name, count, min, max)),
+                    }
+                }
+            }
+            total.sort_by(|a, b| a.0.cmp(&b.0));
+            assert_eq!(total, counts(&whole), "{runner}");
+        }
+
+        Ok(())
+    }
+
+    /// Every runner solves exactly the first lines of a `--limit`, the same as reading just
+    /// those lines, however many threads it splits them between
+    #[test]
+    fn limit() -> Result<(), Box<dyn std::error::Error>> {
+        use crate::window::first_lines;
+
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("measurements.txt");
+        let data = format!("{TEST_DATA}Hamburg;12.0\n").repeat(40);
+        std::fs::write(&path, &data)?;
+        let len = data.len() as u64;
+        let total = data.lines().count() as u64;
+
+        // As in `byte_range`, only the counts & extremes are the same whatever the order
+        let counts = |stations: &[StationInfo]| -> Vec<(String, u32, f32, f32)> {
+            stations
+                .iter()
+                .map(|s| (s.name().to_owned(), s.count(), s.min(), s.max()))
+                .collect()
+        };
+        let baseline = Config::default().with_runner(Runner::Baseline, Source::Cli);
+
+        let mut file = std::fs::File::open(&path)?;
+        for lines in [0, 1, 7, total / 3, total - 1, total, total + 100] {
+            let window = first_lines(&mut file, len, lines)?;
+            let first: String = data
+                .lines()
+                .take(lines as usize)
+                .map(|l| format!("{l}\n"))
+                .collect();
+            assert_eq!(window, 0..first.len() as u64);
+            let (expected, _) = run_with(io::Cursor::new(&first), &baseline)?;
+
+            for &runner in Runner::value_variants() {
+                for threads in [1, 3] {
+                    let mut config = Config {
+                        canonical_input: Setting::new(path.clone(), Source::Cli),
+                        threads: Setting::new(threads, Source::Cli),
+                        ..Config::default().with_runner(runner, Source::Cli)
+                    };
+                    config.byte_range = Some(window.clone());
+                    let (actual, _) = run(&config)?;
+                    assert_eq!(
+                        counts(&actual),
+                        counts(&expected),
+                        "{runner} on {threads} threads, limited to {lines} lines"
+                    );
+                    // The same lines are summed the same way every time
+                    assert_eq!(
+                        bits(&run(&config)?.0),
+                        bits(&actual),
+                        "{runner} isn't deterministic"
+                    );
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Every runner solves the same lines of a `--sample-rate`, the lines of the blocks picked
+    #[test]
+    fn sample() -> Result<(), Box<dyn std::error::Error>> {
+        use crate::sample::Sample;
+        use std::io::Read;
+
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("measurements.txt");
+        let data = format!("{TEST_DATA}Hamburg;12.0\n").repeat(400);
+        std::fs::write(&path, &data)?;
+
+        let counts = |stations: &[StationInfo]| -> Vec<(String, u32, f32, f32)> {
+            stations
+                .iter()
+                .map(|s| (s.name().to_owned(), s.count(), s.min(), s.max()))
+                .collect()
+        };
+        let sample = Sample::new(0.2, 11);
+        let mut picked = String::new();
+        Sampled::new(io::Cursor::new(&data), sample, 512, 100)?.read_to_string(&mut picked)?;
+        let baseline = Config::default().with_runner(Runner::Baseline, Source::Cli);
+        let (expected, _) = run_with(io::Cursor::new(&picked), &baseline)?;
+        let total: u32 = expected.iter().map(StationInfo::count).sum();
+        assert!((200..1_000).contains(&total), "{total} of 4800 lines");
+
+        for &runner in Runner::value_variants() {
+            let mut config = Config {
+                canonical_input: Setting::new(path.clone(), Source::Cli),
+                buffer_size: Setting::new(512, Source::Cli),
+                threads: Setting::new(3, Source::Cli),
+                ..Config::default().with_runner(runner, Source::Cli)
+            };
+            config.sample = Some(sample);
+            let (actual, _) = run(&config)?;
+            assert_eq!(counts(&actual), counts(&expected), "{runner}");
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn fallback() {
+        let attempts = std::cell::RefCell::new(Vec::new());
+        // Neither the runner at the top of the chain nor the next one can map the input here
+        let mock = |config: &Config| -> ChallengeResult {
+            attempts.borrow_mut().push(config.runner.value);
+            match config.runner.value {
+                Runner::ParMmap | Runner::ScopedThreads => Err(ChallengeError::Unsupported {
+                    what: "mapping the input into memory",
+                    source: io::Error::other("not on this filesystem"),
+                }
+                .into()),
+                _ => run_with(io::Cursor::new(TEST_DATA), config),
+            }
+        };
+        let config = Config::default().with_runner(Runner::ParMmap, Source::Default);
+
+        let (actual, stats) = with_fallback(&config, mock).unwrap();
+        assert_eq!(actual, *EXPECTED_RESULT);
+        assert!(actual
+            .iter()
+            .zip(&*EXPECTED_RESULT)
+            .all(|(a, e)| a.eq_rounded(e)));
+        assert_eq!(
+            stats.fallback,
+            Some(Fallback {
+                from: Runner::ParMmap,
+                to: Runner::AHash
+            })
+        );
+        assert_eq!(
+            attempts.take(),
+            [Runner::ParMmap, Runner::ScopedThreads, Runner::AHash]
+        );
+
+        // Nothing to fall back from
+        let plain = config.with_runner(Runner::AHash, Source::Default);
+        assert_eq!(with_fallback(&plain, mock).unwrap().1.fallback, None);
+        attempts.take();
+
+        // Not when turned off ...
+        let off = Config {
+            fallback: Setting::new(false, Source::Cli),
+            ..config.clone()
+        };
+        let e = with_fallback(&off, mock).unwrap_err();
+        assert!(e
+            .to_string()
+            .starts_with("mapping the input into memory failed"));
+        assert_eq!(attempts.take(), [Runner::ParMmap]);
+
+        // ... nor for errors about the input, which any runner would get wrong
+        let malformed = |config: &Config| -> ChallengeResult {
+            attempts.borrow_mut().push(config.runner.value);
+            Err(ChallengeError::MalformedLine {
+                line: 1,
+                reason: "no measurement",
+            }
+            .into())
+        };
+        assert!(with_fallback(&config, malformed).is_err());
+        assert_eq!(attempts.take(), [Runner::ParMmap]);
+
+        // The chain ends with the baseline
+        let unsupported = |config: &Config| -> ChallengeResult {
+            attempts.borrow_mut().push(config.runner.value);
+            Err(ChallengeError::Unsupported {
+                what: "reading",
+                source: io::Error::other("nothing works"),
+            }
+            .into())
+        };
+        assert!(with_fallback(&config, unsupported).is_err());
+        assert_eq!(attempts.take().last(), Some(&Runner::Baseline));
+    }
+
+    #[test]
+    fn case_insensitive() {
+        // Each station's spelling changes from row to row, starting with an unusual one
+        let mut input = String::new();
+        for row in 1..=30 {
+            let station = match row {
+                5 => "İZMİR",
+                27 => "İzmir",
+                _ => ["Hamburg", "Bulawayo", "Palembang"][row % 3],
+            };
+            let station = match row {
+                5 | 27 => station.to_owned(),
+                _ if row % 4 == 1 => station.to_lowercase(),
+                _ if row % 7 == 2 => station.to_uppercase(),
+                _ => station.to_owned(),
+            };
+            input.push_str(&format!("{station};{}.5\n", row % 20));
+        }
+        let expected = [
+            ("Hamburg", 3, 30),
+            ("PALEMBANG", 2, 29),
+            ("bulawayo", 1, 28),
+            ("İZMİR", 5, 27),
+        ];
+
+        let config = Config {
+            case_insensitive: Setting::new(true, Source::Cli),
+            track_extents: Setting::new(true, Source::Cli),
+            buffer_size: Setting::new(140, Source::Cli),
+            threads: Setting::new(3, Source::Cli),
+            ..Config::default()
+        };
+        for &runner in Runner::value_variants() {
+            let config = config.with_runner(runner, Source::Cli);
+            let (stations, _) = run_with(io::Cursor::new(input.clone()), &config).unwrap();
+            let extents: Vec<_> = stations
+                .iter()
+                .map(|s| {
+                    let extents = s.extents().unwrap();
+                    (s.name(), extents.first_row, extents.last_row)
+                })
+                .collect();
+            assert_eq!(extents, expected, "{runner}");
+            assert_eq!(stations.iter().map(|s| s.count()).sum::<u32>(), 30);
+
+            // Rows are still counted to pick the first spelling, but not reported
+            let config = Config {
+                track_extents: Setting::new(false, Source::Cli),
+                ..config
+            };
+            let (without, _) = run_with(io::Cursor::new(input.clone()), &config).unwrap();
+            assert!(without.iter().all(|s| s.extents().is_none()), "{runner}");
+            let names: Vec<_> = without.iter().map(|s| s.name()).collect();
+            assert_eq!(names, expected.map(|(name, ..)| name), "{runner}");
+        }
+    }
+
+    #[test]
+    fn skipped_lines() {
+        // Two lines of each kind of malformation, interleaved with the test data
+        let long = "x".repeat(100);
+        let malformed: [&[u8]; 10] = [
+            b"Hamburg 12.0",
+            b"Hamburg;warm",
+            b"Ham\xffburg;12.0",
+            long.as_bytes(),
+            b"Hamburg;1e40",
+            b"Bulawayo",
+            b"Bulawayo;1.2.3",
+            b"Bul\xfeawayo;8.9",
+            long.as_bytes(),
+            b"Hamburg;150.0",
+        ];
+        let mut input = Vec::new();
+        for (line, bad) in TEST_DATA.lines().zip(malformed) {
+            input.extend_from_slice(bad);
+            input.extend_from_slice(b"\n");
+            input.extend_from_slice(line.as_bytes());
+            input.extend_from_slice(b"\n");
+        }
+        input.extend_from_slice(TEST_DATA.lines().last().unwrap().as_bytes());
+        let expected = SkippedLines {
+            no_semicolon: 2,
+            bad_temperature: 2,
+            invalid_utf8: 2,
+            too_long: 2,
+            out_of_range: 2,
+            other: 0,
+        };
+
+        for &runner in Runner::value_variants() {
+            let config = |max_skipped| Config {
+                max_line_length: Setting::new(64, Source::Cli),
+                max_skipped: Setting::new(max_skipped, Source::Cli),
+                ..Config::default().with_runner(runner, Source::Cli)
+            };
+
+            let (stations, stats) = run_with(io::Cursor::new(&input), &config(10)).unwrap();
+            assert_eq!(Format::Text.render(&stations), clean(runner), "{runner}");
+            assert_eq!(stats.skipped, expected, "{runner}");
+
+            let err = run_with(io::Cursor::new(&input), &config(9)).unwrap_err();
+            assert!(
+                matches!(
+                    err.downcast_ref::<ChallengeError>(),
+                    Some(ChallengeError::TooManySkipped { limit: 9, .. })
+                ),
+                "{runner}: {err}"
+            );
+
+            // Nothing is skipped by default
+            let err = run_with(io::Cursor::new(&input), &config(0)).unwrap_err();
+            assert!(
+                matches!(
+                    err.downcast_ref::<ChallengeError>(),
+                    Some(ChallengeError::MalformedLine { line: 1, .. })
+                ),
+                "{runner}: {err}"
+            );
+        }
+    }
+
+    /// Lines straddling blocks, without a final newline & which a runner's fast path can't split
+    /// must be handled exactly as the baseline handles them, stats & skipped lines alike
+    #[test]
+    fn matches_line_reader() {
+        let mut input = b"\xEF\xBB\xBFA;1.0\r\nB;2\nA;B;3.0\n".to_vec();
+        input.extend_from_slice(&[b'L'; 40]);
+        input.extend_from_slice(b";3.0\nC\xFF;4.0\n\n");
+        input.extend_from_slice(&[b'M'; 29]);
+        input.extend_from_slice(b";-12.5\nA;-1.0");
+
+        let config = |runner| Config {
+            max_line_length: Setting::new(32, Source::Cli),
+            max_skipped: Setting::new(10, Source::Cli),
+            ..Config::default().with_runner(runner, Source::Cli)
+        };
+        let (expected, expected_stats) =
+            run_with(io::Cursor::new(&input), &config(Runner::Baseline)).unwrap();
+        for &runner in Runner::value_variants() {
+            let (actual, stats) = run_with(io::Cursor::new(&input), &config(runner)).unwrap();
+            assert_eq!(bits(&actual), bits(&expected), "{runner}");
+            assert_eq!(stats.skipped, expected_stats.skipped, "{runner}");
+        }
+    }
+
+    #[test]
+    fn split_timings() {
+        for &runner in Runner::value_variants() {
+            let config = Config::default().with_runner(runner, Source::Cli);
+            let (_, stats) = run_with(io::Cursor::new(TEST_DATA), &config).unwrap();
+            assert_eq!(stats.timings.aggregated, None, "{runner}");
+
+            let config = Config {
+                timings: Setting::new(true, Source::Cli),
+                ..config
+            };
+            let (_, stats) = run_with(io::Cursor::new(TEST_DATA), &config).unwrap();
+            let timings = stats.timings;
+            let Some(aggregated) = timings.aggregated else {
+                panic!("{runner} didn't time its aggregation");
+            };
+            assert!(aggregated <= timings.total, "{runner}: {timings:?}");
+        }
+    }
+
+    #[test]
+    fn byte_order_mark() {
+        let input = format!("\u{FEFF}{TEST_DATA}");
+
+        for (runner, result) in run_all(&input, false) {
+            let (actual, _) = result.unwrap();
+            assert_eq!(
+                Format::Text.render(&actual),
+                clean(runner),
+                "BOM changed the output of the {runner} runner"
+            );
+        }
+
+        for (runner, result) in run_all(&input, true) {
+            let err = result.unwrap_err();
+            assert!(
+                matches!(
+                    err.downcast_ref::<ChallengeError>(),
+                    Some(ChallengeError::ByteOrderMark)
+                ),
+                "{runner} runner: {err}"
+            );
+        }
+    }
+
+    #[test]
+    fn leading_blank_lines() {
+        let input = format!("\n\n{TEST_DATA}");
+
+        for (runner, result) in run_all(&input, false) {
+            let (actual, _) = result.unwrap();
+            assert_eq!(
+                Format::Text.render(&actual),
+                clean(runner),
+                "{runner} runner"
+            );
+        }
+
+        for (runner, result) in run_all(&input, true) {
+            let err = result.unwrap_err();
+            assert!(
+                matches!(
+                    err.downcast_ref::<ChallengeError>(),
+                    Some(ChallengeError::MalformedLine { line: 1, .. })
+                ),
+                "{runner} runner: {err}"
+            );
+        }
+    }
+
+    #[test]
+    fn non_canonical_measurements() {
+        for (line, expected) in [
+            ("Hamburg;12", 12.0),
+            ("Hamburg;12.00", 12.0),
+            ("Hamburg;+12.3", 12.3),
+            ("Hamburg;12.", 12.0),
+        ] {
+            let input = format!("Bulawayo;8.9\n{line}\n");
+
+            for (runner, result) in run_all(&input, false) {
+                let (actual, _) = result.unwrap();
+                let hamburg = actual.iter().find(|s| s.name() == "Hamburg").unwrap();
+                assert_eq!(hamburg.min(), expected, "{runner} runner parsing {line:?}");
+            }
+
+            for (runner, result) in run_all(&input, true) {
+                let err = result.unwrap_err();
+                assert!(
+                    matches!(
+                        err.downcast_ref::<ChallengeError>(),
+                        Some(ChallengeError::MalformedLine { line: 2, .. })
+                    ),
+                    "{runner} runner parsing {line:?}: {err}"
+                );
+            }
+        }
+    }
 }
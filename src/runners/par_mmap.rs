@@ -0,0 +1,359 @@
+// 1BRC - my take on the 1 Billion Row Challenge
+// Copyright (C) 2024  Charles German <5donuts@pm.me>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Map the input into memory, split it into newline-aligned chunks, and aggregate the chunks on
+//! several threads into maps of their own which are merged at the end.
+
+use std::collections::HashMap;
+use std::io;
+use std::path::Path;
+use std::time::Instant;
+
+use ahash::RandomState;
+
+use crate::aggregate::{merge_case_variants_by_row, StationData};
+use crate::blocks::{count_newlines, Block, BlockFailure};
+use crate::boundaries::first_newline;
+use crate::config::Config;
+use crate::error::SkippedLines;
+use crate::helpers::*;
+use crate::manifest::ChunkEntry;
+use crate::plan::chunk_boundaries;
+use crate::reader::leading_bom;
+
+use super::mmap::map_file;
+
+pub struct Runner;
+
+/// What was aggregated from a single chunk of the input
+struct Partial {
+    stations: HashMap<String, StationData, RandomState>,
+
+    /// How many lines the chunk has, if counting rows; the rows recorded in `stations` are
+    /// relative to the start of the chunk until they're merged
+    lines: u64,
+
+    /// Malformed lines skipped in this chunk
+    skipped: SkippedLines,
+}
+
+impl Runner {
+    /// Map the file at `path` into memory & aggregate newline-aligned chunks of it on several
+    /// threads, without copying any of it.
+    pub fn run_file(path: &Path, config: &Config) -> ChallengeResult {
+        let start = Instant::now();
+        match map_file(path, config.byte_range.as_ref())? {
+            Some(map) => aggregate(&map, start, config),
+            None => aggregate(&[], start, config),
+        }
+    }
+}
+
+impl ChallengeRunner for Runner {
+    /// Only a file can be mapped, so any other input (e.g. a `Cursor` in tests) is read into
+    /// memory in one go & split up from there instead; see [`Runner::run_file`].
+    fn run<R>(mut input: R, config: &Config) -> ChallengeResult
+    where
+        R: std::io::Read + std::io::Seek,
+    {
+        let start = Instant::now();
+
+        let mut data = Vec::new();
+        input.read_to_end(&mut data)?;
+        aggregate(&data, start, config)
+    }
+}
+
+/// Split `data` into `--num-chunks` newline-aligned chunks, aggregate them on up to `--threads`
+/// threads, & merge the results.
+///
+/// Each chunk gets a map of its own and the maps are merged in the order of the chunks, so the
+/// results only depend on the number of chunks, not on the number of threads or how they're
+/// scheduled.
+fn aggregate(data: &[u8], start: Instant, config: &Config) -> ChallengeResult {
+    if let Some(recorder) = &config.chunk_manifest {
+        recorder.start();
+    }
+    let max_skipped = config.max_skipped.value;
+    let mut skipped = SkippedLines::default();
+
+    // A byte-order mark can only be at the very start of the input. In strict mode, the line
+    // it's on is malformed & skipped (if allowed to be) like any other runner would.
+    let first = match leading_bom(data, config.strict.value, config.verbose) {
+        Ok(skip) => skip,
+        Err(e) => {
+            skipped.skip(e, 1, max_skipped)?;
+            first_newline(data).map_or(data.len(), |idx| idx + 1)
+        }
+    };
+    let mut chunks = chunk_boundaries(
+        &mut io::Cursor::new(data),
+        data.len() as u64,
+        config.num_chunks.value,
+        config.max_line_length.value,
+    )?;
+    // The first line always ends within the first chunk, since chunks start at line starts
+    if let Some(chunk) = chunks.first_mut() {
+        chunk.start = chunk.start.max(first as u64);
+    }
+    chunks.retain(|chunk| !chunk.is_empty());
+
+    let threads = config.threads.value.clamp(1, chunks.len().max(1));
+    let mut results: Vec<(usize, Result<Partial, BlockFailure>)> = std::thread::scope(|s| {
+        let workers: Vec<_> = (0..threads)
+            .map(|worker| {
+                let chunks = &chunks;
+                s.spawn(move || {
+                    chunks
+                        .iter()
+                        .enumerate()
+                        .skip(worker)
+                        .step_by(threads)
+                        .map(|(i, chunk)| {
+                            let block = Block {
+                                offset: chunk.start,
+                                data: &data[chunk.start as usize..chunk.end as usize],
+                                first_line: None,
+                            };
+                            (i, aggregate_chunk(&block, config))
+                        })
+                        .collect::<Vec<_>>()
+                })
+            })
+            .collect();
+        workers
+            .into_iter()
+            .flat_map(|worker| worker.join().expect("Worker thread panicked"))
+            .collect()
+    });
+    results.sort_unstable_by_key(|&(i, _)| i);
+
+    // Report the error closest to the start of the input, like a sequential runner would
+    let mut partials = Vec::with_capacity(results.len());
+    let mut first_failure: Option<BlockFailure> = None;
+    for (_, result) in results {
+        match result {
+            Ok(partial) => partials.push(partial),
+            Err(failure) => {
+                if first_failure
+                    .as_ref()
+                    .is_none_or(|first| failure.offset < first.offset)
+                {
+                    first_failure = Some(failure);
+                }
+            }
+        }
+    }
+    if let Some(failure) = first_failure {
+        return Err(failure.locate(&mut io::Cursor::new(data)).into());
+    }
+
+    // Fold each chunk's map into the total in order, moving the rows each station was on from
+    // being relative to its chunk to being relative to the whole input
+    let track_rows = config.track_rows();
+    let mut lines_before = chunks
+        .first()
+        .map_or(0, |chunk| count_newlines(&data[..chunk.start as usize]));
+    let mut totals: HashMap<String, StationData, RandomState> = HashMap::default();
+    for partial in partials {
+        for (name, mut data) in partial.stations {
+            if track_rows && data.last_row > 0 {
+                data.first_row += lines_before;
+                data.last_row += lines_before;
+            }
+            totals
+                .entry(name)
+                .or_insert_with(StationData::empty)
+                .merge(&data);
+        }
+        lines_before += partial.lines;
+        // Each chunk only knows about the lines skipped in it
+        skipped.merge(&partial.skipped);
+    }
+    skipped.check(max_skipped)?;
+
+    let aggregated = Instant::now();
+    let ignored = totals.values().map(|data| data.skipped as u64).sum();
+
+    // Spellings of the same station were kept apart until now, with the rows they were first on
+    let totals: Vec<(String, StationData)> = if config.case_insensitive.value {
+        merge_case_variants_by_row(totals, config.track_extents.value)
+    } else {
+        totals.into_iter().collect()
+    };
+
+    // Build the alphabetically-sorted list of stations
+    let mut stations: Vec<StationInfo> = totals
+        .into_iter()
+        .filter(|(_, data)| data.cnt > 0)
+        .map(|(name, data)| {
+            StationInfo::new(name, data.min, data.max, data.avg(), data.cnt)
+                .with_extents(data.extents())
+        })
+        .collect();
+    stations.sort_unstable();
+
+    let stats = RunStats::new(Timings::since(start, aggregated, config))
+        .ignored_non_finite(ignored)
+        .skipped(skipped);
+
+    Ok((stations, stats))
+}
+
+/// Aggregate every line of a single chunk into a map of its own
+fn aggregate_chunk(block: &Block<&[u8]>, config: &Config) -> Result<Partial, BlockFailure> {
+    let mut partial = Partial {
+        stations: HashMap::default(),
+        lines: 0,
+        skipped: SkippedLines::default(),
+    };
+    let track_rows = config.track_rows();
+
+    let mut rows = 0;
+    let stations = &mut partial.stations;
+    block.for_each_record(
+        config.max_line_length.value,
+        config.strict.value,
+        config.dialect(),
+        config.max_skipped.value,
+        &mut partial.skipped,
+        |station, measurement, line| {
+            rows += 1;
+            if !stations.contains_key(station) {
+                stations.insert(station.to_owned(), StationData::empty());
+            }
+            let data = stations
+                .get_mut(station)
+                .expect("The station was just added");
+            data.push(measurement, config.strict.value);
+            if track_rows {
+                data.record_row(line);
+            }
+        },
+    )?;
+    if let Some(recorder) = &config.chunk_manifest {
+        recorder.record(ChunkEntry::new(block, rows));
+    }
+    // Only the lines before later chunks matter, and every chunk but the last ends with a newline
+    if track_rows {
+        partial.lines = count_newlines(block.data);
+    }
+
+    Ok(partial)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{Setting, Source};
+    use crate::output::Format;
+    use crate::runners::tests::*;
+    use crate::Runner as Kind;
+    use std::{error, io::Write};
+
+    fn config(num_chunks: usize, threads: usize) -> Config {
+        Config {
+            num_chunks: Setting::new(num_chunks, Source::Cli),
+            threads: Setting::new(threads, Source::Cli),
+            ..Config::default().with_runner(Kind::ParMmap, Source::Cli)
+        }
+    }
+
+    #[test]
+    fn correctness() -> Result<(), Box<dyn error::Error>> {
+        let input = io::Cursor::new(TEST_DATA.as_bytes());
+
+        let (actual, _) = Runner::run(input, &config(4, 4))?;
+        assert_eq!(
+            actual, *EXPECTED_RESULT,
+            "actual != expected for par-mmap runner"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn chunk_boundary_mid_line() -> Result<(), Box<dyn error::Error>> {
+        // Evenly split, the boundaries of 2 & 3 chunks fall in the middle of lines; with enough
+        // chunks, one falls on every byte of a line
+        let len = TEST_DATA.len();
+        let midpoint = len / 2;
+        assert!(
+            TEST_DATA.as_bytes()[midpoint - 1] != b'\n' && TEST_DATA.as_bytes()[midpoint] != b'\n',
+            "The midpoint should be in the middle of a line"
+        );
+
+        for num_chunks in (1..=8).chain([len / 4, len]) {
+            for threads in [1, 3] {
+                let (actual, _) = Runner::run(
+                    io::Cursor::new(TEST_DATA.as_bytes()),
+                    &config(num_chunks, threads),
+                )?;
+                assert_eq!(
+                    actual, *EXPECTED_RESULT,
+                    "{num_chunks} chunks on {threads} threads"
+                );
+            }
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn mapped_file() -> Result<(), Box<dyn error::Error>> {
+        let mut file = tempfile::NamedTempFile::new()?;
+        file.write_all(TEST_DATA.as_bytes())?;
+
+        let mut config = config(3, 2);
+        config.canonical_input.value = file.path().to_owned();
+        let (actual, _) = crate::runners::run(&config)?;
+        assert_eq!(
+            actual, *EXPECTED_RESULT,
+            "mapping the file changed the result"
+        );
+
+        let empty = tempfile::NamedTempFile::new()?;
+        let (actual, _) = Runner::run_file(empty.path(), &config)?;
+        assert!(actual.is_empty());
+
+        Ok(())
+    }
+
+    /// Rows & the line numbers of errors are counted from the start of the input, not the chunk
+    #[test]
+    fn rows_span_chunks() -> Result<(), Box<dyn error::Error>> {
+        let input = format!("\u{FEFF}{TEST_DATA}\n{TEST_DATA}");
+        let mut config = config(5, 2);
+        config.track_extents.value = true;
+        let baseline = Config {
+            track_extents: config.track_extents.clone(),
+            ..Config::default().with_runner(Kind::Baseline, Source::Cli)
+        };
+
+        let (expected, _) = crate::runners::run_with(io::Cursor::new(&input), &baseline)?;
+        let (actual, _) = Runner::run(io::Cursor::new(&input), &config)?;
+        assert_eq!(Format::Text.render(&actual), Format::Text.render(&expected));
+        assert!(actual.iter().all(|station| station.extents().is_some()));
+
+        let malformed = format!("{TEST_DATA}{TEST_DATA}Nowhere\n{TEST_DATA}");
+        let expected = crate::runners::run_with(io::Cursor::new(&malformed), &baseline)
+            .expect_err("The baseline runner accepted a malformed line");
+        let actual = Runner::run(io::Cursor::new(&malformed), &config)
+            .expect_err("A malformed line was accepted");
+        assert_eq!(actual.to_string(), expected.to_string());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,119 @@
+// 1BRC - my take on the 1 Billion Row Challenge
+// Copyright (C) 2024  Charles German <5donuts@pm.me>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Parsers specialized for the challenge's input, for the runners to share. Unlike those in
+//! [`aggregate`](crate::aggregate), these only handle exactly what the challenge generates.
+
+use crate::aggregate::NON_CANONICAL_MEASUREMENT;
+
+/// Parse a measurement in the canonical form (an optional `-`, one or two integer digits, a `.`
+/// & one decimal digit) as a whole number of tenths, e.g. `-12.3` as `-123`.
+///
+/// Rather than matching on the number of digits like
+/// [`parse_tenths`](crate::aggregate::parse_tenths), the digits are right-aligned into four bytes
+/// with a `0` for a missing tens digit, so both lengths are checked & decoded by the same few
+/// arithmetic ops. The only branches are on the length & on whether every byte was what it
+/// should be; anything else is an error, never a made-up number.
+#[inline]
+pub fn parse_tenths_branchless(s: &[u8]) -> Result<i16, &'static str> {
+    let negative = s.first() == Some(&b'-');
+    let digits = &s[negative as usize..];
+    if !(3..=4).contains(&digits.len()) {
+        return Err(NON_CANONICAL_MEASUREMENT);
+    }
+
+    let mut word = [b'0'; 4];
+    word[4 - digits.len()..].copy_from_slice(digits);
+    // Anything below `0` wraps around, so a byte is a digit exactly when this is under 10
+    let [tens, ones, point, tenths] = word.map(|b| b.wrapping_sub(b'0'));
+    let valid = (tens < 10) & (ones < 10) & (point == b'.'.wrapping_sub(b'0')) & (tenths < 10);
+
+    let magnitude = tens as i16 * 100 + ones as i16 * 10 + tenths as i16;
+    let sign = 1 - 2 * negative as i16;
+    if valid {
+        Ok(sign * magnitude)
+    } else {
+        Err(NON_CANONICAL_MEASUREMENT)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::aggregate::parse_tenths;
+
+    /// Every measurement the challenge can generate, with & without a leading zero's sign
+    #[test]
+    fn every_measurement() {
+        for tenths in -999..=999i16 {
+            let sign = if tenths < 0 { "-" } else { "" };
+            let magnitude = tenths.unsigned_abs();
+            let text = format!("{sign}{}.{}", magnitude / 10, magnitude % 10);
+
+            let parsed = parse_tenths_branchless(text.as_bytes());
+            assert_eq!(parsed, Ok(tenths), "{text}");
+            let expected: f32 = text.parse().unwrap();
+            assert_eq!(parsed.unwrap() as f32 / 10.0, expected, "{text}");
+        }
+        assert_eq!(parse_tenths_branchless(b"-0.0"), Ok(0));
+    }
+
+    #[test]
+    fn malformed() {
+        let malformed: [&[u8]; 16] = [
+            b"", b"-", b"1", b"1.", b".5", b"-.5", b"12", b"123.4", b"1.23", b"+1.2", b"--1.2",
+            b"1,2", b"1.2.", b"a.1", b"1.a", b"12.3\n",
+        ];
+        for s in malformed {
+            assert_eq!(
+                parse_tenths_branchless(s),
+                Err(NON_CANONICAL_MEASUREMENT),
+                "{}",
+                String::from_utf8_lossy(s)
+            );
+        }
+    }
+
+    /// Every short string of digits, punctuation & the bytes either side of the digits parses
+    /// the same as the (branching) fixed-layout parser does
+    #[test]
+    fn same_as_parse_tenths() {
+        const BYTES: &[u8] = b"059.-+/:a";
+        let mut strings: Vec<Vec<u8>> = vec![Vec::new()];
+        for len in 1..=6 {
+            let longer: Vec<Vec<u8>> = strings
+                .iter()
+                .filter(|s| s.len() == len - 1)
+                .flat_map(|s| {
+                    BYTES.iter().map(move |&b| {
+                        let mut s = s.clone();
+                        s.push(b);
+                        s
+                    })
+                })
+                .collect();
+            strings.extend(longer);
+        }
+
+        for s in strings {
+            assert_eq!(
+                parse_tenths_branchless(&s).ok(),
+                parse_tenths(&s),
+                "{}",
+                String::from_utf8_lossy(&s)
+            );
+        }
+    }
+}
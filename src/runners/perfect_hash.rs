@@ -0,0 +1,324 @@
+// 1BRC - my take on the 1 Billion Row Challenge
+// Copyright (C) 2024  Charles German <5donuts@pm.me>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Use the same approach as `baseline`, but keep the official generator's stations in an array
+//! indexed by a perfect hash of their names, built when the runner is first used, so each is found
+//! with a single probe. Any other stations are kept in a map.
+
+use std::collections::HashMap;
+use std::sync::OnceLock;
+use std::time::Instant;
+
+use ahash::RandomState;
+
+use crate::aggregate::{merge_case_variants_by_row, parse_record, StationData};
+use crate::config::Config;
+use crate::error::SkippedLines;
+use crate::fingerprint::fnv1a;
+use crate::helpers::*;
+use crate::reader::LineReader;
+
+pub struct Runner;
+
+impl Runner {
+    /// How many bytes the table of official stations & a map of `stations` stations not on the
+    /// official list take up, at most.
+    ///
+    /// This doesn't include the names of the stations in the map.
+    pub fn estimated_size(stations: usize) -> usize {
+        let others = stations.saturating_sub(official().len);
+        // Hash maps keep at least 1/8 of their buckets empty, with a control byte per bucket
+        let bucket = std::mem::size_of::<(String, StationData)>() + 1;
+        SLOTS * std::mem::size_of::<StationData>() + others * bucket * 8 / 7
+    }
+}
+
+/// The stations the official generator draws its measurements from, one per line
+const STATIONS: &str = include_str!("../../data/stations.txt");
+
+/// The number of slots in a [`PerfectHash`]: a power of two, so a hash picks one with a mask
+const SLOTS: usize = 1024;
+
+/// The number of buckets the stations are split into, each placed with a displacement of its own
+const BUCKETS: usize = 256;
+
+/// How many displacements to try for a bucket before giving up on placing its stations
+const MAX_DISPLACEMENT: u32 = 1 << 20;
+
+/// A hash function without collisions for a fixed set of station names, so looking a name up is
+/// a single probe & a comparison.
+///
+/// Names are split into [`BUCKETS`] buckets by their hash. Each bucket has a displacement, picked
+/// when the table is built, which is mixed into the hash of each of its names to pick their
+/// slots, so that no two names share a slot ("hash & displace"). Names which aren't in the set
+/// still land in some slot, so they're told apart by comparing them with the name in it.
+#[derive(Debug)]
+struct PerfectHash {
+    displacements: Vec<u32>,
+
+    /// The name in each slot, if there is one
+    names: Vec<Option<&'static str>>,
+
+    /// The number of names in the table
+    len: usize,
+}
+
+impl PerfectHash {
+    /// Build a table of the given names, or `None` if two of them can't be told apart (e.g. they
+    /// have the same hash)
+    fn build(names: impl IntoIterator<Item = &'static str>) -> Option<Self> {
+        let mut buckets: Vec<Vec<(&'static str, u64)>> = vec![Vec::new(); BUCKETS];
+        let mut len = 0;
+        for name in names {
+            let hash = fnv1a(name.as_bytes());
+            let bucket = &mut buckets[Self::bucket(hash)];
+            if !bucket.iter().any(|&(other, _)| other == name) {
+                bucket.push((name, hash));
+                len += 1;
+            }
+        }
+        if len > SLOTS {
+            return None;
+        }
+
+        // The fullest buckets are the hardest to place, so they go first, while most slots are free
+        let mut order: Vec<usize> = (0..BUCKETS).collect();
+        order.sort_by_key(|&idx| std::cmp::Reverse(buckets[idx].len()));
+
+        let mut table = Self {
+            displacements: vec![0; BUCKETS],
+            names: vec![None; SLOTS],
+            len,
+        };
+        let mut slots = Vec::new();
+        for idx in order
+            .into_iter()
+            .take_while(|&idx| !buckets[idx].is_empty())
+        {
+            let placed = (0..MAX_DISPLACEMENT).any(|displacement| {
+                slots.clear();
+                for &(_, hash) in &buckets[idx] {
+                    let slot = Self::slot_for(hash, displacement);
+                    if table.names[slot].is_some() || slots.contains(&slot) {
+                        return false;
+                    }
+                    slots.push(slot);
+                }
+                table.displacements[idx] = displacement;
+                true
+            });
+            if !placed {
+                return None;
+            }
+            for (&(name, _), &slot) in buckets[idx].iter().zip(&slots) {
+                table.names[slot] = Some(name);
+            }
+        }
+        Some(table)
+    }
+
+    #[inline]
+    fn bucket(hash: u64) -> usize {
+        hash as usize % BUCKETS
+    }
+
+    /// The slot for a name with the given hash in a bucket with the given displacement
+    #[inline]
+    fn slot_for(hash: u64, displacement: u32) -> usize {
+        // The finalizer of SplitMix64, so every bit of the hash & displacement moves the slot
+        let mut z = hash ^ (displacement as u64).wrapping_mul(0x9e37_79b9_7f4a_7c15);
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        (z ^ (z >> 31)) as usize & (SLOTS - 1)
+    }
+
+    /// The slot of the given name, if it's in the table
+    #[inline]
+    fn slot(&self, name: &[u8]) -> Option<usize> {
+        let hash = fnv1a(name);
+        let slot = Self::slot_for(hash, self.displacements[Self::bucket(hash)]);
+        match self.names[slot] {
+            Some(known) if known.as_bytes() == name => Some(slot),
+            _ => None,
+        }
+    }
+}
+
+/// The table of the official stations, built the first time it's needed
+fn official() -> &'static PerfectHash {
+    static TABLE: OnceLock<PerfectHash> = OnceLock::new();
+    TABLE.get_or_init(|| {
+        PerfectHash::build(STATIONS.lines()).expect("The official stations can be told apart")
+    })
+}
+
+impl ChallengeRunner for Runner {
+    /// Same as the baseline, but keeping the data for the official stations in an array indexed
+    /// by a [`PerfectHash`] of their names, & only the data for any others in a map
+    fn run<R>(input: R, config: &Config) -> ChallengeResult
+    where
+        R: std::io::Read + std::io::Seek,
+    {
+        let start = Instant::now();
+
+        let strict = config.strict.value;
+        let max_skipped = config.max_skipped.value;
+        let track_rows = config.track_rows();
+        let dialect = config.dialect();
+
+        let table = official();
+        let mut known = vec![StationData::empty(); SLOTS];
+        let mut others: HashMap<String, StationData, RandomState> = HashMap::default();
+        let mut skipped = SkippedLines::default();
+        let mut lines = LineReader::new(input, config);
+        let mut line_number = 0;
+        loop {
+            let line = match lines.next_line() {
+                Ok(Some(line)) => line,
+                Ok(None) => break,
+                Err(e) => {
+                    line_number += 1;
+                    skipped.skip(e, 1, max_skipped)?;
+                    continue;
+                }
+            };
+            line_number += 1;
+
+            match parse_record(line, line_number, strict, dialect) {
+                Ok(Some((station, measurement))) => {
+                    let data = match table.slot(station.as_bytes()) {
+                        Some(slot) => &mut known[slot],
+                        None => match others.get_mut(station) {
+                            Some(data) => data,
+                            None => others
+                                .entry(station.to_owned())
+                                .or_insert(StationData::empty()),
+                        },
+                    };
+                    data.push(measurement, strict);
+                    if track_rows {
+                        data.record_row(line_number);
+                    }
+                }
+                Ok(None) => {}
+                Err(e) => skipped.skip(e, 1, max_skipped)?,
+            }
+        }
+
+        let aggregated = Instant::now();
+        let ignored = known
+            .iter()
+            .chain(others.values())
+            .map(|data| data.skipped as u64)
+            .sum();
+
+        let stations = table
+            .names
+            .iter()
+            .zip(known)
+            .filter_map(|(name, data)| Some(((*name)?.to_owned(), data)))
+            .chain(others);
+        let named: Vec<(String, StationData)> = if config.case_insensitive.value {
+            merge_case_variants_by_row(stations, config.track_extents.value)
+        } else {
+            stations.collect()
+        };
+
+        // Build the alphabetically-sorted list of stations
+        let mut stations: Vec<StationInfo> = named
+            .into_iter()
+            .filter(|(_, data)| data.cnt > 0)
+            .map(|(name, data)| {
+                StationInfo::new(name, data.min, data.max, data.avg(), data.cnt)
+                    .with_extents(data.extents())
+            })
+            .collect();
+        stations.sort_unstable();
+
+        let stats = RunStats::new(Timings::since(start, aggregated, config))
+            .ignored_non_finite(ignored)
+            .skipped(skipped);
+
+        Ok((stations, stats))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Source;
+    use crate::runners::tests::*;
+    use crate::Runner as Kind;
+    use std::fmt::Write;
+    use std::{error, io};
+
+    #[test]
+    fn correctness() -> Result<(), Box<dyn error::Error>> {
+        // None of these stations are on the official list
+        let input = io::Cursor::new(TEST_DATA.as_bytes());
+
+        let (actual, _) = Runner::run(input, &Config::default())?;
+        assert_eq!(
+            actual, *EXPECTED_RESULT,
+            "actual != expected for perfect-hash runner"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn official_stations() -> Result<(), Box<dyn error::Error>> {
+        let table = official();
+        assert_eq!(table.len, STATIONS.lines().count());
+        for (idx, name) in STATIONS.lines().enumerate() {
+            let slot = table
+                .slot(name.as_bytes())
+                .expect("Every official station has a slot");
+            assert_eq!(table.names[slot], Some(name), "station {idx}");
+        }
+        // Names off the list land in a slot too, but aren't mistaken for its station
+        for name in ["Glens Falls", "hamburg", "Hamburg ", ""] {
+            assert_eq!(table.slot(name.as_bytes()), None, "{name:?}");
+        }
+
+        // Every official station, a few times over, & a couple which aren't
+        let mut input = String::new();
+        for i in 0..5_000 {
+            let name = STATIONS
+                .lines()
+                .nth(i * 11 % 413)
+                .expect("There are 413 stations");
+            writeln!(input, "{name};{}.{}", i as i32 % 100 - 50, i % 10)?;
+            if i % 500 == 0 {
+                writeln!(input, "Glens Falls;{}.5", i % 40)?;
+            }
+        }
+        let baseline = Config::default().with_runner(Kind::Baseline, Source::Cli);
+
+        let (expected, _) = crate::runners::run_with(io::Cursor::new(&input), &baseline)?;
+        let (actual, _) = Runner::run(io::Cursor::new(&input), &Config::default())?;
+        assert_eq!(actual.len(), 414);
+        assert_eq!(bits(&actual), bits(&expected));
+
+        Ok(())
+    }
+
+    #[test]
+    fn duplicate_names() {
+        let table = PerfectHash::build(["Hamburg", "Bulawayo", "Hamburg"]).expect("Two names");
+        assert_eq!(table.len, 2);
+        assert!(table.slot(b"Hamburg").is_some());
+    }
+}
@@ -0,0 +1,398 @@
+// 1BRC - my take on the 1 Billion Row Challenge
+// Copyright (C) 2024  Charles German <5donuts@pm.me>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Read the input in blocks of whole lines on one thread, handing them round-robin to a fixed
+//! number of parser threads over bounded channels, so reading & parsing overlap with only a few
+//! blocks in memory at once. Each parser's map is merged at the end.
+
+use std::collections::HashMap;
+use std::io::Read;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{self, Receiver};
+use std::time::Instant;
+
+use crate::aggregate::{merge_case_variants_by_row, StationData};
+use crate::block_sizing::Observation;
+use crate::blocks::{Block, BlockFailure, BlockReader};
+use crate::config::Config;
+use crate::error::{ChallengeError, SkippedLines};
+use crate::helpers::*;
+use crate::manifest::ChunkEntry;
+
+pub struct Runner;
+
+impl Runner {
+    /// Roughly how many bytes the reader reads into each block, unless they're sized as the
+    /// input is read (with `--block-sizing adaptive`)
+    pub const BLOCK_SIZE: usize = 1 << 20;
+
+    /// How many threads parse the blocks read
+    pub const WORKERS: usize = 4;
+
+    /// How many blocks may be queued for each parser thread before the reader waits for it, so
+    /// no more than `WORKERS * (QUEUE_DEPTH + 1)` blocks are ever in memory at once
+    pub const QUEUE_DEPTH: usize = 2;
+}
+
+/// What a single parser thread aggregated from the blocks it was sent
+struct Partial {
+    stations: HashMap<String, StationData>,
+
+    /// Malformed lines skipped in its blocks
+    skipped: SkippedLines,
+}
+
+impl ChallengeRunner for Runner {
+    /// Read the input in blocks of whole lines on the calling thread, handing each block to one
+    /// of [`Runner::WORKERS`] parser threads over a bounded channel of its own, so reading the
+    /// next block overlaps with parsing the last few. Each parser keeps a map of its own, & the
+    /// maps are merged once the input is exhausted.
+    ///
+    /// With adaptive block sizing, the size of each block is picked by a
+    /// [`Controller`](crate::block_sizing::Controller) from how long the last read took & how
+    /// many blocks were queued for the parsers.
+    fn run<R>(mut input: R, config: &Config) -> ChallengeResult
+    where
+        R: std::io::Read + std::io::Seek,
+    {
+        let start = Instant::now();
+        match pipeline(&mut input, Runner::BLOCK_SIZE, Runner::WORKERS, config)? {
+            Ok(partials) => finish(partials, start, config),
+            Err(failure) => Err(failure.locate(&mut input).into()),
+        }
+    }
+}
+
+/// Read `input` in blocks of `block_size` bytes (or as many as the config's block sizing picks),
+/// handing them round-robin to `workers` parser threads, & collect what each parser aggregated
+/// along with the lines skipped while reading.
+///
+/// Handing the blocks out in a fixed order means each parser sums the same blocks in the same
+/// order every run. The error closest to the start of the input (once no more lines can be
+/// skipped) is returned as the inner `Err` for the caller to [locate](BlockFailure::locate).
+fn pipeline<R: Read>(
+    input: &mut R,
+    block_size: usize,
+    workers: usize,
+    config: &Config,
+) -> Result<Result<Vec<Partial>, BlockFailure>, ChallengeError> {
+    if let Some(recorder) = &config.chunk_manifest {
+        recorder.start();
+    }
+    if let Some(log) = &config.block_sizes {
+        log.start();
+    }
+    let mut controller = config.block_sizing.value.controller();
+    let block_size = controller.as_ref().map_or(block_size, |c| c.size());
+    let max_skipped = config.max_skipped.value;
+    let mut reader = BlockReader::new(input, config)
+        .block_size(block_size)
+        .count_lines(config.track_rows());
+    let workers = workers.max(1);
+    // How many blocks have been sent to the parsers but not yet taken off their queues
+    let queued = AtomicUsize::new(0);
+    let mut read = Partial {
+        stations: HashMap::new(),
+        skipped: SkippedLines::default(),
+    };
+
+    let (failure, results) = std::thread::scope(|s| {
+        let queued = &queued;
+        let (senders, parsers): (Vec<_>, Vec<_>) = (0..workers)
+            .map(|_| {
+                let (sender, blocks) = mpsc::sync_channel(Runner::QUEUE_DEPTH);
+                (
+                    sender,
+                    s.spawn(move || parse_blocks(blocks, queued, config)),
+                )
+            })
+            .unzip();
+
+        let mut failure = None;
+        let mut block_size = block_size;
+        for sender in senders.iter().cycle() {
+            let read_start = Instant::now();
+            let next = reader.next_block();
+            if let Some(controller) = &mut controller {
+                if let (Some(log), Ok(Some(_))) = (&config.block_sizes, &next) {
+                    log.record(block_size);
+                }
+                block_size = controller.observe(Observation {
+                    read: read_start.elapsed(),
+                    queued: queued.load(Ordering::Relaxed),
+                    capacity: workers * Runner::QUEUE_DEPTH,
+                });
+                reader.set_block_size(block_size);
+            }
+            let block = match next {
+                Ok(Some(block)) => block,
+                Ok(None) => break,
+                // Lines too long to fit in a block are skipped here, like any other malformed line
+                Err(e) => {
+                    let offset = e.offset().unwrap_or_default();
+                    match read.skipped.skip(e, 1, max_skipped) {
+                        Ok(()) => continue,
+                        Err(error) => {
+                            failure = Some(BlockFailure {
+                                block_offset: offset,
+                                offset,
+                                error,
+                            });
+                            break;
+                        }
+                    }
+                }
+            };
+            queued.fetch_add(1, Ordering::Relaxed);
+            // The parser gave up at a malformed line, so there's no point reading any further
+            if sender.send(block).is_err() {
+                break;
+            }
+        }
+        drop(senders);
+
+        let results: Vec<Result<Partial, BlockFailure>> = parsers
+            .into_iter()
+            .map(|parser| parser.join().expect("Parser thread panicked"))
+            .collect();
+        (failure, results)
+    });
+
+    // Report the error closest to the start of the input, like a sequential runner would
+    let mut partials = Vec::with_capacity(results.len() + 1);
+    let mut first_failure = failure;
+    for result in results {
+        match result {
+            Ok(partial) => partials.push(partial),
+            Err(failure) => {
+                if first_failure
+                    .as_ref()
+                    .is_none_or(|first| failure.offset < first.offset)
+                {
+                    first_failure = Some(failure);
+                }
+            }
+        }
+    }
+    if let Some(failure) = first_failure {
+        return Ok(Err(failure));
+    }
+    partials.push(read);
+    Ok(Ok(partials))
+}
+
+/// Aggregate every line of the blocks sent to a parser thread into a map of its own, until the
+/// reader is done or a line can't be skipped, counting each block off `queued` as it's taken
+fn parse_blocks(
+    blocks: Receiver<Block>,
+    queued: &AtomicUsize,
+    config: &Config,
+) -> Result<Partial, BlockFailure> {
+    let mut partial = Partial {
+        stations: HashMap::new(),
+        skipped: SkippedLines::default(),
+    };
+    for block in blocks {
+        queued.fetch_sub(1, Ordering::Relaxed);
+        let mut rows = 0;
+        let stations = &mut partial.stations;
+        block.for_each_record(
+            config.max_line_length.value,
+            config.strict.value,
+            config.dialect(),
+            config.max_skipped.value,
+            &mut partial.skipped,
+            |station, measurement, line| {
+                rows += 1;
+                if !stations.contains_key(station) {
+                    stations.insert(station.to_owned(), StationData::empty());
+                }
+                let data = stations
+                    .get_mut(station)
+                    .expect("The station was just added");
+                data.push(measurement, config.strict.value);
+                if let Some(first_line) = block.first_line {
+                    data.record_row(first_line + line - 1);
+                }
+            },
+        )?;
+        if let Some(recorder) = &config.chunk_manifest {
+            recorder.record(ChunkEntry::new(&block, rows));
+        }
+    }
+    Ok(partial)
+}
+
+/// Merge the parsers' maps in order & build the sorted list of stations
+fn finish(partials: Vec<Partial>, start: Instant, config: &Config) -> ChallengeResult {
+    let mut skipped = SkippedLines::default();
+    let mut totals: HashMap<String, StationData> = HashMap::new();
+    for partial in partials {
+        for (name, data) in partial.stations {
+            totals
+                .entry(name)
+                .or_insert_with(StationData::empty)
+                .merge(&data);
+        }
+        // Each thread only knows about the lines skipped in its blocks
+        skipped.merge(&partial.skipped);
+    }
+    skipped.check(config.max_skipped.value)?;
+
+    let aggregated = Instant::now();
+    let ignored = totals.values().map(|data| data.skipped as u64).sum();
+
+    // Spellings of the same station were kept apart until now, with the rows they were first on
+    let totals: Vec<(String, StationData)> = if config.case_insensitive.value {
+        merge_case_variants_by_row(totals, config.track_extents.value)
+    } else {
+        totals.into_iter().collect()
+    };
+
+    // Build the alphabetically-sorted list of stations
+    let mut stations: Vec<StationInfo> = totals
+        .into_iter()
+        .filter(|(_, data)| data.cnt > 0)
+        .map(|(name, data)| {
+            StationInfo::new(name, data.min, data.max, data.avg(), data.cnt)
+                .with_extents(data.extents())
+        })
+        .collect();
+    stations.sort_unstable();
+
+    let stats = RunStats::new(Timings::since(start, aggregated, config))
+        .ignored_non_finite(ignored)
+        .skipped(skipped);
+
+    Ok((stations, stats))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::block_sizing::{BlockSizing, Controller};
+    use crate::config::Source;
+    use crate::runners::tests::*;
+    use crate::Runner as Kind;
+    use std::{error, io};
+
+    /// Run the pipeline with blocks of `block_size` bytes & `workers` parsers
+    fn run_with(input: &str, block_size: usize, workers: usize) -> ChallengeResult {
+        let config = Config::default().with_runner(Kind::Pipelined, Source::Cli);
+        run_configured(input, block_size, workers, &config)
+    }
+
+    fn run_configured(
+        input: &str,
+        block_size: usize,
+        workers: usize,
+        config: &Config,
+    ) -> ChallengeResult {
+        let mut input = io::Cursor::new(input);
+        match pipeline(&mut input, block_size, workers, config)? {
+            Ok(partials) => finish(partials, Instant::now(), config),
+            Err(failure) => Err(failure.locate(&mut input).into()),
+        }
+    }
+
+    #[test]
+    fn correctness() -> Result<(), Box<dyn error::Error>> {
+        let input = io::Cursor::new(TEST_DATA.as_bytes());
+
+        let (actual, _) = Runner::run(input, &Config::default())?;
+        assert_eq!(
+            actual, *EXPECTED_RESULT,
+            "actual != expected for pipelined runner"
+        );
+
+        Ok(())
+    }
+
+    /// Reads end in the middle of the multi-byte characters of station names at some of these
+    /// block sizes, & lines are split across many more blocks than there are parsers
+    #[test]
+    fn block_boundary_mid_character() -> Result<(), Box<dyn error::Error>> {
+        let input = format!("{TEST_DATA}\n東京;12.3\nAïn el Mediour;-1.0\n東京;-4.5\n");
+        let baseline = Config::default().with_runner(Kind::Baseline, Source::Cli);
+        let (expected, _) = crate::runners::run_with(io::Cursor::new(&input), &baseline)?;
+        assert!(expected.iter().any(|station| station.name() == "東京"));
+
+        for block_size in 1..=64 {
+            for workers in [1, 3] {
+                let (actual, _) = run_with(&input, block_size, workers)?;
+                assert_eq!(actual, expected, "{block_size} bytes, {workers} workers");
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Blocks sized as the input is read give the same results as fixed ones, & every block's
+    /// size is one the controller can pick
+    #[test]
+    fn adaptive_block_sizes() -> Result<(), Box<dyn error::Error>> {
+        let input = format!("{TEST_DATA}\n東京;12.3\n").repeat(20);
+        let baseline = Config::default().with_runner(Kind::Baseline, Source::Cli);
+        let (expected, _) = crate::runners::run_with(io::Cursor::new(&input), &baseline)?;
+
+        let sizing: BlockSizing = "adaptive:16-256".parse()?;
+        let mut config = Config::default().with_runner(Kind::Pipelined, Source::Cli);
+        config.block_sizing.value = sizing;
+        config.block_sizes = Some(Default::default());
+        let (actual, _) = run_configured(&input, Runner::BLOCK_SIZE, 3, &config)?;
+        assert_eq!(actual, expected);
+
+        let choices = sizing.controller().expect("Sizing is adaptive").choices();
+        let sizes = config.block_sizes.as_ref().unwrap().sizes();
+        assert!(!sizes.is_empty());
+        assert!(sizes.iter().all(|size| choices.contains(size)), "{sizes:?}");
+
+        Ok(())
+    }
+
+    /// Every size the controller can pick gives the same results, however the sizes end up mixed
+    #[test]
+    fn every_adaptive_size() -> Result<(), Box<dyn error::Error>> {
+        let input = format!("{TEST_DATA}\nAïn el Mediour;-1.0\n").repeat(4);
+        let baseline = Config::default().with_runner(Kind::Baseline, Source::Cli);
+        let (expected, _) = crate::runners::run_with(io::Cursor::new(&input), &baseline)?;
+
+        let choices = Controller::new(1, 4 << 10).choices();
+        assert_eq!(choices.first(), Some(&1));
+        assert_eq!(choices.last(), Some(&(4 << 10)));
+        for block_size in choices {
+            let (actual, _) = run_with(&input, block_size, 2)?;
+            assert_eq!(actual, expected, "{block_size} bytes");
+        }
+
+        Ok(())
+    }
+
+    /// A parser giving up on a malformed line stops the reader, & the error is located from the
+    /// start of the input
+    #[test]
+    fn malformed_line() {
+        let malformed = format!("{TEST_DATA}{TEST_DATA}Nowhere\n{TEST_DATA}");
+        let baseline = Config::default().with_runner(Kind::Baseline, Source::Cli);
+        let expected = crate::runners::run_with(io::Cursor::new(&malformed), &baseline)
+            .expect_err("The baseline runner accepted a malformed line");
+
+        for block_size in [16, 1 << 10] {
+            let actual =
+                run_with(&malformed, block_size, 3).expect_err("A malformed line was accepted");
+            assert_eq!(actual.to_string(), expected.to_string());
+        }
+    }
+}
@@ -0,0 +1,86 @@
+// 1BRC - my take on the 1 Billion Row Challenge
+// Copyright (C) 2024  Charles German <5donuts@pm.me>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Use the same approach as `ahash`, but size the map for the challenge's 10,000 stations (or every
+//! known station, if there are more) up front, so it's never resized during the run.
+
+use ahash::RandomState;
+
+use crate::aggregate::MAX_STATIONS;
+use crate::config::Config;
+use crate::helpers::*;
+
+use super::baseline::run_hashed;
+
+pub struct Runner;
+
+impl ChallengeRunner for Runner {
+    /// Like the [`ahash`](super::AHash) runner, but with room in the map for [`MAX_STATIONS`]
+    /// stations before the first line is read, so however many turn up (within the challenge's
+    /// limit) it's never rehashed.
+    fn run<R>(input: R, config: &Config) -> ChallengeResult
+    where
+        R: std::io::Read + std::io::Seek,
+    {
+        run_hashed::<RandomState, R>(input, config, MAX_STATIONS)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::aggregate::Aggregator;
+    use crate::runners::tests::*;
+    use std::{error, io};
+
+    #[test]
+    fn correctness() -> Result<(), Box<dyn error::Error>> {
+        let input = io::Cursor::new(TEST_DATA.as_bytes());
+
+        let (actual, _) = Runner::run(input, &Config::default())?;
+        assert_eq!(
+            actual, *EXPECTED_RESULT,
+            "actual != expected for presized runner"
+        );
+
+        Ok(())
+    }
+
+    /// The map never grows while the challenge's maximum number of stations is inserted, where
+    /// one started empty is resized over & over
+    #[test]
+    fn never_rehashes() {
+        let names: Vec<String> = (0..MAX_STATIONS).map(|i| format!("Station {i}")).collect();
+
+        let mut presized: Aggregator<RandomState> = Aggregator::new().with_capacity(MAX_STATIONS);
+        let capacity = presized.capacity();
+        assert!(capacity >= MAX_STATIONS);
+        for name in &names {
+            presized.ingest_record(name, 1.0);
+            assert_eq!(presized.capacity(), capacity, "resized at {name}");
+        }
+
+        let mut empty: Aggregator<RandomState> = Aggregator::new();
+        let mut resizes = 0;
+        for name in &names {
+            let before = empty.capacity();
+            empty.ingest_record(name, 1.0);
+            resizes += usize::from(empty.capacity() != before);
+        }
+        assert!(resizes > 5, "{resizes}");
+
+        assert_eq!(presized.into_sorted(), empty.into_sorted());
+    }
+}
@@ -0,0 +1,701 @@
+// 1BRC - my take on the 1 Billion Row Challenge
+// Copyright (C) 2024  Charles German <5donuts@pm.me>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Sample the start of the input to assign each station a dense id, then aggregate blocks of lines
+//! on several threads into flat arrays indexed by those ids. Stations missing from the sample fall
+//! back to a small map per thread.
+
+use std::collections::{BTreeMap, HashMap};
+use std::io::{self, Read, Seek, SeekFrom};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc;
+use std::time::Instant;
+
+use ahash::RandomState;
+
+use crate::aggregate::{merge_case_variants_by_row, parse_line, Dialect, StationData};
+use crate::blocks::{Block, BlockFailure, BlockReader};
+use crate::boundaries::split_at_last_newline;
+use crate::config::Config;
+use crate::error::{ChallengeError, SkippedLines};
+use crate::helpers::*;
+use crate::manifest::ChunkEntry;
+use crate::spill::SpillFile;
+
+pub struct Runner;
+
+/// Roughly how many bytes each station in an [`Overflow`] map takes up, counting the empty
+/// buckets & control bytes of the map and the name on the heap
+const OVERFLOW_ENTRY_SIZE: usize =
+    (std::mem::size_of::<(String, StationData)>() + 1) * 8 / 7 + crate::plan::AVG_NAME_LEN;
+
+/// The fewest stations an [`Overflow`] map is given room for, however little memory there is
+const MIN_HOT_STATIONS: usize = 16;
+
+/// What a single thread has aggregated
+struct Partial {
+    /// Indexed by station id
+    dense: Vec<StationData>,
+
+    /// Stations which weren't seen in the sample
+    overflow: Overflow,
+
+    /// Malformed lines skipped by this thread
+    skipped: SkippedLines,
+}
+
+impl ChallengeRunner for Runner {
+    fn run<R>(mut input: R, config: &Config) -> ChallengeResult
+    where
+        R: std::io::Read + std::io::Seek,
+    {
+        let start = Instant::now();
+        if let Some(recorder) = &config.chunk_manifest {
+            recorder.start();
+        }
+
+        // First, find (almost certainly all of) the stations by sampling the start of the input,
+        // unless they're already known. Their ids are their positions in sorted order so the
+        // output comes out (mostly) sorted for free.
+        //
+        // With a memory limit, each thread gets an even share of it, half for its array & half
+        // for its overflow map. If that's not enough room for every station, only the ones the
+        // sample saw most often get an id; the rest go in the overflow maps, which spill the
+        // stations they've seen least often to disk when they fill up.
+        let threads = config.threads.value.max(1);
+        let budget = match config.max_memory.value {
+            0 => None,
+            limit => Some(usize::try_from(limit).unwrap_or(usize::MAX) / threads / 2),
+        };
+        let max_ids = budget.map(|budget| (budget / std::mem::size_of::<StationData>()).max(1));
+        let hot_capacity =
+            budget.map(|budget| (budget / OVERFLOW_ENTRY_SIZE).max(MIN_HOT_STATIONS));
+        let names: Vec<String> = match &config.known_stations {
+            Some(names) => {
+                let mut names = names.to_vec();
+                names.truncate(max_ids.unwrap_or(usize::MAX));
+                names
+            }
+            None => sample(
+                &mut input,
+                config.sample_fraction.value,
+                config.dialect(),
+                max_ids,
+            )?,
+        };
+        let ids: HashMap<&str, usize, RandomState> = names
+            .iter()
+            .enumerate()
+            .map(|(id, name)| (name.as_str(), id))
+            .collect();
+        input.seek(SeekFrom::Start(0))?;
+
+        // Then, read blocks of lines on this thread & hand them off to the workers, which each
+        // aggregate into a flat array indexed by station id.
+        //
+        // Blocks are dealt out to the workers in turn, rather than to whichever is free, and the
+        // workers' arrays are merged in the same order. Summing floats in a different order can
+        // give a slightly different result, so this keeps the output the same from run to run
+        // (for a given number of threads) no matter how the threads are scheduled.
+        let (txs, rxs): (Vec<_>, Vec<_>) =
+            (0..threads).map(|_| mpsc::sync_channel::<Block>(2)).unzip();
+        let stop = AtomicBool::new(false);
+        // Rows are also needed to tell which spelling of a case-insensitive station came first
+        let mut reader = BlockReader::new(input, config).count_lines(config.track_rows());
+        let max_skipped = config.max_skipped.value;
+        let mut skipped = SkippedLines::default();
+
+        let (read_result, partials) = std::thread::scope(|s| {
+            let workers: Vec<_> = rxs
+                .into_iter()
+                .map(|rx| {
+                    let (ids, stop) = (&ids, &stop);
+                    s.spawn(move || aggregate(rx, ids, hot_capacity, config, stop))
+                })
+                .collect();
+
+            let read_result = (|| {
+                for tx in txs.iter().cycle() {
+                    let block = match reader.next_block() {
+                        Ok(Some(block)) => block,
+                        Ok(None) => break,
+                        // Lines too long to fit in a block are skipped here, rather than by a worker
+                        Err(e) => {
+                            skipped.skip(e, 1, max_skipped)?;
+                            continue;
+                        }
+                    };
+                    if stop.load(Ordering::Relaxed) || tx.send(block).is_err() {
+                        break;
+                    }
+                }
+                Ok::<_, ChallengeError>(())
+            })();
+            drop(txs);
+
+            let partials: Vec<Result<Partial, BlockFailure>> = workers
+                .into_iter()
+                .map(|worker| worker.join().expect("Worker thread panicked"))
+                .collect();
+            (read_result, partials)
+        });
+
+        // Report the error closest to the start of the input, like a sequential runner would
+        let mut partials_ok = Vec::with_capacity(partials.len());
+        let mut first_failure: Option<BlockFailure> = None;
+        for partial in partials {
+            match partial {
+                Ok(partial) => partials_ok.push(partial),
+                Err(failure) => {
+                    if first_failure
+                        .as_ref()
+                        .is_none_or(|first| failure.offset < first.offset)
+                    {
+                        first_failure = Some(failure);
+                    }
+                }
+            }
+        }
+        if let Err(e) = read_result {
+            let read_offset = match &e {
+                ChallengeError::LineTooLong { offset, .. } => *offset,
+                _ => 0,
+            };
+            if first_failure
+                .as_ref()
+                .is_none_or(|f| read_offset < f.offset)
+            {
+                return Err(e.into());
+            }
+        }
+        if let Some(failure) = first_failure {
+            return Err(failure.locate(&mut reader.into_inner()).into());
+        }
+
+        // Merging the arrays is just element-wise addition, in the order the workers were given
+        // blocks. Anything a worker spilled is merged before what it still had in memory.
+        let mut dense = vec![StationData::empty(); names.len()];
+        let mut overflow: HashMap<String, StationData, RandomState> = HashMap::default();
+        for partial in partials_ok {
+            for (total, data) in dense.iter_mut().zip(&partial.dense) {
+                total.merge(data);
+            }
+            partial.overflow.merge_into(&mut overflow)?;
+            // Each thread only knows about the lines it skipped itself
+            skipped.merge(&partial.skipped);
+        }
+        skipped.check(max_skipped)?;
+
+        let aggregated = Instant::now();
+        let ignored = dense
+            .iter()
+            .chain(overflow.values())
+            .map(|data| data.skipped as u64)
+            .sum();
+
+        let info = |(name, data): (String, StationData)| {
+            StationInfo::new(name, data.min, data.max, data.avg(), data.cnt)
+                .with_extents(data.extents())
+        };
+        let stations = if config.case_insensitive.value {
+            // Spellings of the same station may or may not have been sampled, so they're merged
+            // under the one on the earliest row & the lot sorted afterwards
+            let totals = names.into_iter().zip(dense).chain(overflow);
+            let mut stations: Vec<StationInfo> =
+                merge_case_variants_by_row(totals, config.track_extents.value)
+                    .into_iter()
+                    .map(info)
+                    .collect();
+            stations.sort_unstable();
+            stations
+        } else {
+            // Build the alphabetically-sorted list of stations. The sampled stations are already
+            // in order, but any that weren't sampled have to be sorted in amongst them; rather
+            // than sorting the lot on one thread, split them into ranges of names using the
+            // sample & sort each range on its own.
+            let splitters = if overflow.is_empty() {
+                Vec::new()
+            } else {
+                splitters(&names, threads)
+            };
+            let mut stations: Vec<StationInfo> = names
+                .into_iter()
+                .zip(dense)
+                // Sampled stations with a partial line in the sample may never show up in full
+                .filter(|(_, data)| data.cnt > 0)
+                .map(info)
+                .collect();
+            if !overflow.is_empty() {
+                stations.extend(overflow.into_iter().map(info));
+                stations = sort_by_ranges(stations, &splitters);
+            }
+            stations
+        };
+
+        // Compute the time it took to generate the list of sorted stations
+        let stats = RunStats::new(Timings::since(start, aggregated, config))
+            .ignored_non_finite(ignored)
+            .skipped(skipped);
+
+        Ok((stations, stats))
+    }
+}
+
+/// Aggregate blocks from `rx` until there are none left or another worker fails
+fn aggregate(
+    rx: mpsc::Receiver<Block>,
+    ids: &HashMap<&str, usize, RandomState>,
+    hot_capacity: Option<usize>,
+    config: &Config,
+    stop: &AtomicBool,
+) -> Result<Partial, BlockFailure> {
+    let mut partial = Partial {
+        dense: vec![StationData::empty(); ids.len()],
+        overflow: Overflow::new(hot_capacity),
+        skipped: SkippedLines::default(),
+    };
+
+    loop {
+        let block = match rx.recv() {
+            Ok(block) => block,
+            Err(_) => return Ok(partial),
+        };
+        if stop.load(Ordering::Relaxed) {
+            return Ok(partial);
+        }
+
+        let mut rows = 0;
+        let result = block.for_each_record(
+            config.max_line_length.value,
+            config.strict.value,
+            config.dialect(),
+            config.max_skipped.value,
+            &mut partial.skipped,
+            |station, measurement, line| {
+                rows += 1;
+                let data = match ids.get(station) {
+                    Some(&id) => &mut partial.dense[id],
+                    None => partial.overflow.get_mut(station),
+                };
+                data.push(measurement, config.strict.value);
+                // Only counted when tracking extents
+                if let Some(first_line) = block.first_line {
+                    data.record_row(first_line + line - 1);
+                }
+            },
+        );
+        if let Some(recorder) = &config.chunk_manifest {
+            recorder.record(ChunkEntry::new(&block, rows));
+        }
+        let result = result.and_then(|()| match partial.overflow.take_error() {
+            Some(e) => Err(BlockFailure {
+                block_offset: block.offset,
+                offset: block.offset,
+                error: e.into(),
+            }),
+            None => Ok(()),
+        });
+        if let Err(failure) = result {
+            stop.store(true, Ordering::Relaxed);
+            return Err(failure);
+        }
+    }
+}
+
+/// A thread's stations which weren't seen in the sample.
+///
+/// Without a capacity, this is just a map. With one, a full map writes the half of its stations
+/// with the fewest measurements to a [`SpillFile`] to make room; they're read back by
+/// [`merge_into`](Overflow::merge_into). A station may be spilled more than once, so it can have
+/// several records.
+struct Overflow {
+    map: HashMap<String, StationData, RandomState>,
+    capacity: Option<usize>,
+    spill: Option<SpillFile>,
+
+    /// The first error spilling, if any; the map is left to grow from then on
+    error: Option<io::Error>,
+}
+
+impl Overflow {
+    fn new(capacity: Option<usize>) -> Self {
+        Self {
+            map: HashMap::default(),
+            capacity,
+            spill: None,
+            error: None,
+        }
+    }
+
+    /// The data for `station`, added if it's not in the map (which may spill others)
+    fn get_mut(&mut self, station: &str) -> &mut StationData {
+        if !self.map.contains_key(station) {
+            if self
+                .capacity
+                .is_some_and(|capacity| self.map.len() >= capacity)
+            {
+                if let Err(e) = self.spill_cold() {
+                    self.error = Some(e);
+                    self.capacity = None;
+                }
+            }
+            self.map.insert(station.to_owned(), StationData::empty());
+        }
+        self.map
+            .get_mut(station)
+            .expect("The station was just added")
+    }
+
+    /// Write out the coldest half of the map.
+    ///
+    /// Ties are broken by name, so the same blocks always spill the same stations; which
+    /// measurements are summed together (& so the rounding) doesn't depend on the hasher.
+    fn spill_cold(&mut self) -> io::Result<()> {
+        let spill = match &mut self.spill {
+            Some(spill) => spill,
+            None => self.spill.insert(SpillFile::new()?),
+        };
+
+        let mut cold: Vec<(String, StationData)> = self.map.drain().collect();
+        let split = cold.len() / 2;
+        if split > 0 {
+            cold.select_nth_unstable_by(split, |(a_name, a), (b_name, b)| {
+                (a.cnt + a.skipped)
+                    .cmp(&(b.cnt + b.skipped))
+                    .then_with(|| a_name.cmp(b_name))
+            });
+        }
+        self.map.extend(cold.drain(split..));
+        for (name, data) in &cold {
+            spill.write(name, data)?;
+        }
+        Ok(())
+    }
+
+    /// The error from spilling, if there was one
+    fn take_error(&mut self) -> Option<io::Error> {
+        self.error.take()
+    }
+
+    /// How many records have been spilled
+    #[cfg(test)]
+    fn spilled(&self) -> u64 {
+        self.spill.as_ref().map_or(0, SpillFile::records)
+    }
+
+    /// Add everything to `totals`, reading back the spilled records first
+    fn merge_into(
+        mut self,
+        totals: &mut HashMap<String, StationData, RandomState>,
+    ) -> io::Result<()> {
+        let mut merge = |name: String, data: StationData| {
+            totals
+                .entry(name)
+                .and_modify(|total| total.merge(&data))
+                .or_insert(data);
+        };
+        if let Some(spill) = &mut self.spill {
+            for record in spill.read_back()? {
+                let (name, data) = record?;
+                merge(name, data);
+            }
+        }
+        for (name, data) in self.map {
+            merge(name, data);
+        }
+        Ok(())
+    }
+}
+
+/// Up to `ranges - 1` of the sorted `names`, evenly spaced, to split them into ranges with about
+/// as many sampled stations each. A letter lots of stations start with gets split across several
+/// ranges, while a run of rarely-used letters shares one.
+fn splitters(names: &[String], ranges: usize) -> Vec<String> {
+    if names.is_empty() {
+        return Vec::new();
+    }
+    let mut splitters: Vec<String> = (1..ranges)
+        .map(|i| names[i * names.len() / ranges].clone())
+        .collect();
+    splitters.dedup();
+    splitters
+}
+
+/// Sort `stations` by name by splitting them into ranges at each of the (sorted) `splitters`,
+/// sorting each range on its own thread & concatenating them, so there's no global sort.
+///
+/// The splitters are whole names rather than first bytes, so a range boundary can't fall in the
+/// middle of a multi-byte character, and comparing names byte-wise (as `str` does) is the same as
+/// comparing them by code point.
+fn sort_by_ranges(mut stations: Vec<StationInfo>, splitters: &[String]) -> Vec<StationInfo> {
+    if splitters.is_empty() {
+        stations.sort_unstable();
+        return stations;
+    }
+
+    let mut ranges: Vec<Vec<StationInfo>> = vec![Vec::new(); splitters.len() + 1];
+    for station in stations {
+        let range = splitters.partition_point(|splitter| splitter.as_str() <= station.name());
+        ranges[range].push(station);
+    }
+    std::thread::scope(|s| {
+        for range in ranges.iter_mut().filter(|range| range.len() > 1) {
+            s.spawn(|| range.sort_unstable());
+        }
+    });
+    ranges.into_iter().flatten().collect()
+}
+
+/// Collect the sorted station names from the first `fraction` of the input.
+///
+/// Anything that can't be parsed is skipped; the full pass will report it properly. With
+/// `max_names`, only that many of the stations seen most often are kept.
+fn sample<R: Read + Seek>(
+    input: &mut R,
+    fraction: f64,
+    dialect: Dialect,
+    max_names: Option<usize>,
+) -> io::Result<Vec<String>> {
+    let len = input.seek(SeekFrom::End(0))?;
+    input.seek(SeekFrom::Start(0))?;
+    let sample_len = (len as f64 * fraction.clamp(0.0, 1.0)).ceil() as u64;
+
+    let mut sample = Vec::new();
+    input.take(sample_len).read_to_end(&mut sample)?;
+    if sample_len < len {
+        // Don't guess at a station from a partial line
+        let (lines, _) = split_at_last_newline(&sample);
+        sample.truncate(lines.len());
+    }
+
+    let mut counts: BTreeMap<&str, u64> = BTreeMap::new();
+    for line in sample.split(|&b| b == b'\n') {
+        let Ok(line) = std::str::from_utf8(line) else {
+            continue;
+        };
+        let line = line.trim_start_matches('\u{FEFF}').trim_end_matches('\r');
+        if let Ok((station, _)) = parse_line(line, false, dialect) {
+            *counts.entry(station).or_default() += 1;
+        }
+    }
+
+    let mut names: Vec<(&str, u64)> = counts.into_iter().collect();
+    if let Some(max_names) = max_names.filter(|&max| names.len() > max) {
+        names.sort_by(|(a_name, a), (b_name, b)| b.cmp(a).then_with(|| a_name.cmp(b_name)));
+        names.truncate(max_names);
+        names.sort_unstable();
+    }
+    Ok(names.into_iter().map(|(name, _)| name.to_owned()).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{Setting, Source};
+    use crate::runners::tests::*;
+    use std::error;
+
+    #[test]
+    fn correctness() -> Result<(), Box<dyn error::Error>> {
+        let input = io::Cursor::new(TEST_DATA.as_bytes());
+
+        let (actual, _) = Runner::run(input, &Config::default())?;
+        assert_eq!(
+            actual, *EXPECTED_RESULT,
+            "actual != expected for sampled-dense runner"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn deterministic_output() -> Result<(), Box<dyn error::Error>> {
+        // Pairs of measurements a tenth apart, so every mean sits right on a rounding boundary
+        // where the order of the additions can tip it either way
+        let mut input = String::new();
+        for i in 0..40_000i32 {
+            let station = i % 97;
+            let tenths = i / 97 % 2 + station * 7 - 300;
+            input.push_str(&format!(
+                "Station {station};{}{}.{}\n",
+                if tenths < 0 { "-" } else { "" },
+                tenths.abs() / 10,
+                tenths.abs() % 10
+            ));
+        }
+
+        let config = Config {
+            buffer_size: Setting::new(1024, Source::Cli),
+            threads: Setting::new(4, Source::Cli),
+            ..Config::default()
+        };
+        let render = || -> Result<String, Box<dyn error::Error>> {
+            let (stations, _) = Runner::run(io::Cursor::new(&input), &config)?;
+            Ok(crate::output::Format::Text.render(&stations))
+        };
+        let first = render()?;
+        for _ in 1..10 {
+            assert_eq!(render()?, first);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn station_missing_from_sample() -> Result<(), Box<dyn error::Error>> {
+        // 10,000 lines over a handful of stations, plus one which only shows up at the very end
+        let mut input = String::new();
+        for i in 0..10_000 {
+            let station = ["Hamburg", "Bulawayo", "Palembang", "St. John's"][i % 4];
+            input.push_str(&format!("{station};{}.{}\n", i % 50, i % 10));
+        }
+        for i in 0..50 {
+            input.push_str(&format!("Abéché;{}.5\n", i % 30));
+        }
+
+        let config = Config {
+            buffer_size: Setting::new(512, Source::Cli),
+            threads: Setting::new(4, Source::Cli),
+            sample_fraction: Setting::new(0.01, Source::Cli),
+            ..Config::default()
+        };
+        let names = sample(&mut io::Cursor::new(&input), 0.01, Dialect::default(), None)?;
+        assert!(!names.iter().any(|name| name == "Abéché"));
+
+        let (actual, _) = Runner::run(io::Cursor::new(&input), &config)?;
+        let (expected, _) = crate::runners::Baseline::run(io::Cursor::new(&input), &config)?;
+        assert_eq!(
+            crate::output::Format::Text.render(&actual),
+            crate::output::Format::Text.render(&expected)
+        );
+        let abeche = actual.iter().find(|s| s.name() == "Abéché").unwrap();
+        assert_eq!(abeche.count(), 50);
+
+        Ok(())
+    }
+
+    #[test]
+    fn range_sort_matches_global_sort() {
+        // Names which share their first byte across several characters (é, ö & ø all start with
+        // 0xC3), multi-byte first characters & a skewed distribution with most names on one letter
+        let mut names: Vec<String> = [
+            "Abéché",
+            "Zürich",
+            "Ängelholm",
+            "Øresund",
+            "Ölands",
+            "Éfaté",
+            "Ōsaka",
+            "東京",
+            "Tōkyō",
+            "Ürümqi",
+            "Łódź",
+            "Санкт-Петербург",
+            "🌧 Station",
+            "",
+            "S",
+            "Sa",
+            "Sá",
+        ]
+        .iter()
+        .map(|name| name.to_string())
+        .collect();
+        names.extend((0..500).map(|i| format!("S{}", (i * 7919) % 1000)));
+        let stations: Vec<StationInfo> = names
+            .iter()
+            .map(|name| StationInfo::new(name.clone(), 0.0, 0.0, 0.0, 1))
+            .collect();
+        let mut expected = stations.clone();
+        expected.sort_unstable();
+
+        // Sample every third name as the splitters would have been, & one which isn't a station
+        let mut sampled: Vec<String> = names.iter().step_by(3).cloned().collect();
+        sampled.push(String::from("\u{C3}"));
+        sampled.sort_unstable();
+        for ranges in [1, 2, 3, 4, 8, 64, 10_000] {
+            let splitters = splitters(&sampled, ranges);
+            assert!(splitters.len() < ranges);
+            let actual = sort_by_ranges(stations.clone(), &splitters);
+            assert_eq!(actual, expected, "{ranges} ranges");
+        }
+        assert_eq!(sort_by_ranges(stations, &splitters(&[], 4)), expected);
+    }
+
+    #[test]
+    fn overflow_spills_coldest() -> Result<(), Box<dyn error::Error>> {
+        let mut overflow = Overflow::new(Some(10));
+        for round in 0..3 {
+            for i in 0..1000 {
+                let data = overflow.get_mut(&format!("Station {i}"));
+                data.push((i % 10) as f32 + round as f32, false);
+            }
+        }
+        // Station 0 is the only one seen often enough to stay in memory
+        for _ in 0..100 {
+            overflow.get_mut("Station 0").push(-1.0, false);
+        }
+        assert!(overflow.take_error().is_none());
+        assert!(overflow.spilled() > 0);
+        assert!(overflow.map.len() <= 10);
+
+        let mut totals = HashMap::default();
+        overflow.merge_into(&mut totals)?;
+        assert_eq!(totals.len(), 1000);
+        for (name, data) in &totals {
+            let i: u32 = name.trim_start_matches("Station ").parse()?;
+            let extra = if i == 0 { 100 } else { 0 };
+            assert_eq!(data.cnt, 3 + extra, "{name}");
+            assert_eq!(data.min, if i == 0 { -1.0 } else { (i % 10) as f32 });
+            assert_eq!(data.max, (i % 10 + 2) as f32);
+            assert_eq!(data.sum, (3 * (i % 10) + 3) as f32 - extra as f32);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn bounded_memory() -> Result<(), Box<dyn error::Error>> {
+        // 200k stations, each seen twice, with measurements whose sums are exact so spilling
+        // can't change the rounding
+        let mut input = String::new();
+        for pass in 0..2 {
+            for i in 0..200_000 {
+                input.push_str(&format!(
+                    "Station {i};{}.5\n",
+                    (i * 7 + pass * 13) % 100 - 50
+                ));
+            }
+        }
+
+        let unbounded = Config {
+            buffer_size: Setting::new(64 * 1024, Source::Cli),
+            threads: Setting::new(4, Source::Cli),
+            track_extents: Setting::new(true, Source::Cli),
+            ..Config::default()
+        };
+        let bounded = Config {
+            max_memory: Setting::new(4096, Source::Cli),
+            ..unbounded.clone()
+        };
+        let render = |config: &Config| -> Result<String, Box<dyn error::Error>> {
+            let (stations, _) = Runner::run(io::Cursor::new(&input), config)?;
+            assert_eq!(stations.len(), 200_000);
+            Ok(crate::output::Format::Text.render(&stations))
+        };
+        assert_eq!(render(&bounded)?, render(&unbounded)?);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,387 @@
+// 1BRC - my take on the 1 Billion Row Challenge
+// Copyright (C) 2024  Charles German <5donuts@pm.me>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Split the input into one newline-aligned range per thread & aggregate each range on a scoped
+//! thread reading it through a file handle of its own, merging the threads' maps at the end.
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::hash::{BuildHasher, RandomState};
+use std::io::{self, Read, Seek, SeekFrom};
+use std::ops::Range;
+use std::path::Path;
+use std::time::Instant;
+
+use crate::aggregate::{merge_case_variants_by_row, StationData};
+use crate::blocks::{BlockFailure, BlockReader};
+use crate::config::Config;
+use crate::error::SkippedLines;
+use crate::helpers::*;
+use crate::manifest::ChunkEntry;
+use crate::plan::chunk_boundaries;
+use crate::window::Window;
+
+pub struct Runner;
+
+/// What a single thread aggregated from its range of the input, into a map hashed with `S`
+pub(super) struct Partial<S = RandomState> {
+    stations: HashMap<String, StationData, S>,
+
+    /// How many lines the range has, if counting rows; the rows recorded in `stations` are
+    /// relative to the start of the range until they're merged
+    lines: u64,
+
+    /// Malformed lines skipped in this range
+    skipped: SkippedLines,
+}
+
+#[cfg(feature = "affinity")]
+impl<S> Partial<S> {
+    /// How many measurements were aggregated from the range, whether or not they were finite
+    pub(super) fn rows(&self) -> u64 {
+        self.stations
+            .values()
+            .map(|data| data.cnt as u64 + data.skipped as u64)
+            .sum()
+    }
+}
+
+impl Runner {
+    /// Split the file at `path` (or its `--byte-range`) into one newline-aligned range per
+    /// thread & have each thread read its range through a file handle of its own.
+    pub fn run_file(path: &Path, config: &Config) -> ChallengeResult {
+        let start = Instant::now();
+
+        let file = File::open(path)?;
+        let window = match &config.byte_range {
+            Some(window) => window.clone(),
+            None => 0..file.metadata()?.len(),
+        };
+        let len = window.end - window.start;
+        let mut input = Window::new(file, window.clone())?;
+        aggregate(&mut input, len, start, config, |range| {
+            let mut file = File::open(path)?;
+            file.seek(SeekFrom::Start(window.start + range.start))?;
+            Ok(file.take(range.end - range.start))
+        })
+    }
+}
+
+impl ChallengeRunner for Runner {
+    /// Only a file can be opened once per thread, so any other input (e.g. a `Cursor` in tests)
+    /// is read into memory in one go & each thread reads its range from there instead; see
+    /// [`Runner::run_file`].
+    fn run<R>(mut input: R, config: &Config) -> ChallengeResult
+    where
+        R: std::io::Read + std::io::Seek,
+    {
+        let start = Instant::now();
+
+        let mut data = Vec::new();
+        input.read_to_end(&mut data)?;
+        let len = data.len() as u64;
+        aggregate(&mut io::Cursor::new(&data), len, start, config, |range| {
+            Ok(&data[range.start as usize..range.end as usize])
+        })
+    }
+}
+
+/// Split `len` bytes of `input` into `--threads` newline-aligned ranges, aggregate each range on
+/// a thread of its own reading through `open(range)`, & merge the results.
+///
+/// The threads' maps are merged in the order of their ranges, so the results only depend on the
+/// number of threads, not on how they're scheduled.
+fn aggregate<R, O, S>(
+    input: &mut R,
+    len: u64,
+    start: Instant,
+    config: &Config,
+    open: O,
+) -> ChallengeResult
+where
+    R: Read + Seek,
+    O: Fn(&Range<u64>) -> io::Result<S> + Sync,
+    S: Read,
+{
+    if let Some(recorder) = &config.chunk_manifest {
+        recorder.start();
+    }
+    let ranges = chunk_boundaries(
+        input,
+        len,
+        config.threads.value,
+        config.max_line_length.value,
+    )?;
+
+    let results: Vec<Result<Partial, BlockFailure>> = std::thread::scope(|s| {
+        let workers: Vec<_> = ranges
+            .iter()
+            .map(|range| {
+                let open = &open;
+                s.spawn(move || {
+                    let reader = open(range).map_err(|e| BlockFailure {
+                        block_offset: range.start,
+                        offset: range.start,
+                        error: e.into(),
+                    })?;
+                    aggregate_range(reader, range, config)
+                })
+            })
+            .collect();
+        workers
+            .into_iter()
+            .map(|worker| worker.join().expect("Worker thread panicked"))
+            .collect()
+    });
+    merge(input, results, start, config)
+}
+
+/// Merge what was aggregated from each range of `input`, given in the order of the ranges, into
+/// the sorted list of stations, or report the failure closest to the start of the input.
+pub(super) fn merge<R: Read + Seek, S>(
+    input: &mut R,
+    results: Vec<Result<Partial<S>, BlockFailure>>,
+    start: Instant,
+    config: &Config,
+) -> ChallengeResult {
+    let max_skipped = config.max_skipped.value;
+
+    // Report the error closest to the start of the input, like a sequential runner would
+    let mut partials = Vec::with_capacity(results.len());
+    let mut first_failure: Option<BlockFailure> = None;
+    for result in results {
+        match result {
+            Ok(partial) => partials.push(partial),
+            Err(failure) => {
+                if first_failure
+                    .as_ref()
+                    .is_none_or(|first| failure.offset < first.offset)
+                {
+                    first_failure = Some(failure);
+                }
+            }
+        }
+    }
+    if let Some(failure) = first_failure {
+        return Err(failure.locate(input).into());
+    }
+
+    // Fold each range's map into the total in order, moving the rows each station was on from
+    // being relative to its range to being relative to the whole input
+    let track_rows = config.track_rows();
+    let mut skipped = SkippedLines::default();
+    let mut lines_before = 0;
+    let mut totals: HashMap<String, StationData> = HashMap::new();
+    for partial in partials {
+        for (name, mut data) in partial.stations {
+            if track_rows && data.last_row > 0 {
+                data.first_row += lines_before;
+                data.last_row += lines_before;
+            }
+            totals
+                .entry(name)
+                .or_insert_with(StationData::empty)
+                .merge(&data);
+        }
+        lines_before += partial.lines;
+        // Each thread only knows about the lines skipped in its range
+        skipped.merge(&partial.skipped);
+    }
+    skipped.check(max_skipped)?;
+
+    let aggregated = Instant::now();
+    let ignored = totals.values().map(|data| data.skipped as u64).sum();
+
+    // Spellings of the same station were kept apart until now, with the rows they were first on
+    let totals: Vec<(String, StationData)> = if config.case_insensitive.value {
+        merge_case_variants_by_row(totals, config.track_extents.value)
+    } else {
+        totals.into_iter().collect()
+    };
+
+    // Build the alphabetically-sorted list of stations
+    let mut stations: Vec<StationInfo> = totals
+        .into_iter()
+        .filter(|(_, data)| data.cnt > 0)
+        .map(|(name, data)| {
+            StationInfo::new(name, data.min, data.max, data.avg(), data.cnt)
+                .with_extents(data.extents())
+        })
+        .collect();
+    stations.sort_unstable();
+
+    let stats = RunStats::new(Timings::since(start, aggregated, config))
+        .ignored_non_finite(ignored)
+        .skipped(skipped);
+
+    Ok((stations, stats))
+}
+
+/// Aggregate every line of a single range of the input, read in blocks from `input`, into a map
+/// of its own
+pub(super) fn aggregate_range<R: Read, S: BuildHasher + Default>(
+    input: R,
+    range: &Range<u64>,
+    config: &Config,
+) -> Result<Partial<S>, BlockFailure> {
+    let mut partial = Partial {
+        stations: HashMap::default(),
+        lines: 0,
+        skipped: SkippedLines::default(),
+    };
+    let max_skipped = config.max_skipped.value;
+    let mut reader = BlockReader::new(input, config)
+        .starting_at(range.start)
+        .count_lines(config.track_rows());
+
+    loop {
+        let block = match reader.next_block() {
+            Ok(Some(block)) => block,
+            Ok(None) => break,
+            // Lines too long to fit in a block are skipped here, like any other malformed line
+            Err(e) => {
+                partial
+                    .skipped
+                    .skip(e, 1, max_skipped)
+                    .map_err(|error| BlockFailure {
+                        block_offset: range.start,
+                        offset: range.start,
+                        error,
+                    })?;
+                continue;
+            }
+        };
+
+        let mut rows = 0;
+        let stations = &mut partial.stations;
+        block.for_each_record(
+            config.max_line_length.value,
+            config.strict.value,
+            config.dialect(),
+            max_skipped,
+            &mut partial.skipped,
+            |station, measurement, line| {
+                rows += 1;
+                if !stations.contains_key(station) {
+                    stations.insert(station.to_owned(), StationData::empty());
+                }
+                let data = stations
+                    .get_mut(station)
+                    .expect("The station was just added");
+                data.push(measurement, config.strict.value);
+                if let Some(first_line) = block.first_line {
+                    data.record_row(first_line + line - 1);
+                }
+            },
+        )?;
+        if let Some(recorder) = &config.chunk_manifest {
+            recorder.record(ChunkEntry::new(&block, rows));
+        }
+    }
+    // Only the lines before later ranges matter, and every range but the last ends with a newline
+    if let Some(next) = reader.line() {
+        partial.lines = next - 1;
+    }
+
+    Ok(partial)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{Setting, Source};
+    use crate::output::Format;
+    use crate::runners::tests::*;
+    use crate::Runner as Kind;
+    use std::{error, io::Write};
+
+    fn config(threads: usize) -> Config {
+        Config {
+            threads: Setting::new(threads, Source::Cli),
+            ..Config::default().with_runner(Kind::ScopedThreads, Source::Cli)
+        }
+    }
+
+    #[test]
+    fn correctness() -> Result<(), Box<dyn error::Error>> {
+        let input = io::Cursor::new(TEST_DATA.as_bytes());
+
+        let (actual, _) = Runner::run(input, &config(4))?;
+        assert_eq!(
+            actual, *EXPECTED_RESULT,
+            "actual != expected for scoped-threads runner"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn range_boundary_mid_line() -> Result<(), Box<dyn error::Error>> {
+        // Evenly split, the boundaries between most threads' ranges fall in the middle of lines
+        for threads in (1..=8).chain([TEST_DATA.len()]) {
+            let (actual, _) = Runner::run(io::Cursor::new(TEST_DATA.as_bytes()), &config(threads))?;
+            assert_eq!(actual, *EXPECTED_RESULT, "{threads} threads");
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn file_per_thread() -> Result<(), Box<dyn error::Error>> {
+        let mut file = tempfile::NamedTempFile::new()?;
+        file.write_all(TEST_DATA.as_bytes())?;
+
+        let mut config = config(3);
+        config.canonical_input.value = file.path().to_owned();
+        let (actual, _) = crate::runners::run(&config)?;
+        assert_eq!(
+            actual, *EXPECTED_RESULT,
+            "reading the file on each thread changed the result"
+        );
+
+        let empty = tempfile::NamedTempFile::new()?;
+        let (actual, _) = Runner::run_file(empty.path(), &config)?;
+        assert!(actual.is_empty());
+
+        Ok(())
+    }
+
+    /// Rows & the line numbers of errors are counted from the start of the input, not the range
+    #[test]
+    fn rows_span_ranges() -> Result<(), Box<dyn error::Error>> {
+        let input = format!("\u{FEFF}{TEST_DATA}\n{TEST_DATA}");
+        let mut config = config(5);
+        config.track_extents.value = true;
+        config.buffer_size.value = 16;
+        let baseline = Config {
+            track_extents: config.track_extents.clone(),
+            ..Config::default().with_runner(Kind::Baseline, Source::Cli)
+        };
+
+        let (expected, _) = crate::runners::run_with(io::Cursor::new(&input), &baseline)?;
+        let (actual, _) = Runner::run(io::Cursor::new(&input), &config)?;
+        assert_eq!(Format::Text.render(&actual), Format::Text.render(&expected));
+        assert!(actual.iter().all(|station| station.extents().is_some()));
+
+        let malformed = format!("{TEST_DATA}{TEST_DATA}Nowhere\n{TEST_DATA}");
+        let expected = crate::runners::run_with(io::Cursor::new(&malformed), &baseline)
+            .expect_err("The baseline runner accepted a malformed line");
+        let actual = Runner::run(io::Cursor::new(&malformed), &config)
+            .expect_err("A malformed line was accepted");
+        assert_eq!(actual.to_string(), expected.to_string());
+
+        Ok(())
+    }
+}
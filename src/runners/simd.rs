@@ -0,0 +1,345 @@
+// 1BRC - my take on the 1 Billion Row Challenge
+// Copyright (C) 2024  Charles German <5donuts@pm.me>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Use the same approach as `memchr`, but find every newline & delimiter in the input by comparing
+//! 32 bytes at a time with portable SIMD vectors from the `wide` crate, so the loop over the lines
+//! jumps straight from one to the next.
+
+use std::path::Path;
+use std::time::Instant;
+
+use ahash::RandomState;
+use wide::{u8x32, CmpEq};
+
+use crate::aggregate::{parse_fixed, Aggregator, KeyFormat};
+use crate::config::Config;
+use crate::error::ChallengeError;
+use crate::helpers::*;
+use crate::reader::leading_bom;
+
+use super::mmap::map_file;
+
+/// The number of bytes compared at once
+const LANES: usize = 32;
+
+pub struct Runner;
+
+impl Runner {
+    /// Map the file at `path` into memory & scan its bytes in place, like the
+    /// [`mmap`](super::Mmap) runner.
+    pub fn run_file(path: &Path, config: &Config) -> ChallengeResult {
+        let start = Instant::now();
+        match map_file(path, config.byte_range.as_ref())? {
+            Some(map) => aggregate(&map, start, config),
+            None => aggregate(&[], start, config),
+        }
+    }
+}
+
+impl ChallengeRunner for Runner {
+    /// Any input other than a file is read into memory in one go & scanned from there; see
+    /// [`Runner::run_file`].
+    fn run<R>(mut input: R, config: &Config) -> ChallengeResult
+    where
+        R: std::io::Read + std::io::Seek,
+    {
+        let start = Instant::now();
+
+        let mut data = Vec::new();
+        input.read_to_end(&mut data)?;
+        aggregate(&data, start, config)
+    }
+}
+
+/// The offsets of every newline & delimiter in a buffer, in order.
+///
+/// The buffer is compared against both bytes [`LANES`] bytes at a time, and the matches in each
+/// block are kept as a bitmask to hand out one by one, so the scalar loop consuming them never
+/// looks at the bytes in between. The last block is copied into a padded array, and any matches
+/// in the padding are masked off, so the buffer needn't be a multiple of [`LANES`] long.
+struct Delimiters<'a> {
+    data: &'a [u8],
+    delimiter: u8x32,
+    newline: u8x32,
+
+    /// The offset of the block `mask` is for
+    block: usize,
+
+    /// A bit for each match in the block not yet handed out
+    mask: u32,
+}
+
+impl<'a> Delimiters<'a> {
+    fn new(data: &'a [u8], delimiter: u8) -> Self {
+        let mut delimiters = Self {
+            data,
+            delimiter: u8x32::splat(delimiter),
+            newline: u8x32::splat(b'\n'),
+            block: 0,
+            mask: 0,
+        };
+        delimiters.mask = delimiters.matches_at(0);
+        delimiters
+    }
+
+    /// The matches in the block at `offset`
+    #[inline]
+    fn matches_at(&self, offset: usize) -> u32 {
+        let rest = &self.data[offset.min(self.data.len())..];
+        let (bytes, valid) = match rest.first_chunk::<LANES>() {
+            Some(bytes) => (*bytes, u32::MAX),
+            None => {
+                let mut bytes = [0; LANES];
+                bytes[..rest.len()].copy_from_slice(rest);
+                (bytes, (1 << rest.len()) - 1)
+            }
+        };
+        let bytes = u8x32::new(bytes);
+        let matches = bytes.cmp_eq(self.delimiter) | bytes.cmp_eq(self.newline);
+        matches.move_mask() as u32 & valid
+    }
+}
+
+impl Iterator for Delimiters<'_> {
+    type Item = usize;
+
+    #[inline]
+    fn next(&mut self) -> Option<usize> {
+        while self.mask == 0 {
+            self.block += LANES;
+            if self.block >= self.data.len() {
+                return None;
+            }
+            self.mask = self.matches_at(self.block);
+        }
+        let bit = self.mask.trailing_zeros() as usize;
+        // Clear the lowest set bit
+        self.mask &= self.mask - 1;
+        Some(self.block + bit)
+    }
+}
+
+/// Aggregate every line in `data`, finding the ends of the lines & the delimiters in them with
+/// [`Delimiters`].
+fn aggregate(data: &[u8], start: Instant, config: &Config) -> ChallengeResult {
+    aggregate_with(data, Delimiters::new, start, config)
+}
+
+/// Aggregate every line in `data`, finding the ends of the lines & the delimiters in them with
+/// the iterator `scan` returns over the offsets of every newline & `delimiter`, in order. Shared
+/// with the [`swar`](super::Swar) runner, which only finds the offsets differently.
+///
+/// Like the [`memchr`](super::Memchr) runner, lines in the canonical format are split at the
+/// first delimiter found in them & handed to the aggregator as a station & measurement, and
+/// anything else goes through [`Aggregator::ingest_line`], so it's accepted or reported exactly
+/// as the other runners would. A final newline ends the last line rather than starting an empty
+/// one, so the last line doesn't need a newline at all.
+pub(super) fn aggregate_with<'a, D>(
+    data: &'a [u8],
+    scan: fn(&'a [u8], u8) -> D,
+    start: Instant,
+    config: &Config,
+) -> ChallengeResult
+where
+    D: Iterator<Item = usize>,
+{
+    let mut aggregator: Aggregator<RandomState> =
+        Aggregator::with_known_stations(config.known_stations.clone())
+            .strict(config.strict.value)
+            .dialect(config.dialect())
+            .track_extents(config.track_extents.value)
+            .case_insensitive(config.case_insensitive.value)
+            .max_skipped(config.max_skipped.value)
+            .reuse_results(config.take_results_buffer());
+    let max_line_length = config.max_line_length.value;
+
+    // With a decimal comma (or a month before the station), the first delimiter might not be the
+    // one before the measurement
+    let dialect = config.dialect();
+    let delimiter = dialect.delimiter.byte();
+    let split = dialect.key_format == KeyFormat::Station && !dialect.decimal_comma;
+
+    let mut decoded = String::new();
+    let line = Line {
+        data,
+        max_line_length,
+        config,
+        split,
+    };
+    let mut line_start = 0;
+    let mut first_delimiter = None;
+    for idx in scan(data, delimiter) {
+        if data[idx] == b'\n' {
+            line.ingest(
+                line_start..idx,
+                first_delimiter,
+                &mut aggregator,
+                &mut decoded,
+            )?;
+            line_start = idx + 1;
+            first_delimiter = None;
+        } else if first_delimiter.is_none() {
+            first_delimiter = Some(idx);
+        }
+    }
+    if line_start < data.len() {
+        line.ingest(
+            line_start..data.len(),
+            first_delimiter,
+            &mut aggregator,
+            &mut decoded,
+        )?;
+    }
+
+    let aggregated = Instant::now();
+    let ignored = aggregator.ignored_non_finite();
+    let skipped = aggregator.skipped();
+    let stations = aggregator.into_sorted();
+
+    let stats = RunStats::new(Timings::since(start, aggregated, config))
+        .ignored_non_finite(ignored)
+        .skipped(skipped);
+
+    Ok((stations, stats))
+}
+
+/// What's needed to ingest each line of the buffer
+struct Line<'a> {
+    data: &'a [u8],
+    max_line_length: usize,
+    config: &'a Config,
+    split: bool,
+}
+
+impl Line<'_> {
+    /// Ingest the line at `range` (without its newline), whose first delimiter (if any) is at
+    /// `first_delimiter` in the buffer
+    #[inline]
+    fn ingest(
+        &self,
+        range: std::ops::Range<usize>,
+        first_delimiter: Option<usize>,
+        aggregator: &mut Aggregator<RandomState>,
+        decoded: &mut String,
+    ) -> Result<(), ChallengeError> {
+        let mut line_start = range.start;
+        if range.len() > self.max_line_length {
+            return aggregator.skip_unreadable(ChallengeError::LineTooLong {
+                offset: line_start as u64,
+                limit: self.max_line_length,
+            });
+        }
+        let mut line = &self.data[range];
+        line = line.strip_suffix(b"\r").unwrap_or(line);
+
+        if line_start == 0 {
+            match leading_bom(line, self.config.strict.value, self.config.verbose) {
+                Ok(skip) => {
+                    line = &line[skip..];
+                    line_start += skip;
+                }
+                Err(e) => return aggregator.skip_unreadable(e),
+            }
+        }
+
+        let dialect = self.config.dialect();
+        let line_bytes = line.len();
+        let line = match dialect.encoding.decode(line, decoded) {
+            Ok(line) => line,
+            Err(e) => {
+                return aggregator.skip_unreadable(ChallengeError::invalid_utf8(
+                    line_start as u64,
+                    line,
+                    e,
+                ))
+            }
+        };
+
+        // Transcoding turns every non-ASCII byte into several, so a line the same length as its
+        // bytes has the delimiter where it was found. The delimiter is ASCII, so it's never in
+        // the middle of a character.
+        let record = match first_delimiter {
+            Some(idx) if self.split && line.len() == line_bytes => {
+                let idx = idx - line_start;
+                parse_fixed(&line[idx + 1..]).map(|m| (&line[..idx], m))
+            }
+            _ => None,
+        };
+        match record {
+            Some((station, measurement)) => {
+                aggregator.ingest_record(station, measurement);
+                Ok(())
+            }
+            None => aggregator.ingest_line(line),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Source;
+    use crate::runners::tests::*;
+    use crate::Runner as Kind;
+    use std::{error, io};
+
+    fn config() -> Config {
+        Config::default().with_runner(Kind::Simd, Source::Cli)
+    }
+
+    #[test]
+    fn correctness() -> Result<(), Box<dyn error::Error>> {
+        let input = io::Cursor::new(TEST_DATA.as_bytes());
+
+        let (actual, _) = Runner::run(input, &config())?;
+        assert_eq!(
+            actual, *EXPECTED_RESULT,
+            "actual != expected for simd runner"
+        );
+
+        Ok(())
+    }
+
+    /// Every offset found in bulk is one a byte-by-byte scan finds, for buffers of every length
+    /// around a few blocks, starting at every alignment
+    #[test]
+    fn same_as_naive_scan() {
+        // A small LCG is plenty to scatter the bytes, and keeps failures reproducible
+        let mut state: u64 = 0x5DEE_CE66;
+        let mut random = move || {
+            state = state.wrapping_mul(6364136223846793005).wrapping_add(1);
+            (state >> 33) as u8
+        };
+        for len in 0..4 * LANES + 3 {
+            for density in [2, 8, 64] {
+                let bytes: Vec<u8> = (0..len + LANES)
+                    .map(|_| match random() % density {
+                        0 => b'\n',
+                        1 => b';',
+                        _ => random(),
+                    })
+                    .collect();
+                for offset in [0, 1, 7, LANES - 1] {
+                    let data = &bytes[offset..offset + len];
+                    let expected: Vec<usize> = (0..data.len())
+                        .filter(|&idx| data[idx] == b'\n' || data[idx] == b';')
+                        .collect();
+                    let actual: Vec<usize> = Delimiters::new(data, b';').collect();
+                    assert_eq!(actual, expected, "{data:?}");
+                }
+            }
+        }
+    }
+}
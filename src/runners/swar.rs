@@ -0,0 +1,258 @@
+// 1BRC - my take on the 1 Billion Row Challenge
+// Copyright (C) 2024  Charles German <5donuts@pm.me>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Use the same approach as `simd`, but find every newline & delimiter eight bytes at a time with
+//! bit tricks on a `u64` (SIMD within a register), which needs no vector instructions.
+
+use std::path::Path;
+use std::time::Instant;
+
+use crate::config::Config;
+use crate::helpers::*;
+
+use super::mmap::map_file;
+use super::simd::aggregate_with;
+
+/// The number of bytes in a word
+const WORD: usize = std::mem::size_of::<u64>();
+
+/// The low seven bits of every byte of a word
+const LOW_BITS: u64 = 0x7F7F_7F7F_7F7F_7F7F;
+
+pub struct Runner;
+
+impl Runner {
+    /// Map the file at `path` into memory & scan its bytes in place, like the
+    /// [`mmap`](super::Mmap) runner.
+    pub fn run_file(path: &Path, config: &Config) -> ChallengeResult {
+        let start = Instant::now();
+        match map_file(path, config.byte_range.as_ref())? {
+            Some(map) => aggregate_with(&map, Delimiters::new, start, config),
+            None => aggregate_with(&[], Delimiters::new, start, config),
+        }
+    }
+}
+
+impl ChallengeRunner for Runner {
+    /// Any input other than a file is read into memory in one go & scanned from there; see
+    /// [`Runner::run_file`].
+    fn run<R>(mut input: R, config: &Config) -> ChallengeResult
+    where
+        R: std::io::Read + std::io::Seek,
+    {
+        let start = Instant::now();
+
+        let mut data = Vec::new();
+        input.read_to_end(&mut data)?;
+        aggregate_with(&data, Delimiters::new, start, config)
+    }
+}
+
+/// `byte` in every byte of a word
+#[inline]
+const fn broadcast(byte: u8) -> u64 {
+    u64::from_le_bytes([byte; WORD])
+}
+
+/// The high bit of every byte of `word` which is zero, & no other bits.
+///
+/// The classic `(word - 0x01..) & !word & 0x80..` can borrow across bytes, flagging a `0x01` byte
+/// just above a zero one, which is fine for asking whether there's a zero at all but not for
+/// finding where each one is. Adding to the low seven bits of each byte can't carry into the
+/// next, so this flags exactly the zero bytes.
+#[inline]
+const fn zero_bytes(word: u64) -> u64 {
+    let low = (word & LOW_BITS).wrapping_add(LOW_BITS);
+    !(low | word | LOW_BITS)
+}
+
+/// The offsets of every newline & delimiter in a buffer, in order.
+///
+/// The buffer is read a little-endian `u64` at a time, whatever the target's byte order, so the
+/// first byte of each word is always its lowest; XORing each word with a copy of the
+/// newline (or delimiter) in every byte zeroes exactly the bytes which match, which
+/// [`zero_bytes`] turns into a bitmask to hand out one by one. The last word is copied into a
+/// padded one, and any matches in the padding are masked off, so the buffer needn't be a multiple
+/// of eight bytes long.
+struct Delimiters<'a> {
+    data: &'a [u8],
+    delimiter: u64,
+    newline: u64,
+
+    /// The offset of the word `mask` is for
+    word: usize,
+
+    /// The high bit of each byte of the word which matched & hasn't been handed out yet
+    mask: u64,
+}
+
+impl<'a> Delimiters<'a> {
+    fn new(data: &'a [u8], delimiter: u8) -> Self {
+        let mut delimiters = Self {
+            data,
+            delimiter: broadcast(delimiter),
+            newline: broadcast(b'\n'),
+            word: 0,
+            mask: 0,
+        };
+        delimiters.mask = delimiters.matches_at(0);
+        delimiters
+    }
+
+    /// The matches in the word at `offset`
+    #[inline]
+    fn matches_at(&self, offset: usize) -> u64 {
+        let rest = &self.data[offset.min(self.data.len())..];
+        let (bytes, valid) = match rest.first_chunk::<WORD>() {
+            Some(bytes) => (*bytes, u64::MAX),
+            None => {
+                let mut bytes = [0; WORD];
+                bytes[..rest.len()].copy_from_slice(rest);
+                (bytes, (1 << (rest.len() * 8)) - 1)
+            }
+        };
+        let word = u64::from_le_bytes(bytes);
+        (zero_bytes(word ^ self.delimiter) | zero_bytes(word ^ self.newline)) & valid
+    }
+}
+
+impl Iterator for Delimiters<'_> {
+    type Item = usize;
+
+    #[inline]
+    fn next(&mut self) -> Option<usize> {
+        while self.mask == 0 {
+            self.word += WORD;
+            if self.word >= self.data.len() {
+                return None;
+            }
+            self.mask = self.matches_at(self.word);
+        }
+        // The first byte is the lowest, as the word was read little-endian
+        let byte = self.mask.trailing_zeros() as usize / 8;
+        // Clear the lowest set bit
+        self.mask &= self.mask - 1;
+        Some(self.word + byte)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Source;
+    use crate::runners::tests::*;
+    use crate::Runner as Kind;
+    use std::{error, io};
+
+    fn config() -> Config {
+        Config::default().with_runner(Kind::Swar, Source::Cli)
+    }
+
+    fn naive(data: &[u8], delimiter: u8) -> Vec<usize> {
+        (0..data.len())
+            .filter(|&idx| data[idx] == b'\n' || data[idx] == delimiter)
+            .collect()
+    }
+
+    #[test]
+    fn correctness() -> Result<(), Box<dyn error::Error>> {
+        let input = io::Cursor::new(TEST_DATA.as_bytes());
+
+        let (actual, _) = Runner::run(input, &config())?;
+        assert_eq!(
+            actual, *EXPECTED_RESULT,
+            "actual != expected for swar runner"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn zero_bytes_are_exact() {
+        assert_eq!(zero_bytes(u64::MAX), 0);
+        assert_eq!(zero_bytes(0), broadcast(0x80));
+        // A 0x01 byte just above a zero one is where the classic trick goes wrong
+        let word = u64::from_le_bytes([0, 1, 0x80, 0, 0x7F, 0xFF, 1, 0]);
+        assert_eq!(
+            zero_bytes(word),
+            u64::from_le_bytes([0x80, 0, 0, 0x80, 0, 0, 0, 0x80])
+        );
+    }
+
+    /// Buffers which end part of the way through a word, with & without a match in that part
+    #[test]
+    fn final_partial_word() {
+        let cases: [&[u8]; 8] = [
+            b"",
+            b";",
+            b"A;1.0\n",
+            b"Hamburg;",
+            b"Hamburg;1",
+            b"Hamburg;12.0\n",
+            b"Hamburg;12.0\nBulawayo;8.9",
+            b"0123456789abcdef\n",
+        ];
+        for data in cases {
+            assert_eq!(
+                Delimiters::new(data, b';').collect::<Vec<_>>(),
+                naive(data, b';'),
+                "{}",
+                String::from_utf8_lossy(data)
+            );
+        }
+    }
+
+    /// Bytes next to the ones searched for (like `:` next to `;`) are never mistaken for them, at
+    /// any length or position
+    #[test]
+    fn same_as_naive_scan() {
+        const BYTES: &[u8] = b";:\n\x0b\t\0\x01\xffa";
+        for len in 0..=3 * WORD + 1 {
+            for seed in 0..BYTES.len() {
+                let data: Vec<u8> = (0..len)
+                    .map(|idx| BYTES[(idx * 7 + seed * 3 + idx / 5) % BYTES.len()])
+                    .collect();
+                for delimiter in [b';', b'\t', b'\0'] {
+                    assert_eq!(
+                        Delimiters::new(&data, delimiter).collect::<Vec<_>>(),
+                        naive(&data, delimiter),
+                        "{data:?}"
+                    );
+                }
+            }
+        }
+    }
+
+    /// A match in any byte of a word is handed out at that byte's offset, so the words' bytes
+    /// are in the buffer's order on any target
+    #[test]
+    fn each_byte_of_a_word() {
+        for idx in 0..2 * WORD {
+            let mut data = [b'a'; 2 * WORD];
+            data[idx] = b';';
+            assert_eq!(Delimiters::new(&data, b';').collect::<Vec<_>>(), [idx]);
+        }
+    }
+
+    /// Here the first byte of a native load is the highest, which reading the buffer with
+    /// `from_ne_bytes` would hand out last
+    #[cfg(target_endian = "big")]
+    #[test]
+    fn big_endian_order() {
+        let data = b"A;1.0\nB;";
+        assert_ne!(u64::from_ne_bytes(*data), u64::from_le_bytes(*data));
+        assert_eq!(Delimiters::new(data, b';').collect::<Vec<_>>(), [1, 5, 7]);
+    }
+}
@@ -0,0 +1,137 @@
+// 1BRC - my take on the 1 Billion Row Challenge
+// Copyright (C) 2024  Charles German <5donuts@pm.me>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Use the same approach as `table`, but software-pipeline the loop so the table slot for each line
+//! is prefetched while the previous line's stats are still being updated.
+
+use std::time::Instant;
+
+use crate::aggregate::parse_record_bytes;
+use crate::config::Config;
+use crate::helpers::*;
+use crate::reader::LineReader;
+use crate::table::StationTable;
+
+pub struct Runner;
+
+impl ChallengeRunner for Runner {
+    fn run<R>(input: R, config: &Config) -> ChallengeResult
+    where
+        R: std::io::Read + std::io::Seek,
+    {
+        let start = Instant::now();
+
+        // Software-pipeline the table runner's loop: once line N+1 is parsed & its slot is known,
+        // prefetch that slot and only then update the stats for line N. Looking up line N+1's
+        // station should then hit the cache rather than stalling on memory.
+        //
+        // The reader only lends out one line at a time, so line N's station name is copied into
+        // a reusable buffer while it waits.
+        let mut table = StationTable::with_known_stations(config.known_stations.clone())
+            .strict(config.strict.value)
+            .track_extents(config.track_extents.value)
+            .case_insensitive(config.case_insensitive.value)
+            .encoding(config.encoding.value)
+            .max_skipped(config.max_skipped.value);
+        let mut lines = LineReader::new(input, config);
+        let dialect = config.dialect();
+        let mut line_number = 0;
+        let mut pending_name = Vec::new();
+        let mut pending: Option<(u64, f32, u64, u64)> = None;
+        loop {
+            let (offset, line) = match lines.next_line_bytes() {
+                Ok(Some(line)) => line,
+                Ok(None) => break,
+                Err(e) => {
+                    line_number += 1;
+                    table.skip(e)?;
+                    continue;
+                }
+            };
+            line_number += 1;
+            let record = match parse_record_bytes(line, line_number, config.strict.value, dialect) {
+                Ok(record) => record,
+                Err(e) => {
+                    table.skip(e)?;
+                    continue;
+                }
+            };
+            let Some((station, measurement)) = record else {
+                continue;
+            };
+
+            let hash = StationTable::hash(station);
+            table.prefetch(table.slot_index(hash));
+
+            if let Some((pending_hash, pending_measurement, pending_offset, pending_row)) = pending
+            {
+                table.push_hashed(
+                    &pending_name,
+                    pending_hash,
+                    pending_measurement,
+                    pending_offset,
+                    pending_row,
+                );
+            }
+            pending_name.clear();
+            pending_name.extend_from_slice(station);
+            pending = Some((hash, measurement, offset, line_number));
+        }
+        if let Some((pending_hash, pending_measurement, pending_offset, pending_row)) = pending {
+            table.push_hashed(
+                &pending_name,
+                pending_hash,
+                pending_measurement,
+                pending_offset,
+                pending_row,
+            );
+        }
+
+        let aggregated = Instant::now();
+        table.skip_invalid_names()?;
+        let ignored = table.ignored_non_finite();
+        let skipped = table.skipped();
+
+        // Build the alphabetically-sorted list of stations
+        let stations = table.into_sorted()?;
+
+        // Compute the time it took to generate the list of sorted stations
+        let stats = RunStats::new(Timings::since(start, aggregated, config))
+            .ignored_non_finite(ignored)
+            .skipped(skipped);
+
+        Ok((stations, stats))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::runners::tests::*;
+    use std::{error, io};
+
+    #[test]
+    fn correctness() -> Result<(), Box<dyn error::Error>> {
+        let input = io::Cursor::new(TEST_DATA.as_bytes());
+
+        let (actual, _) = Runner::run(input, &Config::default())?;
+        assert_eq!(
+            actual, *EXPECTED_RESULT,
+            "actual != expected for table-prefetch runner"
+        );
+
+        Ok(())
+    }
+}
@@ -0,0 +1,274 @@
+// 1BRC - my take on the 1 Billion Row Challenge
+// Copyright (C) 2024  Charles German <5donuts@pm.me>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Use the same approach as `scoped-threads`, but with a thread for each core available, whatever
+//! `--threads` says, each pinned to its own core & aggregating into an FxHash map. Only built with
+//! the `affinity` feature.
+//!
+//! Like the `scoped-threads` runner, the input is split into one newline-aligned range per
+//! thread, each aggregated into a map of its own & merged in order. Here, though, there's always
+//! a thread for each core the process may run on, whatever `--threads` says, & each one is pinned
+//! to its core for the whole run, so the scheduler never moves a thread (& leaves its cache
+//! behind) part of the way through its range.
+//!
+//! Pinning is only an optimization: if the cores can't be listed, or a thread can't be pinned to
+//! one (e.g. in a cgroup restricted to fewer cores), the thread runs unpinned, with a warning unless
+//! `--no-warnings` is given.
+
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::ops::Range;
+use std::path::Path;
+use std::time::Instant;
+
+use rustc_hash::FxBuildHasher;
+
+use crate::blocks::BlockFailure;
+use crate::config::Config;
+use crate::helpers::*;
+use crate::plan::chunk_boundaries;
+use crate::topology::Cpus;
+use crate::window::Window;
+
+use super::scoped_threads::{aggregate_range, merge, Partial};
+
+pub struct Runner;
+
+/// What a thread aggregated from its range, & the core it ran on if it was pinned to one
+type Pinned = (Result<Partial<FxBuildHasher>, BlockFailure>, Option<usize>);
+
+impl Runner {
+    /// How many threads there are: one for each core available to the process, counted the same
+    /// way as for the default `--threads` (so within a cgroup's limits)
+    pub fn threads() -> usize {
+        Cpus::detect().available().unwrap_or(1)
+    }
+
+    /// Split the file at `path` (or its `--byte-range`) into a newline-aligned range per core &
+    /// have each core's thread read its range through a file handle of its own.
+    pub fn run_file(path: &Path, config: &Config) -> ChallengeResult {
+        let start = Instant::now();
+
+        let file = File::open(path)?;
+        let window = match &config.byte_range {
+            Some(window) => window.clone(),
+            None => 0..file.metadata()?.len(),
+        };
+        let len = window.end - window.start;
+        let mut input = Window::new(file, window.clone())?;
+        aggregate(&mut input, len, Self::threads(), start, config, |range| {
+            let mut file = File::open(path)?;
+            file.seek(SeekFrom::Start(window.start + range.start))?;
+            Ok(file.take(range.end - range.start))
+        })
+    }
+}
+
+impl ChallengeRunner for Runner {
+    /// Only a file can be opened once per thread, so any other input is read into memory in one
+    /// go & each thread reads its range from there instead; see [`Runner::run_file`].
+    fn run<R>(mut input: R, config: &Config) -> ChallengeResult
+    where
+        R: std::io::Read + std::io::Seek,
+    {
+        let start = Instant::now();
+
+        let mut data = Vec::new();
+        input.read_to_end(&mut data)?;
+        let len = data.len() as u64;
+        let threads = Self::threads();
+        aggregate(
+            &mut io::Cursor::new(&data),
+            len,
+            threads,
+            start,
+            config,
+            |range| Ok(&data[range.start as usize..range.end as usize]),
+        )
+    }
+}
+
+/// Split `len` bytes of `input` into `threads` newline-aligned ranges, aggregate each range on a
+/// thread of its own pinned to a core, reading through `open(range)`, & merge the results in the
+/// order of their ranges.
+///
+/// The threads take the cores in the order they're listed, so with a thread per core each has
+/// one to itself. With `--verbose`, how many rows each thread aggregated is printed to stderr.
+fn aggregate<R, O, S>(
+    input: &mut R,
+    len: u64,
+    threads: usize,
+    start: Instant,
+    config: &Config,
+    open: O,
+) -> ChallengeResult
+where
+    R: Read + Seek,
+    O: Fn(&Range<u64>) -> io::Result<S> + Sync,
+    S: Read,
+{
+    if let Some(recorder) = &config.chunk_manifest {
+        recorder.start();
+    }
+    let ranges = chunk_boundaries(input, len, threads, config.max_line_length.value)?;
+    let cores = core_affinity::get_core_ids().unwrap_or_default();
+
+    let results: Vec<Pinned> = std::thread::scope(|s| {
+        let workers: Vec<_> = ranges
+            .iter()
+            .enumerate()
+            .map(|(idx, range)| {
+                let open = &open;
+                let core = (!cores.is_empty()).then(|| cores[idx % cores.len()]);
+                s.spawn(move || {
+                    let pinned = core
+                        .filter(|&core| core_affinity::set_for_current(core))
+                        .map(|core| core.id);
+                    let partial = open(range)
+                        .map_err(|e| BlockFailure {
+                            block_offset: range.start,
+                            offset: range.start,
+                            error: e.into(),
+                        })
+                        .and_then(|reader| aggregate_range(reader, range, config));
+                    (partial, pinned)
+                })
+            })
+            .collect();
+        workers
+            .into_iter()
+            .map(|worker| worker.join().expect("Worker thread panicked"))
+            .collect()
+    });
+
+    let unpinned = results.iter().filter(|(_, core)| core.is_none()).count();
+    if unpinned > 0 && config.warnings {
+        eprintln!(
+            "Warning: unable to pin {unpinned} of {} threads to a core; they ran unpinned",
+            results.len()
+        );
+    }
+    if config.verbose > 0 {
+        for (idx, (partial, core)) in results.iter().enumerate() {
+            let core = core.map_or_else(|| "unpinned".to_owned(), |core| format!("core {core}"));
+            if let Ok(partial) = partial {
+                eprintln!("Thread {idx} ({core}): {} rows", partial.rows());
+            }
+        }
+    }
+
+    let results = results.into_iter().map(|(partial, _)| partial).collect();
+    merge(input, results, start, config)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{Setting, Source};
+    use crate::runners::tests::*;
+    use crate::Runner as Kind;
+    use std::{error, io::Write};
+
+    fn config() -> Config {
+        Config {
+            track_extents: Setting::new(true, Source::Cli),
+            ..Config::default().with_runner(Kind::ThreadPerCore, Source::Cli)
+        }
+    }
+
+    /// Aggregate `input` with `threads` threads, however many cores there are
+    fn run_with_threads(input: &str, threads: usize) -> ChallengeResult {
+        let data = input.as_bytes();
+        let len = data.len() as u64;
+        aggregate(
+            &mut io::Cursor::new(data),
+            len,
+            threads,
+            Instant::now(),
+            &config(),
+            |range| Ok(&data[range.start as usize..range.end as usize]),
+        )
+    }
+
+    #[test]
+    fn correctness() -> Result<(), Box<dyn error::Error>> {
+        let input = io::Cursor::new(TEST_DATA.as_bytes());
+
+        let (actual, _) = Runner::run(input, &Config::default())?;
+        assert_eq!(
+            actual, *EXPECTED_RESULT,
+            "actual != expected for thread-per-core runner"
+        );
+
+        Ok(())
+    }
+
+    /// With the same number of threads, the ranges & the order they're merged in are the same
+    /// as the `scoped-threads` runner's, so the results are the same bits
+    #[test]
+    fn same_as_scoped_threads() -> Result<(), Box<dyn error::Error>> {
+        let input = format!("{TEST_DATA}東京;12.3\n{TEST_DATA}Aïn el Mediour;-1.0\n");
+        for threads in (1..=8).chain([input.len()]) {
+            let scoped = Config {
+                threads: Setting::new(threads, Source::Cli),
+                ..config().with_runner(Kind::ScopedThreads, Source::Cli)
+            };
+            let (expected, _) = crate::runners::run_with(io::Cursor::new(&input), &scoped)?;
+            let (actual, _) = run_with_threads(&input, threads)?;
+            assert_eq!(bits(&actual), bits(&expected), "{threads} threads");
+        }
+
+        Ok(())
+    }
+
+    /// A line which can't be skipped is reported with its number in the whole input, whichever
+    /// thread's range it's in
+    #[test]
+    fn malformed_line() {
+        let input = format!("{TEST_DATA}{TEST_DATA}Nowhere\n{TEST_DATA}");
+        let baseline = Config::default().with_runner(Kind::Baseline, Source::Cli);
+        let expected = crate::runners::run_with(io::Cursor::new(&input), &baseline)
+            .expect_err("The baseline runner accepted a malformed line");
+        for threads in [1, 3, 8] {
+            let actual =
+                run_with_threads(&input, threads).expect_err("A malformed line was accepted");
+            assert_eq!(
+                actual.to_string(),
+                expected.to_string(),
+                "{threads} threads"
+            );
+        }
+    }
+
+    #[test]
+    fn file_per_thread() -> Result<(), Box<dyn error::Error>> {
+        let mut file = tempfile::NamedTempFile::new()?;
+        file.write_all(TEST_DATA.as_bytes())?;
+
+        let mut config = config();
+        config.canonical_input.value = file.path().to_owned();
+        let (actual, _) = crate::runners::run(&config)?;
+        assert_eq!(
+            actual, *EXPECTED_RESULT,
+            "reading the file on each thread changed the result"
+        );
+
+        let empty = tempfile::NamedTempFile::new()?;
+        let (actual, _) = Runner::run_file(empty.path(), &config)?;
+        assert!(actual.is_empty());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,545 @@
+// 1BRC - my take on the 1 Billion Row Challenge
+// Copyright (C) 2024  Charles German <5donuts@pm.me>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Read the input asynchronously on a tokio runtime, parsing up to `--threads` blocks at once on
+//! tokio's blocking threads & merging their maps in order. Only built with the `tokio` feature.
+
+use std::collections::{HashMap, VecDeque};
+use std::io::{self, Read};
+use std::path::Path;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Instant;
+
+use tokio::io::{AsyncRead, AsyncReadExt, ReadBuf};
+use tokio::runtime::Builder;
+use tokio::task::JoinHandle;
+
+use crate::aggregate::{merge_case_variants_by_row, Dialect, StationData};
+use crate::blocks::{count_newlines, Block, BlockFailure};
+use crate::boundaries::{first_newline, last_newline};
+use crate::config::Config;
+use crate::error::{ChallengeError, SkippedLines};
+use crate::helpers::*;
+use crate::manifest::ChunkEntry;
+use crate::reader::leading_bom;
+
+pub struct Runner;
+
+impl Runner {
+    /// Read the file at `path` asynchronously through [`tokio::fs::File`], parsing the blocks
+    /// read on tokio's blocking threads.
+    pub fn run_file(path: &Path, config: &Config) -> ChallengeResult {
+        let start = Instant::now();
+        let runtime = runtime(config)?;
+        let failure = match runtime.block_on(async {
+            let file = tokio::fs::File::open(path).await?;
+            aggregate(file, config).await
+        })? {
+            Ok(totals) => return finish(totals, start, config),
+            Err(failure) => failure,
+        };
+        let mut file = std::fs::File::open(path)?;
+        Err(failure.locate(&mut file).into())
+    }
+}
+
+impl ChallengeRunner for Runner {
+    /// Any input other than a file (e.g. a `Cursor` in tests) is read on the thread driving the
+    /// runtime rather than asynchronously, while the blocks read are still parsed on the blocking
+    /// threads; see [`Runner::run_file`].
+    fn run<R>(mut input: R, config: &Config) -> ChallengeResult
+    where
+        R: std::io::Read + std::io::Seek,
+    {
+        let start = Instant::now();
+        let runtime = runtime(config)?;
+        let failure = match runtime.block_on(aggregate(Blocking(&mut input), config))? {
+            Ok(totals) => return finish(totals, start, config),
+            Err(failure) => failure,
+        };
+        Err(failure.locate(&mut input).into())
+    }
+}
+
+/// A runtime on the calling thread, with enough blocking threads to parse a block on each of
+/// `--threads` threads at once
+fn runtime(config: &Config) -> io::Result<tokio::runtime::Runtime> {
+    Builder::new_current_thread()
+        .max_blocking_threads(config.threads.value.max(1))
+        .build()
+}
+
+/// What was aggregated from a single block of the input
+struct Partial {
+    stations: HashMap<String, StationData>,
+
+    /// Malformed lines skipped in the block
+    skipped: SkippedLines,
+
+    /// The block's entry in the chunk manifest
+    entry: ChunkEntry,
+
+    /// The block's buffer, emptied for the next block to be read into
+    buffer: Vec<u8>,
+}
+
+/// The totals for every station, & every line skipped
+struct Totals {
+    stations: HashMap<String, StationData>,
+    skipped: SkippedLines,
+}
+
+/// The settings needed to parse a block, copied out of the config so each blocking task can own
+/// them
+#[derive(Clone, Copy)]
+struct Parse {
+    max_line_length: usize,
+    strict: bool,
+    dialect: Dialect,
+    max_skipped: u64,
+}
+
+/// Read `input` in blocks of whole lines, parsing up to `--threads` blocks at once on blocking
+/// tasks while the next is read, & merge each block's map into the totals.
+///
+/// The maps are merged in the order of their blocks, so the results only depend on the block
+/// size, not on how the tasks are scheduled. An error from a line (once no more lines can be
+/// skipped) is returned as the inner `Err` for the caller to [locate](BlockFailure::locate).
+async fn aggregate<A>(input: A, config: &Config) -> io::Result<Result<Totals, BlockFailure>>
+where
+    A: AsyncRead + Unpin,
+{
+    if let Some(recorder) = &config.chunk_manifest {
+        recorder.start();
+    }
+    let parse = Parse {
+        max_line_length: config.max_line_length.value,
+        strict: config.strict.value,
+        dialect: config.dialect(),
+        max_skipped: config.max_skipped.value,
+    };
+    let workers = config.threads.value.max(1);
+
+    let mut reader = AsyncBlockReader::new(input, config).count_lines(config.track_rows());
+    let mut totals = Totals {
+        stations: HashMap::new(),
+        skipped: SkippedLines::default(),
+    };
+    let mut pending: VecDeque<JoinHandle<Result<Partial, BlockFailure>>> = VecDeque::new();
+    // Buffers of blocks already merged, so at most one per worker (& the one being read) is ever
+    // allocated
+    let mut pool: Vec<Vec<u8>> = Vec::with_capacity(workers);
+    loop {
+        let buffer = pool.pop().unwrap_or_default();
+        let block = match reader.next_block_into(buffer).await {
+            Ok(Some(block)) => block,
+            Ok(None) => break,
+            // Lines too long to fit in a block are skipped here, like any other malformed line
+            Err(e) => {
+                let offset = reader.offset;
+                if let Err(error) = totals.skipped.skip(e, 1, parse.max_skipped) {
+                    return Ok(Err(BlockFailure {
+                        block_offset: offset,
+                        offset,
+                        error,
+                    }));
+                }
+                continue;
+            }
+        };
+
+        // Wait for the oldest block to be parsed before reading any further ahead
+        if pending.len() == workers {
+            let oldest = pending.pop_front().expect("There's a block being parsed");
+            match merge(&mut totals, oldest.await?, config) {
+                Ok(buffer) => pool.push(buffer),
+                Err(failure) => return Ok(Err(failure)),
+            }
+        }
+        pending.push_back(tokio::task::spawn_blocking(move || {
+            aggregate_block(block, parse)
+        }));
+    }
+    for partial in pending {
+        if let Err(failure) = merge(&mut totals, partial.await?, config) {
+            return Ok(Err(failure));
+        }
+    }
+    Ok(Ok(totals))
+}
+
+/// Aggregate every line of a single block into a map of its own
+fn aggregate_block(block: Block, parse: Parse) -> Result<Partial, BlockFailure> {
+    let mut stations: HashMap<String, StationData> = HashMap::new();
+    let mut skipped = SkippedLines::default();
+    let mut rows = 0;
+    block.for_each_record(
+        parse.max_line_length,
+        parse.strict,
+        parse.dialect,
+        parse.max_skipped,
+        &mut skipped,
+        |station, measurement, line| {
+            rows += 1;
+            if !stations.contains_key(station) {
+                stations.insert(station.to_owned(), StationData::empty());
+            }
+            let data = stations
+                .get_mut(station)
+                .expect("The station was just added");
+            data.push(measurement, parse.strict);
+            if let Some(first_line) = block.first_line {
+                data.record_row(first_line + line - 1);
+            }
+        },
+    )?;
+
+    let entry = ChunkEntry::new(&block, rows);
+    let mut buffer = block.data;
+    buffer.clear();
+    Ok(Partial {
+        stations,
+        skipped,
+        entry,
+        buffer,
+    })
+}
+
+/// Fold the map of the next block in order into the totals, returning the block's buffer
+fn merge(
+    totals: &mut Totals,
+    partial: Result<Partial, BlockFailure>,
+    config: &Config,
+) -> Result<Vec<u8>, BlockFailure> {
+    let partial = partial?;
+    for (name, data) in partial.stations {
+        totals
+            .stations
+            .entry(name)
+            .or_insert_with(StationData::empty)
+            .merge(&data);
+    }
+    // Each task only knows about the lines skipped in its block
+    totals.skipped.merge(&partial.skipped);
+    if let Some(recorder) = &config.chunk_manifest {
+        recorder.record(partial.entry);
+    }
+    Ok(partial.buffer)
+}
+
+/// Build the sorted list of stations from the totals
+fn finish(totals: Totals, start: Instant, config: &Config) -> ChallengeResult {
+    let Totals { stations, skipped } = totals;
+    skipped.check(config.max_skipped.value)?;
+
+    let aggregated = Instant::now();
+    let ignored = stations.values().map(|data| data.skipped as u64).sum();
+
+    // Spellings of the same station were kept apart until now, with the rows they were first on
+    let totals: Vec<(String, StationData)> = if config.case_insensitive.value {
+        merge_case_variants_by_row(stations, config.track_extents.value)
+    } else {
+        stations.into_iter().collect()
+    };
+
+    // Build the alphabetically-sorted list of stations
+    let mut stations: Vec<StationInfo> = totals
+        .into_iter()
+        .filter(|(_, data)| data.cnt > 0)
+        .map(|(name, data)| {
+            StationInfo::new(name, data.min, data.max, data.avg(), data.cnt)
+                .with_extents(data.extents())
+        })
+        .collect();
+    stations.sort_unstable();
+
+    let stats = RunStats::new(Timings::since(start, aggregated, config))
+        .ignored_non_finite(ignored)
+        .skipped(skipped);
+
+    Ok((stations, stats))
+}
+
+/// A blocking reader read from directly by the task polling it, which is fine on a runtime whose
+/// only other work is on the blocking threads
+struct Blocking<'a, R>(&'a mut R);
+
+impl<R: Read> AsyncRead for Blocking<'_, R> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        _: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let n = self.get_mut().0.read(buf.initialize_unfilled())?;
+        buf.advance(n);
+        Poll::Ready(Ok(()))
+    }
+}
+
+/// Reads [`Block`]s of whole lines asynchronously, stitching the partial line at the end of each
+/// read onto the start of the next block.
+///
+/// This is [`BlockReader`](crate::blocks::BlockReader), but awaiting each read: a line longer
+/// than the maximum line length is an error (with the rest of it skipped if reading carries on),
+/// and a leading byte-order mark is skipped (or an error, in strict mode).
+struct AsyncBlockReader<A> {
+    inner: A,
+    block_size: usize,
+    max_line_length: usize,
+    strict: bool,
+    verbose: u8,
+
+    /// Bytes read past the end of the last block
+    carry: Vec<u8>,
+
+    /// Offset of the start of `carry` in the input
+    offset: u64,
+
+    /// The line number of the start of `carry`, if counting lines
+    line: Option<u64>,
+
+    /// Whether the line at `offset` was too long, & the rest of it still needs skipping
+    skip_rest: bool,
+}
+
+impl<A: AsyncRead + Unpin> AsyncBlockReader<A> {
+    fn new(input: A, config: &Config) -> Self {
+        Self {
+            inner: input,
+            block_size: config.buffer_size.value.max(1),
+            max_line_length: config.max_line_length.value,
+            strict: config.strict.value,
+            verbose: config.verbose,
+            carry: Vec::new(),
+            offset: 0,
+            line: None,
+            skip_rest: false,
+        }
+    }
+
+    /// Count the lines in each block as it's read, so each [`Block`] knows its first line's
+    /// number
+    fn count_lines(mut self, count: bool) -> Self {
+        self.line = count.then_some(1);
+        self
+    }
+
+    /// Read the next block into `data` (whatever it held is discarded), or `Ok(None)` once the
+    /// input is exhausted.
+    ///
+    /// Each read goes straight into `data`'s spare capacity with
+    /// [`read_buf`](AsyncReadExt::read_buf), so a buffer handed back from an earlier block is
+    /// reused without being zeroed first.
+    async fn next_block_into(
+        &mut self,
+        mut data: Vec<u8>,
+    ) -> Result<Option<Block>, ChallengeError> {
+        if self.skip_rest {
+            self.skip_line().await?;
+        }
+        data.clear();
+        data.extend_from_slice(&self.carry);
+        self.carry.clear();
+        let mut offset = self.offset;
+
+        let end = loop {
+            let filled = data.len();
+            data.reserve(self.block_size);
+            // Never more than a block at a time, so the blocks are the same whatever the
+            // capacity of the buffer
+            let n = (&mut self.inner)
+                .take(self.block_size as u64)
+                .read_buf(&mut data)
+                .await?;
+
+            if n == 0 {
+                if data.is_empty() {
+                    return Ok(None);
+                }
+                break data.len();
+            }
+            if let Some(idx) = last_newline(&data[filled..]) {
+                break filled + idx + 1;
+            }
+
+            // Everything read so far is part of a single line
+            if data.len() > self.max_line_length {
+                self.offset = offset + data.len() as u64;
+                self.skip_rest = true;
+                return Err(ChallengeError::LineTooLong {
+                    offset,
+                    limit: self.max_line_length,
+                });
+            }
+        };
+
+        self.carry.extend_from_slice(&data[end..]);
+        data.truncate(end);
+        self.offset = offset + end as u64;
+        let first_line = self.line;
+        self.line = first_line.map(|line| line + count_newlines(&data));
+
+        if offset == 0 {
+            let skip = leading_bom(&data, self.strict, self.verbose)?;
+            data.drain(..skip);
+            offset += skip as u64;
+        }
+
+        Ok(Some(Block {
+            offset,
+            data,
+            first_line,
+        }))
+    }
+
+    /// Skip past the next newline, to get to the end of a line which was too long
+    async fn skip_line(&mut self) -> io::Result<()> {
+        let mut buf = vec![0; self.block_size];
+        loop {
+            let n = self.inner.read(&mut buf).await?;
+            if n == 0 {
+                break;
+            }
+            if let Some(idx) = first_newline(&buf[..n]) {
+                self.carry = buf[idx + 1..n].to_vec();
+                self.offset += idx as u64 + 1;
+                self.line = self.line.map(|line| line + 1);
+                break;
+            }
+            self.offset += n as u64;
+        }
+        self.skip_rest = false;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{Setting, Source};
+    use crate::output::Format;
+    use crate::runners::tests::*;
+    use crate::Runner as Kind;
+    use std::error;
+
+    fn config(threads: usize, buffer_size: usize) -> Config {
+        Config {
+            threads: Setting::new(threads, Source::Cli),
+            buffer_size: Setting::new(buffer_size, Source::Cli),
+            ..Config::default().with_runner(Kind::Tokio, Source::Cli)
+        }
+    }
+
+    #[test]
+    fn correctness() -> Result<(), Box<dyn error::Error>> {
+        let input = io::Cursor::new(TEST_DATA.as_bytes());
+
+        let (actual, _) = Runner::run(input, &Config::default())?;
+        assert_eq!(
+            actual, *EXPECTED_RESULT,
+            "actual != expected for tokio runner"
+        );
+
+        Ok(())
+    }
+
+    /// Reads end in the middle of lines at every block size up to a few lines long, whether the
+    /// input is read asynchronously from a file or not
+    #[test]
+    fn lines_split_across_reads() -> Result<(), Box<dyn error::Error>> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("measurements.txt");
+        let input = format!("\u{FEFF}{TEST_DATA}\n{TEST_DATA}");
+        std::fs::write(&path, &input)?;
+
+        let baseline = Config::default().with_runner(Kind::Baseline, Source::Cli);
+        let (expected, _) = crate::runners::run_with(io::Cursor::new(&input), &baseline)?;
+        for buffer_size in 1..=64 {
+            for threads in [1, 3, 8] {
+                let config = config(threads, buffer_size);
+                let (actual, _) = Runner::run(io::Cursor::new(&input), &config)?;
+                assert_eq!(
+                    Format::Text.render(&actual),
+                    Format::Text.render(&expected),
+                    "{buffer_size} bytes, {threads} threads"
+                );
+                let (actual, _) = Runner::run_file(&path, &config)?;
+                assert_eq!(
+                    Format::Text.render(&actual),
+                    Format::Text.render(&expected),
+                    "{buffer_size} bytes, {threads} threads"
+                );
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Rows & the line numbers of errors are counted from the start of the input, not the block
+    #[test]
+    fn rows_span_blocks() -> Result<(), Box<dyn error::Error>> {
+        let mut config = config(5, 16);
+        config.track_extents.value = true;
+        let baseline = Config {
+            track_extents: config.track_extents.clone(),
+            ..Config::default().with_runner(Kind::Baseline, Source::Cli)
+        };
+
+        let input = format!("{TEST_DATA}\n{TEST_DATA}");
+        let (expected, _) = crate::runners::run_with(io::Cursor::new(&input), &baseline)?;
+        let (actual, _) = Runner::run(io::Cursor::new(&input), &config)?;
+        assert_eq!(Format::Text.render(&actual), Format::Text.render(&expected));
+        assert!(actual.iter().all(|station| station.extents().is_some()));
+
+        let malformed = format!("{TEST_DATA}{TEST_DATA}Nowhere\n{TEST_DATA}");
+        let expected = crate::runners::run_with(io::Cursor::new(&malformed), &baseline)
+            .expect_err("The baseline runner accepted a malformed line");
+        let actual = Runner::run(io::Cursor::new(&malformed), &config)
+            .expect_err("A malformed line was accepted");
+        assert_eq!(actual.to_string(), expected.to_string());
+
+        Ok(())
+    }
+
+    /// A buffer with room for several blocks is reused for every block, which are the same as
+    /// the blocking reader's
+    #[test]
+    fn reused_buffer() -> Result<(), Box<dyn error::Error>> {
+        let input = format!("\u{FEFF}{TEST_DATA}");
+        let runtime = Builder::new_current_thread().build()?;
+        for buffer_size in [1, 5, 16, 1024] {
+            let config = config(1, buffer_size);
+            let mut expected = crate::blocks::BlockReader::new(input.as_bytes(), &config);
+
+            let mut cursor = io::Cursor::new(input.as_bytes());
+            let mut reader = AsyncBlockReader::new(Blocking(&mut cursor), &config);
+            let mut buf = Vec::with_capacity(4 * buffer_size + 64);
+            let ptr = buf.as_ptr();
+            while let Some(expected) = expected.next_block()? {
+                let block = runtime
+                    .block_on(reader.next_block_into(buf))?
+                    .expect("as many blocks");
+                assert_eq!(block.offset, expected.offset, "{buffer_size}");
+                assert_eq!(block.data, expected.data, "{buffer_size}");
+                assert_eq!(block.data.as_ptr(), ptr, "{buffer_size}");
+                buf = block.data;
+            }
+            assert!(runtime.block_on(reader.next_block_into(buf))?.is_none());
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,269 @@
+// 1BRC - my take on the 1 Billion Row Challenge
+// Copyright (C) 2024  Charles German <5donuts@pm.me>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Use the same approach as `memchr`, but keep every line as bytes & key the map by the bytes of
+//! each name, which only becomes a `&str` (unchecked) the first time it's seen. Relies on the
+//! challenge's promise that the input is valid UTF-8: other input is undefined behaviour, except in
+//! debug builds, which check each new name.
+//!
+//! Every line stays a `&[u8]` from the moment it's found to the moment it's aggregated, and a
+//! station's name only becomes a `&str` the first time it's seen, with
+//! [`std::str::from_utf8_unchecked`]. Input which isn't valid UTF-8 breaks that contract, and
+//! the results are then undefined behaviour rather than an error.
+//!
+//! With debug assertions on (as under `cargo test`), each new name is checked after all, and one
+//! which isn't valid is reported (or skipped) like any other runner would, so a corrupt fixture
+//! is still caught.
+
+use std::collections::HashMap;
+use std::path::Path;
+use std::time::Instant;
+
+use ahash::RandomState;
+use memchr::memchr;
+
+use crate::aggregate::{
+    merge_case_variants_by_row, parse_record_bytes, parse_tenths, Encoding, KeyFormat, StationData,
+};
+use crate::config::Config;
+use crate::error::{ChallengeError, SkippedLines};
+use crate::helpers::*;
+use crate::reader::leading_bom;
+
+use super::mmap::map_file;
+
+pub struct Runner;
+
+impl Runner {
+    /// Map the file at `path` into memory & search its bytes in place, like the
+    /// [`mmap`](super::Mmap) runner.
+    pub fn run_file(path: &Path, config: &Config) -> ChallengeResult {
+        let start = Instant::now();
+        match map_file(path, config.byte_range.as_ref())? {
+            Some(map) => aggregate(&map, start, config),
+            None => aggregate(&[], start, config),
+        }
+    }
+}
+
+impl ChallengeRunner for Runner {
+    /// Any input other than a file is read into memory in one go & searched from there; see
+    /// [`Runner::run_file`].
+    fn run<R>(mut input: R, config: &Config) -> ChallengeResult
+    where
+        R: std::io::Read + std::io::Seek,
+    {
+        let start = Instant::now();
+
+        let mut data = Vec::new();
+        input.read_to_end(&mut data)?;
+        aggregate(&data, start, config)
+    }
+}
+
+/// Read the name at byte `offset` in the input as UTF-8, without checking it unless debug
+/// assertions are on.
+#[inline]
+fn name(bytes: &[u8], offset: u64) -> Result<&str, ChallengeError> {
+    if cfg!(debug_assertions) {
+        return std::str::from_utf8(bytes)
+            .map_err(|e| ChallengeError::invalid_utf8(offset, bytes, e));
+    }
+    // SAFETY: this runner is only used on UTF-8 input (see `aggregate`), which the challenge
+    // guarantees is valid, & a name is only ever split from the rest of its line at an ASCII
+    // byte, which is never part of a multi-byte character. Input which breaks that guarantee is
+    // documented as undefined behaviour for this runner.
+    Ok(unsafe { std::str::from_utf8_unchecked(bytes) })
+}
+
+/// Aggregate every line in `data`, splitting the lines & their fields with `memchr`, into a map
+/// keyed by the bytes of each station's name, borrowed straight from `data`.
+///
+/// Lines in the canonical format are split here; anything else goes through
+/// [`parse_record_bytes`], so it's accepted or reported exactly as the other runners would.
+fn aggregate(data: &[u8], start: Instant, config: &Config) -> ChallengeResult {
+    // Other encodings are transcoded line by line, which checks them as it goes, so there's
+    // nothing to skip
+    if config.encoding.value != Encoding::Utf8 {
+        return super::memchr::aggregate(data, start, config);
+    }
+
+    let strict = config.strict.value;
+    let max_skipped = config.max_skipped.value;
+    let max_line_length = config.max_line_length.value;
+    let track_rows = config.track_rows();
+    let dialect = config.dialect();
+    let delimiter = dialect.delimiter.byte();
+    // With a decimal comma (or a month before the station), the first delimiter might not be the
+    // one before the measurement
+    let split = dialect.key_format == KeyFormat::Station && !dialect.decimal_comma;
+
+    let known = config
+        .known_stations
+        .as_ref()
+        .map_or(0, |names| names.len());
+    let mut ids: HashMap<&[u8], usize, RandomState> =
+        HashMap::with_capacity_and_hasher(known, RandomState::new());
+    let mut stations: Vec<(&str, StationData)> = Vec::with_capacity(known);
+    let mut skipped = SkippedLines::default();
+
+    let mut offset = 0;
+    let mut line_number = 0;
+    while offset < data.len() {
+        let rest = &data[offset..];
+        let end = memchr(b'\n', rest).unwrap_or(rest.len());
+        let mut line = &rest[..end];
+        let mut line_start = offset as u64;
+        offset += end + 1;
+        line_number += 1;
+
+        if line.len() > max_line_length {
+            skipped.skip(
+                ChallengeError::LineTooLong {
+                    offset: line_start,
+                    limit: max_line_length,
+                },
+                1,
+                max_skipped,
+            )?;
+            continue;
+        }
+        line = line.strip_suffix(b"\r").unwrap_or(line);
+
+        if line_start == 0 {
+            match leading_bom(line, strict, config.verbose) {
+                Ok(skip) => {
+                    line = &line[skip..];
+                    line_start += skip as u64;
+                }
+                Err(e) => {
+                    skipped.skip(e, 1, max_skipped)?;
+                    continue;
+                }
+            }
+        }
+
+        let fixed = match memchr(delimiter, line) {
+            Some(idx) if split => {
+                parse_tenths(&line[idx + 1..]).map(|tenths| (&line[..idx], tenths as f32 / 10.0))
+            }
+            _ => None,
+        };
+        let (station, measurement) = match fixed {
+            Some(record) => record,
+            None => match parse_record_bytes(line, line_number, strict, dialect) {
+                Ok(Some(record)) => record,
+                Ok(None) => continue,
+                Err(e) => {
+                    skipped.skip(e, 1, max_skipped)?;
+                    continue;
+                }
+            },
+        };
+
+        let id = match ids.get(station) {
+            Some(&id) => id,
+            None => match name(station, line_start) {
+                Ok(name) => {
+                    ids.insert(station, stations.len());
+                    stations.push((name, StationData::empty()));
+                    stations.len() - 1
+                }
+                Err(e) => {
+                    skipped.skip(e, 1, max_skipped)?;
+                    continue;
+                }
+            },
+        };
+        let entry = &mut stations[id].1;
+        entry.push(measurement, strict);
+        if track_rows {
+            entry.record_row(line_number);
+        }
+    }
+
+    let aggregated = Instant::now();
+    let ignored = stations.iter().map(|(_, data)| data.skipped as u64).sum();
+
+    let named = stations
+        .into_iter()
+        .map(|(name, data)| (name.to_owned(), data));
+    let named: Vec<(String, StationData)> = if config.case_insensitive.value {
+        merge_case_variants_by_row(named, config.track_extents.value)
+    } else {
+        named.collect()
+    };
+
+    // Build the alphabetically-sorted list of stations
+    let mut stations: Vec<StationInfo> = named
+        .into_iter()
+        .filter(|(_, data)| data.cnt > 0)
+        .map(|(name, data)| {
+            StationInfo::new(name, data.min, data.max, data.avg(), data.cnt)
+                .with_extents(data.extents())
+        })
+        .collect();
+    stations.sort_unstable();
+
+    let stats = RunStats::new(Timings::since(start, aggregated, config))
+        .ignored_non_finite(ignored)
+        .skipped(skipped);
+
+    Ok((stations, stats))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Source;
+    use crate::runners::tests::*;
+    use crate::Runner as Kind;
+    use std::{error, io};
+
+    fn config() -> Config {
+        Config::default().with_runner(Kind::Unchecked, Source::Cli)
+    }
+
+    #[test]
+    fn correctness() -> Result<(), Box<dyn error::Error>> {
+        let input = io::Cursor::new(TEST_DATA.as_bytes());
+
+        let (actual, _) = Runner::run(input, &config())?;
+        assert_eq!(
+            actual, *EXPECTED_RESULT,
+            "actual != expected for unchecked runner"
+        );
+
+        Ok(())
+    }
+
+    /// Under `cargo test`, a name which isn't valid UTF-8 is still caught
+    #[cfg(debug_assertions)]
+    #[test]
+    fn checks_names_with_debug_assertions() {
+        let input = b"Hamburg;12.0\nZ\xFCrich;1.0\n";
+        let err = Runner::run(io::Cursor::new(input), &config()).unwrap_err();
+        match err.downcast_ref::<ChallengeError>() {
+            Some(ChallengeError::InvalidUtf8 { offset: 14, .. }) => {}
+            _ => panic!("unexpected error {err}"),
+        }
+
+        let mut config = config();
+        config.max_skipped.value = 1;
+        let (stations, stats) = Runner::run(io::Cursor::new(input), &config).unwrap();
+        assert_eq!(stations.len(), 1);
+        assert_eq!(stats.skipped.invalid_utf8, 1);
+    }
+}
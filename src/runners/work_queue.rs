@@ -0,0 +1,303 @@
+// 1BRC - my take on the 1 Billion Row Challenge
+// Copyright (C) 2024  Charles German <5donuts@pm.me>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Split the input into many small newline-aligned ranges, all queued up front, which a fixed
+//! number of worker threads take off the queue one at a time until it's empty, so a slow worker (or
+//! a slow range) doesn't leave the others idle. Every worker reads the same file handle with
+//! positioned reads, & the ranges' maps are merged in order at the end.
+//!
+//! The [`scoped-threads`](super::ScopedThreads) runner gives each thread one range of the input,
+//! so a thread which is slower than the rest (or a range with more to do) leaves the others idle
+//! at the end. Here the input is split into many newline-aligned ranges of about
+//! [`Runner::CHUNK_SIZE`] bytes, all queued up front, & each of [`Runner::WORKERS`] threads
+//! takes the next range off the queue whenever it finishes one, until there are none left.
+//!
+//! Every worker reads the same file handle with positioned reads, so none of them contend on a
+//! shared cursor. Each range is aggregated into a map of its own, & the maps are merged in the
+//! order of the ranges, so which worker took which range makes no difference to the results.
+
+use std::fs::File;
+use std::io::{self, Read, Seek};
+use std::ops::Range;
+use std::path::Path;
+use std::time::Instant;
+
+use crate::blocks::BlockFailure;
+use crate::config::Config;
+use crate::error::ChallengeError;
+use crate::helpers::*;
+use crate::plan::chunk_boundaries;
+use crate::platform::read_at;
+use crate::window::Window;
+
+use super::scoped_threads::{aggregate_range, merge, Partial};
+
+pub struct Runner;
+
+impl Runner {
+    /// How many threads take ranges off the queue, whatever `--threads` is
+    pub const WORKERS: usize = 8;
+
+    /// Roughly how long each range queued is
+    pub const CHUNK_SIZE: u64 = 32 * 1024 * 1024;
+
+    /// Queue the ranges of the file at `path` (or its `--byte-range`) & have the workers read
+    /// them from one shared handle with positioned reads.
+    pub fn run_file(path: &Path, config: &Config) -> ChallengeResult {
+        let start = Instant::now();
+
+        let file = File::open(path)?;
+        let window = match &config.byte_range {
+            Some(window) => window.clone(),
+            None => 0..file.metadata()?.len(),
+        };
+        let len = window.end - window.start;
+        // Only this reader moves the handle's cursor; the workers' reads don't need it
+        let mut input = Window::new(&file, window.clone())?;
+        let ranges = ranges(&mut input, len, Self::CHUNK_SIZE, config)?;
+        aggregate(&mut input, ranges, Self::WORKERS, start, config, |range| {
+            Ok(PositionedReader {
+                file: &file,
+                range: window.start + range.start..window.start + range.end,
+            })
+        })
+    }
+}
+
+impl ChallengeRunner for Runner {
+    /// Any input other than a file is read into memory in one go & the workers read their ranges
+    /// from there instead; see [`Runner::run_file`].
+    fn run<R>(mut input: R, config: &Config) -> ChallengeResult
+    where
+        R: std::io::Read + std::io::Seek,
+    {
+        let start = Instant::now();
+
+        let mut data = Vec::new();
+        input.read_to_end(&mut data)?;
+        let len = data.len() as u64;
+        let mut input = io::Cursor::new(&data);
+        let ranges = ranges(&mut input, len, Self::CHUNK_SIZE, config)?;
+        aggregate(&mut input, ranges, Self::WORKERS, start, config, |range| {
+            Ok(&data[range.start as usize..range.end as usize])
+        })
+    }
+}
+
+/// Reads `range` of a file with positioned reads, which leave the file's cursor alone, so any
+/// number of them can read the same handle at once
+struct PositionedReader<'a> {
+    file: &'a File,
+
+    /// What's left to read
+    range: Range<u64>,
+}
+
+impl Read for PositionedReader<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let left = self.range.end - self.range.start;
+        let want = buf.len().min(usize::try_from(left).unwrap_or(usize::MAX));
+        if want == 0 {
+            return Ok(0);
+        }
+        let n = read_at(self.file, &mut buf[..want], self.range.start)?;
+        self.range.start += n as u64;
+        Ok(n)
+    }
+}
+
+/// Split `len` bytes of `input` into newline-aligned ranges of about `chunk_size` bytes
+fn ranges<R: Read + Seek>(
+    input: &mut R,
+    len: u64,
+    chunk_size: u64,
+    config: &Config,
+) -> Result<Vec<Range<u64>>, ChallengeError> {
+    let n = usize::try_from(len.div_ceil(chunk_size)).unwrap_or(usize::MAX);
+    chunk_boundaries(input, len, n.max(1), config.max_line_length.value)
+}
+
+/// Queue every one of the `ranges` of `input`, aggregate them on `workers` threads which each
+/// take the next range off the queue (reading it through `open(range)`) until it's empty, &
+/// merge the ranges' maps in order.
+fn aggregate<R, O, S>(
+    input: &mut R,
+    ranges: Vec<Range<u64>>,
+    workers: usize,
+    start: Instant,
+    config: &Config,
+    open: O,
+) -> ChallengeResult
+where
+    R: Read + Seek,
+    O: Fn(&Range<u64>) -> io::Result<S> + Sync,
+    S: Read,
+{
+    if let Some(recorder) = &config.chunk_manifest {
+        recorder.start();
+    }
+
+    let (queue, queued) = crossbeam_channel::unbounded();
+    for (idx, range) in ranges.iter().enumerate() {
+        queue.send((idx, range)).expect("The queue is still open");
+    }
+    // Once the queue's empty, the workers see it's closed & stop
+    drop(queue);
+
+    let mut results: Vec<(usize, Result<Partial, BlockFailure>)> = std::thread::scope(|s| {
+        let workers: Vec<_> = (0..workers.clamp(1, ranges.len().max(1)))
+            .map(|_| {
+                let (queued, open) = (queued.clone(), &open);
+                s.spawn(move || {
+                    queued
+                        .iter()
+                        .map(|(idx, range)| {
+                            let partial = open(range)
+                                .map_err(|e| BlockFailure {
+                                    block_offset: range.start,
+                                    offset: range.start,
+                                    error: e.into(),
+                                })
+                                .and_then(|reader| aggregate_range(reader, range, config));
+                            (idx, partial)
+                        })
+                        .collect::<Vec<_>>()
+                })
+            })
+            .collect();
+        workers
+            .into_iter()
+            .flat_map(|worker| worker.join().expect("Worker thread panicked"))
+            .collect()
+    });
+
+    results.sort_unstable_by_key(|&(idx, _)| idx);
+    let results = results.into_iter().map(|(_, partial)| partial).collect();
+    merge(input, results, start, config)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Source;
+    use crate::runners::tests::*;
+    use crate::Runner as Kind;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Mutex;
+    use std::time::Duration;
+    use std::{error, io::Write, thread};
+
+    fn config() -> Config {
+        Config::default().with_runner(Kind::WorkQueue, Source::Cli)
+    }
+
+    #[test]
+    fn correctness() -> Result<(), Box<dyn error::Error>> {
+        let input = io::Cursor::new(TEST_DATA.as_bytes());
+
+        let (actual, _) = Runner::run(input, &config())?;
+        assert_eq!(
+            actual, *EXPECTED_RESULT,
+            "actual != expected for work-queue runner"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn positioned_reads() -> Result<(), Box<dyn error::Error>> {
+        let mut file = tempfile::NamedTempFile::new()?;
+        file.write_all(TEST_DATA.as_bytes())?;
+
+        let mut config = config();
+        config.canonical_input.value = file.path().to_owned();
+        let (actual, _) = crate::runners::run(&config)?;
+        assert_eq!(actual, *EXPECTED_RESULT);
+
+        // Many ranges, read through the same handle at once
+        let start = Instant::now();
+        let handle = File::open(file.path())?;
+        let len = TEST_DATA.len() as u64;
+        let mut input = Window::new(&handle, 0..len)?;
+        let ranges = ranges(&mut input, len, 16, &config)?;
+        assert!(ranges.len() > 4);
+        let (actual, _) = aggregate(&mut input, ranges, 4, start, &config, |range| {
+            Ok(PositionedReader {
+                file: &handle,
+                range: range.clone(),
+            })
+        })?;
+        assert_eq!(actual, *EXPECTED_RESULT);
+
+        Ok(())
+    }
+
+    /// While one worker is stuck on a range much larger than the rest, the other takes every
+    /// other range off the queue, rather than the work being split up front
+    #[test]
+    fn uneven_ranges() -> Result<(), Box<dyn error::Error>> {
+        let data = TEST_DATA.repeat(100);
+        let len = data.len() as u64;
+        // Nine tenths of the input in the first range, & a line in each of the others
+        let mut ranges = Vec::new();
+        let mut start = 0;
+        for end in data.match_indices('\n').map(|(idx, _)| idx as u64 + 1) {
+            if end >= len * 9 / 10 {
+                ranges.push(start..end);
+                start = end;
+            }
+        }
+        let small = ranges.len() - 1;
+        assert!(small > 50);
+
+        let opened = AtomicUsize::new(0);
+        let takers = Mutex::new(Vec::new());
+        let (actual, _) = aggregate(
+            &mut io::Cursor::new(&data),
+            ranges,
+            2,
+            Instant::now(),
+            &config(),
+            |range| {
+                takers
+                    .lock()
+                    .unwrap()
+                    .push((range.start, thread::current().id()));
+                if range.start == 0 {
+                    // Only finish the big range once the other worker has taken all the rest
+                    let deadline = Instant::now() + Duration::from_secs(10);
+                    while opened.load(Ordering::SeqCst) < small {
+                        assert!(Instant::now() < deadline, "the small ranges weren't taken");
+                        thread::sleep(Duration::from_millis(1));
+                    }
+                } else {
+                    opened.fetch_add(1, Ordering::SeqCst);
+                }
+                Ok(&data.as_bytes()[range.start as usize..range.end as usize])
+            },
+        )?;
+
+        let (expected, _) = Runner::run(io::Cursor::new(&data), &config())?;
+        assert_eq!(bits(&actual), bits(&expected));
+        let takers = takers.into_inner().unwrap();
+        let big_taker = takers.iter().find(|(start, _)| *start == 0).unwrap().1;
+        assert!(takers
+            .iter()
+            .filter(|(start, _)| *start != 0)
+            .all(|(_, taker)| *taker != big_taker));
+
+        Ok(())
+    }
+}
@@ -0,0 +1,281 @@
+// 1BRC - my take on the 1 Billion Row Challenge
+// Copyright (C) 2024  Charles German <5donuts@pm.me>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Solving a random sample of the input, for `--sample-rate`.
+//!
+//! The input is split into blocks of `--buffer-size` bytes, and each block is
+//! [picked](Sample::picks) or not from a hash of its index & the seed, so the same seed always
+//! picks the same blocks.
+//! The picked blocks are aligned to whole lines like a `--byte-range`, & the runners read them
+//! through a [`Sampled`] reader as if they were the whole input, so none of their loops has to
+//! decide anything per line.
+//!
+//! The means of a sample are estimates, and its min & max are only the extremes observed in it,
+//! so sampled results are always labelled as such (see [`Format::render_with`]), with how many
+//! measurements each station's figures are from.
+//!
+//! [`Format::render_with`]: crate::output::Format::render_with
+
+use std::fmt::{self, Display};
+#[cfg(feature = "native")]
+use std::io::{self, Read, Seek, SeekFrom};
+#[cfg(feature = "native")]
+use std::ops::Range;
+
+use serde::Serialize;
+
+#[cfg(feature = "native")]
+use crate::boundaries::nearest_newline_after;
+#[cfg(feature = "native")]
+use crate::error::ChallengeError;
+
+/// Which blocks of the input to solve
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct Sample {
+    /// Roughly what fraction of the blocks are picked, in `(0, 1]`
+    pub rate: f64,
+
+    /// What the blocks are picked with
+    pub seed: u64,
+}
+
+impl Sample {
+    pub fn new(rate: f64, seed: u64) -> Self {
+        Self { rate, seed }
+    }
+
+    /// Whether the block with the given index is part of the sample.
+    ///
+    /// The index & seed are mixed with SplitMix64's finalizer, whose output is uniform enough
+    /// that a block is picked with probability `rate`, independently of its neighbours.
+    pub fn picks(&self, block: u64) -> bool {
+        let mut z = self
+            .seed
+            .wrapping_add(block.wrapping_add(1).wrapping_mul(0x9e37_79b9_7f4a_7c15));
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^= z >> 31;
+        // The top 53 bits, as a fraction in [0, 1)
+        ((z >> 11) as f64 / (1u64 << 53) as f64) < self.rate
+    }
+}
+
+impl Display for Sample {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}% of the input's blocks (seed {})",
+            self.rate * 100.0,
+            self.seed
+        )
+    }
+}
+
+/// Parse a `--sample-rate`, which must be more than 0 & at most 1
+pub fn parse_rate(s: &str) -> Result<f64, String> {
+    let rate: f64 = s
+        .parse()
+        .map_err(|_| format!("expected a fraction, not '{s}'"))?;
+    if rate > 0.0 && rate <= 1.0 {
+        Ok(rate)
+    } else {
+        Err(format!("the rate must be more than 0 & at most 1, not {s}"))
+    }
+}
+
+#[cfg(feature = "native")]
+/// A reader over the blocks of another picked by a [`Sample`], which reads & seeks as if they
+/// were all there is, one after another
+pub struct Sampled<R> {
+    inner: R,
+
+    /// The line-aligned range of each run of picked blocks, with where it starts in the sample
+    blocks: Vec<(Range<u64>, u64)>,
+
+    /// How many bytes are in the sample
+    len: u64,
+
+    /// Where the next read starts, in the sample
+    pos: u64,
+
+    /// Where the inner reader is, if that's known, to save seeking it before every read
+    inner_pos: Option<u64>,
+}
+
+#[cfg(feature = "native")]
+impl<R: Read + Seek> Sampled<R> {
+    /// Sample the blocks of `block_size` bytes in `inner`.
+    ///
+    /// Each block picked starts at the first line starting in it and ends at the end of the
+    /// line its last byte falls in, as a `--byte-range` of it would, so every line is either
+    /// wholly in the sample or not at all. Like any other runner, a line longer than
+    /// `max_line_length` is an error.
+    pub fn new(
+        mut inner: R,
+        sample: Sample,
+        block_size: usize,
+        max_line_length: usize,
+    ) -> Result<Self, ChallengeError> {
+        let len = inner.seek(SeekFrom::End(0))?;
+        let block_size = block_size.max(1) as u64;
+
+        let mut blocks: Vec<(Range<u64>, u64)> = Vec::new();
+        let mut sampled = 0;
+        for block in (0..len.div_ceil(block_size)).filter(|&block| sample.picks(block)) {
+            let mut align = |offset: u64| match offset {
+                offset if offset >= len => Ok(len),
+                offset => nearest_newline_after(&mut inner, offset, max_line_length),
+            };
+            let start = align(block * block_size)?;
+            let end = align((block + 1) * block_size)?.max(start);
+            if start == end {
+                continue;
+            }
+            // Neighbouring blocks are read as one
+            match blocks.last_mut() {
+                Some((range, _)) if range.end == start => range.end = end,
+                _ => blocks.push((start..end, sampled)),
+            }
+            sampled += end - start;
+        }
+
+        Ok(Self {
+            inner,
+            blocks,
+            len: sampled,
+            pos: 0,
+            inner_pos: None,
+        })
+    }
+}
+
+#[cfg(feature = "native")]
+impl<R: Read + Seek> Read for Sampled<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        // The last run of blocks starting at or before the position
+        let idx = self.blocks.partition_point(|(_, start)| *start <= self.pos);
+        let Some((range, start)) = idx.checked_sub(1).map(|idx| &self.blocks[idx]) else {
+            return Ok(0);
+        };
+        let offset = range.start + (self.pos - start);
+        let left = range.end.saturating_sub(offset);
+        let want = buf.len().min(usize::try_from(left).unwrap_or(usize::MAX));
+        if want == 0 {
+            return Ok(0);
+        }
+
+        if self.inner_pos != Some(offset) {
+            self.inner.seek(SeekFrom::Start(offset))?;
+        }
+        let n = self.inner.read(&mut buf[..want])?;
+        self.inner_pos = Some(offset + n as u64);
+        self.pos += n as u64;
+        Ok(n)
+    }
+}
+
+#[cfg(feature = "native")]
+impl<R> Seek for Sampled<R> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let pos = match pos {
+            SeekFrom::Start(pos) => Some(pos),
+            SeekFrom::End(delta) => self.len.checked_add_signed(delta),
+            SeekFrom::Current(delta) => self.pos.checked_add_signed(delta),
+        }
+        .ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "seek to before the start of the sample",
+            )
+        })?;
+        self.pos = pos;
+        Ok(pos)
+    }
+}
+
+#[cfg(all(test, feature = "native"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse() {
+        assert_eq!(parse_rate("0.01"), Ok(0.01));
+        assert_eq!(parse_rate("1"), Ok(1.0));
+        for bad in ["0", "-0.5", "1.5", "NaN", "ten percent"] {
+            assert!(parse_rate(bad).is_err(), "{bad}");
+        }
+    }
+
+    #[test]
+    fn picks() {
+        let sample = Sample::new(0.1, 7);
+        let picked: Vec<u64> = (0..10_000).filter(|&b| sample.picks(b)).collect();
+        // Reproducible, & about the rate asked for
+        assert_eq!(
+            picked,
+            (0..10_000).filter(|&b| sample.picks(b)).collect::<Vec<_>>()
+        );
+        assert!((900..1100).contains(&picked.len()), "{}", picked.len());
+
+        // Another seed picks other blocks
+        let other = Sample::new(0.1, 8);
+        assert_ne!(
+            picked,
+            (0..10_000).filter(|&b| other.picks(b)).collect::<Vec<_>>()
+        );
+        assert!((0..10_000).all(|b| Sample::new(1.0, 7).picks(b)));
+    }
+
+    #[test]
+    fn whole_lines() -> Result<(), Box<dyn std::error::Error>> {
+        let lines: Vec<String> = (0..2_000).map(|i| format!("Station {i};{i}.0\n")).collect();
+        let data = lines.concat();
+
+        let sample = Sample::new(0.25, 3);
+        let mut sampled = Sampled::new(io::Cursor::new(data.as_bytes()), sample, 256, 64)?;
+        let mut read = String::new();
+        sampled.read_to_string(&mut read)?;
+
+        // Every line read is whole, in order, & read once
+        let mut remaining = lines.iter();
+        for line in read.split_inclusive('\n') {
+            assert!(remaining.any(|l| l == line), "{line:?}");
+        }
+        let count = read.lines().count();
+        assert!((300..700).contains(&count), "{count}");
+
+        // Seeking within the sample is seeking within what's read
+        assert_eq!(sampled.seek(SeekFrom::End(-10))?, read.len() as u64 - 10);
+        let mut tail = String::new();
+        sampled.read_to_string(&mut tail)?;
+        assert_eq!(tail, read[read.len() - 10..]);
+        assert!(sampled
+            .seek(SeekFrom::Current(-(read.len() as i64) - 1))
+            .is_err());
+
+        // A rate of 1 is the whole input
+        let mut all = String::new();
+        Sampled::new(
+            io::Cursor::new(data.as_bytes()),
+            Sample::new(1.0, 3),
+            256,
+            64,
+        )?
+        .read_to_string(&mut all)?;
+        assert_eq!(all, data);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,190 @@
+// 1BRC - my take on the 1 Billion Row Challenge
+// Copyright (C) 2024  Charles German <5donuts@pm.me>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Temporary files for partial aggregates that don't fit in memory.
+//!
+//! When a runner's memory is [bounded](crate::config::Config::max_memory), each thread writes
+//! the stations it has room to forget to its own spill file, as `(name, StationData)` records,
+//! and the records are read back & merged with everything else at the end. The files live in
+//! [`std::env::temp_dir`] and are removed when dropped (or, if the run's interrupted first, by
+//! [`TEMP_FILES`]).
+
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::aggregate::StationData;
+use crate::temp::TEMP_FILES;
+
+/// Tells apart the spill files of a process
+static NEXT_ID: AtomicUsize = AtomicUsize::new(0);
+
+/// A temporary file of `(name, StationData)` records
+pub struct SpillFile {
+    path: PathBuf,
+    writer: BufWriter<File>,
+
+    /// How many records have been written
+    records: u64,
+}
+
+impl SpillFile {
+    /// Create a new, empty spill file in the system's temporary directory
+    pub fn new() -> io::Result<Self> {
+        let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+        let path = std::env::temp_dir().join(format!("onebrc-spill-{}-{id}", std::process::id()));
+        let file = File::options()
+            .read(true)
+            .write(true)
+            .create_new(true)
+            .open(&path)?;
+        TEMP_FILES.register(&path);
+        Ok(Self {
+            path,
+            writer: BufWriter::new(file),
+            records: 0,
+        })
+    }
+
+    /// Append a record for `name`
+    pub fn write(&mut self, name: &str, data: &StationData) -> io::Result<()> {
+        write_record(&mut self.writer, name, data)?;
+        self.records += 1;
+        Ok(())
+    }
+
+    /// How many records have been written
+    pub fn records(&self) -> u64 {
+        self.records
+    }
+
+    /// Read back every record, in the order they were written
+    pub fn read_back(&mut self) -> io::Result<SpillReader<'_>> {
+        self.writer.flush()?;
+        let file = self.writer.get_mut();
+        file.seek(SeekFrom::Start(0))?;
+        Ok(SpillReader {
+            reader: BufReader::new(file),
+            remaining: self.records,
+        })
+    }
+}
+
+impl Drop for SpillFile {
+    fn drop(&mut self) {
+        TEMP_FILES.release(&self.path);
+    }
+}
+
+/// The records of a [`SpillFile`]
+pub struct SpillReader<'a> {
+    reader: BufReader<&'a mut File>,
+    remaining: u64,
+}
+
+impl Iterator for SpillReader<'_> {
+    type Item = io::Result<(String, StationData)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.remaining == 0 {
+            return None;
+        }
+        self.remaining -= 1;
+        Some(read_record(&mut self.reader))
+    }
+}
+
+/// Write a `(name, StationData)` record: the length of the name & the name, then each field of
+/// the data, all little-endian
+pub(crate) fn write_record(w: &mut impl Write, name: &str, data: &StationData) -> io::Result<()> {
+    let len = u32::try_from(name.len())
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "station name too long"))?;
+    w.write_all(&len.to_le_bytes())?;
+    w.write_all(name.as_bytes())?;
+    w.write_all(&data.min.to_le_bytes())?;
+    w.write_all(&data.max.to_le_bytes())?;
+    w.write_all(&data.sum.to_le_bytes())?;
+    w.write_all(&data.cnt.to_le_bytes())?;
+    w.write_all(&data.skipped.to_le_bytes())?;
+    w.write_all(&data.first_row.to_le_bytes())?;
+    w.write_all(&data.last_row.to_le_bytes())?;
+    Ok(())
+}
+
+/// Read a record written by [`write_record`]
+pub(crate) fn read_record(r: &mut impl Read) -> io::Result<(String, StationData)> {
+    let len = u32::from_le_bytes(read_array(r)?) as usize;
+    let mut name = vec![0; len];
+    r.read_exact(&mut name)?;
+    let name =
+        String::from_utf8(name).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    let data = StationData {
+        min: f32::from_le_bytes(read_array(r)?),
+        max: f32::from_le_bytes(read_array(r)?),
+        sum: f32::from_le_bytes(read_array(r)?),
+        cnt: u32::from_le_bytes(read_array(r)?),
+        skipped: u32::from_le_bytes(read_array(r)?),
+        first_row: u64::from_le_bytes(read_array(r)?),
+        last_row: u64::from_le_bytes(read_array(r)?),
+    };
+    Ok((name, data))
+}
+
+pub(crate) fn read_array<const N: usize>(r: &mut impl Read) -> io::Result<[u8; N]> {
+    let mut buf = [0; N];
+    r.read_exact(&mut buf)?;
+    Ok(buf)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::error;
+
+    #[test]
+    fn round_trip() -> Result<(), Box<dyn error::Error>> {
+        let mut hamburg = StationData::new(12.0, false);
+        hamburg.push(34.2, false);
+        hamburg.record_row(3);
+        let abeche = StationData::new(-5.5, false);
+
+        let mut spill = SpillFile::new()?;
+        spill.write("Hamburg", &hamburg)?;
+        spill.write("Abéché", &abeche)?;
+        spill.write("", &StationData::empty())?;
+        assert_eq!(spill.records(), 3);
+
+        let records = spill.read_back()?.collect::<io::Result<Vec<_>>>()?;
+        let names: Vec<_> = records.iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(names, ["Hamburg", "Abéché", ""]);
+        assert_eq!(records[0].1, hamburg);
+        assert_eq!(records[1].1, abeche);
+        assert_eq!(records[2].1, StationData::empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn removed_on_drop() -> Result<(), Box<dyn error::Error>> {
+        let spill = SpillFile::new()?;
+        let path = spill.path.clone();
+        assert!(path.exists());
+        drop(spill);
+        assert!(!path.exists());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,176 @@
+// 1BRC - my take on the 1 Billion Row Challenge
+// Copyright (C) 2024  Charles German <5donuts@pm.me>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! A sidecar cache of the sorted station names in an input file.
+//!
+//! The set of stations in a given input never changes, so repeated runs against the same file
+//! can reuse it to size & prime the station map up front and skip the final sort.
+
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::fingerprint::{fnv1a, Fingerprint};
+use crate::helpers::write_atomically;
+
+/// Bumped whenever the on-disk format changes so old caches are ignored
+const VERSION: u32 = 1;
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct StationCache {
+    version: u32,
+    fingerprint: Fingerprint,
+    checksum: u64,
+    names: Vec<String>,
+}
+
+impl StationCache {
+    /// The path of the sidecar cache for the given input, e.g. `measurements.txt.stations`
+    pub fn path_for(input: &Path) -> PathBuf {
+        let mut path = input.as_os_str().to_os_string();
+        path.push(".stations");
+        PathBuf::from(path)
+    }
+
+    /// Load the cache for the given input.
+    ///
+    /// Returns `Ok(None)` if there is no cache, or an error describing why the cache can't be
+    /// used if it is stale or corrupt.
+    pub fn load(input: &Path) -> Result<Option<Vec<String>>, Box<dyn std::error::Error>> {
+        let contents = match std::fs::read(Self::path_for(input)) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+
+        let cache: Self =
+            serde_json::from_slice(&contents).map_err(|e| format!("cache is corrupt: {e}"))?;
+        if cache.version != VERSION {
+            return Err(format!("unsupported cache version {}", cache.version).into());
+        }
+        if cache.fingerprint != Fingerprint::of(input)? {
+            return Err("cache is stale; the input has changed".into());
+        }
+        if cache.checksum != checksum(&cache.names) {
+            return Err("cache is corrupt: checksum mismatch".into());
+        }
+        if !cache.names.windows(2).all(|w| w[0] < w[1]) {
+            return Err("cache is corrupt: names are not sorted".into());
+        }
+
+        Ok(Some(cache.names))
+    }
+
+    /// Save the (sorted) station names for the given input
+    pub fn save(input: &Path, names: Vec<String>) -> Result<(), Box<dyn std::error::Error>> {
+        let cache = Self {
+            version: VERSION,
+            fingerprint: Fingerprint::of(input)?,
+            checksum: checksum(&names),
+            names,
+        };
+        write_atomically(&Self::path_for(input), &serde_json::to_vec(&cache)?)?;
+        Ok(())
+    }
+}
+
+/// Checksum the station names to catch edits & bit rot in the cache
+fn checksum(names: &[String]) -> u64 {
+    fnv1a(names.join("\n").as_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{Config, Layer, Setting, Source};
+    use crate::output::Format;
+    use crate::runners::{self, tests::*};
+
+    fn fixture(dir: &Path) -> Config {
+        let input = dir.join("measurements.txt");
+        std::fs::write(&input, TEST_DATA).unwrap();
+        let mut config =
+            Config::resolve(&input, Layer::default(), Layer::default(), Layer::default());
+        config.station_cache = Setting::new(true, Source::Cli);
+        config
+    }
+
+    fn names() -> Vec<String> {
+        EXPECTED_RESULT
+            .iter()
+            .map(|s| s.name().to_owned())
+            .collect()
+    }
+
+    #[test]
+    fn creates_cache() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = tempfile::tempdir()?;
+        let config = fixture(dir.path());
+        assert!(StationCache::load(&config.canonical_input.value)?.is_none());
+
+        let (actual, _) = runners::run(&config)?;
+        assert_eq!(actual, *EXPECTED_RESULT);
+        assert_eq!(
+            StationCache::load(&config.canonical_input.value)?,
+            Some(names())
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn warm_cache() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = tempfile::tempdir()?;
+        let config = fixture(dir.path());
+
+        let (cold, _) = runners::run(&config)?;
+        let (warm, _) = runners::run(&config)?;
+        assert_eq!(Format::Text.render(&cold), Format::Text.render(&warm));
+
+        // A cache with extra stations not in the input must not leak them into the output
+        let mut extra = names();
+        extra.push(String::from("Zürich"));
+        StationCache::save(&config.canonical_input.value, extra)?;
+        let (warm, _) = runners::run(&config)?;
+        assert_eq!(Format::Text.render(&cold), Format::Text.render(&warm));
+
+        Ok(())
+    }
+
+    #[test]
+    fn rejects_corrupt_cache() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = tempfile::tempdir()?;
+        let config = fixture(dir.path());
+        let input = &config.canonical_input.value;
+        StationCache::save(input, names())?;
+
+        // Tamper with a name without updating the checksum
+        let path = StationCache::path_for(input);
+        let contents = std::fs::read_to_string(&path)?;
+        std::fs::write(&path, contents.replace("Shimanto", "Shimanta"))?;
+        assert!(StationCache::load(input).is_err());
+
+        // Truncate it
+        std::fs::write(&path, &contents[..contents.len() / 2])?;
+        assert!(StationCache::load(input).is_err());
+
+        // The run ignores the bad cache & replaces it with a good one
+        let (actual, _) = runners::run(&config)?;
+        assert_eq!(actual, *EXPECTED_RESULT);
+        assert_eq!(StationCache::load(input)?, Some(names()));
+
+        Ok(())
+    }
+}
@@ -0,0 +1,204 @@
+// 1BRC - my take on the 1 Billion Row Challenge
+// Copyright (C) 2024  Charles German <5donuts@pm.me>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Statistics over benchmark runs
+
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+/// The number of times a runner is invoked when benchmarking it
+pub const BENCH_RUNS: usize = 5;
+
+/// Summary statistics for a set of benchmark runs
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BenchStats {
+    pub mean: Duration,
+    pub std_dev: Duration,
+}
+
+impl BenchStats {
+    /// Compute the mean and standard deviation of the given runs.
+    ///
+    /// Much like the official competition, the fastest and slowest runs are discarded first
+    /// (as long as there are enough runs to do so).
+    pub fn from_runs(runs: &[Duration]) -> Self {
+        let mut durations = runs.to_vec();
+        durations.sort();
+
+        // Drop the highest & lowest runs
+        let durations = if durations.len() > 2 {
+            &durations[1..durations.len() - 1]
+        } else {
+            &durations[..]
+        };
+
+        // Compute some basic stats, to the nanosecond so runs over small inputs still register
+        let duration_nanos: Vec<_> = durations.iter().map(|d| d.as_nanos() as f64).collect();
+        let mean = duration_nanos.iter().sum::<f64>() / duration_nanos.len() as f64;
+        let variance = duration_nanos
+            .iter()
+            .map(|&n| (n - mean).powf(2.0))
+            .sum::<f64>()
+            / duration_nanos.len() as f64;
+        let std_dev = variance.sqrt();
+
+        // Convert the stats back to durations
+        Self {
+            mean: Duration::from_nanos(mean.round() as u64),
+            std_dev: Duration::from_nanos(std_dev.round() as u64),
+        }
+    }
+}
+
+/// How much of the variation in a set of samples is between groups vs within them, e.g. between
+/// benchmark sessions vs between the runs within each session.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct VarianceComponents {
+    /// The mean of every sample
+    pub mean: f64,
+
+    /// The pooled variance of the samples around their own group's mean
+    pub within: f64,
+
+    /// The variance of the group means around the overall mean, weighted by group size
+    pub between: f64,
+}
+
+impl VarianceComponents {
+    /// Split the variance of the samples in `groups` into its within- & between-group parts,
+    /// as in a one-way ANOVA.
+    ///
+    /// Empty groups are ignored. Either part is `NaN` if there aren't enough samples or groups
+    /// to estimate it.
+    pub fn of(groups: &[Vec<f64>]) -> Self {
+        let groups: Vec<&Vec<f64>> = groups.iter().filter(|g| !g.is_empty()).collect();
+        let n = groups.iter().map(|g| g.len()).sum::<usize>() as f64;
+        let k = groups.len() as f64;
+        let mean = groups.iter().flat_map(|g| g.iter()).sum::<f64>() / n;
+
+        let mut within = 0.0;
+        let mut between = 0.0;
+        for group in &groups {
+            let group_mean = group.iter().sum::<f64>() / group.len() as f64;
+            within += group.iter().map(|x| (x - group_mean).powi(2)).sum::<f64>();
+            between += group.len() as f64 * (group_mean - mean).powi(2);
+        }
+
+        Self {
+            mean,
+            within: within / (n - k),
+            between: between / (k - 1.0),
+        }
+    }
+
+    /// How many times larger the between-group variance is than the within-group variance (the
+    /// F statistic). Around 1 means the groups are indistinguishable.
+    pub fn ratio(&self) -> f64 {
+        self.between / self.within
+    }
+}
+
+/// The slope of the least-squares line through `points`, or `None` if there are fewer than two
+/// distinct `x` values.
+pub fn linear_trend(points: &[(f64, f64)]) -> Option<f64> {
+    let n = points.len() as f64;
+    let mean_x = points.iter().map(|(x, _)| x).sum::<f64>() / n;
+    let mean_y = points.iter().map(|(_, y)| y).sum::<f64>() / n;
+
+    let covariance: f64 = points
+        .iter()
+        .map(|(x, y)| (x - mean_x) * (y - mean_y))
+        .sum();
+    let variance: f64 = points.iter().map(|(x, _)| (x - mean_x).powi(2)).sum();
+    (variance > 0.0).then(|| covariance / variance)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn discards_extremes() {
+        let runs: Vec<_> = [100, 9000, 10, 120, 110]
+            .into_iter()
+            .map(Duration::from_millis)
+            .collect();
+        let stats = BenchStats::from_runs(&runs);
+
+        assert_eq!(stats.mean, Duration::from_millis(110));
+        assert_eq!(stats.std_dev, Duration::from_nanos(8_164_966));
+    }
+
+    #[test]
+    fn sub_millisecond_runs() {
+        let runs: Vec<_> = [400, 9000, 1, 420, 410]
+            .into_iter()
+            .map(Duration::from_micros)
+            .collect();
+        let stats = BenchStats::from_runs(&runs);
+
+        assert_eq!(stats.mean, Duration::from_micros(410));
+        assert_eq!(stats.std_dev, Duration::from_nanos(8_165));
+    }
+
+    #[test]
+    fn bench_tiny_input() -> Result<(), Box<dyn std::error::Error>> {
+        use crate::config::Config;
+        use crate::helpers::ChallengeRunner;
+        use crate::runners::tests::TEST_DATA;
+        use crate::runners::Baseline;
+
+        let runs = (0..BENCH_RUNS)
+            .map(|_| {
+                let input = std::io::Cursor::new(TEST_DATA.as_bytes());
+                Baseline::run(input, &Config::default()).map(|(_, stats)| stats.timings.total)
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        let stats = BenchStats::from_runs(&runs);
+
+        // Only that even runs this short register: how long they take is up to the machine
+        assert!(stats.mean > Duration::ZERO, "{stats:?}");
+
+        Ok(())
+    }
+
+    #[test]
+    fn variance_components() {
+        // Group means of 2, 5, and 8 around an overall mean of 5
+        let groups = vec![
+            vec![1.0, 2.0, 3.0],
+            vec![4.0, 5.0, 6.0],
+            vec![7.0, 8.0, 9.0],
+            vec![],
+        ];
+        let components = VarianceComponents::of(&groups);
+        assert_eq!(components.mean, 5.0);
+        assert_eq!(components.within, 1.0);
+        assert_eq!(components.between, 27.0);
+        assert_eq!(components.ratio(), 27.0);
+
+        // A single group has no between-group variance to speak of
+        assert!(VarianceComponents::of(&groups[..1]).between.is_nan());
+    }
+
+    #[test]
+    fn trend() {
+        let points = [(0.0, 1.0), (1.0, 3.0), (2.0, 5.0), (3.0, 7.0)];
+        assert_eq!(linear_trend(&points), Some(2.0));
+        assert_eq!(linear_trend(&[(1.0, 1.0), (1.0, 2.0)]), None);
+        assert_eq!(linear_trend(&[]), None);
+    }
+}
@@ -0,0 +1,310 @@
+// 1BRC - my take on the 1 Billion Row Challenge
+// Copyright (C) 2024  Charles German <5donuts@pm.me>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Colour & alignment for what's printed to a terminal, like the `--compare` table.
+//!
+//! Whether anything is coloured is decided once per stream by a [`Styler`], from `--color` &
+//! the [`NO_COLOR`](https://no-color.org) convention; with colour off, [`Styler::paint`] leaves
+//! the text alone, so the same rendering code gives plain text for pipes & files. Only text
+//! meant for a terminal is ever painted: results, reports & `--output` files never are.
+//!
+//! Columns are aligned by how wide their text is on screen rather than how many bytes or `char`s
+//! it has (see [`width`]), so a name like `Aïn el Mediour` lines up with the rest, & padding is
+//! worked out from the plain text before it's painted.
+
+use std::borrow::Cow;
+use std::io::IsTerminal;
+
+use unicode_width::UnicodeWidthStr;
+
+/// When to colour output, for `--color`
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum ColorChoice {
+    /// Only when writing to a terminal, & `NO_COLOR` isn't set
+    #[default]
+    Auto,
+
+    /// Always, even into a pipe, & whatever `NO_COLOR` says
+    Always,
+
+    /// Never
+    Never,
+}
+
+/// What some text is painted as
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Style {
+    /// The best of something, or a check which passed: green
+    Good,
+
+    /// A regression or a check which failed: red
+    Bad,
+
+    /// Something worth a second look: yellow
+    Warning,
+}
+
+impl Style {
+    /// The SGR parameter for the style
+    fn code(self) -> &'static str {
+        match self {
+            Style::Good => "32",
+            Style::Bad => "31",
+            Style::Warning => "33",
+        }
+    }
+}
+
+/// Paints text for one output stream, or leaves it alone if that stream shouldn't be coloured
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Styler {
+    color: bool,
+}
+
+impl Styler {
+    /// Never colours anything
+    pub const PLAIN: Styler = Styler { color: false };
+
+    /// Always colours, e.g. for `--color always`
+    pub const COLORED: Styler = Styler { color: true };
+
+    /// Decide whether to colour a stream which is (or isn't) a `terminal`, with `NO_COLOR` set
+    /// to `no_color` (if at all). Following the convention, an empty `NO_COLOR` is as good as
+    /// unset, & it doesn't override asking for colour explicitly.
+    pub fn new(choice: ColorChoice, terminal: bool, no_color: Option<&str>) -> Self {
+        let color = match choice {
+            ColorChoice::Always => true,
+            ColorChoice::Never => false,
+            ColorChoice::Auto => terminal && no_color.is_none_or(str::is_empty),
+        };
+        Self { color }
+    }
+
+    /// The styler for stdout
+    pub fn stdout(choice: ColorChoice) -> Self {
+        Self::for_stream(choice, std::io::stdout().is_terminal())
+    }
+
+    /// The styler for stderr
+    pub fn stderr(choice: ColorChoice) -> Self {
+        Self::for_stream(choice, std::io::stderr().is_terminal())
+    }
+
+    fn for_stream(choice: ColorChoice, terminal: bool) -> Self {
+        let no_color = std::env::var("NO_COLOR").ok();
+        Self::new(choice, terminal, no_color.as_deref())
+    }
+
+    pub fn is_colored(&self) -> bool {
+        self.color
+    }
+
+    /// The text in the given style, if colouring
+    pub fn paint<'a>(&self, text: &'a str, style: Style) -> Cow<'a, str> {
+        if !self.color || text.is_empty() {
+            return Cow::Borrowed(text);
+        }
+        Cow::Owned(format!("\x1b[{}m{text}\x1b[0m", style.code()))
+    }
+}
+
+/// How many columns `text` takes up in a terminal: wide characters (e.g. CJK) take two, and
+/// combining marks none
+pub fn width(text: &str) -> usize {
+    text.width()
+}
+
+/// `text` followed by enough spaces to take up `columns` columns, or just `text` if it's
+/// already that wide
+pub fn pad(text: &str, columns: usize) -> String {
+    let mut padded = String::with_capacity(text.len() + columns);
+    padded.push_str(text);
+    padded.extend(std::iter::repeat_n(
+        ' ',
+        columns.saturating_sub(width(text)),
+    ));
+    padded
+}
+
+/// A cell of a [`Table`], painted in a style or not
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Cell {
+    text: String,
+    style: Option<Style>,
+}
+
+impl Cell {
+    pub fn styled(text: impl Into<String>, style: Style) -> Self {
+        Self {
+            text: text.into(),
+            style: Some(style),
+        }
+    }
+
+    /// The cell in `style` if `cond` holds, or plain otherwise
+    pub fn styled_if(text: impl Into<String>, cond: bool, style: Style) -> Self {
+        Self {
+            text: text.into(),
+            style: cond.then_some(style),
+        }
+    }
+}
+
+impl From<String> for Cell {
+    fn from(text: String) -> Self {
+        Self { text, style: None }
+    }
+}
+
+impl From<&str> for Cell {
+    fn from(text: &str) -> Self {
+        Self::from(text.to_owned())
+    }
+}
+
+/// A markdown table with each column padded to the width of its widest cell, so it's as easy to
+/// read in a terminal as rendered
+#[derive(Debug, Clone, Default)]
+pub struct Table {
+    header: Vec<String>,
+    rows: Vec<Vec<Cell>>,
+}
+
+impl Table {
+    pub fn new(header: &[&str]) -> Self {
+        Self {
+            header: header.iter().map(|&h| h.to_owned()).collect(),
+            rows: Vec::new(),
+        }
+    }
+
+    /// Add a row, with a cell for each column of the header
+    pub fn row(&mut self, cells: Vec<Cell>) {
+        debug_assert_eq!(cells.len(), self.header.len());
+        self.rows.push(cells);
+    }
+
+    /// Render the table, painting each styled cell with `styler`
+    pub fn render(&self, styler: &Styler) -> String {
+        // Markdown needs at least three dashes under each header
+        let widths: Vec<usize> = (0..self.header.len())
+            .map(|col| {
+                self.rows
+                    .iter()
+                    .map(|row| width(&row[col].text))
+                    .chain([width(&self.header[col]), 3])
+                    .max()
+                    .unwrap_or_default()
+            })
+            .collect();
+
+        let mut table = String::new();
+        let mut line = |cells: &mut dyn Iterator<Item = String>| {
+            table.push('|');
+            for cell in cells {
+                table.push(' ');
+                table.push_str(&cell);
+                table.push_str(" |");
+            }
+            table.push('\n');
+        };
+        line(&mut self.header.iter().zip(&widths).map(|(h, &w)| pad(h, w)));
+        line(&mut widths.iter().map(|&w| "-".repeat(w)));
+        for row in &self.rows {
+            line(&mut row.iter().zip(&widths).map(|(cell, &w)| {
+                // Padded outside the colour, which takes up no room of its own
+                let padding = " ".repeat(w.saturating_sub(width(&cell.text)));
+                match cell.style {
+                    Some(style) => format!("{}{padding}", styler.paint(&cell.text, style)),
+                    None => format!("{}{padding}", cell.text),
+                }
+            }));
+        }
+        table
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn widths() {
+        // Two bytes for the ï, but one column
+        assert_eq!("Aïn el Mediour".len(), 15);
+        assert_eq!(width("Aïn el Mediour"), 14);
+        // A combining diaeresis takes up no room of its own
+        assert_eq!(width("Ai\u{308}n el Mediour"), 14);
+        // Each of these takes up two
+        assert_eq!(width("東京"), 4);
+        assert_eq!(width(""), 0);
+    }
+
+    #[test]
+    fn padding() {
+        assert_eq!(pad("Aïn el Mediour", 16), "Aïn el Mediour  ");
+        assert_eq!(pad("Hamburg", 16), "Hamburg         ");
+        assert_eq!(pad("東京", 6), "東京  ");
+        // Never truncated
+        assert_eq!(pad("Palembang", 4), "Palembang");
+    }
+
+    #[test]
+    fn choice() {
+        use ColorChoice::*;
+        assert!(Styler::new(Auto, true, None).is_colored());
+        assert!(Styler::new(Auto, true, Some("")).is_colored());
+        assert!(!Styler::new(Auto, true, Some("1")).is_colored());
+        assert!(!Styler::new(Auto, false, None).is_colored());
+        assert!(Styler::new(Always, false, Some("1")).is_colored());
+        assert!(!Styler::new(Never, true, None).is_colored());
+    }
+
+    #[test]
+    fn paint() {
+        assert_eq!(Styler::PLAIN.paint("ok", Style::Good), "ok");
+        assert_eq!(
+            Styler::COLORED.paint("ok", Style::Good),
+            "\x1b[32mok\x1b[0m"
+        );
+        assert_eq!(Styler::COLORED.paint("", Style::Bad), "");
+    }
+
+    #[test]
+    fn aligned_table() {
+        let mut table = Table::new(&["Station", "Mean"]);
+        table.row(vec!["Aïn el Mediour".into(), "26.6".into()]);
+        table.row(vec!["東京".into(), Cell::styled("15.4", Style::Bad)]);
+        table.row(vec!["Ai\u{308}n".into(), "1.0".into()]);
+
+        assert_eq!(
+            table.render(&Styler::PLAIN),
+            "| Station        | Mean |\n\
+             | -------------- | ---- |\n\
+             | Aïn el Mediour | 26.6 |\n\
+             | 東京           | 15.4 |\n\
+             | Ai\u{308}n            | 1.0  |\n"
+        );
+        // The colour doesn't change where the columns are
+        assert_eq!(
+            table.render(&Styler::COLORED),
+            "| Station        | Mean |\n\
+             | -------------- | ---- |\n\
+             | Aïn el Mediour | 26.6 |\n\
+             | 東京           | \x1b[31m15.4\x1b[0m |\n\
+             | Ai\u{308}n            | 1.0  |\n"
+        );
+    }
+}
@@ -0,0 +1,517 @@
+// 1BRC - my take on the 1 Billion Row Challenge
+// Copyright (C) 2024  Charles German <5donuts@pm.me>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! A purpose-built open-addressing table of station data.
+//!
+//! Unlike a [`HashMap`](std::collections::HashMap), the hash & slot index of a station can be
+//! computed separately from the lookup itself, so a runner can get the slot into cache ahead of
+//! time (see [`StationTable::prefetch`]).
+//!
+//! Stations are keyed by the raw bytes of their names, and each name is only checked to be valid
+//! UTF-8 once, when the results are built.
+
+use std::string::FromUtf8Error;
+use std::sync::Arc;
+
+use crate::aggregate::{merge_case_variants, Encoding, StationData};
+use crate::error::{ChallengeError, SkippedLines};
+use crate::fingerprint::fnv1a;
+use crate::helpers::StationInfo;
+
+/// The fewest slots a table will have; enough for every station in the official input
+const MIN_SLOTS: usize = 1 << 14;
+
+/// The number of entries in a [`CachedTable`]'s cache; a power of two
+const CACHE_ENTRIES: usize = 1024;
+
+struct Slot {
+    hash: u64,
+    name: Box<[u8]>,
+    data: StationData,
+
+    /// Byte offset of the station's first measurement in the input, for locating a name which
+    /// isn't valid UTF-8
+    first_offset: u64,
+}
+
+/// A linear-probing hash table mapping station names to their [`StationData`].
+///
+/// The number of slots is always a power of two, and is doubled whenever the table becomes half
+/// full so probe sequences stay short.
+pub struct StationTable {
+    slots: Vec<Option<Slot>>,
+    len: usize,
+
+    /// The slot of the station the last measurement was for, until the table grows
+    last: Option<usize>,
+    strict: bool,
+    track_extents: bool,
+    case_insensitive: bool,
+    encoding: Encoding,
+    max_skipped: u64,
+    skipped: SkippedLines,
+}
+
+impl StationTable {
+    /// Create a table with room for at least `stations` stations before growing
+    pub fn with_capacity(stations: usize) -> Self {
+        let slots = (stations * 2).next_power_of_two().max(MIN_SLOTS);
+        Self {
+            slots: (0..slots).map(|_| None).collect(),
+            len: 0,
+            last: None,
+            strict: false,
+            track_extents: false,
+            case_insensitive: false,
+            encoding: Encoding::Utf8,
+            max_skipped: 0,
+            skipped: SkippedLines::default(),
+        }
+    }
+
+    /// How many bytes the slots of a table holding `stations` stations take up.
+    ///
+    /// This doesn't include the station names themselves.
+    pub fn estimated_size(stations: usize) -> usize {
+        let slots = (stations * 2).next_power_of_two().max(MIN_SLOTS);
+        slots * std::mem::size_of::<Option<Slot>>()
+    }
+
+    /// Create a table primed with the stations known to be in the input ahead of time
+    pub fn with_known_stations(known_stations: Option<Arc<[String]>>) -> Self {
+        let Some(names) = known_stations else {
+            return Self::with_capacity(0);
+        };
+
+        let mut table = Self::with_capacity(names.len());
+        for name in names.iter() {
+            let hash = Self::hash(name.as_bytes());
+            let idx = table.probe(name.as_bytes(), hash);
+            table.insert(idx, name.as_bytes(), hash, StationData::empty(), 0);
+        }
+        table
+    }
+
+    /// Treat non-finite measurements as a bug rather than skipping them (see [`StationData`])
+    pub fn strict(mut self, strict: bool) -> Self {
+        self.strict = strict;
+        self
+    }
+
+    /// Record the first & last row each station is on (see [`StationData::extents`])
+    pub fn track_extents(mut self, track_extents: bool) -> Self {
+        self.track_extents = track_extents;
+        self
+    }
+
+    /// Merge stations whose names only differ in case when building the
+    /// [sorted list](StationTable::into_sorted), shown with the spelling whose first measurement
+    /// came first in the input.
+    ///
+    /// Each spelling has a slot of its own until then, so neither the table nor a cache in front
+    /// of it does any extra work per line.
+    pub fn case_insensitive(mut self, case_insensitive: bool) -> Self {
+        self.case_insensitive = case_insensitive;
+        self
+    }
+
+    /// Transcode station names from `encoding` to UTF-8 when building the
+    /// [sorted list](StationTable::into_sorted), so only once per station
+    pub fn encoding(mut self, encoding: Encoding) -> Self {
+        self.encoding = encoding;
+        self
+    }
+
+    /// Skip up to `max_skipped` malformed lines rather than failing on the first one (see
+    /// [`SkippedLines::skip`])
+    pub fn max_skipped(mut self, max_skipped: u64) -> Self {
+        self.max_skipped = max_skipped;
+        self
+    }
+
+    /// Hash a station name
+    #[inline]
+    pub fn hash(name: &[u8]) -> u64 {
+        fnv1a(name)
+    }
+
+    /// The slot where the lookup of the station with the given hash starts
+    #[inline]
+    pub fn slot_index(&self, hash: u64) -> usize {
+        hash as usize & (self.slots.len() - 1)
+    }
+
+    /// Hint to the CPU that the given slot is about to be used.
+    ///
+    /// This is only a hint; it has no effect on the table's contents and does nothing on
+    /// architectures without a stable prefetch intrinsic.
+    #[inline]
+    pub fn prefetch(&self, idx: usize) {
+        #[cfg(target_arch = "x86_64")]
+        if let Some(slot) = self.slots.get(idx) {
+            use std::arch::x86_64::{_mm_prefetch, _MM_HINT_T0};
+
+            // SAFETY: SSE is part of the x86_64 baseline, and prefetching never faults
+            unsafe { _mm_prefetch::<_MM_HINT_T0>((slot as *const Option<Slot>).cast()) };
+        }
+
+        #[cfg(not(target_arch = "x86_64"))]
+        let _ = idx;
+    }
+
+    /// Record a measurement for a station, from the line at byte `offset` & (1-based) row `row`
+    /// of the input
+    #[inline]
+    pub fn push(&mut self, name: &[u8], measurement: f32, offset: u64, row: u64) {
+        // Lines often come in runs for the same station, which needn't be hashed at all
+        match self.last_slot(name) {
+            Some(idx) => self.update(idx, measurement, offset, row),
+            None => self.push_hashed(name, Self::hash(name), measurement, offset, row),
+        }
+    }
+
+    /// Record a measurement for a station whose [hash](StationTable::hash) is already known
+    pub fn push_hashed(&mut self, name: &[u8], hash: u64, measurement: f32, offset: u64, row: u64) {
+        let mut idx = match self.last_slot(name) {
+            Some(idx) => idx,
+            None => self.probe(name, hash),
+        };
+        if self.slots[idx].is_some() {
+            self.update(idx, measurement, offset, row);
+            self.last = Some(idx);
+            return;
+        }
+
+        if (self.len + 1) * 2 > self.slots.len() {
+            self.grow();
+            idx = self.probe(name, hash);
+        }
+        let mut data = StationData::new(measurement, self.strict);
+        if self.track_extents {
+            data.record_row(row);
+        }
+        self.insert(idx, name, hash, data, offset);
+        self.last = Some(idx);
+    }
+
+    /// The slot of the last station a measurement was for, if it's `name`
+    #[inline]
+    fn last_slot(&self, name: &[u8]) -> Option<usize> {
+        let idx = self.last?;
+        matches!(&self.slots[idx], Some(slot) if &*slot.name == name).then_some(idx)
+    }
+
+    /// Record a measurement for the station in the (occupied) slot `idx`
+    #[inline]
+    fn update(&mut self, idx: usize, measurement: f32, offset: u64, row: u64) {
+        let slot = self.slots[idx]
+            .as_mut()
+            .expect("Only occupied slots are updated");
+        if slot.data.cnt == 0 {
+            slot.first_offset = offset;
+        }
+        slot.data.push(measurement, self.strict);
+        if self.track_extents {
+            slot.data.record_row(row);
+        }
+    }
+
+    /// How many non-finite measurements have been left out so far
+    pub fn ignored_non_finite(&self) -> u64 {
+        self.slots
+            .iter()
+            .flatten()
+            .map(|slot| slot.data.skipped as u64)
+            .sum()
+    }
+
+    /// Skip a malformed line, if allowed to
+    pub fn skip(&mut self, error: ChallengeError) -> Result<(), ChallengeError> {
+        self.skipped.skip(error, 1, self.max_skipped)
+    }
+
+    /// Skip every line of the stations whose names aren't valid UTF-8, if allowed to, so they're
+    /// left out of the [sorted list](StationTable::into_sorted) rather than failing it
+    pub fn skip_invalid_names(&mut self) -> Result<(), ChallengeError> {
+        if self.max_skipped == 0 {
+            return Ok(());
+        }
+
+        let mut decoded = String::new();
+        for slot in self.slots.iter_mut().flatten() {
+            if slot.data.cnt == 0 && slot.data.skipped == 0 {
+                continue;
+            }
+            if let Err(e) = self.encoding.decode(&slot.name, &mut decoded) {
+                let lines = slot.data.cnt as u64 + slot.data.skipped as u64;
+                let error = ChallengeError::invalid_utf8(slot.first_offset, &slot.name, e);
+                self.skipped.skip(error, lines, self.max_skipped)?;
+                slot.data = StationData::empty();
+            }
+        }
+        Ok(())
+    }
+
+    /// The malformed lines skipped so far
+    pub fn skipped(&self) -> SkippedLines {
+        self.skipped
+    }
+
+    /// Build the alphabetically-sorted list of stations.
+    ///
+    /// Fails if any station name isn't valid UTF-8, pointing at the first occurrence of the
+    /// earliest such station in the input.
+    pub fn into_sorted(self) -> Result<Vec<StationInfo>, ChallengeError> {
+        let mut named = Vec::with_capacity(self.len);
+        let mut invalid: Option<(u64, FromUtf8Error)> = None;
+        for slot in self.slots.into_iter().flatten() {
+            // Known stations which never showed up don't belong in the output
+            if slot.data.cnt == 0 {
+                continue;
+            }
+
+            match self.encoding.decode_owned(slot.name.into_vec()) {
+                Ok(name) => named.push((name, slot.data, slot.first_offset)),
+                Err(e) => {
+                    if invalid
+                        .as_ref()
+                        .is_none_or(|(first, _)| slot.first_offset < *first)
+                    {
+                        invalid = Some((slot.first_offset, e));
+                    }
+                }
+            }
+        }
+
+        if let Some((offset, e)) = invalid {
+            return Err(ChallengeError::invalid_utf8(
+                offset,
+                e.as_bytes(),
+                e.utf8_error(),
+            ));
+        }
+
+        let named = if self.case_insensitive {
+            merge_case_variants(named)
+        } else {
+            named
+                .into_iter()
+                .map(|(name, data, _)| (name, data))
+                .collect()
+        };
+        let mut stations: Vec<StationInfo> = named
+            .into_iter()
+            .map(|(name, data)| {
+                StationInfo::new(name, data.min, data.max, data.avg(), data.cnt)
+                    .with_extents(data.extents())
+            })
+            .collect();
+        stations.sort_unstable();
+        Ok(stations)
+    }
+
+    /// Find the slot holding the given station, or the empty slot where it belongs
+    #[inline]
+    fn probe(&self, name: &[u8], hash: u64) -> usize {
+        let mask = self.slots.len() - 1;
+        let mut idx = hash as usize & mask;
+        loop {
+            match &self.slots[idx] {
+                Some(slot) if slot.hash != hash || &*slot.name != name => idx = (idx + 1) & mask,
+                _ => return idx,
+            }
+        }
+    }
+
+    fn insert(&mut self, idx: usize, name: &[u8], hash: u64, data: StationData, offset: u64) {
+        self.slots[idx] = Some(Slot {
+            hash,
+            name: name.into(),
+            data,
+            first_offset: offset,
+        });
+        self.len += 1;
+    }
+
+    /// Double the number of slots & re-insert every station
+    fn grow(&mut self) {
+        // Every station is about to move
+        self.last = None;
+        let slots = (0..self.slots.len() * 2).map(|_| None).collect();
+        let old = std::mem::replace(&mut self.slots, slots);
+        let mask = self.slots.len() - 1;
+        for slot in old.into_iter().flatten() {
+            let mut idx = slot.hash as usize & mask;
+            while self.slots[idx].is_some() {
+                idx = (idx + 1) & mask;
+            }
+            self.slots[idx] = Some(slot);
+        }
+    }
+}
+
+/// A [`StationTable`] with a small direct-mapped cache in front of it.
+///
+/// Each cache entry holds the hash & slot of the last station whose hash's low bits picked that
+/// entry, so a station seen recently is found in one step rather than by probing the table. On a
+/// miss, including when two stations share an entry, the lookup falls back to the table & the
+/// station takes the entry over. Entries aren't cleared when the table grows: the name in the
+/// slot an entry points at is checked on every hit, so a stale entry is just another miss.
+pub struct CachedTable {
+    table: StationTable,
+    cache: Box<[CacheEntry]>,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct CacheEntry {
+    hash: u64,
+
+    /// `usize::MAX` until the entry is first used
+    slot: usize,
+}
+
+impl CachedTable {
+    pub fn new(table: StationTable) -> Self {
+        let empty = CacheEntry {
+            hash: 0,
+            slot: usize::MAX,
+        };
+        Self {
+            table,
+            cache: vec![empty; CACHE_ENTRIES].into_boxed_slice(),
+        }
+    }
+
+    /// Record a measurement for a station, like [`StationTable::push`]
+    #[inline]
+    pub fn push(&mut self, name: &[u8], measurement: f32, offset: u64, row: u64) {
+        if let Some(idx) = self.table.last_slot(name) {
+            self.table.update(idx, measurement, offset, row);
+            return;
+        }
+
+        let hash = StationTable::hash(name);
+        let entry = &mut self.cache[hash as usize & (CACHE_ENTRIES - 1)];
+        if entry.hash == hash {
+            if let Some(Some(slot)) = self.table.slots.get(entry.slot) {
+                if &*slot.name == name {
+                    self.table.update(entry.slot, measurement, offset, row);
+                    self.table.last = Some(entry.slot);
+                    return;
+                }
+            }
+        }
+
+        self.table.push_hashed(name, hash, measurement, offset, row);
+        *entry = CacheEntry {
+            hash,
+            slot: self
+                .table
+                .last
+                .expect("Pushing a measurement sets the last slot"),
+        };
+    }
+
+    /// The table, for skipping lines
+    pub fn table_mut(&mut self) -> &mut StationTable {
+        &mut self.table
+    }
+
+    /// The table, once every measurement has been pushed
+    pub fn into_inner(self) -> StationTable {
+        self.table
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The stats of every station, as sorted by the table
+    fn results(table: StationTable) -> Vec<(String, f32, f32, f32, u32)> {
+        table
+            .into_sorted()
+            .unwrap()
+            .iter()
+            .map(|s| (s.name().to_owned(), s.min(), s.max(), s.avg(), s.count()))
+            .collect()
+    }
+
+    #[test]
+    fn cache_aliasing() {
+        // Two stations whose hashes pick the same cache entry
+        let names: Vec<String> = (0..).map(|i| format!("Station {i}")).take(5000).collect();
+        let entry =
+            |name: &String| StationTable::hash(name.as_bytes()) as usize & (CACHE_ENTRIES - 1);
+        let (a, b) = names
+            .iter()
+            .enumerate()
+            .find_map(|(i, a)| Some((a, names[..i].iter().find(|b| entry(b) == entry(a))?)))
+            .unwrap();
+
+        // Alternating, so every lookup evicts the other station from the cache
+        let mut cached = CachedTable::new(StationTable::with_capacity(0));
+        let mut plain = StationTable::with_capacity(0);
+        for i in 0..1000 {
+            let (name, measurement) = if i % 2 == 0 {
+                (a, i as f32)
+            } else {
+                (b, -(i as f32))
+            };
+            cached.push(name.as_bytes(), measurement, 0, 1);
+            plain.push(name.as_bytes(), measurement, 0, 1);
+        }
+        let cached = results(cached.into_inner());
+        assert_eq!(cached, results(plain));
+        assert_eq!(cached.len(), 2);
+        assert!(cached.iter().all(|s| s.4 == 500));
+    }
+
+    #[test]
+    fn cache_survives_growth() {
+        // Hot stations cached before the table grows (& every slot moves) & looked up after
+        let mut cached = CachedTable::new(StationTable::with_capacity(0));
+        let mut plain = StationTable::with_capacity(0);
+        for i in 0..MIN_SLOTS * 2 {
+            for name in [
+                String::from("Hamburg"),
+                format!("Station {i}"),
+                String::from("Bulawayo"),
+            ] {
+                cached.push(name.as_bytes(), i as f32, 0, 1);
+                plain.push(name.as_bytes(), i as f32, 0, 1);
+            }
+        }
+        assert_eq!(results(cached.into_inner()), results(plain));
+    }
+
+    #[test]
+    fn grows_past_initial_capacity() {
+        let mut table = StationTable::with_capacity(0);
+        let stations = MIN_SLOTS * 2;
+        for i in 0..stations {
+            let name = format!("Station {i}");
+            table.push(name.as_bytes(), i as f32, 0, 1);
+            table.push(name.as_bytes(), -(i as f32), 0, 1);
+        }
+        assert!(table.slots.len() >= stations * 2);
+
+        let sorted = table.into_sorted().unwrap();
+        assert_eq!(sorted.len(), stations);
+        assert!(sorted.windows(2).all(|w| w[0] < w[1]));
+        let s = sorted.iter().find(|s| s.name() == "Station 7").unwrap();
+        assert_eq!((s.min(), s.max(), s.avg()), (-7.0, 7.0, 0.0));
+    }
+}
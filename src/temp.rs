@@ -0,0 +1,169 @@
+// 1BRC - my take on the 1 Billion Row Challenge
+// Copyright (C) 2024  Charles German <5donuts@pm.me>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Keeping track of the temporary files a run creates, so none are left behind.
+//!
+//! Every temporary file (spill files, & the files results are written to before they're renamed
+//! into place) is [registered](TempRegistry::register) with [`TEMP_FILES`] when it's created,
+//! and [released](TempRegistry::release) when whatever created it is done with it. Anything
+//! still registered when the process exits, fails, or is interrupted is removed by
+//! [`TempRegistry::cleanup`], since an interrupted run never gets as far as dropping the files'
+//! owners. With `--keep-temp` nothing is removed, & the files kept are listed instead.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Mutex, MutexGuard};
+
+/// The temporary files of this process
+pub static TEMP_FILES: TempRegistry = TempRegistry::new();
+
+/// The temporary files created during a run which haven't been removed yet
+#[derive(Debug, Default)]
+pub struct TempRegistry {
+    paths: Mutex<Vec<PathBuf>>,
+
+    /// Whether to keep every file rather than removing it, for `--keep-temp`
+    keep: AtomicBool,
+}
+
+impl TempRegistry {
+    pub const fn new() -> Self {
+        Self {
+            paths: Mutex::new(Vec::new()),
+            keep: AtomicBool::new(false),
+        }
+    }
+
+    /// Keep every file registered, rather than removing them once they're released
+    pub fn keep(&self, keep: bool) {
+        self.keep.store(keep, Ordering::Relaxed);
+    }
+
+    pub fn is_keeping(&self) -> bool {
+        self.keep.load(Ordering::Relaxed)
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Vec<PathBuf>> {
+        // A panic while holding the lock can't leave the list half-updated
+        self.paths.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Track the temporary file at `path` until it's released (or forgotten)
+    pub fn register(&self, path: &Path) {
+        self.lock().push(path.to_path_buf());
+    }
+
+    /// Remove the file at `path`, which its owner is done with, unless every file is being kept
+    pub fn release(&self, path: &Path) {
+        if self.is_keeping() {
+            return;
+        }
+        self.lock().retain(|registered| registered != path);
+        // Nothing to be done about it if the file's already gone
+        let _ = fs::remove_file(path);
+    }
+
+    /// Stop tracking the file at `path` without removing it, e.g. once it's been renamed into
+    /// place as the finished output
+    pub fn forget(&self, path: &Path) {
+        self.lock().retain(|registered| registered != path);
+    }
+
+    /// Remove every file still registered, returning those kept instead if every file is being
+    /// kept
+    pub fn cleanup(&self) -> Vec<PathBuf> {
+        let paths = std::mem::take(&mut *self.lock());
+        if self.is_keeping() {
+            return paths;
+        }
+        for path in paths {
+            let _ = fs::remove_file(path);
+        }
+        Vec::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io;
+
+    /// Create a temporary file in `dir`, then fail before getting round to releasing it, like a
+    /// run which hits an error (or is interrupted) part of the way through
+    fn fail_after_creating(registry: &TempRegistry, dir: &Path) -> io::Result<PathBuf> {
+        let path = dir.join("onebrc-spill-test");
+        fs::write(&path, "partial")?;
+        registry.register(&path);
+        Err(io::Error::other("something went wrong"))?;
+        registry.release(&path);
+        Ok(path)
+    }
+
+    #[test]
+    fn cleanup_after_error() -> io::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let registry = TempRegistry::new();
+        assert!(fail_after_creating(&registry, dir.path()).is_err());
+        assert_eq!(fs::read_dir(dir.path())?.count(), 1);
+
+        assert!(registry.cleanup().is_empty());
+        assert_eq!(fs::read_dir(dir.path())?.count(), 0);
+
+        Ok(())
+    }
+
+    #[test]
+    fn keep_temp() -> io::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let registry = TempRegistry::new();
+        registry.keep(true);
+        assert!(fail_after_creating(&registry, dir.path()).is_err());
+
+        // Released files are kept too
+        let released = dir.path().join("released");
+        fs::write(&released, "")?;
+        registry.register(&released);
+        registry.release(&released);
+
+        let kept = registry.cleanup();
+        assert_eq!(kept, [dir.path().join("onebrc-spill-test"), released]);
+        assert!(kept.iter().all(|path| path.exists()));
+
+        Ok(())
+    }
+
+    #[test]
+    fn release_and_forget() -> io::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let registry = TempRegistry::new();
+        let (released, forgotten) = (dir.path().join("released"), dir.path().join("output"));
+        for path in [&released, &forgotten] {
+            fs::write(path, "")?;
+            registry.register(path);
+        }
+
+        registry.release(&released);
+        assert!(!released.exists());
+        registry.forget(&forgotten);
+        assert!(registry.cleanup().is_empty());
+        assert!(
+            forgotten.exists(),
+            "A forgotten file isn't temporary any more"
+        );
+
+        Ok(())
+    }
+}
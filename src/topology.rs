@@ -0,0 +1,742 @@
+// 1BRC - my take on the 1 Billion Row Challenge
+// Copyright (C) 2024  Charles German <5donuts@pm.me>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Detect how much parallelism the machine can make use of, & what the input is stored on.
+//!
+//! This is a heuristic: more threads than cores just adds contention, and a single spinning
+//! disk can't feed more than a couple of readers before seeking dominates. The input's
+//! [`Filesystem`] is only recorded in reports, since results on tmpfs, a local disk & NFS aren't
+//! comparable.
+
+use std::fmt::Display;
+use std::path::Path;
+
+use serde::Serialize;
+
+/// The most threads worth reading from a single rotational disk
+pub const ROTATIONAL_MAX_THREADS: usize = 2;
+
+/// The kind of storage the input lives on
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Storage {
+    Rotational,
+    SolidState,
+
+    /// Not Linux, or the device couldn't be found in sysfs (e.g. tmpfs, network filesystems)
+    Unknown,
+}
+
+/// The filesystem the input lives on, as far as it can be told; on platforms other than Linux,
+/// nothing can be
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Filesystem {
+    /// The type of filesystem, e.g. `ext4`, `tmpfs`, `nfs` or `fuse.s3fs`, or `unknown`
+    pub kind: String,
+
+    /// What's mounted, e.g. `/dev/nvme0n1p2` or `server:/export`, if known
+    pub device: Option<String>,
+
+    /// Whether the filesystem appears to be on another machine, if that can be told from its
+    /// type (FUSE filesystems can be either)
+    pub network: Option<bool>,
+}
+
+impl Filesystem {
+    pub fn unknown() -> Self {
+        Self {
+            kind: String::from("unknown"),
+            device: None,
+            network: None,
+        }
+    }
+}
+
+/// The CPUs a cgroup (e.g. a container) limits the process to
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct CgroupCpus {
+    /// How many CPUs' worth of time the CPU quota allows, rounded up, if there is a quota
+    pub quota: Option<usize>,
+
+    /// How many CPUs the cpuset allows, if there is one
+    pub cpuset: Option<usize>,
+}
+
+impl CgroupCpus {
+    /// Read the limits of the cgroup the process is in
+    #[cfg(target_os = "linux")]
+    fn detect() -> Self {
+        match std::fs::read_to_string("/proc/self/cgroup") {
+            Ok(membership) => cgroup_cpus(Path::new("/sys/fs/cgroup"), &membership),
+            Err(_) => Self::default(),
+        }
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    fn detect() -> Self {
+        Self::default()
+    }
+
+    /// The most CPUs the cgroup lets the process use, if it's limited
+    pub fn limit(&self) -> Option<usize> {
+        match (self.quota, self.cpuset) {
+            (Some(quota), Some(cpuset)) => Some(quota.min(cpuset)),
+            (quota, cpuset) => quota.or(cpuset),
+        }
+    }
+}
+
+/// How many CPUs the process can make use of.
+///
+/// [`std::thread::available_parallelism`] can report every core on the host when running in a
+/// container which only allows a few, so this also checks the cgroup's limits directly.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct Cpus {
+    /// What [`std::thread::available_parallelism`] reports, if it would say
+    pub reported: Option<usize>,
+    pub cgroup: CgroupCpus,
+}
+
+impl Cpus {
+    pub fn detect() -> Self {
+        Self {
+            reported: std::thread::available_parallelism().ok().map(|n| n.get()),
+            cgroup: CgroupCpus::detect(),
+        }
+    }
+
+    /// The fewest CPUs any of the sources allow, if any of them know
+    pub fn available(&self) -> Option<usize> {
+        match (self.reported, self.cgroup.limit()) {
+            (Some(reported), Some(limit)) => Some(reported.min(limit)),
+            (reported, limit) => reported.or(limit),
+        }
+    }
+}
+
+impl Display for Cpus {
+    /// e.g. `2 (128 reported, cgroup quota 2)`
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.available() {
+            Some(cpus) => write!(f, "{cpus}")?,
+            None => write!(f, "unknown")?,
+        }
+
+        let mut sources = vec![match self.reported {
+            Some(reported) => format!("{reported} reported"),
+            None => String::from("none reported"),
+        }];
+        if let Some(quota) = self.cgroup.quota {
+            sources.push(format!("cgroup quota {quota}"));
+        }
+        if let Some(cpuset) = self.cgroup.cpuset {
+            sources.push(format!("cgroup cpuset {cpuset}"));
+        }
+        write!(f, " ({})", sources.join(", "))
+    }
+}
+
+/// The parallelism available for a run
+#[derive(Debug, Clone, Serialize)]
+pub struct Topology {
+    /// The number of threads which can run in parallel (see [`Cpus`]), if known
+    pub cores: Option<usize>,
+    pub storage: Storage,
+    pub filesystem: Filesystem,
+}
+
+impl Topology {
+    /// Detect the topology relevant to reading the given input
+    pub fn detect(input: &Path) -> Self {
+        Self {
+            cores: Cpus::detect().available(),
+            storage: storage_of(input, Path::new("/sys")),
+            filesystem: filesystem_of(input),
+        }
+    }
+
+    /// The most threads worth using, if there's a limit
+    pub fn max_useful_threads(&self) -> Option<usize> {
+        let disk_limit = (self.storage == Storage::Rotational).then_some(ROTATIONAL_MAX_THREADS);
+        match (self.cores, disk_limit) {
+            (Some(cores), Some(disk)) => Some(cores.min(disk)),
+            (cores, disk) => cores.or(disk),
+        }
+    }
+
+    /// A warning to show if `threads` is more than this machine can make use of
+    pub fn check_threads(&self, threads: usize) -> Option<String> {
+        let suggested = self.max_useful_threads()?;
+        if threads <= suggested {
+            return None;
+        }
+
+        let cores = match self.cores {
+            Some(1) => String::from("1 core"),
+            Some(cores) => format!("{cores} cores"),
+            None => String::from("an unknown number of cores"),
+        };
+        let disk = match self.storage {
+            Storage::Rotational => " and a rotational disk",
+            Storage::SolidState | Storage::Unknown => "",
+        };
+        Some(format!(
+            "{threads} threads is more than this machine can make use of \
+             ({cores}{disk}); try --threads {suggested}"
+        ))
+    }
+}
+
+/// Find the kind of storage the file at `path` lives on using the sysfs mounted at `sysfs`
+#[cfg(target_os = "linux")]
+fn storage_of(path: &Path, sysfs: &Path) -> Storage {
+    use std::os::linux::fs::MetadataExt;
+
+    let Ok(metadata) = std::fs::metadata(path) else {
+        return Storage::Unknown;
+    };
+    let (major, minor) = split_dev(metadata.st_dev());
+    match rotational(sysfs, major, minor) {
+        Some(true) => Storage::Rotational,
+        Some(false) => Storage::SolidState,
+        None => Storage::Unknown,
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn storage_of(_path: &Path, _sysfs: &Path) -> Storage {
+    Storage::Unknown
+}
+
+/// Find the filesystem the file at `path` lives on, from its statfs magic number & the mount
+/// with the same device number in `/proc/self/mountinfo`
+#[cfg(target_os = "linux")]
+fn filesystem_of(path: &Path) -> Filesystem {
+    use std::os::linux::fs::MetadataExt;
+
+    let mount = std::fs::metadata(path).ok().and_then(|metadata| {
+        let (major, minor) = split_dev(metadata.st_dev());
+        let mountinfo = std::fs::read_to_string("/proc/self/mountinfo").ok()?;
+        find_mount(&mountinfo, major, minor)
+    });
+    filesystem(statfs_magic(path), mount)
+}
+
+#[cfg(not(target_os = "linux"))]
+fn filesystem_of(_path: &Path) -> Filesystem {
+    Filesystem::unknown()
+}
+
+/// The magic number identifying the type of filesystem `path` is on (see statfs(2))
+#[cfg(target_os = "linux")]
+fn statfs_magic(path: &Path) -> Option<u32> {
+    use std::os::unix::ffi::OsStrExt;
+
+    let path = std::ffi::CString::new(path.as_os_str().as_bytes()).ok()?;
+    let mut stat = std::mem::MaybeUninit::<libc::statfs>::zeroed();
+    // SAFETY: the path is NUL-terminated & `stat` has room for the result
+    if unsafe { libc::statfs(path.as_ptr(), stat.as_mut_ptr()) } != 0 {
+        return None;
+    }
+    // SAFETY: statfs succeeded, so filled in `stat`
+    let stat = unsafe { stat.assume_init() };
+    // Every magic number fits in 32 bits, though `f_type` is wider (& signed) on some platforms
+    #[allow(clippy::unnecessary_cast)] // It's already a `u32` on s390x
+    Some(stat.f_type as u32)
+}
+
+/// A line of `/proc/self/mountinfo`
+#[cfg(target_os = "linux")]
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Mount {
+    /// e.g. `ext4` or `fuse.sshfs`
+    fs_type: String,
+
+    /// e.g. `/dev/sda1`
+    source: String,
+}
+
+/// Find the mount of device `major:minor` in `mountinfo` (see proc_pid_mountinfo(5))
+#[cfg(target_os = "linux")]
+fn find_mount(mountinfo: &str, major: u64, minor: u64) -> Option<Mount> {
+    let device = format!("{major}:{minor}");
+    mountinfo.lines().find_map(|line| {
+        // ID parent major:minor root mount-point options [optional fields...] - type source ...
+        let (mount, fs) = line.split_once(" - ")?;
+        if mount.split_whitespace().nth(2)? != device {
+            return None;
+        }
+        let mut fs = fs.split_whitespace();
+        Some(Mount {
+            fs_type: fs.next()?.to_owned(),
+            source: fs.next()?.to_owned(),
+        })
+    })
+}
+
+/// The name of the type of filesystem with the statfs magic number `magic`, for the types
+/// benchmarks are likely to run on
+#[cfg(target_os = "linux")]
+fn magic_name(magic: u32) -> Option<&'static str> {
+    Some(match magic {
+        // Shared by ext2, ext3 & ext4
+        0xEF53 => "ext4",
+        0x5846_5342 => "xfs",
+        0x9123_683E => "btrfs",
+        0x2FC1_2FC1 => "zfs",
+        0xF2F5_2010 => "f2fs",
+        0x0102_1994 => "tmpfs",
+        0x8584_58F6 => "ramfs",
+        0x794C_7630 => "overlayfs",
+        0x7371_7368 => "squashfs",
+        0x4D44 => "vfat",
+        0x2011_BAB0 => "exfat",
+        0x5346_544E => "ntfs",
+        0x6969 => "nfs",
+        0xFF53_4D42 => "cifs",
+        0xFE53_4D42 => "smb2",
+        0x00C3_6400 => "ceph",
+        0x0102_1997 => "9p",
+        0x5346_414F => "afs",
+        0x0BD0_0BD0 => "lustre",
+        0x4750_4653 => "gpfs",
+        0x6573_5546 => "fuse",
+        _ => return None,
+    })
+}
+
+/// Whether a filesystem of type `kind` is on another machine, if that can be told
+#[cfg(target_os = "linux")]
+fn is_network(kind: &str) -> Option<bool> {
+    match kind {
+        "nfs" | "nfs4" | "cifs" | "smb2" | "smb3" | "ceph" | "9p" | "afs" | "lustre" | "gpfs" => {
+            Some(true)
+        }
+        // FUSE filesystems backed by another machine or an object store
+        "fuse.sshfs" | "fuse.s3fs" | "fuse.rclone" | "fuse.gcsfuse" | "fuse.goofys"
+        | "fuse.mountpoint-s3" | "fuse.juicefs" | "fuse.glusterfs" | "fuse.blobfuse" => Some(true),
+        "unknown" => None,
+        // Any other FUSE filesystem could be either
+        kind if kind == "fuse" || kind.starts_with("fuse.") || kind.starts_with("fuseblk") => None,
+        _ => Some(false),
+    }
+}
+
+/// Describe a filesystem from its statfs `magic` number & its `mount`, either of which may be
+/// missing
+#[cfg(target_os = "linux")]
+fn filesystem(magic: Option<u32>, mount: Option<Mount>) -> Filesystem {
+    let kind = match (magic.and_then(magic_name), &mount) {
+        // The mount tells ext2/3/4 apart, & which FUSE filesystem it is
+        (Some("ext4" | "fuse"), Some(mount)) => mount.fs_type.clone(),
+        (Some(name), _) => name.to_owned(),
+        (None, Some(mount)) => mount.fs_type.clone(),
+        (None, None) => String::from("unknown"),
+    };
+    Filesystem {
+        network: is_network(&kind),
+        kind,
+        device: mount.map(|mount| mount.source),
+    }
+}
+
+/// Split a Linux device number into its major & minor numbers (like glibc's `major`/`minor`)
+#[cfg(target_os = "linux")]
+fn split_dev(dev: u64) -> (u64, u64) {
+    let major = ((dev >> 32) & 0xffff_f000) | ((dev >> 8) & 0x0fff);
+    let minor = ((dev >> 12) & 0xffff_ff00) | (dev & 0xff);
+    (major, minor)
+}
+
+/// Read the CPU limits of the cgroup(s) listed in `membership` (the contents of
+/// `/proc/self/cgroup`) from the cgroup filesystem mounted at `root`.
+///
+/// Inside a container, the cgroup's own files may be at the root of the mount rather than at the
+/// path listed, so those are checked too.
+#[cfg(target_os = "linux")]
+fn cgroup_cpus(root: &Path, membership: &str) -> CgroupCpus {
+    let read = |mount: &Path, path: &str, file: &str| {
+        std::fs::read_to_string(mount.join(path).join(file))
+            .or_else(|_| std::fs::read_to_string(mount.join(file)))
+            .ok()
+    };
+
+    let mut cpus = CgroupCpus::default();
+    for line in membership.lines() {
+        // hierarchy-ID:controller-list:cgroup-path
+        let mut fields = line.splitn(3, ':');
+        let (Some(_), Some(controllers), Some(path)) =
+            (fields.next(), fields.next(), fields.next())
+        else {
+            continue;
+        };
+        let path = path.trim_start_matches('/');
+
+        if controllers.is_empty() {
+            // cgroup v2, where every controller is in the one hierarchy
+            cpus.quota = cpus
+                .quota
+                .or_else(|| read(root, path, "cpu.max").and_then(|s| parse_cpu_max(&s)));
+            cpus.cpuset = cpus.cpuset.or_else(|| {
+                read(root, path, "cpuset.cpus.effective").and_then(|s| parse_cpu_list(&s))
+            });
+            continue;
+        }
+
+        // cgroup v1, with a hierarchy per set of controllers, e.g. `cpu,cpuacct`
+        let mount = root.join(controllers);
+        for controller in controllers.split(',') {
+            match controller {
+                "cpu" => {
+                    cpus.quota = read(&mount, path, "cpu.cfs_quota_us")
+                        .zip(read(&mount, path, "cpu.cfs_period_us"))
+                        .and_then(|(quota, period)| parse_cfs_quota(&quota, &period))
+                        .or(cpus.quota)
+                }
+                "cpuset" => {
+                    cpus.cpuset = read(&mount, path, "cpuset.cpus")
+                        .and_then(|s| parse_cpu_list(&s))
+                        .or(cpus.cpuset)
+                }
+                _ => {}
+            }
+        }
+    }
+    cpus
+}
+
+/// How many CPUs' worth of time a CPU quota of `quota` per `period` allows, rounded up
+#[cfg(target_os = "linux")]
+fn quota_cpus(quota: u64, period: u64) -> Option<usize> {
+    if period == 0 {
+        return None;
+    }
+    Some(quota.div_ceil(period).max(1) as usize)
+}
+
+/// Parse cgroup v2's `cpu.max`, e.g. `200000 100000`; `max` means there's no quota
+#[cfg(target_os = "linux")]
+fn parse_cpu_max(contents: &str) -> Option<usize> {
+    let mut fields = contents.split_whitespace();
+    let quota = fields.next()?;
+    if quota == "max" {
+        return None;
+    }
+    let period = fields.next().map_or(Some(100_000), |p| p.parse().ok())?;
+    quota_cpus(quota.parse().ok()?, period)
+}
+
+/// Parse cgroup v1's `cpu.cfs_quota_us` & `cpu.cfs_period_us`; a quota of `-1` means there's
+/// no quota
+#[cfg(target_os = "linux")]
+fn parse_cfs_quota(quota: &str, period: &str) -> Option<usize> {
+    // Negative (i.e. unlimited) quotas don't parse
+    quota_cpus(quota.trim().parse().ok()?, period.trim().parse().ok()?)
+}
+
+/// Count the CPUs in a cpuset list, e.g. `0-3,8,10-11`
+#[cfg(target_os = "linux")]
+fn parse_cpu_list(contents: &str) -> Option<usize> {
+    let mut count = 0;
+    for range in contents.trim().split(',').filter(|r| !r.is_empty()) {
+        count += match range.split_once('-') {
+            Some((first, last)) => {
+                let (first, last): (usize, usize) = (first.parse().ok()?, last.parse().ok()?);
+                last.checked_sub(first)? + 1
+            }
+            None => range.parse::<usize>().map(|_| 1).ok()?,
+        };
+    }
+    (count > 0).then_some(count)
+}
+
+/// Read the rotational flag of a block device from sysfs.
+///
+/// Only whole disks have a `queue` directory; for a partition, the flag comes from the disk
+/// it's on, which is its parent in sysfs.
+#[cfg(target_os = "linux")]
+fn rotational(sysfs: &Path, major: u64, minor: u64) -> Option<bool> {
+    let device = std::fs::canonicalize(sysfs.join(format!("dev/block/{major}:{minor}"))).ok()?;
+    let flag = [Some(device.as_path()), device.parent()]
+        .into_iter()
+        .flatten()
+        .find_map(|dir| std::fs::read_to_string(dir.join("queue/rotational")).ok())?;
+
+    match flag.trim() {
+        "1" => Some(true),
+        "0" => Some(false),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn thread_warnings() {
+        let ssd = Topology {
+            cores: Some(8),
+            storage: Storage::SolidState,
+            filesystem: Filesystem::unknown(),
+        };
+        assert_eq!(ssd.check_threads(8), None);
+        let warning = ssd.check_threads(64).unwrap();
+        assert!(warning.contains("--threads 8"), "{warning}");
+
+        let hdd = Topology {
+            cores: Some(8),
+            storage: Storage::Rotational,
+            filesystem: Filesystem::unknown(),
+        };
+        let warning = hdd.check_threads(8).unwrap();
+        assert!(warning.contains("rotational"), "{warning}");
+        assert!(warning.contains("--threads 2"), "{warning}");
+
+        let unknown = Topology {
+            cores: None,
+            storage: Storage::Unknown,
+            filesystem: Filesystem::unknown(),
+        };
+        assert_eq!(unknown.check_threads(1024), None);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn cgroup_file_formats() {
+        assert_eq!(parse_cpu_max("200000 100000\n"), Some(2));
+        assert_eq!(parse_cpu_max("150000 100000\n"), Some(2));
+        assert_eq!(parse_cpu_max("5000 100000\n"), Some(1));
+        assert_eq!(parse_cpu_max("max 100000\n"), None);
+        assert_eq!(parse_cpu_max(""), None);
+
+        assert_eq!(parse_cfs_quota("400000\n", "100000\n"), Some(4));
+        assert_eq!(parse_cfs_quota("-1\n", "100000\n"), None);
+        assert_eq!(parse_cfs_quota("400000\n", "0\n"), None);
+
+        assert_eq!(parse_cpu_list("0-3,8,10-11\n"), Some(7));
+        assert_eq!(parse_cpu_list("5\n"), Some(1));
+        assert_eq!(parse_cpu_list("\n"), None);
+        assert_eq!(parse_cpu_list("3-1"), None);
+    }
+
+    #[test]
+    fn available_cpus() {
+        let limited = Cpus {
+            reported: Some(128),
+            cgroup: CgroupCpus {
+                quota: Some(2),
+                cpuset: Some(4),
+            },
+        };
+        assert_eq!(limited.available(), Some(2));
+        assert_eq!(
+            limited.to_string(),
+            "2 (128 reported, cgroup quota 2, cgroup cpuset 4)"
+        );
+
+        let unlimited = Cpus {
+            reported: Some(8),
+            cgroup: CgroupCpus::default(),
+        };
+        assert_eq!(unlimited.available(), Some(8));
+        assert_eq!(unlimited.to_string(), "8 (8 reported)");
+
+        assert_eq!(Cpus::default().available(), None);
+    }
+
+    /// Write `files` (relative path & contents) to a fake cgroup filesystem
+    #[cfg(target_os = "linux")]
+    fn mock_cgroupfs(files: &[(&str, &str)]) -> std::io::Result<tempfile::TempDir> {
+        let root = tempfile::tempdir()?;
+        for (path, contents) in files {
+            let path = root.path().join(path);
+            std::fs::create_dir_all(path.parent().unwrap())?;
+            std::fs::write(path, contents)?;
+        }
+        Ok(root)
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn cgroup_v2() -> std::io::Result<()> {
+        let root = mock_cgroupfs(&[
+            ("system.slice/app/cpu.max", "300000 100000\n"),
+            ("system.slice/app/cpuset.cpus.effective", "0-7\n"),
+            ("unlimited/cpu.max", "max 100000\n"),
+        ])?;
+        let cpus = cgroup_cpus(root.path(), "0::/system.slice/app\n");
+        assert_eq!(cpus.quota, Some(3));
+        assert_eq!(cpus.cpuset, Some(8));
+        assert_eq!(cpus.limit(), Some(3));
+
+        let cpus = cgroup_cpus(root.path(), "0::/unlimited\n");
+        assert_eq!(cpus, CgroupCpus::default());
+        Ok(())
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn cgroup_v1() -> std::io::Result<()> {
+        // A container without a cgroup namespace sees its own cgroup at the root of the mount
+        let root = mock_cgroupfs(&[
+            ("cpu,cpuacct/cpu.cfs_quota_us", "150000\n"),
+            ("cpu,cpuacct/cpu.cfs_period_us", "100000\n"),
+            ("cpuset/cpuset.cpus", "0,2\n"),
+        ])?;
+        let membership = "12:cpuset:/docker/abc\n4:cpu,cpuacct:/docker/abc\n1:name=systemd:/\n";
+        let cpus = cgroup_cpus(root.path(), membership);
+        assert_eq!(cpus.quota, Some(2));
+        assert_eq!(cpus.cpuset, Some(2));
+
+        let root = mock_cgroupfs(&[
+            ("cpu,cpuacct/cpu.cfs_quota_us", "-1\n"),
+            ("cpu,cpuacct/cpu.cfs_period_us", "100000\n"),
+        ])?;
+        let cpus = cgroup_cpus(root.path(), "4:cpu,cpuacct:/\n");
+        assert_eq!(cpus.limit(), None);
+        Ok(())
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn split_device_numbers() {
+        // makedev(8, 1) & makedev(259, 65536)
+        assert_eq!(split_dev(0x0801), (8, 1));
+        assert_eq!(split_dev(0x1001_0300), (259, 65536));
+    }
+
+    #[cfg(target_os = "linux")]
+    const MOUNTINFO: &str = "\
+22 1 259:2 / / rw,relatime shared:1 - ext4 /dev/nvme0n1p2 rw
+23 22 0:21 / /tmp rw,nosuid shared:2 - tmpfs tmpfs rw,size=8G
+24 22 0:45 / /mnt/data rw,relatime shared:3 - nfs4 nas:/export/data rw,vers=4.2
+25 22 0:52 / /mnt/bucket rw,nosuid,nodev shared:4 master:9 - fuse.s3fs s3fs rw,user_id=0
+26 22 0:53 / /mnt/usb rw - fuseblk /dev/sdb1 rw
+";
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn find_mounts() {
+        let mount = |major, minor| find_mount(MOUNTINFO, major, minor);
+        assert_eq!(
+            mount(259, 2),
+            Some(Mount {
+                fs_type: String::from("ext4"),
+                source: String::from("/dev/nvme0n1p2"),
+            })
+        );
+        // The optional fields before the `-` vary in number
+        assert_eq!(mount(0, 52).unwrap().fs_type, "fuse.s3fs");
+        assert_eq!(mount(0, 45).unwrap().source, "nas:/export/data");
+        assert_eq!(mount(8, 1), None);
+        assert_eq!(find_mount("garbage\n", 0, 21), None);
+    }
+
+    /// Filesystems described from mocked statfs magic numbers & mounts
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn filesystems() {
+        let mount = |major, minor| find_mount(MOUNTINFO, major, minor);
+        let describe = |magic, mount| {
+            let fs = filesystem(magic, mount);
+            (fs.kind, fs.device, fs.network)
+        };
+        let device = |device: &str| Some(String::from(device));
+
+        assert_eq!(
+            describe(Some(0xEF53), mount(259, 2)),
+            ("ext4".into(), device("/dev/nvme0n1p2"), Some(false))
+        );
+        assert_eq!(
+            describe(Some(0x0102_1994), mount(0, 21)),
+            ("tmpfs".into(), device("tmpfs"), Some(false))
+        );
+        assert_eq!(
+            describe(Some(0x6969), mount(0, 45)),
+            ("nfs".into(), device("nas:/export/data"), Some(true))
+        );
+        // The mount says which FUSE filesystem it is, & whether that's on another machine
+        assert_eq!(
+            describe(Some(0x6573_5546), mount(0, 52)),
+            ("fuse.s3fs".into(), device("s3fs"), Some(true))
+        );
+        assert_eq!(
+            describe(Some(0x6573_5546), mount(0, 53)),
+            ("fuseblk".into(), device("/dev/sdb1"), None)
+        );
+        assert_eq!(
+            describe(Some(0x6573_5546), None),
+            ("fuse".into(), None, None)
+        );
+        // Without a magic number the mount's type is used, & without either it's unknown
+        assert_eq!(describe(None, mount(0, 45)).2, Some(true));
+        assert_eq!(describe(Some(0x1234), None), ("unknown".into(), None, None));
+        assert_eq!(filesystem(None, None), Filesystem::unknown());
+    }
+
+    /// Detection never fails, even for a path which doesn't exist
+    #[test]
+    fn detect_filesystem() -> std::io::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("measurements.txt");
+        std::fs::write(&path, "Hamburg;12.0\n")?;
+        let fs = filesystem_of(&path);
+        if cfg!(target_os = "linux") {
+            assert_ne!(fs.kind, "unknown");
+        }
+        assert_eq!(
+            filesystem_of(&dir.path().join("missing")),
+            Filesystem::unknown()
+        );
+        Ok(())
+    }
+
+    /// Build a fake sysfs with a rotational disk `sda` (8:0) with a partition `sda1` (8:1) and
+    /// an SSD `nvme0n1` (259:0)
+    #[cfg(target_os = "linux")]
+    fn mock_sysfs() -> std::io::Result<tempfile::TempDir> {
+        use std::fs;
+        use std::os::unix::fs::symlink;
+
+        let sysfs = tempfile::tempdir()?;
+        let root = sysfs.path();
+        let sda = root.join("devices/pci0000:00/ata1/block/sda");
+        let nvme = root.join("devices/pci0000:00/nvme/nvme0/nvme0n1");
+        fs::create_dir_all(sda.join("queue"))?;
+        fs::create_dir_all(sda.join("sda1"))?;
+        fs::create_dir_all(nvme.join("queue"))?;
+        fs::write(sda.join("queue/rotational"), "1\n")?;
+        fs::write(nvme.join("queue/rotational"), "0\n")?;
+
+        fs::create_dir_all(root.join("dev/block"))?;
+        symlink(&sda, root.join("dev/block/8:0"))?;
+        symlink(sda.join("sda1"), root.join("dev/block/8:1"))?;
+        symlink(&nvme, root.join("dev/block/259:0"))?;
+
+        Ok(sysfs)
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn rotational_flag() -> std::io::Result<()> {
+        let sysfs = mock_sysfs()?;
+        assert_eq!(rotational(sysfs.path(), 8, 0), Some(true));
+        assert_eq!(rotational(sysfs.path(), 8, 1), Some(true));
+        assert_eq!(rotational(sysfs.path(), 259, 0), Some(false));
+        assert_eq!(rotational(sysfs.path(), 7, 0), None);
+        Ok(())
+    }
+}
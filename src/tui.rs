@@ -0,0 +1,182 @@
+// 1BRC - my take on the 1 Billion Row Challenge
+// Copyright (C) 2024  Charles German <5donuts@pm.me>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! The `--tui` dashboard, drawing a run's [`Progress`] while it goes.
+//!
+//! Everything shown comes from a [`View`], worked out in [`crate::progress`]; all this does is
+//! draw it, a few times a second, on a thread of its own. The terminal isn't put in raw mode, so
+//! ctrl-c is still a signal, & whatever handles it (or a panic) only has to [`restore`] the
+//! cursor. The last frame, from the results, is left on screen above whatever's printed next.
+
+use std::io;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::Once;
+use std::time::Duration;
+
+use ratatui::backend::CrosstermBackend;
+use ratatui::crossterm::cursor::{Hide, MoveTo, Show};
+use ratatui::crossterm::{execute, style::Print, terminal};
+use ratatui::layout::{Constraint, Layout, Rect};
+use ratatui::style::{Color, Style};
+use ratatui::text::Line;
+use ratatui::widgets::{Block, Gauge, LineGauge, Paragraph, Row, Table};
+use ratatui::{Frame, Terminal};
+
+use crate::helpers::ChallengeResult;
+use crate::progress::{Progress, StationRow, View, TOP};
+
+/// How often the dashboard is redrawn
+pub const REFRESH: Duration = Duration::from_millis(200);
+
+/// The most threads given a bar of their own
+const MAX_THREADS: usize = 16;
+
+/// Whether the dashboard has the terminal, & so whether there's anything to [`restore`]
+static ACTIVE: AtomicBool = AtomicBool::new(false);
+
+/// Do the `run`, drawing its `progress` on the terminal until it's done.
+///
+/// Only call this with stdout a terminal; see `--tui`.
+pub fn watch(progress: &Progress, run: impl FnOnce() -> ChallengeResult) -> ChallengeResult {
+    let mut terminal = Terminal::new(CrosstermBackend::new(io::stdout()))?;
+    restore_on_panic();
+    terminal.clear()?;
+    execute!(io::stdout(), Hide)?;
+    ACTIVE.store(true, Ordering::SeqCst);
+
+    let (stop, stopped) = mpsc::channel::<()>();
+    let (result, mut terminal) = std::thread::scope(|s| {
+        let drawer = s.spawn(move || {
+            // Until the run's done (or the sender's dropped by a panic)
+            while let Err(RecvTimeoutError::Timeout) = stopped.recv_timeout(REFRESH) {
+                if !ACTIVE.load(Ordering::SeqCst) {
+                    break;
+                }
+                let view = View::new(&progress.reading());
+                if terminal.draw(|frame| draw(frame, &view)).is_err() {
+                    break;
+                }
+            }
+            terminal
+        });
+        let result = run();
+        drop(stop);
+        (result, drawer.join().expect("Dashboard thread panicked"))
+    });
+
+    // Left on screen once the dashboard's gone, from the results rather than the last snapshot
+    if let (Ok((stations, stats)), true) = (&result, ACTIVE.load(Ordering::SeqCst)) {
+        let view = View::finished(&progress.reading(), stations, stats);
+        terminal.draw(|frame| draw(frame, &view))?;
+    }
+    restore();
+    result
+}
+
+/// Give the terminal back as it was, with the cursor shown below the dashboard; safe to call
+/// any number of times, from any thread (e.g. on ctrl-c)
+pub fn restore() {
+    if ACTIVE.swap(false, Ordering::SeqCst) {
+        let rows = terminal::size().map_or(1, |(_, rows)| rows);
+        let _ = execute!(
+            io::stdout(),
+            MoveTo(0, rows.saturating_sub(1)),
+            Show,
+            Print("\n")
+        );
+    }
+}
+
+/// Restore the terminal before a panic's message is printed
+fn restore_on_panic() {
+    static HOOK: Once = Once::new();
+    HOOK.call_once(|| {
+        let previous = std::panic::take_hook();
+        std::panic::set_hook(Box::new(move |info| {
+            restore();
+            previous(info);
+        }));
+    });
+}
+
+fn draw(frame: &mut Frame, view: &View) {
+    let threads = view.threads.len().min(MAX_THREADS) as u16;
+    let [overall, threads_area, tables, counters] = Layout::vertical([
+        Constraint::Length(3),
+        Constraint::Length(threads + 2),
+        Constraint::Length(TOP as u16 + 3),
+        Constraint::Length(1),
+    ])
+    .areas(frame.area());
+
+    frame.render_widget(
+        Gauge::default()
+            .block(Block::bordered().title(" Progress "))
+            .gauge_style(Style::new().fg(Color::Green))
+            .ratio(view.ratio)
+            .label(view.label.as_str()),
+        overall,
+    );
+
+    let block = Block::bordered().title(" Threads ");
+    let inner = block.inner(threads_area);
+    frame.render_widget(block, threads_area);
+    let rows = Layout::vertical(vec![Constraint::Length(1); threads as usize]).split(inner);
+    for (thread, &row) in view.threads.iter().zip(rows.iter()) {
+        frame.render_widget(
+            LineGauge::default()
+                .filled_style(Style::new().fg(Color::Cyan))
+                .ratio(thread.ratio)
+                .label(format!("{:<10}", thread.label)),
+            row,
+        );
+    }
+
+    let [hottest, coldest] = Layout::horizontal([Constraint::Percentage(50); 2]).areas::<2>(tables);
+    draw_stations(frame, hottest, " Hottest ", &view.hottest, Color::Red);
+    draw_stations(frame, coldest, " Coldest ", &view.coldest, Color::Blue);
+
+    frame.render_widget(
+        Paragraph::new(Line::from(format!(
+            " Stations: {}   Skipped lines: {}   Non-finite measurements: {}",
+            view.stations, view.skipped, view.ignored_non_finite
+        ))),
+        counters,
+    );
+}
+
+fn draw_stations(frame: &mut Frame, area: Rect, title: &str, rows: &[StationRow], color: Color) {
+    let rows = rows.iter().map(|row| {
+        Row::new([
+            row.name.clone(),
+            format!("{:.1}", row.mean),
+            format!("{:.1}", row.min),
+            format!("{:.1}", row.max),
+            row.count.to_string(),
+        ])
+    });
+    let widths = [
+        Constraint::Fill(1),
+        Constraint::Length(6),
+        Constraint::Length(6),
+        Constraint::Length(6),
+        Constraint::Length(10),
+    ];
+    let table = Table::new(rows, widths)
+        .header(Row::new(["Station", "Mean", "Min", "Max", "Count"]).style(Style::new().fg(color)))
+        .block(Block::bordered().title(title));
+    frame.render_widget(table, area);
+}
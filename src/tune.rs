@@ -0,0 +1,222 @@
+// 1BRC - my take on the 1 Billion Row Challenge
+// Copyright (C) 2024  Charles German <5donuts@pm.me>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Picking settings by timing the runner on a sample of the input, for `--auto-tune`.
+//!
+//! Only settings left at their defaults are tuned: a value given on the command line, in the
+//! environment or in a config file is the one wanted. For now that's just the buffer size. A
+//! tuned setting's [`Source`] is [`Source::Tuned`], so reports say which numbers came from tuned
+//! settings.
+
+use std::fmt::{self, Display};
+use std::io::{self, Read};
+use std::time::{Duration, Instant};
+
+use crate::config::{Config, Setting, Source};
+use crate::helpers::fmt_duration;
+use crate::runners;
+
+/// How much of the start of the input the candidates are timed on
+pub const SAMPLE_BYTES: u64 = 16 << 20;
+
+/// The buffer sizes tried, in bytes
+pub const BUFFER_SIZES: [usize; 5] = [4 << 10, 16 << 10, 64 << 10, 256 << 10, 1 << 20];
+
+/// How many times each candidate is timed; the fastest time is the one compared
+const TRIALS: usize = 2;
+
+/// Whether a benchmark tunes once up front, or again before every run
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Mode {
+    #[default]
+    Once,
+    EachRun,
+}
+
+impl Display for Mode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Mode::Once => write!(f, "once"),
+            Mode::EachRun => write!(f, "each-run"),
+        }
+    }
+}
+
+/// Every round of tuning behind a run or benchmark
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Tunings {
+    pub mode: Mode,
+
+    /// Each round, in order: just one when tuning once, or one before each run
+    pub rounds: Vec<Tuning>,
+}
+
+/// A candidate buffer size & the fastest it went
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Trial {
+    pub buffer_size: usize,
+    pub time: Duration,
+}
+
+/// The settings picked by one round of tuning
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Tuning {
+    /// The fastest buffer size
+    pub buffer_size: usize,
+
+    /// Every candidate, in the order they were tried
+    pub trials: Vec<Trial>,
+
+    /// How long tuning took, sample & all
+    pub elapsed: Duration,
+}
+
+impl Tuning {
+    /// Time the configured runner on a sample of the input with each candidate setting.
+    ///
+    /// `None` if there's nothing to tune, because every tunable setting was given explicitly.
+    /// Settings tuned before are tuned again.
+    pub fn run(config: &Config) -> Result<Option<Self>, Box<dyn std::error::Error>> {
+        if !matches!(config.buffer_size.source, Source::Default | Source::Tuned) {
+            return Ok(None);
+        }
+        let start = Instant::now();
+        let sample = sample(config)?;
+
+        let mut trials = Vec::with_capacity(BUFFER_SIZES.len());
+        for buffer_size in BUFFER_SIZES {
+            let trial = Config {
+                buffer_size: Setting::new(buffer_size, Source::Tuned),
+                input_size: Setting::new(sample.len() as u64, Source::Auto),
+                // Whatever's wrong with the input is reported by the real run
+                warnings: false,
+                chunk_manifest: None,
+                results_buffer: None,
+                ..config.clone()
+            };
+            let mut time = Duration::MAX;
+            for _ in 0..TRIALS {
+                let (_, stats) = runners::run_with(io::Cursor::new(&sample), &trial)?;
+                time = time.min(stats.timings.total);
+            }
+            trials.push(Trial { buffer_size, time });
+        }
+        let fastest = trials
+            .iter()
+            .min_by_key(|trial| trial.time)
+            .expect("There's more than one candidate");
+
+        Ok(Some(Self {
+            buffer_size: fastest.buffer_size,
+            elapsed: start.elapsed(),
+            trials,
+        }))
+    }
+
+    /// A copy of `config` with the tuned settings in place
+    pub fn apply(&self, config: &Config) -> Config {
+        Config {
+            buffer_size: Setting::new(self.buffer_size, Source::Tuned),
+            ..config.clone()
+        }
+    }
+}
+
+impl Display for Tuning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Auto-tuned the buffer size to {} bytes in {} (",
+            self.buffer_size,
+            fmt_duration(&self.elapsed)
+        )?;
+        for (i, trial) in self.trials.iter().enumerate() {
+            if i > 0 {
+                write!(f, ", ")?;
+            }
+            write!(f, "{}: {}", trial.buffer_size, fmt_duration(&trial.time))?;
+        }
+        write!(f, ")")
+    }
+}
+
+/// The start of the input, cut back to the end of its last whole line
+fn sample(config: &Config) -> io::Result<Vec<u8>> {
+    let f = std::fs::File::open(&config.canonical_input.value)?;
+    let mut sample = Vec::new();
+    f.take(SAMPLE_BYTES).read_to_end(&mut sample)?;
+    if sample.len() as u64 == SAMPLE_BYTES {
+        let end = sample
+            .iter()
+            .rposition(|&b| b == b'\n')
+            .map_or(0, |idx| idx + 1);
+        sample.truncate(end);
+    }
+    Ok(sample)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Layer;
+    use crate::runners::tests::TEST_DATA;
+
+    #[test]
+    fn tunes_defaults_only() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = tempfile::tempdir()?;
+        let input = dir.path().join("measurements.txt");
+        std::fs::write(&input, TEST_DATA)?;
+
+        let cli = Layer {
+            threads: Some(3),
+            ..Layer::default()
+        };
+        let config = Config::resolve(&input, cli, Layer::default(), Layer::default());
+        let tuning = Tuning::run(&config)?.expect("The buffer size is a default");
+        assert_eq!(tuning.trials.len(), BUFFER_SIZES.len());
+        assert!(BUFFER_SIZES.contains(&tuning.buffer_size));
+
+        let tuned = tuning.apply(&config);
+        assert_eq!(tuned.buffer_size.source, Source::Tuned);
+        assert_eq!(tuned.buffer_size.source.to_string(), "auto-tuned");
+        assert_eq!(tuned.threads, Setting::new(3, Source::Cli));
+
+        let cli = Layer {
+            buffer_size: Some(1024),
+            ..Layer::default()
+        };
+        let config = Config::resolve(&input, cli, Layer::default(), Layer::default());
+        assert_eq!(Tuning::run(&config)?, None);
+
+        Ok(())
+    }
+
+    #[test]
+    fn sample_ends_with_a_whole_line() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = tempfile::tempdir()?;
+        let input = dir.path().join("measurements.txt");
+        let line = b"Hamburg;12.0\n";
+        let lines = SAMPLE_BYTES as usize / line.len() + 1;
+        std::fs::write(&input, line.repeat(lines))?;
+
+        let config = Config::resolve(&input, Layer::default(), Layer::default(), Layer::default());
+        let sample = sample(&config)?;
+        assert!(sample.len() as u64 <= SAMPLE_BYTES);
+        assert_eq!(sample.len() % line.len(), 0);
+        assert!(sample.ends_with(b"\n"));
+
+        Ok(())
+    }
+}
@@ -0,0 +1,31 @@
+// 1BRC - my take on the 1 Billion Row Challenge
+// Copyright (C) 2024  Charles German <5donuts@pm.me>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Bindings for using the aggregation core from JavaScript
+
+use wasm_bindgen::prelude::*;
+
+use crate::output::Entry;
+
+/// Aggregate the measurements in `input` (in the challenge's `name;measurement` format).
+///
+/// Returns an alphabetically-sorted array of `{ name, min, mean, max }` objects, or throws an
+/// error describing the first malformed line.
+#[wasm_bindgen]
+pub fn aggregate_text(input: &str) -> Result<JsValue, JsValue> {
+    let stations = crate::solve(input).map_err(|e| JsValue::from_str(&e.to_string()))?;
+    let entries: Vec<Entry> = stations.iter().map(Entry::from).collect();
+    serde_wasm_bindgen::to_value(&entries).map_err(Into::into)
+}
@@ -0,0 +1,287 @@
+// 1BRC - my take on the 1 Billion Row Challenge
+// Copyright (C) 2024  Charles German <5donuts@pm.me>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Solving only some of the input, for `--byte-range` & `--limit`.
+//!
+//! A [`ByteRange`] is what was asked for; it's [aligned](ByteRange::align) to whole lines once
+//! the input is known, & the runners read the aligned range through a [`Window`] (or map only
+//! that range) as if it were the whole input. Byte offsets & row numbers are then counted from
+//! the start of the window.
+//!
+//! A `--limit` of N lines is the window up to the end of the Nth line (see [`first_lines`]), so
+//! every runner solves exactly those lines, however it splits them up.
+
+use std::fmt::{self, Display};
+use std::io::{self, Read, Seek, SeekFrom};
+use std::ops::Range;
+use std::str::FromStr;
+
+use memchr::memchr_iter;
+
+use crate::boundaries::nearest_newline_after;
+use crate::error::ChallengeError;
+use crate::helpers::parse_bytes;
+
+/// The bytes of the input to solve, as given on the command line
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ByteRange {
+    pub start: u64,
+    pub end: u64,
+}
+
+impl ByteRange {
+    /// Align the range to the lines of `input`, which is `len` bytes long.
+    ///
+    /// The start moves forward to the first line starting at or after it (unless it's already 0),
+    /// and the end moves forward to the end of the line containing the byte before it, so two
+    /// adjacent ranges (`0..N` & `N..`) are aligned to adjacent windows which cover each line
+    /// exactly once. Neither end goes past the end of the input.
+    pub fn align<R: Read + Seek>(
+        &self,
+        input: &mut R,
+        len: u64,
+        max_line_length: usize,
+    ) -> Result<Range<u64>, ChallengeError> {
+        let mut align = |offset: u64| match offset {
+            offset if offset >= len => Ok(len),
+            offset => nearest_newline_after(input, offset, max_line_length),
+        };
+        let start = align(self.start)?;
+        let end = align(self.end)?;
+        Ok(start..end.max(start))
+    }
+}
+
+impl FromStr for ByteRange {
+    type Err = String;
+
+    /// Parse `START..END`, each a number of bytes with an optional unit (e.g. `4GiB..5GiB`)
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (start, end) = s
+            .split_once("..")
+            .ok_or_else(|| format!("expected START..END, not '{s}'"))?;
+        let (start, end) = (parse_bytes(start)?, parse_bytes(end)?);
+        if start > end {
+            return Err(format!("the range starts after it ends ({start}..{end})"));
+        }
+        Ok(Self { start, end })
+    }
+}
+
+impl Display for ByteRange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}..{}", self.start, self.end)
+    }
+}
+
+/// The range of the first `lines` lines of `input`, which is `len` bytes long, found by
+/// counting newlines from the start.
+///
+/// Only as much of the input as the lines take up is read. If there are fewer lines than that,
+/// the range is the whole input.
+pub fn first_lines<R: Read + Seek>(input: &mut R, len: u64, lines: u64) -> io::Result<Range<u64>> {
+    if lines == 0 {
+        return Ok(0..0);
+    }
+    input.seek(SeekFrom::Start(0))?;
+
+    let mut buf = vec![0; 64 * 1024];
+    let (mut offset, mut seen) = (0, 0);
+    loop {
+        let n = input.read(&mut buf)?;
+        if n == 0 {
+            return Ok(0..len);
+        }
+        if let Some(end) = memchr_iter(b'\n', &buf[..n]).nth((lines - seen - 1) as usize) {
+            return Ok(0..(offset + end as u64 + 1).min(len));
+        }
+        seen += memchr_iter(b'\n', &buf[..n]).count() as u64;
+        offset += n as u64;
+    }
+}
+
+/// A reader over `range` of another, which reads & seeks as if the range were all there is
+pub struct Window<R> {
+    inner: R,
+    range: Range<u64>,
+
+    /// Where the next read starts, from the start of the range
+    pos: u64,
+}
+
+impl<R: Seek> Window<R> {
+    pub fn new(mut inner: R, range: Range<u64>) -> io::Result<Self> {
+        inner.seek(SeekFrom::Start(range.start))?;
+        Ok(Self {
+            inner,
+            range,
+            pos: 0,
+        })
+    }
+
+    fn len(&self) -> u64 {
+        self.range.end - self.range.start
+    }
+}
+
+impl<R: Read + Seek> Read for Window<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let left = self.len().saturating_sub(self.pos);
+        let want = buf.len().min(usize::try_from(left).unwrap_or(usize::MAX));
+        if want == 0 {
+            return Ok(0);
+        }
+        let n = self.inner.read(&mut buf[..want])?;
+        self.pos += n as u64;
+        Ok(n)
+    }
+}
+
+impl<R: Seek> Seek for Window<R> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let pos = match pos {
+            SeekFrom::Start(pos) => Some(pos),
+            SeekFrom::End(delta) => self.len().checked_add_signed(delta),
+            SeekFrom::Current(delta) => self.pos.checked_add_signed(delta),
+        }
+        .ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "seek to before the start of the window",
+            )
+        })?;
+        self.inner.seek(SeekFrom::Start(self.range.start + pos))?;
+        self.pos = pos;
+        Ok(pos)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::partial::{aggregate, reduce};
+    use crate::runners::tests::{EXPECTED_RESULT, TEST_DATA};
+    use std::error;
+
+    #[test]
+    fn parse() {
+        assert_eq!(
+            "4GiB..5GiB".parse(),
+            Ok(ByteRange {
+                start: 4 << 30,
+                end: 5 << 30
+            })
+        );
+        assert_eq!("0..10".parse::<ByteRange>().unwrap().to_string(), "0..10");
+        assert!("10..5".parse::<ByteRange>().is_err());
+        assert!("10".parse::<ByteRange>().is_err());
+        assert!("1XB..2".parse::<ByteRange>().is_err());
+    }
+
+    #[test]
+    fn align() -> Result<(), ChallengeError> {
+        let data = b"Hamburg;12.0\nBulawayo;8.9\nPalembang;38.8\n";
+        let mut input = io::Cursor::new(data);
+        let len = data.len() as u64;
+        let align =
+            |start, end, input: &mut io::Cursor<_>| ByteRange { start, end }.align(input, len, 64);
+
+        assert_eq!(align(0, len, &mut input)?, 0..len);
+        // Both ends move forward to the start of a line
+        assert_eq!(align(1, 14, &mut input)?, 13..26);
+        // An end just after a newline is already aligned
+        assert_eq!(align(13, 26, &mut input)?, 13..26);
+        // The end includes the line containing the byte before it
+        assert_eq!(align(0, 13, &mut input)?, 0..13);
+        assert_eq!(align(0, 12, &mut input)?, 0..13);
+        // Within one line, the window is empty
+        assert_eq!(align(2, 5, &mut input)?, 13..13);
+        assert_eq!(align(30, 1 << 30, &mut input)?, len..len);
+
+        Ok(())
+    }
+
+    #[test]
+    fn window_reads_and_seeks() -> io::Result<()> {
+        let mut window = Window::new(io::Cursor::new(b"0123456789"), 2..7)?;
+        let mut read = String::new();
+        window.read_to_string(&mut read)?;
+        assert_eq!(read, "23456");
+
+        assert_eq!(window.seek(SeekFrom::End(-2))?, 3);
+        read.clear();
+        window.read_to_string(&mut read)?;
+        assert_eq!(read, "56");
+        assert_eq!(window.seek(SeekFrom::Current(-4))?, 1);
+        assert!(window.seek(SeekFrom::Current(-2)).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn first_lines() -> io::Result<()> {
+        let data = b"Hamburg;12.0\nBulawayo;8.9\n\nPalembang;38.8";
+        let mut input = io::Cursor::new(data);
+        let len = data.len() as u64;
+
+        assert_eq!(super::first_lines(&mut input, len, 0)?, 0..0);
+        assert_eq!(super::first_lines(&mut input, len, 1)?, 0..13);
+        assert_eq!(super::first_lines(&mut input, len, 2)?, 0..26);
+        // Blank lines count too
+        assert_eq!(super::first_lines(&mut input, len, 3)?, 0..27);
+        // The last line has no newline, so runs to the end of the input
+        assert_eq!(super::first_lines(&mut input, len, 4)?, 0..len);
+        assert_eq!(super::first_lines(&mut input, len, 1_000)?, 0..len);
+
+        // Lines spanning the blocks the input is read in
+        let data = "Hamburg;12.0\n".repeat(10_000);
+        let mut input = io::Cursor::new(data.as_bytes());
+        let len = data.len() as u64;
+        for lines in [5_000, 5_041, 10_000] {
+            assert_eq!(super::first_lines(&mut input, len, lines)?, 0..lines * 13);
+        }
+
+        Ok(())
+    }
+
+    /// Adjacent windows split anywhere cover every line exactly once
+    #[test]
+    fn adjacent_windows() -> Result<(), Box<dyn error::Error>> {
+        let len = TEST_DATA.len() as u64;
+        for split in 0..=len {
+            let mut input = io::Cursor::new(TEST_DATA.as_bytes());
+            let partials = [0..split, split..len]
+                .into_iter()
+                .map(|range| {
+                    let range = ByteRange {
+                        start: range.start,
+                        end: range.end,
+                    }
+                    .align(&mut input, len, 64)?;
+                    let window = Window::new(io::Cursor::new(TEST_DATA.as_bytes()), range)?;
+                    Ok(aggregate(window, &Config::default())?)
+                })
+                .collect::<Result<Vec<_>, Box<dyn error::Error>>>()?;
+            assert_eq!(
+                reduce(partials, false),
+                *EXPECTED_RESULT,
+                "split at {split}"
+            );
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,181 @@
+// 1BRC - my take on the 1 Billion Row Challenge
+// Copyright (C) 2024  Charles German <5donuts@pm.me>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Tests counting the allocations made by the runners & output, with an allocator which counts them.
+//!
+//! The allocator features replace the global allocator themselves, so these only run without.
+
+#![cfg(all(
+    feature = "native",
+    not(any(feature = "mimalloc", feature = "jemalloc"))
+))]
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+use std::io::Cursor;
+use std::sync::{Arc, Mutex};
+
+use onebrc::aggregate::Extents;
+use onebrc::config::{Config, Source};
+use onebrc::helpers::{ResultsBuffer, StationInfo};
+use onebrc::output::Format;
+use onebrc::runners;
+use onebrc::Runner;
+
+const TEST_DATA: &str = "Hamburg;12.0\nBulawayo;8.9\nPalembang;38.8\nHamburg;34.2\n\
+St. John's;15.2\nCracow;12.6\nBridgetown;26.9\nIstanbul;6.2\nRoseau;34.4\nConakry;31.2\n";
+
+/// The number of stations in [`TEST_DATA`]
+const STATIONS: u64 = 9;
+
+thread_local! {
+    /// Allocations made by this thread; the tests run in threads of their own, so don't count
+    /// each other's
+    static ALLOCATIONS: Cell<u64> = const { Cell::new(0) };
+}
+
+struct Counting;
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let _ = ALLOCATIONS.try_with(|count| count.set(count.get() + 1));
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let _ = ALLOCATIONS.try_with(|count| count.set(count.get() + 1));
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: Counting = Counting;
+
+/// Solve the test data, returning the results & how many allocations it took
+fn counted_run(config: &Config) -> (Vec<StationInfo>, u64) {
+    let before = ALLOCATIONS.with(Cell::get);
+    let (stations, _) = runners::run_with(Cursor::new(TEST_DATA.as_bytes()), config).unwrap();
+    (stations, ALLOCATIONS.with(Cell::get) - before)
+}
+
+/// Where the list & names of some results are in memory
+fn addresses(stations: &[StationInfo]) -> (*const StationInfo, Vec<*const u8>) {
+    let mut names: Vec<_> = stations.iter().map(|s| s.name().as_ptr()).collect();
+    names.sort_unstable();
+    (stations.as_ptr(), names)
+}
+
+/// Once a benchmark has recycled the results of a run, the runs after it allocate neither the
+/// list of stations nor their names
+#[test]
+fn bench_reuses_results() {
+    let plain = Config::default().with_runner(Runner::Baseline, Source::Cli);
+    let bench = Config {
+        results_buffer: Some(Arc::new(Mutex::new(ResultsBuffer::default()))),
+        ..plain.clone()
+    };
+
+    let (expected, fresh) = counted_run(&plain);
+    assert_eq!(expected.len() as u64, STATIONS);
+
+    // The first run of a benchmark has nothing to reuse yet
+    let (first, allocations) = counted_run(&bench);
+    assert_eq!(allocations, fresh);
+    bench.recycle_results(first);
+
+    let (second, allocations) = counted_run(&bench);
+    assert_eq!(second, expected);
+    assert!(
+        allocations + STATIONS < fresh,
+        "{allocations} allocations when reusing results vs. {fresh} without"
+    );
+    let reused = addresses(&second);
+    bench.recycle_results(second);
+
+    // Every run from then on allocates the same, in the same places
+    for _ in 0..3 {
+        let (stations, again) = counted_run(&bench);
+        assert_eq!(stations, expected);
+        assert_eq!(again, allocations);
+        assert_eq!(addresses(&stations), reused);
+        bench.recycle_results(stations);
+    }
+
+    // Without a buffer, results are never recycled
+    plain.recycle_results(expected);
+    assert_eq!(counted_run(&plain).1, fresh);
+}
+
+/// Rendering the results allocates the document once, sized up front, rather than growing it
+/// (or allocating anything else) for each station
+#[test]
+fn render_allocates_up_front() {
+    let stations = |count: usize, extents: bool| -> Vec<StationInfo> {
+        (0..count)
+            .map(|i| {
+                let station = StationInfo::new(format!("Station {i:05}"), -99.9, 99.9, -0.5, 3);
+                station.with_extents(extents.then_some(Extents {
+                    first_row: i as u64,
+                    last_row: u64::MAX,
+                }))
+            })
+            .collect()
+    };
+
+    for format in [Format::Text, Format::Json, Format::Csv, Format::Tsv] {
+        for extents in [false, true] {
+            let counted = |stations: &[StationInfo]| {
+                let before = ALLOCATIONS.with(Cell::get);
+                let doc = format.render(stations);
+                (
+                    ALLOCATIONS.with(Cell::get) - before,
+                    doc.capacity() - doc.len(),
+                )
+            };
+            // The document itself is the only allocation, however many stations there are
+            let (few, _) = counted(&stations(10, extents));
+            let (many, spare) = counted(&stations(10_000, extents));
+            assert_eq!(few, 1, "{format} with extents: {extents}");
+            assert_eq!(many, 1, "{format} with extents: {extents}");
+            // The estimate of the document's length isn't wildly over either
+            assert!(spare < 10_000 * 64, "{spare} bytes spare in {format}");
+        }
+    }
+}
+
+/// Once every station has been seen, the `entry-ref` runner doesn't allocate for any more lines
+/// of them, so solving the same stations takes the same allocations however many lines there are
+#[test]
+fn entry_ref_repeated_keys() {
+    let config = Config::default().with_runner(Runner::EntryRef, Source::Cli);
+    let counted = |input: &str| {
+        let before = ALLOCATIONS.with(Cell::get);
+        let (stations, _) = runners::run_with(Cursor::new(input.as_bytes()), &config).unwrap();
+        (stations, ALLOCATIONS.with(Cell::get) - before)
+    };
+
+    let (once, allocations) = counted(TEST_DATA);
+    let (repeated, again) = counted(&TEST_DATA.repeat(1_000));
+    assert_eq!(repeated.len(), once.len());
+    assert_eq!(repeated[0].count(), once[0].count() * 1_000);
+    assert_eq!(
+        again, allocations,
+        "{again} allocations for 1,000 times the lines"
+    );
+}
@@ -0,0 +1,952 @@
+// 1BRC - my take on the 1 Billion Row Challenge
+// Copyright (C) 2024  Charles German <5donuts@pm.me>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Tests of the `onebrc` binary itself
+
+#![cfg(all(unix, feature = "native"))]
+
+use std::process::{Command, Stdio};
+
+use onebrc::generate::{Generator, Pattern};
+
+const TEST_DATA: &str = "Hamburg;12.0\nBulawayo;8.9\nPalembang;38.8\nHamburg;34.2\n";
+
+/// Write the test data to a temporary directory, returning the directory & path of the input
+fn fixture() -> std::io::Result<(tempfile::TempDir, std::path::PathBuf)> {
+    let dir = tempfile::tempdir()?;
+    let input = dir.path().join("measurements.txt");
+    std::fs::write(&input, TEST_DATA)?;
+    Ok((dir, input))
+}
+
+#[test]
+fn stdout_closed_early() -> Result<(), Box<dyn std::error::Error>> {
+    let (dir, input) = fixture()?;
+    let report = dir.path().join("report.json");
+
+    // Like piping into a process which exits without reading anything
+    let mut child = Command::new(env!("CARGO_BIN_EXE_onebrc"))
+        .arg("--report")
+        .arg(&report)
+        .arg(&input)
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+    drop(child.stdout.take());
+    let output = child.wait_with_output()?;
+
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(output.status.success(), "{stderr}");
+    assert!(!stderr.contains("panicked"), "{stderr}");
+    assert!(report.exists(), "The report is still written");
+
+    Ok(())
+}
+
+#[test]
+fn repeat() -> Result<(), Box<dyn std::error::Error>> {
+    let (_dir, input) = fixture()?;
+    let output = Command::new(env!("CARGO_BIN_EXE_onebrc"))
+        .args(["--repeat", "3"])
+        .arg(&input)
+        .output()?;
+    let stdout = String::from_utf8(output.stdout)?;
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    let timings = stdout.lines().filter(|l| l.starts_with("Run ")).count();
+    assert_eq!(timings, 3, "{stdout}");
+    assert_eq!(stdout.matches("Hamburg=").count(), 1, "{stdout}");
+    assert!(stdout.contains("Best of 3: "), "{stdout}");
+
+    Ok(())
+}
+
+#[test]
+fn adaptive_block_sizes() -> Result<(), Box<dyn std::error::Error>> {
+    let (_dir, input) = fixture()?;
+    let run = |args: &[&str]| {
+        Command::new(env!("CARGO_BIN_EXE_onebrc"))
+            .args(["--runner", "pipelined", "--block-sizing"])
+            .args(args)
+            .arg(&input)
+            .output()
+    };
+
+    let output = run(&["adaptive:16-1KiB", "--timings"])?;
+    let stdout = String::from_utf8(output.stdout)?;
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert!(stdout.contains("Hamburg=12.0/23.1/34.2"), "{stdout}");
+    assert!(stdout.contains("\nBlock sizes: 1.0 KiB ×"), "{stdout}");
+
+    // The sizes are only shown with the other timings
+    let output = run(&["adaptive"])?;
+    let stdout = String::from_utf8(output.stdout)?;
+    assert!(output.status.success());
+    assert!(!stdout.contains("Block sizes"), "{stdout}");
+
+    let output = run(&["adaptive:1MiB-1KiB"])?;
+    assert!(!output.status.success());
+
+    Ok(())
+}
+
+#[test]
+fn error_report() -> Result<(), Box<dyn std::error::Error>> {
+    let (dir, input) = fixture()?;
+    let report = dir.path().join("error.json");
+    let run = || {
+        Command::new(env!("CARGO_BIN_EXE_onebrc"))
+            .arg("--error-report")
+            .arg(&report)
+            .arg(&input)
+            .output()
+    };
+
+    std::fs::write(&input, format!("{TEST_DATA}Hamburg 12.0\n"))?;
+    let output = run()?;
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("Wrote an error report"), "{stderr}");
+
+    let doc: serde_json::Value = serde_json::from_slice(&std::fs::read(&report)?)?;
+    assert_eq!(doc["kind"], "malformed-line");
+    assert_eq!(
+        doc["message"],
+        "Malformed line 5: missing ';' between station and measurement"
+    );
+    assert_eq!(doc["input"], input.to_str().unwrap());
+    assert_eq!(doc["line"], 5);
+    assert!(doc["offset"].is_null());
+    assert_eq!(doc["config"]["runner"]["value"], "ahash");
+    assert_eq!(doc["stats"]["runs"].as_array().map(Vec::len), Some(0));
+    assert!(doc["timestamp"].as_u64().is_some_and(|t| t > 0));
+
+    // Not being able to write the report doesn't hide the original error
+    let output = Command::new(env!("CARGO_BIN_EXE_onebrc"))
+        .arg("--error-report")
+        .arg(dir.path().join("missing").join("error.json"))
+        .arg(&input)
+        .output()?;
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(!output.status.success());
+    assert!(
+        stderr.contains("couldn't write the error report"),
+        "{stderr}"
+    );
+    assert!(stderr.contains("Error: Malformed line"), "{stderr}");
+
+    // Once the input is fixed, the report of the old failure goes away
+    std::fs::write(&input, TEST_DATA)?;
+    let output = run()?;
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert!(!report.exists());
+
+    Ok(())
+}
+
+#[test]
+fn max_skipped() -> Result<(), Box<dyn std::error::Error>> {
+    let (dir, input) = fixture()?;
+    let report = dir.path().join("report.json");
+    std::fs::write(
+        &input,
+        format!("{TEST_DATA}Hamburg 12.0\nHamburg;warm\nBulawayo;1e40\n"),
+    )?;
+    let run = |max_skipped: &str| {
+        Command::new(env!("CARGO_BIN_EXE_onebrc"))
+            .args(["--max-skipped", max_skipped, "--report"])
+            .arg(&report)
+            .arg(&input)
+            .output()
+    };
+
+    let output = run("3")?;
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(output.status.success(), "{stderr}");
+    assert!(
+        stderr.contains(
+            "skipped 3 malformed lines: 1 no semicolon, 1 bad temperature, 1 out of range"
+        ),
+        "{stderr}"
+    );
+    let doc: serde_json::Value = serde_json::from_slice(&std::fs::read(&report)?)?;
+    assert_eq!(doc["skipped"]["total"], 3);
+    assert_eq!(doc["skipped"]["bad_temperature"], 1);
+    assert_eq!(doc["config"]["max_skipped"]["value"], 3);
+
+    let output = run("2")?;
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(!output.status.success());
+    assert!(
+        stderr.contains("Error: More than 2 malformed lines"),
+        "{stderr}"
+    );
+
+    Ok(())
+}
+
+#[test]
+fn chunk_manifest() -> Result<(), Box<dyn std::error::Error>> {
+    let (dir, input) = fixture()?;
+    let manifest = |runner: &str| -> Result<std::path::PathBuf, Box<dyn std::error::Error>> {
+        let path = dir.path().join(format!("{runner}.json"));
+        let output = Command::new(env!("CARGO_BIN_EXE_onebrc"))
+            .args([
+                "--runner",
+                runner,
+                "--buffer-size",
+                "16",
+                "--max-line-length",
+                "16",
+            ])
+            .args(["--threads", "2", "--quiet", "--chunk-manifest"])
+            .arg(&path)
+            .arg(&input)
+            .output()?;
+        assert!(
+            output.status.success(),
+            "{}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+        Ok(path)
+    };
+    let diff = |first: &std::path::Path, second: &std::path::Path| {
+        Command::new(env!("CARGO_BIN_EXE_onebrc"))
+            .arg("--diff-manifests")
+            .args([first, second])
+            .output()
+    };
+
+    let parallel = manifest("sampled-dense")?;
+    let reference = manifest("baseline")?;
+    let output = diff(&parallel, &reference)?;
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "The manifests match: 4 blocks, 4 rows\n"
+    );
+
+    let mut doc: serde_json::Value = serde_json::from_slice(&std::fs::read(&parallel)?)?;
+    assert_eq!(doc["reference"], false);
+    doc["chunks"][1]["rows"] = 0.into();
+    std::fs::write(&parallel, serde_json::to_vec(&doc)?)?;
+    let output = diff(&parallel, &reference)?;
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(!output.status.success());
+    assert!(
+        stderr.contains(
+            "block 1 (bytes 13..26) has 0 rows in the first manifest but 1 in the second"
+        ),
+        "{stderr}"
+    );
+
+    Ok(())
+}
+
+#[test]
+fn list_runners() -> Result<(), Box<dyn std::error::Error>> {
+    // No input is needed
+    let output = Command::new(env!("CARGO_BIN_EXE_onebrc"))
+        .arg("--list-runners")
+        .output()?;
+    let stdout = String::from_utf8(output.stdout)?;
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    assert!(
+        stdout.contains("| baseline | SipHash-1-3 | no | bit-exact |"),
+        "{stdout}"
+    );
+    assert!(
+        stdout.contains("| sampled-dense | AHasher | yes | rounded-exact |"),
+        "{stdout}"
+    );
+
+    Ok(())
+}
+
+#[test]
+fn repro_bundle() -> Result<(), Box<dyn std::error::Error>> {
+    let (dir, input) = fixture()?;
+    let bundle = dir.path().join("bundle.tar.gz");
+
+    let output = Command::new(env!("CARGO_BIN_EXE_onebrc"))
+        .args(["--bench", "--runner", "table", "--export-repro"])
+        .arg(&bundle)
+        .arg(&input)
+        .output()?;
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    let verify = |input: &std::path::Path| {
+        Command::new(env!("CARGO_BIN_EXE_onebrc"))
+            .arg("--verify-repro")
+            .arg(&bundle)
+            .arg(input)
+            .output()
+    };
+    let output = verify(&input)?;
+    let stdout = String::from_utf8(output.stdout)?;
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert!(stdout.starts_with("Result: match"), "{stdout}");
+    assert!(stdout.contains("(recorded "), "{stdout}");
+
+    // A different input can't reproduce the same result
+    std::fs::write(&input, format!("{TEST_DATA}Hamburg;-5.0\n"))?;
+    let output = verify(&input)?;
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(!output.status.success());
+    assert!(
+        stderr.contains("isn't the input the bundle was exported from"),
+        "{stderr}"
+    );
+
+    Ok(())
+}
+
+#[test]
+fn generate() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = tempfile::tempdir()?;
+    let input = dir.path().join("repeated.txt");
+
+    let output = Command::new(env!("CARGO_BIN_EXE_onebrc"))
+        .args(["--pattern", "repeated", "--rows", "1001", "--seed", "3"])
+        .arg("--generate")
+        .arg(&input)
+        .output()?;
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let expected = std::fs::read_to_string(dir.path().join("repeated.txt.expected"))?;
+    assert_eq!(std::fs::read_to_string(&input)?.lines().count(), 1001);
+
+    let output = Command::new(env!("CARGO_BIN_EXE_onebrc"))
+        .arg(&input)
+        .output()?;
+    let stdout = String::from_utf8(output.stdout)?;
+    assert!(output.status.success());
+    assert_eq!(stdout.lines().next(), expected.lines().next());
+
+    Ok(())
+}
+
+#[test]
+fn partial_aggregates() -> Result<(), Box<dyn std::error::Error>> {
+    let (dir, input) = fixture()?;
+    let run = |args: &[&std::ffi::OsStr]| -> Result<(), Box<dyn std::error::Error>> {
+        let output = Command::new(env!("CARGO_BIN_EXE_onebrc"))
+            .arg("--quiet")
+            .args(args)
+            .output()?;
+        assert!(
+            output.status.success(),
+            "{}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+        Ok(())
+    };
+
+    // Split the input into three shards, each aggregated into a partial result of its own
+    let lines: Vec<&str> = TEST_DATA.lines().collect();
+    let mut parts = Vec::new();
+    for (idx, shard) in [&lines[..1], &lines[1..3], &lines[3..]].iter().enumerate() {
+        let shard_path = dir.path().join(format!("shard-{idx}.txt"));
+        std::fs::write(&shard_path, shard.join("\n") + "\n")?;
+        let part = dir.path().join(format!("shard-{idx}.part"));
+        run(&[
+            "--aggregate-partial".as_ref(),
+            part.as_ref(),
+            shard_path.as_ref(),
+        ])?;
+        parts.push(part);
+    }
+
+    let direct = dir.path().join("direct.txt");
+    run(&["--output".as_ref(), direct.as_ref(), input.as_ref()])?;
+    let reduced = dir.path().join("reduced.txt");
+    let mut args = vec!["--output".as_ref(), reduced.as_ref(), "--reduce".as_ref()];
+    args.extend(parts.iter().map(|part| part.as_os_str()));
+    run(&args)?;
+
+    let expected = std::fs::read_to_string(&direct)?;
+    assert!(expected.contains("Hamburg=12.0/23.1/34.2"), "{expected}");
+    assert_eq!(std::fs::read_to_string(&reduced)?, expected);
+
+    Ok(())
+}
+
+#[test]
+fn byte_range() -> Result<(), Box<dyn std::error::Error>> {
+    let (dir, input) = fixture()?;
+    let run = |args: &[&std::ffi::OsStr]| -> Result<String, Box<dyn std::error::Error>> {
+        let output = Command::new(env!("CARGO_BIN_EXE_onebrc"))
+            .arg("--quiet")
+            .args(args)
+            .output()?;
+        let stderr = String::from_utf8(output.stderr)?;
+        assert!(output.status.success(), "{stderr}");
+        Ok(stderr)
+    };
+
+    // Split in the middle of `Bulawayo;8.9`, which goes in the first range
+    let mut parts = Vec::new();
+    for (idx, range) in ["0..20", "20..1KiB"].into_iter().enumerate() {
+        let part = dir.path().join(format!("{idx}.part"));
+        let stderr = run(&[
+            "--byte-range".as_ref(),
+            range.as_ref(),
+            "--aggregate-partial".as_ref(),
+            part.as_ref(),
+            input.as_ref(),
+        ])?;
+        let aligned = ["0..26 of the input (26 B)", "26..54 of the input (28 B)"][idx];
+        assert!(stderr.contains(aligned), "{stderr}");
+        parts.push(part);
+    }
+
+    let direct = dir.path().join("direct.txt");
+    run(&["--output".as_ref(), direct.as_ref(), input.as_ref()])?;
+    let reduced = dir.path().join("reduced.txt");
+    let mut args = vec!["--output".as_ref(), reduced.as_ref(), "--reduce".as_ref()];
+    args.extend(parts.iter().map(|part| part.as_os_str()));
+    run(&args)?;
+    assert_eq!(
+        std::fs::read_to_string(&reduced)?,
+        std::fs::read_to_string(&direct)?
+    );
+
+    // A runner solves only the range
+    let windowed = dir.path().join("windowed.txt");
+    run(&[
+        "--runner".as_ref(),
+        "par-mmap".as_ref(),
+        "--byte-range".as_ref(),
+        "20..30".as_ref(),
+        "--output".as_ref(),
+        windowed.as_ref(),
+        input.as_ref(),
+    ])?;
+    assert_eq!(
+        std::fs::read_to_string(&windowed)?,
+        "{Palembang=38.8/38.8/38.8}\n"
+    );
+
+    Ok(())
+}
+
+#[test]
+fn limit() -> Result<(), Box<dyn std::error::Error>> {
+    let (_dir, input) = fixture()?;
+    let run = |runner: &str, limit: &str| -> Result<(String, String), Box<dyn std::error::Error>> {
+        let output = Command::new(env!("CARGO_BIN_EXE_onebrc"))
+            .args(["--runner", runner, "--threads", "3", "--limit", limit])
+            .arg(&input)
+            .output()?;
+        let stderr = String::from_utf8(output.stderr)?;
+        assert!(output.status.success(), "{stderr}");
+        Ok((String::from_utf8(output.stdout)?, stderr))
+    };
+
+    for runner in ["ahash", "par-mmap", "scoped-threads", "pipelined"] {
+        let (stdout, stderr) = run(runner, "2")?;
+        assert!(
+            stdout.starts_with("{Bulawayo=8.9/8.9/8.9, Hamburg=12.0/12.0/12.0}\n"),
+            "{stdout}"
+        );
+        assert!(
+            stderr.contains("first 2 lines of the input, bytes 0..26 (26 B)"),
+            "{stderr}"
+        );
+
+        // More lines than there are is the whole input
+        let (stdout, _) = run(runner, "100")?;
+        assert!(stdout.contains("Palembang=38.8"), "{stdout}");
+    }
+
+    Ok(())
+}
+
+#[test]
+fn unopenable_output() -> Result<(), Box<dyn std::error::Error>> {
+    let (dir, input) = fixture()?;
+    let missing = dir.path().join("missing").join("out.txt");
+    let good = dir.path().join("out.txt");
+
+    let output = Command::new(env!("CARGO_BIN_EXE_onebrc"))
+        .arg("--output")
+        .arg(&missing)
+        .arg("--output")
+        .arg(&good)
+        .arg(&input)
+        .output()?;
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(!output.status.success(), "{stderr}");
+    assert!(
+        stderr.contains(&format!("Failed to write results to {}", missing.display())),
+        "{stderr}"
+    );
+
+    // Stdout & the other file get the results all the same
+    let expected = "{Bulawayo=8.9/8.9/8.9, Hamburg=12.0/23.1/34.2, Palembang=38.8/38.8/38.8}";
+    assert!(
+        String::from_utf8(output.stdout)?.starts_with(expected),
+        "{stderr}"
+    );
+    assert_eq!(std::fs::read_to_string(&good)?.trim_end(), expected);
+
+    Ok(())
+}
+
+#[test]
+fn sample_rate() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = tempfile::tempdir()?;
+    let input = dir.path().join("measurements.txt");
+    let generator = Generator::new(Pattern::RoundRobin, 200_000, 20, 5);
+    generator.write(std::fs::File::create(&input)?)?;
+
+    let run = |name: &str, sample: &[&str]| -> Result<String, Box<dyn std::error::Error>> {
+        let json = dir.path().join(format!("{name}.json"));
+        let text = dir.path().join(format!("{name}.txt"));
+        let output = Command::new(env!("CARGO_BIN_EXE_onebrc"))
+            .args(["-q", "--buffer-size", "4096"])
+            .args(sample)
+            .arg("--output")
+            .arg(&json)
+            .arg("--output")
+            .arg(&text)
+            .arg(&input)
+            .output()?;
+        assert!(
+            output.status.success(),
+            "{}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+        Ok(std::fs::read_to_string(&json)? + &std::fs::read_to_string(&text)?)
+    };
+    let sample = ["--sample-rate", "0.1", "--sample-seed", "3"];
+    let sampled = run("sampled", &sample)?;
+    // The same seed samples the same lines
+    assert_eq!(run("again", &sample)?, sampled);
+
+    let (json, text) = sampled.split_once("\n}\n").unwrap();
+    assert!(
+        text.starts_with("Sampled data from 10% of the input's blocks (seed 3): "),
+        "{text}"
+    );
+    let doc: serde_json::Value = serde_json::from_str(&format!("{json}}}"))?;
+    assert_eq!(doc["sampled"]["rate"], 0.1);
+    assert_eq!(doc["sampled"]["seed"], 3);
+
+    // Each station's mean is within three standard errors of the whole input's, from a tenth of
+    // its measurements. A station's measurements are its base plus or minus its amplitude, so
+    // their standard deviation is the amplitude.
+    let stations = doc["stations"].as_array().unwrap();
+    let expected = generator.expected();
+    assert_eq!(stations.len(), expected.len());
+    let counted: u64 = stations.iter().map(|s| s["count"].as_u64().unwrap()).sum();
+    assert!((10_000..30_000).contains(&counted), "{counted}");
+    for (station, expected) in stations.iter().zip(&expected) {
+        assert_eq!(station["name"], expected.name());
+        let mean = station["mean"].as_f64().unwrap();
+        let amplitude = f64::from(expected.max() - expected.min()) / 2.0;
+        let count = station["count"].as_f64().unwrap();
+        assert!(
+            (mean - f64::from(expected.avg())).abs() < 3.0 * amplitude / count.sqrt(),
+            "{station} vs {expected}"
+        );
+        assert!(station["observed_min"].as_f64().unwrap() >= f64::from(expected.min()));
+        assert!(station["observed_max"].as_f64().unwrap() <= f64::from(expected.max()));
+    }
+
+    Ok(())
+}
+
+#[test]
+fn temp_files() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = tempfile::tempdir()?;
+    // Too many stations for the memory limit, so they're spilled to disk, & a malformed line
+    // once they have been
+    let input = dir.path().join("measurements.txt");
+    let mut data: String = (0..20_000)
+        .map(|i| format!("Station {i};{}.5\n", i % 100 - 50))
+        .collect();
+    data.push_str("Nowhere\n");
+    std::fs::write(&input, data)?;
+
+    let run = |keep: bool| -> Result<(String, usize), Box<dyn std::error::Error>> {
+        let tmp = tempfile::tempdir_in(dir.path())?;
+        let output = Command::new(env!("CARGO_BIN_EXE_onebrc"))
+            .env("TMPDIR", tmp.path())
+            .args(["--runner", "sampled-dense", "--max-memory", "4096"])
+            .args(keep.then_some("--keep-temp"))
+            .arg(&input)
+            .output()?;
+        assert!(!output.status.success());
+        let left = std::fs::read_dir(tmp.path())?.count();
+        Ok((String::from_utf8(output.stderr)?, left))
+    };
+
+    let (stderr, left) = run(false)?;
+    assert!(stderr.contains("Error: Malformed line"), "{stderr}");
+    assert_eq!(left, 0, "{stderr}");
+
+    let (stderr, left) = run(true)?;
+    assert!(left > 0, "{stderr}");
+    let listed = stderr
+        .split_once("Kept temporary files:\n")
+        .map_or(0, |(_, kept)| kept.matches("onebrc-spill-").count());
+    assert_eq!(listed, left, "{stderr}");
+
+    Ok(())
+}
+
+#[cfg(feature = "encodings")]
+#[test]
+fn latin1_input() -> Result<(), Box<dyn std::error::Error>> {
+    let input = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/data/latin1.txt");
+    let output = Command::new(env!("CARGO_BIN_EXE_onebrc"))
+        .args(["--encoding", "latin1", input])
+        .output()?;
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    // The names are written out as UTF-8
+    let stdout = String::from_utf8(output.stdout)?;
+    assert!(
+        stdout.contains("{Hamburg=8.0/8.0/8.0, São Paulo=19.1/22.2/25.3, Zürich=-3.5/4.2/12.0}"),
+        "{stdout}"
+    );
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("encoding:      latin1 (cli)"), "{stderr}");
+
+    let output = Command::new(env!("CARGO_BIN_EXE_onebrc"))
+        .arg(input)
+        .output()?;
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("Error: Invalid UTF-8 at byte offset 1:"),
+        "{stderr}"
+    );
+
+    Ok(())
+}
+
+#[test]
+fn expect_stations() -> Result<(), Box<dyn std::error::Error>> {
+    let (dir, input) = fixture()?;
+    // Palembang is in the result but not on the list, & Oslo is on the list but not measured
+    let list = dir.path().join("stations.txt");
+    std::fs::write(&list, "Hamburg\nBulawayo\nOslo\n")?;
+
+    let run = |strict: bool| {
+        let mut command = Command::new(env!("CARGO_BIN_EXE_onebrc"));
+        command.arg("--expect-stations").arg(&list).arg(&input);
+        if strict {
+            command.arg("--strict");
+        }
+        command.output()
+    };
+
+    let output = run(false)?;
+    let stderr = String::from_utf8(output.stderr)?;
+    assert!(output.status.success(), "{stderr}");
+    assert!(
+        stderr.contains(
+            "Unexpected stations (1, in the result but not expected):\n  \"Palembang\"\n\
+             Missing stations (1, expected but not in the result):\n  \"Oslo\"\n"
+        ),
+        "{stderr}"
+    );
+    assert!(
+        stderr.contains("Warning: 1 station in the result is not in"),
+        "{stderr}"
+    );
+    let stdout = String::from_utf8(output.stdout)?;
+    assert!(stdout.contains("Palembang=38.8/38.8/38.8"), "{stdout}");
+
+    let output = run(true)?;
+    let stderr = String::from_utf8(output.stderr)?;
+    assert_eq!(output.status.code(), Some(1), "{stderr}");
+    assert!(stderr.contains("\"Palembang\""), "{stderr}");
+    assert!(
+        stderr.contains("Error: 1 station in the result is not in"),
+        "{stderr}"
+    );
+
+    // Only missing stations is never an error
+    std::fs::write(&list, "Hamburg\nBulawayo\nPalembang\nOslo\n")?;
+    let output = run(true)?;
+    let stderr = String::from_utf8(output.stderr)?;
+    assert!(output.status.success(), "{stderr}");
+    assert!(!stderr.contains("Unexpected"), "{stderr}");
+    assert!(stderr.contains("Missing stations (1"), "{stderr}");
+
+    Ok(())
+}
+
+/// `--color always` colours the check on stderr even into a pipe, but never the results, while
+/// `auto` (the default) leaves a pipe alone, as does `NO_COLOR`
+#[test]
+fn color() -> Result<(), Box<dyn std::error::Error>> {
+    let (dir, input) = fixture()?;
+    let list = dir.path().join("stations.txt");
+    std::fs::write(&list, "Hamburg\nBulawayo\nOslo\n")?;
+    let out = dir.path().join("out.txt");
+
+    let run = |color: Option<&str>, no_color: bool| {
+        let mut command = Command::new(env!("CARGO_BIN_EXE_onebrc"));
+        command
+            .arg("--expect-stations")
+            .arg(&list)
+            .arg("--output")
+            .arg(&out)
+            .arg(&input)
+            .env_remove("NO_COLOR");
+        if let Some(color) = color {
+            command.args(["--color", color]);
+        }
+        if no_color {
+            command.env("NO_COLOR", "1");
+        }
+        command.output()
+    };
+
+    for (color, no_color) in [(Some("always"), false), (Some("always"), true)] {
+        let output = run(color, no_color)?;
+        let stderr = String::from_utf8(output.stderr)?;
+        assert!(output.status.success(), "{stderr}");
+        assert!(
+            stderr.contains(
+                "\x1b[31mUnexpected stations (1, in the result but not expected):\x1b[0m"
+            ),
+            "{stderr}"
+        );
+        assert!(!String::from_utf8(output.stdout)?.contains('\x1b'));
+        assert!(!std::fs::read_to_string(&out)?.contains('\x1b'));
+    }
+
+    for (color, no_color) in [(None, false), (Some("auto"), true), (Some("never"), false)] {
+        let output = run(color, no_color)?;
+        let stderr = String::from_utf8(output.stderr)?;
+        assert!(output.status.success(), "{stderr}");
+        assert!(stderr.contains("Unexpected stations (1"), "{stderr}");
+        assert!(!stderr.contains('\x1b'), "{color:?}, {no_color}: {stderr}");
+    }
+
+    Ok(())
+}
+
+#[test]
+fn emit_missing() -> Result<(), Box<dyn std::error::Error>> {
+    let (dir, input) = fixture()?;
+    let list = dir.path().join("stations.txt");
+    std::fs::write(&list, "Hamburg\nBulawayo\nPalembang\nAbsent\n")?;
+
+    let run = |args: &[&str]| {
+        Command::new(env!("CARGO_BIN_EXE_onebrc"))
+            .arg("--expect-stations")
+            .arg(&list)
+            .args(args)
+            .arg(&input)
+            .output()
+    };
+
+    // Without asking, a station with no measurements isn't written
+    let output = run(&[])?;
+    let stdout = String::from_utf8(output.stdout)?;
+    assert!(output.status.success());
+    assert!(
+        stdout.starts_with(
+            "{Bulawayo=8.9/8.9/8.9, Hamburg=12.0/23.1/34.2, Palembang=38.8/38.8/38.8}\n"
+        ),
+        "{stdout}"
+    );
+
+    let output = run(&["--emit-missing"])?;
+    let stdout = String::from_utf8(output.stdout)?;
+    assert!(output.status.success());
+    assert!(
+        stdout.starts_with(
+            "{Absent=-/-/-, Bulawayo=8.9/8.9/8.9, Hamburg=12.0/23.1/34.2, \
+             Palembang=38.8/38.8/38.8}\n"
+        ),
+        "{stdout}"
+    );
+    // It's still reported as missing
+    let stderr = String::from_utf8(output.stderr)?;
+    assert!(stderr.contains("Missing stations (1"), "{stderr}");
+
+    let json = dir.path().join("out.json");
+    let output = run(&[
+        "--emit-missing",
+        "--quiet",
+        "--output",
+        &json.to_string_lossy(),
+    ])?;
+    assert!(output.status.success());
+    let json: serde_json::Value = serde_json::from_slice(&std::fs::read(&json)?)?;
+    assert_eq!(json[0]["name"], "Absent");
+    assert!(json[0]["mean"].is_null(), "{json}");
+
+    // It only means anything with a list of stations
+    let output = Command::new(env!("CARGO_BIN_EXE_onebrc"))
+        .arg("--emit-missing")
+        .arg(&input)
+        .output()?;
+    assert!(!output.status.success());
+
+    Ok(())
+}
+
+#[test]
+fn metadata() -> Result<(), Box<dyn std::error::Error>> {
+    let (dir, input) = fixture()?;
+    let metadata = dir.path().join("metadata.csv");
+    std::fs::write(
+        &metadata,
+        "station,country,lat\nHamburg,Germany,53.55\nBulawayo,Zimbabwe,-20.15\n",
+    )?;
+
+    let output = Command::new(env!("CARGO_BIN_EXE_onebrc"))
+        .args(["--format", "csv", "--metadata"])
+        .arg(&metadata)
+        .arg(&input)
+        .output()?;
+    let stderr = String::from_utf8(output.stderr)?;
+    assert!(output.status.success(), "{stderr}");
+    assert!(
+        stderr.contains("1 of 3 stations have no metadata in"),
+        "{stderr}"
+    );
+    let stdout = String::from_utf8(output.stdout)?;
+    assert!(
+        stdout.starts_with(
+            "name,min,mean,max,country,lat\n\
+             Bulawayo,8.9,8.9,8.9,Zimbabwe,-20.15\n\
+             Hamburg,12.0,23.1,34.2,Germany,53.55\n\
+             Palembang,38.8,38.8,38.8,,\n"
+        ),
+        "{stdout}"
+    );
+
+    // A duplicate row is refused before anything is run
+    std::fs::write(
+        &metadata,
+        "station,country\nHamburg,Germany\nHamburg,Deutschland\n",
+    )?;
+    let output = Command::new(env!("CARGO_BIN_EXE_onebrc"))
+        .arg("--metadata")
+        .arg(&metadata)
+        .arg(&input)
+        .output()?;
+    let stderr = String::from_utf8(output.stderr)?;
+    assert_eq!(output.status.code(), Some(1), "{stderr}");
+    assert!(stderr.contains("is on both line 2 & line 3"), "{stderr}");
+    assert!(output.stdout.is_empty());
+
+    Ok(())
+}
+
+/// Reports say which settings were tuned, & which were given explicitly
+#[test]
+fn auto_tune() -> Result<(), Box<dyn std::error::Error>> {
+    let (dir, input) = fixture()?;
+    let report = dir.path().join("report.json");
+    let bench = |tuning: &str| {
+        Command::new(env!("CARGO_BIN_EXE_onebrc"))
+            .args([
+                "--bench",
+                "--auto-tune",
+                tuning,
+                "--threads",
+                "2",
+                "--report",
+            ])
+            .arg(&report)
+            .arg(&input)
+            .output()
+    };
+
+    let output = bench("--tune-once")?;
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(output.status.success(), "{stderr}");
+    assert!(stderr.contains("Auto-tuned the buffer size to"), "{stderr}");
+    assert!(stderr.contains("(auto-tuned)"), "{stderr}");
+    let doc: serde_json::Value = serde_json::from_slice(&std::fs::read(&report)?)?;
+    assert_eq!(doc["config"]["threads"]["value"], 2);
+    assert_eq!(doc["config"]["threads"]["source"], "cli");
+    assert_eq!(doc["config"]["buffer_size"]["source"], "auto-tuned");
+    assert_eq!(doc["config"]["max_line_length"]["source"], "default");
+    assert_eq!(doc["tuning"]["mode"], "once");
+    assert_eq!(
+        doc["tuning"]["buffer_sizes"].as_array().map(Vec::len),
+        Some(1)
+    );
+
+    let output = bench("--retune-each-run")?;
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(output.status.success(), "{stdout}");
+    assert!(stdout.contains("Mean tuning:"), "{stdout}");
+    let doc: serde_json::Value = serde_json::from_slice(&std::fs::read(&report)?)?;
+    assert_eq!(doc["config"]["buffer_size"]["source"], "auto-tuned");
+    assert_eq!(doc["tuning"]["mode"], "each-run");
+    assert_eq!(
+        doc["tuning"]["buffer_sizes"].as_array().map(Vec::len),
+        Some(5)
+    );
+    assert_eq!(doc["tuning"]["durations"].as_array().map(Vec::len), Some(5));
+
+    // A buffer size given explicitly is left alone
+    let output = Command::new(env!("CARGO_BIN_EXE_onebrc"))
+        .args(["--auto-tune", "--buffer-size", "4096", "--report"])
+        .arg(&report)
+        .arg(&input)
+        .output()?;
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(output.status.success(), "{stderr}");
+    assert!(stderr.contains("nothing to tune"), "{stderr}");
+    let doc: serde_json::Value = serde_json::from_slice(&std::fs::read(&report)?)?;
+    assert_eq!(doc["config"]["buffer_size"]["source"], "cli");
+    assert!(doc.get("tuning").is_none());
+
+    Ok(())
+}
@@ -0,0 +1,169 @@
+// 1BRC - my take on the 1 Billion Row Challenge
+// Copyright (C) 2024  Charles German <5donuts@pm.me>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Every runner against every input in the corpus of pathological files in `tests/data/corpus`,
+//! which `manifest.toml` there lists with the result or error expected of each.
+
+#![cfg(feature = "native")]
+
+use std::path::{Path, PathBuf};
+
+use clap::ValueEnum;
+use serde::Deserialize;
+
+use onebrc::config::{Config, Layer};
+use onebrc::error::ChallengeError;
+use onebrc::output::Format;
+use onebrc::runners;
+use onebrc::Runner;
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Manifest {
+    case: Vec<Case>,
+}
+
+/// An input & what every runner should make of it
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+struct Case {
+    input: PathBuf,
+
+    /// Settings to run the case with, as in a config file
+    #[serde(default)]
+    config: Layer,
+
+    /// The result, as `--format text` prints it
+    expected: Option<String>,
+
+    /// A file holding the result, for one too long to put in the manifest
+    expected_file: Option<PathBuf>,
+
+    /// The kind of error expected instead of a result
+    error: Option<String>,
+}
+
+/// What a case should come to
+#[derive(Debug, PartialEq)]
+enum Outcome {
+    Result(String),
+    Error(String),
+}
+
+impl Case {
+    fn expected(&self, dir: &Path) -> Result<Outcome, Box<dyn std::error::Error>> {
+        let outcome = match (&self.expected, &self.expected_file, &self.error) {
+            (Some(expected), None, None) => Outcome::Result(expected.clone()),
+            (None, Some(path), None) => Outcome::Result(
+                std::fs::read_to_string(dir.join(path))?
+                    .trim_end()
+                    .to_owned(),
+            ),
+            (None, None, Some(kind)) => Outcome::Error(kind.clone()),
+            _ => {
+                return Err(format!(
+                    "{} needs exactly one of `expected`, `expected-file` & `error`",
+                    self.input.display()
+                )
+                .into())
+            }
+        };
+        Ok(outcome)
+    }
+
+    /// Run the case with a runner, with the case's own settings & then `threads`
+    fn run(&self, dir: &Path, runner: Runner, threads: Option<usize>) -> Outcome {
+        let cli = Layer {
+            runner: Some(runner),
+            threads: threads.or(self.config.threads),
+            ..self.config.clone()
+        };
+        let config = Config::resolve(
+            &dir.join(&self.input),
+            cli,
+            Layer::default(),
+            Layer::default(),
+        );
+        match runners::run(&config) {
+            Ok((stations, _)) => {
+                Outcome::Result(Format::Text.render(&stations).trim_end().to_owned())
+            }
+            Err(e) => match e.downcast_ref::<ChallengeError>() {
+                Some(e) => Outcome::Error(e.kind().to_owned()),
+                None => Outcome::Error(e.to_string()),
+            },
+        }
+    }
+}
+
+#[test]
+fn corpus() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/data/corpus");
+    let manifest: Manifest = toml::from_str(&std::fs::read_to_string(dir.join("manifest.toml"))?)?;
+
+    let mut failures = Vec::new();
+    for (idx, case) in manifest.case.iter().enumerate() {
+        let expected = case.expected(&dir)?;
+        for &runner in Runner::value_variants() {
+            for threads in [None, Some(4)] {
+                let actual = case.run(&dir, runner, threads);
+                if actual != expected {
+                    let shorten = |outcome: &Outcome| {
+                        let s = format!("{outcome:?}");
+                        if s.len() > 200 {
+                            format!("{}...", &s[..s.floor_char_boundary(200)])
+                        } else {
+                            s
+                        }
+                    };
+                    failures.push(format!(
+                        "case {} ({}) with --runner {runner} & {threads:?} threads: {} rather than {}",
+                        idx + 1,
+                        case.input.display(),
+                        shorten(&actual),
+                        shorten(&expected),
+                    ));
+                }
+            }
+        }
+    }
+    assert!(failures.is_empty(), "{}", failures.join("\n"));
+
+    Ok(())
+}
+
+/// Every input in the corpus is in the manifest, so none is forgotten
+#[test]
+fn every_input_has_a_case() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/data/corpus");
+    let manifest: Manifest = toml::from_str(&std::fs::read_to_string(dir.join("manifest.toml"))?)?;
+
+    for entry in std::fs::read_dir(&dir)? {
+        let path = entry?.path();
+        if path.extension().is_some_and(|ext| ext == "txt") {
+            let name = path.file_name().map(PathBuf::from);
+            assert!(
+                manifest
+                    .case
+                    .iter()
+                    .any(|case| Some(&case.input) == name.as_ref()),
+                "{} isn't in the manifest",
+                path.display()
+            );
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,215 @@
+// 1BRC - my take on the 1 Billion Row Challenge
+// Copyright (C) 2024  Charles German <5donuts@pm.me>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Snapshots of the `onebrc` command line (its help, error messages & output), so other scripts
+//! can rely on it & changing it is always a conscious decision.
+//!
+//! Each case is a `tests/cmd/<name>.toml` file in the same format as `trycmd`'s:
+//!
+//! ```toml
+//! bin.name = "onebrc"
+//! args = ["--runner", "nope", "measurements.txt"]
+//! status.code = 2
+//! ```
+//!
+//! The binary is run in `tests/cmd` with those arguments, & what it writes to stdout & stderr
+//! is compared with `<name>.stdout` & `<name>.stderr` (either of which may be left out to not
+//! check that stream). `[..]` in an expected line matches anything, for timings & whatever else
+//! depends on the machine. Run with `TRYCMD=overwrite` to update the snapshots after a deliberate
+//! change; lines with a `[..]` are kept wherever they still match.
+//!
+//! The help & the banner list the runners & allocators built in, so the snapshots are only
+//! checked against the default features.
+
+#![cfg(all(
+    unix,
+    feature = "native",
+    not(any(
+        feature = "io-uring",
+        feature = "direct-io",
+        feature = "tokio",
+        feature = "encodings",
+        feature = "mimalloc",
+        feature = "jemalloc"
+    ))
+))]
+
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use serde::Deserialize;
+
+/// A single command line to run
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Case {
+    bin: Bin,
+
+    #[serde(default)]
+    args: Vec<String>,
+
+    #[serde(default)]
+    status: Status,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Bin {
+    name: String,
+}
+
+/// The exit status the command line is expected to have
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Status {
+    #[serde(default)]
+    code: i32,
+}
+
+/// Whether an actual line of output matches an expected one, which may contain `[..]` wildcards
+fn line_matches(expected: &str, actual: &str) -> bool {
+    let mut parts = expected.split("[..]");
+    let first = parts.next().unwrap_or_default();
+    let Some(mut rest) = actual.strip_prefix(first) else {
+        return false;
+    };
+    let mut parts = parts.peekable();
+    while let Some(part) = parts.next() {
+        if parts.peek().is_none() {
+            return rest.ends_with(part);
+        }
+        match rest.find(part) {
+            Some(idx) => rest = &rest[idx + part.len()..],
+            None => return false,
+        }
+    }
+    rest.is_empty()
+}
+
+/// Whether the actual output of a stream matches its snapshot, line by line
+fn output_matches(expected: &str, actual: &str) -> bool {
+    expected.lines().count() == actual.lines().count()
+        && expected
+            .lines()
+            .zip(actual.lines())
+            .all(|(expected, actual)| line_matches(expected, actual))
+}
+
+/// The snapshot to write for a stream, keeping the lines of the old one that still match
+fn updated(expected: &str, actual: &str) -> String {
+    if expected.lines().count() != actual.lines().count() {
+        return actual.to_string();
+    }
+    let mut updated: String = expected
+        .lines()
+        .zip(actual.lines())
+        .map(|(expected, actual)| {
+            if line_matches(expected, actual) {
+                format!("{expected}\n")
+            } else {
+                format!("{actual}\n")
+            }
+        })
+        .collect();
+    if !actual.ends_with('\n') {
+        updated.pop();
+    }
+    updated
+}
+
+/// Run a case, returning what didn't match its snapshots
+fn check(case_path: &Path, overwrite: bool) -> Result<Vec<String>, Box<dyn std::error::Error>> {
+    let case: Case = toml::from_str(&std::fs::read_to_string(case_path)?)?;
+    assert_eq!(case.bin.name, "onebrc", "{}", case_path.display());
+
+    let mut command = Command::new(env!("CARGO_BIN_EXE_onebrc"));
+    command
+        .args(&case.args)
+        .current_dir(case_path.parent().expect("Cases are in tests/cmd"));
+    // Settings from the environment would show up in the banner
+    for (key, _) in std::env::vars_os() {
+        if key.to_string_lossy().starts_with("ONEBRC_") {
+            command.env_remove(key);
+        }
+    }
+    let output = command.output()?;
+
+    let mut failures = Vec::new();
+    let status = output.status.code();
+    if status != Some(case.status.code) {
+        failures.push(format!(
+            "exited with {status:?} rather than {}",
+            case.status.code
+        ));
+    }
+    for (stream, actual) in [("stdout", &output.stdout), ("stderr", &output.stderr)] {
+        let actual = String::from_utf8_lossy(actual);
+        let snapshot = case_path.with_extension(stream);
+        let expected = match std::fs::read_to_string(&snapshot) {
+            Ok(expected) => expected,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound && !overwrite => continue,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+            Err(e) => return Err(e.into()),
+        };
+        if output_matches(&expected, &actual) {
+            continue;
+        }
+        if overwrite {
+            std::fs::write(&snapshot, updated(&expected, &actual))?;
+        } else {
+            failures.push(format!(
+                "{stream} doesn't match {}:\n{actual}",
+                snapshot.display()
+            ));
+        }
+    }
+    Ok(failures)
+}
+
+#[test]
+fn cli_snapshots() -> Result<(), Box<dyn std::error::Error>> {
+    let overwrite = std::env::var("TRYCMD").is_ok_and(|mode| mode == "overwrite");
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/cmd");
+    let mut cases: Vec<PathBuf> = std::fs::read_dir(dir)?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<Result<_, _>>()?;
+    cases.retain(|path| path.extension().is_some_and(|ext| ext == "toml"));
+    cases.sort();
+    assert!(!cases.is_empty(), "There are no cases in tests/cmd");
+
+    let mut failures = Vec::new();
+    for case in &cases {
+        for failure in check(case, overwrite)? {
+            failures.push(format!("{}: {failure}", case.display()));
+        }
+    }
+    assert!(failures.is_empty(), "{}", failures.join("\n\n"));
+
+    Ok(())
+}
+
+#[test]
+fn wildcards() {
+    assert!(line_matches("Solved in [..]", "Solved in 117.6µs"));
+    assert!(line_matches(
+        "  cpus:          [..] ([..])",
+        "  cpus:          4 (8 reported)"
+    ));
+    assert!(line_matches("[..]", ""));
+    assert!(line_matches("exact", "exact"));
+    assert!(!line_matches("exact", "exactly"));
+    assert!(!line_matches("Solved in [..]", "Solved on 1s"));
+    assert!(!line_matches("a[..]b[..]c", "a c b"));
+}
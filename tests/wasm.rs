@@ -0,0 +1,64 @@
+// 1BRC - my take on the 1 Billion Row Challenge
+// Copyright (C) 2024  Charles German <5donuts@pm.me>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Run with `wasm-pack test --node --no-default-features --features wasm`
+
+#![cfg(all(target_arch = "wasm32", feature = "wasm"))]
+
+use wasm_bindgen_test::*;
+
+use onebrc::wasm::aggregate_text;
+
+#[wasm_bindgen_test]
+fn aggregate_fixture() {
+    let input = "Glens Falls;-47.5
+Shimanto;30.3
+Zverevo;98.1
+Shimanto;74.9
+Zverevo;87.6
+Aïn el Mediour;47.6
+Paidiipalli;91.1
+Shimanto;27.5
+Aïn el Mediour;5.7
+Shimanto;20.9
+Glens Falls;6.6
+";
+
+    let result = aggregate_text(input).expect("fixture is well-formed");
+    let entries: Vec<serde_json::Value> = serde_wasm_bindgen::from_value(result).unwrap();
+
+    let names: Vec<&str> = entries
+        .iter()
+        .map(|e| e["name"].as_str().unwrap())
+        .collect();
+    assert_eq!(
+        names,
+        vec![
+            "Aïn el Mediour",
+            "Glens Falls",
+            "Paidiipalli",
+            "Shimanto",
+            "Zverevo"
+        ]
+    );
+    assert_eq!(entries[1]["min"], -47.5);
+    assert_eq!(entries[3]["mean"], 38.4);
+    assert_eq!(entries[4]["max"], 98.1);
+}
+
+#[wasm_bindgen_test]
+fn malformed_input() {
+    assert!(aggregate_text("Hamburg 12.0\n").is_err());
+}